@@ -1,10 +1,9 @@
-use crate::{
-    components::select::{Select, SelectOption, SelectOptionProps},
-    infra::common_component::{CommonComponent, CommonComponentParts},
-};
+use crate::infra::common_component::{CommonComponent, CommonComponentParts};
 use anyhow::{Error, Result};
+use gloo_timers::callback::Timeout;
 use graphql_client::GraphQLQuery;
-use std::collections::HashSet;
+use std::collections::VecDeque;
+use web_sys::HtmlInputElement;
 use yew::prelude::*;
 
 #[derive(GraphQLQuery)]
@@ -28,19 +27,43 @@ pub struct AddUserToGroup;
 pub struct ListUserNames;
 pub type User = list_user_names::ListUserNamesUsers;
 
+/// How many matching users are suggested.
+const MAX_SUGGESTIONS: usize = 10;
+/// How long to wait after the last key stroke before searching.
+const SEARCH_DEBOUNCE_MS: u32 = 300;
+
+fn user_label(user: &User) -> String {
+    if user.display_name.is_empty() {
+        user.id.clone()
+    } else {
+        format!("{} ({})", user.display_name, user.id)
+    }
+}
+
+/// A search box suggesting the users to add to the group. Several users can be picked, then
+/// added at once.
 pub struct AddGroupMemberComponent {
     common: CommonComponentParts<Self>,
-    /// The list of existing users, initially not loaded.
-    user_list: Option<Vec<User>>,
-    /// The currently selected user.
-    selected_user: Option<User>,
+    search: String,
+    search_timeout: Option<Timeout>,
+    /// A search to run once the current request is done.
+    search_pending: bool,
+    /// The users matching the search, if it was sent.
+    suggestions: Option<Vec<User>>,
+    /// The users picked, to add to the group.
+    selected_users: Vec<User>,
+    /// The users being added, one request at a time.
+    adding: VecDeque<User>,
 }
 
 pub enum Msg {
+    SearchInput(String),
+    Search,
     UserListResponse(Result<list_user_names::ResponseData>),
-    SubmitAddMember,
+    SelectUser(User),
+    UnselectUser(String),
+    SubmitAddMembers,
     AddMemberResponse(Result<add_user_to_group::ResponseData>),
-    SelectionChanged(Option<SelectOptionProps>),
 }
 
 #[derive(yew::Properties, Clone, PartialEq)]
@@ -58,27 +81,48 @@ impl CommonComponent<AddGroupMemberComponent> for AddGroupMemberComponent {
         msg: <Self as Component>::Message,
     ) -> Result<bool> {
         match msg {
+            Msg::SearchInput(search) => {
+                self.search = search;
+                let link = ctx.link().clone();
+                self.search_timeout = Some(Timeout::new(SEARCH_DEBOUNCE_MS, move || {
+                    link.send_message(Msg::Search)
+                }));
+            }
+            Msg::Search => {
+                self.search_timeout = None;
+                self.search_users(ctx);
+            }
             Msg::UserListResponse(response) => {
-                self.user_list = Some(response?.users);
+                if self.search_pending {
+                    // Outdated.
+                    self.run_pending(ctx);
+                    return Ok(false);
+                }
+                self.suggestions = Some(response?.users);
+            }
+            Msg::SelectUser(user) => {
+                if !self.selected_users.contains(&user) {
+                    self.selected_users.push(user);
+                }
+            }
+            Msg::UnselectUser(user_id) => self.selected_users.retain(|u| u.id != user_id),
+            Msg::SubmitAddMembers => {
+                self.adding = std::mem::take(&mut self.selected_users).into();
+                self.run_pending(ctx);
             }
-            Msg::SubmitAddMember => return self.submit_add_member(ctx),
             Msg::AddMemberResponse(response) => {
-                response?;
                 let user = self
-                    .selected_user
-                    .as_ref()
-                    .expect("Could not get selected user")
-                    .clone();
-                // Remove the user from the dropdown.
+                    .adding
+                    .pop_front()
+                    .expect("Could not get the user being added");
+                if let Err(e) = response {
+                    // Keep the users that were not added, to retry.
+                    self.selected_users.push(user);
+                    self.selected_users.extend(self.adding.drain(..));
+                    return Err(e);
+                }
                 ctx.props().on_user_added_to_group.emit(user);
-            }
-            Msg::SelectionChanged(option_props) => {
-                let was_some = self.selected_user.is_some();
-                self.selected_user = option_props.map(|u| User {
-                    id: u.value,
-                    display_name: u.text,
-                });
-                return Ok(self.selected_user.is_some() != was_some);
+                self.run_pending(ctx);
             }
         }
         Ok(true)
@@ -90,39 +134,126 @@ impl CommonComponent<AddGroupMemberComponent> for AddGroupMemberComponent {
 }
 
 impl AddGroupMemberComponent {
-    fn get_user_list(&mut self, ctx: &Context<Self>) {
+    fn search_users(&mut self, ctx: &Context<Self>) {
+        let search = self.search.trim();
+        if search.is_empty() {
+            self.suggestions = None;
+            return;
+        }
+        if self.common.is_task_running() {
+            self.search_pending = true;
+            return;
+        }
+        let filters = list_user_names::RequestFilter {
+            any: None,
+            all: None,
+            not: None,
+            eq: None,
+            member_of: None,
+            member_of_id: None,
+            last_login_before: None,
+            password_changed_before: None,
+            expires_before: None,
+            search: Some(search.to_owned()),
+        };
         self.common.call_graphql::<ListUserNames, _>(
             ctx,
-            list_user_names::Variables { filters: None },
+            list_user_names::Variables {
+                filters: Some(filters),
+            },
             Msg::UserListResponse,
             "Error trying to fetch user list",
         );
     }
 
-    fn submit_add_member(&mut self, ctx: &Context<Self>) -> Result<bool> {
-        let user_id = match self.selected_user.clone() {
-            None => return Ok(false),
-            Some(user) => user.id,
-        };
-        self.common.call_graphql::<AddUserToGroup, _>(
-            ctx,
-            add_user_to_group::Variables {
-                user: user_id,
-                group: ctx.props().group_id,
-            },
-            Msg::AddMemberResponse,
-            "Error trying to initiate adding the user to a group",
-        );
-        Ok(true)
+    /// Sends the next request waiting for the previous one to finish: the next user to add,
+    /// otherwise the search typed in the meantime.
+    fn run_pending(&mut self, ctx: &Context<Self>) {
+        // There is no mutation adding several users to a group, they are added one at a time.
+        if let Some(user) = self.adding.front() {
+            self.common.call_graphql::<AddUserToGroup, _>(
+                ctx,
+                add_user_to_group::Variables {
+                    user: user.id.clone(),
+                    group: ctx.props().group_id,
+                },
+                Msg::AddMemberResponse,
+                "Error trying to initiate adding the user to a group",
+            );
+        } else if self.search_pending {
+            self.search_pending = false;
+            self.search_users(ctx);
+        }
     }
 
-    fn get_selectable_user_list(&self, ctx: &Context<Self>, user_list: &[User]) -> Vec<User> {
-        let user_groups = ctx.props().users.iter().collect::<HashSet<_>>();
-        user_list
+    /// The matching users that are not members yet, nor already picked.
+    fn selectable_users<'a>(&'a self, ctx: &'a Context<Self>) -> impl Iterator<Item = &'a User> {
+        let members = &ctx.props().users;
+        self.suggestions
             .iter()
-            .filter(|u| !user_groups.contains(u))
-            .map(Clone::clone)
-            .collect()
+            .flatten()
+            .filter(move |u| {
+                !members.iter().any(|m| m.id == u.id)
+                    && !self.selected_users.iter().any(|s| s.id == u.id)
+            })
+            .take(MAX_SUGGESTIONS)
+    }
+
+    fn view_suggestions(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        if self.suggestions.is_none() || self.search.trim().is_empty() {
+            return html! {};
+        }
+        let make_suggestion = |user: &User| {
+            let label = user_label(user);
+            let key = user.id.clone();
+            let user = user.clone();
+            html! {
+              <button
+                type="button"
+                key={key}
+                class="list-group-item list-group-item-action"
+                onclick={link.callback(move |_| Msg::SelectUser(user.clone()))}>
+                {label}
+              </button>
+            }
+        };
+        let suggestions = self
+            .selectable_users(ctx)
+            .map(make_suggestion)
+            .collect::<Vec<_>>();
+        if suggestions.is_empty() {
+            html! {
+              <div class="form-text">{"No other user matches this search."}</div>
+            }
+        } else {
+            html! {
+              <div class="list-group mt-1">{suggestions}</div>
+            }
+        }
+    }
+
+    fn view_selected_users(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        let make_selected = |user: &User| {
+            let user_id = user.id.clone();
+            html! {
+              <span class="badge rounded-pill bg-secondary me-1 mb-1" key={user.id.clone()}>
+                {user_label(user)}
+                <button
+                  type="button"
+                  class="btn-close btn-close-white ms-1 align-middle"
+                  style="font-size: 0.6em"
+                  aria-label={format!("Don't add {}", user.id)}
+                  onclick={link.callback(move |_| Msg::UnselectUser(user_id.clone()))} />
+              </span>
+            }
+        };
+        html! {
+          <div class="mt-2">
+            {for self.selected_users.iter().map(make_selected)}
+          </div>
+        }
     }
 }
 
@@ -130,14 +261,16 @@ impl Component for AddGroupMemberComponent {
     type Message = Msg;
     type Properties = Props;
 
-    fn create(ctx: &Context<Self>) -> Self {
-        let mut res = Self {
+    fn create(_: &Context<Self>) -> Self {
+        Self {
             common: CommonComponentParts::<Self>::create(),
-            user_list: None,
-            selected_user: None,
-        };
-        res.get_user_list(ctx);
-        res
+            search: String::new(),
+            search_timeout: None,
+            search_pending: false,
+            suggestions: None,
+            selected_users: Vec::new(),
+            adding: VecDeque::new(),
+        }
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
@@ -151,46 +284,37 @@ impl Component for AddGroupMemberComponent {
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let link = ctx.link();
-        if let Some(user_list) = &self.user_list {
-            let to_add_user_list = self.get_selectable_user_list(ctx, user_list);
-            #[allow(unused_braces)]
-            let make_select_option = |user: User| {
-                let name = if user.display_name.is_empty() {
-                    user.id.clone()
-                } else {
-                    user.display_name.clone()
-                };
-                html_nested! {
-                    <SelectOption value={user.id.clone()} text={name} key={user.id} />
-                }
-            };
-            html! {
-            <div class="row">
-              <div class="col-sm-3">
-                <Select on_selection_change={link.callback(Msg::SelectionChanged)}>
-                  {
-                    to_add_user_list
-                        .into_iter()
-                        .map(make_select_option)
-                        .collect::<Vec<_>>()
-                  }
-                </Select>
-              </div>
-              <div class="col-3">
-                <button
-                  class="btn btn-secondary"
-                  disabled={self.selected_user.is_none() || self.common.is_task_running()}
-                  onclick={link.callback(|_| Msg::SubmitAddMember)}>
-                   <i class="bi-person-plus me-2"></i>
-                  {"Add to group"}
-                </button>
-              </div>
+        let is_adding = !self.adding.is_empty();
+        html! {
+          <div class="row mb-3">
+            <div class="col-sm-6">
+              <label for="addMemberSearch" class="form-label">{"Add members"}</label>
+              <input
+                type="search"
+                class="form-control"
+                id="addMemberSearch"
+                autocomplete="off"
+                placeholder="Search by user ID, email or display name"
+                value={self.search.clone()}
+                disabled={is_adding}
+                oninput={link.callback(|e: InputEvent| {
+                  let input: HtmlInputElement = e.target_unchecked_into();
+                  Msg::SearchInput(input.value())
+                })} />
+              {self.view_suggestions(ctx)}
+              {self.view_selected_users(ctx)}
+              <button
+                class="btn btn-secondary mt-2"
+                disabled={self.selected_users.is_empty() || is_adding}
+                onclick={link.callback(|_| Msg::SubmitAddMembers)}>
+                <i class="bi-person-plus me-2"></i>
+                {match self.selected_users.len() {
+                  0 | 1 => "Add to group".to_owned(),
+                  n => format!("Add {} users to group", n),
+                }}
+              </button>
             </div>
-            }
-        } else {
-            html! {
-              {"Loading groups"}
-            }
+          </div>
         }
     }
 }
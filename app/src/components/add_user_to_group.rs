@@ -107,7 +107,7 @@ impl AddUserToGroupComponent {
     fn get_group_list(&mut self, ctx: &Context<Self>) {
         self.common.call_graphql::<GetGroupList, _>(
             ctx,
-            get_group_list::Variables,
+            get_group_list::Variables { search: None },
             Msg::GroupListResponse,
             "Error trying to fetch group list",
         );
@@ -13,9 +13,11 @@ use crate::{
         reset_password_step1::ResetPasswordStep1Form,
         reset_password_step2::ResetPasswordStep2Form,
         router::{AppRoute, Link, Redirect},
+        security_banner::SecurityBanner,
         user_details::UserDetails,
         user_schema_table::ListUserSchema,
         user_table::UserTable,
+        verify_email::VerifyEmail,
     },
     infra::{api::HostService, cookies::get_cookie},
 };
@@ -150,6 +152,7 @@ impl App {
                     | AppRoute::Login
                     | AppRoute::StartResetPassword
                     | AppRoute::FinishResetPassword { token: _ }
+                    | AppRoute::VerifyEmail { token: _ }
             )
         })
     }
@@ -169,6 +172,8 @@ impl App {
                     None
                 }
             }
+            // The link works without being logged in.
+            (Some(AppRoute::VerifyEmail { token: _ }), _, _) => None,
             (None, _, _) | (_, None, _) => Some(AppRoute::Login),
             // User is logged in, a URL was given, don't redirect.
             (_, Some(_), Some(_)) => None,
@@ -198,10 +203,11 @@ impl App {
                 <LoginForm on_logged_in={link.callback(Msg::Login)} password_reset_enabled={password_reset_enabled.unwrap_or(false)}/>
             },
             AppRoute::CreateUser => html! {
-                <CreateUserForm/>
+                <CreateUserForm invitations_enabled={password_reset_enabled.unwrap_or(false)}/>
             },
             AppRoute::Index | AppRoute::ListUsers => html! {
                 <div>
+                  {if is_admin { html! { <SecurityBanner /> } } else { html! {} }}
                   <UserTable />
                   <Link classes="btn btn-primary" to={AppRoute::CreateUser}>
                     <i class="bi-person-plus me-2"></i>
@@ -257,6 +263,9 @@ impl App {
                 }
                 None => html! {},
             },
+            AppRoute::VerifyEmail { token } => html! {
+                <VerifyEmail token={token.clone()} />
+            },
         }
     }
 
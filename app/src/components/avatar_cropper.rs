@@ -0,0 +1,365 @@
+use anyhow::{anyhow, bail, Error, Result};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{CanvasRenderingContext2d, Element, HtmlCanvasElement, HtmlImageElement};
+use yew::prelude::*;
+
+/// Size of the live preview, in pixels.
+const PREVIEW_SIZE: u32 = 128;
+/// Quality of the JPEG sent to the server, between 0 and 1.
+const JPEG_QUALITY: f64 = 0.9;
+/// The smallest crop, in percent of the shorter side of the picture.
+const MIN_ZOOM_PERCENT: u32 = 10;
+
+/// The square to keep, in pixels of the original picture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Crop {
+    x: f64,
+    y: f64,
+    size: f64,
+}
+
+struct Drag {
+    start_x: i32,
+    start_y: i32,
+    start_crop: Crop,
+    /// Pixels of the original picture per pixel on the screen.
+    scale: f64,
+}
+
+/// Shows a picture with a square crop selector, that can be moved and resized, and a preview of
+/// the result. The cropped square is then downscaled in the browser and encoded as a JPEG, so
+/// that a phone photo doesn't upload megabytes for a 128 pixels avatar.
+pub struct AvatarCropper {
+    /// The picture shown, to notice when a new one is picked.
+    src: AttrValue,
+    image: NodeRef,
+    preview: NodeRef,
+    /// The dimensions of the picture, once loaded.
+    natural_size: Option<(f64, f64)>,
+    crop: Crop,
+    zoom_percent: u32,
+    drag: Option<Drag>,
+}
+
+pub enum Msg {
+    ImageLoaded,
+    ImageFailed,
+    DragStart(PointerEvent),
+    DragMove(PointerEvent),
+    DragEnd,
+    Zoom(u32),
+    Apply,
+    Cancel,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    /// URL of the picked picture, e.g. an object URL.
+    pub src: AttrValue,
+    /// Largest width and height of the result.
+    pub max_dimension: u32,
+    /// Largest size of the resulting JPEG, in kilobytes.
+    pub max_size_kb: u32,
+    /// The JPEG bytes of the cropped avatar.
+    pub on_cropped: Callback<Vec<u8>>,
+    pub on_cancel: Callback<()>,
+    pub on_error: Callback<Error>,
+}
+
+fn js_error(context: &str) -> impl '_ + FnOnce(JsValue) -> Error {
+    move |e| anyhow!("{}: {:?}", context, e)
+}
+
+fn get_context_2d(canvas: &HtmlCanvasElement) -> Result<CanvasRenderingContext2d> {
+    canvas
+        .get_context("2d")
+        .map_err(js_error("Could not get the canvas context"))?
+        .ok_or_else(|| anyhow!("Canvas not supported"))?
+        .dyn_into::<CanvasRenderingContext2d>()
+        .map_err(|_| anyhow!("Unexpected canvas context"))
+}
+
+/// Draws the cropped square of `image` to fill `canvas`.
+fn draw_crop(image: &HtmlImageElement, canvas: &HtmlCanvasElement, crop: &Crop) -> Result<()> {
+    let context = get_context_2d(canvas)?;
+    let (width, height) = (f64::from(canvas.width()), f64::from(canvas.height()));
+    // JPEGs have no transparency: make the transparent parts of a PNG white rather than black.
+    context.set_fill_style(&JsValue::from_str("#fff"));
+    context.fill_rect(0.0, 0.0, width, height);
+    context.set_image_smoothing_enabled(true);
+    context
+        .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+            image, crop.x, crop.y, crop.size, crop.size, 0.0, 0.0, width, height,
+        )
+        .map_err(js_error("Could not draw the picture"))
+}
+
+impl AvatarCropper {
+    fn clamp_crop(&mut self) {
+        if let Some((width, height)) = self.natural_size {
+            self.crop.x = self.crop.x.clamp(0.0, width - self.crop.size);
+            self.crop.y = self.crop.y.clamp(0.0, height - self.crop.size);
+        }
+    }
+
+    /// Downscales the cropped square in an offscreen canvas, and encodes it as a JPEG.
+    fn export(&self, ctx: &Context<Self>) -> Result<Vec<u8>> {
+        let image = self
+            .image
+            .cast::<HtmlImageElement>()
+            .ok_or_else(|| anyhow!("The picture is not loaded"))?;
+        let size = (self.crop.size.round() as u32)
+            .min(ctx.props().max_dimension)
+            .max(1);
+        let canvas = web_sys::window()
+            .and_then(|w| w.document())
+            .ok_or_else(|| anyhow!("Could not get window document"))?
+            .create_element("canvas")
+            .map_err(js_error("Could not create a canvas"))?
+            .dyn_into::<HtmlCanvasElement>()
+            .map_err(|_| anyhow!("Unexpected canvas element"))?;
+        canvas.set_width(size);
+        canvas.set_height(size);
+        draw_crop(&image, &canvas, &self.crop)?;
+        let data_url = canvas
+            .to_data_url_with_type_and_encoder_options("image/jpeg", &JPEG_QUALITY.into())
+            .map_err(js_error("Could not encode the avatar"))?;
+        let bytes = data_url
+            .split_once(',')
+            .and_then(|(_, data)| base64::decode(data).ok())
+            .ok_or_else(|| anyhow!("Could not encode the avatar"))?;
+        let max_size_kb = ctx.props().max_size_kb as usize;
+        if bytes.len() > max_size_kb * 1024 {
+            bail!(
+                "The cropped avatar is {} kB, over the limit of {} kB of the server",
+                bytes.len().div_ceil(1024),
+                max_size_kb
+            );
+        }
+        Ok(bytes)
+    }
+
+    fn view_selector(&self, ctx: &Context<Self>) -> Html {
+        let (width, height) = match self.natural_size {
+            Some(size) => size,
+            None => return html! {},
+        };
+        let link = ctx.link();
+        let style = format!(
+            "position: absolute; left: {}%; top: {}%; width: {}%; height: {}%; \
+             border: 2px dashed #fff; box-shadow: 0 0 0 9999px rgba(0, 0, 0, 0.5); \
+             cursor: move; touch-action: none;",
+            self.crop.x / width * 100.0,
+            self.crop.y / height * 100.0,
+            self.crop.size / width * 100.0,
+            self.crop.size / height * 100.0,
+        );
+        html! {
+          <div
+            style={style}
+            onpointerdown={link.callback(Msg::DragStart)}
+            onpointermove={link.callback(Msg::DragMove)}
+            onpointerup={link.callback(|_| Msg::DragEnd)}
+            onpointercancel={link.callback(|_| Msg::DragEnd)} />
+        }
+    }
+}
+
+impl Component for AvatarCropper {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        Self {
+            src: ctx.props().src.clone(),
+            image: NodeRef::default(),
+            preview: NodeRef::default(),
+            natural_size: None,
+            crop: Crop {
+                x: 0.0,
+                y: 0.0,
+                size: 0.0,
+            },
+            zoom_percent: 100,
+            drag: None,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::ImageLoaded => {
+                let image = match self.image.cast::<HtmlImageElement>() {
+                    Some(image) => image,
+                    None => return false,
+                };
+                let (width, height) = (
+                    f64::from(image.natural_width()),
+                    f64::from(image.natural_height()),
+                );
+                if width == 0.0 || height == 0.0 {
+                    ctx.props()
+                        .on_error
+                        .emit(anyhow!("The picture could not be read"));
+                    return false;
+                }
+                // Start with the largest centered square.
+                let size = width.min(height);
+                self.natural_size = Some((width, height));
+                self.zoom_percent = 100;
+                self.crop = Crop {
+                    x: (width - size) / 2.0,
+                    y: (height - size) / 2.0,
+                    size,
+                };
+            }
+            Msg::ImageFailed => {
+                ctx.props().on_error.emit(anyhow!(
+                    "The picture could not be read, it may be corrupted or in a format that \
+                     this browser doesn't support"
+                ));
+                return false;
+            }
+            Msg::DragStart(event) => {
+                let image = match self.image.cast::<HtmlImageElement>() {
+                    Some(image) if image.client_width() > 0 => image,
+                    _ => return false,
+                };
+                event.prevent_default();
+                let target: Element = event.target_unchecked_into();
+                // Keep receiving the moves when the pointer leaves the square.
+                let _ = target.set_pointer_capture(event.pointer_id());
+                self.drag = Some(Drag {
+                    start_x: event.client_x(),
+                    start_y: event.client_y(),
+                    start_crop: self.crop,
+                    scale: f64::from(image.natural_width()) / f64::from(image.client_width()),
+                });
+                return false;
+            }
+            Msg::DragMove(event) => {
+                let drag = match &self.drag {
+                    Some(drag) => drag,
+                    None => return false,
+                };
+                self.crop.x =
+                    drag.start_crop.x + f64::from(event.client_x() - drag.start_x) * drag.scale;
+                self.crop.y =
+                    drag.start_crop.y + f64::from(event.client_y() - drag.start_y) * drag.scale;
+                self.clamp_crop();
+            }
+            Msg::DragEnd => {
+                self.drag = None;
+                return false;
+            }
+            Msg::Zoom(percent) => {
+                let (width, height) = match self.natural_size {
+                    Some(size) => size,
+                    None => return false,
+                };
+                // Resize around the center of the square.
+                let center = (
+                    self.crop.x + self.crop.size / 2.0,
+                    self.crop.y + self.crop.size / 2.0,
+                );
+                self.zoom_percent = percent.clamp(MIN_ZOOM_PERCENT, 100);
+                self.crop.size = width.min(height) * f64::from(self.zoom_percent) / 100.0;
+                self.crop.x = center.0 - self.crop.size / 2.0;
+                self.crop.y = center.1 - self.crop.size / 2.0;
+                self.clamp_crop();
+            }
+            Msg::Apply => match self.export(ctx) {
+                Ok(bytes) => ctx.props().on_cropped.emit(bytes),
+                Err(e) => ctx.props().on_error.emit(e),
+            },
+            Msg::Cancel => ctx.props().on_cancel.emit(()),
+        }
+        true
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        if self.src != ctx.props().src {
+            // A new picture: wait for it to load.
+            self.src = ctx.props().src.clone();
+            self.natural_size = None;
+            self.drag = None;
+        }
+        true
+    }
+
+    fn rendered(&mut self, _: &Context<Self>, _first_render: bool) {
+        if self.natural_size.is_none() {
+            return;
+        }
+        if let (Some(image), Some(preview)) = (
+            self.image.cast::<HtmlImageElement>(),
+            self.preview.cast::<HtmlCanvasElement>(),
+        ) {
+            // The preview is only informative, the errors show up when applying.
+            let _ = draw_crop(&image, &preview, &self.crop);
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        let loaded = self.natural_size.is_some();
+        html! {
+          <div class="border rounded p-2 mb-3">
+            <div class="row g-3 align-items-center">
+              <div class="col-sm-8">
+                <div style="position: relative; display: inline-block; overflow: hidden; line-height: 0;">
+                  <img
+                    ref={self.image.clone()}
+                    src={ctx.props().src.clone()}
+                    style="max-width: 100%; max-height: 320px; user-select: none;"
+                    draggable="false"
+                    alt="Picture to crop"
+                    onload={link.callback(|_| Msg::ImageLoaded)}
+                    onerror={link.callback(|_| Msg::ImageFailed)} />
+                  {self.view_selector(ctx)}
+                </div>
+                <label for="avatarZoom" class="form-label mt-2 mb-0">{"Zoom"}</label>
+                <input
+                  type="range"
+                  class="form-range"
+                  id="avatarZoom"
+                  min={MIN_ZOOM_PERCENT.to_string()}
+                  max="100"
+                  // The slider zooms in: the right end is the smallest square.
+                  value={(100 + MIN_ZOOM_PERCENT - self.zoom_percent).to_string()}
+                  disabled={!loaded}
+                  oninput={link.callback(|e: InputEvent| {
+                    let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+                    let value = input.value().parse::<u32>().unwrap_or(MIN_ZOOM_PERCENT);
+                    Msg::Zoom(100 + MIN_ZOOM_PERCENT - value)
+                  })} />
+              </div>
+              <div class="col-sm-4 text-center">
+                <div class="form-text mb-1">{"Preview"}</div>
+                <canvas
+                  ref={self.preview.clone()}
+                  id="avatarPreview"
+                  class="rounded-circle border"
+                  width={PREVIEW_SIZE.to_string()}
+                  height={PREVIEW_SIZE.to_string()} />
+                <div class="mt-2">
+                  <button
+                    type="button"
+                    class="btn btn-primary btn-sm me-2"
+                    disabled={!loaded}
+                    onclick={link.callback(|_| Msg::Apply)}>
+                    <i class="bi-crop me-1"></i>
+                    {"Crop"}
+                  </button>
+                  <button
+                    type="button"
+                    class="btn btn-secondary btn-sm"
+                    onclick={link.callback(|_| Msg::Cancel)}>
+                    {"Cancel"}
+                  </button>
+                </div>
+              </div>
+            </div>
+          </div>
+        }
+    }
+}
@@ -4,7 +4,8 @@ use crate::components::{
     router::{AppRoute, Link},
 };
 use wasm_bindgen::prelude::wasm_bindgen;
-use yew::{function_component, html, Callback, Properties};
+use web_sys::MouseEvent;
+use yew::{classes, function_component, html, use_state, Callback, Properties};
 
 #[derive(Properties, PartialEq)]
 pub struct Props {
@@ -13,58 +14,86 @@ pub struct Props {
     pub on_logged_out: Callback<()>,
 }
 
+/// The navigation bar. Below the `lg` breakpoint, the links collapse behind a menu button, and
+/// following a link closes the menu again.
 #[function_component(Banner)]
 pub fn banner(props: &Props) -> Html {
+    let menu_open = use_state(|| false);
+    let toggle_menu = {
+        let menu_open = menu_open.clone();
+        Callback::from(move |_: MouseEvent| menu_open.set(!*menu_open))
+    };
+    let close_menu = {
+        let menu_open = menu_open.clone();
+        Callback::from(move |_: MouseEvent| menu_open.set(false))
+    };
     html! {
       <header class="p-2 mb-3 border-bottom">
-        <div class="container">
-          <div class="d-flex flex-wrap align-items-center justify-content-center justify-content-lg-start">
-            <a href={yew_router::utils::base_url().unwrap_or("/".to_string())} class="d-flex align-items-center mt-2 mb-lg-0 me-md-5 text-decoration-none">
+        <nav class="navbar navbar-expand-lg p-0">
+          <div class="container">
+            <a href={yew_router::utils::base_url().unwrap_or("/".to_string())} class="navbar-brand d-flex align-items-center mt-2 mb-lg-0 me-md-5 text-decoration-none">
               <h2>{"LLDAP"}</h2>
             </a>
-
-            <ul class="nav col-12 col-lg-auto me-lg-auto mb-2 justify-content-center mb-md-0">
-              {if props.is_admin { html! {
-                <>
-                  <li>
-                    <Link
-                      classes="nav-link px-2 h6"
-                      to={AppRoute::ListUsers}>
-                      <i class="bi-people me-2"></i>
-                      {"Users"}
-                    </Link>
-                  </li>
-                  <li>
-                    <Link
-                      classes="nav-link px-2 h6"
-                      to={AppRoute::ListGroups}>
-                      <i class="bi-collection me-2"></i>
-                      {"Groups"}
-                    </Link>
-                  </li>
-                  <li>
-                    <Link
-                      classes="nav-link px-2 h6"
-                      to={AppRoute::ListUserSchema}>
-                      <i class="bi-list-ul me-2"></i>
-                      {"User schema"}
-                    </Link>
-                  </li>
-                  <li>
-                    <Link
-                      classes="nav-link px-2 h6"
-                      to={AppRoute::ListGroupSchema}>
-                      <i class="bi-list-ul me-2"></i>
-                      {"Group schema"}
-                    </Link>
-                  </li>
-                </>
-              } } else { html!{} } }
-            </ul>
-            <UserMenu username={props.username.clone()} on_logged_out={props.on_logged_out.clone()}/>
-            <DarkModeToggle />
+            <button
+              class="navbar-toggler"
+              type="button"
+              aria-controls="navbarMenu"
+              aria-expanded={(*menu_open).to_string()}
+              aria-label="Toggle navigation"
+              onclick={toggle_menu}>
+              <i class="bi-list"></i>
+            </button>
+            <div
+              class={classes!("collapse", "navbar-collapse", (*menu_open).then_some("show"))}
+              id="navbarMenu">
+              <ul class="navbar-nav me-lg-auto mb-2 mb-lg-0" onclick={close_menu.clone()}>
+                {if props.is_admin { html! {
+                  <>
+                    <li class="nav-item">
+                      <Link
+                        classes="nav-link px-2 h6"
+                        to={AppRoute::ListUsers}>
+                        <i class="bi-people me-2"></i>
+                        {"Users"}
+                      </Link>
+                    </li>
+                    <li class="nav-item">
+                      <Link
+                        classes="nav-link px-2 h6"
+                        to={AppRoute::ListGroups}>
+                        <i class="bi-collection me-2"></i>
+                        {"Groups"}
+                      </Link>
+                    </li>
+                    <li class="nav-item">
+                      <Link
+                        classes="nav-link px-2 h6"
+                        to={AppRoute::ListUserSchema}>
+                        <i class="bi-list-ul me-2"></i>
+                        {"User schema"}
+                      </Link>
+                    </li>
+                    <li class="nav-item">
+                      <Link
+                        classes="nav-link px-2 h6"
+                        to={AppRoute::ListGroupSchema}>
+                        <i class="bi-list-ul me-2"></i>
+                        {"Group schema"}
+                      </Link>
+                    </li>
+                  </>
+                } } else { html!{} } }
+              </ul>
+              <div class="d-flex align-items-center mb-2 mb-lg-0">
+                <UserMenu
+                  username={props.username.clone()}
+                  on_logged_out={props.on_logged_out.clone()}
+                  on_navigate={close_menu} />
+                <ThemeSelector />
+              </div>
+            </div>
           </div>
-        </div>
+        </nav>
       </header>
     }
 }
@@ -73,6 +102,8 @@ pub fn banner(props: &Props) -> Html {
 struct UserMenuProps {
     pub username: Option<String>,
     pub on_logged_out: Callback<()>,
+    /// Called when following the link to the user details.
+    pub on_navigate: Callback<MouseEvent>,
 }
 
 #[function_component(UserMenu)]
@@ -94,7 +125,7 @@ fn user_menu(props: &UserMenuProps) -> Html {
               class="dropdown-menu text-small dropdown-menu-lg-end"
               aria-labelledby="dropdownUser1"
               style="">
-              <li>
+              <li onclick={props.on_navigate.clone()}>
                 <Link
                   classes="dropdown-item"
                   to={AppRoute::UserDetails{ user_id: username.to_string() }}>
@@ -114,19 +145,60 @@ fn user_menu(props: &UserMenuProps) -> Html {
 
 #[wasm_bindgen]
 extern "C" {
-    #[wasm_bindgen(js_namespace = darkmode)]
-    fn toggleDarkMode(doSave: bool);
+    #[wasm_bindgen(js_namespace = lldapTheme)]
+    fn getTheme() -> String;
 
-    #[wasm_bindgen]
-    fn inDarkMode() -> bool;
+    #[wasm_bindgen(js_namespace = lldapTheme)]
+    fn setTheme(theme: &str);
 }
 
-#[function_component(DarkModeToggle)]
-fn dark_mode_toggle() -> Html {
+/// The themes, with their label and icon. The theme is applied and saved by static/theme.js.
+const THEMES: [(&str, &str, &str); 3] = [
+    ("light", "Light", "bi-sun"),
+    ("dark", "Dark", "bi-moon-stars"),
+    ("system", "System", "bi-circle-half"),
+];
+
+#[function_component(ThemeSelector)]
+fn theme_selector() -> Html {
+    let theme = use_state(getTheme);
+    let icon = THEMES
+        .iter()
+        .find(|(name, _, _)| *name == theme.as_str())
+        .map(|(_, _, icon)| *icon)
+        .unwrap_or("bi-circle-half");
     html! {
-      <div class="form-check form-switch">
-        <input class="form-check-input" onclick={|_| toggleDarkMode(true)} type="checkbox" id="darkModeToggle" checked={inDarkMode()}/>
-        <label class="form-check-label" for="darkModeToggle">{"Dark mode"}</label>
+      <div class="dropdown ms-2">
+        <button
+          class="btn btn-link nav-link dropdown-toggle"
+          id="themeSelector"
+          title="Theme"
+          data-bs-toggle="dropdown"
+          aria-expanded="false">
+          <i class={icon}></i>
+          <span class="visually-hidden">{"Theme"}</span>
+        </button>
+        <ul class="dropdown-menu dropdown-menu-lg-end" aria-labelledby="themeSelector">
+          {for THEMES.iter().map(|&(name, label, icon)| {
+            let onclick = {
+              let theme = theme.clone();
+              Callback::from(move |_: MouseEvent| {
+                setTheme(name);
+                theme.set(name.to_string());
+              })
+            };
+            html! {
+              <li>
+                <button
+                  class={classes!("dropdown-item", (theme.as_str() == name).then_some("active"))}
+                  {onclick}>
+                  <i class={classes!(icon, "me-2")}></i>
+                  {label}
+                </button>
+              </li>
+            }
+          })}
+        </ul>
       </div>
     }
 }
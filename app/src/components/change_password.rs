@@ -1,6 +1,10 @@
 use crate::{
     components::{
-        form::{field::Field, submit::Submit},
+        form::{
+            field::Field,
+            password_strength::{check_password_policy, PasswordStrength},
+            submit::Submit,
+        },
         router::{AppRoute, Link},
     },
     infra::{
@@ -10,7 +14,7 @@ use crate::{
 };
 use anyhow::{anyhow, bail, Result};
 use gloo_console::error;
-use lldap_auth::*;
+use lldap_auth::{password_policy::PasswordPolicy, *};
 use validator_derive::Validate;
 use yew::prelude::*;
 use yew_form::Form;
@@ -39,7 +43,7 @@ pub struct FormModel {
         message = "Password should be longer than 8 characters"
     ))]
     old_password: String,
-    #[validate(length(min = 8, message = "Invalid password. Min length: 8"))]
+    #[validate(length(min = 1, message = "Password is required"))]
     password: String,
     #[validate(must_match(other = "password", message = "Passwords must match"))]
     confirm_password: String,
@@ -57,6 +61,7 @@ pub struct ChangePasswordForm {
     common: CommonComponentParts<Self>,
     form: Form<FormModel>,
     opaque_data: OpaqueData,
+    password_policy: PasswordPolicy,
 }
 
 #[derive(Clone, PartialEq, Eq, Properties)]
@@ -66,6 +71,7 @@ pub struct Props {
 }
 
 pub enum Msg {
+    PasswordPolicyResponse(Result<PasswordPolicy>),
     FormUpdate,
     Submit,
     AuthenticationStartResponse(Result<Box<login::ServerLoginStartResponse>>),
@@ -82,11 +88,20 @@ impl CommonComponent<ChangePasswordForm> for ChangePasswordForm {
     ) -> Result<bool> {
         use anyhow::Context;
         match msg {
+            Msg::PasswordPolicyResponse(policy) => {
+                match policy {
+                    Ok(policy) => self.password_policy = policy,
+                    // The default policy is still checked.
+                    Err(e) => error!(&format!("Could not fetch the password policy: {}", e)),
+                }
+                Ok(true)
+            }
             Msg::FormUpdate => Ok(true),
             Msg::Submit => {
                 if !self.form.validate() {
                     bail!("Check the form for errors");
                 }
+                check_password_policy(&self.password_policy, &self.form.model().password)?;
                 if ctx.props().is_admin {
                     self.handle_msg(ctx, Msg::SubmitNewPassword)
                 } else {
@@ -195,11 +210,15 @@ impl Component for ChangePasswordForm {
     type Message = Msg;
     type Properties = Props;
 
-    fn create(_: &Context<Self>) -> Self {
+    fn create(ctx: &Context<Self>) -> Self {
+        ctx.link().send_future(async {
+            Msg::PasswordPolicyResponse(HostService::get_password_policy().await)
+        });
         ChangePasswordForm {
             common: CommonComponentParts::<Self>::create(),
             form: yew_form::Form::<FormModel>::new(FormModel::default()),
             opaque_data: OpaqueData::None,
+            password_policy: PasswordPolicy::default(),
         }
     }
 
@@ -245,6 +264,10 @@ impl Component for ChangePasswordForm {
                 input_type="password"
                 autocomplete="new-password"
                 oninput={link.callback(|_| Msg::FormUpdate)} />
+              <PasswordStrength
+                password={self.form.model().password}
+                policy={self.password_policy.clone()}
+                user_inputs={vec![ctx.props().username.clone()]} />
               <Field<FormModel>
                 form={&self.form}
                 required=true
@@ -0,0 +1,245 @@
+use crate::infra::modal::Modal;
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{HtmlElement, HtmlInputElement};
+use yew::prelude::*;
+
+/// Deleting a group with more members than this needs the safety text.
+pub const LARGE_GROUP_MEMBERS: usize = 10;
+
+/// The groups granting permissions in LLDAP: any change of their members or to the group itself
+/// needs the safety text.
+pub fn is_admin_group(name: &str) -> bool {
+    [
+        "lldap_admin",
+        "lldap_password_manager",
+        "lldap_strict_readonly",
+    ]
+    .contains(&name)
+}
+
+/// A confirmation modal for the destructive actions. For the high-impact ones, the admin has to
+/// type a safety text (e.g. the name of the group) before confirming.
+///
+/// Escape cancels, and Enter confirms: from the safety input once it matches, or from the
+/// focused confirm button.
+pub struct ConfirmDialog {
+    node_ref: NodeRef,
+    safety_input: NodeRef,
+    confirm_button: NodeRef,
+    modal: Option<Modal>,
+    /// Focuses the first input once the modal is visible.
+    _on_shown: Option<Closure<dyn FnMut()>>,
+    visible: bool,
+    typed: String,
+}
+
+pub enum Msg {
+    Typed(String),
+    KeyDown(KeyboardEvent),
+    Confirm,
+    Cancel,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    /// Unique in the page, for the ids of the elements.
+    pub id: AttrValue,
+    pub show: bool,
+    pub title: AttrValue,
+    /// The question, e.g. "Are you sure you want to delete ...?".
+    pub children: Children,
+    #[prop_or(AttrValue::Static("Yes, I'm sure"))]
+    pub confirm_label: AttrValue,
+    /// The text to type to enable the confirm button.
+    #[prop_or_default]
+    pub safety_text: Option<AttrValue>,
+    pub on_confirm: Callback<()>,
+    pub on_cancel: Callback<()>,
+}
+
+impl ConfirmDialog {
+    fn can_confirm(&self, ctx: &Context<Self>) -> bool {
+        match &ctx.props().safety_text {
+            Some(text) => self.typed.trim() == text.as_str(),
+            None => true,
+        }
+    }
+
+    fn update_visibility(&mut self, ctx: &Context<Self>) {
+        if ctx.props().show == self.visible {
+            return;
+        }
+        self.visible = ctx.props().show;
+        if let Some(modal) = &self.modal {
+            if self.visible {
+                self.typed.clear();
+                if let Some(input) = self.safety_input.cast::<HtmlInputElement>() {
+                    input.set_value("");
+                }
+                modal.show();
+            } else {
+                modal.hide();
+            }
+        }
+    }
+}
+
+impl Component for ConfirmDialog {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(_: &Context<Self>) -> Self {
+        Self {
+            node_ref: NodeRef::default(),
+            safety_input: NodeRef::default(),
+            confirm_button: NodeRef::default(),
+            modal: None,
+            _on_shown: None,
+            visible: false,
+            typed: String::new(),
+        }
+    }
+
+    fn rendered(&mut self, ctx: &Context<Self>, first_render: bool) {
+        if first_render {
+            let element = self
+                .node_ref
+                .cast::<web_sys::Element>()
+                .expect("Modal node is not an element");
+            let (safety_input, confirm_button) =
+                (self.safety_input.clone(), self.confirm_button.clone());
+            let on_shown = Closure::<dyn FnMut()>::new(move || {
+                let focused = safety_input
+                    .cast::<HtmlElement>()
+                    .or_else(|| confirm_button.cast::<HtmlElement>());
+                if let Some(element) = focused {
+                    let _ = element.focus();
+                }
+            });
+            let _ = element.add_event_listener_with_callback(
+                "shown.bs.modal",
+                on_shown.as_ref().unchecked_ref(),
+            );
+            self._on_shown = Some(on_shown);
+            self.modal = Some(Modal::new(element));
+            self.update_visibility(ctx);
+        }
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        self.update_visibility(ctx);
+        true
+    }
+
+    fn destroy(&mut self, _: &Context<Self>) {
+        // Otherwise the backdrop stays when the dialog goes away with its row.
+        if let (true, Some(modal)) = (self.visible, &self.modal) {
+            modal.hide();
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Typed(text) => {
+                self.typed = text;
+                true
+            }
+            Msg::KeyDown(e) => {
+                if e.key() == "Escape" {
+                    e.prevent_default();
+                    ctx.props().on_cancel.emit(());
+                }
+                false
+            }
+            Msg::Confirm => {
+                if self.can_confirm(ctx) {
+                    ctx.props().on_confirm.emit(());
+                }
+                false
+            }
+            Msg::Cancel => {
+                ctx.props().on_cancel.emit(());
+                false
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        let props = ctx.props();
+        let label_id = format!("{}Label", props.id);
+        html! {
+          <div
+            class="modal fade"
+            id={props.id.clone()}
+            tabindex="-1"
+            role="dialog"
+            aria-labelledby={label_id.clone()}
+            aria-hidden="true"
+            data-bs-backdrop="static"
+            data-bs-keyboard="false"
+            onkeydown={link.callback(Msg::KeyDown)}
+            ref={self.node_ref.clone()}>
+            <div class="modal-dialog modal-dialog-centered modal-dialog-scrollable">
+              <div class="modal-content">
+                <div class="modal-header">
+                  <h5 class="modal-title" id={label_id}>{&props.title}</h5>
+                  <button
+                    type="button"
+                    class="btn-close"
+                    aria-label="Close"
+                    onclick={link.callback(|_| Msg::Cancel)} />
+                </div>
+                <div class="modal-body">
+                  <div>{props.children.clone()}</div>
+                  {match &props.safety_text {
+                      None => html! {},
+                      Some(text) => html! {
+                        <div class="mt-3">
+                          <label class="form-label" for={format!("{}Safety", props.id)}>
+                            {"To confirm, type "}<b>{text}</b>{":"}
+                          </label>
+                          <input
+                            type="text"
+                            class="form-control"
+                            id={format!("{}Safety", props.id)}
+                            autocomplete="off"
+                            ref={self.safety_input.clone()}
+                            oninput={link.callback(|e: InputEvent| {
+                              let input: HtmlInputElement = e.target_unchecked_into();
+                              Msg::Typed(input.value())
+                            })}
+                            onkeydown={link.batch_callback(|e: KeyboardEvent| {
+                              (e.key() == "Enter").then(|| {
+                                e.prevent_default();
+                                Msg::Confirm
+                              })
+                            })} />
+                        </div>
+                      },
+                  }}
+                </div>
+                <div class="modal-footer">
+                  <button
+                    type="button"
+                    class="btn btn-secondary"
+                    onclick={link.callback(|_| Msg::Cancel)}>
+                    <i class="bi-x-circle me-2"></i>
+                    {"Cancel"}
+                  </button>
+                  <button
+                    type="button"
+                    class="btn btn-danger"
+                    disabled={!self.can_confirm(ctx)}
+                    ref={self.confirm_button.clone()}
+                    onclick={link.callback(|_| Msg::Confirm)}>
+                    <i class="bi-check-circle me-2"></i>
+                    {&props.confirm_label}
+                  </button>
+                </div>
+              </div>
+            </div>
+          </div>
+        }
+    }
+}
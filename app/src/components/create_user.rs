@@ -1,6 +1,11 @@
 use crate::{
     components::{
-        form::{field::Field, submit::Submit},
+        form::{
+            checkbox::CheckBox,
+            field::Field,
+            password_strength::{check_password_policy, PasswordStrength},
+            submit::Submit,
+        },
         router::AppRoute,
     },
     infra::{
@@ -9,9 +14,9 @@ use crate::{
     },
 };
 use anyhow::{bail, Result};
-use gloo_console::log;
+use gloo_console::{error, log};
 use graphql_client::GraphQLQuery;
-use lldap_auth::{opaque, registration};
+use lldap_auth::{opaque, password_policy::PasswordPolicy, registration};
 use validator_derive::Validate;
 use yew::prelude::*;
 use yew_form_derive::Model;
@@ -29,6 +34,7 @@ pub struct CreateUser;
 pub struct CreateUserForm {
     common: CommonComponentParts<Self>,
     form: yew_form::Form<CreateUserModel>,
+    password_policy: PasswordPolicy,
 }
 
 #[derive(Model, Validate, PartialEq, Eq, Clone, Default)]
@@ -40,24 +46,22 @@ pub struct CreateUserModel {
     display_name: String,
     first_name: String,
     last_name: String,
-    #[validate(custom(
-        function = "empty_or_long",
-        message = "Password should be longer than 8 characters (or left empty)"
-    ))]
+    /// Send an email to let the user choose their password, instead of setting it here.
+    invite: bool,
+    /// Checked against the password policy, unless left empty.
     password: String,
     #[validate(must_match(other = "password", message = "Passwords must match"))]
     confirm_password: String,
 }
 
-fn empty_or_long(value: &str) -> Result<(), validator::ValidationError> {
-    if value.is_empty() || value.len() >= 8 {
-        Ok(())
-    } else {
-        Err(validator::ValidationError::new(""))
-    }
+#[derive(yew::Properties, Clone, PartialEq, Eq)]
+pub struct Props {
+    /// Whether the server can send invitation emails.
+    pub invitations_enabled: bool,
 }
 
 pub enum Msg {
+    PasswordPolicyResponse(Result<PasswordPolicy>),
     Update,
     SubmitForm,
     CreateUserResponse(Result<create_user::ResponseData>),
@@ -78,12 +82,23 @@ impl CommonComponent<CreateUserForm> for CreateUserForm {
         msg: <Self as Component>::Message,
     ) -> Result<bool> {
         match msg {
+            Msg::PasswordPolicyResponse(policy) => {
+                match policy {
+                    Ok(policy) => self.password_policy = policy,
+                    // The default policy is still checked.
+                    Err(e) => error!(&format!("Could not fetch the password policy: {}", e)),
+                }
+                Ok(true)
+            }
             Msg::Update => Ok(true),
             Msg::SubmitForm => {
                 if !self.form.validate() {
                     bail!("Check the form for errors");
                 }
                 let model = self.form.model();
+                if !model.invite && !model.password.is_empty() {
+                    check_password_policy(&self.password_policy, &model.password)?;
+                }
                 let to_option = |s: String| if s.is_empty() { None } else { Some(s) };
                 let req = create_user::Variables {
                     user: create_user::CreateUserInput {
@@ -93,8 +108,18 @@ impl CommonComponent<CreateUserForm> for CreateUserForm {
                         firstName: to_option(model.first_name),
                         lastName: to_option(model.last_name),
                         avatar: None,
+                        preferredLanguage: None,
+                        hosts: None,
+                        phone: None,
+                        mobile: None,
+                        postalAddress: None,
+                        locality: None,
+                        stateOrProvince: None,
+                        expiresAt: None,
                         attributes: None,
+                        groups: None,
                     },
+                    sendInvite: Some(model.invite),
                 };
                 self.common.call_graphql::<CreateUser, _>(
                     ctx,
@@ -115,7 +140,7 @@ impl CommonComponent<CreateUserForm> for CreateUserForm {
                 let model = self.form.model();
                 let user_id = model.username;
                 let password = model.password;
-                if !password.is_empty() {
+                if !model.invite && !password.is_empty() {
                     // User was successfully created, let's register the password.
                     let mut rng = rand::rngs::OsRng;
                     let opaque::client::registration::ClientRegistrationStartResult {
@@ -175,12 +200,16 @@ impl CommonComponent<CreateUserForm> for CreateUserForm {
 
 impl Component for CreateUserForm {
     type Message = Msg;
-    type Properties = ();
+    type Properties = Props;
 
-    fn create(_: &Context<Self>) -> Self {
+    fn create(ctx: &Context<Self>) -> Self {
+        ctx.link().send_future(async {
+            Msg::PasswordPolicyResponse(HostService::get_password_policy().await)
+        });
         Self {
             common: CommonComponentParts::<Self>::create(),
             form: yew_form::Form::<CreateUserModel>::new(CreateUserModel::default()),
+            password_policy: PasswordPolicy::default(),
         }
     }
 
@@ -190,6 +219,8 @@ impl Component for CreateUserForm {
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let link = &ctx.link();
+        let model = self.form.model();
+        let invite = model.invite;
         html! {
           <div class="row justify-content-center">
             <form class="form py-3" style="max-width: 636px">
@@ -224,20 +255,47 @@ impl Component for CreateUserForm {
                 field_name="last_name"
                 autocomplete="family-name"
                 oninput={link.callback(|_| Msg::Update)} />
-              <Field<CreateUserModel>
-                form={&self.form}
-                label="Password"
-                field_name="password"
-                input_type="password"
-                autocomplete="new-password"
-                oninput={link.callback(|_| Msg::Update)} />
-              <Field<CreateUserModel>
-                form={&self.form}
-                label="Confirm password"
-                field_name="confirm_password"
-                input_type="password"
-                autocomplete="new-password"
-                oninput={link.callback(|_| Msg::Update)} />
+              {
+                if ctx.props().invitations_enabled {
+                  html! {
+                    <CheckBox<CreateUserModel>
+                      label="Invite by email"
+                      form={&self.form}
+                      field_name="invite"
+                      ontoggle={link.callback(|_| Msg::Update)} />
+                  }
+                } else { html! {} }
+              }
+              {
+                if invite { html! {} } else {
+                  html! {
+                    <>
+                      <Field<CreateUserModel>
+                        form={&self.form}
+                        label="Password"
+                        field_name="password"
+                        input_type="password"
+                        autocomplete="new-password"
+                        oninput={link.callback(|_| Msg::Update)} />
+                      <PasswordStrength
+                        password={model.password.clone()}
+                        policy={self.password_policy.clone()}
+                        user_inputs={vec![
+                          model.username.clone(),
+                          model.email.clone(),
+                          model.display_name.clone(),
+                        ]} />
+                      <Field<CreateUserModel>
+                        form={&self.form}
+                        label="Confirm password"
+                        field_name="confirm_password"
+                        input_type="password"
+                        autocomplete="new-password"
+                        oninput={link.callback(|_| Msg::Update)} />
+                    </>
+                  }
+                }
+              }
               <Submit
                 disabled={self.common.is_task_running()}
                 onclick={link.callback(|e: MouseEvent| {e.prevent_default(); Msg::SubmitForm})} />
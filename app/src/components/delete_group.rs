@@ -1,9 +1,9 @@
 use crate::{
-    components::group_table::Group,
-    infra::{
-        common_component::{CommonComponent, CommonComponentParts},
-        modal::Modal,
+    components::{
+        confirm_dialog::{is_admin_group, ConfirmDialog, LARGE_GROUP_MEMBERS},
+        group_table::Group,
     },
+    infra::common_component::{CommonComponent, CommonComponentParts},
 };
 use anyhow::{Error, Result};
 use graphql_client::GraphQLQuery;
@@ -20,8 +20,7 @@ pub struct DeleteGroupQuery;
 
 pub struct DeleteGroup {
     common: CommonComponentParts<Self>,
-    node_ref: NodeRef,
-    modal: Option<Modal>,
+    show_confirmation: bool,
 }
 
 #[derive(yew::Properties, Clone, PartialEq, Debug)]
@@ -46,10 +45,10 @@ impl CommonComponent<DeleteGroup> for DeleteGroup {
     ) -> Result<bool> {
         match msg {
             Msg::ClickedDeleteGroup => {
-                self.modal.as_ref().expect("modal not initialized").show();
+                self.show_confirmation = true;
             }
             Msg::ConfirmDeleteGroup => {
-                self.update(ctx, Msg::DismissModal);
+                self.show_confirmation = false;
                 self.common.call_graphql::<DeleteGroupQuery, _>(
                     ctx,
                     delete_group_query::Variables {
@@ -60,7 +59,7 @@ impl CommonComponent<DeleteGroup> for DeleteGroup {
                 );
             }
             Msg::DismissModal => {
-                self.modal.as_ref().expect("modal not initialized").hide();
+                self.show_confirmation = false;
             }
             Msg::DeleteGroupResponse(response) => {
                 response?;
@@ -82,18 +81,7 @@ impl Component for DeleteGroup {
     fn create(_: &Context<Self>) -> Self {
         Self {
             common: CommonComponentParts::<Self>::create(),
-            node_ref: NodeRef::default(),
-            modal: None,
-        }
-    }
-
-    fn rendered(&mut self, _: &Context<Self>, first_render: bool) {
-        if first_render {
-            self.modal = Some(Modal::new(
-                self.node_ref
-                    .cast::<web_sys::Element>()
-                    .expect("Modal node is not an element"),
-            ));
+            show_confirmation: false,
         }
     }
 
@@ -108,6 +96,9 @@ impl Component for DeleteGroup {
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let link = &ctx.link();
+        let group = &ctx.props().group;
+        let high_impact =
+            group.users.len() > LARGE_GROUP_MEMBERS || is_admin_group(&group.display_name);
         html! {
           <>
           <button
@@ -116,58 +107,18 @@ impl Component for DeleteGroup {
             onclick={link.callback(|_| Msg::ClickedDeleteGroup)}>
             <i class="bi-x-circle-fill" aria-label="Delete group" />
           </button>
-          {self.show_modal(ctx)}
+          <ConfirmDialog
+            id={format!("deleteGroupModal{}", group.id)}
+            show={self.show_confirmation}
+            title="Delete group?"
+            safety_text={high_impact.then(|| AttrValue::from(group.display_name.clone()))}
+            on_confirm={link.callback(|_| Msg::ConfirmDeleteGroup)}
+            on_cancel={link.callback(|_| Msg::DismissModal)}>
+            {"Are you sure you want to delete group "}
+            <b>{&group.display_name}</b>
+            {format!(", with {} members? This can't be undone.", group.users.len())}
+          </ConfirmDialog>
           </>
         }
     }
 }
-
-impl DeleteGroup {
-    fn show_modal(&self, ctx: &Context<Self>) -> Html {
-        let link = &ctx.link();
-        html! {
-          <div
-            class="modal fade"
-            id={"deleteGroupModal".to_string() + &ctx.props().group.id.to_string()}
-            tabindex="-1"
-            aria-labelledby="deleteGroupModalLabel"
-            aria-hidden="true"
-            ref={self.node_ref.clone()}>
-            <div class="modal-dialog">
-              <div class="modal-content">
-                <div class="modal-header">
-                  <h5 class="modal-title" id="deleteGroupModalLabel">{"Delete group?"}</h5>
-                  <button
-                    type="button"
-                    class="btn-close"
-                    aria-label="Close"
-                    onclick={link.callback(|_| Msg::DismissModal)} />
-                </div>
-                <div class="modal-body">
-                <span>
-                  {"Are you sure you want to delete group "}
-                  <b>{&ctx.props().group.display_name}</b>{"?"}
-                </span>
-                </div>
-                <div class="modal-footer">
-                  <button
-                    type="button"
-                    class="btn btn-secondary"
-                    onclick={link.callback(|_| Msg::DismissModal)}>
-                      <i class="bi-x-circle me-2"></i>
-                      {"Cancel"}
-                  </button>
-                  <button
-                    type="button"
-                    onclick={link.callback(|_| Msg::ConfirmDeleteGroup)}
-                    class="btn btn-danger">
-                    <i class="bi-check-circle me-2"></i>
-                    {"Yes, I'm sure"}
-                 </button>
-                </div>
-              </div>
-            </div>
-          </div>
-        }
-    }
-}
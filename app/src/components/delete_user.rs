@@ -1,6 +1,6 @@
-use crate::infra::{
-    common_component::{CommonComponent, CommonComponentParts},
-    modal::Modal,
+use crate::{
+    components::confirm_dialog::ConfirmDialog,
+    infra::common_component::{CommonComponent, CommonComponentParts},
 };
 use anyhow::{Error, Result};
 use graphql_client::GraphQLQuery;
@@ -15,10 +15,51 @@ use yew::prelude::*;
 )]
 pub struct DeleteUserQuery;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/get_groups_emptied_by_deletion.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql"
+)]
+pub struct GetGroupsEmptiedByDeletion;
+
+use delete_user_query::EmptyGroupPolicy;
+
+/// What to do with the groups that the user is the last member of, offered in the confirmation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum EmptyGroupChoice {
+    Keep,
+    Delete,
+    Fail,
+}
+
+impl EmptyGroupChoice {
+    const ALL: [Self; 3] = [Self::Keep, Self::Delete, Self::Fail];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Keep => "Keep these groups, empty",
+            Self::Delete => "Delete these groups",
+            Self::Fail => "Don't delete the user if it is still the last member of a group",
+        }
+    }
+
+    fn policy(self) -> EmptyGroupPolicy {
+        match self {
+            Self::Keep => EmptyGroupPolicy::KEEP,
+            Self::Delete => EmptyGroupPolicy::DELETE_EMPTY,
+            Self::Fail => EmptyGroupPolicy::FAIL_IF_LAST_MEMBER,
+        }
+    }
+}
+
 pub struct DeleteUser {
     common: CommonComponentParts<Self>,
-    node_ref: NodeRef,
-    modal: Option<Modal>,
+    show_confirmation: bool,
+    /// The names of the groups that the deletion would leave empty.
+    emptied_groups: Vec<String>,
+    choice: EmptyGroupChoice,
 }
 
 #[derive(yew::Properties, Clone, PartialEq, Debug)]
@@ -30,6 +71,8 @@ pub struct DeleteUserProps {
 
 pub enum Msg {
     ClickedDeleteUser,
+    EmptiedGroupsResponse(Result<get_groups_emptied_by_deletion::ResponseData>),
+    SetChoice(EmptyGroupChoice),
     ConfirmDeleteUser,
     DismissModal,
     DeleteUserResponse(Result<delete_user_query::ResponseData>),
@@ -43,21 +86,42 @@ impl CommonComponent<DeleteUser> for DeleteUser {
     ) -> Result<bool> {
         match msg {
             Msg::ClickedDeleteUser => {
-                self.modal.as_ref().expect("modal not initialized").show();
+                self.common.call_graphql::<GetGroupsEmptiedByDeletion, _>(
+                    ctx,
+                    get_groups_emptied_by_deletion::Variables {
+                        id: ctx.props().username.clone(),
+                    },
+                    Msg::EmptiedGroupsResponse,
+                    "Error trying to list the groups of the user",
+                );
+            }
+            Msg::EmptiedGroupsResponse(response) => {
+                self.emptied_groups = response?
+                    .user
+                    .groups_emptied_by_deletion
+                    .into_iter()
+                    .map(|g| g.display_name)
+                    .collect();
+                self.choice = EmptyGroupChoice::Keep;
+                self.show_confirmation = true;
+            }
+            Msg::SetChoice(choice) => {
+                self.choice = choice;
             }
             Msg::ConfirmDeleteUser => {
-                self.update(ctx, Msg::DismissModal);
+                self.show_confirmation = false;
                 self.common.call_graphql::<DeleteUserQuery, _>(
                     ctx,
                     delete_user_query::Variables {
                         user: ctx.props().username.clone(),
+                        policy: Some(self.choice.policy()),
                     },
                     Msg::DeleteUserResponse,
                     "Error trying to delete user",
                 );
             }
             Msg::DismissModal => {
-                self.modal.as_ref().expect("modal not initialized").hide();
+                self.show_confirmation = false;
             }
             Msg::DeleteUserResponse(response) => {
                 response?;
@@ -74,6 +138,47 @@ impl CommonComponent<DeleteUser> for DeleteUser {
     }
 }
 
+impl DeleteUser {
+    fn view_emptied_groups(&self, ctx: &Context<Self>) -> Html {
+        if self.emptied_groups.is_empty() {
+            return html! {};
+        }
+        let link = &ctx.link();
+        let username = &ctx.props().username;
+        if self.emptied_groups.iter().any(|g| g == "lldap_admin") {
+            return html! {
+              <p class="text-danger mt-3">
+                {"This is the last member of "}<b>{"lldap_admin"}</b>
+                {": it can't be deleted before another user is made admin."}
+              </p>
+            };
+        }
+        html! {
+          <div class="mt-3">
+            <p>{"This is the last member of these groups, that would be left empty:"}</p>
+            <ul>
+              {for self.emptied_groups.iter().map(|g| html! {<li>{g}</li>})}
+            </ul>
+            {for EmptyGroupChoice::ALL.into_iter().enumerate().map(|(i, choice)| {
+              let id = format!("deleteUser{}EmptyGroups{}", username, i);
+              html! {
+                <div class="form-check">
+                  <input
+                    class="form-check-input"
+                    type="radio"
+                    name={format!("deleteUser{}EmptyGroups", username)}
+                    id={id.clone()}
+                    checked={self.choice == choice}
+                    onchange={link.callback(move |_| Msg::SetChoice(choice))} />
+                  <label class="form-check-label" for={id}>{choice.label()}</label>
+                </div>
+              }
+            })}
+          </div>
+        }
+    }
+}
+
 impl Component for DeleteUser {
     type Message = Msg;
     type Properties = DeleteUserProps;
@@ -81,18 +186,9 @@ impl Component for DeleteUser {
     fn create(_: &Context<Self>) -> Self {
         Self {
             common: CommonComponentParts::<Self>::create(),
-            node_ref: NodeRef::default(),
-            modal: None,
-        }
-    }
-
-    fn rendered(&mut self, _: &Context<Self>, first_render: bool) {
-        if first_render {
-            self.modal = Some(Modal::new(
-                self.node_ref
-                    .cast::<web_sys::Element>()
-                    .expect("Modal node is not an element"),
-            ));
+            show_confirmation: false,
+            emptied_groups: Vec::new(),
+            choice: EmptyGroupChoice::Keep,
         }
     }
 
@@ -115,59 +211,17 @@ impl Component for DeleteUser {
             onclick={link.callback(|_| Msg::ClickedDeleteUser)}>
             <i class="bi-x-circle-fill" aria-label="Delete user" />
           </button>
-          {self.show_modal(ctx)}
+          <ConfirmDialog
+            id={format!("deleteUserModal{}", ctx.props().username)}
+            show={self.show_confirmation}
+            title="Delete user?"
+            on_confirm={link.callback(|_| Msg::ConfirmDeleteUser)}
+            on_cancel={link.callback(|_| Msg::DismissModal)}>
+            {"Are you sure you want to delete user "}
+            <b>{&ctx.props().username}</b>{"?"}
+            {self.view_emptied_groups(ctx)}
+          </ConfirmDialog>
           </>
         }
     }
 }
-
-impl DeleteUser {
-    fn show_modal(&self, ctx: &Context<Self>) -> Html {
-        let link = &ctx.link();
-        html! {
-          <div
-            class="modal fade"
-            id={"deleteUserModal".to_string() + &ctx.props().username}
-            tabindex="-1"
-            //role="dialog"
-            aria-labelledby="deleteUserModalLabel"
-            aria-hidden="true"
-            ref={self.node_ref.clone()}>
-            <div class="modal-dialog" /*role="document"*/>
-              <div class="modal-content">
-                <div class="modal-header">
-                  <h5 class="modal-title" id="deleteUserModalLabel">{"Delete user?"}</h5>
-                  <button
-                    type="button"
-                    class="btn-close"
-                    aria-label="Close"
-                    onclick={link.callback(|_| Msg::DismissModal)} />
-                </div>
-                <div class="modal-body">
-                <span>
-                  {"Are you sure you want to delete user "}
-                  <b>{&ctx.props().username}</b>{"?"}
-                </span>
-                </div>
-                <div class="modal-footer">
-                  <button
-                    type="button"
-                    class="btn btn-secondary"
-                    onclick={link.callback(|_| Msg::DismissModal)}>
-                    <i class="bi-x-circle me-2"></i>
-                    {"Cancel"}
-                  </button>
-                  <button
-                    type="button"
-                    onclick={link.callback(|_| Msg::ConfirmDeleteUser)}
-                    class="btn btn-danger">
-                    <i class="bi-check-circle me-2"></i>
-                    {"Yes, I'm sure"}
-                  </button>
-                </div>
-              </div>
-            </div>
-          </div>
-        }
-    }
-}
@@ -1,6 +1,6 @@
-use crate::infra::{
-    common_component::{CommonComponent, CommonComponentParts},
-    modal::Modal,
+use crate::{
+    components::confirm_dialog::ConfirmDialog,
+    infra::common_component::{CommonComponent, CommonComponentParts},
 };
 use anyhow::{Error, Result};
 use graphql_client::GraphQLQuery;
@@ -17,8 +17,7 @@ pub struct DeleteUserAttributeQuery;
 
 pub struct DeleteUserAttribute {
     common: CommonComponentParts<Self>,
-    node_ref: NodeRef,
-    modal: Option<Modal>,
+    show_confirmation: bool,
 }
 
 #[derive(yew::Properties, Clone, PartialEq, Debug)]
@@ -43,10 +42,10 @@ impl CommonComponent<DeleteUserAttribute> for DeleteUserAttribute {
     ) -> Result<bool> {
         match msg {
             Msg::ClickedDeleteUserAttribute => {
-                self.modal.as_ref().expect("modal not initialized").show();
+                self.show_confirmation = true;
             }
             Msg::ConfirmDeleteUserAttribute => {
-                self.update(ctx, Msg::DismissModal);
+                self.show_confirmation = false;
                 self.common.call_graphql::<DeleteUserAttributeQuery, _>(
                     ctx,
                     delete_user_attribute_query::Variables {
@@ -57,7 +56,7 @@ impl CommonComponent<DeleteUserAttribute> for DeleteUserAttribute {
                 );
             }
             Msg::DismissModal => {
-                self.modal.as_ref().expect("modal not initialized").hide();
+                self.show_confirmation = false;
             }
             Msg::DeleteUserAttributeResponse(response) => {
                 response?;
@@ -81,18 +80,7 @@ impl Component for DeleteUserAttribute {
     fn create(_: &Context<Self>) -> Self {
         Self {
             common: CommonComponentParts::<Self>::create(),
-            node_ref: NodeRef::default(),
-            modal: None,
-        }
-    }
-
-    fn rendered(&mut self, _: &Context<Self>, first_render: bool) {
-        if first_render {
-            self.modal = Some(Modal::new(
-                self.node_ref
-                    .cast::<web_sys::Element>()
-                    .expect("Modal node is not an element"),
-            ));
+            show_confirmation: false,
         }
     }
 
@@ -107,6 +95,7 @@ impl Component for DeleteUserAttribute {
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let link = &ctx.link();
+        let attribute_name = &ctx.props().attribute_name;
         html! {
           <>
           <button
@@ -115,58 +104,22 @@ impl Component for DeleteUserAttribute {
             onclick={link.callback(|_| Msg::ClickedDeleteUserAttribute)}>
             <i class="bi-x-circle-fill" aria-label="Delete attribute" />
           </button>
-          {self.show_modal(ctx)}
-          </>
-        }
-    }
-}
-
-impl DeleteUserAttribute {
-    fn show_modal(&self, ctx: &Context<Self>) -> Html {
-        let link = &ctx.link();
-        html! {
-          <div
-            class="modal fade"
-            id={"deleteUserAttributeModal".to_string() + &ctx.props().attribute_name}
-            tabindex="-1"
-            aria-labelledby="deleteUserAttributeModalLabel"
-            aria-hidden="true"
-            ref={self.node_ref.clone()}>
-            <div class="modal-dialog">
-              <div class="modal-content">
-                <div class="modal-header">
-                  <h5 class="modal-title" id="deleteUserAttributeModalLabel">{"Delete user attribute?"}</h5>
-                  <button
-                    type="button"
-                    class="btn-close"
-                    aria-label="Close"
-                    onclick={link.callback(|_| Msg::DismissModal)} />
-                </div>
-                <div class="modal-body">
-                <span>
-                  {"Are you sure you want to delete user attribute "}
-                  <b>{&ctx.props().attribute_name}</b>{"?"}
-                </span>
-                </div>
-                <div class="modal-footer">
-                  <button
-                    type="button"
-                    class="btn btn-secondary"
-                    onclick={link.callback(|_| Msg::DismissModal)}>
-                      <i class="bi-x-circle me-2"></i>
-                      {"Cancel"}
-                  </button>
-                  <button
-                    type="button"
-                    onclick={link.callback(|_| Msg::ConfirmDeleteUserAttribute)}
-                    class="btn btn-danger">
-                    <i class="bi-check-circle me-2"></i>
-                    {"Yes, I'm sure"}
-                 </button>
-                </div>
-              </div>
+          <ConfirmDialog
+            id={format!("deleteUserAttributeModal{}", attribute_name)}
+            show={self.show_confirmation}
+            title="Delete user attribute?"
+            safety_text={AttrValue::from(attribute_name.clone())}
+            on_confirm={link.callback(|_| Msg::ConfirmDeleteUserAttribute)}
+            on_cancel={link.callback(|_| Msg::DismissModal)}>
+            <p>
+              {"Are you sure you want to delete user attribute "}
+              <b>{attribute_name}</b>{"?"}
+            </p>
+            <div class="alert alert-warning mb-0">
+              {"The values of this attribute will be deleted from all the users. This cannot be undone."}
             </div>
-          </div>
+          </ConfirmDialog>
+          </>
         }
     }
 }
@@ -0,0 +1,136 @@
+use crate::infra::clipboard;
+use chrono::{DateTime, SecondsFormat, Utc};
+use gloo_timers::callback::Timeout;
+use yew::prelude::*;
+
+/// How long the "Copied" feedback stays, in milliseconds.
+const COPIED_FEEDBACK_MS: u32 = 1500;
+
+/// A read-only value of the directory information card.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub label: &'static str,
+    pub id: &'static str,
+    /// None if there is nothing to show, e.g. no login yet.
+    pub value: Option<String>,
+}
+
+impl DirectoryEntry {
+    pub fn new(label: &'static str, id: &'static str, value: impl Into<String>) -> Self {
+        Self {
+            label,
+            id,
+            value: Some(value.into()),
+        }
+    }
+
+    /// The full timestamp, as LDAP clients see it, rather than just the date.
+    pub fn date(label: &'static str, id: &'static str, date: Option<DateTime<Utc>>) -> Self {
+        Self {
+            label,
+            id,
+            value: date.map(|d| d.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        }
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    pub entries: Vec<DirectoryEntry>,
+}
+
+/// A card with what LDAP clients see of an entry, such as its DN and UUID, to troubleshoot the
+/// client integrations. The values come from the server, each with a button to copy it.
+#[function_component(DirectoryInfo)]
+pub fn directory_info(props: &Props) -> Html {
+    html! {
+      <div class="card mb-3">
+        <div class="card-header fw-bold">{"Directory information"}</div>
+        <div class="card-body">
+          {for props.entries.iter().map(|entry| html! {
+            <div class="row mb-2 align-items-center">
+              <div class="col-sm-4 text-muted">{entry.label}</div>
+              <div class="col-sm-8 d-flex align-items-center">
+                {match &entry.value {
+                    None => html! {<span id={entry.id} class="text-muted">{"Never"}</span>},
+                    Some(value) => html! {
+                      <>
+                        <code id={entry.id} class="text-break me-2">{value}</code>
+                        <CopyButton value={value.clone()} label={entry.label} />
+                      </>
+                    },
+                }}
+              </div>
+            </div>
+          })}
+        </div>
+      </div>
+    }
+}
+
+struct CopyButton {
+    copied: bool,
+    _timeout: Option<Timeout>,
+}
+
+enum CopyMsg {
+    Copy,
+    Reset,
+}
+
+#[derive(Properties, PartialEq)]
+struct CopyButtonProps {
+    value: String,
+    label: &'static str,
+}
+
+impl Component for CopyButton {
+    type Message = CopyMsg;
+    type Properties = CopyButtonProps;
+
+    fn create(_: &Context<Self>) -> Self {
+        Self {
+            copied: false,
+            _timeout: None,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            CopyMsg::Copy => {
+                if let Err(e) = clipboard::write_text(&ctx.props().value) {
+                    gloo_console::error!(format!("Could not copy to the clipboard: {:?}", e));
+                    return false;
+                }
+                self.copied = true;
+                let link = ctx.link().clone();
+                self._timeout = Some(Timeout::new(COPIED_FEEDBACK_MS, move || {
+                    link.send_message(CopyMsg::Reset)
+                }));
+            }
+            CopyMsg::Reset => {
+                self.copied = false;
+                self._timeout = None;
+            }
+        }
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let (icon, title) = if self.copied {
+            ("bi-clipboard-check", "Copied".to_owned())
+        } else {
+            ("bi-clipboard", format!("Copy {}", ctx.props().label))
+        };
+        html! {
+          <button
+            type="button"
+            class="btn btn-sm btn-outline-secondary"
+            title={title.clone()}
+            aria-label={title}
+            onclick={ctx.link().callback(|_| CopyMsg::Copy)}>
+            <i class={icon}></i>
+          </button>
+        }
+    }
+}
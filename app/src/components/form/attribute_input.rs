@@ -0,0 +1,260 @@
+use crate::infra::schema::AttributeType;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use gloo_file::{callbacks::FileReader, File};
+use web_sys::{HtmlInputElement, HtmlTextAreaElement};
+use yew::prelude::*;
+
+/// The format of the `datetime-local` inputs.
+const DATETIME_LOCAL_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// The value of a `datetime-local` input, in the time zone of the browser, from an RFC 3339 date.
+fn to_datetime_local(value: &str) -> String {
+    DateTime::parse_from_rfc3339(value)
+        .map(|d| {
+            d.with_timezone(&Local)
+                .format(DATETIME_LOCAL_FORMAT)
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+/// The RFC 3339 date expected by the server, from the value of a `datetime-local` input. The
+/// seconds are optional in the input.
+fn from_datetime_local(value: &str) -> String {
+    NaiveDateTime::parse_from_str(value, DATETIME_LOCAL_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+        .ok()
+        // The earliest of the two times repeated when the clocks go back.
+        .and_then(|d| Local.from_local_datetime(&d).earliest())
+        .map(|d| d.to_rfc3339())
+        .unwrap_or_else(|| value.to_owned())
+}
+
+/// An input for the values of a custom attribute, matching its type: a date picker for the
+/// dates, a file input for the pictures, and a text area with a value per line for the lists.
+///
+/// The values are the ones of the GraphQL API: RFC 3339 for the dates, base64 for the pictures.
+pub struct AttributeInput {
+    _reader: Option<FileReader>,
+}
+
+pub enum Msg {
+    Changed(Vec<String>),
+    PhotoSelected(File),
+    PhotoRead(Result<Vec<u8>, gloo_file::FileReadError>),
+    RemovePhoto(usize),
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct Props {
+    pub name: String,
+    pub attribute_type: AttributeType,
+    pub is_list: bool,
+    pub values: Vec<String>,
+    /// Read-only otherwise.
+    pub editable: bool,
+    /// Reported by the server for this attribute.
+    #[prop_or_default]
+    pub error: Option<String>,
+    pub on_change: Callback<Vec<String>>,
+}
+
+impl Component for AttributeInput {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(_: &Context<Self>) -> Self {
+        Self { _reader: None }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        let props = ctx.props();
+        match msg {
+            Msg::Changed(values) => props.on_change.emit(values),
+            Msg::PhotoSelected(file) => {
+                let link = ctx.link().clone();
+                self._reader = Some(gloo_file::callbacks::read_as_bytes(&file, move |bytes| {
+                    link.send_message(Msg::PhotoRead(bytes))
+                }));
+            }
+            Msg::PhotoRead(bytes) => {
+                self._reader = None;
+                match bytes {
+                    Ok(bytes) => {
+                        let photo = base64::encode(bytes);
+                        let values = if props.is_list {
+                            let mut values = props.values.clone();
+                            values.push(photo);
+                            values
+                        } else {
+                            vec![photo]
+                        };
+                        props.on_change.emit(values);
+                    }
+                    Err(e) => gloo_console::error!(format!("Could not read the picture: {}", e)),
+                }
+            }
+            Msg::RemovePhoto(index) => {
+                let mut values = props.values.clone();
+                values.remove(index);
+                props.on_change.emit(values);
+            }
+        }
+        false
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let props = ctx.props();
+        let id = format!("attribute_{}", props.name);
+        let label = if props.attribute_type == AttributeType::DateTime {
+            format!("{} (UTC)", props.name)
+        } else {
+            props.name.clone()
+        };
+        html! {
+          <div class="row mb-3">
+            <label for={id.clone()} class="form-label col-md-4 col-form-label">
+              {label}{":"}
+            </label>
+            <div class="col-md-8">
+              {if props.editable {
+                  self.view_input(ctx, &id)
+              } else {
+                  self.view_read_only(ctx, &id)
+              }}
+              {match &props.error {
+                  Some(error) => html! {<div class="invalid-feedback d-block">{error}</div>},
+                  None => html! {},
+              }}
+            </div>
+          </div>
+        }
+    }
+}
+
+impl AttributeInput {
+    fn view_read_only(&self, ctx: &Context<Self>, id: &str) -> Html {
+        let props = ctx.props();
+        if props.attribute_type == AttributeType::Jpeg {
+            return html! {
+              <div id={id.to_owned()}>{for props.values.iter().map(|photo| Self::view_photo(photo))}</div>
+            };
+        }
+        let values = if props.attribute_type == AttributeType::DateTime {
+            props
+                .values
+                .iter()
+                .map(|v| to_datetime_local(v).replace('T', " "))
+                .collect::<Vec<_>>()
+        } else {
+            props.values.clone()
+        };
+        html! {
+          <span id={id.to_owned()} class="form-control-static">{values.join(", ")}</span>
+        }
+    }
+
+    fn view_photo(photo: &str) -> Html {
+        html! {
+          <img
+            src={format!("data:image/jpeg;base64, {}", photo)}
+            class="me-2 mb-2"
+            style="max-height:64px;max-width:64px;height:auto;width:auto;"
+            alt="Picture" />
+        }
+    }
+
+    fn view_input(&self, ctx: &Context<Self>, id: &str) -> Html {
+        let props = ctx.props();
+        let link = ctx.link();
+        let class = classes!(
+            "form-control",
+            props.error.is_some().then_some("is-invalid")
+        );
+        match (props.attribute_type, props.is_list) {
+            (AttributeType::Jpeg, is_list) => html! {
+              <>
+                <div>
+                  {for props.values.iter().enumerate().map(|(i, photo)| html! {
+                    <span class="d-inline-flex align-items-start">
+                      {Self::view_photo(photo)}
+                      <button
+                        type="button"
+                        class="btn-close me-2"
+                        aria-label="Remove the picture"
+                        onclick={link.callback(move |_| Msg::RemovePhoto(i))}>
+                      </button>
+                    </span>
+                  })}
+                </div>
+                <input
+                  type="file"
+                  class={class}
+                  id={id.to_owned()}
+                  accept="image/jpeg"
+                  title={if is_list { "Add a picture" } else { "Replace the picture" }}
+                  onchange={link.batch_callback(|e: Event| {
+                    let input: HtmlInputElement = e.target_unchecked_into();
+                    let file = input.files().and_then(|f| f.get(0)).map(File::from);
+                    input.set_value("");
+                    file.map(Msg::PhotoSelected)
+                  })} />
+              </>
+            },
+            (attribute_type, true) => {
+                let placeholder = match attribute_type {
+                    AttributeType::DateTime => {
+                        "One RFC 3339 date per line, e.g. 2024-01-31T12:00:00Z"
+                    }
+                    AttributeType::Integer => "One number per line",
+                    _ => "One value per line",
+                };
+                html! {
+                  <textarea
+                    class={class}
+                    id={id.to_owned()}
+                    rows="3"
+                    placeholder={placeholder}
+                    value={props.values.join("\n")}
+                    onchange={link.callback(|e: Event| {
+                      let input: HtmlTextAreaElement = e.target_unchecked_into();
+                      Msg::Changed(
+                        input
+                          .value()
+                          .lines()
+                          .map(str::trim)
+                          .filter(|l| !l.is_empty())
+                          .map(str::to_owned)
+                          .collect(),
+                      )
+                    })} />
+                }
+            }
+            (AttributeType::DateTime, false) => html! {
+              <input
+                type="datetime-local"
+                step="1"
+                class={class}
+                id={id.to_owned()}
+                value={props.values.first().map(|v| to_datetime_local(v)).unwrap_or_default()}
+                onchange={link.callback(|e: Event| {
+                  let input: HtmlInputElement = e.target_unchecked_into();
+                  let value = input.value();
+                  Msg::Changed(if value.is_empty() { Vec::new() } else { vec![from_datetime_local(&value)] })
+                })} />
+            },
+            (attribute_type, false) => html! {
+              <input
+                type={if attribute_type == AttributeType::Integer { "number" } else { "text" }}
+                class={class}
+                id={id.to_owned()}
+                value={props.values.first().cloned().unwrap_or_default()}
+                onchange={link.callback(|e: Event| {
+                  let input: HtmlInputElement = e.target_unchecked_into();
+                  let value = input.value();
+                  Msg::Changed(if value.is_empty() { Vec::new() } else { vec![value] })
+                })} />
+            },
+        }
+    }
+}
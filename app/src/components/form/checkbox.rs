@@ -17,14 +17,14 @@ pub fn checkbox<T: Model>(props: &Props<T>) -> Html {
     html! {
         <div class="form-group row mb-3">
             <label for={props.field_name.clone()}
-                class="form-label col-4 col-form-label">
+                class="form-label col-md-4 col-form-label">
                 {&props.label}
                 {if props.required {
                     html!{<span class="text-danger">{"*"}</span>}
                 } else {html!{}}}
                 {":"}
             </label>
-            <div class="col-8">
+            <div class="col-md-8">
                 <yew_form::CheckBox<T>
                 form={&props.form}
                 field_name={props.field_name.clone()}
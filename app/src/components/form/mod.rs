@@ -1,5 +1,7 @@
+pub mod attribute_input;
 pub mod checkbox;
 pub mod field;
+pub mod password_strength;
 pub mod select;
 pub mod static_value;
 pub mod submit;
@@ -0,0 +1,279 @@
+use anyhow::{bail, Result};
+use lldap_auth::password_policy::PasswordPolicy;
+use yew::{function_component, html, Properties};
+
+/// A few of the most common passwords, rejected whatever their length.
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456",
+    "12345678",
+    "123456789",
+    "1234567890",
+    "password",
+    "password1",
+    "password123",
+    "qwerty",
+    "qwerty123",
+    "qwertyuiop",
+    "azerty",
+    "abc123",
+    "111111",
+    "000000",
+    "iloveyou",
+    "letmein",
+    "welcome",
+    "admin",
+    "administrator",
+    "changeme",
+    "monkey",
+    "dragon",
+    "football",
+    "sunshine",
+    "princess",
+];
+
+/// A rough estimate of how hard a password is to guess, computed in the browser: the password
+/// is never sent anywhere to be rated.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Strength {
+    /// From 0 (very weak) to 4 (strong).
+    pub score: u8,
+    pub suggestions: Vec<&'static str>,
+}
+
+fn is_sequence(a: char, b: char, c: char) -> bool {
+    let (a, b, c) = (a as i64, b as i64, c as i64);
+    b - a == c - b && (b - a).abs() <= 1
+}
+
+/// Estimates the strength of `password`, penalizing the parts that are easy to guess: common
+/// passwords, repeated characters, sequences and `user_inputs` such as the user name.
+pub fn estimate_strength(password: &str, user_inputs: &[String]) -> Strength {
+    let mut suggestions = Vec::new();
+    if password.is_empty() {
+        return Strength {
+            score: 0,
+            suggestions,
+        };
+    }
+    let lowercase = password.to_lowercase();
+    if COMMON_PASSWORDS.contains(&lowercase.as_str()) {
+        suggestions.push("This is a very common password.");
+        return Strength {
+            score: 0,
+            suggestions,
+        };
+    }
+    let chars = password.chars().collect::<Vec<_>>();
+    let charset_size = [
+        (chars.iter().any(|c| c.is_lowercase()), 26),
+        (chars.iter().any(|c| c.is_uppercase()), 26),
+        (chars.iter().any(|c| c.is_ascii_digit()), 10),
+        (chars.iter().any(|c| !c.is_alphanumeric()), 33),
+    ]
+    .iter()
+    .filter(|(present, _)| *present)
+    .map(|(_, size)| size)
+    .sum::<u32>();
+    // Only the characters that are hard to guess count.
+    let mut guessable = 0;
+    for (i, c) in chars.iter().enumerate().skip(2) {
+        if (chars[i - 2] == chars[i - 1] && chars[i - 1] == *c)
+            || is_sequence(chars[i - 2], chars[i - 1], *c)
+        {
+            guessable += 1;
+        }
+    }
+    if guessable > 0 {
+        suggestions.push("Avoid repeated characters and sequences like \"abc\" or \"123\".");
+    }
+    let mut effective_length = chars.len() - guessable;
+    for input in user_inputs {
+        let input = input.to_lowercase();
+        if input.chars().count() >= 3 && lowercase.contains(&input) {
+            effective_length = effective_length.saturating_sub(input.chars().count());
+            suggestions.push("Avoid your name, user name or email.");
+            break;
+        }
+    }
+    let bits = effective_length as f64 * f64::from(charset_size.max(1)).log2();
+    let score = match bits as u32 {
+        0..=27 => 0,
+        28..=35 => 1,
+        36..=59 => 2,
+        60..=79 => 3,
+        _ => 4,
+    };
+    if score < 4 {
+        if chars.len() < 12 {
+            suggestions.push("Use a longer password, e.g. a few unrelated words.");
+        }
+        if charset_size < 62 {
+            suggestions.push("Mix uppercase and lowercase letters, digits and symbols.");
+        }
+    }
+    Strength { score, suggestions }
+}
+
+/// Fails with the requirements of the policy that the password doesn't meet.
+pub fn check_password_policy(policy: &PasswordPolicy, password: &str) -> Result<()> {
+    let violations = policy.violations(password);
+    if !violations.is_empty() {
+        bail!("The password {}", violations.join(", "));
+    }
+    Ok(())
+}
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    pub password: String,
+    pub policy: PasswordPolicy,
+    /// Personal information that shouldn't be part of the password, like the user name.
+    #[prop_or_default]
+    pub user_inputs: Vec<String>,
+}
+
+/// A meter of the strength of the password being typed, with the requirements of the policy
+/// that it doesn't meet yet. Meant to be placed under the password [`super::field::Field`].
+#[function_component(PasswordStrength)]
+pub fn password_strength(props: &Props) -> Html {
+    if props.password.is_empty() {
+        return html! {};
+    }
+    let strength = estimate_strength(&props.password, &props.user_inputs);
+    let (label, color) = match strength.score {
+        0 => ("Very weak", "bg-danger"),
+        1 => ("Weak", "bg-danger"),
+        2 => ("Fair", "bg-warning"),
+        3 => ("Good", "bg-info"),
+        _ => ("Strong", "bg-success"),
+    };
+    let percent = (u32::from(strength.score) + 1) * 20;
+    let violations = props.policy.violations(&props.password);
+    html! {
+      <div class="row mb-3">
+        <div class="col-md-8 offset-md-4">
+          <div
+            class="progress"
+            style="height: 0.5rem"
+            role="progressbar"
+            aria-label="Password strength"
+            aria-valuenow={strength.score.to_string()}
+            aria-valuemin="0"
+            aria-valuemax="4">
+            <div class={format!("progress-bar {}", color)} style={format!("width: {}%", percent)}></div>
+          </div>
+          <div class="form-text">{format!("Strength: {}", label)}</div>
+          {if violations.is_empty() { html! {} } else { html! {
+            <ul class="text-danger small mb-0">
+              {for violations.iter().map(|v| html! { <li>{format!("The password {}", v)}</li> })}
+            </ul>
+          }}}
+          {if strength.suggestions.is_empty() { html! {} } else { html! {
+            <ul class="form-text mb-0">
+              {for strength.suggestions.iter().map(|s| html! { <li>{*s}</li> })}
+            </ul>
+          }}}
+        </div>
+      </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_password_policy() {
+        let policy = PasswordPolicy {
+            min_length: 12,
+            require_digit: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            check_password_policy(&policy, "ééééééééééé")
+                .unwrap_err()
+                .to_string(),
+            "The password needs 12+ characters, needs a digit"
+        );
+        assert_eq!(
+            check_password_policy(&policy, "éééééééééééé")
+                .unwrap_err()
+                .to_string(),
+            "The password needs a digit"
+        );
+        assert!(check_password_policy(&policy, "ééééééééééé1").is_ok());
+    }
+
+    #[test]
+    fn test_estimate_strength_empty_and_common() {
+        assert_eq!(
+            estimate_strength("", &[]),
+            Strength {
+                score: 0,
+                suggestions: vec![]
+            }
+        );
+        for password in ["password", "PassWord", "qwerty123"] {
+            assert_eq!(
+                estimate_strength(password, &[]),
+                Strength {
+                    score: 0,
+                    suggestions: vec!["This is a very common password."]
+                },
+                "{}",
+                password
+            );
+        }
+    }
+
+    #[test]
+    fn test_estimate_strength_repeats_and_sequences() {
+        for password in ["aaaaaa", "abcdef", "fedcba"] {
+            assert_eq!(
+                estimate_strength(password, &[]),
+                Strength {
+                    score: 0,
+                    suggestions: vec![
+                        "Avoid repeated characters and sequences like \"abc\" or \"123\".",
+                        "Use a longer password, e.g. a few unrelated words.",
+                        "Mix uppercase and lowercase letters, digits and symbols.",
+                    ]
+                },
+                "{}",
+                password
+            );
+        }
+    }
+
+    #[test]
+    fn test_estimate_strength_user_inputs() {
+        assert_eq!(
+            estimate_strength("Alice#2024", &[]),
+            Strength {
+                score: 3,
+                suggestions: vec!["Use a longer password, e.g. a few unrelated words."]
+            }
+        );
+        assert_eq!(
+            estimate_strength("Alice#2024", &["alice".to_owned()]),
+            Strength {
+                score: 1,
+                suggestions: vec![
+                    "Avoid your name, user name or email.",
+                    "Use a longer password, e.g. a few unrelated words.",
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn test_estimate_strength_strong() {
+        assert_eq!(
+            estimate_strength("correct horse battery staple", &[]),
+            Strength {
+                score: 4,
+                suggestions: vec![]
+            }
+        );
+    }
+}
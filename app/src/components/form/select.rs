@@ -20,14 +20,14 @@ pub fn select<T: Model>(props: &Props<T>) -> Html {
     html! {
         <div class="row mb-3">
             <label for={props.field_name.clone()}
-                class="form-label col-4 col-form-label">
+                class="form-label col-md-4 col-form-label">
                 {&props.label}
                 {if props.required {
                     html!{<span class="text-danger">{"*"}</span>}
                 } else {html!{}}}
                 {":"}
             </label>
-            <div class="col-8">
+            <div class="col-md-8">
                 <yew_form::Select<T>
                     form={&props.form}
                     class="form-control"
@@ -12,11 +12,11 @@ pub fn static_value(props: &Props) -> Html {
     html! {
       <div class="row mb-3">
         <label for={props.id.clone()}
-          class="form-label col-4 col-form-label">
+          class="form-label col-md-4 col-form-label">
           {&props.label}
           {":"}
         </label>
-        <div class="col-8">
+        <div class="col-md-8">
           <span id={props.id.clone()} class="form-control-static">
             {for props.children.iter()}
           </span>
@@ -1,14 +1,22 @@
 use crate::{
     components::{
         add_group_member::{self, AddGroupMemberComponent},
+        directory_info::{DirectoryEntry, DirectoryInfo},
+        form::{field::Field, submit::Submit},
         remove_user_from_group::RemoveUserFromGroupComponent,
         router::{AppRoute, Link},
     },
-    infra::common_component::{CommonComponent, CommonComponentParts},
+    infra::{
+        common_component::{CommonComponent, CommonComponentParts},
+        cookies::get_cookie,
+    },
 };
 use anyhow::{bail, Error, Result};
 use graphql_client::GraphQLQuery;
+use validator_derive::Validate;
+use web_sys::HtmlInputElement;
 use yew::prelude::*;
+use yew_form_derive::Model;
 
 #[derive(GraphQLQuery)]
 #[graphql(
@@ -19,6 +27,24 @@ use yew::prelude::*;
 )]
 pub struct GetGroupDetails;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/update_group.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql"
+)]
+pub struct UpdateGroup;
+
+/// The editable details of the group.
+#[derive(Model, Validate, PartialEq, Eq, Clone, Default)]
+pub struct GroupModel {
+    #[validate(length(max = 1024, message = "The description is too long"))]
+    description: String,
+    #[validate(length(max = 4096, message = "The notes are too long"))]
+    notes: String,
+}
+
 pub type Group = get_group_details::GetGroupDetailsGroup;
 pub type User = get_group_details::GetGroupDetailsGroupUsers;
 pub type AddGroupMemberUser = add_group_member::User;
@@ -28,6 +54,13 @@ pub struct GroupDetails {
     /// The group info. If none, the error is in `error`. If `error` is None, then we haven't
     /// received the server response yet.
     group: Option<Group>,
+    form: yew_form::Form<GroupModel>,
+    /// Whether the last update succeeded, to confirm it.
+    just_updated: bool,
+    /// Filters the displayed members.
+    member_filter: String,
+    /// The logged in user, who can't remove themselves from `lldap_admin`.
+    current_user: Option<String>,
 }
 
 /// State machine describing the possible transitions of the component state.
@@ -38,6 +71,10 @@ pub enum Msg {
     OnError(Error),
     OnUserAddedToGroup(AddGroupMemberUser),
     OnUserRemovedFromGroup((String, i64)),
+    FilterMembers(String),
+    FormUpdate,
+    SubmitDetails,
+    GroupUpdated(Result<update_group::ResponseData>),
 }
 
 #[derive(yew::Properties, Clone, PartialEq, Eq)]
@@ -69,7 +106,35 @@ impl GroupDetails {
         }
     }
 
-    fn view_details(&self, g: &Group) -> Html {
+    fn submit_details(&mut self, ctx: &Context<Self>) -> Result<bool> {
+        if !self.form.validate() {
+            bail!("Check the form for errors");
+        }
+        let group = self.group.as_ref().unwrap();
+        let model = self.form.model();
+        // Only the changed fields are sent, the empty strings remove the values.
+        let changed = |new: String, old: &Option<String>| {
+            (new != old.clone().unwrap_or_default()).then_some(new)
+        };
+        let group = update_group::UpdateGroupInput {
+            id: group.id,
+            displayName: None,
+            description: changed(model.description, &group.description),
+            notes: changed(model.notes, &group.notes),
+            removeAttributes: None,
+            insertAttributes: None,
+        };
+        self.common.call_graphql::<UpdateGroup, _>(
+            ctx,
+            update_group::Variables { group },
+            Msg::GroupUpdated,
+            "Error trying to update the group",
+        );
+        Ok(true)
+    }
+
+    fn view_details(&self, ctx: &Context<Self>, g: &Group) -> Html {
+        let link = ctx.link();
         html! {
           <>
             <h3>{g.display_name.to_string()}</h3>
@@ -77,65 +142,119 @@ impl GroupDetails {
               <form class="form">
                 <div class="form-group row mb-3">
                   <label for="displayName"
-                    class="form-label col-4 col-form-label">
+                    class="form-label col-md-4 col-form-label">
                     {"Group: "}
                   </label>
-                  <div class="col-8">
+                  <div class="col-md-8">
                     <span id="groupId" class="form-constrol-static">{g.display_name.to_string()}</span>
                   </div>
                 </div>
-                <div class="form-group row mb-3">
-                  <label for="creationDate"
-                    class="form-label col-4 col-form-label">
-                    {"Creation date: "}
-                  </label>
-                  <div class="col-8">
-                    <span id="creationDate" class="form-constrol-static">{g.creation_date.naive_local().date()}</span>
-                  </div>
-                </div>
-                <div class="form-group row mb-3">
-                  <label for="uuid"
-                    class="form-label col-4 col-form-label">
-                    {"UUID: "}
-                  </label>
-                  <div class="col-8">
-                    <span id="uuid" class="form-constrol-static">{g.uuid.to_string()}</span>
-                  </div>
-                </div>
+                <Field<GroupModel>
+                  form={&self.form}
+                  label="Description"
+                  field_name="description"
+                  autocomplete="off"
+                  oninput={link.callback(|_| Msg::FormUpdate)} />
+                <Field<GroupModel>
+                  form={&self.form}
+                  label="Admin notes"
+                  field_name="notes"
+                  autocomplete="off"
+                  oninput={link.callback(|_| Msg::FormUpdate)} />
+                <Submit
+                  text="Save changes"
+                  disabled={self.common.is_task_running()}
+                  onclick={link.callback(|e: MouseEvent| {e.prevent_default(); Msg::SubmitDetails})} />
               </form>
+              <div hidden={!self.just_updated}>
+                <div class="alert alert-success mt-4">{"Group successfully updated!"}</div>
+              </div>
             </div>
+            <DirectoryInfo entries={vec![
+              DirectoryEntry::new("DN", "dn", g.dn.clone()),
+              DirectoryEntry::new("UUID", "uuid", g.uuid.clone()),
+              DirectoryEntry::date("Creation date", "creationDate", Some(g.creation_date)),
+            ]} />
           </>
         }
     }
 
+    /// Why the user can't be removed from the group, if so. The server refuses to remove the
+    /// current user from `lldap_admin`, and removing its last member would leave nobody able to
+    /// administer LLDAP.
+    fn removal_blocked_reason(&self, g: &Group, user_id: &str) -> Option<String> {
+        if g.display_name != "lldap_admin" {
+            return None;
+        }
+        if g.users.len() == 1 {
+            Some("The last member of lldap_admin can't be removed: nobody could administer LLDAP anymore.".to_owned())
+        } else if self.current_user.as_deref() == Some(user_id) {
+            Some("You can't remove your own admin rights.".to_owned())
+        } else {
+            None
+        }
+    }
+
     fn view_user_list(&self, ctx: &Context<Self>, g: &Group) -> Html {
         let link = ctx.link();
         let make_user_row = |user: &User| {
             let user_id = user.id.clone();
             let display_name = user.display_name.clone();
             html! {
-              <tr>
-                <td>
+              <tr key={user_id.clone()}>
+                <td data-label="User Id">
                   <Link to={AppRoute::UserDetails{user_id: user_id.clone()}}>
                     {user_id.clone()}
                   </Link>
                 </td>
-                <td>{display_name}</td>
+                <td data-label="Display name">{display_name}</td>
                 <td>
                   <RemoveUserFromGroupComponent
-                    username={user_id}
+                    username={user_id.clone()}
                     group_id={g.id}
+                    group_name={g.display_name.clone()}
+                    disabled_reason={self.removal_blocked_reason(g, &user_id)}
                     on_user_removed_from_group={link.callback(Msg::OnUserRemovedFromGroup)}
                     on_error={link.callback(Msg::OnError)}/>
                 </td>
               </tr>
             }
         };
+        let filter = self.member_filter.trim().to_lowercase();
+        let members = g
+            .users
+            .iter()
+            .filter(|u| {
+                filter.is_empty()
+                    || u.id.to_lowercase().contains(&filter)
+                    || u.display_name.to_lowercase().contains(&filter)
+            })
+            .collect::<Vec<_>>();
         html! {
           <>
-            <h5 class="fw-bold">{"Members"}</h5>
+            <h5 class="fw-bold">
+              {"Members "}
+              <span class="badge rounded-pill bg-secondary">{g.users.len()}</span>
+            </h5>
+            {if g.users.is_empty() {
+              html! {}
+            } else {
+              html! {
+                <input
+                  type="search"
+                  class="form-control my-2"
+                  id="memberFilter"
+                  placeholder="Filter the members"
+                  aria-label="Filter the members"
+                  value={self.member_filter.clone()}
+                  oninput={link.callback(|e: InputEvent| {
+                    let input: HtmlInputElement = e.target_unchecked_into();
+                    Msg::FilterMembers(input.value())
+                  })} />
+              }
+            }}
             <div class="table-responsive">
-              <table class="table table-hover">
+              <table class="table table-hover table-cards">
                 <thead>
                   <tr key="headerRow">
                     <th>{"User Id"}</th>
@@ -151,8 +270,15 @@ impl GroupDetails {
                         <td/>
                       </tr>
                     }
+                  } else if members.is_empty() {
+                    html! {
+                      <tr key="EmptyRow">
+                        <td>{"No member matches this filter."}</td>
+                        <td/>
+                      </tr>
+                    }
                   } else {
-                    html! {<>{g.users.iter().map(make_user_row).collect::<Vec<_>>()}</>}
+                    html! {<>{members.into_iter().map(make_user_row).collect::<Vec<_>>()}</>}
                   }}
                 </tbody>
               </table>
@@ -182,10 +308,20 @@ impl GroupDetails {
 }
 
 impl CommonComponent<GroupDetails> for GroupDetails {
-    fn handle_msg(&mut self, _: &Context<Self>, msg: <Self as Component>::Message) -> Result<bool> {
+    fn handle_msg(
+        &mut self,
+        ctx: &Context<Self>,
+        msg: <Self as Component>::Message,
+    ) -> Result<bool> {
         match msg {
             Msg::GroupDetailsResponse(response) => match response {
-                Ok(group) => self.group = Some(group.group),
+                Ok(group) => {
+                    self.form = yew_form::Form::new(GroupModel {
+                        description: group.group.description.clone().unwrap_or_default(),
+                        notes: group.group.notes.clone().unwrap_or_default(),
+                    });
+                    self.group = Some(group.group);
+                }
                 Err(e) => {
                     self.group = None;
                     bail!("Error getting user details: {}", e);
@@ -205,6 +341,17 @@ impl CommonComponent<GroupDetails> for GroupDetails {
                     .users
                     .retain(|u| u.id != user_id);
             }
+            Msg::FilterMembers(filter) => self.member_filter = filter,
+            Msg::FormUpdate => {}
+            Msg::SubmitDetails => return self.submit_details(ctx),
+            Msg::GroupUpdated(response) => {
+                response?;
+                let model = self.form.model();
+                let group = self.group.as_mut().unwrap();
+                group.description = Some(model.description).filter(|d| !d.is_empty());
+                group.notes = Some(model.notes).filter(|n| !n.is_empty());
+                self.just_updated = true;
+            }
         }
         Ok(true)
     }
@@ -222,12 +369,17 @@ impl Component for GroupDetails {
         let mut table = Self {
             common: CommonComponentParts::<Self>::create(),
             group: None,
+            form: yew_form::Form::new(GroupModel::default()),
+            just_updated: false,
+            member_filter: String::new(),
+            current_user: get_cookie("user_id").ok().flatten(),
         };
         table.get_group_details(ctx);
         table
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        self.just_updated = false;
         CommonComponentParts::<Self>::update(self, ctx, msg)
     }
 
@@ -238,7 +390,7 @@ impl Component for GroupDetails {
             (Some(u), error) => {
                 html! {
                     <div>
-                      {self.view_details(u)}
+                      {self.view_details(ctx, u)}
                       {self.view_user_list(ctx, u)}
                       {self.view_add_user_button(ctx, u)}
                       {self.view_messages(error)}
@@ -6,7 +6,9 @@ use crate::{
     infra::common_component::{CommonComponent, CommonComponentParts},
 };
 use anyhow::{Error, Result};
+use gloo_timers::callback::Timeout;
 use graphql_client::GraphQLQuery;
+use web_sys::HtmlInputElement;
 use yew::prelude::*;
 
 #[derive(GraphQLQuery)]
@@ -22,19 +24,45 @@ use get_group_list::ResponseData;
 
 pub type Group = get_group_list::GetGroupListGroups;
 
+/// How long to wait after the last key stroke before searching.
+const SEARCH_DEBOUNCE_MS: u32 = 300;
+
 pub struct GroupTable {
     common: CommonComponentParts<Self>,
     groups: Option<Vec<Group>>,
+    /// The content of the search box, which is searched once the user stops typing.
+    search_input: String,
+    search_timeout: Option<Timeout>,
 }
 
 pub enum Msg {
     ListGroupsResponse(Result<ResponseData>),
     OnGroupDeleted(i64),
     OnError(Error),
+    SearchInput(String),
+    Search,
+}
+
+impl GroupTable {
+    fn get_groups(&mut self, ctx: &Context<Self>) {
+        let search = self.search_input.trim();
+        self.common.call_graphql::<GetGroupList, _>(
+            ctx,
+            get_group_list::Variables {
+                search: (!search.is_empty()).then(|| search.to_owned()),
+            },
+            Msg::ListGroupsResponse,
+            "Error trying to fetch groups",
+        );
+    }
 }
 
 impl CommonComponent<GroupTable> for GroupTable {
-    fn handle_msg(&mut self, _: &Context<Self>, msg: <Self as Component>::Message) -> Result<bool> {
+    fn handle_msg(
+        &mut self,
+        ctx: &Context<Self>,
+        msg: <Self as Component>::Message,
+    ) -> Result<bool> {
         match msg {
             Msg::ListGroupsResponse(groups) => {
                 self.groups = Some(groups?.groups.into_iter().collect());
@@ -46,6 +74,19 @@ impl CommonComponent<GroupTable> for GroupTable {
                 self.groups.as_mut().unwrap().retain(|u| u.id != group_id);
                 Ok(true)
             }
+            Msg::SearchInput(search) => {
+                self.search_input = search;
+                let link = ctx.link().clone();
+                self.search_timeout = Some(Timeout::new(SEARCH_DEBOUNCE_MS, move || {
+                    link.send_message(Msg::Search)
+                }));
+                Ok(true)
+            }
+            Msg::Search => {
+                self.search_timeout = None;
+                self.get_groups(ctx);
+                Ok(false)
+            }
         }
     }
 
@@ -62,13 +103,10 @@ impl Component for GroupTable {
         let mut table = GroupTable {
             common: CommonComponentParts::<Self>::create(),
             groups: None,
+            search_input: String::new(),
+            search_timeout: None,
         };
-        table.common.call_graphql::<GetGroupList, _>(
-            ctx,
-            get_group_list::Variables {},
-            Msg::ListGroupsResponse,
-            "Error trying to fetch groups",
-        );
+        table.get_groups(ctx);
         table
     }
 
@@ -79,6 +117,7 @@ impl Component for GroupTable {
     fn view(&self, ctx: &Context<Self>) -> Html {
         html! {
             <div>
+              {self.view_search(ctx)}
               {self.view_groups(ctx)}
               {self.view_errors()}
             </div>
@@ -87,14 +126,36 @@ impl Component for GroupTable {
 }
 
 impl GroupTable {
+    fn view_search(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        html! {
+          <div class="input-group mb-3">
+            <span class="input-group-text"><i class="bi-search"></i></span>
+            <input
+              type="search"
+              class="form-control"
+              id="groupSearch"
+              placeholder="Search by name, description or notes"
+              aria-label="Search groups"
+              value={self.search_input.clone()}
+              oninput={link.callback(|e: InputEvent| {
+                let input: HtmlInputElement = e.target_unchecked_into();
+                Msg::SearchInput(input.value())
+              })} />
+          </div>
+        }
+    }
+
     fn view_groups(&self, ctx: &Context<Self>) -> Html {
         let make_table = |groups: &Vec<Group>| {
             html! {
                 <div class="table-responsive">
-                  <table class="table table-hover">
+                  <table class="table table-hover table-cards">
                     <thead>
                       <tr>
                         <th>{"Group name"}</th>
+                        <th>{"Description"}</th>
+                        <th>{"Members"}</th>
                         <th>{"Creation date"}</th>
                         <th>{"Delete"}</th>
                       </tr>
@@ -116,13 +177,15 @@ impl GroupTable {
         let link = ctx.link();
         html! {
           <tr key={group.id}>
-              <td>
+              <td data-label="Group name">
                 <Link to={AppRoute::GroupDetails{group_id: group.id}}>
                   {&group.display_name}
                 </Link>
               </td>
-              <td>
-                {&group.creation_date.naive_local().date()}
+              <td data-label="Description">{group.description.clone().unwrap_or_default()}</td>
+              <td data-label="Members">{group.users.len()}</td>
+              <td class="card-secondary" data-label="Creation date">
+                {&group.creation_date.with_timezone(&chrono::Local).naive_local().date()}
               </td>
               <td>
                 <DeleteGroup
@@ -0,0 +1,740 @@
+use crate::infra::common_component::{CommonComponent, CommonComponentParts};
+use anyhow::{anyhow, Error, Result};
+use gloo_file::{callbacks::FileReader, Blob, ObjectUrl};
+use graphql_client::GraphQLQuery;
+use std::collections::HashSet;
+use web_sys::HtmlSelectElement;
+use yew::prelude::*;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/create_users.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql"
+)]
+pub struct CreateUsers;
+
+use create_users::{CreateUserStatus, ExistingUserPolicy};
+
+/// Users per request, below the limit of the server.
+const CHUNK_SIZE: usize = 50;
+/// Rows shown in the preview.
+const PREVIEW_ROWS: usize = 5;
+
+/// Splits a CSV file into rows of fields. Handles quoted fields, with `""` for a quote and
+/// line breaks inside the quotes. The separator is a comma, or a semicolon if the header has
+/// more of them, as exported by spreadsheets in some locales. Empty lines are dropped.
+pub fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let text = text.trim_start_matches('\u{feff}');
+    let header = text.lines().next().unwrap_or_default();
+    let separator = if header.matches(';').count() > header.matches(',').count() {
+        ';'
+    } else {
+        ','
+    };
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            }
+            '"' if field.is_empty() => in_quotes = true,
+            '\r' if !in_quotes => {}
+            '\n' if !in_quotes => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c if c == separator && !in_quotes => row.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|r| r.iter().any(|f| !f.trim().is_empty()));
+    rows
+}
+
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UserField {
+    Id,
+    Email,
+    DisplayName,
+    FirstName,
+    LastName,
+    Hosts,
+    Phone,
+    Mobile,
+    PostalAddress,
+    Locality,
+    StateOrProvince,
+}
+
+const USER_FIELDS: [UserField; 11] = [
+    UserField::Id,
+    UserField::Email,
+    UserField::DisplayName,
+    UserField::FirstName,
+    UserField::LastName,
+    UserField::Hosts,
+    UserField::Phone,
+    UserField::Mobile,
+    UserField::PostalAddress,
+    UserField::Locality,
+    UserField::StateOrProvince,
+];
+
+impl UserField {
+    fn label(self) -> &'static str {
+        match self {
+            UserField::Id => "User ID",
+            UserField::Email => "Email",
+            UserField::DisplayName => "Display name",
+            UserField::FirstName => "First name",
+            UserField::LastName => "Last name",
+            UserField::Hosts => "Hosts",
+            UserField::Phone => "Phone",
+            UserField::Mobile => "Mobile",
+            UserField::PostalAddress => "Postal address",
+            UserField::Locality => "City",
+            UserField::StateOrProvince => "State or province",
+        }
+    }
+
+    fn is_required(self) -> bool {
+        matches!(self, UserField::Id | UserField::Email)
+    }
+
+    /// Whether a column with this header is likely to hold the field.
+    fn matches_header(self, header: &str) -> bool {
+        let header = header
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_ascii_lowercase();
+        let names: &[&str] = match self {
+            UserField::Id => &["id", "userid", "user", "username", "uid", "login"],
+            UserField::Email => &["email", "mail", "emailaddress"],
+            UserField::DisplayName => &["displayname", "name", "fullname", "cn"],
+            UserField::FirstName => &["firstname", "givenname", "forename"],
+            UserField::LastName => &["lastname", "surname", "familyname", "sn"],
+            UserField::Hosts => &["host", "hosts"],
+            UserField::Phone => &["phone", "telephone", "telephonenumber", "phonenumber"],
+            UserField::Mobile => &["mobile", "mobilephone", "mobiletelephonenumber"],
+            UserField::PostalAddress => &["postaladdress", "address", "street"],
+            UserField::Locality => &["locality", "city", "town", "l"],
+            UserField::StateOrProvince => &["stateorprovince", "state", "province", "region", "st"],
+        };
+        names.contains(&header.as_str())
+    }
+}
+
+fn is_valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+/// A data row of the file, once mapped to the user fields.
+#[derive(Debug)]
+struct ImportRow {
+    /// 1-based, counting the header.
+    line: usize,
+    id: String,
+    email: String,
+    display_name: String,
+    first_name: String,
+    last_name: String,
+    /// Separated by spaces, or by commas in a quoted field.
+    hosts: String,
+    phone: String,
+    mobile: String,
+    /// The lines of a quoted field.
+    postal_address: String,
+    locality: String,
+    state_or_province: String,
+    /// Why the row can't be imported, found before sending anything.
+    error: Option<String>,
+}
+
+impl ImportRow {
+    fn to_input(&self) -> create_users::CreateUserInput {
+        let to_option = |s: &String| if s.is_empty() { None } else { Some(s.clone()) };
+        create_users::CreateUserInput {
+            id: self.id.clone(),
+            email: self.email.clone(),
+            displayName: to_option(&self.display_name),
+            firstName: to_option(&self.first_name),
+            lastName: to_option(&self.last_name),
+            avatar: None,
+            preferredLanguage: None,
+            hosts: Some(
+                self.hosts
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|host| !host.is_empty())
+                    .map(str::to_owned)
+                    .collect(),
+            )
+            .filter(|hosts: &Vec<String>| !hosts.is_empty()),
+            phone: to_option(&self.phone),
+            mobile: to_option(&self.mobile),
+            postalAddress: to_option(&self.postal_address),
+            locality: to_option(&self.locality),
+            stateOrProvince: to_option(&self.state_or_province),
+            expiresAt: None,
+            attributes: None,
+            groups: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    Created,
+    Updated,
+    Skipped,
+    Failed,
+    /// Rejected by the checks of the wizard, never sent.
+    Invalid,
+    /// Not sent because the import stopped on an error.
+    NotImported,
+}
+
+impl Outcome {
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Created => "created",
+            Outcome::Updated => "updated",
+            Outcome::Skipped => "skipped",
+            Outcome::Failed => "failed",
+            Outcome::Invalid => "invalid",
+            Outcome::NotImported => "not imported",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RowOutcome {
+    line: usize,
+    id: String,
+    outcome: Outcome,
+    error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Step {
+    Reading,
+    Mapping,
+    Importing,
+    Done,
+}
+
+/// A wizard importing users from a CSV file: the columns are mapped to the user fields, the
+/// first rows are previewed and checked, then the users are sent in chunks to `createUsers`.
+/// The file is parsed in the browser. The summary counts what the server reported for each
+/// user, and the rows that weren't imported can be downloaded as a CSV report.
+pub struct ImportUsers {
+    common: CommonComponentParts<Self>,
+    step: Step,
+    _reader: Option<FileReader>,
+    headers: Vec<String>,
+    records: Vec<Vec<String>>,
+    /// The column of each of [`USER_FIELDS`].
+    mapping: Vec<Option<usize>>,
+    update_existing: bool,
+    /// The valid rows, sent in order.
+    to_send: Vec<ImportRow>,
+    sent: usize,
+    outcomes: Vec<RowOutcome>,
+    report: Option<ObjectUrl>,
+}
+
+pub enum Msg {
+    FileRead(Result<String>),
+    SetMapping(usize, Option<usize>),
+    SetUpdateExisting(bool),
+    Start,
+    ChunkResponse(Result<create_users::ResponseData>),
+    Close,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    pub file: web_sys::File,
+    pub on_close: Callback<()>,
+}
+
+impl CommonComponent<ImportUsers> for ImportUsers {
+    fn handle_msg(
+        &mut self,
+        ctx: &Context<Self>,
+        msg: <Self as Component>::Message,
+    ) -> Result<bool> {
+        match msg {
+            Msg::FileRead(text) => {
+                self._reader = None;
+                let mut rows = parse_csv(&text?).into_iter();
+                self.headers = rows
+                    .next()
+                    .ok_or_else(|| anyhow!("The file is empty"))?
+                    .into_iter()
+                    .map(|h| h.trim().to_owned())
+                    .collect();
+                self.records = rows.collect();
+                if self.records.is_empty() {
+                    return Err(anyhow!("The file has no users, only a header"));
+                }
+                self.mapping = USER_FIELDS
+                    .iter()
+                    .map(|field| self.headers.iter().position(|h| field.matches_header(h)))
+                    .collect();
+                self.step = Step::Mapping;
+                Ok(true)
+            }
+            Msg::SetMapping(field, column) => {
+                self.mapping[field] = column;
+                Ok(true)
+            }
+            Msg::SetUpdateExisting(update) => {
+                self.update_existing = update;
+                Ok(true)
+            }
+            Msg::Start => {
+                let (valid, invalid) = self
+                    .rows()
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|r| r.error.is_none());
+                self.outcomes = invalid
+                    .into_iter()
+                    .map(|r| RowOutcome {
+                        line: r.line,
+                        id: r.id,
+                        outcome: Outcome::Invalid,
+                        error: r.error,
+                    })
+                    .collect();
+                self.to_send = valid;
+                self.sent = 0;
+                self.step = Step::Importing;
+                self.send_next_chunk(ctx);
+                Ok(true)
+            }
+            Msg::ChunkResponse(response) => {
+                let chunk_end = std::cmp::min(self.sent + CHUNK_SIZE, self.to_send.len());
+                let chunk = &self.to_send[self.sent..chunk_end];
+                let results = match response {
+                    Ok(r) => r.create_users,
+                    Err(e) => {
+                        self.stop(&e);
+                        return Err(e);
+                    }
+                };
+                if results.len() != chunk.len() {
+                    let e = anyhow!(
+                        "The server returned {} results for {} users",
+                        results.len(),
+                        chunk.len()
+                    );
+                    self.stop(&e);
+                    return Err(e);
+                }
+                for (row, result) in chunk.iter().zip(results) {
+                    let outcome = match result.status {
+                        CreateUserStatus::CREATED => Outcome::Created,
+                        CreateUserStatus::UPDATED => Outcome::Updated,
+                        CreateUserStatus::SKIPPED => Outcome::Skipped,
+                        _ => Outcome::Failed,
+                    };
+                    self.outcomes.push(RowOutcome {
+                        line: row.line,
+                        id: result.id,
+                        outcome,
+                        error: result.error,
+                    });
+                }
+                self.sent = chunk_end;
+                if self.sent < self.to_send.len() {
+                    self.send_next_chunk(ctx);
+                } else {
+                    self.finish();
+                }
+                Ok(true)
+            }
+            Msg::Close => {
+                ctx.props().on_close.emit(());
+                Ok(false)
+            }
+        }
+    }
+
+    fn mut_common(&mut self) -> &mut CommonComponentParts<Self> {
+        &mut self.common
+    }
+}
+
+impl ImportUsers {
+    fn column(&self, field: UserField) -> Option<usize> {
+        USER_FIELDS
+            .iter()
+            .position(|f| *f == field)
+            .and_then(|i| self.mapping[i])
+    }
+
+    /// The records mapped to the user fields, and checked.
+    fn rows(&self) -> Vec<ImportRow> {
+        let get = |record: &Vec<String>, field| {
+            self.column(field)
+                .and_then(|c| record.get(c))
+                .map(|v| v.trim().to_owned())
+                .unwrap_or_default()
+        };
+        let mut seen_ids = HashSet::new();
+        self.records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| {
+                let mut row = ImportRow {
+                    line: i + 2,
+                    id: get(record, UserField::Id),
+                    email: get(record, UserField::Email),
+                    display_name: get(record, UserField::DisplayName),
+                    first_name: get(record, UserField::FirstName),
+                    last_name: get(record, UserField::LastName),
+                    hosts: get(record, UserField::Hosts),
+                    phone: get(record, UserField::Phone),
+                    mobile: get(record, UserField::Mobile),
+                    postal_address: get(record, UserField::PostalAddress),
+                    locality: get(record, UserField::Locality),
+                    state_or_province: get(record, UserField::StateOrProvince),
+                    error: None,
+                };
+                row.error = if row.id.is_empty() {
+                    Some("Missing user ID".to_owned())
+                } else if !seen_ids.insert(row.id.to_lowercase()) {
+                    Some("Duplicate user ID in the file".to_owned())
+                } else if !is_valid_email(&row.email) {
+                    Some(format!("Invalid email \"{}\"", row.email))
+                } else {
+                    None
+                };
+                row
+            })
+            .collect()
+    }
+
+    fn send_next_chunk(&mut self, ctx: &Context<Self>) {
+        if self.to_send.is_empty() {
+            self.finish();
+            return;
+        }
+        let chunk_end = std::cmp::min(self.sent + CHUNK_SIZE, self.to_send.len());
+        self.common.call_graphql::<CreateUsers, _>(
+            ctx,
+            create_users::Variables {
+                users: self.to_send[self.sent..chunk_end]
+                    .iter()
+                    .map(ImportRow::to_input)
+                    .collect(),
+                onExisting: Some(if self.update_existing {
+                    ExistingUserPolicy::UPDATE
+                } else {
+                    ExistingUserPolicy::SKIP
+                }),
+            },
+            Msg::ChunkResponse,
+            "Error trying to import users",
+        );
+    }
+
+    /// Marks the rows not sent yet as not imported, after a request failed.
+    fn stop(&mut self, error: &Error) {
+        self.outcomes
+            .extend(self.to_send[self.sent..].iter().map(|row| RowOutcome {
+                line: row.line,
+                id: row.id.clone(),
+                outcome: Outcome::NotImported,
+                error: Some(error.to_string()),
+            }));
+        self.sent = self.to_send.len();
+        self.finish();
+    }
+
+    fn finish(&mut self) {
+        self.step = Step::Done;
+        self.outcomes.sort_by_key(|o| o.line);
+        let mut report = "line,id,status,error\n".to_owned();
+        for o in self.outcomes.iter().filter(|o| {
+            matches!(
+                o.outcome,
+                Outcome::Failed | Outcome::Invalid | Outcome::NotImported
+            )
+        }) {
+            report.push_str(&format!(
+                "{},{},{},{}\n",
+                o.line,
+                escape_csv(&o.id),
+                o.outcome.label(),
+                escape_csv(o.error.as_deref().unwrap_or_default())
+            ));
+        }
+        self.report = Some(ObjectUrl::from(Blob::new_with_options(
+            report.as_str(),
+            Some("text/csv"),
+        )));
+    }
+
+    fn count(&self, outcome: Outcome) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|o| o.outcome == outcome)
+            .count()
+    }
+}
+
+impl Component for ImportUsers {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let link = ctx.link().clone();
+        let reader = gloo_file::callbacks::read_as_text(
+            &gloo_file::File::from(ctx.props().file.clone()),
+            move |text| {
+                link.send_message(Msg::FileRead(
+                    text.map_err(|e| anyhow!("Could not read the file: {}", e)),
+                ))
+            },
+        );
+        ImportUsers {
+            common: CommonComponentParts::<Self>::create(),
+            step: Step::Reading,
+            _reader: Some(reader),
+            headers: Vec::new(),
+            records: Vec::new(),
+            mapping: Vec::new(),
+            update_existing: false,
+            to_send: Vec::new(),
+            sent: 0,
+            outcomes: Vec::new(),
+            report: None,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        CommonComponentParts::<Self>::update(self, ctx, msg)
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        html! {
+          <div class="card mb-3">
+            <div class="card-header d-flex align-items-center">
+              <span class="me-auto">
+                <i class="bi-file-earmark-spreadsheet me-2"></i>
+                {format!("Import users from {}", ctx.props().file.name())}
+              </span>
+              <button
+                type="button"
+                class="btn-close"
+                aria-label="Close"
+                disabled={self.step == Step::Importing}
+                onclick={link.callback(|_| Msg::Close)}>
+              </button>
+            </div>
+            <div class="card-body">
+              {match self.step {
+                  Step::Reading => html! {
+                    <div class="text-muted">{"Reading the file..."}</div>
+                  },
+                  Step::Mapping => self.view_mapping(ctx),
+                  Step::Importing => self.view_progress(),
+                  Step::Done => self.view_summary(ctx),
+              }}
+              {self.view_errors()}
+            </div>
+          </div>
+        }
+    }
+}
+
+impl ImportUsers {
+    fn view_mapping(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        let rows = self.rows();
+        let invalid = rows.iter().filter(|r| r.error.is_some()).count();
+        let missing_required = USER_FIELDS
+            .iter()
+            .any(|f| f.is_required() && self.column(*f).is_none());
+        html! {
+          <>
+            <h6>{"Columns"}</h6>
+            {for USER_FIELDS.iter().enumerate().map(|(i, field)| html! {
+              <div class="row mb-2 align-items-center">
+                <label class="col-md-4 col-form-label" for={format!("importColumn{}", i)}>
+                  {field.label()}
+                  {if field.is_required() { html! {<span class="text-danger">{"*"}</span>} } else { html! {} }}
+                </label>
+                <div class="col-md-8">
+                  <select
+                    class="form-select"
+                    id={format!("importColumn{}", i)}
+                    onchange={link.callback(move |e: Event| {
+                      let select: HtmlSelectElement = e.target_unchecked_into();
+                      Msg::SetMapping(i, select.value().parse().ok())
+                    })}>
+                    <option value="" selected={self.mapping[i].is_none()}>{"(none)"}</option>
+                    {for self.headers.iter().enumerate().map(|(c, header)| html! {
+                      <option value={c.to_string()} selected={self.mapping[i] == Some(c)}>
+                        {header}
+                      </option>
+                    })}
+                  </select>
+                </div>
+              </div>
+            })}
+            <div class="form-check my-3">
+              <input
+                class="form-check-input"
+                type="checkbox"
+                id="importUpdateExisting"
+                checked={self.update_existing}
+                onchange={link.callback(|e: Event| {
+                  let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+                  Msg::SetUpdateExisting(input.checked())
+                })} />
+              <label class="form-check-label" for="importUpdateExisting">
+                {"Update the users that already exist, instead of skipping them"}
+              </label>
+            </div>
+            <h6>{format!("Preview of the first {} of {} users", std::cmp::min(PREVIEW_ROWS, rows.len()), rows.len())}</h6>
+            <div class="table-responsive">
+              <table class="table table-sm">
+                <thead>
+                  <tr>
+                    <th>{"Line"}</th>
+                    {for USER_FIELDS.iter().map(|f| html! {<th>{f.label()}</th>})}
+                    <th></th>
+                  </tr>
+                </thead>
+                <tbody>
+                  {for rows.iter().take(PREVIEW_ROWS).map(|row| html! {
+                    <tr class={classes!(row.error.is_some().then_some("table-danger"))}>
+                      <td>{row.line}</td>
+                      <td>{&row.id}</td>
+                      <td>{&row.email}</td>
+                      <td>{&row.display_name}</td>
+                      <td>{&row.first_name}</td>
+                      <td>{&row.last_name}</td>
+                      <td>{&row.hosts}</td>
+                      <td>{&row.phone}</td>
+                      <td>{&row.mobile}</td>
+                      <td style="white-space: pre-line">{&row.postal_address}</td>
+                      <td>{&row.locality}</td>
+                      <td>{&row.state_or_province}</td>
+                      <td class="text-danger">{row.error.as_deref().unwrap_or_default()}</td>
+                    </tr>
+                  })}
+                </tbody>
+              </table>
+            </div>
+            {if invalid > 0 { html! {
+              <div class="alert alert-warning">
+                {format!("{} of the {} rows are invalid and will not be imported.", invalid, rows.len())}
+              </div>
+            }} else { html! {} }}
+            <button
+              type="button"
+              class="btn btn-primary"
+              disabled={missing_required || invalid == rows.len()}
+              onclick={link.callback(|_| Msg::Start)}>
+              <i class="bi-upload me-2"></i>
+              {format!("Import {} users", rows.len() - invalid)}
+            </button>
+          </>
+        }
+    }
+
+    fn view_progress(&self) -> Html {
+        let total = self.to_send.len().max(1);
+        let percent = self.sent * 100 / total;
+        html! {
+          <>
+            <div
+              class="progress mb-2"
+              role="progressbar"
+              aria-label="Import progress"
+              aria-valuenow={percent.to_string()}
+              aria-valuemin="0"
+              aria-valuemax="100">
+              <div class="progress-bar progress-bar-striped progress-bar-animated" style={format!("width: {}%", percent)}></div>
+            </div>
+            <div class="text-muted">{format!("Imported {} of {} users...", self.sent, self.to_send.len())}</div>
+          </>
+        }
+    }
+
+    fn view_summary(&self, ctx: &Context<Self>) -> Html {
+        let not_imported = self.count(Outcome::Failed)
+            + self.count(Outcome::Invalid)
+            + self.count(Outcome::NotImported);
+        html! {
+          <>
+            <ul class="list-unstyled">
+              <li>{format!("Created: {}", self.count(Outcome::Created))}</li>
+              <li>{format!("Updated: {}", self.count(Outcome::Updated))}</li>
+              <li>{format!("Skipped, already existing: {}", self.count(Outcome::Skipped))}</li>
+              <li>{format!("Failed: {}", self.count(Outcome::Failed))}</li>
+              <li>{format!("Invalid, not sent: {}", self.count(Outcome::Invalid))}</li>
+              {if self.count(Outcome::NotImported) > 0 { html! {
+                <li>{format!("Not imported after an error: {}", self.count(Outcome::NotImported))}</li>
+              }} else { html! {} }}
+            </ul>
+            {match (&self.report, not_imported) {
+                (Some(url), n) if n > 0 => html! {
+                  <a class="btn btn-secondary me-2" href={url.to_string()} download="import_errors.csv">
+                    <i class="bi-download me-2"></i>
+                    {"Download the error report"}
+                  </a>
+                },
+                _ => html! {},
+            }}
+            <button type="button" class="btn btn-primary" onclick={ctx.link().callback(|_| Msg::Close)}>
+              {"Done"}
+            </button>
+          </>
+        }
+    }
+
+    fn view_errors(&self) -> Html {
+        match &self.common.error {
+            None => html! {},
+            Some(e) => html! {<div class="text-danger mt-2">{"Error: "}{e.to_string()}</div>},
+        }
+    }
+}
@@ -20,6 +20,10 @@ pub struct LoginForm {
     common: CommonComponentParts<Self>,
     form: Form<FormModel>,
     refreshing: bool,
+    /// The choice of the submitted form.
+    remember_me: bool,
+    /// With `login_identifier = "email"`. The user IDs are still accepted.
+    login_with_email: bool,
 }
 
 /// The fields of the form, with the constraints.
@@ -29,6 +33,8 @@ pub struct FormModel {
     username: String,
     #[validate(length(min = 8, message = "Invalid password. Min length: 8"))]
     password: String,
+    /// Keep the session after the browser is closed.
+    remember_me: bool,
 }
 
 #[derive(Clone, PartialEq, Properties)]
@@ -38,6 +44,7 @@ pub struct Props {
 }
 
 pub enum Msg {
+    LoginOptionsResponse(Result<login::LoginOptions>),
     Update,
     Submit,
     AuthenticationRefreshResponse(Result<(String, bool)>),
@@ -58,12 +65,33 @@ impl CommonComponent<LoginForm> for LoginForm {
     ) -> Result<bool> {
         use anyhow::Context;
         match msg {
+            Msg::LoginOptionsResponse(options) => {
+                match options {
+                    Ok(options) => {
+                        self.login_with_email = options.login_with_email;
+                        self.form = Form::new(FormModel {
+                            remember_me: options.remember_me_default,
+                            ..self.form.model()
+                        })
+                    }
+                    Err(e) => error!(&format!("Could not fetch the login options: {}", e)),
+                }
+                Ok(true)
+            }
             Msg::Update => Ok(true),
             Msg::Submit => {
                 if !self.form.validate() {
+                    if self.form.model().username.is_empty() {
+                        bail!("Missing {}", self.login_label().to_lowercase());
+                    }
                     bail!("Check the form for errors");
                 }
-                let FormModel { username, password } = self.form.model();
+                let FormModel {
+                    username,
+                    password,
+                    remember_me,
+                } = self.form.model();
+                self.remember_me = remember_me;
                 let mut rng = rand::rngs::OsRng;
                 let opaque::client::login::ClientLoginStartResult { state, message } =
                     opaque::client::login::start_login(&password, &mut rng)
@@ -86,8 +114,12 @@ impl CommonComponent<LoginForm> for LoginForm {
                         Err(e) => {
                             // Common error, we want to print a full error to the console but only a
                             // simple one to the user.
-                            error!(&format!("Invalid username or password: {}", e));
-                            self.common.error = Some(anyhow!("Invalid username or password"));
+                            let message = format!(
+                                "Invalid {} or password",
+                                self.login_label().to_lowercase()
+                            );
+                            error!(&format!("{}: {}", message, e));
+                            self.common.error = Some(anyhow!(message));
                             return Ok(true);
                         }
                         Ok(l) => l,
@@ -95,6 +127,7 @@ impl CommonComponent<LoginForm> for LoginForm {
                 let req = login::ClientLoginFinishRequest {
                     server_data: res.server_data,
                     credential_finalization: login_finish.message,
+                    remember_me: Some(self.remember_me),
                 };
                 self.common.call_backend(
                     ctx,
@@ -124,6 +157,16 @@ impl CommonComponent<LoginForm> for LoginForm {
     }
 }
 
+impl LoginForm {
+    fn login_label(&self) -> &'static str {
+        if self.login_with_email {
+            "Email"
+        } else {
+            "Username"
+        }
+    }
+}
+
 impl Component for LoginForm {
     type Message = Msg;
     type Properties = Props;
@@ -131,9 +174,17 @@ impl Component for LoginForm {
     fn create(ctx: &Context<Self>) -> Self {
         let mut app = LoginForm {
             common: CommonComponentParts::<Self>::create(),
-            form: Form::<FormModel>::new(FormModel::default()),
+            form: Form::<FormModel>::new(FormModel {
+                remember_me: true,
+                ..Default::default()
+            }),
             refreshing: true,
+            remember_me: true,
+            login_with_email: false,
         };
+        ctx.link().send_future(async {
+            Msg::LoginOptionsResponse(HostService::get_login_options().await)
+        });
         app.common.call_backend(
             ctx,
             HostService::refresh(),
@@ -158,7 +209,7 @@ impl Component for LoginForm {
             }
         } else {
             html! {
-              <form class="form center-block col-sm-4 col-offset-4">
+              <form class="form center-block col-sm-8 col-md-6 col-lg-4 col-offset-4">
                 <div class="input-group">
                   <div class="input-group-prepend">
                     <span class="input-group-text">
@@ -171,7 +222,7 @@ impl Component for LoginForm {
                     class_valid="has-success"
                     form={&self.form}
                     field_name="username"
-                    placeholder="Username"
+                    placeholder={self.login_label()}
                     autocomplete="username"
                     oninput={link.callback(|_| Msg::Update)} />
                 </div>
@@ -191,6 +242,15 @@ impl Component for LoginForm {
                     placeholder="Password"
                     autocomplete="current-password" />
                 </div>
+                <div class="form-check mt-2">
+                  <label class="form-check-label">
+                    <yew_form::CheckBox<FormModel>
+                      form={&self.form}
+                      field_name="remember_me"
+                      ontoggle={link.callback(|_| Msg::Update)} />
+                    {" Remember me"}
+                  </label>
+                </div>
                 <Submit
                   text="Login"
                   disabled={self.common.is_task_running()}
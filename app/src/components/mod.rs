@@ -2,8 +2,10 @@ pub mod add_group_member;
 pub mod add_user_to_group;
 pub mod app;
 pub mod avatar;
+pub mod avatar_cropper;
 pub mod banner;
 pub mod change_password;
+pub mod confirm_dialog;
 pub mod create_group;
 pub mod create_group_attribute;
 pub mod create_user;
@@ -12,18 +14,23 @@ pub mod delete_group;
 pub mod delete_group_attribute;
 pub mod delete_user;
 pub mod delete_user_attribute;
+pub mod directory_info;
 pub mod form;
 pub mod group_details;
 pub mod group_schema_table;
 pub mod group_table;
+pub mod import_users;
 pub mod login;
 pub mod logout;
 pub mod remove_user_from_group;
 pub mod reset_password_step1;
 pub mod reset_password_step2;
 pub mod router;
+pub mod security_banner;
 pub mod select;
+pub mod undo_toast;
 pub mod user_details;
 pub mod user_details_form;
 pub mod user_schema_table;
 pub mod user_table;
+pub mod verify_email;
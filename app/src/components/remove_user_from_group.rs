@@ -1,4 +1,7 @@
-use crate::infra::common_component::{CommonComponent, CommonComponentParts};
+use crate::{
+    components::confirm_dialog::{is_admin_group, ConfirmDialog},
+    infra::common_component::{CommonComponent, CommonComponentParts},
+};
 use anyhow::{Error, Result};
 use graphql_client::GraphQLQuery;
 use yew::prelude::*;
@@ -15,17 +18,24 @@ pub struct RemoveUserFromGroup;
 
 pub struct RemoveUserFromGroupComponent {
     common: CommonComponentParts<Self>,
+    show_confirmation: bool,
 }
 
 #[derive(yew::Properties, Clone, PartialEq)]
 pub struct Props {
     pub username: String,
     pub group_id: i64,
+    pub group_name: String,
+    /// Why the user can't be removed, if so. The button is then disabled.
+    #[prop_or_default]
+    pub disabled_reason: Option<String>,
     pub on_user_removed_from_group: Callback<(String, i64)>,
     pub on_error: Callback<Error>,
 }
 
 pub enum Msg {
+    ClickedRemove,
+    DismissModal,
     SubmitRemoveGroup,
     RemoveGroupResponse(Result<remove_user_from_group::ResponseData>),
 }
@@ -37,7 +47,12 @@ impl CommonComponent<RemoveUserFromGroupComponent> for RemoveUserFromGroupCompon
         msg: <Self as Component>::Message,
     ) -> Result<bool> {
         match msg {
-            Msg::SubmitRemoveGroup => self.submit_remove_group(ctx),
+            Msg::ClickedRemove => self.show_confirmation = true,
+            Msg::DismissModal => self.show_confirmation = false,
+            Msg::SubmitRemoveGroup => {
+                self.show_confirmation = false;
+                self.submit_remove_group(ctx);
+            }
             Msg::RemoveGroupResponse(response) => {
                 response?;
                 ctx.props()
@@ -74,6 +89,7 @@ impl Component for RemoveUserFromGroupComponent {
     fn create(_: &Context<Self>) -> Self {
         Self {
             common: CommonComponentParts::<Self>::create(),
+            show_confirmation: false,
         }
     }
 
@@ -88,13 +104,33 @@ impl Component for RemoveUserFromGroupComponent {
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let link = &ctx.link();
+        let props = ctx.props();
+        let disabled_reason = &props.disabled_reason;
         html! {
-          <button
-            class="btn btn-danger"
-            disabled={self.common.is_task_running()}
-            onclick={link.callback(|_| Msg::SubmitRemoveGroup)}>
-            <i class="bi-x-circle-fill" aria-label="Remove user from group" />
-          </button>
+          <>
+            <button
+              class="btn btn-danger"
+              disabled={self.common.is_task_running() || disabled_reason.is_some()}
+              title={disabled_reason.clone()}
+              onclick={link.callback(|_| Msg::ClickedRemove)}>
+              <i class="bi-x-circle-fill" aria-label="Remove user from group" />
+            </button>
+            <ConfirmDialog
+              id={format!("removeUserFromGroupModal{}_{}", props.username, props.group_id)}
+              show={self.show_confirmation}
+              title="Remove from group?"
+              confirm_label="Remove"
+              safety_text={is_admin_group(&props.group_name).then(|| AttrValue::from(props.username.clone()))}
+              on_confirm={link.callback(|_| Msg::SubmitRemoveGroup)}
+              on_cancel={link.callback(|_| Msg::DismissModal)}>
+              {"Remove "}<b>{&props.username}</b>{" from the group "}<b>{&props.group_name}</b>{"?"}
+            </ConfirmDialog>
+            {if let Some(reason) = disabled_reason {
+              html! {<small class="text-muted ms-2">{reason}</small>}
+            } else {
+              html! {}
+            }}
+          </>
         }
     }
 }
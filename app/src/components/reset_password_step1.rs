@@ -85,7 +85,7 @@ impl Component for ResetPasswordStep1Form {
         let link = &ctx.link();
         html! {
             <form
-              class="form center-block col-sm-4 col-offset-4">
+              class="form center-block col-sm-8 col-md-6 col-lg-4 col-offset-4">
                 <div class="input-group">
                   <div class="input-group-prepend">
                     <span class="input-group-text">
@@ -1,6 +1,10 @@
 use crate::{
     components::{
-        form::{field::Field, submit::Submit},
+        form::{
+            field::Field,
+            password_strength::{check_password_policy, PasswordStrength},
+            submit::Submit,
+        },
         router::{AppRoute, Link},
     },
     infra::{
@@ -9,8 +13,9 @@ use crate::{
     },
 };
 use anyhow::{bail, Result};
+use gloo_console::error;
 use lldap_auth::{
-    opaque::client::registration as opaque_registration,
+    opaque::client::registration as opaque_registration, password_policy::PasswordPolicy,
     password_reset::ServerPasswordResetResponse, registration,
 };
 use validator_derive::Validate;
@@ -22,7 +27,7 @@ use yew_router::{prelude::History, scope_ext::RouterScopeExt};
 /// The fields of the form, with the constraints.
 #[derive(Model, Validate, PartialEq, Eq, Clone, Default)]
 pub struct FormModel {
-    #[validate(length(min = 8, message = "Invalid password. Min length: 8"))]
+    #[validate(length(min = 1, message = "Password is required"))]
     password: String,
     #[validate(must_match(other = "password", message = "Passwords must match"))]
     confirm_password: String,
@@ -33,6 +38,7 @@ pub struct ResetPasswordStep2Form {
     form: Form<FormModel>,
     username: Option<String>,
     opaque_data: Option<opaque_registration::ClientRegistration>,
+    password_policy: PasswordPolicy,
 }
 
 #[derive(Clone, PartialEq, Eq, Properties)]
@@ -42,6 +48,7 @@ pub struct Props {
 
 pub enum Msg {
     ValidateTokenResponse(Result<ServerPasswordResetResponse>),
+    PasswordPolicyResponse(Result<PasswordPolicy>),
     FormUpdate,
     Submit,
     RegistrationStartResponse(Result<Box<registration::ServerRegistrationStartResponse>>),
@@ -60,11 +67,20 @@ impl CommonComponent<ResetPasswordStep2Form> for ResetPasswordStep2Form {
                 self.username = Some(response?.user_id);
                 Ok(true)
             }
+            Msg::PasswordPolicyResponse(policy) => {
+                match policy {
+                    Ok(policy) => self.password_policy = policy,
+                    // The default policy is still checked.
+                    Err(e) => error!(&format!("Could not fetch the password policy: {}", e)),
+                }
+                Ok(true)
+            }
             Msg::FormUpdate => Ok(true),
             Msg::Submit => {
                 if !self.form.validate() {
                     bail!("Check the form for errors");
                 }
+                check_password_policy(&self.password_policy, &self.form.model().password)?;
                 let mut rng = rand::rngs::OsRng;
                 let new_password = self.form.model().password;
                 let registration_start_request =
@@ -128,7 +144,11 @@ impl Component for ResetPasswordStep2Form {
             form: yew_form::Form::<FormModel>::new(FormModel::default()),
             opaque_data: None,
             username: None,
+            password_policy: PasswordPolicy::default(),
         };
+        ctx.link().send_future(async {
+            Msg::PasswordPolicyResponse(HostService::get_password_policy().await)
+        });
         let token = ctx.props().token.clone();
         component.common.call_backend(
             ctx,
@@ -179,6 +199,10 @@ impl Component for ResetPasswordStep2Form {
                 autocomplete="new-password"
                 input_type="password"
                 oninput={link.callback(|_| Msg::FormUpdate)} />
+              <PasswordStrength
+                password={self.form.model().password}
+                policy={self.password_policy.clone()}
+                user_inputs={self.username.iter().cloned().collect::<Vec<_>>()} />
               <Field<FormModel>
                 label="Confirm password"
                 required=true
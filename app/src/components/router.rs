@@ -8,6 +8,8 @@ pub enum AppRoute {
     StartResetPassword,
     #[at("/reset-password/step2/:token")]
     FinishResetPassword { token: String },
+    #[at("/verify-email/:token")]
+    VerifyEmail { token: String },
     #[at("/users/create")]
     CreateUser,
     #[at("/users")]
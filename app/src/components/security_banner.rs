@@ -0,0 +1,120 @@
+use crate::infra::{api::HostService, local_storage};
+use anyhow::Result;
+use gloo_console::error;
+use graphql_client::GraphQLQuery;
+use yew::prelude::*;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/get_security_status.graphql",
+    response_derives = "Debug,Clone,PartialEq",
+    custom_scalars_module = "crate::infra::graphql"
+)]
+pub struct GetSecurityStatus;
+
+type SecurityWarning = get_security_status::GetSecurityStatusSecurityStatus;
+
+/// The codes of the issues dismissed in this browser, comma-separated.
+const DISMISSED_ISSUES_KEY: &str = "lldap_dismissed_security_issues";
+
+fn issue_code(warning: &SecurityWarning) -> String {
+    serde_json::to_value(&warning.code)
+        .ok()
+        .and_then(|code| code.as_str().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+fn dismissed_issues() -> Vec<String> {
+    local_storage::get_item(DISMISSED_ISSUES_KEY)
+        .unwrap_or_else(|e| {
+            error!(&e.to_string());
+            None
+        })
+        .map(|codes| codes.split(',').map(str::to_owned).collect())
+        .unwrap_or_default()
+}
+
+/// A warning for the admins about the misconfigurations of the server, with how to fix them.
+///
+/// Dismissing it hides it in this browser until an issue that was not there at the time shows up.
+pub struct SecurityBanner {
+    warnings: Vec<SecurityWarning>,
+    dismissed: bool,
+}
+
+pub enum Msg {
+    SecurityStatusResponse(Result<get_security_status::ResponseData>),
+    Dismiss,
+}
+
+impl Component for SecurityBanner {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        ctx.link().send_future(async {
+            Msg::SecurityStatusResponse(
+                HostService::graphql_query::<GetSecurityStatus>(
+                    get_security_status::Variables {},
+                    "Error trying to fetch the security status",
+                )
+                .await,
+            )
+        });
+        Self {
+            warnings: Vec::new(),
+            dismissed: false,
+        }
+    }
+
+    fn update(&mut self, _: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::SecurityStatusResponse(Ok(response)) => {
+                let dismissed = dismissed_issues();
+                self.dismissed = response
+                    .security_status
+                    .iter()
+                    .all(|w| dismissed.contains(&issue_code(w)));
+                self.warnings = response.security_status;
+            }
+            Msg::SecurityStatusResponse(Err(e)) => error!(&format!("{:#}", e)),
+            Msg::Dismiss => {
+                let codes = self.warnings.iter().map(issue_code).collect::<Vec<_>>();
+                if let Err(e) = local_storage::set_item(DISMISSED_ISSUES_KEY, &codes.join(",")) {
+                    error!(&e.to_string());
+                }
+                self.dismissed = true;
+            }
+        }
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        if self.dismissed || self.warnings.is_empty() {
+            return html! {};
+        }
+        html! {
+          <div class="alert alert-warning alert-dismissible" role="alert">
+            <h5 class="alert-heading">
+              <i class="bi-shield-exclamation me-2"></i>
+              {"Security warnings"}
+            </h5>
+            <ul class="mb-0">
+              {for self.warnings.iter().map(|w| html! {
+                <li>
+                  {&w.message}
+                  <div class="small">{&w.remediation}</div>
+                </li>
+              })}
+            </ul>
+            <button
+              type="button"
+              class="btn-close"
+              aria-label="Dismiss"
+              title="Hide until a new issue comes up"
+              onclick={ctx.link().callback(|_| Msg::Dismiss)} />
+          </div>
+        }
+    }
+}
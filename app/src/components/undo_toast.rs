@@ -0,0 +1,69 @@
+use gloo_timers::callback::Timeout;
+use yew::prelude::*;
+
+/// How long the undo toast stays, in milliseconds.
+const UNDO_TIMEOUT_MS: u32 = 8000;
+
+/// A toast offering to undo an action that can be reverted, like deleting a user, for a few
+/// seconds.
+pub struct UndoToast {
+    _timeout: Timeout,
+}
+
+pub enum Msg {
+    Undo,
+    Dismiss,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    pub message: AttrValue,
+    pub on_undo: Callback<()>,
+    /// Called when the toast is closed or times out, but not after undoing.
+    pub on_dismiss: Callback<()>,
+}
+
+impl Component for UndoToast {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let link = ctx.link().clone();
+        Self {
+            _timeout: Timeout::new(UNDO_TIMEOUT_MS, move || link.send_message(Msg::Dismiss)),
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Undo => ctx.props().on_undo.emit(()),
+            Msg::Dismiss => ctx.props().on_dismiss.emit(()),
+        }
+        false
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        html! {
+          <div class="toast-container position-fixed bottom-0 end-0 p-3">
+            <div class="toast show" role="status" aria-live="polite" aria-atomic="true">
+              <div class="d-flex align-items-center">
+                <div class="toast-body me-auto">{&ctx.props().message}</div>
+                <button
+                  type="button"
+                  class="btn btn-link btn-sm fw-bold"
+                  onclick={link.callback(|_| Msg::Undo)}>
+                  {"Undo"}
+                </button>
+                <button
+                  type="button"
+                  class="btn-close me-2"
+                  aria-label="Close"
+                  onclick={link.callback(|_| Msg::Dismiss)}>
+                </button>
+              </div>
+            </div>
+          </div>
+        }
+    }
+}
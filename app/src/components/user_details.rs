@@ -1,6 +1,7 @@
 use crate::{
     components::{
         add_user_to_group::AddUserToGroupComponent,
+        directory_info::{DirectoryEntry, DirectoryInfo},
         remove_user_from_group::RemoveUserFromGroupComponent,
         router::{AppRoute, Link},
         user_details_form::UserDetailsForm,
@@ -22,6 +23,7 @@ pub struct GetUserDetails;
 
 pub type User = get_user_details::GetUserDetailsUser;
 pub type Group = get_user_details::GetUserDetailsUserGroups;
+pub type UserAttribute = get_user_details::GetUserDetailsUserAttributes;
 
 pub struct UserDetails {
     common: CommonComponentParts<Self>,
@@ -58,14 +60,20 @@ impl CommonComponent<UserDetails> for UserDetails {
             },
             Msg::OnError(e) => return Err(e),
             Msg::OnUserAddedToGroup(group) => {
-                self.user.as_mut().unwrap().groups.push(group);
+                self.user
+                    .as_mut()
+                    .unwrap()
+                    .groups
+                    .get_or_insert_with(Vec::new)
+                    .push(group);
             }
             Msg::OnUserRemovedFromGroup((_, group_id)) => {
                 self.user
                     .as_mut()
                     .unwrap()
                     .groups
-                    .retain(|g| g.id != group_id);
+                    .iter_mut()
+                    .for_each(|groups| groups.retain(|g| g.id != group_id));
             }
         }
         Ok(true)
@@ -117,6 +125,7 @@ impl UserDetails {
                       <RemoveUserFromGroupComponent
                         username={u.id.clone()}
                         group_id={group.id}
+                        group_name={group.display_name.clone()}
                         on_user_removed_from_group={link.callback(Msg::OnUserRemovedFromGroup)}
                         on_error={link.callback(Msg::OnError)}/>
                     </td>
@@ -127,6 +136,7 @@ impl UserDetails {
               </tr>
             }
         };
+        let groups = u.groups.as_deref().unwrap_or_default();
         html! {
           <>
             <h5 class="row m-3 fw-bold">{"Group memberships"}</h5>
@@ -139,14 +149,14 @@ impl UserDetails {
                   </tr>
                 </thead>
                 <tbody>
-                  {if u.groups.is_empty() {
+                  {if groups.is_empty() {
                     html! {
                       <tr key="EmptyRow">
                         <td>{"This user is not a member of any groups."}</td>
                       </tr>
                     }
                   } else {
-                    html! {<>{u.groups.iter().map(make_group_row).collect::<Vec<_>>()}</>}
+                    html! {<>{groups.iter().map(make_group_row).collect::<Vec<_>>()}</>}
                   }}
                 </tbody>
               </table>
@@ -161,7 +171,7 @@ impl UserDetails {
             html! {
                 <AddUserToGroupComponent
                     username={u.id.clone()}
-                    groups={u.groups.clone()}
+                    groups={u.groups.clone().unwrap_or_default()}
                     on_error={link.callback(Msg::OnError)}
                     on_user_added_to_group={link.callback(Msg::OnUserAddedToGroup)}/>
             }
@@ -207,7 +217,15 @@ impl Component for UserDetails {
                     <div>
                       <h5 class="row m-3 fw-bold">{"User details"}</h5>
                     </div>
-                    <UserDetailsForm user={u.clone()} />
+                    <UserDetailsForm user={u.clone()} is_admin={ctx.props().is_admin} />
+                    <DirectoryInfo entries={vec![
+                      DirectoryEntry::new("DN", "dn", u.dn.clone()),
+                      DirectoryEntry::new("UUID", "uuid", u.uuid.clone()),
+                      DirectoryEntry::date("Creation date", "creationDate", Some(u.creation_date)),
+                      DirectoryEntry::date("Last login", "lastLogin", u.last_login),
+                      DirectoryEntry::date("Password changed", "passwordChangedAt", u.password_changed_at),
+                      DirectoryEntry::date("Expires", "expiresAt", u.expires_at),
+                    ]} />
                     {self.view_group_memberships(ctx, u)}
                     {self.view_add_group_button(ctx, u)}
                     {self.view_messages(error)}
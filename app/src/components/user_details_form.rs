@@ -1,20 +1,29 @@
-use std::{fmt::Display, str::FromStr};
+use std::{collections::HashMap, fmt::Display, str::FromStr};
 
 use crate::{
     components::{
-        form::{field::Field, static_value::StaticValue, submit::Submit},
-        user_details::User,
+        avatar_cropper::AvatarCropper,
+        confirm_dialog::ConfirmDialog,
+        form::{
+            attribute_input::AttributeInput, field::Field, select::Select,
+            static_value::StaticValue, submit::Submit,
+        },
+        user_details::{User, UserAttribute},
+        user_schema_table::{get_user_attributes_schema, Attribute, GetUserAttributesSchema},
+    },
+    infra::{
+        api::{HostService, InvalidFieldError},
+        common_component::{CommonComponent, CommonComponentParts},
+        graphql::DateTimeUtc,
+        schema::AttributeType,
     },
-    infra::common_component::{CommonComponent, CommonComponentParts},
 };
 use anyhow::{bail, Error, Result};
-use gloo_file::{
-    callbacks::{read_as_bytes, FileReader},
-    File,
-};
+use gloo_console::error;
+use gloo_file::{File, ObjectUrl};
 use graphql_client::GraphQLQuery;
 use validator_derive::Validate;
-use web_sys::{FileList, HtmlInputElement, InputEvent};
+use web_sys::{FileList, HtmlInputElement, HtmlTextAreaElement, InputEvent};
 use yew::prelude::*;
 use yew_form_derive::Model;
 
@@ -46,6 +55,13 @@ impl FromStr for JsFile {
     }
 }
 
+/// The pictures that can be cropped into an avatar. They are converted to JPEG before the upload.
+const AVATAR_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/webp", "image/gif"];
+
+/// The languages of the built-in email templates.
+const EMAIL_LANGUAGES: [(&str, &str); 3] =
+    [("en", "English"), ("fr", "Français"), ("de", "Deutsch")];
+
 /// The fields of the form, with the editable details and the constraints.
 #[derive(Model, Validate, PartialEq, Eq, Clone)]
 pub struct UserModel {
@@ -54,6 +70,14 @@ pub struct UserModel {
     display_name: String,
     first_name: String,
     last_name: String,
+    /// Empty for the server default.
+    preferred_language: String,
+    /// Separated by commas or spaces.
+    hosts: String,
+    phone: String,
+    mobile: String,
+    locality: String,
+    state_or_province: String,
 }
 
 /// The GraphQL query sent to the server to update the user details.
@@ -67,15 +91,65 @@ pub struct UserModel {
 )]
 pub struct UpdateUser;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/get_avatar_limits.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql"
+)]
+pub struct GetAvatarLimits;
+pub type AvatarLimits = get_avatar_limits::GetAvatarLimitsAvatarLimits;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/get_pending_email_change.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql"
+)]
+pub struct GetPendingEmailChange;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/cancel_email_change.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql"
+)]
+pub struct CancelEmailChange;
+
+/// A new email address waiting for the user to click the link sent to it.
+#[derive(Clone, PartialEq, Eq)]
+struct PendingEmail {
+    email: String,
+    expires_at: DateTimeUtc,
+}
+
 /// A [yew::Component] to display the user details, with a form allowing to edit them.
 pub struct UserDetailsForm {
     common: CommonComponentParts<Self>,
     form: yew_form::Form<UserModel>,
     // None means that the avatar hasn't changed.
     avatar: Option<JsFile>,
-    reader: Option<FileReader>,
+    /// The picked picture being cropped, before it becomes the avatar.
+    cropping: Option<(File, ObjectUrl)>,
+    avatar_input: NodeRef,
+    avatar_limits: AvatarLimits,
+    show_clear_avatar_confirmation: bool,
+    /// The custom attributes that can be displayed, once the schema is fetched.
+    attribute_schema: Vec<Attribute>,
+    /// The values of the custom attributes, with the pending changes.
+    attribute_values: HashMap<String, Vec<String>>,
+    /// The error reported by the server for a custom attribute, by attribute name.
+    attribute_errors: HashMap<String, String>,
+    /// The lines of the postal address, with the pending changes.
+    postal_address: String,
     /// True if we just successfully updated the user, to display a success message.
     just_updated: bool,
+    /// Admins only: apply the new email right away.
+    skip_email_verification: bool,
+    pending_email: Option<PendingEmail>,
     user: User,
 }
 
@@ -88,16 +162,34 @@ pub enum Msg {
     SubmitClicked,
     /// The "Clear" button for the avatar was clicked.
     ClearAvatarClicked,
-    /// A picked file finished loading.
-    FileLoaded(String, Result<Vec<u8>>),
+    ConfirmClearAvatar,
+    DismissClearAvatar,
+    /// The picked picture was cropped and converted to a JPEG.
+    AvatarCropped(Vec<u8>),
+    /// The cropping was cancelled.
+    CropCancelled,
+    /// The picked picture could not be cropped.
+    CropFailed(Error),
+    AvatarLimitsResponse(Result<get_avatar_limits::ResponseData>),
+    AttributeSchemaResponse(Result<get_user_attributes_schema::ResponseData>),
+    /// The values of a custom attribute changed.
+    AttributeChanged(String, Vec<String>),
+    PostalAddressChanged(String),
     /// We got the response from the server about our update message.
     UserUpdated(Result<update_user::ResponseData>),
+    SetSkipEmailVerification(bool),
+    /// The current email and the pending change, after updating the email.
+    PendingEmailChangeResponse(Result<get_pending_email_change::ResponseData>),
+    CancelEmailChangeClicked,
+    EmailChangeCancelled(Result<cancel_email_change::ResponseData>),
 }
 
 #[derive(yew::Properties, Clone, PartialEq, Eq)]
 pub struct Props {
     /// The current user details.
     pub user: User,
+    /// Admins can see and edit all the custom attributes, not only the visible and editable ones.
+    pub is_admin: bool,
 }
 
 impl CommonComponent<UserDetailsForm> for UserDetailsForm {
@@ -109,51 +201,118 @@ impl CommonComponent<UserDetailsForm> for UserDetailsForm {
         match msg {
             Msg::Update => Ok(true),
             Msg::FileSelected(new_avatar) => {
-                if self
-                    .avatar
-                    .as_ref()
-                    .and_then(|f| f.file.as_ref().map(|f| f.name()))
-                    != Some(new_avatar.name())
-                {
-                    let file_name = new_avatar.name();
-                    let link = ctx.link().clone();
-                    self.reader = Some(read_as_bytes(&new_avatar, move |res| {
-                        link.send_message(Msg::FileLoaded(
-                            file_name,
-                            res.map_err(|e| anyhow::anyhow!("{:#}", e)),
-                        ))
-                    }));
+                let file_type = new_avatar.raw_mime_type();
+                if !AVATAR_TYPES.contains(&file_type.as_str()) {
+                    self.clear_avatar_input();
+                    bail!(
+                        "Unsupported picture type \"{}\": choose a JPEG, PNG, WebP or GIF picture",
+                        file_type
+                    );
+                }
+                let url = ObjectUrl::from(new_avatar.clone());
+                self.cropping = Some((new_avatar, url));
+                Ok(true)
+            }
+            Msg::AvatarCropped(contents) => {
+                if let Some((file, _)) = self.cropping.take() {
                     self.avatar = Some(JsFile {
-                        file: Some(new_avatar),
-                        contents: None,
+                        file: Some(file),
+                        contents: Some(contents),
                     });
                 }
                 Ok(true)
             }
+            Msg::CropCancelled => {
+                self.cropping = None;
+                self.clear_avatar_input();
+                Ok(true)
+            }
+            Msg::CropFailed(e) => {
+                self.cropping = None;
+                self.clear_avatar_input();
+                Err(e)
+            }
+            Msg::AvatarLimitsResponse(response) => {
+                match response {
+                    Ok(response) => self.avatar_limits = response.avatar_limits,
+                    // The server still checks the size.
+                    Err(e) => error!(&format!("Could not fetch the avatar limits: {:#}", e)),
+                }
+                Ok(false)
+            }
+            Msg::AttributeSchemaResponse(response) => {
+                let is_admin = ctx.props().is_admin;
+                self.attribute_schema = response?
+                    .schema
+                    .user_schema
+                    .attributes
+                    .into_iter()
+                    .filter(|a| !a.is_hardcoded && (is_admin || a.is_visible || a.is_editable))
+                    .collect();
+                Ok(true)
+            }
+            Msg::AttributeChanged(name, values) => {
+                self.attribute_errors.remove(&name);
+                self.attribute_values.insert(name, values);
+                Ok(true)
+            }
+            Msg::PostalAddressChanged(address) => {
+                self.postal_address = address;
+                Ok(true)
+            }
             Msg::SubmitClicked => self.submit_user_update_form(ctx),
             Msg::ClearAvatarClicked => {
+                if self.has_avatar() {
+                    self.show_clear_avatar_confirmation = true;
+                }
+                Ok(true)
+            }
+            Msg::ConfirmClearAvatar => {
+                self.show_clear_avatar_confirmation = false;
                 self.avatar = Some(JsFile::default());
                 Ok(true)
             }
-            Msg::UserUpdated(response) => self.user_update_finished(response),
-            Msg::FileLoaded(file_name, data) => {
-                if let Some(avatar) = &mut self.avatar {
-                    if let Some(file) = &avatar.file {
-                        if file.name() == file_name {
-                            let data = data?;
-                            if !is_valid_jpeg(data.as_slice()) {
-                                // Clear the selection.
-                                self.avatar = None;
-                                bail!("Chosen image is not a valid JPEG");
-                            } else {
-                                avatar.contents = Some(data);
-                                return Ok(true);
-                            }
-                        }
-                    }
-                }
-                self.reader = None;
-                Ok(false)
+            Msg::DismissClearAvatar => {
+                self.show_clear_avatar_confirmation = false;
+                Ok(true)
+            }
+            Msg::UserUpdated(response) => self.user_update_finished(ctx, response),
+            Msg::SetSkipEmailVerification(skip) => {
+                self.skip_email_verification = skip;
+                Ok(true)
+            }
+            Msg::PendingEmailChangeResponse(response) => {
+                let user = response?.user;
+                let email = user.email.unwrap_or_default();
+                self.pending_email = user.pending_email_change.map(|change| PendingEmail {
+                    email: change.email,
+                    expires_at: change.expires_at,
+                });
+                // The form shows the current address until the new one is verified.
+                self.user.email = Some(email.clone());
+                self.form = yew_form::Form::new(UserModel {
+                    email,
+                    ..self.form.model()
+                });
+                // Still the answer to the update.
+                self.just_updated = true;
+                Ok(true)
+            }
+            Msg::CancelEmailChangeClicked => {
+                self.common.call_graphql::<CancelEmailChange, _>(
+                    ctx,
+                    cancel_email_change::Variables {
+                        user_id: self.user.id.clone(),
+                    },
+                    Msg::EmailChangeCancelled,
+                    "Error trying to cancel the email change",
+                );
+                Ok(true)
+            }
+            Msg::EmailChangeCancelled(response) => {
+                response?;
+                self.pending_email = None;
+                Ok(true)
             }
         }
     }
@@ -169,17 +328,72 @@ impl Component for UserDetailsForm {
 
     fn create(ctx: &Context<Self>) -> Self {
         let model = UserModel {
-            email: ctx.props().user.email.clone(),
+            email: ctx.props().user.email.clone().unwrap_or_default(),
             display_name: ctx.props().user.display_name.clone(),
-            first_name: ctx.props().user.first_name.clone(),
-            last_name: ctx.props().user.last_name.clone(),
+            first_name: ctx.props().user.first_name.clone().unwrap_or_default(),
+            last_name: ctx.props().user.last_name.clone().unwrap_or_default(),
+            preferred_language: ctx
+                .props()
+                .user
+                .preferred_language
+                .clone()
+                .unwrap_or_default(),
+            hosts: ctx.props().user.hosts.join(", "),
+            phone: ctx.props().user.phone.clone().unwrap_or_default(),
+            mobile: ctx.props().user.mobile.clone().unwrap_or_default(),
+            locality: ctx.props().user.locality.clone().unwrap_or_default(),
+            state_or_province: ctx
+                .props()
+                .user
+                .state_or_province
+                .clone()
+                .unwrap_or_default(),
         };
+        ctx.link().send_future(async {
+            Msg::AvatarLimitsResponse(
+                HostService::graphql_query::<GetAvatarLimits>(
+                    get_avatar_limits::Variables {},
+                    "Error trying to fetch the avatar limits",
+                )
+                .await,
+            )
+        });
+        ctx.link().send_future(async {
+            Msg::AttributeSchemaResponse(
+                HostService::graphql_query::<GetUserAttributesSchema>(
+                    get_user_attributes_schema::Variables {},
+                    "Error trying to fetch the user attributes",
+                )
+                .await,
+            )
+        });
         Self {
             common: CommonComponentParts::<Self>::create(),
             form: yew_form::Form::new(model),
             avatar: None,
+            cropping: None,
+            avatar_input: NodeRef::default(),
+            // The server defaults, until the actual limits are fetched.
+            avatar_limits: AvatarLimits {
+                max_size_kb: 1024,
+                max_dimension: 512,
+            },
+            show_clear_avatar_confirmation: false,
+            attribute_schema: Vec::new(),
+            attribute_values: attribute_values(&ctx.props().user),
+            attribute_errors: HashMap::new(),
+            postal_address: ctx.props().user.postal_address.clone().unwrap_or_default(),
             just_updated: false,
-            reader: None,
+            skip_email_verification: false,
+            pending_email: ctx
+                .props()
+                .user
+                .pending_email_change
+                .as_ref()
+                .map(|change| PendingEmail {
+                    email: change.email.clone(),
+                    expires_at: change.expires_at,
+                }),
             user: ctx.props().user.clone(),
         }
     }
@@ -199,18 +413,19 @@ impl Component for UserDetailsForm {
             }
             None => self.user.avatar.as_deref().unwrap_or("").to_owned(),
         };
+        let current_language = self.user.preferred_language.as_deref().unwrap_or_default();
+        // A language set through the API, without built-in templates.
+        let other_language = (!current_language.is_empty()
+            && !EMAIL_LANGUAGES
+                .iter()
+                .any(|(tag, _)| *tag == current_language))
+        .then_some(current_language);
         html! {
           <div class="py-3">
             <form class="form">
               <StaticValue label="User ID" id="userId">
                 <i>{&self.user.id}</i>
               </StaticValue>
-              <StaticValue label="Creation date" id="creationDate">
-                {&self.user.creation_date.naive_local().date()}
-              </StaticValue>
-              <StaticValue label="UUID" id="uuid">
-                {&self.user.uuid}
-              </StaticValue>
               <Field<UserModel>
                 form={&self.form}
                 required=true
@@ -218,6 +433,7 @@ impl Component for UserDetailsForm {
                 field_name="email"
                 input_type="email"
                 oninput={link.callback(|_| Msg::Update)} />
+              {self.view_email_change(ctx)}
               <Field<UserModel>
                 form={&self.form}
                 label="Display name"
@@ -236,25 +452,45 @@ impl Component for UserDetailsForm {
                 field_name="last_name"
                 autocomplete="family-name"
                 oninput={link.callback(|_| Msg::Update)} />
+              <Select<UserModel>
+                label="Email language"
+                form={&self.form}
+                field_name="preferred_language"
+                oninput={link.callback(|_| Msg::Update)}>
+                <option value="" selected={current_language.is_empty()}>{"Server default"}</option>
+                {
+                  for EMAIL_LANGUAGES.iter().map(|(tag, name)| html! {
+                    <option value={*tag} selected={*tag == current_language}>{*name}</option>
+                  })
+                }
+                {
+                  for other_language.map(|tag| html! {
+                    <option value={tag.to_owned()} selected=true>{tag}</option>
+                  })
+                }
+              </Select<UserModel>>
+              {self.view_hosts(ctx)}
+              {self.view_address_book(ctx)}
               <div class="form-group row align-items-center mb-3">
                 <label for="avatar"
-                  class="form-label col-4 col-form-label">
+                  class="form-label col-md-4 col-form-label">
                   {"Avatar: "}
                 </label>
-                <div class="col-8">
-                  <div class="row align-items-center">
-                    <div class="col-5">
+                <div class="col-md-8">
+                  <div class="row g-2 align-items-center">
+                    <div class="col-sm-5">
                       <input
+                        ref={self.avatar_input.clone()}
                         class="form-control"
                         id="avatarInput"
                         type="file"
-                        accept="image/jpeg"
+                        accept={AVATAR_TYPES.join(",")}
                         oninput={link.callback(|e: InputEvent| {
                             let input: HtmlInputElement = e.target_unchecked_into();
                             Self::upload_files(input.files())
                         })} />
                     </div>
-                    <div class="col-3">
+                    <div class="col-auto">
                       <button
                         class="btn btn-secondary col-auto"
                         id="avatarClear"
@@ -262,8 +498,17 @@ impl Component for UserDetailsForm {
                         onclick={link.callback(|e: MouseEvent| {e.prevent_default(); Msg::ClearAvatarClicked})}>
                       {"Clear"}
                       </button>
+                      <ConfirmDialog
+                        id="clearAvatarModal"
+                        show={self.show_clear_avatar_confirmation}
+                        title="Clear the avatar?"
+                        confirm_label="Clear"
+                        on_confirm={link.callback(|_| Msg::ConfirmClearAvatar)}
+                        on_cancel={link.callback(|_| Msg::DismissClearAvatar)}>
+                        {"The avatar will be removed when saving the changes."}
+                      </ConfirmDialog>
                     </div>
-                    <div class="col-4">
+                    <div class="col-auto">
                     {
                       if !avatar_string.is_empty() {
                         html!{
@@ -279,9 +524,11 @@ impl Component for UserDetailsForm {
                   </div>
                 </div>
               </div>
+              {self.view_cropper(ctx)}
+              {self.view_attributes(ctx)}
               <Submit
                 text="Save changes"
-                disabled={self.common.is_task_running()}
+                disabled={self.common.is_task_running() || self.cropping.is_some()}
                 onclick={link.callback(|e: MouseEvent| {e.prevent_default(); Msg::SubmitClicked})} />
             </form>
             {
@@ -294,7 +541,14 @@ impl Component for UserDetailsForm {
               } else { html! {} }
             }
             <div hidden={!self.just_updated}>
-              <div class="alert alert-success mt-4">{"User successfully updated!"}</div>
+              <div class="alert alert-success mt-4">
+                {"User successfully updated!"}
+                {if self.pending_email.is_some() {
+                    " The new email address applies once verified with the link sent to it."
+                } else {
+                    ""
+                }}
+              </div>
             </div>
           </div>
         }
@@ -321,27 +575,70 @@ impl UserDetailsForm {
             firstName: None,
             lastName: None,
             avatar: None,
+            preferredLanguage: None,
+            hosts: None,
+            phone: None,
+            mobile: None,
+            postalAddress: None,
+            locality: None,
+            stateOrProvince: None,
+            expiresAt: None,
+            removeExpiration: None,
             removeAttributes: None,
             insertAttributes: None,
+            skipEmailVerification: None,
         };
         let default_user_input = user_input.clone();
         let model = self.form.model();
         let email = model.email;
-        if base_user.email != email {
+        if base_user.email.as_ref() != Some(&email) {
             user_input.email = Some(email);
+            if ctx.props().is_admin && self.skip_email_verification {
+                user_input.skipEmailVerification = Some(true);
+            }
         }
         if base_user.display_name != model.display_name {
             user_input.displayName = Some(model.display_name);
         }
-        if base_user.first_name != model.first_name {
+        if base_user.first_name.as_ref() != Some(&model.first_name) {
             user_input.firstName = Some(model.first_name);
         }
-        if base_user.last_name != model.last_name {
+        if base_user.last_name.as_ref() != Some(&model.last_name) {
             user_input.lastName = Some(model.last_name);
         }
+        if base_user.preferred_language.as_deref().unwrap_or_default() != model.preferred_language {
+            user_input.preferredLanguage = Some(model.preferred_language);
+        }
+        let hosts = split_hosts(&model.hosts);
+        if ctx.props().is_admin && base_user.hosts != hosts {
+            user_input.hosts = Some(hosts);
+        }
+        if base_user.phone.as_deref().unwrap_or_default() != model.phone {
+            user_input.phone = Some(model.phone);
+        }
+        if base_user.mobile.as_deref().unwrap_or_default() != model.mobile {
+            user_input.mobile = Some(model.mobile);
+        }
+        let postal_address = self.postal_address.trim_end();
+        if base_user.postal_address.as_deref().unwrap_or_default() != postal_address {
+            user_input.postalAddress = Some(postal_address.to_owned());
+        }
+        if base_user.locality.as_deref().unwrap_or_default() != model.locality {
+            user_input.locality = Some(model.locality);
+        }
+        if base_user.state_or_province.as_deref().unwrap_or_default() != model.state_or_province {
+            user_input.stateOrProvince = Some(model.state_or_province);
+        }
         if let Some(avatar) = &self.avatar {
             user_input.avatar = Some(to_base64(avatar)?);
         }
+        let (insert_attributes, remove_attributes) = self.changed_attributes(ctx);
+        if !insert_attributes.is_empty() {
+            user_input.insertAttributes = Some(insert_attributes);
+        }
+        if !remove_attributes.is_empty() {
+            user_input.removeAttributes = Some(remove_attributes);
+        }
         // Nothing changed.
         if user_input == default_user_input {
             return Ok(false);
@@ -356,20 +653,279 @@ impl UserDetailsForm {
         Ok(false)
     }
 
-    fn user_update_finished(&mut self, r: Result<update_user::ResponseData>) -> Result<bool> {
-        r?;
+    fn user_update_finished(
+        &mut self,
+        ctx: &Context<Self>,
+        r: Result<update_user::ResponseData>,
+    ) -> Result<bool> {
+        if let Err(e) = r {
+            if let Some(e) = e.downcast_ref::<InvalidFieldError>() {
+                if self.attribute_schema.iter().any(|a| a.name == e.field) {
+                    self.attribute_errors
+                        .insert(e.field.clone(), e.message.clone());
+                }
+            }
+            return Err(e);
+        }
         let model = self.form.model();
-        self.user.email = model.email;
+        if self.user.email.as_ref() != Some(&model.email) {
+            // The new address may be waiting for verification instead.
+            let id = self.user.id.clone();
+            ctx.link().send_future(async {
+                Msg::PendingEmailChangeResponse(
+                    HostService::graphql_query::<GetPendingEmailChange>(
+                        get_pending_email_change::Variables { id },
+                        "Error trying to fetch the email of the user",
+                    )
+                    .await,
+                )
+            });
+        }
         self.user.display_name = model.display_name;
-        self.user.first_name = model.first_name;
-        self.user.last_name = model.last_name;
+        self.user.first_name = Some(model.first_name);
+        self.user.last_name = Some(model.last_name);
+        self.user.preferred_language =
+            Some(model.preferred_language).filter(|language| !language.is_empty());
+        if ctx.props().is_admin {
+            self.user.hosts = split_hosts(&model.hosts);
+        }
+        let non_empty = |value: String| Some(value).filter(|v| !v.is_empty());
+        self.user.phone = non_empty(model.phone);
+        self.user.mobile = non_empty(model.mobile);
+        self.user.postal_address = non_empty(self.postal_address.trim_end().to_owned());
+        self.user.locality = non_empty(model.locality);
+        self.user.state_or_province = non_empty(model.state_or_province);
         if let Some(avatar) = &self.avatar {
             self.user.avatar = Some(to_base64(avatar)?);
         }
+        self.user.attributes = self
+            .attribute_values
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, value)| UserAttribute {
+                name: name.clone(),
+                value: value.clone(),
+            })
+            .collect();
         self.just_updated = true;
         Ok(true)
     }
 
+    /// The custom attributes modified in the form: the ones to insert, and the ones to remove.
+    fn changed_attributes(
+        &self,
+        ctx: &Context<Self>,
+    ) -> (Vec<update_user::AttributeValueInput>, Vec<String>) {
+        let original = attribute_values(&self.user);
+        let mut insert_attributes = Vec::new();
+        let mut remove_attributes = Vec::new();
+        for attribute in &self.attribute_schema {
+            if !ctx.props().is_admin && !attribute.is_editable {
+                continue;
+            }
+            let name = &attribute.name;
+            let value = self.attribute_values.get(name).cloned().unwrap_or_default();
+            if original.get(name).cloned().unwrap_or_default() == value {
+                continue;
+            }
+            if value.is_empty() {
+                remove_attributes.push(name.clone());
+            } else {
+                insert_attributes.push(update_user::AttributeValueInput {
+                    name: name.clone(),
+                    value,
+                });
+            }
+        }
+        (insert_attributes, remove_attributes)
+    }
+
+    fn view_attributes(&self, ctx: &Context<Self>) -> Html {
+        if self.attribute_schema.is_empty() {
+            return html! {};
+        }
+        let link = ctx.link();
+        let is_admin = ctx.props().is_admin;
+        html! {
+          <>
+            <h5 class="fw-bold mt-4 mb-3">{"Attributes"}</h5>
+            {for self.attribute_schema.iter().map(|attribute| {
+                let name = attribute.name.clone();
+                html! {
+                  <AttributeInput
+                    key={name.clone()}
+                    name={name.clone()}
+                    attribute_type={AttributeType::from(attribute.attribute_type.clone())}
+                    is_list={attribute.is_list}
+                    values={self.attribute_values.get(&name).cloned().unwrap_or_default()}
+                    editable={is_admin || attribute.is_editable}
+                    error={self.attribute_errors.get(&name).cloned()}
+                    on_change={link.callback(move |values| Msg::AttributeChanged(name.clone(), values))} />
+                }
+            })}
+          </>
+        }
+    }
+
+    /// The new address waiting for verification, and for the admins the option to skip it.
+    fn view_email_change(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        let pending = match &self.pending_email {
+            None => html! {},
+            Some(PendingEmail { email, expires_at }) => html! {
+              <div class="alert alert-info d-flex align-items-center py-2" id="pendingEmailChange">
+                <span class="me-auto">
+                  {"Waiting for the verification of "}<b>{email}</b>
+                  {format!(": the link sent to this address expires on {}.", expires_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"))}
+                </span>
+                <button
+                  type="button"
+                  class="btn btn-sm btn-outline-secondary ms-2"
+                  disabled={self.common.is_task_running()}
+                  onclick={link.callback(|_| Msg::CancelEmailChangeClicked)}>
+                  {"Cancel the change"}
+                </button>
+              </div>
+            },
+        };
+        let skip = if ctx.props().is_admin {
+            html! {
+              <div class="form-check mb-3">
+                <input
+                  class="form-check-input"
+                  type="checkbox"
+                  id="skipEmailVerification"
+                  checked={self.skip_email_verification}
+                  onchange={link.callback(|e: Event| {
+                    let input: HtmlInputElement = e.target_unchecked_into();
+                    Msg::SetSkipEmailVerification(input.checked())
+                  })} />
+                <label class="form-check-label" for="skipEmailVerification">
+                  {"Change the email right away, without verifying the new address"}
+                </label>
+              </div>
+            }
+        } else {
+            html! {}
+        };
+        html! {
+          <div class="row">
+            <div class="col-md-8 offset-md-4">
+              {pending}
+              {skip}
+            </div>
+          </div>
+        }
+    }
+
+    /// The hosts the user may log into: only the admins can change them.
+    fn view_hosts(&self, ctx: &Context<Self>) -> Html {
+        if ctx.props().is_admin {
+            html! {
+              <Field<UserModel>
+                form={&self.form}
+                label="Hosts"
+                field_name="hosts"
+                oninput={ctx.link().callback(|_| Msg::Update)} />
+            }
+        } else if self.user.hosts.is_empty() {
+            html! {}
+        } else {
+            html! {
+              <StaticValue label="Hosts" id="hosts">
+                {self.user.hosts.join(", ")}
+              </StaticValue>
+            }
+        }
+    }
+
+    /// The phone numbers and the address, which the users can edit themselves.
+    fn view_address_book(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        html! {
+          <>
+            <Field<UserModel>
+              form={&self.form}
+              label="Phone"
+              field_name="phone"
+              input_type="tel"
+              autocomplete="tel"
+              oninput={link.callback(|_| Msg::Update)} />
+            <Field<UserModel>
+              form={&self.form}
+              label="Mobile"
+              field_name="mobile"
+              input_type="tel"
+              autocomplete="tel"
+              oninput={link.callback(|_| Msg::Update)} />
+            <div class="row mb-3">
+              <label for="postalAddress" class="form-label col-md-4 col-form-label">
+                {"Postal address:"}
+              </label>
+              <div class="col-md-8">
+                <textarea
+                  class="form-control"
+                  id="postalAddress"
+                  rows="3"
+                  autocomplete="street-address"
+                  value={self.postal_address.clone()}
+                  oninput={link.callback(|e: InputEvent| {
+                    let input: HtmlTextAreaElement = e.target_unchecked_into();
+                    Msg::PostalAddressChanged(input.value())
+                  })} />
+              </div>
+            </div>
+            <Field<UserModel>
+              form={&self.form}
+              label="City"
+              field_name="locality"
+              autocomplete="address-level2"
+              oninput={link.callback(|_| Msg::Update)} />
+            <Field<UserModel>
+              form={&self.form}
+              label="State or province"
+              field_name="state_or_province"
+              autocomplete="address-level1"
+              oninput={link.callback(|_| Msg::Update)} />
+          </>
+        }
+    }
+
+    fn view_cropper(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        match &self.cropping {
+            None => html! {},
+            Some((_, url)) => html! {
+              <div class="row">
+                <div class="col-md-8 offset-md-4">
+                  <AvatarCropper
+                    src={url.to_string()}
+                    max_dimension={self.avatar_limits.max_dimension.max(1) as u32}
+                    max_size_kb={self.avatar_limits.max_size_kb.max(1) as u32}
+                    on_cropped={link.callback(Msg::AvatarCropped)}
+                    on_cancel={link.callback(|_| Msg::CropCancelled)}
+                    on_error={link.callback(Msg::CropFailed)} />
+                </div>
+              </div>
+            },
+        }
+    }
+
+    /// Whether an avatar is shown, saved or picked.
+    fn has_avatar(&self) -> bool {
+        match &self.avatar {
+            Some(avatar) => avatar.contents.is_some(),
+            None => self.user.avatar.as_deref().is_some_and(|a| !a.is_empty()),
+        }
+    }
+
+    /// Allows picking the same file again.
+    fn clear_avatar_input(&self) {
+        if let Some(input) = self.avatar_input.cast::<HtmlInputElement>() {
+            input.set_value("");
+        }
+    }
+
     fn upload_files(files: Option<FileList>) -> Msg {
         if let Some(files) = files {
             if files.length() > 0 {
@@ -383,6 +939,26 @@ impl UserDetailsForm {
     }
 }
 
+/// The hosts typed in the form, separated by commas or spaces, normalized like the server does.
+fn split_hosts(hosts: &str) -> Vec<String> {
+    let mut hosts = hosts
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|host| !host.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>();
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+/// The values of the custom attributes of the user, by attribute name.
+fn attribute_values(user: &User) -> HashMap<String, Vec<String>> {
+    user.attributes
+        .iter()
+        .map(|a| (a.name.clone(), a.value.clone()))
+        .collect()
+}
+
 fn is_valid_jpeg(bytes: &[u8]) -> bool {
     image::io::Reader::with_format(std::io::Cursor::new(bytes), image::ImageFormat::Jpeg)
         .decode()
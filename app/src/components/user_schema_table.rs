@@ -115,7 +115,7 @@ impl UserSchemaTable {
             html! {
                 <div class="table-responsive">
                     <h3>{if hardcoded {"Hardcoded"} else {"User-defined"}}{" attributes"}</h3>
-                    <table class="table table-hover">
+                    <table class="table table-hover table-cards">
                         <thead>
                             <tr>
                                 <th>{"Attribute name"}</th>
@@ -153,10 +153,10 @@ impl UserSchemaTable {
         let hardcoded = ctx.props().hardcoded;
         html! {
             <tr key={attribute.name.clone()}>
-                <td>{&attribute.name}</td>
-                <td>{if attribute.is_list { format!("List<{attribute_type}>")} else {attribute_type.to_string()}}</td>
-                <td>{if attribute.is_editable {checkmark.clone()} else {html!{}}}</td>
-                <td>{if attribute.is_visible {checkmark.clone()} else {html!{}}}</td>
+                <td data-label="Attribute name">{&attribute.name}</td>
+                <td data-label="Type">{if attribute.is_list { format!("List<{attribute_type}>")} else {attribute_type.to_string()}}</td>
+                <td data-label="Editable">{if attribute.is_editable {checkmark.clone()} else {html!{}}}</td>
+                <td data-label="Visible">{if attribute.is_visible {checkmark.clone()} else {html!{}}}</td>
                 {
                     if hardcoded {
                         html!{}
@@ -1,13 +1,26 @@
 use crate::{
     components::{
         delete_user::DeleteUser,
+        import_users::ImportUsers,
         router::{AppRoute, Link},
+        undo_toast::UndoToast,
+    },
+    infra::{
+        api::HostService,
+        common_component::{CommonComponent, CommonComponentParts},
     },
-    infra::common_component::{CommonComponent, CommonComponentParts},
 };
 use anyhow::{Error, Result};
+use gloo_timers::callback::Timeout;
 use graphql_client::GraphQLQuery;
+use serde::{Deserialize, Serialize};
+use web_sys::{HtmlInputElement, HtmlSelectElement};
 use yew::prelude::*;
+use yew_router::{
+    history::HistoryListener,
+    prelude::{History, Location},
+    scope_ext::RouterScopeExt,
+};
 
 #[derive(GraphQLQuery)]
 #[graphql(
@@ -18,32 +31,363 @@ use yew::prelude::*;
 )]
 pub struct ListUsersQuery;
 
-use list_users_query::{RequestFilter, ResponseData};
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/restore_user.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql"
+)]
+pub struct RestoreUser;
+
+use list_users_query::{RequestFilter, ResponseData, UserSortField};
+
+type User = list_users_query::ListUsersQueryUsersPageUsers;
+
+const PAGE_SIZES: [usize; 4] = [10, 25, 50, 100];
+const DEFAULT_PAGE_SIZE: usize = 25;
+/// How long to wait after the last key stroke before searching.
+const SEARCH_DEBOUNCE_MS: u32 = 300;
+/// The "Expiring soon" filter shows the accounts expiring within that many days.
+const EXPIRING_SOON_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SortColumn {
+    UserId,
+    Email,
+    DisplayName,
+    CreationDate,
+}
 
-type User = list_users_query::ListUsersQueryUsers;
+/// The columns offered in the sort selector, which replaces the column headers on narrow screens.
+const SORT_COLUMNS: [(SortColumn, &str); 4] = [
+    (SortColumn::UserId, "User ID"),
+    (SortColumn::Email, "Email"),
+    (SortColumn::DisplayName, "Display name"),
+    (SortColumn::CreationDate, "Creation date"),
+];
+
+impl SortColumn {
+    fn to_graphql(self) -> UserSortField {
+        match self {
+            SortColumn::UserId => UserSortField::USER_ID,
+            SortColumn::Email => UserSortField::EMAIL,
+            SortColumn::DisplayName => UserSortField::DISPLAY_NAME,
+            SortColumn::CreationDate => UserSortField::CREATION_DATE,
+        }
+    }
+}
+
+fn default_page_size() -> usize {
+    DEFAULT_PAGE_SIZE
+}
+
+fn is_default_page_size(size: &usize) -> bool {
+    *size == DEFAULT_PAGE_SIZE
+}
+
+fn is_zero(page: &usize) -> bool {
+    *page == 0
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// What is displayed, stored in the query string of the URL so that reloading the page and the
+/// back button work.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ListState {
+    /// 0-based.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    page: usize,
+    #[serde(
+        default = "default_page_size",
+        skip_serializing_if = "is_default_page_size"
+    )]
+    size: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sort: Option<SortColumn>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    desc: bool,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    search: String,
+    /// Only the accounts expiring within `EXPIRING_SOON_DAYS`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    expiring: bool,
+}
+
+impl Default for ListState {
+    fn default() -> Self {
+        ListState {
+            page: 0,
+            size: DEFAULT_PAGE_SIZE,
+            sort: None,
+            desc: false,
+            search: String::new(),
+            expiring: false,
+        }
+    }
+}
+
+impl ListState {
+    fn from_location(ctx: &Context<UserTable>) -> Self {
+        let mut state = ctx
+            .link()
+            .location()
+            .and_then(|l| l.query::<ListState>().ok())
+            .unwrap_or_default();
+        if !PAGE_SIZES.contains(&state.size) {
+            state.size = DEFAULT_PAGE_SIZE;
+        }
+        state
+    }
+
+    fn filters(&self) -> Option<RequestFilter> {
+        let empty_filter = || RequestFilter {
+            any: None,
+            all: None,
+            not: None,
+            eq: None,
+            member_of: None,
+            member_of_id: None,
+            last_login_before: None,
+            password_changed_before: None,
+            expires_before: None,
+            search: None,
+        };
+        let search = self.search.trim();
+        let mut filters = Vec::new();
+        if !search.is_empty() {
+            filters.push(RequestFilter {
+                search: Some(search.to_owned()),
+                ..empty_filter()
+            });
+        }
+        if self.expiring {
+            filters.push(RequestFilter {
+                expires_before: Some(
+                    chrono::Utc::now() + chrono::Duration::days(EXPIRING_SOON_DAYS),
+                ),
+                ..empty_filter()
+            });
+        }
+        match filters.len() {
+            0 => None,
+            1 => filters.pop(),
+            _ => Some(RequestFilter {
+                all: Some(filters),
+                ..empty_filter()
+            }),
+        }
+    }
+}
 
 pub struct UserTable {
     common: CommonComponentParts<Self>,
+    state: ListState,
+    /// The content of the search box, which is searched once the user stops typing.
+    search_input: String,
+    search_timeout: Option<Timeout>,
     users: Option<Vec<User>>,
+    total_count: usize,
+    /// The state changed while a request was running: fetch the users again when it's done.
+    refetch: bool,
+    /// The CSV file being imported.
+    import_file: Option<web_sys::File>,
+    /// Counts the imports, to start the wizard over when another file is picked.
+    import_count: usize,
+    /// The last deleted user, that can be restored from the undo toast.
+    deleted_user: Option<String>,
+    /// Counts the deletions, to restart the toast timer on each one.
+    deletion_count: usize,
+    _history_listener: Option<HistoryListener>,
 }
 
 pub enum Msg {
     ListUsersResponse(Result<ResponseData>),
+    LocationChanged,
+    SearchInput(String),
+    Search,
+    SetExpiring(bool),
+    SortBy(SortColumn),
+    SetSort(Option<(SortColumn, bool)>),
+    GoToPage(usize),
+    SetPageSize(usize),
     OnUserDeleted(String),
+    UndoDelete,
+    DismissUndo,
+    RestoreUserResponse(Result<restore_user::ResponseData>),
+    ImportFile(Option<web_sys::File>),
+    ImportClosed,
     OnError(Error),
 }
 
 impl CommonComponent<UserTable> for UserTable {
-    fn handle_msg(&mut self, _: &Context<Self>, msg: <Self as Component>::Message) -> Result<bool> {
+    fn handle_msg(
+        &mut self,
+        ctx: &Context<Self>,
+        msg: <Self as Component>::Message,
+    ) -> Result<bool> {
         match msg {
-            Msg::ListUsersResponse(users) => {
-                self.users = Some(users?.users.into_iter().collect());
+            Msg::ListUsersResponse(response) => {
+                if self.refetch {
+                    // Outdated.
+                    self.refetch = false;
+                    self.get_users(ctx);
+                    return Ok(false);
+                }
+                let page = response?.users_page;
+                self.total_count = page.total_count as usize;
+                self.users = Some(page.users);
+                // The page doesn't exist anymore, e.g. after deleting its last user.
+                if self.users.as_ref().unwrap().is_empty()
+                    && self.state.page > 0
+                    && self.total_count > 0
+                {
+                    self.push_state(
+                        ctx,
+                        ListState {
+                            page: self.last_page(),
+                            ..self.state.clone()
+                        },
+                    );
+                }
+                Ok(true)
+            }
+            Msg::LocationChanged => {
+                let state = ListState::from_location(ctx);
+                if state != self.state {
+                    self.search_input = state.search.clone();
+                    self.state = state;
+                    self.get_users(ctx);
+                }
+                Ok(true)
+            }
+            Msg::SearchInput(search) => {
+                self.search_input = search;
+                let link = ctx.link().clone();
+                self.search_timeout = Some(Timeout::new(SEARCH_DEBOUNCE_MS, move || {
+                    link.send_message(Msg::Search)
+                }));
+                Ok(true)
+            }
+            Msg::Search => {
+                self.search_timeout = None;
+                if self.search_input != self.state.search {
+                    self.push_state(
+                        ctx,
+                        ListState {
+                            page: 0,
+                            search: self.search_input.clone(),
+                            ..self.state.clone()
+                        },
+                    );
+                }
+                Ok(false)
+            }
+            Msg::SetExpiring(expiring) => {
+                self.push_state(
+                    ctx,
+                    ListState {
+                        page: 0,
+                        expiring,
+                        ..self.state.clone()
+                    },
+                );
+                Ok(false)
+            }
+            Msg::SortBy(column) => {
+                let desc = self.state.sort == Some(column) && !self.state.desc;
+                self.push_state(
+                    ctx,
+                    ListState {
+                        page: 0,
+                        sort: Some(column),
+                        desc,
+                        ..self.state.clone()
+                    },
+                );
+                Ok(false)
+            }
+            Msg::SetSort(sort) => {
+                self.push_state(
+                    ctx,
+                    ListState {
+                        page: 0,
+                        sort: sort.map(|(column, _)| column),
+                        desc: sort.map(|(_, desc)| desc).unwrap_or_default(),
+                        ..self.state.clone()
+                    },
+                );
+                Ok(false)
+            }
+            Msg::GoToPage(page) => {
+                self.push_state(
+                    ctx,
+                    ListState {
+                        page,
+                        ..self.state.clone()
+                    },
+                );
+                Ok(false)
+            }
+            Msg::SetPageSize(size) => {
+                self.push_state(
+                    ctx,
+                    ListState {
+                        page: 0,
+                        size,
+                        ..self.state.clone()
+                    },
+                );
+                Ok(false)
+            }
+            Msg::ImportFile(file) => {
+                if file.is_some() {
+                    self.import_file = file;
+                    self.import_count += 1;
+                }
+                Ok(true)
+            }
+            Msg::ImportClosed => {
+                self.import_file = None;
+                self.get_users(ctx);
                 Ok(true)
             }
             Msg::OnError(e) => Err(e),
             Msg::OnUserDeleted(user_id) => {
                 debug_assert!(self.users.is_some());
                 self.users.as_mut().unwrap().retain(|u| u.id != user_id);
+                self.total_count = self.total_count.saturating_sub(1);
+                self.deleted_user = Some(user_id);
+                self.deletion_count += 1;
+                // Bring the next user onto this page.
+                self.get_users(ctx);
+                Ok(true)
+            }
+            Msg::UndoDelete => {
+                if let Some(user_id) = self.deleted_user.take() {
+                    ctx.link().send_future(async move {
+                        Msg::RestoreUserResponse(
+                            HostService::graphql_query::<RestoreUser>(
+                                restore_user::Variables { user_id },
+                                "Error trying to restore the user",
+                            )
+                            .await,
+                        )
+                    });
+                }
+                Ok(true)
+            }
+            Msg::DismissUndo => {
+                self.deleted_user = None;
+                Ok(true)
+            }
+            Msg::RestoreUserResponse(response) => {
+                response?;
+                self.get_users(ctx);
                 Ok(true)
             }
         }
@@ -55,14 +399,41 @@ impl CommonComponent<UserTable> for UserTable {
 }
 
 impl UserTable {
-    fn get_users(&mut self, ctx: &Context<Self>, req: Option<RequestFilter>) {
+    fn get_users(&mut self, ctx: &Context<Self>) {
+        if self.common.is_task_running() {
+            self.refetch = true;
+            return;
+        }
         self.common.call_graphql::<ListUsersQuery, _>(
             ctx,
-            list_users_query::Variables { filters: req },
+            list_users_query::Variables {
+                filters: self.state.filters(),
+                order_by: self.state.sort.map(SortColumn::to_graphql),
+                descending: self.state.sort.map(|_| self.state.desc),
+                offset: (self.state.page * self.state.size) as i64,
+                limit: self.state.size as i64,
+            },
             Msg::ListUsersResponse,
             "Error trying to fetch users",
         );
     }
+
+    /// Changes the URL, which updates the list through the history listener.
+    fn push_state(&self, ctx: &Context<Self>, state: ListState) {
+        if let Some(history) = ctx.link().history() {
+            if let Err(e) = history.push_with_query(AppRoute::ListUsers, state) {
+                gloo_console::error!(format!("Could not update the URL: {}", e));
+            }
+        }
+    }
+
+    fn last_page(&self) -> usize {
+        self.total_count.saturating_sub(1) / self.state.size
+    }
+
+    fn is_loading(&self) -> bool {
+        self.common.is_task_running()
+    }
 }
 
 impl Component for UserTable {
@@ -70,11 +441,25 @@ impl Component for UserTable {
     type Properties = ();
 
     fn create(ctx: &Context<Self>) -> Self {
+        let state = ListState::from_location(ctx);
+        let history_listener = ctx
+            .link()
+            .add_history_listener(ctx.link().callback(|_| Msg::LocationChanged));
         let mut table = UserTable {
             common: CommonComponentParts::<Self>::create(),
+            search_input: state.search.clone(),
+            state,
+            search_timeout: None,
             users: None,
+            total_count: 0,
+            refetch: false,
+            import_file: None,
+            import_count: 0,
+            deleted_user: None,
+            deletion_count: 0,
+            _history_listener: history_listener,
         };
-        table.get_users(ctx, None);
+        table.get_users(ctx);
         table
     }
 
@@ -83,9 +468,27 @@ impl Component for UserTable {
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
         html! {
-            <div>
+            <div
+              ondragover={Callback::from(|e: DragEvent| e.prevent_default())}
+              ondrop={link.callback(|e: DragEvent| {
+                e.prevent_default();
+                Msg::ImportFile(e.data_transfer().and_then(|d| d.files()).and_then(|f| f.get(0)))
+              })}>
+              {self.view_toolbar(ctx)}
+              {match &self.import_file {
+                  None => html! {},
+                  Some(file) => html! {
+                    <ImportUsers
+                      key={self.import_count}
+                      file={file.clone()}
+                      on_close={link.callback(|_| Msg::ImportClosed)} />
+                  },
+              }}
               {self.view_users(ctx)}
+              {self.view_pagination(ctx)}
+              {self.view_undo_toast(ctx)}
               {self.view_errors()}
             </div>
         }
@@ -93,19 +496,143 @@ impl Component for UserTable {
 }
 
 impl UserTable {
+    fn view_toolbar(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        html! {
+          <div class="row g-2 mb-3 align-items-center">
+            <div class="col-lg-7">
+              <div class="input-group">
+                <span class="input-group-text"><i class="bi-search"></i></span>
+                <input
+                  type="search"
+                  class="form-control"
+                  id="userSearch"
+                  placeholder="Search by user ID, email or display name"
+                  aria-label="Search users"
+                  value={self.search_input.clone()}
+                  oninput={link.callback(|e: InputEvent| {
+                    let input: HtmlInputElement = e.target_unchecked_into();
+                    Msg::SearchInput(input.value())
+                  })} />
+              </div>
+            </div>
+            <div class="col-lg-5 d-flex flex-wrap gap-2 align-items-center justify-content-lg-end">
+              <div class="form-check form-switch text-nowrap mb-0">
+                <input
+                  type="checkbox"
+                  class="form-check-input"
+                  id="expiringSoon"
+                  checked={self.state.expiring}
+                  onchange={link.callback(|e: Event| {
+                    let input: HtmlInputElement = e.target_unchecked_into();
+                    Msg::SetExpiring(input.checked())
+                  })} />
+                <label class="form-check-label" for="expiringSoon">
+                  {format!("Expiring within {} days", EXPIRING_SOON_DAYS)}
+                </label>
+              </div>
+              {self.view_sort_select(ctx)}
+              <label
+                class="btn btn-outline-secondary text-nowrap"
+                title="Or drop a CSV file on the list">
+                <i class="bi-file-earmark-arrow-up me-2"></i>
+                {"Import from CSV"}
+                <input
+                  type="file"
+                  class="d-none"
+                  accept=".csv,text/csv"
+                  onchange={link.callback(|e: Event| {
+                    let input: HtmlInputElement = e.target_unchecked_into();
+                    let file = input.files().and_then(|f| f.get(0));
+                    // Allows picking the same file again.
+                    input.set_value("");
+                    Msg::ImportFile(file)
+                  })} />
+              </label>
+              <label class="text-nowrap" for="pageSize">{"Per page"}</label>
+              <select
+                class="form-select w-auto"
+                id="pageSize"
+                onchange={link.callback(|e: Event| {
+                  let select: HtmlSelectElement = e.target_unchecked_into();
+                  Msg::SetPageSize(select.value().parse().unwrap_or(DEFAULT_PAGE_SIZE))
+                })}>
+                {for PAGE_SIZES.iter().map(|size| html! {
+                  <option value={size.to_string()} selected={*size == self.state.size}>
+                    {size}
+                  </option>
+                })}
+              </select>
+            </div>
+          </div>
+        }
+    }
+
+    fn view_sort_select(&self, ctx: &Context<Self>) -> Html {
+        let selected = self.state.sort.map(|column| (column, self.state.desc));
+        html! {
+          <select
+            class="form-select w-auto d-md-none"
+            id="sortBy"
+            aria-label="Sort by"
+            onchange={ctx.link().callback(|e: Event| {
+              let select: HtmlSelectElement = e.target_unchecked_into();
+              let index = select.selected_index();
+              Msg::SetSort(
+                usize::try_from(index - 1)
+                  .ok()
+                  .and_then(|i| SORT_COLUMNS.get(i / 2))
+                  .map(|(column, _)| (*column, index % 2 == 0)),
+              )
+            })}>
+            <option selected={selected.is_none()}>{"Default order"}</option>
+            {for SORT_COLUMNS.iter().flat_map(|&(column, label)| {
+              [false, true].into_iter().map(move |desc| html! {
+                <option selected={selected == Some((column, desc))}>
+                  {format!("{} {}", label, if desc { "↓" } else { "↑" })}
+                </option>
+              })
+            })}
+          </select>
+        }
+    }
+
+    fn view_sort_header(&self, ctx: &Context<Self>, column: SortColumn, label: &str) -> Html {
+        let icon = match (self.state.sort == Some(column), self.state.desc) {
+            (false, _) => "bi-arrow-down-up text-muted",
+            (true, false) => "bi-caret-up-fill",
+            (true, true) => "bi-caret-down-fill",
+        };
+        let aria_sort = match (self.state.sort == Some(column), self.state.desc) {
+            (false, _) => "none",
+            (true, false) => "ascending",
+            (true, true) => "descending",
+        };
+        html! {
+          <th aria-sort={aria_sort}>
+            <button
+              class="btn btn-link p-0 fw-bold text-reset text-decoration-none text-nowrap"
+              onclick={ctx.link().callback(move |_| Msg::SortBy(column))}>
+              {label}
+              <i class={classes!(icon, "ms-1", "small")}></i>
+            </button>
+          </th>
+        }
+    }
+
     fn view_users(&self, ctx: &Context<Self>) -> Html {
         let make_table = |users: &Vec<User>| {
             html! {
                 <div class="table-responsive">
-                  <table class="table table-hover">
+                  <table class={classes!("table", "table-hover", "table-cards", self.is_loading().then_some("opacity-50"))}>
                     <thead>
                       <tr>
-                        <th>{"User ID"}</th>
-                        <th>{"Email"}</th>
-                        <th>{"Display name"}</th>
+                        {self.view_sort_header(ctx, SortColumn::UserId, "User ID")}
+                        {self.view_sort_header(ctx, SortColumn::Email, "Email")}
+                        {self.view_sort_header(ctx, SortColumn::DisplayName, "Display name")}
                         <th>{"First name"}</th>
                         <th>{"Last name"}</th>
-                        <th>{"Creation date"}</th>
+                        {self.view_sort_header(ctx, SortColumn::CreationDate, "Creation date")}
                         <th>{"Delete"}</th>
                       </tr>
                     </thead>
@@ -117,21 +644,95 @@ impl UserTable {
             }
         };
         match &self.users {
-            None => html! {{"Loading..."}},
+            None => html! {
+              <div class="text-center text-muted py-5">
+                <div class="spinner-border mb-2" role="status"></div>
+                <div>{"Loading users..."}</div>
+              </div>
+            },
+            Some(users) if users.is_empty() && !self.is_loading() => {
+                if self.state.search.trim().is_empty() && self.state.expiring {
+                    html! {
+                      <div class="text-center text-muted py-5">
+                        {format!("No user expires within {} days.", EXPIRING_SOON_DAYS)}
+                      </div>
+                    }
+                } else if self.state.search.trim().is_empty() {
+                    html! {
+                      <div class="text-center text-muted py-5">{"There are no users yet."}</div>
+                    }
+                } else {
+                    html! {
+                      <div class="text-center text-muted py-5">
+                        {format!("No user matches \"{}\".", self.state.search.trim())}
+                      </div>
+                    }
+                }
+            }
             Some(users) => make_table(users),
         }
     }
 
+    fn view_pagination(&self, ctx: &Context<Self>) -> Html {
+        if self.total_count == 0 {
+            return html! {};
+        }
+        let link = ctx.link();
+        let page = self.state.page;
+        let first = page * self.state.size + 1;
+        let last = std::cmp::min((page + 1) * self.state.size, self.total_count);
+        let has_previous = page > 0;
+        let has_next = page < self.last_page();
+        html! {
+          <nav class="d-flex flex-wrap align-items-center justify-content-between mb-3" aria-label="Pages of users">
+            <span class="text-muted">
+              {format!("{}–{} of {} users", first, last, self.total_count)}
+            </span>
+            <ul class="pagination mb-0">
+              <li class={classes!("page-item", (!has_previous).then_some("disabled"))}>
+                <button
+                  class="page-link"
+                  disabled={!has_previous}
+                  onclick={link.callback(move |_| Msg::GoToPage(page.saturating_sub(1)))}>
+                  {"Previous"}
+                </button>
+              </li>
+              <li class="page-item disabled">
+                <span class="page-link">{format!("Page {} of {}", page + 1, self.last_page() + 1)}</span>
+              </li>
+              <li class={classes!("page-item", (!has_next).then_some("disabled"))}>
+                <button
+                  class="page-link"
+                  disabled={!has_next}
+                  onclick={link.callback(move |_| Msg::GoToPage(page + 1))}>
+                  {"Next"}
+                </button>
+              </li>
+            </ul>
+          </nav>
+        }
+    }
+
     fn view_user(&self, ctx: &Context<Self>, user: &User) -> Html {
         let link = &ctx.link();
         html! {
           <tr key={user.id.clone()}>
-              <td><Link to={AppRoute::UserDetails{user_id: user.id.clone()}}>{&user.id}</Link></td>
-              <td>{&user.email}</td>
-              <td>{&user.display_name}</td>
-              <td>{&user.first_name}</td>
-              <td>{&user.last_name}</td>
-              <td>{&user.creation_date.naive_local().date()}</td>
+              <td data-label="User ID">
+                <Link to={AppRoute::UserDetails{user_id: user.id.clone()}}>{&user.id}</Link>
+                {match &user.expires_at {
+                    None => html! {},
+                    Some(date) => html! {
+                      <span class="badge text-bg-warning ms-2" title="The account is disabled on that date">
+                        {format!("Expires {}", date.with_timezone(&chrono::Local).naive_local().date())}
+                      </span>
+                    },
+                }}
+              </td>
+              <td data-label="Email">{user.email.as_deref().unwrap_or_default()}</td>
+              <td data-label="Display name">{&user.display_name}</td>
+              <td class="card-secondary" data-label="First name">{user.first_name.as_deref().unwrap_or_default()}</td>
+              <td class="card-secondary" data-label="Last name">{user.last_name.as_deref().unwrap_or_default()}</td>
+              <td class="card-secondary" data-label="Creation date">{&user.creation_date.with_timezone(&chrono::Local).naive_local().date()}</td>
               <td>
                 <DeleteUser
                   username={user.id.clone()}
@@ -142,6 +743,20 @@ impl UserTable {
         }
     }
 
+    fn view_undo_toast(&self, ctx: &Context<Self>) -> Html {
+        let link = ctx.link();
+        match &self.deleted_user {
+            None => html! {},
+            Some(user_id) => html! {
+              <UndoToast
+                key={self.deletion_count}
+                message={format!("User {} deleted.", user_id)}
+                on_undo={link.callback(|_| Msg::UndoDelete)}
+                on_dismiss={link.callback(|_| Msg::DismissUndo)} />
+            },
+        }
+    }
+
     fn view_errors(&self) -> Html {
         match &self.common.error {
             None => html! {},
@@ -0,0 +1,91 @@
+use crate::{
+    components::router::{AppRoute, Link},
+    infra::{
+        api::HostService,
+        common_component::{CommonComponent, CommonComponentParts},
+    },
+};
+use anyhow::Result;
+use yew::prelude::*;
+
+/// The page of the link sent to verify a new email address: opening it applies the change.
+pub struct VerifyEmail {
+    common: CommonComponentParts<Self>,
+    verified: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Properties)]
+pub struct Props {
+    pub token: String,
+}
+
+pub enum Msg {
+    VerifyResponse(Result<()>),
+}
+
+impl CommonComponent<VerifyEmail> for VerifyEmail {
+    fn handle_msg(&mut self, _: &Context<Self>, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::VerifyResponse(response) => {
+                response?;
+                self.verified = true;
+                Ok(true)
+            }
+        }
+    }
+
+    fn mut_common(&mut self) -> &mut CommonComponentParts<Self> {
+        &mut self.common
+    }
+}
+
+impl Component for VerifyEmail {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let mut component = VerifyEmail {
+            common: CommonComponentParts::<Self>::create(),
+            verified: false,
+        };
+        let token = ctx.props().token.clone();
+        component
+            .common
+            .call_backend(ctx, HostService::verify_email(token), Msg::VerifyResponse);
+        component
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        CommonComponentParts::<Self>::update(self, ctx, msg)
+    }
+
+    fn view(&self, _: &Context<Self>) -> Html {
+        let status = match (&self.common.error, self.verified) {
+            (Some(e), _) => html! {
+              <div class="alert alert-danger">
+                {e.to_string()}
+              </div>
+            },
+            (None, true) => html! {
+              <div class="alert alert-success">
+                {"Your new email address is verified, and is now the one of your account."}
+              </div>
+            },
+            (None, false) => html! {
+              {"Verifying the email address"}
+            },
+        };
+        html! {
+          <>
+            <h2>{"Email verification"}</h2>
+            {status}
+            <Link
+              classes="btn-link btn"
+              disabled={self.common.is_task_running()}
+              to={AppRoute::Index}>
+              {"Back"}
+            </Link>
+          </>
+        }
+    }
+}
@@ -10,6 +10,22 @@ use web_sys::RequestCredentials;
 #[derive(Default)]
 pub struct HostService {}
 
+/// A GraphQL error about one field of the input, e.g. the invalid value of an attribute, to be
+/// shown next to that field. Returned in the [`anyhow::Error`] of [`HostService::graphql_query`].
+#[derive(Debug)]
+pub struct InvalidFieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for InvalidFieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for InvalidFieldError {}
+
 fn get_claims_from_jwt(jwt: &str) -> Result<JWTClaims> {
     use jwt::*;
     let token = Token::<header::Header, JWTClaims, token::Unverified>::parse_unverified(jwt)?;
@@ -54,6 +70,61 @@ async fn call_server<Body: Serialize>(
     }
 }
 
+/// Like [`call_server`], but the requests that the server rejected before running them (e.g. too
+/// large) are also answered with GraphQL errors: those are returned, to be reported like the
+/// others.
+async fn call_graphql_server<Body: Serialize>(
+    body: Body,
+    error_message: &'static str,
+) -> Result<String> {
+    let response = Request::new(&(base_url() + "/api/graphql"))
+        .header("Content-Type", "application/json")
+        .credentials(RequestCredentials::SameOrigin)
+        .body(serde_json::to_string(&body)?)
+        .method(Method::POST)
+        .send()
+        .await?;
+    let text = response.text().await?;
+    let has_graphql_errors = || {
+        serde_json::from_str::<graphql_client::Response<serde_json::Value>>(&text)
+            .is_ok_and(|r| r.errors.is_some())
+    };
+    if response.ok() || has_graphql_errors() {
+        Ok(text)
+    } else {
+        Err(anyhow!(
+            "{}[{} {}]: {}",
+            error_message,
+            response.status(),
+            response.status_text(),
+            text
+        ))
+    }
+}
+
+/// A message for the users, from the "code" extension of a GraphQL error. The codes that aren't
+/// listed keep the message of the server, which is specific enough.
+fn friendly_error_message(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "PAYLOAD_TOO_LARGE" => "The request is too large: try with a smaller picture",
+        "PERMISSION_DENIED" => "You don't have the permission to do that",
+        "SERVER_BUSY" => "The server is busy, please try again in a few seconds",
+        "INTERNAL_ERROR" => "Something went wrong on the server",
+        "INVALID_REQUEST" => "The server could not understand the request",
+        _ => return None,
+    })
+}
+
+fn describe_error(error: &graphql_client::Error) -> String {
+    error
+        .extensions
+        .as_ref()
+        .and_then(|e| e.get("code")?.as_str())
+        .and_then(friendly_error_message)
+        .map(str::to_owned)
+        .unwrap_or_else(|| error.to_string())
+}
+
 async fn call_server_json_with_error_message<CallbackResult, Body: Serialize>(
     url: &str,
     request: RequestType<Body>,
@@ -77,13 +148,24 @@ async fn call_server_empty_response_with_error_message<Body: Serialize>(
 fn set_cookies_from_jwt(response: login::ServerLoginResponse) -> Result<(String, bool)> {
     let jwt_claims = get_claims_from_jwt(response.token.as_str()).context("Could not parse JWT")?;
     let is_admin = jwt_claims.groups.contains("lldap_admin");
-    set_cookie("user_id", &jwt_claims.user, &jwt_claims.exp)
-        .map(|_| set_cookie("is_admin", &is_admin.to_string(), &jwt_claims.exp))
+    // The cookies of a session without "remember me" are dropped when the browser is closed.
+    let expiration = (!response.session_only).then_some(&jwt_claims.exp);
+    set_cookie("user_id", &jwt_claims.user, expiration)
+        .map(|_| set_cookie("is_admin", &is_admin.to_string(), expiration))
         .map(|_| (jwt_claims.user.clone(), is_admin))
         .context("Error setting cookie")
 }
 
 impl HostService {
+    /// Served with the session cookie, and cached by the browser until the avatar changes.
+    pub fn avatar_url(user_id: &str) -> String {
+        format!(
+            "{}/avatars/{}",
+            base_url(),
+            url_escape::encode_component(user_id)
+        )
+    }
+
     pub async fn graphql_query<QueryType>(
         variables: QueryType::Variables,
         error_message: &'static str,
@@ -93,25 +175,43 @@ impl HostService {
     {
         let unwrap_graphql_response = |graphql_client::Response { data, errors }| {
             data.ok_or_else(|| {
+                let errors = errors.unwrap_or_default();
+                if let [error] = errors.as_slice() {
+                    if let Some(field) = error
+                        .extensions
+                        .as_ref()
+                        .and_then(|e| e.get("field")?.as_str())
+                    {
+                        return InvalidFieldError {
+                            field: field.to_owned(),
+                            message: error.message.clone(),
+                        }
+                        .into();
+                    }
+                }
+                // The ID of the request in the server logs, for the support.
+                let request_id = errors
+                    .iter()
+                    .filter_map(|e| e.extensions.as_ref()?.get("requestId")?.as_str())
+                    .next()
+                    .map(|id| format!(" (error id: {})", id))
+                    .unwrap_or_default();
                 anyhow!(
-                    "Errors: [{}]",
+                    "Errors: [{}]{}",
                     errors
-                        .unwrap_or_default()
                         .iter()
-                        .map(ToString::to_string)
+                        .map(describe_error)
                         .collect::<Vec<_>>()
-                        .join(", ")
+                        .join(", "),
+                    request_id
                 )
             })
         };
         let request_body = QueryType::build_query(variables);
-        call_server_json_with_error_message::<graphql_client::Response<_>, _>(
-            &(base_url() + "/api/graphql"),
-            RequestType::Post(request_body),
-            error_message,
-        )
-        .await
-        .and_then(unwrap_graphql_response)
+        let response = call_graphql_server(request_body, error_message).await?;
+        serde_json::from_str::<graphql_client::Response<_>>(&response)
+            .context("Could not parse response")
+            .and_then(unwrap_graphql_response)
     }
 
     pub async fn login_start(
@@ -135,6 +235,15 @@ impl HostService {
         .and_then(set_cookies_from_jwt)
     }
 
+    pub async fn get_login_options() -> Result<login::LoginOptions> {
+        call_server_json_with_error_message(
+            &(base_url() + "/auth/login_options"),
+            GET_REQUEST,
+            "Could not fetch the login options",
+        )
+        .await
+    }
+
     pub async fn register_start(
         request: registration::ClientRegistrationStartRequest,
     ) -> Result<Box<registration::ServerRegistrationStartResponse>> {
@@ -201,6 +310,24 @@ impl HostService {
         .await
     }
 
+    pub async fn verify_email(token: String) -> Result<()> {
+        call_server_empty_response_with_error_message(
+            &format!("{}/auth/email/verify/{}", base_url(), token),
+            RequestType::Post(""),
+            "Could not verify the email address",
+        )
+        .await
+    }
+
+    pub async fn get_password_policy() -> Result<lldap_auth::password_policy::PasswordPolicy> {
+        call_server_json_with_error_message(
+            &(base_url() + "/api/password_policy"),
+            GET_REQUEST,
+            "Could not fetch the password policy",
+        )
+        .await
+    }
+
     pub async fn probe_password_reset() -> Result<bool> {
         Ok(gloo_net::http::Request::get(
             &(base_url() + "/auth/reset/step1/lldap_unlikely_very_long_user_name"),
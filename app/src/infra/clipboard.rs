@@ -0,0 +1,9 @@
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+extern "C" {
+    /// Copies the text to the clipboard. Only available in secure contexts (HTTPS or localhost):
+    /// fails otherwise.
+    #[wasm_bindgen(catch, js_namespace = ["navigator", "clipboard"], js_name = writeText)]
+    pub fn write_text(text: &str) -> Result<JsValue, JsValue>;
+}
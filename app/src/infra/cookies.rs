@@ -13,7 +13,12 @@ fn get_document() -> Result<HtmlDocument> {
         })
 }
 
-pub fn set_cookie(cookie_name: &str, value: &str, expiration: &DateTime<Utc>) -> Result<()> {
+/// Without `expiration`, the cookie is dropped when the browser is closed.
+pub fn set_cookie(
+    cookie_name: &str,
+    value: &str,
+    expiration: Option<&DateTime<Utc>>,
+) -> Result<()> {
     let doc = web_sys::window()
         .and_then(|w| w.document())
         .ok_or_else(|| anyhow!("Could not get window document"))
@@ -21,11 +26,14 @@ pub fn set_cookie(cookie_name: &str, value: &str, expiration: &DateTime<Utc>) ->
             d.dyn_into::<web_sys::HtmlDocument>()
                 .map_err(|_| anyhow!("Document is not an HTMLDocument"))
         })?;
+    let expires = expiration
+        .map(|e| format!("; expires={}", e.to_rfc2822()))
+        .unwrap_or_default();
     let cookie_string = format!(
-        "{}={}; expires={}; sameSite=Strict; path={}/",
+        "{}={}{}; sameSite=Strict; path={}/",
         cookie_name,
         value,
-        expiration.to_rfc2822(),
+        expires,
         yew_router::utils::base_url().unwrap_or_default()
     );
     doc.set_cookie(&cookie_string)
@@ -57,7 +65,7 @@ pub fn delete_cookie(cookie_name: &str) -> Result<()> {
         set_cookie(
             cookie_name,
             "",
-            &Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap(),
+            Some(&Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap()),
         )
     } else {
         Ok(())
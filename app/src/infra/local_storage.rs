@@ -0,0 +1,20 @@
+use anyhow::{anyhow, Result};
+use web_sys::Storage;
+
+fn get_storage() -> Result<Storage> {
+    web_sys::window()
+        .and_then(|w| w.local_storage().ok().flatten())
+        .ok_or_else(|| anyhow!("Could not get the local storage"))
+}
+
+pub fn get_item(key: &str) -> Result<Option<String>> {
+    get_storage()?
+        .get_item(key)
+        .map_err(|_| anyhow!("Could not read \"{}\" from the local storage", key))
+}
+
+pub fn set_item(key: &str, value: &str) -> Result<()> {
+    get_storage()?
+        .set_item(key, value)
+        .map_err(|_| anyhow!("Could not write \"{}\" to the local storage", key))
+}
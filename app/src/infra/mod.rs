@@ -1,7 +1,9 @@
 pub mod api;
+pub mod clipboard;
 pub mod common_component;
 pub mod cookies;
 pub mod functional;
 pub mod graphql;
+pub mod local_storage;
 pub mod modal;
 pub mod schema;
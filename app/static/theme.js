@@ -0,0 +1,54 @@
+// The light/dark theme. Loaded (blocking) from the head of the page so that the theme is applied
+// before the first paint, without a flash of the wrong one.
+//
+// The preference is "light", "dark" or "system" (the default), which follows
+// `prefers-color-scheme`. The dark theme is the `dark` class on <html>, which the nightshade
+// stylesheet and style.css are based on.
+(function () {
+  const STORAGE_KEY = "lldap-theme";
+  const THEMES = ["light", "dark", "system"];
+  const systemDark = window.matchMedia("(prefers-color-scheme: dark)");
+
+  function getTheme() {
+    let theme = null;
+    try {
+      theme = window.localStorage.getItem(STORAGE_KEY);
+    } catch (e) {
+      // Storage disabled: fall back to the system theme.
+    }
+    return THEMES.includes(theme) ? theme : "system";
+  }
+
+  function applyTheme(theme) {
+    const dark = theme === "dark" || (theme === "system" && systemDark.matches);
+    const root = document.documentElement;
+    root.classList.toggle("dark", dark);
+    root.dataset.theme = theme;
+    root.style.colorScheme = dark ? "dark" : "light";
+  }
+
+  function setTheme(theme) {
+    if (!THEMES.includes(theme)) {
+      return;
+    }
+    try {
+      window.localStorage.setItem(STORAGE_KEY, theme);
+    } catch (e) {
+      // Not persisted, but still applied to this page.
+    }
+    applyTheme(theme);
+  }
+
+  systemDark.addEventListener("change", function () {
+    applyTheme(getTheme());
+  });
+  // Another tab changed the preference.
+  window.addEventListener("storage", function (event) {
+    if (event.key === STORAGE_KEY) {
+      applyTheme(getTheme());
+    }
+  });
+
+  window.lldapTheme = { getTheme: getTheme, setTheme: setTheme };
+  applyTheme(getTheme());
+})();
@@ -35,12 +35,18 @@ pub mod login {
         /// Encrypted ServerData from the previous step.
         pub server_data: String,
         pub credential_finalization: opaque::client::login::CredentialFinalization,
+        /// Whether to keep the session after the browser is closed. Defaults to true.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub remember_me: Option<bool>,
     }
 
     #[derive(Serialize, Deserialize, Clone)]
     pub struct ClientSimpleLoginRequest {
         pub username: UserId,
         pub password: String,
+        /// Whether to keep the session after the browser is closed. Defaults to true.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub remember_me: Option<bool>,
     }
 
     impl fmt::Debug for ClientSimpleLoginRequest {
@@ -48,15 +54,34 @@ pub mod login {
             f.debug_struct("ClientSimpleLoginRequest")
                 .field("username", &self.username.as_str())
                 .field("password", &"***********")
+                .field("remember_me", &self.remember_me)
                 .finish()
         }
     }
 
+    /// The settings of the login form.
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+    pub struct LoginOptions {
+        /// The initial state of the "remember me" checkbox.
+        pub remember_me_default: bool,
+        /// Whether the users log in with their email rather than their user ID, see
+        /// `login_identifier`.
+        #[serde(default)]
+        pub login_with_email: bool,
+    }
+
     #[derive(Serialize, Deserialize, Clone)]
     pub struct ServerLoginResponse {
         pub token: String,
         #[serde(rename = "refreshToken", skip_serializing_if = "Option::is_none")]
         pub refresh_token: Option<String>,
+        /// Whether the session ends when the browser is closed ("remember me" unchecked).
+        #[serde(
+            rename = "sessionOnly",
+            default,
+            skip_serializing_if = "std::ops::Not::not"
+        )]
+        pub session_only: bool,
     }
 }
 
@@ -104,12 +129,198 @@ pub mod password_reset {
     }
 }
 
+/// The constraints on the new passwords, from the server configuration. With OPAQUE, the server
+/// never sees the passwords set through the web UI: the clients enforce them.
+pub mod password_policy {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct PasswordPolicy {
+        /// In characters.
+        pub min_length: usize,
+        pub require_uppercase: bool,
+        pub require_lowercase: bool,
+        pub require_digit: bool,
+        /// Anything else than a letter or a digit, e.g. a space or "!".
+        pub require_special: bool,
+    }
+
+    impl Default for PasswordPolicy {
+        fn default() -> Self {
+            PasswordPolicy {
+                min_length: 8,
+                require_uppercase: false,
+                require_lowercase: false,
+                require_digit: false,
+                require_special: false,
+            }
+        }
+    }
+
+    impl PasswordPolicy {
+        /// The requirements that the password doesn't meet, as messages for the user.
+        pub fn violations(&self, password: &str) -> Vec<String> {
+            let mut violations = Vec::new();
+            if password.chars().count() < self.min_length {
+                violations.push(format!("needs {}+ characters", self.min_length));
+            }
+            let checks = [
+                (
+                    self.require_uppercase,
+                    char::is_uppercase as fn(char) -> bool,
+                    "an uppercase letter",
+                ),
+                (
+                    self.require_lowercase,
+                    char::is_lowercase,
+                    "a lowercase letter",
+                ),
+                (self.require_digit, |c: char| c.is_ascii_digit(), "a digit"),
+                (
+                    self.require_special,
+                    |c: char| !c.is_alphanumeric(),
+                    "a special character",
+                ),
+            ];
+            for (required, matches, description) in checks {
+                if required && !password.chars().any(matches) {
+                    violations.push(format!("needs {}", description));
+                }
+            }
+            violations
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn no_requirement() -> PasswordPolicy {
+            PasswordPolicy {
+                min_length: 0,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_default_policy() {
+            let policy = PasswordPolicy::default();
+            assert_eq!(policy.violations("short"), vec!["needs 8+ characters"]);
+            assert!(policy.violations("longer password").is_empty());
+        }
+
+        #[test]
+        fn test_min_length_in_characters() {
+            let policy = PasswordPolicy {
+                min_length: 12,
+                ..no_requirement()
+            };
+            // 12 bytes, but only 6 characters.
+            assert_eq!(policy.violations("éééééé"), vec!["needs 12+ characters"]);
+            assert_eq!(
+                policy.violations("ééééééééééé"),
+                vec!["needs 12+ characters"]
+            );
+            assert!(policy.violations("éééééééééééé").is_empty());
+        }
+
+        #[test]
+        fn test_each_requirement() {
+            for (policy, message) in [
+                (
+                    PasswordPolicy {
+                        require_uppercase: true,
+                        ..no_requirement()
+                    },
+                    "needs an uppercase letter",
+                ),
+                (
+                    PasswordPolicy {
+                        require_lowercase: true,
+                        ..no_requirement()
+                    },
+                    "needs a lowercase letter",
+                ),
+                (
+                    PasswordPolicy {
+                        require_digit: true,
+                        ..no_requirement()
+                    },
+                    "needs a digit",
+                ),
+                (
+                    PasswordPolicy {
+                        require_special: true,
+                        ..no_requirement()
+                    },
+                    "needs a special character",
+                ),
+            ] {
+                assert_eq!(policy.violations(""), vec![message]);
+                assert!(policy.violations("Aa1!").is_empty(), "{}", message);
+            }
+        }
+
+        #[test]
+        fn test_all_requirements() {
+            let policy = PasswordPolicy {
+                min_length: 12,
+                require_uppercase: true,
+                require_lowercase: true,
+                require_digit: true,
+                require_special: true,
+            };
+            assert_eq!(
+                policy.violations(""),
+                vec![
+                    "needs 12+ characters",
+                    "needs an uppercase letter",
+                    "needs a lowercase letter",
+                    "needs a digit",
+                    "needs a special character",
+                ]
+            );
+            assert!(policy.violations("Correct horse 1").is_empty());
+        }
+
+        #[test]
+        fn test_non_ascii_characters() {
+            let policy = PasswordPolicy {
+                require_uppercase: true,
+                require_lowercase: true,
+                require_digit: true,
+                require_special: true,
+                ..no_requirement()
+            };
+            // The letters of any script count, in either case.
+            assert!(policy.violations("Éé1 ").is_empty());
+            assert!(policy.violations("Ωω1!").is_empty());
+            assert_eq!(policy.violations("ÉÈ1!"), vec!["needs a lowercase letter"]);
+            assert_eq!(policy.violations("éè1!"), vec!["needs an uppercase letter"]);
+            // A letter is not a special character, even outside of ASCII.
+            assert_eq!(policy.violations("Aaß1"), vec!["needs a special character"]);
+            // Only the ASCII digits are digits, and the others aren't special characters.
+            assert_eq!(policy.violations("Aa!٣"), vec!["needs a digit"]);
+            assert_eq!(
+                policy.violations("Aa٣"),
+                vec!["needs a digit", "needs a special character"]
+            );
+        }
+    }
+}
+
 pub mod types {
     use serde::{Deserialize, Serialize};
 
     #[cfg(feature = "sea_orm")]
-    use sea_orm::{DbErr, DeriveValueType, QueryResult, TryFromU64, Value};
+    use sea_orm::{
+        sea_query::{ArrayType, ColumnType, Nullable, ValueType, ValueTypeErr},
+        ColIdx, DbErr, DeriveValueType, QueryResult, TryFromU64, TryGetError, TryGetable, Value,
+    };
 
+    /// Lowercased, all the letters and not only the ASCII ones, like the lowercase columns of the
+    /// database: this is the normalized form, used as a key.
     #[derive(
         PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Default, Hash, Serialize, Deserialize,
     )]
@@ -119,7 +330,7 @@ pub mod types {
 
     impl CaseInsensitiveString {
         pub fn new(s: &str) -> Self {
-            Self(s.to_ascii_lowercase())
+            Self(s.to_lowercase())
         }
 
         pub fn as_str(&self) -> &str {
@@ -132,9 +343,8 @@ pub mod types {
     }
 
     impl From<String> for CaseInsensitiveString {
-        fn from(mut s: String) -> Self {
-            s.make_ascii_lowercase();
-            Self(s)
+        fn from(s: String) -> Self {
+            Self(s.to_lowercase())
         }
     }
 
@@ -150,38 +360,103 @@ pub mod types {
         }
     }
 
-    #[derive(
-        PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Default, Hash, Serialize, Deserialize,
-    )]
-    #[cfg_attr(feature = "sea_orm", derive(DeriveValueType))]
-    #[serde(from = "CaseInsensitiveString")]
-    pub struct UserId(CaseInsensitiveString);
+    /// The user IDs are case-insensitive: they are kept as entered, to be displayed, and compared,
+    /// sorted, hashed and stored in the key columns of the database in their lowercase form, the
+    /// same whether they come from GraphQL, an LDAP bind or a token.
+    ///
+    /// The form as entered is the one that is serialized (tokens, JSON) and the one stored in
+    /// `users.user_id_as_entered`; it is what `String::from` gives. The lowercase key is the one
+    /// stored in the `user_id` key columns, through `Value`, and is taken with `into_key`.
+    #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+    #[serde(from = "String", into = "String")]
+    pub struct UserId {
+        as_entered: String,
+        key: CaseInsensitiveString,
+    }
 
     impl UserId {
         pub fn new(s: &str) -> Self {
             s.into()
         }
+        /// The lowercase form, to compare with the other user IDs and the key columns.
         pub fn as_str(&self) -> &str {
-            self.0.as_str()
+            self.key.as_str()
         }
-        pub fn into_string(self) -> String {
-            self.0.into_string()
+        /// The user ID as it was entered when the user was created, for display.
+        pub fn as_entered(&self) -> &str {
+            self.as_entered.as_str()
+        }
+        /// The lowercase form, owned. `String::from` gives the form as entered instead.
+        pub fn into_key(self) -> String {
+            self.key.into_string()
+        }
+    }
+    impl From<String> for UserId {
+        fn from(s: String) -> Self {
+            Self {
+                key: CaseInsensitiveString::new(&s),
+                as_entered: s,
+            }
+        }
+    }
+    impl From<&String> for UserId {
+        fn from(s: &String) -> Self {
+            s.as_str().into()
+        }
+    }
+    impl From<&str> for UserId {
+        fn from(s: &str) -> Self {
+            s.to_owned().into()
+        }
+    }
+    impl From<CaseInsensitiveString> for UserId {
+        fn from(key: CaseInsensitiveString) -> Self {
+            Self {
+                as_entered: key.as_str().to_owned(),
+                key,
+            }
         }
     }
-    impl<T> From<T> for UserId
-    where
-        T: Into<CaseInsensitiveString>,
-    {
-        fn from(s: T) -> Self {
-            Self(s.into())
+    /// The form as entered, as serialized. Use `UserId::into_key` for the lowercase form.
+    impl From<UserId> for String {
+        fn from(user_id: UserId) -> Self {
+            user_id.as_entered
+        }
+    }
+    impl PartialEq for UserId {
+        fn eq(&self, other: &Self) -> bool {
+            self.key == other.key
+        }
+    }
+    impl Eq for UserId {}
+    impl PartialOrd for UserId {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+    impl Ord for UserId {
+        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+            self.key.cmp(&other.key)
+        }
+    }
+    impl std::hash::Hash for UserId {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            self.key.hash(state)
         }
     }
     impl std::fmt::Display for UserId {
         fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-            write!(f, "{}", self.0.as_str())
+            write!(f, "{}", self.as_str())
         }
     }
 
+    // The database only sees the lowercase form, in the key columns and the filters.
+    #[cfg(feature = "sea_orm")]
+    impl From<UserId> for Value {
+        fn from(user_id: UserId) -> Self {
+            user_id.key.into()
+        }
+    }
     #[cfg(feature = "sea_orm")]
     impl From<&UserId> for Value {
         fn from(user_id: &UserId) -> Self {
@@ -189,6 +464,33 @@ pub mod types {
         }
     }
     #[cfg(feature = "sea_orm")]
+    impl TryGetable for UserId {
+        fn try_get_by<I: ColIdx>(res: &QueryResult, index: I) -> Result<Self, TryGetError> {
+            String::try_get_by(res, index).map(Self::from)
+        }
+    }
+    #[cfg(feature = "sea_orm")]
+    impl ValueType for UserId {
+        fn try_from(v: Value) -> Result<Self, ValueTypeErr> {
+            <String as ValueType>::try_from(v).map(Self::from)
+        }
+        fn type_name() -> String {
+            "UserId".to_owned()
+        }
+        fn array_type() -> ArrayType {
+            ArrayType::String
+        }
+        fn column_type() -> ColumnType {
+            ColumnType::String(None)
+        }
+    }
+    #[cfg(feature = "sea_orm")]
+    impl Nullable for UserId {
+        fn null() -> Value {
+            String::null()
+        }
+    }
+    #[cfg(feature = "sea_orm")]
     impl TryFromU64 for UserId {
         fn try_from_u64(_n: u64) -> Result<Self, DbErr> {
             Err(DbErr::ConvertFromU64(
@@ -196,6 +498,22 @@ pub mod types {
             ))
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_user_id_string_conversions() {
+            let user_id = UserId::new("Bob");
+            assert_eq!(user_id.as_str(), "bob");
+            assert_eq!(user_id.as_entered(), "Bob");
+            assert_eq!(user_id.to_string(), "bob");
+            assert_eq!(user_id.clone().into_key(), "bob");
+            assert_eq!(String::from(user_id.clone()), "Bob");
+            assert_eq!(UserId::from(String::from(user_id.clone())), user_id);
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -204,4 +522,8 @@ pub struct JWTClaims {
     pub iat: DateTime<Utc>,
     pub user: String,
     pub groups: HashSet<String>,
+    /// What the user logs in with, when it's not `user`: their email with
+    /// `login_identifier = "email"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login: Option<String>,
 }
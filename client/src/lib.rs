@@ -0,0 +1,410 @@
+//! A client for the APIs of an LLDAP server: the OPAQUE login, the refresh of the session
+//! token, the password changes and the main GraphQL operations.
+//!
+//! The request and response types are generated from the `schema.graphql` at the root of the
+//! repository, that the server tests keep in sync with the server.
+//!
+//! ```no_run
+//! # async fn run() -> lldap_client::Result<()> {
+//! let client = lldap_client::LldapClient::login("http://localhost:17170", "admin", "password")
+//!     .await?;
+//! for user in client.list_users(None).await? {
+//!     println!("{}", user.id);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod queries;
+
+use graphql_client::GraphQLQuery;
+use lldap_auth::{login, opaque, registration};
+use reqwest::{StatusCode, Url};
+
+pub use queries::*;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid server URL: {0}")]
+    InvalidUrl(String),
+    #[error("Could not reach the server: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Error from the server ({status}): {message}")]
+    Server { status: StatusCode, message: String },
+    #[error("GraphQL error: {}", format_graphql_errors(.0))]
+    GraphQL(Vec<graphql_client::Error>),
+    #[error("OPAQUE protocol error: {0}")]
+    Opaque(#[from] opaque::AuthenticationError),
+    #[error("No refresh token, the client was created from a token")]
+    MissingRefreshToken,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn format_graphql_errors(errors: &[graphql_client::Error]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Turns the HTTP errors into [`Error::Server`], with the body of the response as message.
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else {
+        Err(Error::Server {
+            status,
+            message: response.text().await.unwrap_or_default(),
+        })
+    }
+}
+
+/// A client authenticated as one user: what it can do depends on the groups of the user.
+pub struct LldapClient {
+    base_url: Url,
+    http: reqwest::Client,
+    token: String,
+    refresh_token: Option<String>,
+}
+
+impl LldapClient {
+    /// Creates a client from an existing token, e.g. a JWT or an API token. It cannot be
+    /// refreshed.
+    pub fn with_token(base_url: &str, token: impl Into<String>) -> Result<Self> {
+        Ok(Self {
+            base_url: parse_base_url(base_url)?,
+            http: reqwest::Client::new(),
+            token: token.into(),
+            refresh_token: None,
+        })
+    }
+
+    /// Replaces the HTTP client, e.g. to set timeouts or extra root certificates.
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Logs in with the OPAQUE protocol: the password never leaves the client.
+    pub async fn login(base_url: &str, username: &str, password: &str) -> Result<Self> {
+        Self::login_with_http_client(base_url, username, password, reqwest::Client::new()).await
+    }
+
+    pub async fn login_with_http_client(
+        base_url: &str,
+        username: &str,
+        password: &str,
+        http: reqwest::Client,
+    ) -> Result<Self> {
+        let base_url = parse_base_url(base_url)?;
+        let mut rng = rand::rngs::OsRng;
+        let opaque::client::login::ClientLoginStartResult { state, message } =
+            opaque::client::login::start_login(password, &mut rng)?;
+        let start_response = check_status(
+            http.post(join_url(&base_url, &["auth", "opaque", "login", "start"]))
+                .json(&login::ClientLoginStartRequest {
+                    username: username.into(),
+                    login_start_request: message,
+                })
+                .send()
+                .await?,
+        )
+        .await?
+        .json::<login::ServerLoginStartResponse>()
+        .await?;
+        let login_finish =
+            opaque::client::login::finish_login(state, start_response.credential_response)?;
+        let response = check_status(
+            http.post(join_url(&base_url, &["auth", "opaque", "login", "finish"]))
+                .json(&login::ClientLoginFinishRequest {
+                    server_data: start_response.server_data,
+                    credential_finalization: login_finish.message,
+                    remember_me: None,
+                })
+                .send()
+                .await?,
+        )
+        .await?
+        .json::<login::ServerLoginResponse>()
+        .await?;
+        Ok(Self {
+            base_url,
+            http,
+            token: response.token,
+            refresh_token: response.refresh_token,
+        })
+    }
+
+    /// The current session token.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Gets a new session token with the refresh token of the login, before the current one
+    /// expires (after a day).
+    pub async fn refresh(&mut self) -> Result<()> {
+        let refresh_token = self
+            .refresh_token
+            .as_ref()
+            .ok_or(Error::MissingRefreshToken)?;
+        let response = check_status(
+            self.http
+                .get(self.url(&["auth", "refresh"]))
+                .header("refresh-token", refresh_token)
+                .send()
+                .await?,
+        )
+        .await?
+        .json::<login::ServerLoginResponse>()
+        .await?;
+        self.token = response.token;
+        Ok(())
+    }
+
+    /// Sends a GraphQL operation of [`queries`], or one generated by the caller from the same
+    /// schema.
+    pub async fn post<QueryType>(
+        &self,
+        variables: QueryType::Variables,
+    ) -> Result<QueryType::ResponseData>
+    where
+        QueryType: GraphQLQuery,
+    {
+        let graphql_client::Response { data, errors, .. } = check_status(
+            self.http
+                .post(self.url(&["api", "graphql"]))
+                .bearer_auth(&self.token)
+                .json(&QueryType::build_query(variables))
+                .send()
+                .await?,
+        )
+        .await?
+        .json::<graphql_client::Response<QueryType::ResponseData>>()
+        .await?;
+        match (data, errors.unwrap_or_default()) {
+            (Some(data), errors) if errors.is_empty() => Ok(data),
+            (_, errors) => Err(Error::GraphQL(errors)),
+        }
+    }
+
+    pub async fn list_users(&self, filters: Option<RequestFilter>) -> Result<Vec<UserSummary>> {
+        Ok(self
+            .post::<ListUsers>(list_users::Variables { filters })
+            .await?
+            .users)
+    }
+
+    pub async fn get_user(&self, id: &str) -> Result<User> {
+        Ok(self
+            .post::<GetUser>(get_user::Variables { id: id.to_owned() })
+            .await?
+            .user)
+    }
+
+    /// With `send_invite`, the user gets an email with a link to choose their password.
+    pub async fn create_user(
+        &self,
+        user: CreateUserInput,
+        send_invite: bool,
+    ) -> Result<CreatedUser> {
+        Ok(self
+            .post::<CreateUser>(create_user::Variables {
+                user,
+                send_invite: Some(send_invite),
+            })
+            .await?
+            .create_user)
+    }
+
+    pub async fn update_user(&self, user: UpdateUserInput) -> Result<()> {
+        self.post::<UpdateUser>(update_user::Variables { user })
+            .await
+            .map(|_| ())
+    }
+
+    /// Unless `permanent` is set, the user can still be restored until it is purged.
+    pub async fn delete_user(&self, id: &str, permanent: bool) -> Result<()> {
+        self.post::<DeleteUser>(delete_user::Variables {
+            id: id.to_owned(),
+            permanent: Some(permanent),
+        })
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn list_groups(&self) -> Result<Vec<GroupSummary>> {
+        Ok(self
+            .post::<ListGroups>(list_groups::Variables {})
+            .await?
+            .groups)
+    }
+
+    pub async fn get_group(&self, id: i64) -> Result<Group> {
+        Ok(self
+            .post::<GetGroup>(get_group::Variables { id })
+            .await?
+            .group)
+    }
+
+    pub async fn create_group(&self, request: CreateGroupInput) -> Result<CreatedGroup> {
+        Ok(self
+            .post::<CreateGroup>(create_group::Variables { request })
+            .await?
+            .create_group_with_details)
+    }
+
+    pub async fn update_group(&self, group: UpdateGroupInput) -> Result<()> {
+        self.post::<UpdateGroup>(update_group::Variables { group })
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn delete_group(&self, id: i64) -> Result<()> {
+        self.post::<DeleteGroup>(delete_group::Variables { id })
+            .await
+            .map(|_| ())
+    }
+
+    /// Returns false if the user was already a member of the group.
+    pub async fn add_user_to_group(&self, user: &str, group: i64) -> Result<bool> {
+        Ok(self
+            .post::<AddUserToGroup>(add_user_to_group::Variables {
+                user: user.to_owned(),
+                group,
+            })
+            .await?
+            .add_user_to_group
+            .changed)
+    }
+
+    /// Returns false if the user was not a member of the group.
+    pub async fn remove_user_from_group(&self, user: &str, group: i64) -> Result<bool> {
+        Ok(self
+            .post::<RemoveUserFromGroup>(remove_user_from_group::Variables {
+                user: user.to_owned(),
+                group,
+            })
+            .await?
+            .remove_user_from_group
+            .changed)
+    }
+
+    /// Sends the password reset email to the user, given by ID or email. The server answers
+    /// the same whether the user exists or not.
+    pub async fn send_password_reset(&self, user_id_or_email: &str) -> Result<()> {
+        check_status(
+            self.http
+                .post(self.url(&["auth", "reset", "step1", user_id_or_email]))
+                .send()
+                .await?,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Sets the password of a user with the OPAQUE registration: the password never leaves
+    /// the client. Admins can set any password, the users only their own.
+    pub async fn set_password(&self, user_id: &str, password: &str) -> Result<()> {
+        let mut rng = rand::rngs::OsRng;
+        let registration_start =
+            opaque::client::registration::start_registration(password.as_bytes(), &mut rng)?;
+        let start_response = check_status(
+            self.http
+                .post(self.url(&["auth", "opaque", "register", "start"]))
+                .bearer_auth(&self.token)
+                .json(&registration::ClientRegistrationStartRequest {
+                    username: user_id.into(),
+                    registration_start_request: registration_start.message,
+                })
+                .send()
+                .await?,
+        )
+        .await?
+        .json::<registration::ServerRegistrationStartResponse>()
+        .await?;
+        let registration_finish = opaque::client::registration::finish_registration(
+            registration_start.state,
+            start_response.registration_response,
+            &mut rng,
+        )?;
+        check_status(
+            self.http
+                .post(self.url(&["auth", "opaque", "register", "finish"]))
+                .bearer_auth(&self.token)
+                .json(&registration::ClientRegistrationFinishRequest {
+                    server_data: start_response.server_data,
+                    registration_upload: registration_finish.message,
+                })
+                .send()
+                .await?,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    fn url(&self, segments: &[&str]) -> Url {
+        join_url(&self.base_url, segments)
+    }
+}
+
+fn parse_base_url(base_url: &str) -> Result<Url> {
+    let url = Url::parse(base_url).map_err(|e| Error::InvalidUrl(format!("{base_url}: {e}")))?;
+    if url.cannot_be_a_base() || !matches!(url.scheme(), "http" | "https") {
+        return Err(Error::InvalidUrl(format!(
+            "{base_url}: expected an http:// or https:// URL"
+        )));
+    }
+    Ok(url)
+}
+
+/// Appends the segments to the path of the base URL, escaping them.
+fn join_url(base_url: &Url, segments: &[&str]) -> Url {
+    let mut url = base_url.clone();
+    url.path_segments_mut()
+        .expect("checked by parse_base_url")
+        .pop_if_empty()
+        .extend(segments);
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_url() {
+        let url = |base: &str| parse_base_url(base).unwrap();
+        assert_eq!(
+            join_url(&url("http://localhost:17170"), &["api", "graphql"]).as_str(),
+            "http://localhost:17170/api/graphql"
+        );
+        assert_eq!(
+            join_url(&url("https://example.com/lldap/"), &["auth", "refresh"]).as_str(),
+            "https://example.com/lldap/auth/refresh"
+        );
+        assert_eq!(
+            join_url(
+                &url("https://example.com/lldap"),
+                &["auth", "reset", "step1", "a/b c"]
+            )
+            .as_str(),
+            "https://example.com/lldap/auth/reset/step1/a%2Fb%20c"
+        );
+    }
+
+    #[test]
+    fn test_parse_base_url() {
+        assert!(matches!(
+            parse_base_url("localhost:17170"),
+            Err(Error::InvalidUrl(_))
+        ));
+        assert!(matches!(
+            parse_base_url("ldap://localhost"),
+            Err(Error::InvalidUrl(_))
+        ));
+        assert!(parse_base_url("http://localhost:17170").is_ok());
+    }
+}
@@ -0,0 +1,144 @@
+//! The GraphQL operations of the client, with the types generated from `schema.graphql`.
+//!
+//! Each operation has a module with its `Variables`, its input types and its response types,
+//! e.g. [`create_user::CreateUserInput`]. The most common ones are aliased at the top.
+
+use graphql_client::GraphQLQuery;
+
+pub type DateTimeUtc = chrono::DateTime<chrono::Utc>;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/list_users.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct ListUsers;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/get_user.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct GetUser;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/create_user.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct CreateUser;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/update_user.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct UpdateUser;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/delete_user.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct DeleteUser;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/list_groups.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct ListGroups;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/get_group.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct GetGroup;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/create_group.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct CreateGroup;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/update_group.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct UpdateGroup;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/delete_group.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct DeleteGroup;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/add_user_to_group.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct AddUserToGroup;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/remove_user_from_group.graphql",
+    response_derives = "Debug,Clone",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::queries"
+)]
+pub struct RemoveUserFromGroup;
+
+pub type RequestFilter = list_users::RequestFilter;
+pub type EqualityConstraint = list_users::EqualityConstraint;
+pub type CreateUserInput = create_user::CreateUserInput;
+pub type UpdateUserInput = update_user::UpdateUserInput;
+pub type CreateGroupInput = create_group::CreateGroupInput;
+pub type UpdateGroupInput = update_group::UpdateGroupInput;
+
+/// A user of [`crate::LldapClient::list_users`], without the details.
+pub type UserSummary = list_users::ListUsersUsers;
+pub type User = get_user::GetUserUser;
+pub type CreatedUser = create_user::CreateUserCreateUser;
+/// A group of [`crate::LldapClient::list_groups`], with the IDs of its members.
+pub type GroupSummary = list_groups::ListGroupsGroups;
+pub type Group = get_group::GetGroupGroup;
+pub type CreatedGroup = create_group::CreateGroupCreateGroupWithDetails;
@@ -0,0 +1,49 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+
+/// A GraphQL client, logged in as the admin.
+#[derive(Clone)]
+pub struct GraphQl {
+    client: Client,
+    url: String,
+    token: String,
+}
+
+impl GraphQl {
+    pub fn login(http_url: &str, username: &str, password: &str) -> Result<Self> {
+        let client = Client::new();
+        let response: Value = client
+            .post(format!("{}/auth/simple/login", http_url))
+            .json(&json!({ "username": username, "password": password }))
+            .send()?
+            .error_for_status()
+            .context("Could not log in")?
+            .json()?;
+        let token = response["token"]
+            .as_str()
+            .ok_or_else(|| anyhow!("No token in the login response: {}", response))?
+            .to_owned();
+        Ok(Self {
+            client,
+            url: format!("{}/api/graphql", http_url),
+            token,
+        })
+    }
+
+    /// Returns the `data` of the response, or fails with its `errors`.
+    pub fn query(&self, query: &str, variables: Value) -> Result<Value> {
+        let mut response: Value = self
+            .client
+            .post(&self.url)
+            .bearer_auth(&self.token)
+            .json(&json!({ "query": query, "variables": variables }))
+            .send()?
+            .error_for_status()?
+            .json()?;
+        if let Some(errors) = response.get("errors") {
+            return Err(anyhow!("GraphQL errors: {}", errors));
+        }
+        Ok(response["data"].take())
+    }
+}
@@ -0,0 +1,90 @@
+//! Load test harness for LLDAP: seeds users and groups through the API, then drives concurrent
+//! LDAP binds, LDAP searches and GraphQL user lists against a running server, and reports the
+//! throughput and latency percentiles of each.
+//!
+//! The seeded entries are generated from `Profile::seed`, so that the runs with the same profile
+//! are comparable. They are prefixed with "loadtest", and deleted at the end of the run.
+
+mod graphql;
+mod report;
+mod scenarios;
+mod seed;
+
+pub use report::{Latencies, Report, ScenarioReport};
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// The size of the directory and of the load.
+#[derive(Clone, Debug, Serialize)]
+pub struct Profile {
+    /// Users created before the run.
+    pub users: usize,
+    /// Groups created before the run; each user is in up to 3 of them.
+    pub groups: usize,
+    /// Users given a password, to bind as.
+    pub bind_users: usize,
+    /// Concurrent clients of each scenario.
+    pub concurrency: usize,
+    /// Requests sent by each client, in each scenario.
+    pub requests_per_client: usize,
+    /// Seed of the generated names, groups and memberships.
+    pub seed: u64,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            users: 1000,
+            groups: 20,
+            bind_users: 20,
+            concurrency: 8,
+            requests_per_client: 200,
+            seed: 0,
+        }
+    }
+}
+
+impl Profile {
+    /// A few requests of each kind, to check that the harness still works.
+    pub fn smoke() -> Self {
+        Self {
+            users: 20,
+            groups: 3,
+            bind_users: 2,
+            concurrency: 2,
+            requests_per_client: 5,
+            seed: 0,
+        }
+    }
+}
+
+/// The server under test.
+#[derive(Clone, Debug)]
+pub struct Target {
+    /// E.g. "ldap://localhost:3890".
+    pub ldap_url: String,
+    /// E.g. "http://localhost:17170".
+    pub http_url: String,
+    pub base_dn: String,
+    pub admin_user: String,
+    pub admin_password: String,
+}
+
+impl Target {
+    pub fn user_dn(&self, user_id: &str) -> String {
+        format!("uid={},ou=people,{}", user_id, self.base_dn)
+    }
+
+    pub fn group_dn(&self, name: &str) -> String {
+        format!("cn={},ou=groups,{}", name, self.base_dn)
+    }
+}
+
+/// Seeds the directory, runs the scenarios one after the other, and deletes the seeded entries.
+pub fn run(target: &Target, profile: &Profile) -> Result<Report> {
+    let seeded = seed::seed(target, profile)?;
+    let report = scenarios::run_all(target, profile, &seeded);
+    seed::clean_up(target)?;
+    report
+}
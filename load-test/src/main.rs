@@ -0,0 +1,158 @@
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use lldap_load_test::{Profile, Target};
+use std::{
+    path::PathBuf,
+    process::{Child, Command},
+    thread,
+    time::Duration,
+};
+
+/// Seed LLDAP with generated users and groups, load it with binds, searches and GraphQL queries,
+/// and print a JSON report of the throughput and latencies.
+#[derive(Debug, Parser, Clone)]
+pub struct CliOpts {
+    /// Path to an lldap binary, started on a temporary database for the run. Without it, the run
+    /// targets the running server given by --ldap-url and --http-url.
+    #[clap(long)]
+    pub lldap: Option<PathBuf>,
+
+    /// LDAP URL of the server, e.g. "ldap://localhost:3890".
+    #[clap(long, default_value = "ldap://localhost:3890")]
+    pub ldap_url: String,
+
+    /// HTTP URL of the server, e.g. "http://localhost:17170".
+    #[clap(long, default_value = "http://localhost:17170")]
+    pub http_url: String,
+
+    #[clap(long, default_value = "dc=example,dc=com")]
+    pub base_dn: String,
+
+    #[clap(long, default_value = "admin")]
+    pub admin_username: String,
+
+    #[clap(long, env = "LLDAP_LDAP_USER_PASS", default_value = "password")]
+    pub admin_password: String,
+
+    /// Run the small smoke profile, ignoring the size options below.
+    #[clap(long)]
+    pub smoke: bool,
+
+    #[clap(long, default_value_t = Profile::default().users)]
+    pub users: usize,
+
+    #[clap(long, default_value_t = Profile::default().groups)]
+    pub groups: usize,
+
+    /// Number of users with a password, used for the binds.
+    #[clap(long, default_value_t = Profile::default().bind_users)]
+    pub bind_users: usize,
+
+    /// Number of concurrent clients in each scenario.
+    #[clap(short, long, default_value_t = Profile::default().concurrency)]
+    pub concurrency: usize,
+
+    #[clap(short, long, default_value_t = Profile::default().requests_per_client)]
+    pub requests_per_client: usize,
+
+    /// Seed of the generated data: the same seed gives the same users and groups.
+    #[clap(long, default_value_t = 0)]
+    pub seed: u64,
+}
+
+impl CliOpts {
+    fn profile(&self) -> Profile {
+        let mut profile = if self.smoke {
+            Profile::smoke()
+        } else {
+            Profile {
+                users: self.users,
+                groups: self.groups,
+                bind_users: self.bind_users,
+                concurrency: self.concurrency,
+                requests_per_client: self.requests_per_client,
+                seed: 0,
+            }
+        };
+        profile.seed = self.seed;
+        profile
+    }
+
+    fn target(&self) -> Target {
+        Target {
+            ldap_url: self.ldap_url.clone(),
+            http_url: self.http_url.clone(),
+            base_dn: self.base_dn.clone(),
+            admin_user: self.admin_username.clone(),
+            admin_password: self.admin_password.clone(),
+        }
+    }
+}
+
+/// An lldap server running on a fresh database, stopped and removed on drop.
+struct LocalServer {
+    child: Child,
+    directory: PathBuf,
+}
+
+impl LocalServer {
+    fn start(binary: &PathBuf, opts: &CliOpts) -> Result<Self> {
+        let directory =
+            std::env::temp_dir().join(format!("lldap-load-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory)?;
+        let port = |url: &str| url.rsplit(':').next().unwrap_or_default().to_owned();
+        let child = Command::new(binary)
+            .arg("run")
+            .current_dir(&directory)
+            .env(
+                "LLDAP_DATABASE_URL",
+                format!("sqlite://{}?mode=rwc", directory.join("users.db").display()),
+            )
+            .env("LLDAP_LDAP_PORT", port(&opts.ldap_url))
+            .env("LLDAP_HTTP_PORT", port(&opts.http_url))
+            .env("LLDAP_LDAP_BASE_DN", &opts.base_dn)
+            .env("LLDAP_LDAP_USER_DN", &opts.admin_username)
+            .env("LLDAP_LDAP_USER_PASS", &opts.admin_password)
+            .env("LLDAP_JWT_SECRET", "load test secret")
+            .env("LLDAP_KEY_SEED", "load test seed")
+            .spawn()
+            .with_context(|| format!("Could not start {}", binary.display()))?;
+        let server = Self { child, directory };
+        server.wait_until_ready(&opts.http_url)?;
+        Ok(server)
+    }
+
+    fn wait_until_ready(&self, http_url: &str) -> Result<()> {
+        for _ in 0..30 {
+            if reqwest::blocking::get(format!("{}/ready", http_url))
+                .is_ok_and(|response| response.status().is_success())
+            {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+        bail!("The server at {} did not become ready", http_url)
+    }
+}
+
+impl Drop for LocalServer {
+    fn drop(&mut self) {
+        if let Err(e) = self.child.kill() {
+            eprintln!("Could not stop the server: {:#}", e);
+        }
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.directory);
+    }
+}
+
+fn main() -> Result<()> {
+    let opts = CliOpts::parse();
+    let _server = opts
+        .lldap
+        .as_ref()
+        .map(|binary| LocalServer::start(binary, &opts))
+        .transpose()?;
+    let report = lldap_load_test::run(&opts.target(), &opts.profile())?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use crate::Profile;
+use serde::Serialize;
+use std::{collections::BTreeMap, time::Duration};
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub profile: Profile,
+    /// By scenario name: "bind", "search" and "graphql_users".
+    pub scenarios: BTreeMap<String, ScenarioReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScenarioReport {
+    pub requests: usize,
+    pub errors: usize,
+    pub duration_secs: f64,
+    pub throughput_per_sec: f64,
+    /// Of all the requests, including the failed ones.
+    pub latency_ms: Latencies,
+}
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct Latencies {
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Latencies {
+    pub fn from_samples(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort();
+        let percentile = |p: f64| {
+            let index = (p / 100.0 * (samples.len() - 1) as f64).round() as usize;
+            samples[index].as_micros() as f64 / 1000.0
+        };
+        Self {
+            min: percentile(0.0),
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+            max: percentile(100.0),
+        }
+    }
+}
+
+impl ScenarioReport {
+    pub fn new(samples: Vec<Duration>, errors: usize, duration: Duration) -> Self {
+        let requests = samples.len();
+        Self {
+            requests,
+            errors,
+            duration_secs: duration.as_secs_f64(),
+            throughput_per_sec: requests as f64 / duration.as_secs_f64().max(f64::EPSILON),
+            latency_ms: Latencies::from_samples(samples),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latencies() {
+        let samples = (1..=100).rev().map(Duration::from_millis).collect();
+        assert_eq!(
+            Latencies::from_samples(samples),
+            Latencies {
+                min: 1.0,
+                p50: 51.0,
+                p90: 90.0,
+                p99: 99.0,
+                max: 100.0,
+            }
+        );
+        assert_eq!(Latencies::from_samples(Vec::new()), Latencies::default());
+    }
+}
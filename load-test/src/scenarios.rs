@@ -0,0 +1,168 @@
+use crate::{
+    graphql::GraphQl,
+    report::{Report, ScenarioReport},
+    seed::{admin_connection, Seeded, SeededUser},
+    Profile, Target,
+};
+use anyhow::{Context, Result};
+use ldap3::{LdapConn, Scope};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+const SEARCH_ATTRIBUTES: [&str; 5] = ["uid", "mail", "cn", "memberOf", "entryUuid"];
+
+pub fn run_all(target: &Target, profile: &Profile, seeded: &Seeded) -> Result<Report> {
+    let mut scenarios = BTreeMap::new();
+    scenarios.insert(
+        "bind".to_owned(),
+        run_scenario(
+            profile,
+            |_| Ok(LdapConn::new(&target.ldap_url)?),
+            |ldap, request| bind(target, seeded, ldap, request),
+        )
+        .context("bind scenario")?,
+    );
+    scenarios.insert(
+        "search".to_owned(),
+        run_scenario(
+            profile,
+            |_| admin_connection(target),
+            |ldap, request| search(target, seeded, ldap, request),
+        )
+        .context("search scenario")?,
+    );
+    scenarios.insert(
+        "graphql_users".to_owned(),
+        run_scenario(
+            profile,
+            |_| GraphQl::login(&target.http_url, &target.admin_user, &target.admin_password),
+            |graphql, request| list_users(seeded, graphql, request),
+        )
+        .context("graphql_users scenario")?,
+    );
+    Ok(Report {
+        profile: profile.clone(),
+        scenarios,
+    })
+}
+
+/// Runs `profile.concurrency` clients, each sending `profile.requests_per_client` requests. The
+/// requests are numbered across the clients, to pick the users and filters deterministically.
+fn run_scenario<C>(
+    profile: &Profile,
+    connect: impl Fn(usize) -> Result<C> + Sync,
+    send: impl Fn(&mut C, usize) -> Result<()> + Sync,
+) -> Result<ScenarioReport> {
+    let clients = (0..profile.concurrency)
+        .map(&connect)
+        .collect::<Result<Vec<_>>>()?;
+    let start = Instant::now();
+    let results = std::thread::scope(|scope| {
+        let send = &send;
+        clients
+            .into_iter()
+            .enumerate()
+            .map(|(client_index, mut client)| {
+                scope.spawn(move || {
+                    let mut samples = Vec::with_capacity(profile.requests_per_client);
+                    let mut errors = 0;
+                    for i in 0..profile.requests_per_client {
+                        let request = client_index * profile.requests_per_client + i;
+                        let request_start = Instant::now();
+                        if send(&mut client, request).is_err() {
+                            errors += 1;
+                        }
+                        samples.push(request_start.elapsed());
+                    }
+                    (samples, errors)
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().expect("A client panicked"))
+            .collect::<Vec<(Vec<Duration>, usize)>>()
+    });
+    let duration = start.elapsed();
+    let (samples, errors) = results.into_iter().fold(
+        (Vec::new(), 0),
+        |(mut all_samples, all_errors), (samples, errors)| {
+            all_samples.extend(samples);
+            (all_samples, all_errors + errors)
+        },
+    );
+    Ok(ScenarioReport::new(samples, errors, duration))
+}
+
+fn nth_user(seeded: &Seeded, n: usize) -> &SeededUser {
+    &seeded.users[n % seeded.users.len()]
+}
+
+fn bind(target: &Target, seeded: &Seeded, ldap: &mut LdapConn, request: usize) -> Result<()> {
+    // Only the first users have a password.
+    let bind_users = seeded
+        .users
+        .iter()
+        .take_while(|u| u.password.is_some())
+        .count();
+    let user = &seeded.users[request % bind_users.max(1)];
+    ldap.simple_bind(
+        &target.user_dn(&user.id),
+        user.password.as_deref().unwrap_or_default(),
+    )?
+    .success()?;
+    Ok(())
+}
+
+/// Cycles through the filters of the usual clients: lookups by ID and email, group members,
+/// prefix and substring searches.
+fn search(target: &Target, seeded: &Seeded, ldap: &mut LdapConn, request: usize) -> Result<()> {
+    let user = nth_user(seeded, request * 7);
+    let group = seeded
+        .groups
+        .get(request % seeded.groups.len().max(1))
+        .map(String::as_str)
+        .unwrap_or_default();
+    let (first_name, last_name) = user.display_name.split_once(' ').unwrap_or_default();
+    let filter = match request % 6 {
+        0 => format!("(uid={})", user.id),
+        1 => format!("(mail={})", user.email.to_uppercase()),
+        2 => format!(
+            "(&(objectClass=person)(memberOf={}))",
+            target.group_dn(group)
+        ),
+        3 => format!("(cn={}*)", first_name),
+        4 => format!(
+            "(|(uid={})(uid={}))",
+            user.id,
+            nth_user(seeded, request * 7 + 1).id
+        ),
+        _ => format!("(&(objectClass=person)(cn=*{}*))", last_name.to_lowercase()),
+    };
+    ldap.search(
+        &format!("ou=people,{}", target.base_dn),
+        Scope::Subtree,
+        &filter,
+        SEARCH_ATTRIBUTES.to_vec(),
+    )?
+    .success()?;
+    Ok(())
+}
+
+/// Alternates between the members of a group and a search, like the user list of the web UI.
+fn list_users(seeded: &Seeded, graphql: &mut GraphQl, request: usize) -> Result<()> {
+    let filters = if request % 2 == 0 && !seeded.groups.is_empty() {
+        json!({ "memberOf": seeded.groups[request % seeded.groups.len()] })
+    } else {
+        let user = nth_user(seeded, request * 7);
+        let (_, last_name) = user.display_name.split_once(' ').unwrap_or_default();
+        json!({ "search": last_name })
+    };
+    graphql.query(
+        "query($filters: RequestFilter) { users(filters: $filters) { id email displayName groups { id } } }",
+        json!({ "filters": filters }),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use crate::{graphql::GraphQl, Profile, Target};
+use anyhow::{anyhow, bail, Context, Result};
+use ldap3::{exop::PasswordModify, LdapConn};
+use rand::{seq::index::sample, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde_json::{json, Value};
+
+/// The prefix of the seeded users and groups, to tell them apart from the real ones.
+pub const PREFIX: &str = "loadtest-";
+
+/// `createUsers` accepts at most 100 users per request.
+const USER_BATCH_SIZE: usize = 100;
+
+const FIRST_NAMES: [&str; 12] = [
+    "Alice", "Bob", "Carol", "David", "Eve", "Frank", "Grace", "Heidi", "Ivan", "Judy", "Mallory",
+    "Olivia",
+];
+const LAST_NAMES: [&str; 10] = [
+    "Smith",
+    "Johnson",
+    "Garcia",
+    "Dubois",
+    "Rossi",
+    "Kowalski",
+    "Nakamura",
+    "Silva",
+    "O'Brien",
+    "Van der Berg",
+];
+
+pub struct SeededUser {
+    pub id: String,
+    pub email: String,
+    pub display_name: String,
+    /// Only for the first `Profile::bind_users` users.
+    pub password: Option<String>,
+}
+
+pub struct Seeded {
+    pub users: Vec<SeededUser>,
+    pub groups: Vec<String>,
+}
+
+/// Creates the users and groups of the profile, after removing the ones of a previous run.
+pub fn seed(target: &Target, profile: &Profile) -> Result<Seeded> {
+    clean_up(target)?;
+    let graphql = GraphQl::login(&target.http_url, &target.admin_user, &target.admin_password)?;
+    let mut rng = ChaCha8Rng::seed_from_u64(profile.seed);
+
+    let mut groups = Vec::with_capacity(profile.groups);
+    let mut group_ids = Vec::with_capacity(profile.groups);
+    for i in 0..profile.groups {
+        let name = format!("{}group{:03}", PREFIX, i);
+        let data = graphql.query(
+            "mutation($name: String!) { createGroup(name: $name) { id } }",
+            json!({ "name": name }),
+        )?;
+        group_ids.push(data["createGroup"]["id"].clone());
+        groups.push(name);
+    }
+
+    let users = (0..profile.users)
+        .map(|i| {
+            let first_name = FIRST_NAMES[rng.gen_range(0..FIRST_NAMES.len())];
+            let last_name = LAST_NAMES[rng.gen_range(0..LAST_NAMES.len())];
+            let group_count = rng.gen_range(0..=profile.groups.min(3));
+            let user_groups = sample(&mut rng, profile.groups, group_count)
+                .into_iter()
+                .map(|g| group_ids[g].clone())
+                .collect::<Vec<_>>();
+            let user = SeededUser {
+                id: format!("{}user{:05}", PREFIX, i),
+                // Mixed case, to exercise the case-insensitive lookups.
+                email: format!("LoadTest.User{:05}@Example.com", i),
+                display_name: format!("{} {}", first_name, last_name),
+                password: (i < profile.bind_users).then(|| format!("LoadTest-Password-{}", i)),
+            };
+            let input = json!({
+                "id": user.id,
+                "email": user.email,
+                "displayName": user.display_name,
+                "firstName": first_name,
+                "lastName": last_name,
+                "groups": user_groups,
+            });
+            (user, input)
+        })
+        .collect::<Vec<_>>();
+    for batch in users.chunks(USER_BATCH_SIZE) {
+        let inputs = batch.iter().map(|(_, input)| input).collect::<Vec<_>>();
+        let data = graphql.query(
+            "mutation($users: [CreateUserInput!]!) { createUsers(users: $users) { id status error } }",
+            json!({ "users": inputs }),
+        )?;
+        for result in data["createUsers"].as_array().into_iter().flatten() {
+            if result["status"] != "CREATED" {
+                bail!(
+                    "Could not create the user {}: {}",
+                    result["id"],
+                    result["error"]
+                );
+            }
+        }
+    }
+    let users = users.into_iter().map(|(user, _)| user).collect::<Vec<_>>();
+
+    let mut ldap = admin_connection(target)?;
+    for user in &users {
+        if let Some(password) = &user.password {
+            ldap.extended(PasswordModify {
+                user_id: Some(&target.user_dn(&user.id)),
+                old_pass: None,
+                new_pass: Some(password),
+            })?
+            .success()
+            .with_context(|| format!("Could not set the password of {}", user.id))?;
+        }
+    }
+    ldap.unbind()?;
+
+    Ok(Seeded { users, groups })
+}
+
+pub fn admin_connection(target: &Target) -> Result<LdapConn> {
+    let mut ldap = LdapConn::new(&target.ldap_url)
+        .with_context(|| format!("Could not connect to {}", target.ldap_url))?;
+    ldap.simple_bind(&target.user_dn(&target.admin_user), &target.admin_password)?
+        .success()
+        .context("Could not bind as the admin")?;
+    Ok(ldap)
+}
+
+/// Permanently deletes the seeded users and groups.
+pub fn clean_up(target: &Target) -> Result<()> {
+    let graphql = GraphQl::login(&target.http_url, &target.admin_user, &target.admin_password)?;
+    let data = graphql.query(
+        "query($search: String!) { users(filters: {search: $search}) { id } }",
+        json!({ "search": PREFIX }),
+    )?;
+    for user in seeded_entries(&data["users"], "id")? {
+        graphql.query(
+            "mutation($id: String!) { deleteUser(userId: $id, permanent: true) { ok } }",
+            json!({ "id": user["id"] }),
+        )?;
+    }
+    let data = graphql.query("query { groups { id displayName } }", json!({}))?;
+    for group in seeded_entries(&data["groups"], "displayName")? {
+        graphql.query(
+            "mutation($id: Int!) { deleteGroup(groupId: $id) { ok } }",
+            json!({ "id": group["id"] }),
+        )?;
+    }
+    Ok(())
+}
+
+/// The entries of a list whose `name_field` starts with the prefix.
+fn seeded_entries<'a>(list: &'a Value, name_field: &str) -> Result<Vec<&'a Value>> {
+    Ok(list
+        .as_array()
+        .ok_or_else(|| anyhow!("Expected a list, got {}", list))?
+        .iter()
+        .filter(|entry| {
+            entry[name_field]
+                .as_str()
+                .is_some_and(|name| name.starts_with(PREFIX))
+        })
+        .collect())
+}
@@ -1,9 +1,12 @@
-use anyhow::{anyhow, Context, Result};
-use ldap3::{ResultEntry, SearchEntry};
+use anyhow::{anyhow, Result};
+use ldap3::SearchEntry;
 use requestty::{prompt_one, Question};
 use smallvec::SmallVec;
 
-use crate::lldap::User;
+use crate::{
+    lldap::User,
+    mapping::{AttributeRule, GroupMapping, Mapping, Membership, Report},
+};
 
 pub struct LdapClient {
     domain: String,
@@ -140,68 +143,6 @@ fn bind_ldap(
     }
 }
 
-impl TryFrom<ResultEntry> for User {
-    type Error = anyhow::Error;
-
-    fn try_from(value: ResultEntry) -> Result<Self> {
-        let entry = SearchEntry::construct(value);
-        let get_required_attribute = |attr| {
-            entry
-                .attrs
-                .get(attr)
-                .ok_or_else(|| anyhow!("Missing {} for user", attr))
-                .and_then(|u| -> Result<String> {
-                    u.iter()
-                        .next()
-                        .map(String::to_owned)
-                        .ok_or_else(|| anyhow!("Too many {}s", attr))
-                })
-        };
-        let id = get_required_attribute("uid")
-            .or_else(|_| get_required_attribute("sAMAccountName"))
-            .or_else(|_| get_required_attribute("userPrincipalName"))?;
-        let email = get_required_attribute("mail")
-            .or_else(|_| get_required_attribute("rfc822mailbox"))
-            .context(format!("for user '{}'", id))?;
-
-        let get_optional_attribute = |attr: &str| {
-            entry
-                .attrs
-                .get(attr)
-                .and_then(|v| v.first().map(|s| s.as_str()))
-                .filter(|s| !s.is_empty())
-                .map(str::to_owned)
-        };
-        let last_name = get_optional_attribute("sn").or_else(|| get_optional_attribute("surname"));
-        let display_name = get_optional_attribute("cn")
-            .or_else(|| get_optional_attribute("commonName"))
-            .or_else(|| get_optional_attribute("name"))
-            .or_else(|| get_optional_attribute("displayName"));
-        let first_name = get_optional_attribute("givenName");
-        let avatar = entry
-            .attrs
-            .get("jpegPhoto")
-            .map(|v| v.iter().map(|s| s.as_bytes().to_vec()).collect::<Vec<_>>())
-            .or_else(|| entry.bin_attrs.get("jpegPhoto").cloned())
-            .and_then(|v| v.into_iter().next().filter(|s| !s.is_empty()));
-        let password =
-            get_optional_attribute("userPassword").or_else(|| get_optional_attribute("password"));
-        Ok(User::new(
-            crate::lldap::CreateUserInput {
-                id,
-                email,
-                display_name,
-                first_name,
-                last_name,
-                avatar: avatar.map(base64::encode),
-                attributes: None,
-            },
-            password,
-            entry.dn,
-        ))
-    }
-}
-
 enum OuType {
     User,
     Group,
@@ -242,14 +183,32 @@ fn detect_ou(
     Ok((detected_ou, all_ous))
 }
 
-pub fn get_users(connection: &mut LdapClient) -> Result<Vec<User>, anyhow::Error> {
+/// Appends the domain to the DN, unless it's already there.
+fn with_domain(mut dn: String, domain: &str) -> String {
+    if !dn.ends_with(domain) {
+        if !dn.is_empty() {
+            dn += ",";
+        }
+        dn += domain;
+    }
+    dn
+}
+
+/// Returns the users, and adds the entries that are missing a required field to the report.
+pub fn get_users(
+    connection: &mut LdapClient,
+    mapping: &Mapping,
+    report: &mut Report,
+) -> Result<Vec<User>, anyhow::Error> {
     let LdapClient {
         connection: ldap_connection,
         domain,
     } = connection;
     let domain = domain.as_str();
-    let (maybe_user_ou, all_ous) = detect_ou(ldap_connection, domain, OuType::User)?;
-    let user_ou = {
+    let user_ou = if let Some(base) = &mapping.users.base {
+        with_domain(base.clone(), domain)
+    } else {
+        let (maybe_user_ou, all_ous) = detect_ou(ldap_connection, domain, OuType::User)?;
         let question = Question::input("ldap_user_ou")
             .message(format!(
                 "Where are the users located (under '{}')? {}(LDAP_USERS_DN)",
@@ -277,91 +236,82 @@ pub fn get_users(connection: &mut LdapClient) -> Result<Vec<User>, anyhow::Error
             })
             .build();
         let answer = prompt_one(question)?;
-        let mut answer = answer.as_string().unwrap().to_owned();
-        if !answer.ends_with(domain) {
-            if !answer.is_empty() {
-                answer += ",";
-            }
-            answer += domain;
-        }
-        answer
+        with_domain(answer.as_string().unwrap().to_owned(), domain)
     };
     let users = ldap_connection
         .search(
             &user_ou,
             ldap3::Scope::Subtree,
-            "(|(objectClass=inetOrgPerson)(objectClass=person)(objectClass=mailAccount)(objectClass=posixAccount)(objectClass=user)(objectClass=organizationalPerson))",
-            vec![
-                "uid",
-                "sAMAccountName",
-                "userPrincipalName",
-                "mail",
-                "rfc822mailbox",
-                "givenName",
-                "sn",
-                "surname",
-                "cn",
-                "commonName",
-                "displayName",
-                "name",
-                "userPassword",
-            ],
+            &mapping.users.search_filter(),
+            mapping
+                .users
+                .requested_attributes(mapping.groups.membership),
         )?
         .success()?
         .0;
-    users
-        .into_iter()
-        .map(TryFrom::try_from)
-        .collect::<Result<Vec<User>>>()
+    let mut result = Vec::new();
+    for entry in users {
+        let entry = SearchEntry::construct(entry);
+        let dn = entry.dn.clone();
+        match mapping.users.to_user(entry) {
+            Ok(user) => result.push(user),
+            Err(e) => report.add(dn, e),
+        }
+    }
+    Ok(result)
 }
 
 #[derive(Debug)]
 pub struct LdapGroup {
     pub name: String,
+    pub dn: String,
+    /// The DNs of the members.
     pub members: Vec<String>,
+    /// The ids of the members, for [`Membership::MemberUid`].
+    pub member_ids: Vec<String>,
 }
 
-impl TryFrom<ResultEntry> for LdapGroup {
-    type Error = anyhow::Error;
-
-    // https://github.com/graphql-rust/graphql-client/issues/386
-    #[allow(non_snake_case)]
-    fn try_from(value: ResultEntry) -> Result<Self> {
-        let entry = SearchEntry::construct(value);
-        let get_required_attribute = |attr| {
-            entry
-                .attrs
-                .get(attr)
-                .ok_or_else(|| anyhow!("Missing {} for user", attr))
-                .and_then(|u| {
-                    if u.len() > 1 {
-                        Err(anyhow!("Too many {}s", attr))
-                    } else {
-                        Ok(u.first().unwrap().to_owned())
-                    }
-                })
+impl LdapGroup {
+    fn new(entry: SearchEntry, mapping: &GroupMapping, id_rule: &AttributeRule) -> Result<Self> {
+        let name = mapping
+            .name
+            .first(&entry)
+            .ok_or_else(|| anyhow!("Missing the group name (from {})", mapping.name))?;
+        let members = mapping.members(&entry);
+        let (members, member_ids) = match mapping.membership {
+            Some(Membership::MemberUid) => (
+                Vec::new(),
+                members
+                    .into_iter()
+                    .map(|id| id_rule.transform(id))
+                    .collect(),
+            ),
+            _ => (members, Vec::new()),
         };
-        let name = get_required_attribute("cn")
-            .or_else(|_| get_required_attribute("commonName"))
-            .or_else(|_| get_required_attribute("displayName"))
-            .or_else(|_| get_required_attribute("name"))?;
-
-        let get_repeated_attribute = |attr: &str| entry.attrs.get(attr).map(|v| v.to_owned());
-        let members = get_repeated_attribute("member")
-            .or_else(|| get_repeated_attribute("uniqueMember"))
-            .unwrap_or_default();
-        Ok(LdapGroup { name, members })
+        Ok(LdapGroup {
+            name,
+            dn: entry.dn,
+            members,
+            member_ids,
+        })
     }
 }
 
-pub fn get_groups(connection: &mut LdapClient) -> Result<Vec<LdapGroup>> {
+/// Returns the groups, and adds the entries that are missing a required field to the report.
+pub fn get_groups(
+    connection: &mut LdapClient,
+    mapping: &Mapping,
+    report: &mut Report,
+) -> Result<Vec<LdapGroup>> {
     let LdapClient {
         connection: ldap_connection,
         domain,
     } = connection;
     let domain = domain.as_str();
-    let (maybe_group_ou, all_ous) = detect_ou(ldap_connection, domain, OuType::Group)?;
-    let group_ou = {
+    let group_ou = if let Some(base) = &mapping.groups.base {
+        with_domain(base.clone(), domain)
+    } else {
+        let (maybe_group_ou, all_ous) = detect_ou(ldap_connection, domain, OuType::Group)?;
         let question = Question::input("ldap_group_ou")
             .message(format!(
                 "Where are the groups located (under '{}')? {}(LDAP_GROUPS_DN)",
@@ -389,36 +339,27 @@ pub fn get_groups(connection: &mut LdapClient) -> Result<Vec<LdapGroup>> {
             })
             .build();
         let answer = prompt_one(question)?;
-        let mut answer = answer.as_string().unwrap().to_owned();
-        if !answer.ends_with(domain) {
-            if !answer.is_empty() {
-                answer += ",";
-            }
-            answer += domain;
-        }
-        answer
+        with_domain(answer.as_string().unwrap().to_owned(), domain)
     };
     let groups = ldap_connection
         .search(
             &group_ou,
             ldap3::Scope::Subtree,
-            "(|(objectClass=group)(objectClass=groupOfNames)(objectClass=groupOfUniqueNames))",
-            vec![
-                "cn",
-                "commonName",
-                "displayName",
-                "name",
-                "member",
-                "uniqueMember",
-            ],
+            &mapping.groups.search_filter(),
+            mapping.groups.requested_attributes(),
         )?
         .success()?
         .0;
-    let input_groups = groups
-        .into_iter()
-        .map(TryFrom::try_from)
-        .collect::<Result<Vec<LdapGroup>>>()?;
-    Ok(input_groups)
+    let mut result = Vec::new();
+    for entry in groups {
+        let entry = SearchEntry::construct(entry);
+        let dn = entry.dn.clone();
+        match LdapGroup::new(entry, &mapping.groups, &mapping.users.id) {
+            Ok(group) => result.push(group),
+            Err(e) => report.add(dn, e),
+        }
+    }
+    Ok(result)
 }
 
 pub fn get_ldap_connection() -> Result<LdapClient, anyhow::Error> {
@@ -65,6 +65,8 @@ pub struct User {
     pub user_input: create_user::CreateUserInput,
     pub password: Option<String>,
     pub dn: String,
+    /// The DNs of the groups, for [`crate::mapping::Membership::MemberOf`].
+    pub member_of: Vec<String>,
 }
 
 impl User {
@@ -73,11 +75,13 @@ impl User {
         user_input: create_user::CreateUserInput,
         password: Option<String>,
         dn: String,
+        member_of: Vec<String>,
     ) -> User {
         User {
             user_input,
             password,
             dn,
+            member_of,
         }
     }
 }
@@ -93,6 +97,7 @@ impl User {
 struct CreateUser;
 
 pub type CreateUserInput = create_user::CreateUserInput;
+pub type AttributeValueInput = create_user::AttributeValueInput;
 
 #[derive(GraphQLQuery)]
 #[graphql(
@@ -155,6 +160,7 @@ fn try_login(
     let req = ClientLoginFinishRequest {
         server_data: login_start_response.server_data,
         credential_finalization: login_finish.message,
+        remember_me: None,
     };
     let response = client
         .post(format!("{}/auth/opaque/login/finish", lldap_server))
@@ -379,10 +385,11 @@ pub fn insert_group_memberships_into_lldap(
     let existing_groups = HashMap::<&str, &LldapGroup>::from_iter(
         existing_groups.iter().map(|g| (g.display_name.as_str(), g)),
     );
-    let dn_resolver = HashMap::<&str, &str>::from_iter(
+    // The DNs are case-insensitive.
+    let dn_resolver = HashMap::<String, &str>::from_iter(
         ldap_users
             .iter()
-            .map(|u| (u.dn.as_str(), u.user_input.id.as_str())),
+            .map(|u| (u.dn.to_lowercase(), u.user_input.id.as_str())),
     );
     let mut skip_all = false;
     let mut added_membership_count = 0;
@@ -391,12 +398,22 @@ pub fn insert_group_memberships_into_lldap(
             let lldap_members =
                 HashSet::<&str>::from_iter(lldap_group.users.iter().map(|u| u.id.as_str()));
             let mut skip_group = false;
-            for user in &group.members {
-                let user = if let Some(id) = dn_resolver.get(user.as_str()) {
-                    id
-                } else {
-                    continue;
-                };
+            let group_dn = group.dn.to_lowercase();
+            let mut members = group
+                .members
+                .iter()
+                .filter_map(|dn| dn_resolver.get(&dn.to_lowercase()).copied())
+                .chain(group.member_ids.iter().map(String::as_str))
+                .chain(
+                    ldap_users
+                        .iter()
+                        .filter(|u| u.member_of.iter().any(|g| g.to_lowercase() == group_dn))
+                        .map(|u| u.user_input.id.as_str()),
+                )
+                .collect::<Vec<_>>();
+            members.sort_unstable();
+            members.dedup();
+            for user in members {
                 if lldap_members.contains(user) || !existing_users.contains(user) {
                     continue;
                 }
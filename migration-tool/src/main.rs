@@ -1,15 +1,29 @@
 #![allow(clippy::uninlined_format_args)]
 
-use std::collections::HashSet;
+use std::{collections::HashSet, path::PathBuf};
 
 use anyhow::{anyhow, Result};
+use clap::Parser;
 use requestty::{prompt_one, Question};
 
 mod ldap;
 mod lldap;
+mod mapping;
 
 use ldap::LdapGroup;
 use lldap::{LldapGroup, User};
+use mapping::{Mapping, Report};
+
+/// Interactive tool to import the users and groups of an LDAP server (e.g. OpenLDAP or Active
+/// Directory) into LLDAP.
+#[derive(Debug, Parser)]
+#[clap(version, author)]
+struct CliOpts {
+    /// How the source entries translate to LLDAP users and groups: attributes, value
+    /// transforms, memberships and entry filters. See tests/mappings for examples.
+    #[clap(long)]
+    mapping: Option<PathBuf>,
+}
 
 fn ask_generic_confirmation(name: &str, message: &str) -> Result<bool> {
     let confirm = Question::confirm(name)
@@ -92,11 +106,13 @@ struct GroupList {
 fn migrate_groups(
     graphql_client: &lldap::GraphQLClient,
     ldap_connection: &mut ldap::LdapClient,
+    mapping: &Mapping,
+    report: &mut Report,
 ) -> Result<Option<GroupList>> {
     Ok(
         if ask_generic_confirmation("should_import_groups", "Do you want to import groups?")? {
             let mut existing_groups = lldap::get_lldap_groups(graphql_client)?;
-            let ldap_groups = ldap::get_groups(ldap_connection)?;
+            let ldap_groups = ldap::get_groups(ldap_connection, mapping, report)?;
             if should_insert_groups(&ldap_groups, &existing_groups)? {
                 lldap::insert_groups_into_lldap(
                     &ldap_groups,
@@ -122,11 +138,13 @@ struct UserList {
 fn migrate_users(
     graphql_client: &lldap::GraphQLClient,
     ldap_connection: &mut ldap::LdapClient,
+    mapping: &Mapping,
+    report: &mut Report,
 ) -> Result<Option<UserList>> {
     Ok(
         if ask_generic_confirmation("should_import_users", "Do you want to import users?")? {
             let mut existing_users = lldap::get_lldap_users(graphql_client)?;
-            let users = ldap::get_users(ldap_connection)?;
+            let users = ldap::get_users(ldap_connection, mapping, report)?;
             if let Some(users_to_add) = get_users_to_add(&users, &existing_users)? {
                 lldap::insert_users_into_lldap(users_to_add, &mut existing_users, graphql_client)?;
             }
@@ -145,6 +163,8 @@ fn migrate_memberships(
     group_list: Option<GroupList>,
     graphql_client: lldap::GraphQLClient,
     ldap_connection: &mut ldap::LdapClient,
+    mapping: &Mapping,
+    report: &mut Report,
 ) -> Result<()> {
     let (ldap_users, existing_users) = user_list
         .map(
@@ -164,10 +184,10 @@ fn migrate_memberships(
         .unwrap_or_default();
     let ldap_users = ldap_users
         .ok_or_else(|| anyhow!("Missing LDAP users"))
-        .or_else(|_| ldap::get_users(ldap_connection))?;
+        .or_else(|_| ldap::get_users(ldap_connection, mapping, report))?;
     let ldap_groups = ldap_groups
         .ok_or_else(|| anyhow!("Missing LDAP groups"))
-        .or_else(|_| ldap::get_groups(ldap_connection))?;
+        .or_else(|_| ldap::get_groups(ldap_connection, mapping, report))?;
     let existing_groups = existing_groups
         .ok_or_else(|| anyhow!("Missing LLDAP groups"))
         .or_else(|_| lldap::get_lldap_groups(&graphql_client))?;
@@ -185,6 +205,11 @@ fn migrate_memberships(
 }
 
 fn main() -> Result<()> {
+    let opts = CliOpts::parse();
+    let mapping = match &opts.mapping {
+        Some(path) => Mapping::from_file(path)?,
+        None => Mapping::default(),
+    };
     println!(
         "The migration tool requires access to both the original LDAP \
          server and the HTTP API of the target LLDAP server."
@@ -194,14 +219,23 @@ fn main() -> Result<()> {
     }
     let mut ldap_connection = ldap::get_ldap_connection()?;
     let graphql_client = lldap::get_lldap_client()?;
-    let user_list = migrate_users(&graphql_client, &mut ldap_connection)?;
-    let group_list = migrate_groups(&graphql_client, &mut ldap_connection)?;
+    let mut report = Report::default();
+    let user_list = migrate_users(&graphql_client, &mut ldap_connection, &mapping, &mut report)?;
+    let group_list = migrate_groups(&graphql_client, &mut ldap_connection, &mapping, &mut report)?;
     if ask_generic_confirmation(
         "should_import_memberships",
         "Do you want to import group memberships?",
     )? {
-        migrate_memberships(user_list, group_list, graphql_client, &mut ldap_connection)?;
+        migrate_memberships(
+            user_list,
+            group_list,
+            graphql_client,
+            &mut ldap_connection,
+            &mapping,
+            &mut report,
+        )?;
     }
+    report.print();
 
     Ok(())
 }
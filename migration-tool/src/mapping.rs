@@ -0,0 +1,634 @@
+//! How the entries of the source directory translate to LLDAP users and groups.
+//!
+//! The defaults match the usual OpenLDAP and Active Directory attributes. A mapping file
+//! (`--mapping mapping.toml`) overrides them, e.g.:
+//!
+//! ```toml
+//! [users]
+//! base = "ou=staff"
+//! filter = "(objectClass=user)"
+//! exclude = "(objectClass=computer)"
+//! id = { source = "sAMAccountName", transforms = ["lowercase"] }
+//! email = ["mail", "userPrincipalName"]
+//!
+//! [users.attributes]
+//! mail_alias = { source = "proxyAddresses", transforms = [{ strip_prefix = "smtp:" }] }
+//!
+//! [groups]
+//! membership = "memberUid"
+//! ```
+
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use ldap3::SearchEntry;
+use serde::Deserialize;
+
+use crate::lldap::{AttributeValueInput, CreateUserInput, User};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Mapping {
+    pub users: UserMapping,
+    pub groups: GroupMapping,
+}
+
+impl Mapping {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read the mapping file {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("Invalid mapping file {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mapping: Mapping = toml::from_str(contents)?;
+        for filter in [
+            Some(&mapping.users.filter),
+            mapping.users.exclude.as_ref(),
+            Some(&mapping.groups.filter),
+            mapping.groups.exclude.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !filter.starts_with('(') || !filter.ends_with(')') {
+                bail!(
+                    "Invalid LDAP filter '{}', expected something like '(objectClass=person)'",
+                    filter
+                );
+            }
+        }
+        Ok(mapping)
+    }
+}
+
+/// A change applied to the values of a source attribute, in order.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    Lowercase,
+    /// Case-insensitive, e.g. a domain: `{ strip_suffix = "@example.com" }`.
+    StripSuffix(String),
+    /// Case-insensitive, e.g. `{ strip_prefix = "smtp:" }`.
+    StripPrefix(String),
+}
+
+impl Transform {
+    fn apply(&self, value: String) -> String {
+        match self {
+            Transform::Lowercase => value.to_lowercase(),
+            Transform::StripSuffix(suffix) => {
+                let start = value.len().wrapping_sub(suffix.len());
+                match value.get(start..) {
+                    Some(end) if end.eq_ignore_ascii_case(suffix) => value[..start].to_owned(),
+                    _ => value,
+                }
+            }
+            Transform::StripPrefix(prefix) => match value.get(..prefix.len()) {
+                Some(start) if start.eq_ignore_ascii_case(prefix) => {
+                    value[prefix.len()..].to_owned()
+                }
+                _ => value,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Sources {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<Sources> for Vec<String> {
+    fn from(sources: Sources) -> Self {
+        match sources {
+            Sources::One(source) => vec![source],
+            Sources::Many(sources) => sources,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DetailedRule {
+    source: Sources,
+    #[serde(default)]
+    transforms: Vec<Transform>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawAttributeRule {
+    Sources(Sources),
+    Detailed(DetailedRule),
+}
+
+/// Where a field comes from: the first of the source attributes that the entry has, e.g.
+/// `"uid"`, `["uid", "sAMAccountName"]` or `{ source = "uid", transforms = ["lowercase"] }`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "RawAttributeRule")]
+pub struct AttributeRule {
+    pub sources: Vec<String>,
+    pub transforms: Vec<Transform>,
+}
+
+impl From<RawAttributeRule> for AttributeRule {
+    fn from(rule: RawAttributeRule) -> Self {
+        match rule {
+            RawAttributeRule::Sources(sources) => Self {
+                sources: sources.into(),
+                transforms: Vec::new(),
+            },
+            RawAttributeRule::Detailed(DetailedRule { source, transforms }) => Self {
+                sources: source.into(),
+                transforms,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for AttributeRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.sources.join(" or "))
+    }
+}
+
+/// The values of the attribute, ignoring the case of its name like LDAP does.
+fn get_attribute<'a, T>(
+    attributes: &'a std::collections::HashMap<String, Vec<T>>,
+    name: &str,
+) -> Option<&'a Vec<T>> {
+    attributes
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, values)| values)
+}
+
+impl AttributeRule {
+    fn new(sources: &[&str]) -> Self {
+        Self {
+            sources: sources.iter().map(|s| s.to_string()).collect(),
+            transforms: Vec::new(),
+        }
+    }
+
+    pub fn transform(&self, value: String) -> String {
+        self.transforms
+            .iter()
+            .fold(value, |value, transform| transform.apply(value))
+    }
+
+    /// The non-empty, transformed values of the first source attribute that the entry has.
+    pub fn values(&self, entry: &SearchEntry) -> Vec<String> {
+        self.sources
+            .iter()
+            .filter_map(|source| get_attribute(&entry.attrs, source))
+            .map(|values| {
+                values
+                    .iter()
+                    .filter(|v| !v.is_empty())
+                    .map(|v| self.transform(v.clone()))
+                    .filter(|v| !v.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .find(|values| !values.is_empty())
+            .unwrap_or_default()
+    }
+
+    pub fn first(&self, entry: &SearchEntry) -> Option<String> {
+        self.values(entry).into_iter().next()
+    }
+
+    /// The first non-empty value, without transforms, also looking at the binary attributes.
+    pub fn binary(&self, entry: &SearchEntry) -> Option<Vec<u8>> {
+        self.sources.iter().find_map(|source| {
+            get_attribute(&entry.bin_attrs, source)
+                .and_then(|values| values.iter().find(|v| !v.is_empty()).cloned())
+                .or_else(|| {
+                    get_attribute(&entry.attrs, source)
+                        .and_then(|values| values.iter().find(|v| !v.is_empty()))
+                        .map(|v| v.as_bytes().to_vec())
+                })
+        })
+    }
+}
+
+/// The LDAP search filter for `filter`, without the entries matching `exclude`.
+fn search_filter(filter: &str, exclude: Option<&str>) -> String {
+    match exclude {
+        Some(exclude) => format!("(&{}(!{}))", filter, exclude),
+        None => filter.to_owned(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct UserMapping {
+    /// Where the users are, e.g. "ou=people". Asked interactively if not set.
+    pub base: Option<String>,
+    /// The entries that become users.
+    pub filter: String,
+    /// The entries to skip among those.
+    pub exclude: Option<String>,
+    pub id: AttributeRule,
+    pub email: AttributeRule,
+    pub display_name: AttributeRule,
+    pub first_name: AttributeRule,
+    pub last_name: AttributeRule,
+    pub avatar: AttributeRule,
+    pub password: AttributeRule,
+    /// Only the first phone number is kept, LLDAP has a single one.
+    pub phone: AttributeRule,
+    pub mobile: AttributeRule,
+    pub postal_address: AttributeRule,
+    pub locality: AttributeRule,
+    pub state_or_province: AttributeRule,
+    /// The custom attributes of LDAP, by name. They must be defined in the LLDAP schema.
+    pub attributes: BTreeMap<String, AttributeRule>,
+}
+
+impl Default for UserMapping {
+    fn default() -> Self {
+        Self {
+            base: None,
+            filter: "(|(objectClass=inetOrgPerson)(objectClass=person)(objectClass=mailAccount)(objectClass=posixAccount)(objectClass=user)(objectClass=organizationalPerson))".to_owned(),
+            exclude: None,
+            id: AttributeRule::new(&["uid", "sAMAccountName", "userPrincipalName"]),
+            email: AttributeRule::new(&["mail", "rfc822mailbox"]),
+            display_name: AttributeRule::new(&["cn", "commonName", "name", "displayName"]),
+            first_name: AttributeRule::new(&["givenName"]),
+            last_name: AttributeRule::new(&["sn", "surname"]),
+            avatar: AttributeRule::new(&["jpegPhoto"]),
+            password: AttributeRule::new(&["userPassword", "password"]),
+            phone: AttributeRule::new(&["telephoneNumber"]),
+            mobile: AttributeRule::new(&["mobile", "mobileTelephoneNumber"]),
+            postal_address: AttributeRule::new(&["postalAddress"]),
+            locality: AttributeRule::new(&["l", "localityName"]),
+            state_or_province: AttributeRule::new(&["st", "stateOrProvinceName"]),
+            attributes: BTreeMap::new(),
+        }
+    }
+}
+
+impl UserMapping {
+    pub fn search_filter(&self) -> String {
+        search_filter(&self.filter, self.exclude.as_deref())
+    }
+
+    fn rules(&self) -> impl Iterator<Item = &AttributeRule> {
+        [
+            &self.id,
+            &self.email,
+            &self.display_name,
+            &self.first_name,
+            &self.last_name,
+            &self.avatar,
+            &self.password,
+            &self.phone,
+            &self.mobile,
+            &self.postal_address,
+            &self.locality,
+            &self.state_or_province,
+        ]
+        .into_iter()
+        .chain(self.attributes.values())
+    }
+
+    /// The source attributes to fetch, with the group memberships for [`Membership::MemberOf`].
+    pub fn requested_attributes(&self, membership: Option<Membership>) -> Vec<String> {
+        let mut attributes = self
+            .rules()
+            .flat_map(|rule| rule.sources.iter().cloned())
+            .collect::<Vec<_>>();
+        if membership == Some(Membership::MemberOf) {
+            attributes.push("memberOf".to_owned());
+        }
+        attributes.sort();
+        attributes.dedup();
+        attributes
+    }
+
+    pub fn to_user(&self, entry: SearchEntry) -> Result<User> {
+        let id = self
+            .id
+            .first(&entry)
+            .ok_or_else(|| anyhow!("Missing the user id (from {})", self.id))?;
+        let email = self
+            .email
+            .first(&entry)
+            .ok_or_else(|| anyhow!("Missing the email (from {}) for user '{}'", self.email, id))?;
+        let attributes = self
+            .attributes
+            .iter()
+            .map(|(name, rule)| AttributeValueInput {
+                name: name.clone(),
+                value: rule.values(&entry),
+            })
+            .filter(|a| !a.value.is_empty())
+            .collect::<Vec<_>>();
+        let member_of = get_attribute(&entry.attrs, "memberOf")
+            .cloned()
+            .unwrap_or_default();
+        Ok(User::new(
+            CreateUserInput {
+                id,
+                email,
+                display_name: self.display_name.first(&entry),
+                first_name: self.first_name.first(&entry),
+                last_name: self.last_name.first(&entry),
+                avatar: self.avatar.binary(&entry).map(base64::encode),
+                preferred_language: None,
+                hosts: None,
+                phone: self.phone.first(&entry),
+                mobile: self.mobile.first(&entry),
+                // The lines of the LDAP postal addresses are separated by '$'.
+                postal_address: self
+                    .postal_address
+                    .first(&entry)
+                    .map(|address| address.replace('$', "\n")),
+                locality: self.locality.first(&entry),
+                state_or_province: self.state_or_province.first(&entry),
+                attributes: (!attributes.is_empty()).then_some(attributes),
+                groups: None,
+            },
+            self.password.first(&entry),
+            entry.dn,
+            member_of,
+        ))
+    }
+}
+
+/// Where the members of the groups are listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Membership {
+    /// The DNs of the members, in the groups.
+    Member,
+    /// The DNs of the members, in the groups.
+    UniqueMember,
+    /// The ids of the members (before the `id` transforms), in the groups.
+    MemberUid,
+    /// The DNs of the groups, in the users.
+    MemberOf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct GroupMapping {
+    /// Where the groups are, e.g. "ou=groups". Asked interactively if not set.
+    pub base: Option<String>,
+    /// The entries that become groups.
+    pub filter: String,
+    /// The entries to skip among those.
+    pub exclude: Option<String>,
+    pub name: AttributeRule,
+    /// Defaults to "member", or "uniqueMember" for the groups without "member".
+    pub membership: Option<Membership>,
+}
+
+impl Default for GroupMapping {
+    fn default() -> Self {
+        Self {
+            base: None,
+            filter:
+                "(|(objectClass=group)(objectClass=groupOfNames)(objectClass=groupOfUniqueNames))"
+                    .to_owned(),
+            exclude: None,
+            name: AttributeRule::new(&["cn", "commonName", "displayName", "name"]),
+            membership: None,
+        }
+    }
+}
+
+impl GroupMapping {
+    pub fn search_filter(&self) -> String {
+        search_filter(&self.filter, self.exclude.as_deref())
+    }
+
+    fn member_attributes(&self) -> &'static [&'static str] {
+        match self.membership {
+            None => &["member", "uniqueMember"],
+            Some(Membership::Member) => &["member"],
+            Some(Membership::UniqueMember) => &["uniqueMember"],
+            Some(Membership::MemberUid) => &["memberUid"],
+            Some(Membership::MemberOf) => &[],
+        }
+    }
+
+    pub fn requested_attributes(&self) -> Vec<String> {
+        let mut attributes = self.name.sources.clone();
+        attributes.extend(self.member_attributes().iter().map(|a| a.to_string()));
+        attributes
+    }
+
+    /// The members listed in the group: DNs, or user ids for [`Membership::MemberUid`].
+    pub fn members(&self, entry: &SearchEntry) -> Vec<String> {
+        self.member_attributes()
+            .iter()
+            .find_map(|attribute| get_attribute(&entry.attrs, attribute))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// The entries that could not be converted: they are skipped, and reported at the end.
+#[derive(Default)]
+pub struct Report {
+    errors: Vec<(String, anyhow::Error)>,
+}
+
+impl Report {
+    pub fn add(&mut self, dn: String, error: anyhow::Error) {
+        self.errors.push((dn, error));
+    }
+
+    pub fn print(&self) {
+        if self.errors.is_empty() {
+            return;
+        }
+        println!("{} entries were skipped:", self.errors.len());
+        for (dn, error) in &self.errors {
+            println!("  {}: {:#}", dn, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn entry(dn: &str, attrs: &[(&str, &[&str])]) -> SearchEntry {
+        SearchEntry {
+            dn: dn.to_owned(),
+            attrs: attrs
+                .iter()
+                .map(|(name, values)| {
+                    (
+                        name.to_string(),
+                        values.iter().map(|v| v.to_string()).collect(),
+                    )
+                })
+                .collect(),
+            bin_attrs: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_transforms() {
+        let rule = AttributeRule {
+            sources: vec!["proxyAddresses".to_owned()],
+            transforms: vec![
+                Transform::StripPrefix("smtp:".to_owned()),
+                Transform::StripSuffix("@Example.com".to_owned()),
+                Transform::Lowercase,
+            ],
+        };
+        assert_eq!(rule.transform("SMTP:John@example.COM".to_owned()), "john");
+        assert_eq!(rule.transform("x400:John".to_owned()), "x400:john");
+        assert_eq!(rule.transform("é".to_owned()), "é");
+    }
+
+    #[test]
+    fn test_default_mapping() {
+        let mapping = Mapping::default();
+        let user = mapping
+            .users
+            .to_user(entry(
+                "uid=bob,ou=people,dc=example,dc=com",
+                &[
+                    ("uid", &["bob"]),
+                    ("mail", &["bob@example.com"]),
+                    ("cn", &["Bob Bobberson"]),
+                    ("sn", &[""]),
+                ],
+            ))
+            .unwrap();
+        assert_eq!(user.user_input.id, "bob");
+        assert_eq!(user.user_input.email, "bob@example.com");
+        assert_eq!(
+            user.user_input.display_name.as_deref(),
+            Some("Bob Bobberson")
+        );
+        assert_eq!(user.user_input.last_name, None);
+        assert_eq!(user.dn, "uid=bob,ou=people,dc=example,dc=com");
+        assert_eq!(
+            mapping
+                .users
+                .to_user(entry("uid=john,dc=example,dc=com", &[("uid", &["john"])]))
+                .unwrap_err()
+                .to_string(),
+            "Missing the email (from mail or rfc822mailbox) for user 'john'"
+        );
+    }
+
+    #[test]
+    fn test_active_directory_mapping() {
+        let mapping =
+            Mapping::parse(include_str!("../tests/mappings/active_directory.toml")).unwrap();
+        assert_eq!(mapping.users.base.as_deref(), Some("cn=Users"));
+        assert_eq!(
+            mapping.users.search_filter(),
+            "(&(objectClass=user)(!(|(objectClass=computer)(sAMAccountName=krbtgt))))"
+        );
+        let user = mapping
+            .users
+            .to_user(entry(
+                "CN=John Doe,CN=Users,DC=example,DC=com",
+                &[
+                    ("sAMAccountName", &["JDoe"]),
+                    ("userPrincipalName", &["JDoe@Example.com"]),
+                    ("displayName", &["John Doe"]),
+                    (
+                        "proxyAddresses",
+                        &["SMTP:john.doe@example.com", "smtp:jd@example.com"],
+                    ),
+                    ("memberOf", &["CN=Admins,CN=Users,DC=example,DC=com"]),
+                ],
+            ))
+            .unwrap();
+        assert_eq!(user.user_input.id, "jdoe");
+        assert_eq!(user.user_input.email, "JDoe@Example.com");
+        assert_eq!(user.user_input.display_name.as_deref(), Some("John Doe"));
+        let attributes = user.user_input.attributes.unwrap();
+        assert_eq!(attributes.len(), 1);
+        assert_eq!(attributes[0].name, "mail_alias");
+        assert_eq!(
+            attributes[0].value,
+            vec!["john.doe@example.com", "jd@example.com"]
+        );
+        assert_eq!(user.member_of, vec!["CN=Admins,CN=Users,DC=example,DC=com"]);
+        assert_eq!(mapping.groups.membership, Some(Membership::MemberOf));
+        assert!(mapping
+            .users
+            .requested_attributes(mapping.groups.membership)
+            .contains(&"memberOf".to_owned()));
+        assert_eq!(
+            mapping
+                .users
+                .to_user(entry(
+                    "CN=Printer,CN=Users,DC=example,DC=com",
+                    &[("mail", &["printer@example.com"])]
+                ))
+                .unwrap_err()
+                .to_string(),
+            "Missing the user id (from sAMAccountName)"
+        );
+    }
+
+    #[test]
+    fn test_openldap_mapping() {
+        let mapping = Mapping::parse(include_str!("../tests/mappings/openldap.toml")).unwrap();
+        let user = mapping
+            .users
+            .to_user(entry(
+                "uid=bob,ou=people,dc=example,dc=com",
+                &[
+                    ("uid", &["Bob@example.com"]),
+                    ("mail", &["bob@example.com"]),
+                    ("givenName", &["Bob"]),
+                    ("SN", &["Bobberson"]),
+                    ("telephoneNumber", &["+1 555 010-9999", "+1 555 010-0000"]),
+                    ("postalAddress", &["1 Main Street$Springfield"]),
+                ],
+            ))
+            .unwrap();
+        assert_eq!(user.user_input.id, "bob");
+        assert_eq!(user.user_input.first_name.as_deref(), Some("Bob"));
+        assert_eq!(user.user_input.last_name.as_deref(), Some("Bobberson"));
+        assert_eq!(user.user_input.phone.as_deref(), Some("+1 555 010-9999"));
+        assert_eq!(
+            user.user_input.postal_address.as_deref(),
+            Some("1 Main Street\nSpringfield")
+        );
+        let group = entry(
+            "cn=devs,ou=groups,dc=example,dc=com",
+            &[
+                ("cn", &["devs"]),
+                ("memberUid", &["Bob@example.com", "john"]),
+                ("member", &["uid=bob,ou=people,dc=example,dc=com"]),
+            ],
+        );
+        assert_eq!(mapping.groups.name.first(&group).as_deref(), Some("devs"));
+        assert_eq!(
+            mapping.groups.members(&group),
+            vec!["Bob@example.com", "john"]
+        );
+        assert_eq!(mapping.groups.search_filter(), "(objectClass=posixGroup)");
+    }
+
+    #[test]
+    fn test_invalid_mapping() {
+        assert!(Mapping::parse("[users]\nid = 3").is_err());
+        assert!(Mapping::parse("[users]\nunknown = \"uid\"").is_err());
+        assert!(Mapping::parse("[groups]\nmembership = \"members\"").is_err());
+        assert_eq!(
+            Mapping::parse("[users]\nfilter = \"objectClass=user\"")
+                .unwrap_err()
+                .to_string(),
+            "Invalid LDAP filter 'objectClass=user', expected something like '(objectClass=person)'"
+        );
+    }
+}
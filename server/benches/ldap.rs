@@ -0,0 +1,198 @@
+//! Micro-benchmarks of the LDAP hot paths that don't touch the database: the translation of the
+//! search filters to SQL, and the serialization of the search result entries.
+//!
+//! Run with `cargo bench -p lldap`. The load test harness (`lldap_load_test`) covers the full
+//! requests against a running server.
+
+// The server is a binary: the benchmarks include its modules directly.
+#![allow(dead_code)]
+#![allow(clippy::blocks_in_conditions)]
+
+#[path = "../src/domain/mod.rs"]
+mod domain;
+#[path = "../src/infra/mod.rs"]
+mod infra;
+
+use crate::domain::{
+    handler::ReadSchemaBackendHandler,
+    ldap::{
+        user::{convert_user_filter, convert_users_to_ldap_op},
+        utils::{parse_distinguished_name, LdapInfo},
+    },
+    model,
+    schema::PublicSchema,
+    sql_backend_handler::SqlBackendHandler,
+    sql_tables::init_table,
+    sql_user_backend_handler::get_user_filter_expr,
+    types::{AttributeValue, GroupDetails, GroupId, Serialized, User, UserAndGroups, UserId, Uuid},
+};
+use bytes::BytesMut;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use ldap3_proto::{
+    proto::{LdapFilter, LdapMsg, LdapSubstringFilter},
+    LdapCodec,
+};
+use sea_orm::{DbBackend, EntityTrait, QueryFilter, QueryTrait};
+use tokio_util::codec::Encoder;
+
+const BASE_DN: &str = "dc=example,dc=com";
+
+fn ldap_info() -> LdapInfo {
+    LdapInfo {
+        base_dn: parse_distinguished_name(BASE_DN).unwrap(),
+        base_dn_str: BASE_DN.to_owned(),
+        ignored_user_attributes: Vec::new(),
+        ignored_group_attributes: Vec::new(),
+        search_page_size: 500,
+        host_wildcard: false,
+    }
+}
+
+/// The schema of a new database.
+fn default_schema() -> PublicSchema {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let pool = sea_orm::Database::connect("sqlite::memory:").await.unwrap();
+        init_table(&pool).await.unwrap();
+        let handler = SqlBackendHandler::new(Default::default(), pool);
+        PublicSchema::from(handler.get_schema().await.unwrap())
+    })
+}
+
+/// A filter typical of the mail servers and the SSO proxies.
+fn user_filter() -> LdapFilter {
+    LdapFilter::And(vec![
+        LdapFilter::Equality("objectClass".to_owned(), "person".to_owned()),
+        LdapFilter::Or(vec![
+            LdapFilter::Equality("uid".to_owned(), "bob".to_owned()),
+            LdapFilter::Equality("mail".to_owned(), "Bob@example.com".to_owned()),
+            LdapFilter::Substring(
+                "cn".to_owned(),
+                LdapSubstringFilter {
+                    initial: Some("Bob".to_owned()),
+                    any: vec!["van".to_owned()],
+                    final_: None,
+                },
+            ),
+        ]),
+        LdapFilter::Equality(
+            "memberOf".to_owned(),
+            format!("cn=mail_users,ou=groups,{}", BASE_DN),
+        ),
+        LdapFilter::Not(Box::new(LdapFilter::Equality(
+            "givenName".to_owned(),
+            "Robert".to_owned(),
+        ))),
+    ])
+}
+
+fn users(count: usize) -> Vec<UserAndGroups> {
+    let now = chrono::Utc::now();
+    (0..count)
+        .map(|i| {
+            let user_id = format!("user{:05}", i);
+            UserAndGroups {
+                user: User {
+                    user_id: UserId::new(&user_id),
+                    email: format!("{}@example.com", user_id).into(),
+                    display_name: Some(format!("User {}", i)),
+                    creation_date: now,
+                    uuid: Uuid::from_name_and_date(&user_id, &now),
+                    last_login: Some(now),
+                    password_changed_at: None,
+                    preferred_language: None,
+                    hosts: Vec::new(),
+                    phone: Some(format!("+1555010{:04}", i % 10000)),
+                    mobile: None,
+                    postal_address: None,
+                    locality: Some("Springfield".to_owned()),
+                    state_or_province: None,
+                    expires_at: None,
+                    attributes: vec![
+                        AttributeValue {
+                            name: "first_name".into(),
+                            value: Serialized::from("User"),
+                        },
+                        AttributeValue {
+                            name: "last_name".into(),
+                            value: Serialized::from(&i.to_string()),
+                        },
+                    ],
+                },
+                groups: Some(
+                    (0..3)
+                        .map(|g| GroupDetails {
+                            group_id: GroupId(g),
+                            display_name: format!("group{}", g).into(),
+                            creation_date: now,
+                            uuid: Uuid::from_name_and_date(&format!("group{}", g), &now),
+                            description: None,
+                            notes: None,
+                            attributes: Vec::new(),
+                        })
+                        .collect(),
+                ),
+            }
+        })
+        .collect()
+}
+
+fn filter_translation(c: &mut Criterion) {
+    let ldap_info = ldap_info();
+    let schema = default_schema();
+    let filter = user_filter();
+    c.bench_function("ldap filter to user filter", |b| {
+        b.iter(|| convert_user_filter(&ldap_info, black_box(&filter), &schema).unwrap())
+    });
+    let user_filter = convert_user_filter(&ldap_info, &filter, &schema).unwrap();
+    c.bench_function("user filter to sql", |b| {
+        b.iter_batched(
+            || user_filter.clone(),
+            |f| {
+                model::User::find()
+                    .filter(get_user_filter_expr(f))
+                    .build(DbBackend::Postgres)
+                    .to_string()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn entry_serialization(c: &mut Criterion) {
+    let ldap_info = ldap_info();
+    let schema = default_schema();
+    let attributes = vec![
+        "*".to_owned(),
+        "memberOf".to_owned(),
+        "entryUuid".to_owned(),
+    ];
+    c.bench_function("500 user entries to ber", |b| {
+        b.iter_batched(
+            || users(500),
+            |users| {
+                let mut codec = LdapCodec::default();
+                let mut buffer = BytesMut::new();
+                for op in
+                    convert_users_to_ldap_op(users, &attributes, &ldap_info, &schema, |_, _| true)
+                {
+                    codec
+                        .encode(
+                            LdapMsg {
+                                msgid: 2,
+                                op,
+                                ctrl: Vec::new(),
+                            },
+                            &mut buffer,
+                        )
+                        .unwrap();
+                }
+                buffer.len()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, filter_translation, entry_serialization);
+criterion_main!(benches);
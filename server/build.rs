@@ -0,0 +1,92 @@
+//! Captures the build information shown by `lldap version`, see `infra::build_info`. Everything is
+//! optional: outside of a git checkout (e.g. from a source archive), the commit is unknown unless
+//! `LLDAP_GIT_COMMIT` is set, and `SOURCE_DATE_EPOCH` replaces the build time for reproducible
+//! builds.
+
+use std::process::Command;
+
+/// The dependencies whose version is reported, as named in Cargo.lock.
+const COMPONENTS: [&str; 5] = [
+    "ldap3_proto",
+    "opaque-ke",
+    "juniper",
+    "sea-orm",
+    "actix-web",
+];
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+}
+
+fn git_info() {
+    println!("cargo:rerun-if-env-changed=LLDAP_GIT_COMMIT");
+    if let Ok(commit) = std::env::var("LLDAP_GIT_COMMIT") {
+        println!("cargo:rustc-env=LLDAP_GIT_COMMIT={}", commit);
+        return;
+    }
+    let Some(commit) = git(&["rev-parse", "HEAD"]) else {
+        return;
+    };
+    println!("cargo:rustc-env=LLDAP_GIT_COMMIT={}", commit);
+    if let Some(dirty) = git(&["status", "--porcelain", "--untracked-files=no"]) {
+        println!("cargo:rustc-env=LLDAP_GIT_DIRTY={}", !dirty.is_empty());
+    }
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/index", git_dir);
+    }
+}
+
+fn build_timestamp() {
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let timestamp = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch,
+        Err(_) => std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs().to_string())
+            .unwrap_or_default(),
+    };
+    println!("cargo:rustc-env=LLDAP_BUILD_TIMESTAMP={}", timestamp);
+}
+
+/// The versions from the lock file of the workspace, as "name=version,name=version".
+fn component_versions() {
+    let lock_file =
+        std::path::Path::new(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("../Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock_file.display());
+    let Ok(lock) = std::fs::read_to_string(&lock_file) else {
+        return;
+    };
+    let mut versions = Vec::new();
+    for component in COMPONENTS {
+        let name_line = format!("name = \"{}\"", component);
+        let version = lock
+            .lines()
+            .skip_while(|line| *line != name_line)
+            .nth(1)
+            .and_then(|line| line.strip_prefix("version = \""))
+            .and_then(|version| version.strip_suffix('"'));
+        if let Some(version) = version {
+            versions.push(format!("{}={}", component, version));
+        }
+    }
+    println!(
+        "cargo:rustc-env=LLDAP_COMPONENT_VERSIONS={}",
+        versions.join(",")
+    );
+}
+
+fn main() {
+    git_info();
+    build_timestamp();
+    component_versions();
+    println!(
+        "cargo:rustc-env=LLDAP_BUILD_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+    println!("cargo:rerun-if-changed=build.rs");
+}
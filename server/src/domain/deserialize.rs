@@ -1,5 +1,8 @@
-use crate::domain::types::{AttributeType, JpegPhoto, Serialized};
-use anyhow::{bail, Context as AnyhowContext};
+use crate::domain::{
+    handler::AttributeList,
+    types::{AttributeName, AttributeType, AttributeValue, JpegPhoto, Serialized},
+};
+use anyhow::{anyhow, bail, Context as AnyhowContext};
 
 pub fn deserialize_attribute_value(
     value: &[String],
@@ -48,3 +51,32 @@ pub fn deserialize_attribute_value(
         ),
     })
 }
+
+/// Checks the values given for an attribute against the schema, from the API or an import.
+pub fn deserialize_attribute(
+    attribute_schema: &AttributeList,
+    name: &str,
+    value: &[String],
+    is_admin: bool,
+) -> anyhow::Result<AttributeValue> {
+    let attribute_name = AttributeName::from(name);
+    let attribute_schema = attribute_schema
+        .get_attribute_schema(&attribute_name)
+        .ok_or_else(|| anyhow!("Attribute {} is not defined in the schema", name))?;
+    if !is_admin && !attribute_schema.is_editable {
+        bail!(
+            "Permission denied: Attribute {} is not editable by regular users",
+            name
+        );
+    }
+    let deserialized_values = deserialize_attribute_value(
+        value,
+        attribute_schema.attribute_type,
+        attribute_schema.is_list,
+    )
+    .context(format!("While deserializing attribute {}", name))?;
+    Ok(AttributeValue {
+        name: attribute_name,
+        value: deserialized_values,
+    })
+}
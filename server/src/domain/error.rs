@@ -6,9 +6,9 @@ pub enum DomainError {
     #[error("Authentication error: `{0}`")]
     AuthenticationError(String),
     #[error("Database error: `{0}`")]
-    DatabaseError(#[from] sea_orm::DbErr),
+    DatabaseError(sea_orm::DbErr),
     #[error("Database transaction error: `{0}`")]
-    DatabaseTransactionError(#[from] sea_orm::TransactionError<sea_orm::DbErr>),
+    DatabaseTransactionError(sea_orm::TransactionError<sea_orm::DbErr>),
     #[error("Authentication protocol error for `{0}`")]
     AuthenticationProtocolError(#[from] lldap_auth::opaque::AuthenticationError),
     #[error("Unknown crypto error: `{0}`")]
@@ -17,10 +17,52 @@ pub enum DomainError {
     BinarySerializationError(#[from] bincode::Error),
     #[error("Invalid base64: `{0}`")]
     Base64DecodeError(#[from] base64::DecodeError),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Email already in use: `{0}`")]
+    EmailAlreadyInUse(String),
     #[error("Entity not found: `{0}`")]
     EntityNotFound(String),
+    /// Nobody could administrate the server anymore.
+    #[error("Cannot remove `{0}` from lldap_admin: it is the last active admin")]
+    LastAdmin(String),
+    /// The user is the last active member of these groups, see `EmptyGroupPolicy`.
+    #[error("Cannot delete `{0}`: it is the last member of {}", .1.join(", "))]
+    LastGroupMember(String, Vec<String>),
     #[error("Internal error: `{0}`")]
     InternalError(String),
+    /// Temporary: the request can be retried later.
+    #[error("Server busy: `{0}`")]
+    ServerBusy(String),
+    /// The server is a replica of another LLDAP instance, the changes have to be made there.
+    #[error("Read-only replica: the changes have to be made on the primary server")]
+    ReadOnlyReplica,
+}
+
+const CONNECTION_ACQUIRE_TIMEOUT: &str = "Timed out waiting for a database connection";
+
+/// Running out of connections is temporary: it is reported as `ServerBusy`, for the clients to
+/// retry, rather than as a database error.
+impl From<sea_orm::DbErr> for DomainError {
+    fn from(value: sea_orm::DbErr) -> Self {
+        match value {
+            sea_orm::DbErr::ConnectionAcquire(sea_orm::ConnAcquireErr::Timeout) => {
+                DomainError::ServerBusy(CONNECTION_ACQUIRE_TIMEOUT.to_owned())
+            }
+            e => DomainError::DatabaseError(e),
+        }
+    }
+}
+
+impl From<sea_orm::TransactionError<sea_orm::DbErr>> for DomainError {
+    fn from(value: sea_orm::TransactionError<sea_orm::DbErr>) -> Self {
+        match value {
+            sea_orm::TransactionError::Connection(sea_orm::DbErr::ConnectionAcquire(
+                sea_orm::ConnAcquireErr::Timeout,
+            )) => DomainError::ServerBusy(CONNECTION_ACQUIRE_TIMEOUT.to_owned()),
+            e => DomainError::DatabaseTransactionError(e),
+        }
+    }
 }
 
 impl From<sea_orm::TransactionError<DomainError>> for DomainError {
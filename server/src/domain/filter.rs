@@ -0,0 +1,770 @@
+//! The user and group filters, in a typed representation lowered to SQL conditions in one place.
+//!
+//! The request filters are converted into a `FilterExpr`: boolean nodes over comparisons of an
+//! attribute with a value. The attributes are the columns of the users or groups, the columns of
+//! the related entity (the groups of a user, the memberships of a group), or the custom
+//! attributes. The filters on unknown attributes are resolved to constants before that, when
+//! the LDAP filters are converted.
+
+use crate::domain::{
+    handler::{GroupRequestFilter, SubStringFilter, UserRequestFilter},
+    model::{self, GroupColumn, MembershipColumn, UserColumn},
+    types::AttributeName,
+    validation::{check_host, normalize_phone_number},
+};
+use sea_orm::{
+    sea_query::{
+        Alias, Cond, Expr, Func, IntoColumnRef, IntoCondition, SelectStatement, SimpleExpr,
+    },
+    ColumnTrait, EntityTrait, QueryFilter, QuerySelect, QueryTrait, Value,
+};
+use std::fmt::Debug;
+
+/// The entities that can be filtered, with the SQL building blocks of their filters.
+pub trait FilterTarget {
+    type Column: Copy + Debug;
+    type RelatedColumn: Copy + Debug;
+
+    /// The ID of the entities, matched against the results of the subqueries.
+    fn key() -> SimpleExpr;
+
+    /// The column, or a lowercase version of it if `ignore_case`.
+    fn column(column: Self::Column, ignore_case: bool) -> SimpleExpr;
+
+    /// A column of the related entity, for `related_keys`.
+    fn related_column(column: Self::RelatedColumn, ignore_case: bool) -> SimpleExpr;
+
+    /// The IDs of the entities with a related entity matching the condition.
+    fn related_keys(condition: Cond) -> SelectStatement;
+
+    /// The serialized value of the custom attributes, for `attribute_keys`.
+    fn attribute_value() -> SimpleExpr;
+
+    /// The IDs of the entities with a value of the attribute matching the condition.
+    fn attribute_keys(name: AttributeName, condition: Cond) -> SelectStatement;
+}
+
+#[derive(Clone, Debug)]
+pub enum Users {}
+
+#[derive(Clone, Debug)]
+pub enum Groups {}
+
+#[derive(Clone, Debug)]
+pub enum AttributeRef<T: FilterTarget> {
+    Column(T::Column),
+    Related(T::RelatedColumn),
+    /// A custom attribute. The values are serialized, so only `Comparison::Equal` and
+    /// `Comparison::NotNull` apply.
+    Custom(AttributeName),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Comparison {
+    Equal(Value),
+    EqualIgnoreCase(String),
+    /// Case-insensitive.
+    SubString(SubStringFilter),
+    /// A NULL timestamp means "never", which is before any date.
+    NullOrBefore(chrono::DateTime<chrono::Utc>),
+    /// Any value. The lowercase copies of the columns are NULL for the empty values.
+    NotNull,
+}
+
+impl Comparison {
+    pub(crate) fn ignores_case(&self) -> bool {
+        matches!(self, Self::EqualIgnoreCase(_) | Self::SubString(_))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum FilterExpr<T: FilterTarget> {
+    Constant(bool),
+    And(Vec<FilterExpr<T>>),
+    Or(Vec<FilterExpr<T>>),
+    Not(Box<FilterExpr<T>>),
+    Compare(AttributeRef<T>, Comparison),
+}
+
+impl<T: FilterTarget> FilterExpr<T> {
+    fn and(filters: Vec<Self>) -> Self {
+        if filters.is_empty() {
+            Self::Constant(true)
+        } else {
+            Self::And(filters)
+        }
+    }
+
+    fn or(filters: Vec<Self>) -> Self {
+        if filters.is_empty() {
+            Self::Constant(false)
+        } else {
+            Self::Or(filters)
+        }
+    }
+
+    fn not(filter: Self) -> Self {
+        match filter {
+            Self::Constant(value) => Self::Constant(!value),
+            filter => Self::Not(Box::new(filter)),
+        }
+    }
+
+    fn column(column: T::Column, comparison: Comparison) -> Self {
+        Self::Compare(AttributeRef::Column(column), comparison)
+    }
+
+    fn related(column: T::RelatedColumn, comparison: Comparison) -> Self {
+        Self::Compare(AttributeRef::Related(column), comparison)
+    }
+
+    fn custom(name: AttributeName, value: impl Into<Value>) -> Self {
+        Self::Compare(AttributeRef::Custom(name), Comparison::Equal(value.into()))
+    }
+
+    fn custom_present(name: AttributeName) -> Self {
+        Self::Compare(AttributeRef::Custom(name), Comparison::NotNull)
+    }
+}
+
+/// Lowers the filter to an SQL condition on the entity table.
+pub fn to_condition<T: FilterTarget>(filter: FilterExpr<T>) -> Cond {
+    use FilterExpr::*;
+    match filter {
+        Constant(value) => SimpleExpr::Value(value.into()).into_condition(),
+        And(fs) if fs.is_empty() => to_condition(FilterExpr::<T>::Constant(true)),
+        Or(fs) if fs.is_empty() => to_condition(FilterExpr::<T>::Constant(false)),
+        And(fs) => fs
+            .into_iter()
+            .map(to_condition)
+            .fold(Cond::all(), Cond::add),
+        Or(fs) => fs
+            .into_iter()
+            .map(to_condition)
+            .fold(Cond::any(), Cond::add),
+        Not(f) => to_condition(*f).not(),
+        Compare(attribute, comparison) => {
+            let ignore_case = comparison.ignores_case();
+            match attribute {
+                AttributeRef::Column(column) => compare(T::column(column, ignore_case), comparison),
+                AttributeRef::Related(column) => Expr::expr(T::key())
+                    .in_subquery(T::related_keys(compare(
+                        T::related_column(column, ignore_case),
+                        comparison,
+                    )))
+                    .into_condition(),
+                AttributeRef::Custom(name) => Expr::expr(T::key())
+                    .in_subquery(T::attribute_keys(
+                        name,
+                        compare(T::attribute_value(), comparison),
+                    ))
+                    .into_condition(),
+            }
+        }
+    }
+}
+
+fn compare(operand: SimpleExpr, comparison: Comparison) -> Cond {
+    match comparison {
+        Comparison::Equal(value) => Expr::expr(operand).eq(value).into_condition(),
+        Comparison::EqualIgnoreCase(value) => Expr::expr(operand)
+            .eq(value.to_lowercase())
+            .into_condition(),
+        Comparison::SubString(filter) => Expr::expr(operand)
+            .like(filter.to_sql_filter())
+            .into_condition(),
+        Comparison::NullOrBefore(date) => Cond::any()
+            .add(Expr::expr(operand.clone()).is_null())
+            .add(Expr::expr(operand).lt(date)),
+        Comparison::NotNull => Expr::expr(operand).is_not_null().into_condition(),
+    }
+}
+
+/// The column, or if `ignore_case` its lowercase copy when it has one, else its lowercase value.
+fn column_expr<C: IntoColumnRef>(column: C, lowercase: Option<C>, ignore_case: bool) -> SimpleExpr {
+    match (ignore_case, lowercase) {
+        (false, _) => Expr::col(column).into(),
+        (true, Some(lowercase)) => Expr::col(lowercase).into(),
+        (true, None) => SimpleExpr::FunctionCall(Func::lower(Expr::col(column))),
+    }
+}
+
+/// The columns holding the lowercase values of the case-insensitive user columns. The user IDs
+/// and the hosts are stored in lowercase.
+fn lowercase_user_column(column: UserColumn) -> Option<UserColumn> {
+    match column {
+        UserColumn::UserId => Some(UserColumn::UserId),
+        UserColumn::Hosts => Some(UserColumn::Hosts),
+        UserColumn::Email | UserColumn::LowercaseEmail => Some(UserColumn::LowercaseEmail),
+        UserColumn::DisplayName | UserColumn::LowercaseDisplayName => {
+            Some(UserColumn::LowercaseDisplayName)
+        }
+        _ => None,
+    }
+}
+
+fn lowercase_group_column(column: GroupColumn) -> Option<GroupColumn> {
+    match column {
+        GroupColumn::DisplayName | GroupColumn::LowercaseDisplayName => {
+            Some(GroupColumn::LowercaseDisplayName)
+        }
+        _ => None,
+    }
+}
+
+impl FilterTarget for Users {
+    type Column = UserColumn;
+    type RelatedColumn = GroupColumn;
+
+    fn key() -> SimpleExpr {
+        Expr::col(UserColumn::UserId.as_column_ref()).into()
+    }
+
+    fn column(column: UserColumn, ignore_case: bool) -> SimpleExpr {
+        column_expr(
+            column.as_column_ref(),
+            lowercase_user_column(column).map(|c| c.as_column_ref()),
+            ignore_case,
+        )
+    }
+
+    /// The groups are joined as "r1" in `related_keys`.
+    fn related_column(column: GroupColumn, ignore_case: bool) -> SimpleExpr {
+        let group_table = || Alias::new("r1");
+        column_expr(
+            (group_table(), column),
+            lowercase_group_column(column).map(|c| (group_table(), c)),
+            ignore_case,
+        )
+    }
+
+    fn related_keys(condition: Cond) -> SelectStatement {
+        model::User::find()
+            .find_also_linked(model::memberships::UserToGroup)
+            .select_only()
+            .column(UserColumn::UserId)
+            .filter(condition)
+            .into_query()
+    }
+
+    fn attribute_value() -> SimpleExpr {
+        Expr::col(model::UserAttributesColumn::Value.as_column_ref()).into()
+    }
+
+    fn attribute_keys(name: AttributeName, condition: Cond) -> SelectStatement {
+        model::UserAttributes::find()
+            .select_only()
+            .column(model::UserAttributesColumn::UserId)
+            .filter(model::UserAttributesColumn::AttributeName.eq(name))
+            .filter(condition)
+            .into_query()
+    }
+}
+
+impl FilterTarget for Groups {
+    type Column = GroupColumn;
+    type RelatedColumn = MembershipColumn;
+
+    fn key() -> SimpleExpr {
+        Expr::col(GroupColumn::GroupId.as_column_ref()).into()
+    }
+
+    fn column(column: GroupColumn, ignore_case: bool) -> SimpleExpr {
+        column_expr(
+            column.as_column_ref(),
+            lowercase_group_column(column).map(|c| c.as_column_ref()),
+            ignore_case,
+        )
+    }
+
+    /// The user IDs of the memberships are stored in lowercase.
+    fn related_column(column: MembershipColumn, ignore_case: bool) -> SimpleExpr {
+        let lowercase = matches!(column, MembershipColumn::UserId).then(|| column.as_column_ref());
+        column_expr(column.as_column_ref(), lowercase, ignore_case)
+    }
+
+    fn related_keys(condition: Cond) -> SelectStatement {
+        model::Membership::find()
+            .select_only()
+            .column(MembershipColumn::GroupId)
+            .filter(condition)
+            .into_query()
+    }
+
+    fn attribute_value() -> SimpleExpr {
+        Expr::col(model::GroupAttributesColumn::Value.as_column_ref()).into()
+    }
+
+    fn attribute_keys(name: AttributeName, condition: Cond) -> SelectStatement {
+        model::GroupAttributes::find()
+            .select_only()
+            .column(model::GroupAttributesColumn::GroupId)
+            .filter(model::GroupAttributesColumn::AttributeName.eq(name))
+            .filter(condition)
+            .into_query()
+    }
+}
+
+impl From<UserRequestFilter> for FilterExpr<Users> {
+    fn from(filter: UserRequestFilter) -> Self {
+        use UserRequestFilter::*;
+        let convert = |fs: Vec<UserRequestFilter>| fs.into_iter().map(Self::from).collect();
+        match filter {
+            And(fs) => Self::and(convert(fs)),
+            Or(fs) => Self::or(convert(fs)),
+            Not(f) => Self::not((*f).into()),
+            UserId(user_id) => Self::column(UserColumn::UserId, Comparison::Equal(user_id.into())),
+            // The user IDs are normalized, like the `UserId` filter.
+            Equality(UserColumn::UserId, value) => Self::column(
+                UserColumn::UserId,
+                Comparison::Equal(crate::domain::types::UserId::new(&value).into()),
+            ),
+            // The phone numbers are stored normalized, without their formatting.
+            Equality(column @ (UserColumn::Phone | UserColumn::Mobile), value) => Self::column(
+                column,
+                Comparison::Equal(normalize_phone_number(&value).into()),
+            ),
+            Equality(
+                column @ (UserColumn::PostalAddress
+                | UserColumn::Locality
+                | UserColumn::StateOrProvince),
+                value,
+            ) => Self::column(column, Comparison::EqualIgnoreCase(value)),
+            Equality(column, value) => Self::column(
+                column,
+                match lowercase_user_column(column) {
+                    Some(_) => Comparison::EqualIgnoreCase(value),
+                    None => Comparison::Equal(value.into()),
+                },
+            ),
+            AttributeEquality(name, value) => Self::custom(name, value),
+            MemberOf(group) => Self::related(
+                GroupColumn::DisplayName,
+                Comparison::EqualIgnoreCase(group.as_str().to_owned()),
+            ),
+            MemberOfId(group_id) => {
+                Self::related(GroupColumn::GroupId, Comparison::Equal(group_id.into()))
+            }
+            UserIdSubString(filter) => {
+                Self::column(UserColumn::UserId, Comparison::SubString(filter))
+            }
+            SubString(column @ (UserColumn::Phone | UserColumn::Mobile), filter) => {
+                let normalize = |part: String| normalize_phone_number(&part);
+                Self::column(
+                    column,
+                    Comparison::SubString(SubStringFilter {
+                        initial: filter.initial.map(normalize),
+                        any: filter.any.into_iter().map(normalize).collect(),
+                        final_: filter.final_.map(normalize),
+                    }),
+                )
+            }
+            SubString(column, filter) => Self::column(column, Comparison::SubString(filter)),
+            LastLoginBefore(date) => {
+                Self::column(UserColumn::LastLogin, Comparison::NullOrBefore(date))
+            }
+            PasswordChangedBefore(date) => Self::column(
+                UserColumn::PasswordChangedAt,
+                Comparison::NullOrBefore(date),
+            ),
+            ExpiresBefore(date) => Self::and(vec![
+                Self::column(UserColumn::ExpiresAt, Comparison::NotNull),
+                Self::column(UserColumn::ExpiresAt, Comparison::NullOrBefore(date)),
+            ]),
+            // The lowercase copies are NULL for the empty emails and display names.
+            Present(column) => Self::column(
+                lowercase_user_column(column).unwrap_or(column),
+                Comparison::NotNull,
+            ),
+            AttributePresent(name) => Self::custom_present(name),
+            // The groups are left joined: NULL for the users without any.
+            MemberOfAny => Self::related(GroupColumn::GroupId, Comparison::NotNull),
+            // A valid host can't contain a comma, nor the LIKE wildcards once escaped: see
+            // `hosts_to_column`. The users without hosts don't match rather than being NULL, so
+            // that they match the negated filter.
+            Host(host) => match check_host(host.trim()) {
+                Ok(()) => Self::and(vec![
+                    Self::column(UserColumn::Hosts, Comparison::NotNull),
+                    Self::column(
+                        UserColumn::Hosts,
+                        Comparison::SubString(SubStringFilter {
+                            initial: None,
+                            any: vec![format!(",{},", host.trim().to_ascii_lowercase())],
+                            final_: None,
+                        }),
+                    ),
+                ]),
+                Err(_) => Self::Constant(false),
+            },
+        }
+    }
+}
+
+impl From<GroupRequestFilter> for FilterExpr<Groups> {
+    fn from(filter: GroupRequestFilter) -> Self {
+        use GroupRequestFilter::*;
+        let convert = |fs: Vec<GroupRequestFilter>| fs.into_iter().map(Self::from).collect();
+        match filter {
+            And(fs) => Self::and(convert(fs)),
+            Or(fs) => Self::or(convert(fs)),
+            Not(f) => Self::not((*f).into()),
+            DisplayName(name) => Self::column(
+                GroupColumn::DisplayName,
+                Comparison::EqualIgnoreCase(name.as_str().to_owned()),
+            ),
+            DisplayNameSubString(filter) => {
+                Self::column(GroupColumn::DisplayName, Comparison::SubString(filter))
+            }
+            Uuid(uuid) => Self::column(
+                GroupColumn::Uuid,
+                Comparison::Equal(uuid.to_string().into()),
+            ),
+            GroupId(group_id) => {
+                Self::column(GroupColumn::GroupId, Comparison::Equal(group_id.into()))
+            }
+            // The free-text columns compare like the LDAP `description`: ignoring the case.
+            Equality(
+                column @ (GroupColumn::DisplayName | GroupColumn::Description | GroupColumn::Notes),
+                value,
+            ) => Self::column(column, Comparison::EqualIgnoreCase(value)),
+            Equality(column, value) => Self::column(column, Comparison::Equal(value.into())),
+            SubString(column, filter) => Self::column(column, Comparison::SubString(filter)),
+            // The empty descriptions and notes are stored as NULL.
+            Present(column) => Self::column(column, Comparison::NotNull),
+            Member(user_id) => {
+                Self::related(MembershipColumn::UserId, Comparison::Equal(user_id.into()))
+            }
+            AttributeEquality(name, value) => Self::custom(name, value),
+            AttributePresent(name) => Self::custom_present(name),
+            HasMembers => Self::related(MembershipColumn::UserId, Comparison::NotNull),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        handler::{
+            GroupBackendHandler, GroupListerBackendHandler, UpdateGroupRequest, UpdateUserRequest,
+            UserBackendHandler,
+        },
+        sql_backend_handler::{tests::*, SqlBackendHandler},
+        types::{Serialized, UserId},
+    };
+    use pretty_assertions::assert_eq;
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+    use std::collections::BTreeSet;
+
+    /// A boolean combination of terms, given by their index.
+    #[derive(Debug)]
+    enum Tree {
+        Term(usize),
+        And(Vec<Tree>),
+        Or(Vec<Tree>),
+        Not(Box<Tree>),
+    }
+
+    impl Tree {
+        fn random(rng: &mut SmallRng, depth: u32, terms: usize) -> Self {
+            match if depth == 0 { 0 } else { rng.gen_range(0..4) } {
+                0 => Self::Term(rng.gen_range(0..terms)),
+                1 => Self::And(Self::random_children(rng, depth - 1, terms)),
+                2 => Self::Or(Self::random_children(rng, depth - 1, terms)),
+                _ => Self::Not(Box::new(Self::random(rng, depth - 1, terms))),
+            }
+        }
+
+        /// Up to 3 children, to cover the empty `And` and `Or` too.
+        fn random_children(rng: &mut SmallRng, depth: u32, terms: usize) -> Vec<Self> {
+            (0..rng.gen_range(0..=3))
+                .map(|_| Self::random(rng, depth, terms))
+                .collect()
+        }
+
+        fn to_filter<F: Clone>(
+            &self,
+            terms: &[F],
+            and: fn(Vec<F>) -> F,
+            or: fn(Vec<F>) -> F,
+            not: fn(Box<F>) -> F,
+        ) -> F {
+            let children = |children: &[Tree]| {
+                children
+                    .iter()
+                    .map(|c| c.to_filter(terms, and, or, not))
+                    .collect()
+            };
+            match self {
+                Self::Term(i) => terms[*i].clone(),
+                Self::And(c) => and(children(c)),
+                Self::Or(c) => or(children(c)),
+                Self::Not(c) => not(Box::new(c.to_filter(terms, and, or, not))),
+            }
+        }
+
+        /// The entries matching the filter, from the entries matching each term.
+        fn expected(&self, all: &BTreeSet<String>, terms: &[BTreeSet<String>]) -> BTreeSet<String> {
+            match self {
+                Self::Term(i) => terms[*i].clone(),
+                Self::And(c) => c
+                    .iter()
+                    .fold(all.clone(), |result, c| &result & &c.expected(all, terms)),
+                Self::Or(c) => c.iter().fold(BTreeSet::new(), |result, c| {
+                    &result | &c.expected(all, terms)
+                }),
+                Self::Not(c) => all - &c.expected(all, terms),
+            }
+        }
+    }
+
+    fn substring(initial: Option<&str>, any: &[&str]) -> SubStringFilter {
+        SubStringFilter {
+            initial: initial.map(str::to_owned),
+            any: any.iter().map(|s| s.to_string()).collect(),
+            final_: None,
+        }
+    }
+
+    async fn get_group_names(
+        handler: &SqlBackendHandler,
+        filters: Option<GroupRequestFilter>,
+    ) -> BTreeSet<String> {
+        handler
+            .list_groups(filters)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|g| g.display_name.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_constants() {
+        assert!(matches!(
+            FilterExpr::<Users>::from(UserRequestFilter::from(false)),
+            FilterExpr::Constant(false)
+        ));
+        assert!(matches!(
+            FilterExpr::<Groups>::from(GroupRequestFilter::Or(vec![GroupRequestFilter::from(
+                true
+            )])),
+            FilterExpr::Or(fs) if matches!(fs[..], [FilterExpr::Constant(true)])
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_host_filter() {
+        let fixture = TestFixture::new().await;
+        for (user_id, hosts) in [("bob", vec!["web01", "db02"]), ("patrick", vec!["*"])] {
+            fixture
+                .handler
+                .update_user(UpdateUserRequest {
+                    user_id: UserId::new(user_id),
+                    hosts: Some(hosts.into_iter().map(str::to_owned).collect()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        let hosts = |host: &str| {
+            get_user_names(
+                &fixture.handler,
+                Some(UserRequestFilter::Host(host.to_owned())),
+            )
+        };
+        assert_eq!(hosts("web01").await, vec!["bob"]);
+        assert_eq!(hosts("DB02").await, vec!["bob"]);
+        // Only whole hosts match.
+        assert!(hosts("web").await.is_empty());
+        assert!(hosts("eb01").await.is_empty());
+        // The wildcard is a plain value here.
+        assert_eq!(hosts("*").await, vec!["patrick"]);
+        assert!(hosts("web01,db02").await.is_empty());
+        assert!(hosts("%").await.is_empty());
+        assert_eq!(
+            get_user_names(
+                &fixture.handler,
+                Some(UserRequestFilter::Not(Box::new(UserRequestFilter::Host(
+                    "web01".to_owned()
+                ))))
+            )
+            .await,
+            vec!["john", "nogroup", "patrick"]
+        );
+        assert_eq!(
+            get_user_names(
+                &fixture.handler,
+                Some(UserRequestFilter::Present(UserColumn::Hosts))
+            )
+            .await,
+            vec!["bob", "patrick"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_phone_and_address_filters() {
+        let fixture = TestFixture::new().await;
+        for (user_id, phone, locality) in [
+            ("bob", "+1 555 010-9999", "Springfield"),
+            ("patrick", "(030) 1234567", "Berlin"),
+        ] {
+            fixture
+                .handler
+                .update_user(UpdateUserRequest {
+                    user_id: UserId::new(user_id),
+                    phone: Some(phone.to_owned()),
+                    locality: Some(locality.to_owned()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        let users = |filter: UserRequestFilter| get_user_names(&fixture.handler, Some(filter));
+        // The formatting of the filter values is ignored, like in the stored numbers.
+        assert_eq!(
+            users(UserRequestFilter::Equality(
+                UserColumn::Phone,
+                "+1 (555) 010 9999".to_owned()
+            ))
+            .await,
+            vec!["bob"]
+        );
+        assert_eq!(
+            users(UserRequestFilter::SubString(
+                UserColumn::Phone,
+                substring(Some("030-"), &["45"])
+            ))
+            .await,
+            vec!["patrick"]
+        );
+        assert_eq!(
+            users(UserRequestFilter::Equality(
+                UserColumn::Locality,
+                "BERLIN".to_owned()
+            ))
+            .await,
+            vec!["patrick"]
+        );
+        assert_eq!(
+            users(UserRequestFilter::SubString(
+                UserColumn::Locality,
+                substring(None, &["FIELD"])
+            ))
+            .await,
+            vec!["bob"]
+        );
+        assert_eq!(
+            users(UserRequestFilter::Present(UserColumn::Mobile)).await,
+            Vec::<String>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_random_user_filters() {
+        let fixture = TestFixture::new().await;
+        let terms = vec![
+            UserRequestFilter::UserId(UserId::new("bob")),
+            UserRequestFilter::Equality(UserColumn::Email, "PATRICK@bob.bob".to_owned()),
+            UserRequestFilter::Equality(UserColumn::DisplayName, "display john".to_owned()),
+            UserRequestFilter::MemberOf("best GROUP".into()),
+            UserRequestFilter::MemberOfId(fixture.groups[1]),
+            UserRequestFilter::UserIdSubString(substring(Some("P"), &[])),
+            UserRequestFilter::SubString(UserColumn::DisplayName, substring(None, &["O"])),
+            UserRequestFilter::AttributeEquality(
+                AttributeName::from("first_name"),
+                Serialized::from("first bob"),
+            ),
+            UserRequestFilter::LastLoginBefore(chrono::Utc::now()),
+            UserRequestFilter::ExpiresBefore(chrono::Utc::now()),
+            UserRequestFilter::Present(UserColumn::LastLogin),
+            UserRequestFilter::AttributePresent(AttributeName::from("avatar")),
+            UserRequestFilter::MemberOfAny,
+            UserRequestFilter::from(false),
+        ];
+        let handler = &fixture.handler;
+        let user_names = |filters: Option<UserRequestFilter>| async move {
+            get_user_names(handler, filters)
+                .await
+                .into_iter()
+                .collect::<BTreeSet<_>>()
+        };
+        let all = user_names(None).await;
+        let mut term_results = Vec::new();
+        for term in &terms {
+            term_results.push(user_names(Some(term.clone())).await);
+        }
+        let mut rng = SmallRng::seed_from_u64(42);
+        for _ in 0..200 {
+            let tree = Tree::random(&mut rng, 4, terms.len());
+            let filter = tree.to_filter(
+                &terms,
+                UserRequestFilter::And,
+                UserRequestFilter::Or,
+                UserRequestFilter::Not,
+            );
+            assert_eq!(
+                user_names(Some(filter)).await,
+                tree.expected(&all, &term_results),
+                "{:?}",
+                tree
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_random_group_filters() {
+        let fixture = TestFixture::new().await;
+        fixture
+            .handler
+            .update_group(UpdateGroupRequest {
+                group_id: fixture.groups[1],
+                display_name: None,
+                description: Some("The Worst of them".to_owned()),
+                notes: None,
+                delete_attributes: Vec::new(),
+                insert_attributes: Vec::new(),
+            })
+            .await
+            .unwrap();
+        let terms = vec![
+            GroupRequestFilter::DisplayName("best GROUP".into()),
+            GroupRequestFilter::DisplayNameSubString(substring(None, &["ST"])),
+            GroupRequestFilter::GroupId(fixture.groups[2]),
+            GroupRequestFilter::Equality(GroupColumn::Description, "the worst OF THEM".to_owned()),
+            GroupRequestFilter::SubString(GroupColumn::Description, substring(None, &["WORST"])),
+            GroupRequestFilter::Present(GroupColumn::Description),
+            GroupRequestFilter::Member(UserId::new("patrick")),
+            GroupRequestFilter::Member(UserId::new("John")),
+            GroupRequestFilter::HasMembers,
+            GroupRequestFilter::AttributePresent(AttributeName::from("gid")),
+            GroupRequestFilter::AttributeEquality(
+                AttributeName::from("gid"),
+                Serialized::from(&512),
+            ),
+            GroupRequestFilter::from(false),
+        ];
+        let handler = &fixture.handler;
+        let all = get_group_names(handler, None).await;
+        let mut term_results = Vec::new();
+        for term in &terms {
+            term_results.push(get_group_names(handler, Some(term.clone())).await);
+        }
+        let mut rng = SmallRng::seed_from_u64(42);
+        for _ in 0..200 {
+            let tree = Tree::random(&mut rng, 4, terms.len());
+            let filter = tree.to_filter(
+                &terms,
+                GroupRequestFilter::And,
+                GroupRequestFilter::Or,
+                GroupRequestFilter::Not,
+            );
+            assert_eq!(
+                get_group_names(handler, Some(filter)).await,
+                tree.expected(&all, &term_results),
+                "{:?}",
+                tree
+            );
+        }
+    }
+}
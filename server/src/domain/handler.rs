@@ -1,30 +1,47 @@
 use crate::domain::{
     error::Result,
+    model::GroupColumn,
     types::{
-        AttributeName, AttributeType, AttributeValue, Email, Group, GroupDetails, GroupId,
-        GroupName, JpegPhoto, LdapObjectClass, Serialized, User, UserAndGroups, UserColumn, UserId,
-        Uuid,
+        AttributeName, AttributeType, AttributeValue, DeletedUser, Email, Group, GroupDetails,
+        GroupId, GroupName, JpegPhoto, LdapObjectClass, Serialized, User, UserAndGroups,
+        UserColumn, UserId, Uuid,
     },
 };
 use async_trait::async_trait;
+use sea_orm::sea_query::LikeExpr;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 pub struct BindRequest {
+    /// The user ID, or the email with `login_identifier = "email"`.
     pub name: UserId,
     pub password: String,
 }
 
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Serialize, Deserialize, Clone)]
 pub struct SubStringFilter {
     pub initial: Option<String>,
     pub any: Vec<String>,
     pub final_: Option<String>,
 }
 
+/// Escapes the LIKE wildcards from user input. The escape character is set explicitly in the
+/// query, since the default one differs between SQLite, Postgres and MySQL.
+fn escape_like_pattern(pattern: &str) -> String {
+    let mut escaped = String::with_capacity(pattern.len());
+    for c in pattern.to_lowercase().chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 impl SubStringFilter {
-    pub fn to_sql_filter(&self) -> String {
+    /// Returns a LIKE expression to be matched against a lowercase column.
+    pub fn to_sql_filter(&self) -> LikeExpr {
         let mut filter = String::with_capacity(
             self.initial.as_ref().map(String::len).unwrap_or_default()
                 + 1
@@ -33,21 +50,42 @@ impl SubStringFilter {
                 + self.final_.as_ref().map(String::len).unwrap_or_default(),
         );
         if let Some(f) = &self.initial {
-            filter.push_str(&f.to_ascii_lowercase());
+            filter.push_str(&escape_like_pattern(f));
         }
         filter.push('%');
         for part in self.any.iter() {
-            filter.push_str(&part.to_ascii_lowercase());
+            filter.push_str(&escape_like_pattern(part));
             filter.push('%');
         }
         if let Some(f) = &self.final_ {
-            filter.push_str(&f.to_ascii_lowercase());
+            filter.push_str(&escape_like_pattern(f));
+        }
+        LikeExpr::new(filter).escape('\\')
+    }
+
+    /// The in-memory equivalent of `to_sql_filter`, on a lowercase value.
+    pub fn matches(&self, value: &str) -> bool {
+        let lowercase = |part: &Option<String>| part.as_deref().unwrap_or_default().to_lowercase();
+        let (initial, final_) = (lowercase(&self.initial), lowercase(&self.final_));
+        let rest = match value.strip_prefix(initial.as_str()) {
+            Some(rest) if rest.len() >= final_.len() => rest,
+            _ => return false,
+        };
+        if !rest.ends_with(final_.as_str()) {
+            return false;
         }
-        filter
+        let mut middle = &rest[..rest.len() - final_.len()];
+        for part in self.any.iter().map(|part| part.to_lowercase()) {
+            match middle.find(part.as_str()) {
+                Some(index) => middle = &middle[index + part.len()..],
+                None => return false,
+            }
+        }
+        true
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Serialize, Deserialize, Clone)]
 pub enum UserRequestFilter {
     And(Vec<UserRequestFilter>),
     Or(Vec<UserRequestFilter>),
@@ -61,6 +99,20 @@ pub enum UserRequestFilter {
     MemberOf(GroupName),
     // Same, by id.
     MemberOfId(GroupId),
+    // Users who haven't logged in since the given date, including those who never did.
+    LastLoginBefore(chrono::DateTime<chrono::Utc>),
+    // Users who haven't changed their password since the given date, or never set one.
+    PasswordChangedBefore(chrono::DateTime<chrono::Utc>),
+    // Users with an expiration date before the given date.
+    ExpiresBefore(chrono::DateTime<chrono::Utc>),
+    // Users with a non-empty value in the column.
+    Present(UserColumn),
+    // Users with a value for the attribute.
+    AttributePresent(AttributeName),
+    // Users who belong to at least one group.
+    MemberOfAny,
+    // Users with the host in their list. The wildcard only matches the users with "*".
+    Host(String),
 }
 
 impl From<bool> for UserRequestFilter {
@@ -73,7 +125,7 @@ impl From<bool> for UserRequestFilter {
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Serialize, Deserialize, Clone)]
 pub enum GroupRequestFilter {
     And(Vec<GroupRequestFilter>),
     Or(Vec<GroupRequestFilter>),
@@ -82,9 +134,18 @@ pub enum GroupRequestFilter {
     DisplayNameSubString(SubStringFilter),
     Uuid(Uuid),
     GroupId(GroupId),
+    Equality(GroupColumn, String),
+    SubString(GroupColumn, SubStringFilter),
+    // Groups with a non-empty value in the column.
+    Present(GroupColumn),
     // Check if the group contains a user identified by uid.
     Member(UserId),
     AttributeEquality(AttributeName, Serialized),
+    // Groups with a value for the attribute.
+    AttributePresent(AttributeName),
+    // Groups with at least one member. Like for `Member`, that includes the deleted users that
+    // are not purged yet.
+    HasMembers,
 }
 
 impl From<bool> for GroupRequestFilter {
@@ -106,7 +167,21 @@ pub struct CreateUserRequest {
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub avatar: Option<JpegPhoto>,
+    /// BCP 47 language tag, for the emails.
+    pub preferred_language: Option<String>,
+    /// The hosts the user may log into, "*" for all of them.
+    pub hosts: Vec<String>,
+    /// The phone numbers are normalized, see `normalize_phone_number`.
+    pub phone: Option<String>,
+    pub mobile: Option<String>,
+    pub postal_address: Option<String>,
+    pub locality: Option<String>,
+    pub state_or_province: Option<String>,
+    /// The account is disabled (deleted) at that date, which can't be in the past.
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
     pub attributes: Vec<AttributeValue>,
+    /// The groups to add the user to, in the same transaction as the creation.
+    pub groups: Vec<GroupId>,
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
@@ -118,20 +193,70 @@ pub struct UpdateUserRequest {
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub avatar: Option<JpegPhoto>,
+    /// An empty string removes the preferred language.
+    pub preferred_language: Option<String>,
+    /// Replaces all the hosts. An empty list removes them.
+    pub hosts: Option<Vec<String>>,
+    /// For the phone numbers and the address fields, an empty string removes the value.
+    pub phone: Option<String>,
+    pub mobile: Option<String>,
+    pub postal_address: Option<String>,
+    pub locality: Option<String>,
+    pub state_or_province: Option<String>,
+    /// `Some(None)` removes the expiration date.
+    pub expires_at: Option<Option<chrono::DateTime<chrono::Utc>>>,
     pub delete_attributes: Vec<AttributeName>,
     pub insert_attributes: Vec<AttributeValue>,
 }
 
+/// What to do with the groups left without active members by the deletion of a user.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum EmptyGroupPolicy {
+    /// Keep the groups, empty.
+    #[default]
+    Keep,
+    /// Delete the groups along with the user.
+    DeleteEmpty,
+    /// Refuse to delete the user, with `LastGroupMember`.
+    FailIfLastMember,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DeleteUserRequest {
+    pub user_id: UserId,
+    /// Deletes the user without a way back, see `permanently_delete_user`.
+    pub permanent: bool,
+    pub empty_group_policy: EmptyGroupPolicy,
+}
+
+/// A group left without active members by the deletion of a user.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub struct EmptiedGroup {
+    pub group_id: GroupId,
+    pub display_name: GroupName,
+    /// Whether the group was deleted, or kept empty.
+    pub deleted: bool,
+}
+
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
 pub struct CreateGroupRequest {
     pub display_name: GroupName,
+    pub description: Option<String>,
+    /// Free-text notes, only visible to the admins.
+    pub notes: Option<String>,
     pub attributes: Vec<AttributeValue>,
+    /// The users to add to the group, in the same transaction as the creation.
+    pub members: Vec<UserId>,
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 pub struct UpdateGroupRequest {
     pub group_id: GroupId,
     pub display_name: Option<GroupName>,
+    /// An empty string removes the description.
+    pub description: Option<String>,
+    /// An empty string removes the notes.
+    pub notes: Option<String>,
     pub delete_attributes: Vec<AttributeName>,
     pub insert_attributes: Vec<AttributeValue>,
 }
@@ -182,9 +307,15 @@ pub struct Schema {
 
 #[async_trait]
 pub trait LoginHandler: Send + Sync {
-    async fn bind(&self, request: BindRequest) -> Result<()>;
+    /// Returns the ID of the user, which is not the name of the request when logging in with
+    /// the email.
+    async fn bind(&self, request: BindRequest) -> Result<UserId>;
+    /// Whether the password is the user's, without logging them in.
+    async fn check_password(&self, request: BindRequest) -> Result<bool>;
 }
 
+/// The lister traits only read, and their results may lag slightly behind the writes: they can be
+/// served by a read replica (see `database_read_url`).
 #[async_trait]
 pub trait GroupListerBackendHandler: ReadSchemaBackendHandler {
     async fn list_groups(&self, filters: Option<GroupRequestFilter>) -> Result<Vec<Group>>;
@@ -198,24 +329,72 @@ pub trait GroupBackendHandler: ReadSchemaBackendHandler {
     async fn delete_group(&self, group_id: GroupId) -> Result<()>;
 }
 
+/// See `GroupListerBackendHandler` about the read replica.
 #[async_trait]
 pub trait UserListerBackendHandler: ReadSchemaBackendHandler {
+    /// Lists the users, without their `JpegPhoto` attributes (e.g. the avatar): they can be
+    /// large, and are rarely needed for a list. See `get_user_photos`.
+    ///
+    /// The groups are only fetched with `get_groups`, otherwise they are `None`.
     async fn list_users(
         &self,
         filters: Option<UserRequestFilter>,
         get_groups: bool,
     ) -> Result<Vec<UserAndGroups>>;
+    /// A page of `list_users`: at most `limit` users, the first ones after `after` in the order
+    /// of the user IDs. The large LDAP searches send each page before fetching the next one.
+    ///
+    /// By default, the first page has all the users.
+    async fn list_users_page(
+        &self,
+        filters: Option<UserRequestFilter>,
+        get_groups: bool,
+        after: Option<UserId>,
+        _limit: u64,
+    ) -> Result<Vec<UserAndGroups>> {
+        match after {
+            None => self.list_users(filters, get_groups).await,
+            Some(_) => Ok(Vec::new()),
+        }
+    }
+    async fn list_deleted_users(&self) -> Result<Vec<DeletedUser>>;
+    /// Loads the `JpegPhoto` attributes of these users, which `list_users` leaves out.
+    async fn get_user_photos(
+        &self,
+        user_ids: &[UserId],
+    ) -> Result<HashMap<UserId, Vec<AttributeValue>>>;
 }
 
 #[async_trait]
 pub trait UserBackendHandler: ReadSchemaBackendHandler {
     async fn get_user_details(&self, user_id: &UserId) -> Result<User>;
-    async fn create_user(&self, request: CreateUserRequest) -> Result<()>;
+    /// Returns the ID of the new user: with `login_identifier = "email"`, the users created
+    /// without an ID get one from their email.
+    async fn create_user(&self, request: CreateUserRequest) -> Result<UserId>;
     async fn update_user(&self, request: UpdateUserRequest) -> Result<()>;
+    /// Marks the user as deleted: it is hidden everywhere until it is restored or purged.
     async fn delete_user(&self, user_id: &UserId) -> Result<()>;
-    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
-    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
+    async fn restore_user(&self, user_id: &UserId) -> Result<()>;
+    /// Deletes the user along with its attributes and memberships, without a way back.
+    async fn permanently_delete_user(&self, user_id: &UserId) -> Result<()>;
+    /// Deletes the user, applying the policy to the groups it is the last active member of in
+    /// the same transaction, and returns these groups. Fails with `LastAdmin` rather than leave
+    /// `lldap_admin` without active members, whatever the policy.
+    async fn delete_user_with_policy(
+        &self,
+        request: DeleteUserRequest,
+    ) -> Result<Vec<EmptiedGroup>>;
+    /// The groups that the user is the last active member of, sorted by name: the ones that
+    /// deleting the user would leave empty.
+    async fn get_groups_emptied_by_deletion(&self, user_id: &UserId) -> Result<Vec<GroupDetails>>;
+    /// Returns false if the user was already a member.
+    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<bool>;
+    /// Returns false if the user wasn't a member. Fails with `LastAdmin` rather than leave
+    /// `lldap_admin` without active members.
+    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<bool>;
     async fn get_user_groups(&self, user_id: &UserId) -> Result<HashSet<GroupDetails>>;
+    /// The hash of the avatar of the user, if any: see `Serialized::content_hash`.
+    async fn get_user_avatar_hash(&self, user_id: &UserId) -> Result<Option<String>>;
 }
 
 #[async_trait]
@@ -260,14 +439,8 @@ mod tests {
     fn test_uuid_time() {
         use chrono::prelude::*;
         let user_id = "bob";
-        let date1 = Utc
-            .with_ymd_and_hms(2014, 7, 8, 9, 10, 11)
-            .unwrap()
-            .naive_utc();
-        let date2 = Utc
-            .with_ymd_and_hms(2014, 7, 8, 9, 10, 12)
-            .unwrap()
-            .naive_utc();
+        let date1 = Utc.with_ymd_and_hms(2014, 7, 8, 9, 10, 11).unwrap();
+        let date2 = Utc.with_ymd_and_hms(2014, 7, 8, 9, 10, 12).unwrap();
         assert_ne!(
             Uuid::from_name_and_date(user_id, &date1),
             Uuid::from_name_and_date(user_id, &date2)
@@ -282,4 +455,25 @@ mod tests {
             .unwrap();
         JpegPhoto::try_from(base64_jpeg).unwrap();
     }
+
+    #[test]
+    fn test_substring_matches() {
+        let filter = |initial: Option<&str>, any: &[&str], final_: Option<&str>| SubStringFilter {
+            initial: initial.map(str::to_owned),
+            any: any.iter().map(|s| s.to_string()).collect(),
+            final_: final_.map(str::to_owned),
+        };
+        assert!(filter(Some("Jo"), &[], None).matches("john"));
+        assert!(!filter(Some("jo"), &[], None).matches("bob"));
+        assert!(filter(None, &["o", "N"], Some("doe")).matches("john doe"));
+        assert!(!filter(None, &["n", "o"], None).matches("john"));
+        assert!(filter(Some("JÖ"), &[], None).matches("jöhn"));
+        // The parts don't overlap.
+        assert!(!filter(Some("ab"), &[], Some("ba")).matches("aba"));
+        assert!(filter(Some("ab"), &[], Some("ba")).matches("abba"));
+        assert!(!filter(None, &["b"], Some("b")).matches("ab"));
+        // Not a pattern: the wildcards match themselves.
+        assert!(filter(None, &["%"], None).matches("100%"));
+        assert!(!filter(None, &["_"], None).matches("a"));
+    }
 }
@@ -0,0 +1,436 @@
+//! Distinguished names, as in RFC 4514: parsing, with the escapes, and serialization. The
+//! serialization of a parsed DN parses back to the same DN.
+
+use crate::domain::ldap::error::{LdapError, LdapResult};
+use ldap3_proto::LdapResultCode;
+
+/// An attribute of an RDN, e.g. `uid=bob`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AttributeTypeAndValue {
+    /// A name, or a numeric OID.
+    pub attribute_type: String,
+    /// Unescaped.
+    pub value: String,
+}
+
+/// A relative distinguished name: usually a single attribute, but there can be several, e.g.
+/// `ou=Sales+cn=J. Smith`.
+pub type Rdn = Vec<AttributeTypeAndValue>;
+
+/// The RDNs, from the entry up to the root.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DistinguishedName(pub Vec<Rdn>);
+
+/// Escapes an attribute value to be used in a DN (RFC 4514, section 2.4). New user ids and group
+/// names can't contain these characters, but older ones might.
+pub fn escape_dn_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '#' if i == 0 => escaped.push_str("\\#"),
+            ' ' if i == 0 || i == last => escaped.push_str("\\ "),
+            '\0' => escaped.push_str("\\00"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(b' ') {
+            self.pos += 1;
+        }
+    }
+
+    /// A name ("cn") or a numeric OID ("2.5.4.3"), and the "=". The spaces around them are
+    /// accepted, like in RFC 2253.
+    fn attribute_type(&mut self) -> Result<String, String> {
+        self.skip_spaces();
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'.')
+        {
+            self.pos += 1;
+        }
+        let attribute_type = &self.input[start..self.pos];
+        let valid = match attribute_type.first() {
+            Some(c) if c.is_ascii_alphabetic() => attribute_type
+                .iter()
+                .all(|c| c.is_ascii_alphanumeric() || *c == b'-'),
+            Some(c) if c.is_ascii_digit() => attribute_type
+                .split(|c| *c == b'.')
+                .all(|n| !n.is_empty() && n.iter().all(u8::is_ascii_digit)),
+            _ => false,
+        };
+        // Only ASCII characters so far.
+        let attribute_type = String::from_utf8_lossy(attribute_type).into_owned();
+        if !valid {
+            return Err(if attribute_type.is_empty() {
+                "missing attribute type".to_owned()
+            } else {
+                format!(r#"invalid attribute type "{}""#, attribute_type)
+            });
+        }
+        self.skip_spaces();
+        if self.peek() != Some(b'=') {
+            return Err(format!(r#"missing "=" after "{}""#, attribute_type));
+        }
+        self.pos += 1;
+        self.skip_spaces();
+        Ok(attribute_type)
+    }
+
+    /// After a backslash: a special character, or the hex code of a byte.
+    fn escaped(&mut self) -> Result<u8, String> {
+        match self.peek() {
+            Some(c @ (b' ' | b'"' | b'#' | b'+' | b',' | b';' | b'<' | b'=' | b'>' | b'\\')) => {
+                self.pos += 1;
+                Ok(c)
+            }
+            _ => {
+                let hex = self
+                    .input
+                    .get(self.pos..self.pos + 2)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .ok_or_else(|| "invalid escape sequence".to_owned())?;
+                self.pos += 2;
+                Ok(hex_byte(hex))
+            }
+        }
+    }
+
+    /// Up to the next unescaped "," or "+". The unescaped spaces at the end are not part of it.
+    fn attribute_value(&mut self) -> Result<String, String> {
+        if self.peek() == Some(b'#') {
+            return self.hex_value();
+        }
+        let mut value = Vec::new();
+        let mut significant_len = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                b',' | b'+' => break,
+                b'\\' => {
+                    self.pos += 1;
+                    value.push(self.escaped()?);
+                    significant_len = value.len();
+                    continue;
+                }
+                b' ' => value.push(c),
+                b'=' | b'"' | b';' | b'<' | b'>' => {
+                    return Err(format!(r#"unescaped "{}" in a value"#, c as char))
+                }
+                _ => {
+                    value.push(c);
+                    significant_len = value.len();
+                }
+            }
+            self.pos += 1;
+        }
+        value.truncate(significant_len);
+        String::from_utf8(value).map_err(|_| "the value is not valid UTF-8".to_owned())
+    }
+
+    /// "#" and the hex encoding of the BER value. Only the string types are supported.
+    fn hex_value(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.pos += 1;
+        }
+        let hex = &self.input[start..self.pos];
+        self.skip_spaces();
+        if hex.is_empty() || hex.len() % 2 != 0 || !matches!(self.peek(), None | Some(b',' | b'+'))
+        {
+            return Err("invalid hex value".to_owned());
+        }
+        let ber: Vec<u8> = hex.chunks(2).map(hex_byte).collect();
+        match ber.as_slice() {
+            // OCTET STRING, UTF8String, PrintableString or IA5String, with a short length.
+            [0x04 | 0x0c | 0x13 | 0x16, len, content @ ..]
+                if *len < 0x80 && usize::from(*len) == content.len() =>
+            {
+                String::from_utf8(content.to_vec())
+                    .map_err(|_| "the value is not valid UTF-8".to_owned())
+            }
+            _ => Err("unsupported hex value, only strings are supported".to_owned()),
+        }
+    }
+}
+
+fn hex_byte(hex: &[u8]) -> u8 {
+    let digit = |c: u8| (c as char).to_digit(16).unwrap() as u8;
+    (digit(hex[0]) << 4) | digit(hex[1])
+}
+
+impl DistinguishedName {
+    pub fn parse(dn: &str) -> LdapResult<Self> {
+        let error = |reason: String| LdapError {
+            code: LdapResultCode::InvalidDNSyntax,
+            message: format!(r#"Invalid DN "{}": {}"#, dn, reason),
+        };
+        let mut parser = Parser {
+            input: dn.as_bytes(),
+            pos: 0,
+        };
+        parser.skip_spaces();
+        if parser.peek().is_none() {
+            return Ok(Self::default());
+        }
+        let mut rdns = Vec::new();
+        let mut rdn = Vec::new();
+        loop {
+            rdn.push(AttributeTypeAndValue {
+                attribute_type: parser.attribute_type().map_err(error)?,
+                value: parser.attribute_value().map_err(error)?,
+            });
+            let separator = parser.peek();
+            parser.pos += 1;
+            match separator {
+                Some(b'+') => {}
+                Some(b',') => rdns.push(std::mem::take(&mut rdn)),
+                _ => break,
+            }
+        }
+        rdns.push(rdn);
+        Ok(Self(rdns))
+    }
+
+    /// The form to compare: the attribute types and values in lowercase, and the attributes of
+    /// each RDN sorted. All the attributes naming the LLDAP entries are case-insensitive.
+    pub fn normalized(&self) -> Self {
+        Self(
+            self.0
+                .iter()
+                .map(|rdn| {
+                    let mut rdn: Rdn = rdn
+                        .iter()
+                        .map(|attribute| AttributeTypeAndValue {
+                            attribute_type: attribute.attribute_type.to_ascii_lowercase(),
+                            value: attribute.value.to_lowercase(),
+                        })
+                        .collect();
+                    rdn.sort();
+                    rdn
+                })
+                .collect(),
+        )
+    }
+}
+
+impl std::fmt::Display for DistinguishedName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, rdn) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            for (j, attribute) in rdn.iter().enumerate() {
+                if j > 0 {
+                    f.write_str("+")?;
+                }
+                write!(
+                    f,
+                    "{}={}",
+                    attribute.attribute_type,
+                    escape_dn_value(&attribute.value)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn dn(rdns: &[&[(&str, &str)]]) -> DistinguishedName {
+        DistinguishedName(
+            rdns.iter()
+                .map(|rdn| {
+                    rdn.iter()
+                        .map(|(attribute_type, value)| AttributeTypeAndValue {
+                            attribute_type: attribute_type.to_string(),
+                            value: value.to_string(),
+                        })
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_escape_dn_value() {
+        assert_eq!(escape_dn_value("bob"), "bob");
+        assert_eq!(escape_dn_value("Best Group"), "Best Group");
+        assert_eq!(escape_dn_value("doe, john"), "doe\\, john");
+        assert_eq!(escape_dn_value("a+b=c"), "a\\+b\\=c");
+        assert_eq!(escape_dn_value(r#""<x>";\"#), r#"\"\<x\>\"\;\\"#);
+        assert_eq!(escape_dn_value("#admins "), "\\#admins\\ ");
+        assert_eq!(escape_dn_value(" a#b"), "\\ a#b");
+        assert_eq!(escape_dn_value(" "), "\\ ");
+    }
+
+    #[test]
+    fn test_parse_and_serialize() {
+        // The DN, how it parses, and how it is serialized.
+        let cases: &[(&str, DistinguishedName, &str)] = &[
+            // The examples of RFC 4514, section 4.
+            (
+                "UID=jsmith,DC=example,DC=net",
+                dn(&[&[("UID", "jsmith")], &[("DC", "example")], &[("DC", "net")]]),
+                "UID=jsmith,DC=example,DC=net",
+            ),
+            (
+                "OU=Sales+CN=J.  Smith,DC=example,DC=net",
+                dn(&[
+                    &[("OU", "Sales"), ("CN", "J.  Smith")],
+                    &[("DC", "example")],
+                    &[("DC", "net")],
+                ]),
+                "OU=Sales+CN=J.  Smith,DC=example,DC=net",
+            ),
+            (
+                r#"CN=James \"Jim\" Smith\, III,DC=example,DC=net"#,
+                dn(&[
+                    &[("CN", r#"James "Jim" Smith, III"#)],
+                    &[("DC", "example")],
+                    &[("DC", "net")],
+                ]),
+                r#"CN=James \"Jim\" Smith\, III,DC=example,DC=net"#,
+            ),
+            (
+                r"CN=Before\0dAfter,DC=example,DC=net",
+                dn(&[
+                    &[("CN", "Before\rAfter")],
+                    &[("DC", "example")],
+                    &[("DC", "net")],
+                ]),
+                "CN=Before\rAfter,DC=example,DC=net",
+            ),
+            (
+                "1.3.6.1.4.1.1466.0=#04024869,DC=example,DC=com",
+                dn(&[
+                    &[("1.3.6.1.4.1.1466.0", "Hi")],
+                    &[("DC", "example")],
+                    &[("DC", "com")],
+                ]),
+                "1.3.6.1.4.1.1466.0=Hi,DC=example,DC=com",
+            ),
+            (r"CN=Lu\C4\8Di\C4\87", dn(&[&[("CN", "Lučić")]]), "CN=Lučić"),
+            // Escaped separators, spaces after the commas, leading and trailing spaces.
+            (
+                r"uid=doe\, john,ou=people,dc=example,dc=com",
+                dn(&[
+                    &[("uid", "doe, john")],
+                    &[("ou", "people")],
+                    &[("dc", "example")],
+                    &[("dc", "com")],
+                ]),
+                r"uid=doe\, john,ou=people,dc=example,dc=com",
+            ),
+            (
+                "uid=bob, ou=people, dc=example, dc=com",
+                dn(&[
+                    &[("uid", "bob")],
+                    &[("ou", "people")],
+                    &[("dc", "example")],
+                    &[("dc", "com")],
+                ]),
+                "uid=bob,ou=people,dc=example,dc=com",
+            ),
+            (
+                r" cn = \ padded\  + sn=x ,dc=com ",
+                dn(&[&[("cn", " padded "), ("sn", "x")], &[("dc", "com")]]),
+                r"cn=\ padded\ +sn=x,dc=com",
+            ),
+            (
+                r"cn=\#hash\+plus\\back\3Dequals,dc=com",
+                dn(&[&[("cn", r"#hash+plus\back=equals")], &[("dc", "com")]]),
+                r"cn=\#hash\+plus\\back\=equals,dc=com",
+            ),
+            (
+                "cn=,dc=com",
+                dn(&[&[("cn", "")], &[("dc", "com")]]),
+                "cn=,dc=com",
+            ),
+            ("", dn(&[]), ""),
+        ];
+        for (input, parsed, serialized) in cases {
+            assert_eq!(
+                &DistinguishedName::parse(input).unwrap(),
+                parsed,
+                "{}",
+                input
+            );
+            assert_eq!(&parsed.to_string(), serialized, "{}", input);
+            assert_eq!(
+                &DistinguishedName::parse(serialized).unwrap(),
+                parsed,
+                "{}",
+                serialized
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for input in [
+            "dc=com,",
+            "dc=example,,dc=com",
+            "=bob,dc=com",
+            "uid,dc=com",
+            "uid=bob=test,dc=com",
+            r"cn=a\,dc=com\",
+            r"cn=a\4",
+            r"cn=a\zz",
+            r"cn=\ff",
+            "cn=#zz",
+            "cn=#0402486",
+            "cn=#0203010001",
+            "1.2.=x",
+            "c n=x",
+        ] {
+            let error = DistinguishedName::parse(input).unwrap_err();
+            assert_eq!(error.code, LdapResultCode::InvalidDNSyntax, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_compare_normalized() {
+        let normalized = |dn: &str| DistinguishedName::parse(dn).unwrap().normalized();
+        for (dn1, dn2) in [
+            ("UID=Bob,DC=Example,DC=com", "uid=bob, dc=example, dc=com"),
+            ("cn=a+sn=b,dc=com", "SN=B + CN=A,dc=com"),
+            (r"cn=x\41,dc=com", "cn=xa,dc=com"),
+            (r"cn=Lu\C4\8Di\C4\87", "cn=LUČIĆ"),
+            ("cn=#04024869", "cn=hi"),
+        ] {
+            assert_eq!(normalized(dn1), normalized(dn2), "{} == {}", dn1, dn2);
+        }
+        for (dn1, dn2) in [
+            ("uid=bob,dc=com", "uid=bob,dc=org"),
+            (r"cn=a\,b,dc=com", "cn=a,cn=b,dc=com"),
+            ("cn=a+sn=b,dc=com", "cn=a,sn=b,dc=com"),
+            (r"cn=\ a", "cn=a"),
+        ] {
+            assert_ne!(normalized(dn1), normalized(dn2), "{} != {}", dn1, dn2);
+        }
+    }
+}
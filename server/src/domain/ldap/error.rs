@@ -1,3 +1,4 @@
+use crate::domain::error::DomainError;
 use ldap3_proto::LdapResultCode;
 
 #[derive(Debug, PartialEq)]
@@ -15,3 +16,14 @@ impl std::fmt::Display for LdapError {
 impl std::error::Error for LdapError {}
 
 pub type LdapResult<T> = std::result::Result<T, LdapError>;
+
+/// The code of a backend error: `Busy` if the client can retry later, e.g. when no database
+/// connection was available in time, `UnwillingToPerform` for a write on a read-only replica,
+/// `code` otherwise.
+pub fn backend_error_code(error: &DomainError, code: LdapResultCode) -> LdapResultCode {
+    match error {
+        DomainError::ServerBusy(_) => LdapResultCode::Busy,
+        DomainError::ReadOnlyReplica => LdapResultCode::UnwillingToPerform,
+        _ => code,
+    }
+}
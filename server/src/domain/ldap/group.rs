@@ -1,4 +1,3 @@
-use chrono::TimeZone;
 use ldap3_proto::{
     proto::LdapOp, LdapFilter, LdapPartialAttribute, LdapResultCode, LdapSearchResultEntry,
 };
@@ -6,17 +5,23 @@ use tracing::{debug, instrument, warn};
 
 use crate::domain::{
     deserialize::deserialize_attribute_value,
-    handler::{GroupListerBackendHandler, GroupRequestFilter},
-    ldap::error::LdapError,
+    handler::{CreateGroupRequest, GroupListerBackendHandler, GroupRequestFilter},
+    ldap::error::{backend_error_code, LdapError},
+    model::GroupColumn,
     schema::{PublicSchema, SchemaGroupAttributeExtractor},
-    types::{AttributeName, AttributeType, Group, LdapObjectClass, UserId, Uuid},
+    types::{
+        AttributeName, AttributeType, AttributeValue, Group, GroupName, JpegPhoto, LdapObjectClass,
+        Serialized, UserId, Uuid,
+    },
 };
 
 use super::{
+    dn::escape_dn_value,
     error::LdapResult,
     utils::{
         expand_attribute_wildcards, get_custom_attribute, get_group_id_from_distinguished_name,
-        get_user_id_from_distinguished_name, map_group_field, GroupFieldType, LdapInfo,
+        get_user_id_from_distinguished_name, group_dn, is_operational_timestamp, map_group_field,
+        to_generalized_time, user_dn, GroupFieldType, LdapInfo,
     },
 };
 
@@ -24,14 +29,16 @@ pub fn get_group_attribute(
     group: &Group,
     base_dn_str: &str,
     attribute: &str,
-    user_filter: &Option<UserId>,
+    can_see_member: &impl Fn(&UserId) -> bool,
     ignored_group_attributes: &[AttributeName],
     schema: &PublicSchema,
 ) -> Option<Vec<Vec<u8>>> {
     let attribute = AttributeName::from(attribute);
     let attribute_values = match map_group_field(&attribute, schema) {
         GroupFieldType::ObjectClass => {
-            let mut classes = vec![b"groupOfUniqueNames".to_vec()];
+            // One class per form of the members. memberUid is served without posixGroup, which
+            // requires a gidNumber.
+            let mut classes = vec![b"groupOfUniqueNames".to_vec(), b"groupOfNames".to_vec()];
             classes.extend(
                 schema
                     .get_schema()
@@ -44,20 +51,35 @@ pub fn get_group_attribute(
         // Always returned as part of the base response.
         GroupFieldType::Dn => return None,
         GroupFieldType::EntryDn => {
-            vec![format!("uid={},ou=groups,{}", group.display_name, base_dn_str).into_bytes()]
+            vec![format!(
+                "uid={},ou=groups,{}",
+                escape_dn_value(group.display_name.as_str()),
+                base_dn_str
+            )
+            .into_bytes()]
         }
         GroupFieldType::DisplayName => vec![group.display_name.to_string().into_bytes()],
-        GroupFieldType::CreationDate => vec![chrono::Utc
-            .from_utc_datetime(&group.creation_date)
-            .to_rfc3339()
-            .into_bytes()],
+        GroupFieldType::CreationDate => {
+            if is_operational_timestamp(&attribute) {
+                vec![to_generalized_time(&group.creation_date)]
+            } else {
+                vec![group.creation_date.to_rfc3339().into_bytes()]
+            }
+        }
         GroupFieldType::Member => group
             .users
             .iter()
-            .filter(|u| user_filter.as_ref().map(|f| *u == f).unwrap_or(true))
-            .map(|u| format!("uid={},ou=people,{}", u, base_dn_str).into_bytes())
+            .filter(|u| can_see_member(u))
+            .map(|u| user_dn(u, base_dn_str).into_bytes())
+            .collect(),
+        GroupFieldType::MemberUid => group
+            .users
+            .iter()
+            .filter(|u| can_see_member(u))
+            .map(|u| u.to_string().into_bytes())
             .collect(),
         GroupFieldType::Uuid => vec![group.uuid.to_string().into_bytes()],
+        GroupFieldType::Description => vec![group.description.clone()?.into_bytes()],
         GroupFieldType::Attribute(attr, _, _) => {
             get_custom_attribute::<SchemaGroupAttributeExtractor>(&group.attributes, &attr, schema)?
         }
@@ -100,7 +122,9 @@ const ALL_GROUP_ATTRIBUTE_KEYS: &[&str] = &[
     "cn",
     "member",
     "uniquemember",
+    "memberuid",
     "entryuuid",
+    "description",
 ];
 
 fn expand_group_attribute_wildcards(attributes: &[String]) -> Vec<&str> {
@@ -111,12 +135,12 @@ fn make_ldap_search_group_result_entry(
     group: Group,
     base_dn_str: &str,
     expanded_attributes: &[&str],
-    user_filter: &Option<UserId>,
+    can_see_member: &impl Fn(&UserId) -> bool,
     ignored_group_attributes: &[AttributeName],
     schema: &PublicSchema,
 ) -> LdapSearchResultEntry {
     LdapSearchResultEntry {
-        dn: format!("cn={},ou=groups,{}", group.display_name, base_dn_str),
+        dn: group_dn(group.display_name.as_str(), base_dn_str),
         attributes: expanded_attributes
             .iter()
             .filter_map(|a| {
@@ -124,7 +148,7 @@ fn make_ldap_search_group_result_entry(
                     &group,
                     base_dn_str,
                     a,
-                    user_filter,
+                    can_see_member,
                     ignored_group_attributes,
                     schema,
                 )?;
@@ -151,6 +175,39 @@ fn get_group_attribute_equality_filter(
         .map(|v| GroupRequestFilter::AttributeEquality(field.clone(), v))
 }
 
+fn get_group_attribute_present_filter(
+    field: AttributeName,
+    typ: AttributeType,
+    is_list: bool,
+) -> GroupRequestFilter {
+    let present = GroupRequestFilter::AttributePresent(field.clone());
+    match (typ, is_list) {
+        // The empty strings are not returned.
+        (AttributeType::String, false) => GroupRequestFilter::And(vec![
+            present,
+            GroupRequestFilter::Not(Box::new(GroupRequestFilter::AttributeEquality(
+                field,
+                Serialized::from(""),
+            ))),
+        ]),
+        _ => present,
+    }
+}
+
+fn unknown_group_attribute_filter(
+    ldap_info: &LdapInfo,
+    field: &AttributeName,
+) -> GroupRequestFilter {
+    if !ldap_info.ignored_group_attributes.contains(field) {
+        warn!(
+            r#"Ignoring unknown group attribute "{}" in filter.\n\
+                To disable this warning, add it to "ignored_group_attributes" in the config."#,
+            field
+        );
+    }
+    GroupRequestFilter::from(false)
+}
+
 fn convert_group_filter(
     ldap_info: &LdapInfo,
     filter: &LdapFilter,
@@ -177,6 +234,7 @@ fn convert_group_filter(
                     )?;
                     Ok(GroupRequestFilter::Member(user_name))
                 }
+                GroupFieldType::MemberUid => Ok(GroupRequestFilter::Member(UserId::new(&value))),
                 GroupFieldType::ObjectClass => Ok(GroupRequestFilter::from(
                     matches!(value.as_str(), "groupofuniquenames" | "groupofnames")
                         || schema
@@ -196,16 +254,11 @@ fn convert_group_filter(
                         GroupRequestFilter::from(false)
                     }))
                 }
-                GroupFieldType::NoMatch => {
-                    if !ldap_info.ignored_group_attributes.contains(&field) {
-                        warn!(
-                            r#"Ignoring unknown group attribute "{}" in filter.\n\
-                                To disable this warning, add it to "ignored_group_attributes" in the config."#,
-                            field
-                        );
-                    }
-                    Ok(GroupRequestFilter::from(false))
-                }
+                GroupFieldType::Description => Ok(GroupRequestFilter::Equality(
+                    GroupColumn::Description,
+                    value,
+                )),
+                GroupFieldType::NoMatch => Ok(unknown_group_attribute_filter(ldap_info, &field)),
                 GroupFieldType::Attribute(field, typ, is_list) => {
                     get_group_attribute_equality_filter(&field, typ, is_list, &value)
                 }
@@ -222,12 +275,27 @@ fn convert_group_filter(
             filters.iter().map(rec).collect::<LdapResult<_>>()?,
         )),
         LdapFilter::Not(filter) => Ok(GroupRequestFilter::Not(Box::new(rec(filter)?))),
+        // Matches the groups for which the attribute would be returned.
         LdapFilter::Present(field) => {
             let field = AttributeName::from(field.as_str());
-            Ok(GroupRequestFilter::from(!matches!(
-                map_group_field(&field, schema),
-                GroupFieldType::NoMatch
-            )))
+            Ok(match map_group_field(&field, schema) {
+                GroupFieldType::ObjectClass
+                | GroupFieldType::Dn
+                | GroupFieldType::EntryDn
+                | GroupFieldType::DisplayName
+                | GroupFieldType::CreationDate
+                | GroupFieldType::Uuid => GroupRequestFilter::from(true),
+                GroupFieldType::Member | GroupFieldType::MemberUid => {
+                    GroupRequestFilter::HasMembers
+                }
+                GroupFieldType::Description => {
+                    GroupRequestFilter::Present(GroupColumn::Description)
+                }
+                GroupFieldType::Attribute(field, typ, is_list) => {
+                    get_group_attribute_present_filter(field, typ, is_list)
+                }
+                GroupFieldType::NoMatch => unknown_group_attribute_filter(ldap_info, &field),
+            })
         }
         LdapFilter::Substring(field, substring_filter) => {
             let field = AttributeName::from(field.as_str());
@@ -235,6 +303,10 @@ fn convert_group_filter(
                 GroupFieldType::DisplayName => Ok(GroupRequestFilter::DisplayNameSubString(
                     substring_filter.clone().into(),
                 )),
+                GroupFieldType::Description => Ok(GroupRequestFilter::SubString(
+                    GroupColumn::Description,
+                    substring_filter.clone().into(),
+                )),
                 GroupFieldType::NoMatch => Ok(GroupRequestFilter::from(false)),
                 _ => Err(LdapError {
                     code: LdapResultCode::UnwillingToPerform,
@@ -266,7 +338,7 @@ pub async fn get_groups_list<Backend: GroupListerBackendHandler>(
         .list_groups(Some(filters))
         .await
         .map_err(|e| LdapError {
-            code: LdapResultCode::Other,
+            code: backend_error_code(&e, LdapResultCode::Other),
             message: format!(r#"Error while listing groups "{}": {:#}"#, base, e),
         })
 }
@@ -275,8 +347,8 @@ pub fn convert_groups_to_ldap_op<'a>(
     groups: Vec<Group>,
     attributes: &'a [String],
     ldap_info: &'a LdapInfo,
-    user_filter: &'a Option<UserId>,
     schema: &'a PublicSchema,
+    can_see_member: impl Fn(&UserId) -> bool + 'a,
 ) -> impl Iterator<Item = LdapOp> + 'a {
     let expanded_attributes = if groups.is_empty() {
         None
@@ -289,9 +361,159 @@ pub fn convert_groups_to_ldap_op<'a>(
             g,
             &ldap_info.base_dn_str,
             expanded_attributes.as_ref().unwrap(),
-            user_filter,
+            &can_see_member,
             &ldap_info.ignored_group_attributes,
             schema,
         ))
     })
 }
+
+/// The object classes accepted when adding a group, besides the extra ones of the schema.
+const GROUP_OBJECT_CLASSES: &[&str] = &["top", "groupofuniquenames", "groupofnames"];
+
+/// Converts the attributes of an LDAP add request into the creation of a group, with its initial
+/// members. The members and the values are checked by the backend.
+pub fn convert_group_add(
+    display_name: GroupName,
+    attributes: &[LdapPartialAttribute],
+    ldap_info: &LdapInfo,
+    schema: &PublicSchema,
+) -> LdapResult<CreateGroupRequest> {
+    let mut request = CreateGroupRequest {
+        display_name,
+        ..Default::default()
+    };
+    let mut members = Vec::new();
+    for attribute in attributes {
+        let name = AttributeName::from(attribute.atype.to_ascii_lowercase().as_str());
+        let values = attribute.vals.iter().map(|v| {
+            std::str::from_utf8(v)
+                .map(str::to_owned)
+                .map_err(|e| LdapError {
+                    code: LdapResultCode::InvalidAttributeSyntax,
+                    message: format!("Value of `{}` is invalid UTF-8: {}", attribute.atype, e),
+                })
+        });
+        match map_group_field(&name, schema) {
+            GroupFieldType::ObjectClass => {
+                for class in values {
+                    let class = class?;
+                    let is_supported = GROUP_OBJECT_CLASSES
+                        .contains(&class.to_lowercase().as_str())
+                        || schema
+                            .get_schema()
+                            .extra_group_object_classes
+                            .contains(&LdapObjectClass::from(class.as_str()));
+                    if !is_supported {
+                        return Err(LdapError {
+                            code: LdapResultCode::ObjectClassViolation,
+                            message: format!(
+                                "Unsupported object class for a group: `{}`. The supported ones are {} and the extra group object classes of the schema",
+                                class,
+                                GROUP_OBJECT_CLASSES.join(", ")
+                            ),
+                        });
+                    }
+                }
+            }
+            GroupFieldType::DisplayName => {
+                for value in values {
+                    if GroupName::from(value?) != request.display_name {
+                        return Err(LdapError {
+                            code: LdapResultCode::NamingViolation,
+                            message: format!(
+                                "The value of `{}` doesn't match the DN",
+                                attribute.atype
+                            ),
+                        });
+                    }
+                }
+            }
+            GroupFieldType::Member => {
+                for value in values {
+                    members.push(get_user_id_from_distinguished_name(
+                        &value?,
+                        &ldap_info.base_dn,
+                        &ldap_info.base_dn_str,
+                    )?);
+                }
+            }
+            GroupFieldType::MemberUid => {
+                for value in values {
+                    members.push(UserId::new(&value?));
+                }
+            }
+            GroupFieldType::Description => {
+                request.description = match values.collect::<LdapResult<Vec<_>>>()?.as_slice() {
+                    [] => None,
+                    [description] => Some(description.clone()),
+                    _ => {
+                        return Err(LdapError {
+                            code: LdapResultCode::ConstraintViolation,
+                            message: format!("Attribute `{}` has a single value", attribute.atype),
+                        })
+                    }
+                };
+            }
+            GroupFieldType::Attribute(name, AttributeType::JpegPhoto, is_list) => {
+                let photos = attribute
+                    .vals
+                    .iter()
+                    .map(|v| {
+                        JpegPhoto::try_from(v.as_slice()).map_err(|e| LdapError {
+                            code: LdapResultCode::InvalidAttributeSyntax,
+                            message: format!(
+                                "Invalid JPEG photo for `{}`: {:#}",
+                                attribute.atype, e
+                            ),
+                        })
+                    })
+                    .collect::<LdapResult<Vec<_>>>()?;
+                let value = match (is_list, photos.as_slice()) {
+                    (true, _) => Serialized::from(&photos),
+                    (false, [photo]) => Serialized::from(photo),
+                    (false, _) => {
+                        return Err(LdapError {
+                            code: LdapResultCode::ConstraintViolation,
+                            message: format!("Attribute `{}` has a single value", attribute.atype),
+                        })
+                    }
+                };
+                request.attributes.push(AttributeValue { name, value });
+            }
+            GroupFieldType::Attribute(name, attribute_type, is_list) => {
+                let strings = values.collect::<LdapResult<Vec<_>>>()?;
+                let value = deserialize_attribute_value(&strings, attribute_type, is_list)
+                    .map_err(|e| LdapError {
+                        code: if !is_list && strings.len() > 1 {
+                            LdapResultCode::ConstraintViolation
+                        } else {
+                            LdapResultCode::InvalidAttributeSyntax
+                        },
+                        message: format!("Invalid value for `{}`: {:#}", attribute.atype, e),
+                    })?;
+                request.attributes.push(AttributeValue { name, value });
+            }
+            GroupFieldType::NoMatch if ldap_info.ignored_group_attributes.contains(&name) => {}
+            GroupFieldType::NoMatch => {
+                return Err(LdapError {
+                    code: LdapResultCode::UndefinedAttributeType,
+                    message: format!("Unknown attribute `{}`", attribute.atype),
+                })
+            }
+            GroupFieldType::CreationDate
+            | GroupFieldType::Dn
+            | GroupFieldType::EntryDn
+            | GroupFieldType::Uuid => {
+                return Err(LdapError {
+                    code: LdapResultCode::UnwillingToPerform,
+                    message: format!("Attribute `{}` is read-only", attribute.atype),
+                })
+            }
+        }
+    }
+    members.sort();
+    members.dedup();
+    request.members = members;
+    Ok(request)
+}
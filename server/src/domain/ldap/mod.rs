@@ -1,3 +1,4 @@
+pub mod dn;
 pub mod error;
 pub mod group;
 pub mod user;
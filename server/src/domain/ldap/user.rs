@@ -1,34 +1,44 @@
-use chrono::TimeZone;
 use ldap3_proto::{
-    proto::LdapOp, LdapFilter, LdapPartialAttribute, LdapResultCode, LdapSearchResultEntry,
+    proto::{LdapModify, LdapModifyType, LdapOp},
+    LdapFilter, LdapPartialAttribute, LdapResultCode, LdapSearchResultEntry,
 };
 use tracing::{debug, instrument, warn};
 
 use crate::domain::{
     deserialize::deserialize_attribute_value,
-    handler::{UserListerBackendHandler, UserRequestFilter},
+    handler::{
+        CreateUserRequest, SubStringFilter, UpdateUserRequest, UserListerBackendHandler,
+        UserRequestFilter,
+    },
     ldap::{
-        error::{LdapError, LdapResult},
+        error::{backend_error_code, LdapError, LdapResult},
         utils::{
-            expand_attribute_wildcards, get_custom_attribute, get_group_id_from_distinguished_name,
-            get_user_id_from_distinguished_name, map_user_field, LdapInfo, UserFieldType,
+            expand_attribute_wildcards, from_postal_address_syntax, get_custom_attribute,
+            get_group_id_from_distinguished_name, get_user_id_from_distinguished_name, group_dn,
+            is_operational_timestamp, map_user_field, to_generalized_time,
+            to_postal_address_syntax, user_dn, LdapInfo, UserFieldType,
         },
     },
     schema::{PublicSchema, SchemaUserAttributeExtractor},
     types::{
-        AttributeName, AttributeType, GroupDetails, LdapObjectClass, User, UserAndGroups,
-        UserColumn, UserId,
+        AttributeName, AttributeType, AttributeValue, Email, GroupDetails, JpegPhoto,
+        LdapObjectClass, Serialized, User, UserAndGroups, UserColumn, UserId, Uuid,
+    },
+    validation::{
+        check_email, normalize_email, HOST_WILDCARD, MAX_ATTRIBUTE_VALUE_LENGTH,
+        MAX_LOCALITY_LENGTH, MAX_NAME_LENGTH, MAX_POSTAL_ADDRESS_LENGTH,
     },
 };
+use crate::infra::configuration::LoginIdentifier;
 
 pub fn get_user_attribute(
     user: &User,
     attribute: &str,
-    base_dn_str: &str,
+    ldap_info: &LdapInfo,
     groups: Option<&[GroupDetails]>,
-    ignored_user_attributes: &[AttributeName],
     schema: &PublicSchema,
 ) -> Option<Vec<Vec<u8>>> {
+    let base_dn_str = &ldap_info.base_dn_str;
     let attribute = AttributeName::from(attribute);
     let attribute_values = match map_user_field(&attribute, schema) {
         UserFieldType::ObjectClass => {
@@ -38,6 +48,9 @@ pub fn get_user_attribute(
                 b"mailAccount".to_vec(),
                 b"person".to_vec(),
             ];
+            if !user.hosts.is_empty() {
+                classes.push(b"hostObject".to_vec());
+            }
             classes.extend(
                 schema
                     .get_schema()
@@ -50,33 +63,72 @@ pub fn get_user_attribute(
         // dn is always returned as part of the base response.
         UserFieldType::Dn => return None,
         UserFieldType::EntryDn => {
-            vec![format!("uid={},ou=people,{}", &user.user_id, base_dn_str).into_bytes()]
+            vec![user_dn(&user.user_id, base_dn_str).into_bytes()]
         }
         UserFieldType::MemberOf => groups
             .into_iter()
             .flatten()
             .map(|id_and_name| {
-                format!("cn={},ou=groups,{}", &id_and_name.display_name, base_dn_str).into_bytes()
+                group_dn(id_and_name.display_name.as_str(), base_dn_str).into_bytes()
             })
             .collect(),
+        // The users without an email keep logging in with their ID.
+        UserFieldType::PrimaryField(UserColumn::UserId)
+            if ldap_info.login_identifier == LoginIdentifier::Email
+                && !user.email.as_str().is_empty() =>
+        {
+            vec![user.email.to_string().into_bytes()]
+        }
         UserFieldType::PrimaryField(UserColumn::UserId) => {
-            vec![user.user_id.to_string().into_bytes()]
+            vec![user.user_id.as_entered().as_bytes().to_vec()]
         }
         UserFieldType::PrimaryField(UserColumn::Email) => vec![user.email.to_string().into_bytes()],
         UserFieldType::PrimaryField(
             UserColumn::LowercaseEmail
+            | UserColumn::LowercaseDisplayName
             | UserColumn::PasswordHash
             | UserColumn::TotpSecret
-            | UserColumn::MfaType,
+            | UserColumn::MfaType
+            | UserColumn::DeletedAt
+            | UserColumn::LegacyPasswordHash
+            | UserColumn::PreferredLanguage
+            | UserColumn::ExpiresAt
+            | UserColumn::ExpiryWarningSentAt,
         ) => panic!("Should not get here"),
         UserFieldType::PrimaryField(UserColumn::Uuid) => vec![user.uuid.to_string().into_bytes()],
         UserFieldType::PrimaryField(UserColumn::DisplayName) => {
             vec![user.display_name.clone()?.into_bytes()]
         }
-        UserFieldType::PrimaryField(UserColumn::CreationDate) => vec![chrono::Utc
-            .from_utc_datetime(&user.creation_date)
-            .to_rfc3339()
-            .into_bytes()],
+        UserFieldType::PrimaryField(UserColumn::CreationDate) => {
+            if is_operational_timestamp(&attribute) {
+                vec![to_generalized_time(&user.creation_date)]
+            } else {
+                vec![user.creation_date.to_rfc3339().into_bytes()]
+            }
+        }
+        UserFieldType::PrimaryField(UserColumn::LastLogin) => {
+            vec![to_generalized_time(user.last_login.as_ref()?)]
+        }
+        UserFieldType::PrimaryField(UserColumn::PasswordChangedAt) => {
+            vec![to_generalized_time(user.password_changed_at.as_ref()?)]
+        }
+        UserFieldType::PrimaryField(UserColumn::Hosts) => {
+            if user.hosts.is_empty() {
+                return None;
+            }
+            user.hosts.iter().map(|h| h.clone().into_bytes()).collect()
+        }
+        UserFieldType::PrimaryField(UserColumn::Phone) => vec![user.phone.clone()?.into_bytes()],
+        UserFieldType::PrimaryField(UserColumn::Mobile) => vec![user.mobile.clone()?.into_bytes()],
+        UserFieldType::PrimaryField(UserColumn::PostalAddress) => {
+            vec![to_postal_address_syntax(user.postal_address.as_deref()?).into_bytes()]
+        }
+        UserFieldType::PrimaryField(UserColumn::Locality) => {
+            vec![user.locality.clone()?.into_bytes()]
+        }
+        UserFieldType::PrimaryField(UserColumn::StateOrProvince) => {
+            vec![user.state_or_province.clone()?.into_bytes()]
+        }
         UserFieldType::Attribute(attr, _, _) => {
             get_custom_attribute::<SchemaUserAttributeExtractor>(&user.attributes, &attr, schema)?
         }
@@ -91,7 +143,7 @@ pub fn get_user_attribute(
                 )
             }
             _ => {
-                if ignored_user_attributes.contains(&attribute) {
+                if ldap_info.ignored_user_attributes.contains(&attribute) {
                     return None;
                 }
                 get_custom_attribute::<SchemaUserAttributeExtractor>(
@@ -117,6 +169,48 @@ pub fn get_user_attribute(
     }
 }
 
+/// Returns the schema name of the attribute that controls the visibility of the given column, or
+/// None if the column is never served.
+pub fn get_user_column_visibility_key(column: &UserColumn) -> Option<AttributeName> {
+    Some(
+        match column {
+            UserColumn::UserId => "user_id",
+            UserColumn::Email => "mail",
+            UserColumn::DisplayName => "display_name",
+            UserColumn::CreationDate => "creation_date",
+            UserColumn::Uuid => "uuid",
+            UserColumn::LastLogin => "last_login",
+            UserColumn::PasswordChangedAt => "password_changed_at",
+            UserColumn::ExpiresAt => "expires_at",
+            UserColumn::Hosts => "hosts",
+            UserColumn::Phone => "phone",
+            UserColumn::Mobile => "mobile",
+            UserColumn::PostalAddress => "postal_address",
+            UserColumn::Locality => "locality",
+            UserColumn::StateOrProvince => "state_or_province",
+            _ => return None,
+        }
+        .into(),
+    )
+}
+
+/// Returns the schema name of the attribute that controls the visibility of the given LDAP
+/// attribute, or None if the attribute is always visible (e.g. objectClass).
+pub fn get_user_attribute_visibility_key(
+    attribute: &AttributeName,
+    schema: &PublicSchema,
+) -> Option<AttributeName> {
+    match map_user_field(attribute, schema) {
+        UserFieldType::PrimaryField(column) => get_user_column_visibility_key(&column),
+        UserFieldType::Attribute(name, _, _) => Some(name),
+        UserFieldType::MemberOf => Some("groups".into()),
+        UserFieldType::ObjectClass
+        | UserFieldType::Dn
+        | UserFieldType::EntryDn
+        | UserFieldType::NoMatch => None,
+    }
+}
+
 const ALL_USER_ATTRIBUTE_KEYS: &[&str] = &[
     "objectclass",
     "uid",
@@ -127,30 +221,34 @@ const ALL_USER_ATTRIBUTE_KEYS: &[&str] = &[
     "jpegPhoto",
     "createtimestamp",
     "entryuuid",
+    "host",
+    "telephonenumber",
+    "mobile",
+    "postaladdress",
+    "l",
+    "st",
 ];
 
 fn make_ldap_search_user_result_entry(
     user: User,
-    base_dn_str: &str,
+    ldap_info: &LdapInfo,
     expanded_attributes: &[&str],
     groups: Option<&[GroupDetails]>,
-    ignored_user_attributes: &[AttributeName],
     schema: &PublicSchema,
+    can_read_attribute: &impl Fn(&UserId, &AttributeName) -> bool,
 ) -> LdapSearchResultEntry {
-    let dn = format!("uid={},ou=people,{}", user.user_id.as_str(), base_dn_str);
+    let dn = user_dn(&user.user_id, &ldap_info.base_dn_str);
     LdapSearchResultEntry {
         dn,
         attributes: expanded_attributes
             .iter()
+            .filter(|a| {
+                get_user_attribute_visibility_key(&AttributeName::from(**a), schema)
+                    .map(|key| can_read_attribute(&user.user_id, &key))
+                    .unwrap_or(true)
+            })
             .filter_map(|a| {
-                let values = get_user_attribute(
-                    &user,
-                    a,
-                    base_dn_str,
-                    groups,
-                    ignored_user_attributes,
-                    schema,
-                )?;
+                let values = get_user_attribute(&user, a, ldap_info, groups, schema)?;
                 Some(LdapPartialAttribute {
                     atype: a.to_string(),
                     vals: values,
@@ -174,7 +272,51 @@ fn get_user_attribute_equality_filter(
         .map(|v| UserRequestFilter::AttributeEquality(field.clone(), v))
 }
 
-fn convert_user_filter(
+fn get_user_attribute_present_filter(
+    field: AttributeName,
+    typ: AttributeType,
+    is_list: bool,
+) -> UserRequestFilter {
+    let present = UserRequestFilter::AttributePresent(field.clone());
+    match (typ, is_list) {
+        // The empty strings are not returned.
+        (AttributeType::String, false) => UserRequestFilter::And(vec![
+            present,
+            UserRequestFilter::Not(Box::new(UserRequestFilter::AttributeEquality(
+                field,
+                Serialized::from(""),
+            ))),
+        ]),
+        _ => present,
+    }
+}
+
+/// The users allowed on the host. With `host_wildcard`, that includes the users with "*", for
+/// the PAM modules that search for `(host=<hostname>)`. Without it, they only match the value
+/// "*" itself (`(host=\2a)`): the modules that read the values handle the wildcard themselves.
+fn get_user_host_filter(ldap_info: &LdapInfo, host: String) -> UserRequestFilter {
+    if ldap_info.host_wildcard && host != HOST_WILDCARD {
+        UserRequestFilter::Or(vec![
+            UserRequestFilter::Host(host),
+            UserRequestFilter::Host(HOST_WILDCARD.to_owned()),
+        ])
+    } else {
+        UserRequestFilter::Host(host)
+    }
+}
+
+fn unknown_user_attribute_filter(ldap_info: &LdapInfo, field: &AttributeName) -> UserRequestFilter {
+    if !ldap_info.ignored_user_attributes.contains(field) {
+        warn!(
+            r#"Ignoring unknown user attribute "{}" in filter.\n\
+                      To disable this warning, add it to "ignored_user_attributes" in the config"#,
+            field
+        );
+    }
+    UserRequestFilter::from(false)
+}
+
+pub(crate) fn convert_user_filter(
     ldap_info: &LdapInfo,
     filter: &LdapFilter,
     schema: &PublicSchema,
@@ -192,6 +334,15 @@ fn convert_user_filter(
             let field = AttributeName::from(field.as_str());
             let value = value.to_ascii_lowercase();
             match map_user_field(&field, schema) {
+                // Both, since the users without an email keep their ID as uid.
+                UserFieldType::PrimaryField(UserColumn::UserId)
+                    if ldap_info.login_identifier == LoginIdentifier::Email =>
+                {
+                    Ok(UserRequestFilter::Or(vec![
+                        UserRequestFilter::UserId(UserId::new(&value)),
+                        UserRequestFilter::Equality(UserColumn::LowercaseEmail, value),
+                    ]))
+                }
                 UserFieldType::PrimaryField(UserColumn::UserId) => {
                     Ok(UserRequestFilter::UserId(UserId::new(&value)))
                 }
@@ -199,19 +350,23 @@ fn convert_user_filter(
                     UserColumn::LowercaseEmail,
                     value,
                 )),
+                UserFieldType::PrimaryField(UserColumn::Hosts) => {
+                    Ok(get_user_host_filter(ldap_info, value))
+                }
+                UserFieldType::PrimaryField(UserColumn::PostalAddress) => {
+                    Ok(UserRequestFilter::Equality(
+                        UserColumn::PostalAddress,
+                        from_postal_address_syntax(&value),
+                    ))
+                }
                 UserFieldType::PrimaryField(field) => Ok(UserRequestFilter::Equality(field, value)),
                 UserFieldType::Attribute(field, typ, is_list) => {
                     get_user_attribute_equality_filter(&field, typ, is_list, &value)
                 }
-                UserFieldType::NoMatch => {
-                    if !ldap_info.ignored_user_attributes.contains(&field) {
-                        warn!(
-                            r#"Ignoring unknown user attribute "{}" in filter.\n\
-                                      To disable this warning, add it to "ignored_user_attributes" in the config"#,
-                            field
-                        );
-                    }
-                    Ok(UserRequestFilter::from(false))
+                UserFieldType::NoMatch => Ok(unknown_user_attribute_filter(ldap_info, &field)),
+                // Only the users with hosts have the hostObject class.
+                UserFieldType::ObjectClass if value == "hostobject" => {
+                    Ok(UserRequestFilter::Present(UserColumn::Hosts))
                 }
                 UserFieldType::ObjectClass => Ok(UserRequestFilter::from(
                     matches!(
@@ -243,19 +398,35 @@ fn convert_user_filter(
                 }
             }
         }
+        // Matches the users for which the attribute would be returned.
         LdapFilter::Present(field) => {
             let field = AttributeName::from(field.as_str());
-            // Check that it's a field we support.
-            Ok(UserRequestFilter::from(
-                field.as_str() == "objectclass"
-                    || field.as_str() == "dn"
-                    || field.as_str() == "distinguishedname"
-                    || !matches!(map_user_field(&field, schema), UserFieldType::NoMatch),
-            ))
+            Ok(match map_user_field(&field, schema) {
+                UserFieldType::ObjectClass | UserFieldType::Dn | UserFieldType::EntryDn => {
+                    UserRequestFilter::from(true)
+                }
+                UserFieldType::MemberOf => UserRequestFilter::MemberOfAny,
+                UserFieldType::PrimaryField(column) => UserRequestFilter::Present(column),
+                UserFieldType::Attribute(field, typ, is_list) => {
+                    get_user_attribute_present_filter(field, typ, is_list)
+                }
+                UserFieldType::NoMatch => unknown_user_attribute_filter(ldap_info, &field),
+            })
         }
         LdapFilter::Substring(field, substring_filter) => {
             let field = AttributeName::from(field.as_str());
             match map_user_field(&field, schema) {
+                UserFieldType::PrimaryField(UserColumn::UserId)
+                    if ldap_info.login_identifier == LoginIdentifier::Email =>
+                {
+                    Ok(UserRequestFilter::Or(vec![
+                        UserRequestFilter::UserIdSubString(substring_filter.clone().into()),
+                        UserRequestFilter::SubString(
+                            UserColumn::LowercaseEmail,
+                            substring_filter.clone().into(),
+                        ),
+                    ]))
+                }
                 UserFieldType::PrimaryField(UserColumn::UserId) => Ok(
                     UserRequestFilter::UserIdSubString(substring_filter.clone().into()),
                 ),
@@ -265,7 +436,10 @@ fn convert_user_filter(
                 | UserFieldType::Dn
                 | UserFieldType::EntryDn
                 | UserFieldType::PrimaryField(UserColumn::CreationDate)
-                | UserFieldType::PrimaryField(UserColumn::Uuid) => Err(LdapError {
+                | UserFieldType::PrimaryField(UserColumn::LastLogin)
+                | UserFieldType::PrimaryField(UserColumn::PasswordChangedAt)
+                | UserFieldType::PrimaryField(UserColumn::Uuid)
+                | UserFieldType::PrimaryField(UserColumn::Hosts) => Err(LdapError {
                     code: LdapResultCode::UnwillingToPerform,
                     message: format!(
                         "Unsupported user attribute for substring filter: {:?}",
@@ -277,6 +451,18 @@ fn convert_user_filter(
                     UserColumn::LowercaseEmail,
                     substring_filter.clone().into(),
                 )),
+                UserFieldType::PrimaryField(UserColumn::PostalAddress) => {
+                    let filter = SubStringFilter::from(substring_filter.clone());
+                    let convert = |part: String| from_postal_address_syntax(&part);
+                    Ok(UserRequestFilter::SubString(
+                        UserColumn::PostalAddress,
+                        SubStringFilter {
+                            initial: filter.initial.map(convert),
+                            any: filter.any.into_iter().map(convert).collect(),
+                            final_: filter.final_.map(convert),
+                        },
+                    ))
+                }
                 UserFieldType::PrimaryField(field) => Ok(UserRequestFilter::SubString(
                     field,
                     substring_filter.clone().into(),
@@ -294,24 +480,115 @@ fn expand_user_attribute_wildcards(attributes: &[String]) -> Vec<&str> {
     expand_attribute_wildcards(attributes, ALL_USER_ATTRIBUTE_KEYS)
 }
 
-#[instrument(skip_all, level = "debug", fields(ldap_filter, request_groups))]
-pub async fn get_user_list<Backend: UserListerBackendHandler>(
+/// Whether the requested attributes include a `JpegPhoto` attribute, e.g. jpegPhoto.
+pub fn requests_photos(attributes: &[String], schema: &PublicSchema) -> bool {
+    expand_user_attribute_wildcards(attributes)
+        .into_iter()
+        .any(|attribute| {
+            matches!(
+                map_user_field(&AttributeName::from(attribute), schema),
+                UserFieldType::Attribute(_, AttributeType::JpegPhoto, _)
+            )
+        })
+}
+
+/// The users matching a search, fetched one page at a time: the entries of a page can be sent
+/// before the next one is fetched, instead of loading a large directory whole.
+pub struct UserPages<'a, Backend> {
+    backend: &'a Backend,
+    filters: UserRequestFilter,
+    request_groups: bool,
+    request_photos: bool,
+    base: &'a str,
+    page_size: u64,
+    /// Fetched upfront, so that a failing search is reported before any entry is sent.
+    first_page: Option<Vec<UserAndGroups>>,
+    after: Option<UserId>,
+    done: bool,
+}
+
+impl<'a, Backend: UserListerBackendHandler> UserPages<'a, Backend> {
+    async fn fetch_page(&mut self) -> LdapResult<Vec<UserAndGroups>> {
+        let to_ldap_error = |e| LdapError {
+            code: backend_error_code(&e, LdapResultCode::Other),
+            message: format!(r#"Error while searching user "{}": {:#}"#, self.base, e),
+        };
+        let mut users = self
+            .backend
+            .list_users_page(
+                Some(self.filters.clone()),
+                self.request_groups,
+                self.after.take(),
+                self.page_size,
+            )
+            .await
+            .map_err(to_ldap_error)?;
+        self.done = (users.len() as u64) < self.page_size;
+        self.after = users.last().map(|u| u.user.user_id.clone());
+        // The photos are not part of the list, they are only loaded when requested.
+        if self.request_photos && !users.is_empty() {
+            let user_ids = users
+                .iter()
+                .map(|u| u.user.user_id.clone())
+                .collect::<Vec<_>>();
+            let mut photos = self
+                .backend
+                .get_user_photos(&user_ids)
+                .await
+                .map_err(to_ldap_error)?;
+            for user in users.iter_mut() {
+                if let Some(photos) = photos.remove(&user.user.user_id) {
+                    user.user
+                        .attributes
+                        .retain(|a| !photos.iter().any(|p| p.name == a.name));
+                    user.user.attributes.extend(photos);
+                }
+            }
+        }
+        Ok(users)
+    }
+
+    /// The next page of users, or `None` once they were all returned.
+    pub async fn next_page(&mut self) -> LdapResult<Option<Vec<UserAndGroups>>> {
+        if let Some(users) = self.first_page.take() {
+            return Ok(Some(users));
+        }
+        if self.done {
+            return Ok(None);
+        }
+        self.fetch_page().await.map(Some)
+    }
+}
+
+#[instrument(
+    skip_all,
+    level = "debug",
+    fields(ldap_filter, request_groups, request_photos)
+)]
+pub async fn get_user_list<'a, Backend: UserListerBackendHandler>(
     ldap_info: &LdapInfo,
     ldap_filter: &LdapFilter,
     request_groups: bool,
-    base: &str,
-    backend: &Backend,
+    request_photos: bool,
+    base: &'a str,
+    backend: &'a Backend,
     schema: &PublicSchema,
-) -> LdapResult<Vec<UserAndGroups>> {
+) -> LdapResult<UserPages<'a, Backend>> {
     let filters = convert_user_filter(ldap_info, ldap_filter, schema)?;
     debug!(?filters);
-    backend
-        .list_users(Some(filters), request_groups)
-        .await
-        .map_err(|e| LdapError {
-            code: LdapResultCode::Other,
-            message: format!(r#"Error while searching user "{}": {:#}"#, base, e),
-        })
+    let mut pages = UserPages {
+        backend,
+        filters,
+        request_groups,
+        request_photos,
+        base,
+        page_size: ldap_info.search_page_size,
+        first_page: None,
+        after: None,
+        done: false,
+    };
+    pages.first_page = Some(pages.fetch_page().await?);
+    Ok(pages)
 }
 
 pub fn convert_users_to_ldap_op<'a>(
@@ -319,6 +596,7 @@ pub fn convert_users_to_ldap_op<'a>(
     attributes: &'a [String],
     ldap_info: &'a LdapInfo,
     schema: &'a PublicSchema,
+    can_read_attribute: impl Fn(&UserId, &AttributeName) -> bool + 'a,
 ) -> impl Iterator<Item = LdapOp> + 'a {
     let expanded_attributes = if users.is_empty() {
         None
@@ -328,11 +606,440 @@ pub fn convert_users_to_ldap_op<'a>(
     users.into_iter().map(move |u| {
         LdapOp::SearchResultEntry(make_ldap_search_user_result_entry(
             u.user,
-            &ldap_info.base_dn_str,
+            ldap_info,
             expanded_attributes.as_ref().unwrap(),
             u.groups.as_deref(),
-            &ldap_info.ignored_user_attributes,
             schema,
+            &can_read_attribute,
         ))
     })
 }
+
+/// Applies an LDAP modification to the current values of an attribute.
+fn apply_modification(values: &mut Vec<Vec<u8>>, change: &LdapModify) -> LdapResult<()> {
+    let attribute = &change.modification.atype;
+    let describe = |value: &[u8]| String::from_utf8_lossy(value).into_owned();
+    match change.operation {
+        LdapModifyType::Add => {
+            if change.modification.vals.is_empty() {
+                return Err(LdapError {
+                    code: LdapResultCode::ConstraintViolation,
+                    message: format!("No value to add to `{}`", attribute),
+                });
+            }
+            for value in &change.modification.vals {
+                if values.contains(value) {
+                    return Err(LdapError {
+                        code: LdapResultCode::AttributeOrValueExists,
+                        message: format!(
+                            "Attribute `{}` already has the value \"{}\"",
+                            attribute,
+                            describe(value)
+                        ),
+                    });
+                }
+                values.push(value.clone());
+            }
+        }
+        LdapModifyType::Delete => {
+            if change.modification.vals.is_empty() {
+                if values.is_empty() {
+                    return Err(LdapError {
+                        code: LdapResultCode::NoSuchAttribute,
+                        message: format!("Attribute `{}` has no value", attribute),
+                    });
+                }
+                values.clear();
+            }
+            for value in &change.modification.vals {
+                match values.iter().position(|v| v == value) {
+                    Some(index) => {
+                        values.remove(index);
+                    }
+                    None => {
+                        return Err(LdapError {
+                            code: LdapResultCode::NoSuchAttribute,
+                            message: format!(
+                                "Attribute `{}` doesn't have the value \"{}\"",
+                                attribute,
+                                describe(value)
+                            ),
+                        })
+                    }
+                }
+            }
+        }
+        LdapModifyType::Replace => {
+            values.clear();
+            for value in &change.modification.vals {
+                if !values.contains(value) {
+                    values.push(value.clone());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn decode_value(attribute: &str, value: &[u8]) -> LdapResult<String> {
+    std::str::from_utf8(value)
+        .map(str::to_owned)
+        .map_err(|e| LdapError {
+            code: LdapResultCode::InvalidAttributeSyntax,
+            message: format!("Value of `{}` is invalid UTF-8: {}", attribute, e),
+        })
+}
+
+/// The single value of an attribute, or an empty string to remove it.
+fn get_single_value(attribute: &str, values: &[Vec<u8>]) -> LdapResult<String> {
+    match values {
+        [] => Ok(String::new()),
+        [value] => decode_value(attribute, value),
+        _ => Err(LdapError {
+            code: LdapResultCode::ConstraintViolation,
+            message: format!("Attribute `{}` has a single value", attribute),
+        }),
+    }
+}
+
+fn check_length(attribute: &str, value: String, max_length: usize) -> LdapResult<String> {
+    if value.chars().count() > max_length {
+        return Err(LdapError {
+            code: LdapResultCode::ConstraintViolation,
+            message: format!(
+                "Value of `{}` is longer than {} characters",
+                attribute, max_length
+            ),
+        });
+    }
+    Ok(value)
+}
+
+fn parse_photo(attribute: &str, value: &[u8]) -> LdapResult<JpegPhoto> {
+    JpegPhoto::try_from(value).map_err(|e| LdapError {
+        code: LdapResultCode::InvalidAttributeSyntax,
+        message: format!("Invalid JPEG photo for `{}`: {:#}", attribute, e),
+    })
+}
+
+/// Converts the changes of an LDAP modify request, except for the password, into an update of
+/// the user. All the changes are checked before returning, so that the request is applied
+/// entirely or not at all. The uid, the timestamps, the memberships and the other computed
+/// attributes cannot be modified, and the others only if `can_write_attribute` allows it.
+pub fn convert_user_modifications(
+    user: &User,
+    changes: &[&LdapModify],
+    ldap_info: &LdapInfo,
+    schema: &PublicSchema,
+    can_write_attribute: impl Fn(&AttributeName) -> bool,
+) -> LdapResult<UpdateUserRequest> {
+    // The values after the changes, by schema name, with the LDAP name used first.
+    let mut modified: Vec<(AttributeName, String, Vec<Vec<u8>>)> = Vec::new();
+    for change in changes {
+        let ldap_name = change.modification.atype.to_ascii_lowercase();
+        let attribute = AttributeName::from(ldap_name.as_str());
+        let key = match map_user_field(&attribute, schema) {
+            UserFieldType::PrimaryField(
+                UserColumn::Email
+                | UserColumn::DisplayName
+                | UserColumn::Hosts
+                | UserColumn::Phone
+                | UserColumn::Mobile
+                | UserColumn::PostalAddress
+                | UserColumn::Locality
+                | UserColumn::StateOrProvince,
+            )
+            | UserFieldType::Attribute(..) => get_user_attribute_visibility_key(&attribute, schema)
+                .expect("Writable attributes have a visibility key"),
+            UserFieldType::NoMatch => {
+                return Err(LdapError {
+                    code: LdapResultCode::UndefinedAttributeType,
+                    message: format!("Unknown attribute `{}`", change.modification.atype),
+                })
+            }
+            _ => {
+                return Err(LdapError {
+                    code: LdapResultCode::UnwillingToPerform,
+                    message: format!("Attribute `{}` is read-only", change.modification.atype),
+                })
+            }
+        };
+        if !can_write_attribute(&key) {
+            return Err(LdapError {
+                code: LdapResultCode::InsufficentAccessRights,
+                message: format!(
+                    "Attribute `{}` cannot be modified by the user",
+                    change.modification.atype
+                ),
+            });
+        }
+        let index = match modified.iter().position(|(k, _, _)| *k == key) {
+            Some(index) => index,
+            None => {
+                let values = get_user_attribute(user, &ldap_name, ldap_info, None, schema)
+                    .unwrap_or_default();
+                modified.push((key, ldap_name, values));
+                modified.len() - 1
+            }
+        };
+        apply_modification(&mut modified[index].2, change)?;
+    }
+    let mut request = UpdateUserRequest {
+        user_id: user.user_id.clone(),
+        ..Default::default()
+    };
+    for (key, ldap_name, values) in modified {
+        let attribute = ldap_name.as_str();
+        let single_value = || get_single_value(attribute, &values);
+        let short_value =
+            |max_length| check_length(attribute, get_single_value(attribute, &values)?, max_length);
+        match map_user_field(&AttributeName::from(attribute), schema) {
+            UserFieldType::PrimaryField(UserColumn::Email) => {
+                let email = normalize_email(&single_value()?);
+                if email.is_empty() {
+                    return Err(LdapError {
+                        code: LdapResultCode::ConstraintViolation,
+                        message: "The email cannot be removed".to_owned(),
+                    });
+                }
+                check_email(&email).map_err(|e| LdapError {
+                    code: LdapResultCode::InvalidAttributeSyntax,
+                    message: format!("Invalid email \"{}\": {}", email, e),
+                })?;
+                request.email = Some(email.into());
+            }
+            UserFieldType::PrimaryField(UserColumn::DisplayName) => {
+                request.display_name = Some(short_value(MAX_NAME_LENGTH)?);
+            }
+            UserFieldType::PrimaryField(UserColumn::Hosts) => {
+                request.hosts = Some(
+                    values
+                        .iter()
+                        .map(|v| decode_value(attribute, v))
+                        .collect::<LdapResult<_>>()?,
+                );
+            }
+            UserFieldType::PrimaryField(UserColumn::Phone) => {
+                request.phone = Some(single_value()?);
+            }
+            UserFieldType::PrimaryField(UserColumn::Mobile) => {
+                request.mobile = Some(single_value()?);
+            }
+            UserFieldType::PrimaryField(UserColumn::PostalAddress) => {
+                request.postal_address = Some(from_postal_address_syntax(&short_value(
+                    MAX_POSTAL_ADDRESS_LENGTH,
+                )?));
+            }
+            UserFieldType::PrimaryField(UserColumn::Locality) => {
+                request.locality = Some(short_value(MAX_LOCALITY_LENGTH)?);
+            }
+            UserFieldType::PrimaryField(UserColumn::StateOrProvince) => {
+                request.state_or_province = Some(short_value(MAX_LOCALITY_LENGTH)?);
+            }
+            UserFieldType::Attribute(name, _, _) if name.as_str() == "first_name" => {
+                request.first_name = Some(short_value(MAX_NAME_LENGTH)?);
+            }
+            UserFieldType::Attribute(name, _, _) if name.as_str() == "last_name" => {
+                request.last_name = Some(short_value(MAX_NAME_LENGTH)?);
+            }
+            UserFieldType::Attribute(name, _, _) if name.as_str() == "avatar" => {
+                request.avatar = Some(match values.as_slice() {
+                    [] => JpegPhoto::null(),
+                    [photo] => parse_photo(attribute, photo)?,
+                    _ => {
+                        return Err(LdapError {
+                            code: LdapResultCode::ConstraintViolation,
+                            message: format!("Attribute `{}` has a single value", attribute),
+                        })
+                    }
+                });
+            }
+            UserFieldType::Attribute(name, _, _) if values.is_empty() => {
+                request.delete_attributes.push(name);
+            }
+            UserFieldType::Attribute(name, AttributeType::JpegPhoto, is_list) => {
+                let photos = values
+                    .iter()
+                    .map(|v| parse_photo(attribute, v))
+                    .collect::<LdapResult<Vec<_>>>()?;
+                let value = match (is_list, photos.as_slice()) {
+                    (true, _) => Serialized::from(&photos),
+                    (false, [photo]) => Serialized::from(photo),
+                    (false, _) => {
+                        return Err(LdapError {
+                            code: LdapResultCode::ConstraintViolation,
+                            message: format!("Attribute `{}` has a single value", attribute),
+                        })
+                    }
+                };
+                request
+                    .insert_attributes
+                    .push(AttributeValue { name, value });
+            }
+            UserFieldType::Attribute(name, attribute_type, is_list) => {
+                let strings = values
+                    .iter()
+                    .map(|v| decode_value(attribute, v))
+                    .collect::<LdapResult<Vec<_>>>()?;
+                if strings
+                    .iter()
+                    .any(|s| s.chars().count() > MAX_ATTRIBUTE_VALUE_LENGTH)
+                {
+                    return Err(LdapError {
+                        code: LdapResultCode::ConstraintViolation,
+                        message: format!(
+                            "Value of `{}` is longer than {} characters",
+                            attribute, MAX_ATTRIBUTE_VALUE_LENGTH
+                        ),
+                    });
+                }
+                let value = deserialize_attribute_value(&strings, attribute_type, is_list)
+                    .map_err(|e| LdapError {
+                        code: if !is_list && strings.len() > 1 {
+                            LdapResultCode::ConstraintViolation
+                        } else {
+                            LdapResultCode::InvalidAttributeSyntax
+                        },
+                        message: format!("Invalid value for `{}`: {:#}", attribute, e),
+                    })?;
+                request
+                    .insert_attributes
+                    .push(AttributeValue { name, value });
+            }
+            _ => unreachable!("Attribute {} was checked to be writable", key),
+        }
+    }
+    Ok(request)
+}
+
+/// The object classes accepted when adding a user, besides the extra ones of the schema.
+const USER_OBJECT_CLASSES: &[&str] = &[
+    "top",
+    "person",
+    "organizationalperson",
+    "inetorgperson",
+    "posixaccount",
+    "mailaccount",
+    "hostobject",
+];
+
+/// The attributes of `posixAccount` that LLDAP doesn't store: they are ignored when adding a user,
+/// unless they are custom attributes of the schema.
+const IGNORED_POSIX_ACCOUNT_ATTRIBUTES: &[&str] = &[
+    "uidnumber",
+    "gidnumber",
+    "homedirectory",
+    "loginshell",
+    "gecos",
+];
+
+/// Converts the attributes of an LDAP add request into the creation of a user. The values are
+/// checked like for a modify request replacing them all.
+pub fn convert_user_add(
+    user_id: UserId,
+    attributes: &[LdapPartialAttribute],
+    ldap_info: &LdapInfo,
+    schema: &PublicSchema,
+) -> LdapResult<CreateUserRequest> {
+    let mut changes = Vec::new();
+    for attribute in attributes {
+        let name = AttributeName::from(attribute.atype.to_ascii_lowercase().as_str());
+        match name.as_str() {
+            "objectclass" => {
+                for value in &attribute.vals {
+                    let class = String::from_utf8_lossy(value).into_owned();
+                    let is_supported = USER_OBJECT_CLASSES.contains(&class.to_lowercase().as_str())
+                        || schema
+                            .get_schema()
+                            .extra_user_object_classes
+                            .contains(&LdapObjectClass::from(class.as_str()));
+                    if !is_supported {
+                        return Err(LdapError {
+                            code: LdapResultCode::ObjectClassViolation,
+                            message: format!(
+                                "Unsupported object class for a user: `{}`. The supported ones are {} and the extra user object classes of the schema",
+                                class,
+                                USER_OBJECT_CLASSES.join(", ")
+                            ),
+                        });
+                    }
+                }
+                continue;
+            }
+            "userpassword" => {
+                return Err(LdapError {
+                    code: LdapResultCode::UnwillingToPerform,
+                    message: "The password cannot be set when adding a user: replace the userPassword after the creation, or use the password modify extended operation".to_owned(),
+                })
+            }
+            posix_name
+                if IGNORED_POSIX_ACCOUNT_ATTRIBUTES.contains(&posix_name)
+                    && matches!(map_user_field(&name, schema), UserFieldType::NoMatch) =>
+            {
+                continue
+            }
+            _ if ldap_info.ignored_user_attributes.contains(&name) => continue,
+            _ => {}
+        }
+        if let UserFieldType::PrimaryField(UserColumn::UserId) = map_user_field(&name, schema) {
+            // The uid is the one of the DN.
+            if attribute
+                .vals
+                .iter()
+                .any(|v| UserId::new(&String::from_utf8_lossy(v)) != user_id)
+            {
+                return Err(LdapError {
+                    code: LdapResultCode::NamingViolation,
+                    message: format!("The value of `{}` doesn't match the DN", attribute.atype),
+                });
+            }
+            continue;
+        }
+        changes.push(LdapModify {
+            operation: LdapModifyType::Replace,
+            modification: attribute.clone(),
+        });
+    }
+    let creation_date = chrono::Utc::now();
+    let user = User {
+        user_id: user_id.clone(),
+        email: Email::default(),
+        display_name: None,
+        creation_date,
+        uuid: Uuid::from_name_and_date(user_id.as_str(), &creation_date),
+        last_login: None,
+        password_changed_at: None,
+        preferred_language: None,
+        hosts: Vec::new(),
+        phone: None,
+        mobile: None,
+        postal_address: None,
+        locality: None,
+        state_or_province: None,
+        expires_at: None,
+        attributes: Vec::new(),
+    };
+    let request = convert_user_modifications(
+        &user,
+        &changes.iter().collect::<Vec<_>>(),
+        ldap_info,
+        schema,
+        |_| true,
+    )?;
+    Ok(CreateUserRequest {
+        user_id,
+        email: request.email.unwrap_or_default(),
+        display_name: request.display_name,
+        first_name: request.first_name,
+        last_name: request.last_name,
+        avatar: request.avatar,
+        hosts: request.hosts.unwrap_or_default(),
+        phone: request.phone,
+        mobile: request.mobile,
+        postal_address: request.postal_address,
+        locality: request.locality,
+        state_or_province: request.state_or_province,
+        attributes: request.insert_attributes,
+        ..Default::default()
+    })
+}
@@ -1,16 +1,20 @@
-use chrono::{NaiveDateTime, TimeZone};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use itertools::Itertools;
 use ldap3_proto::{proto::LdapSubstringFilter, LdapResultCode};
 use tracing::{debug, instrument, warn};
 
 use crate::domain::{
     handler::SubStringFilter,
-    ldap::error::{LdapError, LdapResult},
+    ldap::{
+        dn::{escape_dn_value, AttributeTypeAndValue, DistinguishedName},
+        error::{LdapError, LdapResult},
+    },
     schema::{PublicSchema, SchemaAttributeExtractor},
     types::{
         AttributeName, AttributeType, AttributeValue, GroupName, JpegPhoto, UserColumn, UserId,
     },
 };
+use crate::infra::configuration::LoginIdentifier;
 
 impl From<LdapSubstringFilter> for SubStringFilter {
     fn from(
@@ -28,37 +32,60 @@ impl From<LdapSubstringFilter> for SubStringFilter {
     }
 }
 
-fn make_dn_pair<I>(mut iter: I) -> LdapResult<(String, String)>
-where
-    I: Iterator<Item = String>,
-{
-    (|| {
-        let pair = (
-            iter.next().ok_or_else(|| "Empty DN element".to_string())?,
-            iter.next().ok_or_else(|| "Missing DN value".to_string())?,
-        );
-        if let Some(e) = iter.next() {
-            Err(format!(
-                r#"Too many elements in distinguished name: "{:?}", "{:?}", "{:?}""#,
-                pair.0, pair.1, e
-            ))
-        } else {
-            Ok(pair)
-        }
-    })()
-    .map_err(|s| LdapError {
-        code: LdapResultCode::InvalidDNSyntax,
-        message: s,
-    })
+/// The DN of a user, as served over LDAP.
+pub fn user_dn(user_id: &UserId, base_dn_str: &str) -> String {
+    format!(
+        "uid={},ou=people,{}",
+        escape_dn_value(user_id.as_str()),
+        base_dn_str
+    )
 }
 
+/// The DN of a group, as served over LDAP.
+pub fn group_dn(display_name: &str, base_dn_str: &str) -> String {
+    format!(
+        "cn={},ou=groups,{}",
+        escape_dn_value(display_name),
+        base_dn_str
+    )
+}
+
+/// The (attribute, value) pairs of a DN, normalized for comparisons: `UID=Bob, OU=People` is
+/// `[("uid", "bob"), ("ou", "people")]`.
 pub fn parse_distinguished_name(dn: &str) -> LdapResult<Vec<(String, String)>> {
-    assert!(dn == dn.to_ascii_lowercase());
-    dn.split(',')
-        .map(|s| make_dn_pair(s.split('=').map(str::trim).map(String::from)))
+    DistinguishedName::parse(dn)?
+        .normalized()
+        .0
+        .into_iter()
+        .map(|mut rdn| match rdn.len() {
+            1 => {
+                let attribute = rdn.pop().unwrap();
+                Ok((attribute.attribute_type, attribute.value))
+            }
+            _ => Err(LdapError {
+                code: LdapResultCode::UnwillingToPerform,
+                message: format!(r#"Multi-valued RDNs are not supported: "{}""#, dn),
+            }),
+        })
         .collect()
 }
 
+/// The DN of the pairs returned by [`parse_distinguished_name`].
+pub fn serialize_distinguished_name(parts: &[(String, String)]) -> String {
+    DistinguishedName(
+        parts
+            .iter()
+            .map(|(attribute_type, value)| {
+                vec![AttributeTypeAndValue {
+                    attribute_type: attribute_type.clone(),
+                    value: value.clone(),
+                }]
+            })
+            .collect(),
+    )
+    .to_string()
+}
+
 fn get_id_from_distinguished_name(
     dn: &str,
     base_tree: &[(String, String)],
@@ -196,6 +223,20 @@ pub fn map_user_field(field: &AttributeName, schema: &PublicSchema) -> UserField
             UserFieldType::PrimaryField(UserColumn::CreationDate)
         }
         "entryuuid" | "uuid" => UserFieldType::PrimaryField(UserColumn::Uuid),
+        "authtimestamp" | "last_login" => UserFieldType::PrimaryField(UserColumn::LastLogin),
+        "pwdchangedtime" | "password_changed_at" => {
+            UserFieldType::PrimaryField(UserColumn::PasswordChangedAt)
+        }
+        "host" | "hosts" => UserFieldType::PrimaryField(UserColumn::Hosts),
+        "telephonenumber" | "phone" => UserFieldType::PrimaryField(UserColumn::Phone),
+        "mobile" | "mobiletelephonenumber" => UserFieldType::PrimaryField(UserColumn::Mobile),
+        "postaladdress" | "postal_address" => {
+            UserFieldType::PrimaryField(UserColumn::PostalAddress)
+        }
+        "l" | "localityname" | "locality" => UserFieldType::PrimaryField(UserColumn::Locality),
+        "st" | "stateorprovincename" | "state_or_province" => {
+            UserFieldType::PrimaryField(UserColumn::StateOrProvince)
+        }
         _ => schema
             .get_schema()
             .user_attributes
@@ -213,8 +254,12 @@ pub enum GroupFieldType {
     Dn,
     // Like Dn, but returned as part of the attributes.
     EntryDn,
+    /// member and uniqueMember, with the DNs of the users.
     Member,
+    /// With the bare user IDs, for the clients that only understand posixGroup.
+    MemberUid,
     Uuid,
+    Description,
     Attribute(AttributeName, AttributeType, bool),
 }
 
@@ -228,7 +273,9 @@ pub fn map_group_field(field: &AttributeName, schema: &PublicSchema) -> GroupFie
             GroupFieldType::CreationDate
         }
         "member" | "uniquemember" => GroupFieldType::Member,
+        "memberuid" => GroupFieldType::MemberUid,
         "entryuuid" | "uuid" => GroupFieldType::Uuid,
+        "description" => GroupFieldType::Description,
         _ => schema
             .get_schema()
             .group_attributes
@@ -243,6 +290,47 @@ pub struct LdapInfo {
     pub base_dn_str: String,
     pub ignored_user_attributes: Vec<AttributeName>,
     pub ignored_group_attributes: Vec<AttributeName>,
+    /// How many users a search fetches at once.
+    pub search_page_size: u64,
+    /// Whether the users with the host "*" match any host filter, see `ldap_host_wildcard`.
+    pub host_wildcard: bool,
+    /// With `Email`, the uid of the users is their email, see `login_identifier`.
+    pub login_identifier: LoginIdentifier,
+}
+
+/// Formats a timestamp as an LDAP GeneralizedTime in UTC, as used by the operational attributes
+/// like createTimestamp or authTimestamp.
+pub fn to_generalized_time(date: &DateTime<Utc>) -> Vec<u8> {
+    date.format("%Y%m%d%H%M%SZ").to_string().into_bytes()
+}
+
+/// Formats a postal address in the LDAP syntax (RFC 4517): the lines are separated by '$', and
+/// the '\' and '$' in the lines are escaped as "\5C" and "\24".
+pub fn to_postal_address_syntax(address: &str) -> String {
+    address
+        .lines()
+        .map(|line| line.replace('\\', "\\5C").replace('$', "\\24"))
+        .collect::<Vec<_>>()
+        .join("$")
+}
+
+/// The reverse of `to_postal_address_syntax`, for the filter values.
+pub fn from_postal_address_syntax(value: &str) -> String {
+    value
+        .split('$')
+        .map(|line| {
+            line.replace("\\24", "$")
+                .replace("\\5C", "\\")
+                .replace("\\5c", "\\")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The operational attributes aliasing the creation date, which are in GeneralizedTime rather
+/// than in RFC 3339 like `creationDate`.
+pub fn is_operational_timestamp(attribute: &AttributeName) -> bool {
+    matches!(attribute.as_str(), "createtimestamp" | "modifytimestamp")
 }
 
 pub fn get_custom_attribute<Extractor: SchemaAttributeExtractor>(
@@ -304,3 +392,54 @@ pub fn get_custom_attribute<Extractor: SchemaAttributeExtractor>(
                 })
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_distinguished_name() {
+        assert_eq!(
+            parse_distinguished_name(r"UID=Doe\, John, ou=People,dc=example").unwrap(),
+            vec![
+                ("uid".to_owned(), "doe, john".to_owned()),
+                ("ou".to_owned(), "people".to_owned()),
+                ("dc".to_owned(), "example".to_owned()),
+            ]
+        );
+        assert_eq!(
+            parse_distinguished_name("cn=a+sn=b,dc=example")
+                .unwrap_err()
+                .code,
+            LdapResultCode::UnwillingToPerform
+        );
+        assert_eq!(
+            parse_distinguished_name("uid=bob=test,dc=example")
+                .unwrap_err()
+                .code,
+            LdapResultCode::InvalidDNSyntax
+        );
+    }
+
+    #[test]
+    fn test_serialize_distinguished_name() {
+        let dn = r"uid=doe\, john,ou=people,dc=example";
+        assert_eq!(
+            serialize_distinguished_name(&parse_distinguished_name(dn).unwrap()),
+            dn
+        );
+    }
+
+    #[test]
+    fn test_postal_address_syntax() {
+        let address = "1 Main Street\nSuite $5 \\ back\nSpringfield";
+        let value = to_postal_address_syntax(address);
+        assert_eq!(value, r"1 Main Street$Suite \245 \5C back$Springfield");
+        assert_eq!(from_postal_address_syntax(&value), address);
+        assert_eq!(
+            from_postal_address_syntax(&value.to_ascii_lowercase()),
+            address.to_ascii_lowercase()
+        );
+    }
+}
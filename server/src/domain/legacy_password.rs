@@ -0,0 +1,108 @@
+//! Verification of the password hashes imported from other LDAP servers, in the RFC 2307 format:
+//! "{SCHEME}base64-encoded-hash".
+
+use base64::Engine;
+use sha2::Digest;
+
+/// The schemes that can be verified, and the length of their digest.
+const SUPPORTED_SCHEMES: &[(&str, usize)] = &[
+    ("SHA", 20),
+    ("SSHA", 20),
+    ("SHA256", 32),
+    ("SSHA256", 32),
+    ("SHA512", 64),
+    ("SSHA512", 64),
+];
+
+fn split_scheme(hash: &str) -> Option<(String, &str)> {
+    let (scheme, value) = hash.strip_prefix('{')?.split_once('}')?;
+    Some((scheme.to_ascii_uppercase(), value.trim()))
+}
+
+/// Returns the scheme of the hash, e.g. "SSHA", if it's in the RFC 2307 format.
+pub fn get_scheme(hash: &str) -> Option<String> {
+    split_scheme(hash).map(|(scheme, _)| scheme)
+}
+
+/// Whether the password can be checked against this hash.
+pub fn is_supported(hash: &str) -> bool {
+    get_scheme(hash).map_or(false, |scheme| {
+        SUPPORTED_SCHEMES.iter().any(|(s, _)| *s == scheme)
+    })
+}
+
+fn compute_digest<D: Digest>(password: &[u8], salt: &[u8]) -> Vec<u8> {
+    let mut hasher = D::new();
+    hasher.update(password);
+    hasher.update(salt);
+    hasher.finalize().to_vec()
+}
+
+/// Checks the password against the hash. Unsupported or malformed hashes never match.
+pub fn verify(hash: &str, password: &str) -> bool {
+    let (scheme, value) = match split_scheme(hash) {
+        Some(s) => s,
+        None => return false,
+    };
+    let digest_len = match SUPPORTED_SCHEMES.iter().find(|(s, _)| *s == scheme) {
+        Some((_, len)) => *len,
+        None => return false,
+    };
+    let decoded = match base64::engine::general_purpose::STANDARD.decode(value) {
+        Ok(d) => d,
+        Err(_) => return false,
+    };
+    let is_salted = scheme.starts_with("SSHA");
+    if decoded.len() < digest_len || (!is_salted && decoded.len() != digest_len) {
+        return false;
+    }
+    let (expected, salt) = decoded.split_at(digest_len);
+    let password = password.as_bytes();
+    let digest = match digest_len {
+        20 => compute_digest::<sha1::Sha1>(password, salt),
+        32 => compute_digest::<sha2::Sha256>(password, salt),
+        _ => compute_digest::<sha2::Sha512>(password, salt),
+    };
+    orion::util::secure_cmp(&digest, expected).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_sha1() {
+        assert!(verify("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=", "password"));
+        assert!(!verify("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=", "Password"));
+        assert!(verify("{ssha}yI6cZwQadOA1e+/f+T+H3eCQQhRzYWx0", "password"));
+        assert!(!verify("{SSHA}yI6cZwQadOA1e+/f+T+H3eCQQhRzYWx0", "wrong"));
+    }
+
+    #[test]
+    fn test_verify_sha2() {
+        assert!(verify(
+            "{SSHA256}ZqbcWj6+KqD5BBm617QPtrpLWEgXUSAWm8MErrSqRq5zYWx0MTIzNA==",
+            "password"
+        ));
+        assert!(verify(
+            "{SSHA512}BEfBbuXLz5jPB1yfxOEeYDS2NZyLd2aAj3cQbV0Dn6naOkbUByXXPt6mUn6X/NlYwZc+xOW9+QFnoGUDwa/4hXNhbHR5",
+            "password"
+        ));
+        // The salt is not accepted for unsalted schemes.
+        assert!(!verify(
+            "{SHA256}ZqbcWj6+KqD5BBm617QPtrpLWEgXUSAWm8MErrSqRq5zYWx0MTIzNA==",
+            "password"
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_schemes() {
+        assert!(!is_supported("{CRYPT}$6$salt$hash"));
+        assert!(!verify("{CRYPT}$6$salt$hash", "password"));
+        assert!(!is_supported("password"));
+        assert!(!verify("password", "password"));
+        assert!(!verify("{SSHA}not base64!", "password"));
+        assert!(is_supported("{SSHA512}abc"));
+        assert_eq!(get_scheme("{crypt}$1$abc"), Some("CRYPT".to_owned()));
+    }
+}
@@ -1,9 +1,15 @@
 pub mod deserialize;
 pub mod error;
+pub mod filter;
 pub mod handler;
 pub mod ldap;
+pub mod legacy_password;
 pub mod model;
 pub mod opaque_handler;
+pub mod password_check_pool;
+pub mod query_metrics;
+pub mod read_cache;
+pub mod read_model;
 pub mod schema;
 pub mod sql_backend_handler;
 pub mod sql_group_backend_handler;
@@ -13,3 +19,4 @@ pub mod sql_schema_backend_handler;
 pub mod sql_tables;
 pub mod sql_user_backend_handler;
 pub mod types;
+pub mod validation;
@@ -12,8 +12,11 @@ pub struct Model {
     pub group_id: GroupId,
     pub display_name: GroupName,
     pub lowercase_display_name: String,
-    pub creation_date: chrono::NaiveDateTime,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
     pub uuid: Uuid,
+    pub description: Option<String>,
+    /// Free-text notes, only visible to the admins.
+    pub notes: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -37,6 +40,8 @@ impl From<Model> for crate::domain::types::Group {
             display_name: group.display_name,
             creation_date: group.creation_date,
             uuid: group.uuid,
+            description: group.description,
+            notes: group.notes,
             users: vec![],
             attributes: Vec::new(),
         }
@@ -50,6 +55,8 @@ impl From<Model> for crate::domain::types::GroupDetails {
             display_name: group.display_name,
             creation_date: group.creation_date,
             uuid: group.uuid,
+            description: group.description,
+            notes: group.notes,
             attributes: Vec::new(),
         }
     }
@@ -11,7 +11,9 @@ pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub token: String,
     pub user_id: UserId,
-    pub expiry_date: chrono::NaiveDateTime,
+    pub expiry_date: chrono::DateTime<chrono::Utc>,
+    /// Set for the tokens verifying a new email address.
+    pub new_email: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
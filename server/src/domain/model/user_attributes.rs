@@ -20,6 +20,9 @@ pub struct Model {
     pub attribute_name: AttributeName,
     #[sea_orm(column_name = "user_attribute_value")]
     pub value: Serialized,
+    /// See `Serialized::content_hash`.
+    #[sea_orm(column_name = "user_attribute_value_hash")]
+    pub value_hash: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -56,12 +59,25 @@ impl Related<super::UserAttributeSchema> for Entity {
 
 impl ActiveModelBehavior for ActiveModel {}
 
+impl ActiveModel {
+    /// A value of the attribute, along with its hash.
+    pub fn new(user_id: UserId, attribute_name: AttributeName, value: Serialized) -> Self {
+        Self {
+            user_id: sea_orm::Set(user_id),
+            attribute_name: sea_orm::Set(attribute_name),
+            value_hash: sea_orm::Set(Some(value.content_hash())),
+            value: sea_orm::Set(value),
+        }
+    }
+}
+
 impl From<Model> for AttributeValue {
     fn from(
         Model {
             user_id: _,
             attribute_name,
             value,
+            value_hash: _,
         }: Model,
     ) -> Self {
         Self {
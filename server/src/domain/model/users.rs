@@ -12,15 +12,40 @@ pub struct Entity;
 #[sea_orm(table_name = "users")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
+    /// The lowercase form, see `UserId`.
     pub user_id: UserId,
+    /// The user ID with its case, to be displayed. NULL in the backups from before it existed.
+    pub user_id_as_entered: Option<String>,
     pub email: Email,
-    pub lowercase_email: String,
+    /// NULL for empty emails, so that they are not subject to the uniqueness constraint.
+    pub lowercase_email: Option<String>,
     pub display_name: Option<String>,
-    pub creation_date: chrono::NaiveDateTime,
+    /// For the case-insensitive filters on the display name.
+    pub lowercase_display_name: Option<String>,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
     pub password_hash: Option<Vec<u8>>,
     pub totp_secret: Option<String>,
     pub mfa_type: Option<String>,
     pub uuid: Uuid,
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_login: Option<chrono::DateTime<chrono::Utc>>,
+    pub password_changed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Password hash imported from another LDAP server, e.g. "{SSHA}...". It is replaced by an
+    /// OPAQUE password file on the first successful bind.
+    pub legacy_password_hash: Option<String>,
+    /// BCP 47 language tag, for the emails.
+    pub preferred_language: Option<String>,
+    /// The hosts the user may log into, see `hosts_to_column`.
+    pub hosts: Option<String>,
+    /// The phone numbers are normalized, see `normalize_phone_number`.
+    pub phone: Option<String>,
+    pub mobile: Option<String>,
+    pub postal_address: Option<String>,
+    pub locality: Option<String>,
+    pub state_or_province: Option<String>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the expiration warning was sent, reset when the expiration date changes.
+    pub expiry_warning_sent_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 impl EntityName for Entity {
@@ -29,17 +54,34 @@ impl EntityName for Entity {
     }
 }
 
-#[derive(Copy, Clone, Debug, EnumIter, DeriveColumn, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(
+    Copy, Clone, Debug, EnumIter, DeriveColumn, PartialEq, Eq, Hash, Serialize, Deserialize,
+)]
 pub enum Column {
     UserId,
+    UserIdAsEntered,
     Email,
     LowercaseEmail,
     DisplayName,
+    LowercaseDisplayName,
     CreationDate,
     PasswordHash,
     TotpSecret,
     MfaType,
     Uuid,
+    DeletedAt,
+    LastLogin,
+    PasswordChangedAt,
+    LegacyPasswordHash,
+    PreferredLanguage,
+    Hosts,
+    Phone,
+    Mobile,
+    PostalAddress,
+    Locality,
+    StateOrProvince,
+    ExpiresAt,
+    ExpiryWarningSentAt,
 }
 
 impl ColumnTrait for Column {
@@ -48,14 +90,29 @@ impl ColumnTrait for Column {
     fn def(&self) -> ColumnDef {
         match self {
             Column::UserId => ColumnType::String(Some(255)),
+            Column::UserIdAsEntered => ColumnType::String(Some(255)),
             Column::Email => ColumnType::String(Some(255)),
             Column::LowercaseEmail => ColumnType::String(Some(255)),
             Column::DisplayName => ColumnType::String(Some(255)),
-            Column::CreationDate => ColumnType::DateTime,
+            Column::LowercaseDisplayName => ColumnType::String(Some(255)),
+            Column::CreationDate => ColumnType::TimestampWithTimeZone,
             Column::PasswordHash => ColumnType::Binary(BlobSize::Medium),
             Column::TotpSecret => ColumnType::String(Some(64)),
             Column::MfaType => ColumnType::String(Some(64)),
             Column::Uuid => ColumnType::String(Some(36)),
+            Column::DeletedAt => ColumnType::TimestampWithTimeZone,
+            Column::LastLogin => ColumnType::TimestampWithTimeZone,
+            Column::PasswordChangedAt => ColumnType::TimestampWithTimeZone,
+            Column::LegacyPasswordHash => ColumnType::String(Some(255)),
+            Column::PreferredLanguage => ColumnType::String(Some(35)),
+            Column::Hosts => ColumnType::Text,
+            Column::Phone => ColumnType::Text,
+            Column::Mobile => ColumnType::Text,
+            Column::PostalAddress => ColumnType::Text,
+            Column::Locality => ColumnType::Text,
+            Column::StateOrProvince => ColumnType::Text,
+            Column::ExpiresAt => ColumnType::TimestampWithTimeZone,
+            Column::ExpiryWarningSentAt => ColumnType::TimestampWithTimeZone,
         }
         .def()
     }
@@ -112,14 +169,51 @@ impl Related<super::password_reset_tokens::Entity> for Entity {
 
 impl ActiveModelBehavior for ActiveModel {}
 
+/// The normalized hosts (see `normalize_hosts`) are stored comma-separated, with a comma at both
+/// ends so that each of them can be matched with `LIKE '%,web01,%'`: ",db02,web01,". NULL when
+/// there are none.
+pub fn hosts_to_column<S: AsRef<str>>(hosts: &[S]) -> Option<String> {
+    if hosts.is_empty() {
+        return None;
+    }
+    let mut column = String::from(",");
+    for host in hosts {
+        column.push_str(host.as_ref());
+        column.push(',');
+    }
+    Some(column)
+}
+
+pub fn hosts_from_column(column: Option<&str>) -> Vec<String> {
+    column
+        .unwrap_or_default()
+        .split(',')
+        .filter(|host| !host.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
 impl From<Model> for crate::domain::types::User {
     fn from(user: Model) -> Self {
         Self {
-            user_id: user.user_id,
+            user_id: match user.user_id_as_entered {
+                Some(user_id) => UserId::from(user_id),
+                None => user.user_id,
+            },
             email: user.email,
             display_name: user.display_name,
             creation_date: user.creation_date,
             uuid: user.uuid,
+            last_login: user.last_login,
+            password_changed_at: user.password_changed_at,
+            preferred_language: user.preferred_language,
+            hosts: hosts_from_column(user.hosts.as_deref()),
+            phone: user.phone,
+            mobile: user.mobile,
+            postal_address: user.postal_address,
+            locality: user.locality,
+            state_or_province: user.state_or_province,
+            expires_at: user.expires_at,
             attributes: Vec::new(),
         }
     }
@@ -0,0 +1,101 @@
+//! The OPAQUE computations and the imported password hashes are CPU-heavy by design: run on the
+//! async workers, a flood of binds would delay every other request. They run on the blocking
+//! threads instead, a limited number at a time.
+//!
+//! Past the limit, the checks wait for a slot up to `max_wait`, then fail with
+//! `DomainError::ServerBusy` for the client to retry, rather than piling up.
+
+use crate::domain::error::{DomainError, Result};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+
+/// How long a password check waits for a slot before being refused.
+const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct PasswordCheckPool {
+    permits: Arc<Semaphore>,
+    max_wait: Duration,
+}
+
+impl PasswordCheckPool {
+    /// At most `max_concurrent` checks at the same time, or one per CPU if 0.
+    pub fn new(max_concurrent: usize) -> Self {
+        Self::with_max_wait(max_concurrent, DEFAULT_MAX_WAIT)
+    }
+
+    pub fn with_max_wait(max_concurrent: usize, max_wait: Duration) -> Self {
+        let max_concurrent = if max_concurrent == 0 {
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            max_concurrent
+        };
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            max_wait,
+        }
+    }
+
+    /// Runs `check` on a blocking thread once a slot is free.
+    pub async fn run<T, F>(&self, check: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let permit = tokio::time::timeout(self.max_wait, self.permits.clone().acquire_owned())
+            .await
+            .map_err(|_| {
+                DomainError::ServerBusy("Too many password checks in progress".to_owned())
+            })?
+            .map_err(|e| DomainError::InternalError(e.to_string()))?;
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            check()
+        })
+        .await
+        .map_err(|e| DomainError::InternalError(format!("The password check failed: {}", e)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_run() {
+        let pool = PasswordCheckPool::new(2);
+        assert_eq!(pool.run(|| Ok(1)).await.unwrap(), 1);
+        pool.run(|| -> Result<()> { Err(DomainError::AuthenticationError("bob".to_owned())) })
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_busy() {
+        let pool = PasswordCheckPool::with_max_wait(1, Duration::from_millis(10));
+        let (release, released) = mpsc::channel::<()>();
+        let running = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.run(move || {
+                    released.recv().unwrap();
+                    Ok(())
+                })
+                .await
+            }
+        });
+        // Wait for the first check to take the only slot.
+        while pool.permits.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(
+            pool.run(|| Ok(())).await,
+            Err(DomainError::ServerBusy(_))
+        ));
+        release.send(()).unwrap();
+        running.await.unwrap().unwrap();
+        // The slot is free again.
+        pool.run(|| Ok(())).await.unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+//! Latency histograms of the backend queries, by query class. The classes are the names of the
+//! `SqlBackendHandler` methods, fixed at compile time, so that the number of series is bounded.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Upper bounds of the histogram buckets, in seconds.
+pub const BUCKETS_SECS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// Observations in each bucket of `BUCKETS_SECS`, then above the last one. Not cumulative.
+    pub counts: [u64; BUCKETS_SECS.len() + 1],
+    pub sum_secs: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = BUCKETS_SECS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(BUCKETS_SECS.len());
+        self.counts[bucket] += 1;
+        self.sum_secs += secs;
+        self.count += 1;
+    }
+
+    /// The cumulative counts, with their upper bound: `None` for +Inf.
+    pub fn cumulative_buckets(&self) -> impl Iterator<Item = (Option<f64>, u64)> + '_ {
+        self.counts
+            .iter()
+            .scan(0, |total, count| {
+                *total += count;
+                Some(*total)
+            })
+            .zip(BUCKETS_SECS.iter().map(|b| Some(*b)).chain([None]))
+            .map(|(total, bound)| (bound, total))
+    }
+}
+
+#[derive(Default)]
+pub struct QueryMetrics {
+    in_flight: AtomicUsize,
+    durations: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+/// Counts a query as in flight until dropped, then records its duration. Dropped when the query
+/// ends, with or without an error, or when its future is cancelled.
+pub struct QueryTimer<'a> {
+    metrics: &'a QueryMetrics,
+    query_class: &'static str,
+    start: Instant,
+}
+
+impl Drop for QueryTimer<'_> {
+    fn drop(&mut self) {
+        self.metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.metrics.observe(self.query_class, self.start.elapsed());
+    }
+}
+
+impl QueryMetrics {
+    pub fn start(&self, query_class: &'static str) -> QueryTimer<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        QueryTimer {
+            metrics: self,
+            query_class,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn observe(&self, query_class: &'static str, duration: Duration) {
+        self.durations
+            .lock()
+            .unwrap()
+            .entry(query_class)
+            .or_default()
+            .observe(duration);
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// The histograms of the query classes that ran at least once.
+    pub fn durations(&self) -> BTreeMap<&'static str, Histogram> {
+        self.durations.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(200));
+        histogram.observe(Duration::from_millis(1));
+        histogram.observe(Duration::from_millis(30));
+        histogram.observe(Duration::from_secs(10));
+        assert_eq!(histogram.count, 4);
+        assert!((histogram.sum_secs - 10.0312).abs() < 1e-9);
+        let buckets = histogram.cumulative_buckets().collect::<Vec<_>>();
+        assert_eq!(buckets.len(), BUCKETS_SECS.len() + 1);
+        assert_eq!(buckets[0], (Some(0.0005), 1));
+        assert_eq!(buckets[1], (Some(0.001), 2));
+        assert_eq!(buckets[5], (Some(0.025), 2));
+        assert_eq!(buckets[6], (Some(0.05), 3));
+        assert_eq!(buckets[11], (Some(2.5), 3));
+        assert_eq!(buckets[12], (None, 4));
+    }
+
+    #[test]
+    fn test_query_timer() {
+        let metrics = QueryMetrics::default();
+        let first = metrics.start("list_users");
+        {
+            let _second = metrics.start("get_user_details");
+            assert_eq!(metrics.in_flight(), 2);
+        }
+        assert_eq!(metrics.in_flight(), 1);
+        drop(first);
+        assert_eq!(metrics.in_flight(), 0);
+        assert_eq!(
+            metrics
+                .durations()
+                .into_iter()
+                .map(|(class, h)| (class, h.count))
+                .collect::<Vec<_>>(),
+            vec![("get_user_details", 1), ("list_users", 1)]
+        );
+    }
+}
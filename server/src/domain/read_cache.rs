@@ -0,0 +1,243 @@
+//! An optional in-memory cache of the hot reads of the SQL backend, e.g. the lookups of the users
+//! by email from the mail servers.
+//!
+//! Every write of the `SqlBackendHandler` clears the whole cache: the directory changes rarely,
+//! and a write never has to figure out which lookups it affects. The writes also bump a generation
+//! counter in the database, checked before serving from the cache, so that the writes of other
+//! processes on the same database (e.g. the `lldap` commands, or another instance) clear it too.
+
+use crate::{
+    domain::{
+        handler::{GroupRequestFilter, UserRequestFilter},
+        types::UserId,
+    },
+    infra::configuration::CacheOptions,
+};
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// The cached lookups. The type of the value depends on the variant.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    UserDetails(UserId),
+    UserGroups(UserId),
+    Users(Option<UserRequestFilter>, bool),
+    Groups(Option<GroupRequestFilter>),
+}
+
+struct CacheEntry {
+    inserted: Instant,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// Incremented by each invalidation, so that a read that started before a write doesn't
+    /// cache what it read.
+    generation: u64,
+    /// The generation of the database when the entries were read, see `sync_database_generation`.
+    database_generation: Option<u64>,
+    entries: HashMap<CacheKey, CacheEntry>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+pub struct ReadCache {
+    ttl: Duration,
+    max_entries: usize,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ReadCache {
+    pub fn new(options: &CacheOptions) -> Self {
+        Self {
+            ttl: Duration::from_secs(options.ttl_secs),
+            max_entries: options.max_entries,
+            state: Mutex::new(CacheState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The generation to pass to `insert` for a value read from now on.
+    pub fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    pub fn get<T: Clone + 'static>(&self, key: &CacheKey) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        let value = match state.entries.get(key) {
+            Some(entry) if entry.inserted.elapsed() < self.ttl => {
+                entry.value.downcast_ref::<T>().cloned()
+            }
+            Some(_) => {
+                state.entries.remove(key);
+                None
+            }
+            None => None,
+        };
+        match value {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
+    }
+
+    /// Caches a value read at `generation`, unless the cache was invalidated since. When the
+    /// cache is full, the expired entries are dropped, then the oldest one.
+    pub fn insert<T: Send + Sync + 'static>(&self, key: CacheKey, value: T, generation: u64) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return;
+        }
+        if state.entries.len() >= self.max_entries && !state.entries.contains_key(&key) {
+            let ttl = self.ttl;
+            state
+                .entries
+                .retain(|_, entry| entry.inserted.elapsed() < ttl);
+            if state.entries.len() >= self.max_entries {
+                let oldest = state
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.inserted)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    state.entries.remove(&oldest);
+                }
+            }
+        }
+        state.entries.insert(
+            key,
+            CacheEntry {
+                inserted: Instant::now(),
+                value: Arc::new(value),
+            },
+        );
+    }
+
+    pub fn invalidate(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.entries.clear();
+    }
+
+    /// Clears the cache if the generation of the database changed since the last call, i.e. if
+    /// the database was written to, possibly by another process.
+    pub fn sync_database_generation(&self, database_generation: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.database_generation != Some(database_generation) {
+            state.database_generation = Some(database_generation);
+            state.generation += 1;
+            state.entries.clear();
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.state.lock().unwrap().entries.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn cache(ttl_secs: u64, max_entries: usize) -> ReadCache {
+        ReadCache::new(&CacheOptions {
+            enabled: true,
+            ttl_secs,
+            max_entries,
+        })
+    }
+
+    fn key(user: &str) -> CacheKey {
+        CacheKey::UserDetails(UserId::new(user))
+    }
+
+    #[test]
+    fn test_get_and_insert() {
+        let cache = cache(60, 10);
+        assert_eq!(cache.get::<String>(&key("bob")), None);
+        cache.insert(key("bob"), "Bob".to_owned(), cache.generation());
+        assert_eq!(cache.get::<String>(&key("bob")), Some("Bob".to_owned()));
+        // The keys are case-insensitive, like the user IDs.
+        assert_eq!(cache.get::<String>(&key("BOB")), Some("Bob".to_owned()));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 1,
+                entries: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache = cache(60, 10);
+        let generation = cache.generation();
+        cache.insert(key("bob"), "Bob".to_owned(), generation);
+        cache.invalidate();
+        assert_eq!(cache.get::<String>(&key("bob")), None);
+        // Read before the invalidation: not cached.
+        cache.insert(key("bob"), "Bob".to_owned(), generation);
+        assert_eq!(cache.get::<String>(&key("bob")), None);
+        cache.insert(key("bob"), "Robert".to_owned(), cache.generation());
+        assert_eq!(cache.get::<String>(&key("bob")), Some("Robert".to_owned()));
+    }
+
+    #[test]
+    fn test_sync_database_generation() {
+        let cache = cache(60, 10);
+        cache.sync_database_generation(3);
+        cache.insert(key("bob"), "Bob".to_owned(), cache.generation());
+        cache.sync_database_generation(3);
+        assert_eq!(cache.get::<String>(&key("bob")), Some("Bob".to_owned()));
+        let generation = cache.generation();
+        cache.sync_database_generation(4);
+        assert_eq!(cache.get::<String>(&key("bob")), None);
+        // Read before the database changed: not cached.
+        cache.insert(key("bob"), "Bob".to_owned(), generation);
+        assert_eq!(cache.get::<String>(&key("bob")), None);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let cache = cache(0, 10);
+        cache.insert(key("bob"), "Bob".to_owned(), cache.generation());
+        assert_eq!(cache.get::<String>(&key("bob")), None);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let cache = cache(60, 2);
+        for user in ["alice", "bob", "carol"] {
+            cache.insert(key(user), user.to_owned(), cache.generation());
+        }
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.get::<String>(&key("alice")), None);
+        assert_eq!(cache.get::<String>(&key("carol")), Some("carol".to_owned()));
+    }
+}
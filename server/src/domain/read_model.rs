@@ -0,0 +1,1157 @@
+//! An optional in-memory copy of the users, groups and memberships, for the small read-mostly
+//! directories: see `in_memory_read_model`. It serves the lists of users and groups and the
+//! groups of a user without a database round-trip.
+//!
+//! It is loaded at startup. Every write of the `SqlBackendHandler` then reloads the entities it
+//! changed, once committed: the reloads are serialized, so that an older state can't overwrite a
+//! more recent one, and a write returns once the model shows it. The writes of other processes
+//! are never seen, hence the single instance.
+//!
+//! The filters are evaluated on the `FilterExpr`, like the SQL ones, with the same three-valued
+//! logic for the NULL columns. The few filters on data the model doesn't keep (e.g. the password
+//! hashes, the photos) are left to the database.
+
+use crate::domain::{
+    error::{DomainError, Result},
+    filter::{AttributeRef, Comparison, FilterExpr, FilterTarget, Groups, Users},
+    handler::{GroupRequestFilter, UserRequestFilter},
+    model::{self, GroupColumn, MembershipColumn, UserColumn},
+    sql_group_backend_handler::fetch_groups,
+    sql_tables::DbConnection,
+    sql_user_backend_handler::fetch_users_and_groups,
+    types::{
+        AttributeName, AttributeType, AttributeValue, Group, GroupDetails, GroupId, User,
+        UserAndGroups, UserId,
+    },
+};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QuerySelect, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+use tracing::warn;
+
+/// The entities changed by a write, to reload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// The user, its attributes and its memberships.
+    User(UserId),
+    /// The group, its attributes and its members.
+    Group(GroupId),
+    /// Everything, e.g. after a schema change.
+    All,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadModelStats {
+    pub users: usize,
+    pub groups: usize,
+    pub memberships: usize,
+    /// The contents of the entities and of the indexes, without the overhead of the maps.
+    pub approximate_bytes: usize,
+}
+
+#[derive(Default)]
+struct Directory {
+    /// The active users, without their photos (like `list_users`).
+    users: BTreeMap<UserId, User>,
+    groups: BTreeMap<GroupId, GroupDetails>,
+    /// The memberships of the active users, both ways.
+    user_groups: HashMap<UserId, BTreeSet<GroupId>>,
+    group_users: HashMap<GroupId, BTreeSet<UserId>>,
+    /// Lowercase email to user: the lowercase emails are unique.
+    emails: HashMap<String, UserId>,
+    /// Lowercase name to group: the lowercase names are unique.
+    group_names: HashMap<String, GroupId>,
+    /// The user attributes of type `JpegPhoto`, left out of `users`.
+    photo_attributes: HashSet<AttributeName>,
+    /// The deleted users that are not purged yet: their memberships are kept, and the group
+    /// filters on the members still see them. The purges of the `DbCleaner` are not seen, which
+    /// only sends more of these filters to the database.
+    deleted_users: BTreeSet<UserId>,
+}
+
+/// A filter on data that the model doesn't have: the database answers instead.
+struct Unsupported;
+
+/// The SQL truth value of a condition: `None` for unknown, when comparing a NULL. A row matches
+/// a filter only if it is `Some(true)`.
+type Truth = Option<bool>;
+
+type Supported<T> = std::result::Result<T, Unsupported>;
+
+fn as_str(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(Some(s)) => Some(s.as_str()),
+        _ => None,
+    }
+}
+
+fn lowercase(value: Option<&str>) -> Option<Value> {
+    value.map(|s| s.to_lowercase().into())
+}
+
+/// Like the lowercase columns, which are NULL for an empty value.
+fn lowercase_column(value: &str) -> Option<Value> {
+    Some(value.to_lowercase())
+        .filter(|s| !s.is_empty())
+        .map(Into::into)
+}
+
+fn compare(value: Option<Value>, comparison: &Comparison) -> Supported<Truth> {
+    if let Comparison::NullOrBefore(date) = comparison {
+        return match value {
+            None => Ok(Some(true)),
+            Some(Value::ChronoDateTimeUtc(Some(value))) => Ok(Some(*value < *date)),
+            Some(_) => Err(Unsupported),
+        };
+    }
+    if let Comparison::NotNull = comparison {
+        return Ok(Some(value.is_some()));
+    }
+    let value = match value {
+        None => return Ok(None),
+        Some(value) => value,
+    };
+    match comparison {
+        Comparison::Equal(expected) => {
+            // Different types could compare differently in SQL.
+            if std::mem::discriminant(&value) != std::mem::discriminant(expected) {
+                return Err(Unsupported);
+            }
+            Ok(Some(value == *expected))
+        }
+        Comparison::EqualIgnoreCase(expected) => match as_str(&value) {
+            Some(value) => Ok(Some(value == expected.to_lowercase())),
+            None => Err(Unsupported),
+        },
+        Comparison::SubString(filter) => match as_str(&value) {
+            Some(value) => Ok(Some(filter.matches(value))),
+            None => Err(Unsupported),
+        },
+        Comparison::NullOrBefore(_) | Comparison::NotNull => unreachable!(),
+    }
+}
+
+/// The in-memory counterpart of `FilterTarget`: the values compared by the filters.
+trait Entity<T: FilterTarget> {
+    /// The value of the column, or of its lowercase version if `ignore_case`: `Ok(None)` for
+    /// NULL.
+    fn column(&self, column: T::Column, ignore_case: bool) -> Supported<Option<Value>>;
+
+    /// Whether a related entity has a value of the column matching the comparison.
+    fn any_related(
+        &self,
+        directory: &Directory,
+        column: T::RelatedColumn,
+        comparison: &Comparison,
+    ) -> Supported<bool>;
+
+    fn attributes(
+        &self,
+        directory: &Directory,
+        name: &AttributeName,
+    ) -> Supported<&[AttributeValue]>;
+}
+
+impl Entity<Users> for User {
+    fn column(&self, column: UserColumn, ignore_case: bool) -> Supported<Option<Value>> {
+        Ok(match (column, ignore_case) {
+            (UserColumn::UserId, _) => Some(self.user_id.clone().into()),
+            (UserColumn::Email, false) => Some(self.email.as_str().into()),
+            (UserColumn::Email | UserColumn::LowercaseEmail, _) => {
+                lowercase_column(self.email.as_str())
+            }
+            (UserColumn::DisplayName, false) => self.display_name.clone().map(Into::into),
+            (UserColumn::DisplayName | UserColumn::LowercaseDisplayName, _) => {
+                self.display_name.as_deref().and_then(lowercase_column)
+            }
+            (UserColumn::Uuid, false) => Some(self.uuid.to_string().into()),
+            (UserColumn::Uuid, true) => Some(self.uuid.to_string().to_lowercase().into()),
+            (UserColumn::PreferredLanguage, false) => {
+                self.preferred_language.clone().map(Into::into)
+            }
+            (UserColumn::PreferredLanguage, true) => lowercase(self.preferred_language.as_deref()),
+            (UserColumn::Hosts, _) => model::users::hosts_to_column(&self.hosts).map(Into::into),
+            // The phone numbers have no letters.
+            (UserColumn::Phone, _) => self.phone.clone().map(Into::into),
+            (UserColumn::Mobile, _) => self.mobile.clone().map(Into::into),
+            (UserColumn::PostalAddress, false) => self.postal_address.clone().map(Into::into),
+            (UserColumn::PostalAddress, true) => lowercase(self.postal_address.as_deref()),
+            (UserColumn::Locality, false) => self.locality.clone().map(Into::into),
+            (UserColumn::Locality, true) => lowercase(self.locality.as_deref()),
+            (UserColumn::StateOrProvince, false) => self.state_or_province.clone().map(Into::into),
+            (UserColumn::StateOrProvince, true) => lowercase(self.state_or_province.as_deref()),
+            (UserColumn::CreationDate, false) => Some(self.creation_date.into()),
+            (UserColumn::LastLogin, false) => self.last_login.map(Into::into),
+            (UserColumn::PasswordChangedAt, false) => self.password_changed_at.map(Into::into),
+            (UserColumn::ExpiresAt, false) => self.expires_at.map(Into::into),
+            // Only the active users are in the model.
+            (UserColumn::DeletedAt, false) => None,
+            _ => return Err(Unsupported),
+        })
+    }
+
+    fn any_related(
+        &self,
+        directory: &Directory,
+        column: GroupColumn,
+        comparison: &Comparison,
+    ) -> Supported<bool> {
+        for group_id in directory
+            .user_groups
+            .get(&self.user_id)
+            .into_iter()
+            .flatten()
+        {
+            if let Some(group) = directory.groups.get(group_id) {
+                let value = group_column(group, column, comparison.ignores_case())?;
+                if compare(value, comparison)? == Some(true) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn attributes(
+        &self,
+        directory: &Directory,
+        name: &AttributeName,
+    ) -> Supported<&[AttributeValue]> {
+        if directory.photo_attributes.contains(name) {
+            return Err(Unsupported);
+        }
+        Ok(&self.attributes)
+    }
+}
+
+fn group_column(
+    group: &GroupDetails,
+    column: GroupColumn,
+    ignore_case: bool,
+) -> Supported<Option<Value>> {
+    Ok(match (column, ignore_case) {
+        (GroupColumn::GroupId, _) => Some(group.group_id.into()),
+        (GroupColumn::DisplayName, false) => Some(group.display_name.as_str().into()),
+        (GroupColumn::DisplayName | GroupColumn::LowercaseDisplayName, _) => {
+            lowercase(Some(group.display_name.as_str()))
+        }
+        (GroupColumn::Uuid, false) => Some(group.uuid.to_string().into()),
+        (GroupColumn::Uuid, true) => Some(group.uuid.to_string().to_lowercase().into()),
+        (GroupColumn::CreationDate, false) => Some(group.creation_date.into()),
+        (GroupColumn::Description, false) => group.description.clone().map(Into::into),
+        (GroupColumn::Description, true) => lowercase(group.description.as_deref()),
+        (GroupColumn::Notes, false) => group.notes.clone().map(Into::into),
+        (GroupColumn::Notes, true) => lowercase(group.notes.as_deref()),
+        _ => return Err(Unsupported),
+    })
+}
+
+impl Entity<Groups> for GroupDetails {
+    fn column(&self, column: GroupColumn, ignore_case: bool) -> Supported<Option<Value>> {
+        group_column(self, column, ignore_case)
+    }
+
+    /// The memberships of the deleted users are not in the model: if one of them could match,
+    /// the database answers.
+    fn any_related(
+        &self,
+        directory: &Directory,
+        column: MembershipColumn,
+        comparison: &Comparison,
+    ) -> Supported<bool> {
+        let membership_value = |user_id: &UserId| match column {
+            MembershipColumn::UserId => user_id.clone().into(),
+            MembershipColumn::GroupId => self.group_id.into(),
+        };
+        for user_id in directory
+            .group_users
+            .get(&self.group_id)
+            .into_iter()
+            .flatten()
+        {
+            if compare(Some(membership_value(user_id)), comparison)? == Some(true) {
+                return Ok(true);
+            }
+        }
+        for user_id in &directory.deleted_users {
+            if compare(Some(membership_value(user_id)), comparison)? == Some(true) {
+                return Err(Unsupported);
+            }
+        }
+        Ok(false)
+    }
+
+    fn attributes(&self, _: &Directory, _: &AttributeName) -> Supported<&[AttributeValue]> {
+        Ok(&self.attributes)
+    }
+}
+
+fn evaluate<T: FilterTarget, E: Entity<T>>(
+    filter: &FilterExpr<T>,
+    entity: &E,
+    directory: &Directory,
+) -> Supported<Truth> {
+    Ok(match filter {
+        FilterExpr::Constant(value) => Some(*value),
+        FilterExpr::And(filters) => {
+            let mut result = Some(true);
+            for filter in filters {
+                match evaluate(filter, entity, directory)? {
+                    Some(false) => return Ok(Some(false)),
+                    None => result = None,
+                    Some(true) => {}
+                }
+            }
+            result
+        }
+        FilterExpr::Or(filters) => {
+            let mut result = Some(false);
+            for filter in filters {
+                match evaluate(filter, entity, directory)? {
+                    Some(true) => return Ok(Some(true)),
+                    None => result = None,
+                    Some(false) => {}
+                }
+            }
+            result
+        }
+        FilterExpr::Not(filter) => evaluate(filter, entity, directory)?.map(|value| !value),
+        FilterExpr::Compare(AttributeRef::Column(column), comparison) => compare(
+            entity.column(*column, comparison.ignores_case())?,
+            comparison,
+        )?,
+        FilterExpr::Compare(AttributeRef::Related(column), comparison) => {
+            Some(entity.any_related(directory, *column, comparison)?)
+        }
+        FilterExpr::Compare(AttributeRef::Custom(name), comparison) => {
+            let mut found = false;
+            for attribute in entity.attributes(directory, name)? {
+                if attribute.name == *name
+                    && compare(Some(attribute.value.clone().into()), comparison)? == Some(true)
+                {
+                    found = true;
+                    break;
+                }
+            }
+            Some(found)
+        }
+    })
+}
+
+async fn fetch_deleted_user_ids(
+    connection: &DbConnection,
+    user_id: Option<&UserId>,
+) -> Result<BTreeSet<UserId>> {
+    let mut query = model::User::find()
+        .select_only()
+        .column(UserColumn::UserId)
+        .filter(UserColumn::DeletedAt.is_not_null());
+    if let Some(user_id) = user_id {
+        query = query.filter(UserColumn::UserId.eq(user_id));
+    }
+    Ok(query
+        .into_tuple::<(UserId,)>()
+        .all(connection)
+        .await?
+        .into_iter()
+        .map(|(user_id,)| user_id)
+        .collect())
+}
+
+impl Directory {
+    /// The users that can match the filter, from the indexes, or `None` to check them all.
+    fn user_candidates(&self, filter: &FilterExpr<Users>) -> Option<BTreeSet<UserId>> {
+        use AttributeRef::*;
+        match filter {
+            FilterExpr::Compare(Column(UserColumn::UserId), Comparison::Equal(value)) => {
+                Some(as_str(value).map(UserId::new).into_iter().collect())
+            }
+            FilterExpr::Compare(
+                Column(UserColumn::Email | UserColumn::LowercaseEmail),
+                Comparison::EqualIgnoreCase(email),
+            ) => Some(
+                self.emails
+                    .get(&email.to_lowercase())
+                    .cloned()
+                    .into_iter()
+                    .collect(),
+            ),
+            FilterExpr::Compare(
+                Related(GroupColumn::DisplayName),
+                Comparison::EqualIgnoreCase(name),
+            ) => Some(
+                self.group_names
+                    .get(&name.to_lowercase())
+                    .and_then(|group_id| self.group_users.get(group_id))
+                    .cloned()
+                    .unwrap_or_default(),
+            ),
+            FilterExpr::Compare(
+                Related(GroupColumn::GroupId),
+                Comparison::Equal(Value::Int(Some(group_id))),
+            ) => Some(
+                self.group_users
+                    .get(&GroupId(*group_id))
+                    .cloned()
+                    .unwrap_or_default(),
+            ),
+            FilterExpr::And(filters) => filters
+                .iter()
+                .filter_map(|filter| self.user_candidates(filter))
+                .min_by_key(BTreeSet::len),
+            FilterExpr::Or(filters) => filters
+                .iter()
+                .map(|filter| self.user_candidates(filter))
+                .try_fold(BTreeSet::new(), |mut all, candidates| {
+                    all.extend(candidates?);
+                    Some(all)
+                }),
+            _ => None,
+        }
+    }
+
+    /// The groups of the user, like in `list_users`: without their attributes, by name.
+    fn groups_of(&self, user_id: &UserId) -> Vec<GroupDetails> {
+        let mut groups: Vec<_> = self
+            .user_groups
+            .get(user_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|group_id| self.groups.get(group_id))
+            .map(|group| GroupDetails {
+                attributes: Vec::new(),
+                ..group.clone()
+            })
+            .collect();
+        groups.sort_by(|g1, g2| g1.display_name.as_str().cmp(g2.display_name.as_str()));
+        groups
+    }
+
+    fn list_users(
+        &self,
+        filters: Option<&UserRequestFilter>,
+        get_groups: bool,
+        after: Option<&UserId>,
+        limit: Option<u64>,
+    ) -> Option<Vec<UserAndGroups>> {
+        let filter = filters
+            .cloned()
+            .map(FilterExpr::<Users>::from)
+            .unwrap_or(FilterExpr::Constant(true));
+        let candidates: Vec<&User> = match self.user_candidates(&filter) {
+            Some(user_ids) => user_ids
+                .iter()
+                .filter_map(|user_id| self.users.get(user_id))
+                .collect(),
+            None => self.users.values().collect(),
+        };
+        let mut users = Vec::new();
+        for user in candidates
+            .into_iter()
+            .filter(|user| after.map_or(true, |after| user.user_id > *after))
+        {
+            if limit.is_some_and(|limit| users.len() as u64 >= limit) {
+                break;
+            }
+            if evaluate(&filter, user, self).ok()? == Some(true) {
+                users.push(UserAndGroups {
+                    user: user.clone(),
+                    groups: get_groups.then(|| self.groups_of(&user.user_id)),
+                });
+            }
+        }
+        Some(users)
+    }
+
+    fn list_groups(&self, filters: Option<&GroupRequestFilter>) -> Option<Vec<Group>> {
+        let filter = filters
+            .cloned()
+            .map(FilterExpr::<Groups>::from)
+            .unwrap_or(FilterExpr::Constant(true));
+        let mut groups = Vec::new();
+        for group in self.groups.values() {
+            if evaluate(&filter, group, self).ok()? == Some(true) {
+                groups.push(Group {
+                    id: group.group_id,
+                    display_name: group.display_name.clone(),
+                    creation_date: group.creation_date,
+                    uuid: group.uuid.clone(),
+                    description: group.description.clone(),
+                    notes: group.notes.clone(),
+                    users: self
+                        .group_users
+                        .get(&group.group_id)
+                        .into_iter()
+                        .flatten()
+                        .cloned()
+                        .collect(),
+                    attributes: group.attributes.clone(),
+                });
+            }
+        }
+        groups.sort_by(|g1, g2| g1.display_name.cmp(&g2.display_name));
+        Some(groups)
+    }
+
+    fn remove_user(&mut self, user_id: &UserId) {
+        if let Some(user) = self.users.remove(user_id) {
+            self.emails.remove(&user.email.as_str().to_lowercase());
+        }
+        for group_id in self.user_groups.remove(user_id).into_iter().flatten() {
+            if let Some(members) = self.group_users.get_mut(&group_id) {
+                members.remove(user_id);
+            }
+        }
+    }
+
+    fn insert_user(&mut self, user: UserAndGroups) {
+        let user_id = user.user.user_id.clone();
+        if !user.user.email.as_str().is_empty() {
+            self.emails
+                .insert(user.user.email.as_str().to_lowercase(), user_id.clone());
+        }
+        let group_ids: BTreeSet<_> = user
+            .groups
+            .into_iter()
+            .flatten()
+            .map(|group| group.group_id)
+            .collect();
+        for group_id in &group_ids {
+            self.group_users
+                .entry(*group_id)
+                .or_default()
+                .insert(user_id.clone());
+        }
+        self.user_groups.insert(user_id.clone(), group_ids);
+        self.users.insert(user_id, user.user);
+    }
+
+    fn remove_group(&mut self, group_id: GroupId) {
+        if let Some(group) = self.groups.remove(&group_id) {
+            self.group_names
+                .remove(&group.display_name.as_str().to_lowercase());
+        }
+        for user_id in self.group_users.remove(&group_id).into_iter().flatten() {
+            if let Some(groups) = self.user_groups.get_mut(&user_id) {
+                groups.remove(&group_id);
+            }
+        }
+    }
+
+    fn insert_group(&mut self, group: Group) {
+        self.group_names
+            .insert(group.display_name.as_str().to_lowercase(), group.id);
+        for user_id in &group.users {
+            self.user_groups
+                .entry(user_id.clone())
+                .or_default()
+                .insert(group.id);
+        }
+        self.group_users
+            .insert(group.id, group.users.into_iter().collect());
+        self.groups.insert(
+            group.id,
+            GroupDetails {
+                group_id: group.id,
+                display_name: group.display_name,
+                creation_date: group.creation_date,
+                uuid: group.uuid,
+                description: group.description,
+                notes: group.notes,
+                attributes: group.attributes,
+            },
+        );
+    }
+
+    async fn load(connection: &DbConnection) -> Result<Self> {
+        let photo_attributes = model::UserAttributeSchema::find()
+            .select_only()
+            .column(model::UserAttributeSchemaColumn::AttributeName)
+            .filter(model::UserAttributeSchemaColumn::AttributeType.eq(AttributeType::JpegPhoto))
+            .into_tuple::<(AttributeName,)>()
+            .all(connection)
+            .await?
+            .into_iter()
+            .map(|(name,)| name)
+            .collect();
+        let mut directory = Directory {
+            photo_attributes,
+            deleted_users: fetch_deleted_user_ids(connection, None).await?,
+            ..Default::default()
+        };
+        for group in fetch_groups(connection, None).await? {
+            directory.insert_group(group);
+        }
+        for user in fetch_users_and_groups(connection, None).await? {
+            directory.insert_user(user);
+        }
+        Ok(directory)
+    }
+
+    fn set_user(&mut self, user_id: &UserId, users: Vec<UserAndGroups>, deleted: bool) {
+        self.remove_user(user_id);
+        users.into_iter().for_each(|user| self.insert_user(user));
+        if deleted {
+            self.deleted_users.insert(user_id.clone());
+        } else {
+            self.deleted_users.remove(user_id);
+        }
+    }
+
+    fn set_group(&mut self, group_id: GroupId, groups: Vec<Group>) {
+        self.remove_group(group_id);
+        groups
+            .into_iter()
+            .for_each(|group| self.insert_group(group));
+    }
+
+    fn stats(&self) -> ReadModelStats {
+        let attributes_bytes = |attributes: &[AttributeValue]| -> usize {
+            attributes
+                .iter()
+                .map(|a| {
+                    std::mem::size_of::<AttributeValue>()
+                        + a.name.as_str().len()
+                        + a.value.byte_len()
+                })
+                .sum()
+        };
+        let users_bytes: usize = self
+            .users
+            .values()
+            .map(|user| {
+                std::mem::size_of::<User>()
+                    // The key, and the email index.
+                    + 2 * user.user_id.as_str().len()
+                    + 2 * user.email.as_str().len()
+                    + user.display_name.as_ref().map_or(0, String::len)
+                    + user.preferred_language.as_ref().map_or(0, String::len)
+                    + user.hosts.iter().map(String::len).sum::<usize>()
+                    + [
+                        &user.phone,
+                        &user.mobile,
+                        &user.postal_address,
+                        &user.locality,
+                        &user.state_or_province,
+                    ]
+                    .into_iter()
+                    .map(|field| field.as_ref().map_or(0, String::len))
+                    .sum::<usize>()
+                    + attributes_bytes(&user.attributes)
+            })
+            .sum();
+        let groups_bytes: usize = self
+            .groups
+            .values()
+            .map(|group| {
+                std::mem::size_of::<GroupDetails>()
+                    + 2 * group.display_name.as_str().len()
+                    + group.description.as_ref().map_or(0, String::len)
+                    + group.notes.as_ref().map_or(0, String::len)
+                    + attributes_bytes(&group.attributes)
+            })
+            .sum();
+        let memberships: usize = self.user_groups.values().map(BTreeSet::len).sum();
+        let memberships_bytes: usize = self
+            .user_groups
+            .iter()
+            .map(|(user_id, groups)| {
+                groups.len()
+                    * 2
+                    * (std::mem::size_of::<GroupId>()
+                        + std::mem::size_of::<UserId>()
+                        + user_id.as_str().len())
+            })
+            .sum();
+        ReadModelStats {
+            users: self.users.len(),
+            groups: self.groups.len(),
+            memberships,
+            approximate_bytes: users_bytes + groups_bytes + memberships_bytes,
+        }
+    }
+}
+
+pub struct ReadModel {
+    directory: RwLock<Directory>,
+    /// Serializes the reloads, held from the query to the update of `directory`.
+    reload_lock: tokio::sync::Mutex<()>,
+    /// Set when a reload failed: the reads go to the database until the next write reloads
+    /// everything.
+    stale: AtomicBool,
+}
+
+impl ReadModel {
+    pub async fn load(connection: &DbConnection) -> Result<Self> {
+        Ok(Self {
+            directory: RwLock::new(Directory::load(connection).await?),
+            reload_lock: tokio::sync::Mutex::new(()),
+            stale: AtomicBool::new(false),
+        })
+    }
+
+    /// Reloads the changed entities from the database, after a write.
+    pub async fn reload(&self, connection: &DbConnection, change: Change) {
+        let _guard = self.reload_lock.lock().await;
+        let change = if self.stale.load(Ordering::Acquire) {
+            Change::All
+        } else {
+            change
+        };
+        match self.fetch_and_apply(connection, &change).await {
+            Ok(()) => self.stale.store(false, Ordering::Release),
+            Err(e) => {
+                warn!(
+                    "Could not update the in-memory read model after a change of {:?}, the reads go to the database until the next write: {:#}",
+                    change, e
+                );
+                self.stale.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    async fn fetch_and_apply(&self, connection: &DbConnection, change: &Change) -> Result<()> {
+        match change {
+            Change::User(user_id) => {
+                let users = fetch_users_and_groups(
+                    connection,
+                    Some(UserRequestFilter::UserId(user_id.clone())),
+                )
+                .await?;
+                let deleted = users.is_empty()
+                    && !fetch_deleted_user_ids(connection, Some(user_id))
+                        .await?
+                        .is_empty();
+                self.directory
+                    .write()
+                    .unwrap()
+                    .set_user(user_id, users, deleted);
+            }
+            Change::Group(group_id) => {
+                let groups =
+                    fetch_groups(connection, Some(GroupRequestFilter::GroupId(*group_id))).await?;
+                self.directory.write().unwrap().set_group(*group_id, groups);
+            }
+            Change::All => {
+                let directory = Directory::load(connection).await?;
+                *self.directory.write().unwrap() = directory;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a read on the directory, unless the model is stale.
+    fn read<T>(&self, read: impl FnOnce(&Directory) -> Option<T>) -> Option<T> {
+        if self.stale.load(Ordering::Acquire) {
+            return None;
+        }
+        read(&self.directory.read().unwrap())
+    }
+
+    /// `None` if the model can't answer: the database has to.
+    pub fn list_users(
+        &self,
+        filters: Option<&UserRequestFilter>,
+        get_groups: bool,
+        after: Option<&UserId>,
+        limit: Option<u64>,
+    ) -> Option<Vec<UserAndGroups>> {
+        self.read(|directory| directory.list_users(filters, get_groups, after, limit))
+    }
+
+    pub fn list_groups(&self, filters: Option<&GroupRequestFilter>) -> Option<Vec<Group>> {
+        self.read(|directory| directory.list_groups(filters))
+    }
+
+    pub fn get_user_groups(&self, user_id: &UserId) -> Option<Result<HashSet<GroupDetails>>> {
+        self.read(|directory| {
+            Some(if directory.users.contains_key(user_id) {
+                Ok(directory.groups_of(user_id).into_iter().collect())
+            } else {
+                Err(DomainError::EntityNotFound(user_id.to_string()))
+            })
+        })
+    }
+
+    pub fn get_group_details(&self, group_id: GroupId) -> Option<Result<GroupDetails>> {
+        self.read(|directory| {
+            Some(
+                directory
+                    .groups
+                    .get(&group_id)
+                    .cloned()
+                    .ok_or_else(|| DomainError::EntityNotFound(format!("{:?}", group_id))),
+            )
+        })
+    }
+
+    pub fn stats(&self) -> ReadModelStats {
+        self.directory.read().unwrap().stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        handler::{
+            CreateUserRequest, GroupBackendHandler, GroupListerBackendHandler, SubStringFilter,
+            UpdateGroupRequest, UpdateUserRequest, UserBackendHandler, UserListerBackendHandler,
+        },
+        sql_backend_handler::{tests::*, SqlBackendHandler},
+        types::Serialized,
+    };
+    use pretty_assertions::assert_eq;
+
+    fn user_filters(groups: &[GroupId]) -> Vec<Option<UserRequestFilter>> {
+        use UserRequestFilter::*;
+        let now = chrono::Utc::now();
+        vec![
+            None,
+            Some(UserId("bob".into())),
+            Some(Equality(UserColumn::UserId, "BOB".to_owned())),
+            Some(Equality(UserColumn::Email, "Patrick@bob.bob".to_owned())),
+            Some(Equality(UserColumn::DisplayName, "DISPLAY john".to_owned())),
+            Some(MemberOf("best group".into())),
+            Some(MemberOfId(groups[1])),
+            Some(Not(Box::new(MemberOf("Best Group".into())))),
+            Some(Or(vec![
+                UserId("john".into()),
+                MemberOf("Worst Group".into()),
+            ])),
+            Some(And(vec![MemberOfId(groups[0]), MemberOfId(groups[1])])),
+            Some(SubString(
+                UserColumn::DisplayName,
+                SubStringFilter {
+                    initial: Some("DISP".to_owned()),
+                    any: vec!["a".to_owned()],
+                    final_: Some("K".to_owned()),
+                },
+            )),
+            Some(UserIdSubString(SubStringFilter {
+                initial: None,
+                any: vec!["o".to_owned()],
+                final_: None,
+            })),
+            Some(AttributeEquality(
+                "first_name".into(),
+                Serialized::from("first bob"),
+            )),
+            Some(LastLoginBefore(now)),
+            Some(Not(Box::new(PasswordChangedBefore(now)))),
+            Some(ExpiresBefore(now + chrono::Duration::days(30))),
+            Some(Not(Box::new(Equality(
+                UserColumn::DisplayName,
+                "display bob".to_owned(),
+            )))),
+            Some(Present(UserColumn::Email)),
+            Some(Not(Box::new(Present(UserColumn::LastLogin)))),
+            Some(AttributePresent("last_name".into())),
+            Some(Not(Box::new(MemberOfAny))),
+            Some(Host("WEB01".to_owned())),
+            Some(Host("db".to_owned())),
+            Some(Or(vec![Host("db02".to_owned()), Host("*".to_owned())])),
+            Some(Host("web01,db02".to_owned())),
+            Some(Not(Box::new(Host("web01".to_owned())))),
+            Some(Not(Box::new(Present(UserColumn::Hosts)))),
+            Some(Equality(UserColumn::Phone, "+1 555 010-9999".to_owned())),
+            Some(SubString(
+                UserColumn::Phone,
+                SubStringFilter {
+                    initial: Some("+1 (555)".to_owned()),
+                    any: vec![],
+                    final_: None,
+                },
+            )),
+            Some(Equality(UserColumn::Locality, "SPRINGFIELD".to_owned())),
+            Some(SubString(
+                UserColumn::PostalAddress,
+                SubStringFilter {
+                    initial: None,
+                    any: vec!["main".to_owned()],
+                    final_: None,
+                },
+            )),
+            Some(Not(Box::new(Present(UserColumn::StateOrProvince)))),
+            // Not supported by the model.
+            Some(Equality(UserColumn::CreationDate, "2020-01-01".to_owned())),
+            Some(AttributeEquality("avatar".into(), Serialized::from("x"))),
+        ]
+    }
+
+    fn group_filters(groups: &[GroupId]) -> Vec<Option<GroupRequestFilter>> {
+        use GroupRequestFilter::*;
+        vec![
+            None,
+            Some(DisplayName("best GROUP".into())),
+            Some(GroupId(groups[2])),
+            Some(Member("patrick".into())),
+            Some(Not(Box::new(Member("bob".into())))),
+            Some(DisplayNameSubString(SubStringFilter {
+                initial: None,
+                any: vec![],
+                final_: Some("group".to_owned()),
+            })),
+            Some(Or(vec![
+                Member("john".into()),
+                DisplayName("Empty Group".into()),
+            ])),
+            Some(Not(Box::new(HasMembers))),
+            Some(Equality(
+                GroupColumn::Description,
+                "FOR THE BEST".to_owned(),
+            )),
+            Some(SubString(
+                GroupColumn::Description,
+                SubStringFilter {
+                    initial: Some("for".to_owned()),
+                    any: vec![],
+                    final_: None,
+                },
+            )),
+            Some(Not(Box::new(Present(GroupColumn::Notes)))),
+        ]
+    }
+
+    /// Checks that `handler` answers like the database.
+    async fn assert_consistent(
+        handler: &SqlBackendHandler,
+        database: &SqlBackendHandler,
+        groups: &[GroupId],
+    ) {
+        for filter in user_filters(groups) {
+            for get_groups in [false, true] {
+                assert_eq!(
+                    handler
+                        .list_users(filter.clone(), get_groups)
+                        .await
+                        .unwrap(),
+                    database
+                        .list_users(filter.clone(), get_groups)
+                        .await
+                        .unwrap(),
+                    "{:?}",
+                    filter
+                );
+            }
+            assert_eq!(
+                handler
+                    .list_users_page(filter.clone(), true, Some("bob".into()), 2)
+                    .await
+                    .unwrap(),
+                database
+                    .list_users_page(filter.clone(), true, Some("bob".into()), 2)
+                    .await
+                    .unwrap(),
+                "{:?}",
+                filter
+            );
+        }
+        for filter in group_filters(groups) {
+            assert_eq!(
+                handler.list_groups(filter.clone()).await.unwrap(),
+                database.list_groups(filter.clone()).await.unwrap(),
+                "{:?}",
+                filter
+            );
+        }
+        for user in database.list_users(None, false).await.unwrap() {
+            let user_id = &user.user.user_id;
+            assert_eq!(
+                handler.get_user_groups(user_id).await.unwrap(),
+                database.get_user_groups(user_id).await.unwrap()
+            );
+        }
+        for group_id in groups {
+            assert_eq!(
+                handler.get_group_details(*group_id).await.ok(),
+                database.get_group_details(*group_id).await.ok()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_model_matches_database() {
+        let fixture = TestFixture::new().await;
+        for (user_id, hosts) in [("bob", vec!["web01", "db02"]), ("patrick", vec!["*"])] {
+            fixture
+                .handler
+                .update_user(UpdateUserRequest {
+                    user_id: UserId::new(user_id),
+                    hosts: Some(hosts.into_iter().map(str::to_owned).collect()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                phone: Some("+15550109999".to_owned()),
+                postal_address: Some("1 Main Street".to_owned()),
+                locality: Some("Springfield".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        fixture
+            .handler
+            .update_group(UpdateGroupRequest {
+                group_id: fixture.groups[0],
+                display_name: None,
+                description: Some("For the best".to_owned()),
+                notes: Some("Admins only".to_owned()),
+                delete_attributes: Vec::new(),
+                insert_attributes: Vec::new(),
+            })
+            .await
+            .unwrap();
+        let handler = fixture.handler.clone().with_read_model().await.unwrap();
+        assert_eq!(
+            handler.read_model().unwrap().stats(),
+            ReadModelStats {
+                users: 4,
+                groups: 3,
+                memberships: 4,
+                ..handler.read_model().unwrap().stats()
+            }
+        );
+        assert_consistent(&handler, &fixture.handler, &fixture.groups).await;
+        assert!(handler
+            .get_user_groups(&UserId::new("nobody"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_model_serves_from_memory() {
+        let mut sql_pool = get_initialized_db().await;
+        let queries = count_queries(&mut sql_pool);
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool)
+            .with_read_model()
+            .await
+            .unwrap();
+        insert_user_no_password(&handler, "bob").await;
+        let group_id = insert_group(&handler, "Best Group").await;
+        insert_membership(&handler, group_id, "bob").await;
+        let before = queries.load(Ordering::Relaxed);
+        assert_eq!(
+            get_user_names(
+                &handler,
+                Some(UserRequestFilter::MemberOf("best group".into()))
+            )
+            .await,
+            vec!["bob"]
+        );
+        assert_eq!(handler.list_groups(None).await.unwrap().len(), 1);
+        assert_eq!(
+            handler.get_user_groups(&"bob".into()).await.unwrap().len(),
+            1
+        );
+        assert_eq!(queries.load(Ordering::Relaxed), before);
+        // The filters on data that the model doesn't keep go to the database.
+        get_user_names(
+            &handler,
+            Some(UserRequestFilter::Equality(
+                UserColumn::PasswordHash,
+                String::new(),
+            )),
+        )
+        .await;
+        assert!(queries.load(Ordering::Relaxed) > before);
+    }
+
+    #[tokio::test]
+    async fn test_interleaved_writes_and_reads() {
+        let fixture = TestFixture::new().await;
+        let handler = fixture.handler.clone().with_read_model().await.unwrap();
+        let groups = fixture.groups.clone();
+        let mut tasks = Vec::new();
+        for i in 0..12 {
+            let handler = handler.clone();
+            let groups = groups.clone();
+            tasks.push(tokio::spawn(async move {
+                let user_id = UserId::new(&format!("user{}", i));
+                let group_id = groups[i % groups.len()];
+                handler
+                    .create_user(CreateUserRequest {
+                        user_id: user_id.clone(),
+                        email: format!("user{}@bob.bob", i).into(),
+                        display_name: Some(format!("User {}", i)),
+                        ..Default::default()
+                    })
+                    .await
+                    .unwrap();
+                handler.add_user_to_group(&user_id, group_id).await.unwrap();
+                // Each write is visible as soon as it returns.
+                let members = handler
+                    .list_users(Some(UserRequestFilter::MemberOfId(group_id)), false)
+                    .await
+                    .unwrap();
+                assert!(members.iter().any(|u| u.user.user_id == user_id));
+                if i % 4 == 0 {
+                    handler.delete_user(&user_id).await.unwrap();
+                    assert!(handler.get_user_groups(&user_id).await.is_err());
+                }
+                if i % 5 == 0 {
+                    let name = format!("Group {}", i);
+                    handler
+                        .update_group(UpdateGroupRequest {
+                            group_id,
+                            display_name: Some(name.as_str().into()),
+                            description: None,
+                            notes: None,
+                            delete_attributes: Vec::new(),
+                            insert_attributes: Vec::new(),
+                        })
+                        .await
+                        .unwrap();
+                    assert_eq!(
+                        handler
+                            .get_group_details(group_id)
+                            .await
+                            .unwrap()
+                            .display_name
+                            .as_str(),
+                        name
+                    );
+                }
+                if i % 3 == 1 {
+                    handler
+                        .remove_user_from_group(&"patrick".into(), groups[1])
+                        .await
+                        .ok();
+                }
+            }));
+            let handler = handler.clone();
+            tasks.push(tokio::spawn(async move {
+                handler.list_users(None, true).await.unwrap();
+                handler.list_groups(None).await.unwrap();
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let database = SqlBackendHandler::new(get_default_config(), handler.sql_pool.clone());
+        assert_consistent(&handler, &database, &groups).await;
+        // The memberships of the deleted users are kept: the database answers these filters.
+        assert_eq!(
+            handler
+                .list_groups(Some(GroupRequestFilter::Member("user0".into())))
+                .await
+                .unwrap(),
+            database
+                .list_groups(Some(GroupRequestFilter::Member("user0".into())))
+                .await
+                .unwrap()
+        );
+        handler.restore_user(&"user0".into()).await.unwrap();
+        assert_consistent(&handler, &database, &groups).await;
+    }
+}
@@ -1,16 +1,215 @@
-use crate::domain::{handler::BackendHandler, sql_tables::DbConnection};
-use crate::infra::configuration::Configuration;
+use crate::domain::{
+    error::{DomainError, Result},
+    handler::BackendHandler,
+    password_check_pool::PasswordCheckPool,
+    query_metrics::{QueryMetrics, QueryTimer},
+    read_cache::{CacheKey, ReadCache},
+    read_model::{Change, ReadModel},
+    sql_tables::{bump_cache_generation, get_cache_generation, DbConnection},
+};
+use crate::infra::{
+    configuration::{CacheOptions, Configuration},
+    db_connection::is_transient_error,
+};
 use async_trait::async_trait;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// How long to send the reads to the primary after the replica failed to answer.
+const REPLICA_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+struct ReadReplica {
+    pool: DbConnection,
+    unavailable_until: Arc<Mutex<Option<Instant>>>,
+}
 
 #[derive(Clone)]
 pub struct SqlBackendHandler {
     pub(crate) config: Configuration,
     pub(crate) sql_pool: DbConnection,
+    read_replica: Option<ReadReplica>,
+    cache: Option<Arc<ReadCache>>,
+    read_model: Option<Arc<ReadModel>>,
+    pub(crate) password_checks: PasswordCheckPool,
+    query_metrics: Arc<QueryMetrics>,
+    read_only: bool,
 }
 
 impl SqlBackendHandler {
     pub fn new(config: Configuration, sql_pool: DbConnection) -> Self {
-        SqlBackendHandler { config, sql_pool }
+        let password_checks = PasswordCheckPool::new(config.max_concurrent_password_checks);
+        SqlBackendHandler {
+            config,
+            sql_pool,
+            read_replica: None,
+            cache: None,
+            read_model: None,
+            password_checks,
+            query_metrics: Arc::default(),
+            read_only: false,
+        }
+    }
+
+    /// Shared with the clones of the handler.
+    pub fn query_metrics(&self) -> Arc<QueryMetrics> {
+        self.query_metrics.clone()
+    }
+
+    /// Times a query until the returned timer is dropped, as `query_class`: the name of the
+    /// `BackendHandler` method. Within the fetch of `cached`, so that the cache hits don't count.
+    pub(crate) fn time_query(&self, query_class: &'static str) -> QueryTimer<'_> {
+        self.query_metrics.start(query_class)
+    }
+
+    /// The connection pools, by role: "primary", then "replica" if there is one.
+    pub fn database_pools(&self) -> Vec<(&'static str, DbConnection)> {
+        std::iter::once(("primary", self.sql_pool.clone()))
+            .chain(
+                self.read_replica
+                    .as_ref()
+                    .map(|replica| ("replica", replica.pool.clone())),
+            )
+            .collect()
+    }
+
+    /// Caches the user and group lookups, see `ReadCache`. Every write to the database, by this
+    /// handler or by another process, clears the cache.
+    pub fn with_cache(mut self, options: &CacheOptions) -> Self {
+        self.cache = Some(Arc::new(ReadCache::new(options)));
+        self
+    }
+
+    pub fn read_cache(&self) -> Option<Arc<ReadCache>> {
+        self.cache.clone()
+    }
+
+    /// Returns the cached value for `key`, or fetches it and caches it.
+    pub(crate) async fn cached<T, F, Fut>(&self, key: CacheKey, fetch: F) -> Result<T>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return fetch().await,
+        };
+        // From the primary: the replica could still have an older generation.
+        cache.sync_database_generation(get_cache_generation(&self.sql_pool).await?);
+        if let Some(value) = cache.get(&key) {
+            return Ok(value);
+        }
+        let generation = cache.generation();
+        let value = fetch().await?;
+        cache.insert(key, value.clone(), generation);
+        Ok(value)
+    }
+
+    /// Loads the directory in memory, see `ReadModel`. It is then kept up to date by the writes
+    /// of this handler (or of its clones), and only by them.
+    pub async fn with_read_model(mut self) -> Result<Self> {
+        self.read_model = Some(Arc::new(ReadModel::load(&self.sql_pool).await?));
+        Ok(self)
+    }
+
+    pub fn read_model(&self) -> Option<Arc<ReadModel>> {
+        self.read_model.clone()
+    }
+
+    /// Answers from the read model if there is one and it can, see `ReadModel`.
+    pub(crate) fn read_from_model<T>(
+        &self,
+        read: impl FnOnce(&ReadModel) -> Option<T>,
+    ) -> Option<T> {
+        self.read_model.as_deref().and_then(read)
+    }
+
+    /// To call after each write, once it is committed, with the entities it changed.
+    pub(crate) async fn after_write(&self, change: Change) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate();
+        }
+        // For the caches of the other processes. The write itself succeeded.
+        if let Err(e) = bump_cache_generation(&self.sql_pool).await {
+            warn!("Could not mark the cached reads as outdated: {}", e);
+        }
+        if let Some(model) = &self.read_model {
+            model.reload(&self.sql_pool, change).await;
+        }
+    }
+
+    /// Refuses all the writes, for a replica of another instance: the directory is only changed
+    /// by the replication, see `infra::replication`.
+    pub fn with_read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// To call before each write.
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(DomainError::ReadOnlyReplica)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sends the queries of the `*ListerBackendHandler` traits to a read replica. Everything
+    /// else, including the reads that must see the latest writes, goes to the primary.
+    pub fn with_read_replica(mut self, pool: DbConnection) -> Self {
+        self.read_replica = Some(ReadReplica {
+            pool,
+            unavailable_until: Arc::new(Mutex::new(None)),
+        });
+        self
+    }
+
+    /// Runs a read-only query on the replica if there is one, falling back to the primary if
+    /// the replica can't be reached.
+    pub(crate) async fn read<'a, T, F, Fut>(&'a self, query: F) -> Result<T>
+    where
+        F: Fn(&'a DbConnection) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        if let Some(replica) = &self.read_replica {
+            let available = replica
+                .unavailable_until
+                .lock()
+                .unwrap()
+                .map_or(true, |until| Instant::now() >= until);
+            if available {
+                match query(&replica.pool).await {
+                    Err(e) if is_unavailable(&e) => {
+                        warn!(
+                            "The read replica is unavailable, using the primary database for the next {:?}: {}",
+                            REPLICA_RETRY_DELAY, e
+                        );
+                        *replica.unavailable_until.lock().unwrap() =
+                            Some(Instant::now() + REPLICA_RETRY_DELAY);
+                    }
+                    result => return result,
+                }
+            }
+        }
+        query(&self.sql_pool).await
+    }
+}
+
+/// Whether the database could not answer, as opposed to rejecting the query.
+fn is_unavailable(error: &DomainError) -> bool {
+    match error {
+        DomainError::DatabaseError(e) => is_transient_error(e),
+        DomainError::ServerBusy(_) => true,
+        _ => false,
     }
 }
 
@@ -23,7 +222,8 @@ pub mod tests {
     use crate::{
         domain::{
             handler::{
-                CreateGroupRequest, CreateUserRequest, GroupBackendHandler, UserBackendHandler,
+                CreateGroupRequest, CreateUserRequest, GroupBackendHandler,
+                GroupListerBackendHandler, UpdateUserRequest, UserBackendHandler,
                 UserListerBackendHandler, UserRequestFilter,
             },
             sql_tables::init_table,
@@ -34,6 +234,7 @@ pub mod tests {
     use lldap_auth::{opaque, registration};
     use pretty_assertions::assert_eq;
     use sea_orm::Database;
+    pub use std::sync::atomic::{AtomicUsize, Ordering};
 
     pub fn get_default_config() -> Configuration {
         ConfigurationBuilder::for_tests()
@@ -127,6 +328,26 @@ pub mod tests {
             .collect::<Vec<_>>()
     }
 
+    /// Counts the queries sent to the database from now on.
+    pub fn count_queries(pool: &mut DbConnection) -> Arc<AtomicUsize> {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        pool.set_metric_callback(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        count
+    }
+
+    /// Records the statements sent to the database from now on.
+    pub fn record_queries(pool: &mut DbConnection) -> Arc<Mutex<Vec<String>>> {
+        let statements = Arc::new(Mutex::new(Vec::new()));
+        let recorder = statements.clone();
+        pool.set_metric_callback(move |info| {
+            recorder.lock().unwrap().push(info.statement.to_string());
+        });
+        statements
+    }
+
     pub struct TestFixture {
         pub handler: SqlBackendHandler,
         pub groups: Vec<GroupId>,
@@ -174,4 +395,169 @@ pub mod tests {
             assert_eq!(user.user_id, user_name);
         }
     }
+
+    #[tokio::test]
+    async fn test_lists_use_read_replica() {
+        let fixture = TestFixture::new().await;
+        let replica = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user_no_password(&replica, "replicated").await;
+        let handler = fixture
+            .handler
+            .clone()
+            .with_read_replica(replica.sql_pool.clone());
+
+        assert_eq!(get_user_names(&handler, None).await, vec!["replicated"]);
+        handler.get_user_details(&UserId::new("bob")).await.unwrap();
+        insert_user_no_password(&handler, "james").await;
+        fixture
+            .handler
+            .get_user_details(&UserId::new("james"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_replica_fallback() {
+        let fixture = TestFixture::new().await;
+        let replica = get_initialized_db().await;
+        let handler = fixture.handler.clone().with_read_replica(replica);
+        let replica_pool = &handler.read_replica.as_ref().unwrap().pool;
+        let attempts = Mutex::new(Vec::new());
+        let query = |pool: &DbConnection| {
+            let on_replica = std::ptr::eq(pool, replica_pool);
+            attempts.lock().unwrap().push(on_replica);
+            async move {
+                if on_replica {
+                    Err(DomainError::DatabaseError(
+                        sea_orm::DbErr::ConnectionAcquire(sea_orm::ConnAcquireErr::Timeout),
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        handler.read(query).await.unwrap();
+        // The replica is not retried right away.
+        handler.read(query).await.unwrap();
+        assert_eq!(*attempts.lock().unwrap(), vec![true, false, false]);
+    }
+
+    #[tokio::test]
+    async fn test_connection_acquire_timeout_is_busy() {
+        use sea_orm::{sqlx::sqlite::SqlitePoolOptions, SqlxSqliteConnector};
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(Duration::from_millis(50))
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let sql_pool = SqlxSqliteConnector::from_sqlx_sqlite_pool(pool.clone());
+        init_table(&sql_pool).await.unwrap();
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        let _only_connection = pool.acquire().await.unwrap();
+        assert!(matches!(
+            handler.list_users(None, false).await,
+            Err(DomainError::ServerBusy(_))
+        ));
+        // The writes fail when starting their transaction.
+        assert!(matches!(
+            handler
+                .create_group(CreateGroupRequest {
+                    display_name: "group".into(),
+                    ..Default::default()
+                })
+                .await,
+            Err(DomainError::ServerBusy(_))
+        ));
+    }
+
+    fn cache_options() -> CacheOptions {
+        CacheOptions {
+            enabled: true,
+            ttl_secs: 60,
+            max_entries: 100,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_is_invalidated_by_writes() {
+        let fixture = TestFixture::new().await;
+        let handler = fixture.handler.clone().with_cache(&cache_options());
+        let bob = UserId::new("bob");
+        assert_eq!(
+            handler.get_user_details(&bob).await.unwrap().display_name,
+            Some("display bob".to_owned())
+        );
+        assert_eq!(handler.get_user_groups(&bob).await.unwrap().len(), 1);
+        assert_eq!(handler.list_groups(None).await.unwrap().len(), 3);
+        assert_eq!(get_user_names(&handler, None).await.len(), 4);
+        // Served from the cache.
+        handler.get_user_details(&bob).await.unwrap();
+        assert_eq!(handler.read_cache().unwrap().stats().hits, 1);
+
+        // The writes go through a clone, like the ones of the LDAP and HTTP servers.
+        let writer = handler.clone();
+        writer
+            .update_user(UpdateUserRequest {
+                user_id: bob.clone(),
+                display_name: Some("Bobby".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.get_user_details(&bob).await.unwrap().display_name,
+            Some("Bobby".to_owned())
+        );
+        insert_membership(&writer, fixture.groups[1], "bob").await;
+        assert_eq!(handler.get_user_groups(&bob).await.unwrap().len(), 2);
+        insert_group(&writer, "New Group").await;
+        assert_eq!(handler.list_groups(None).await.unwrap().len(), 4);
+        writer.delete_user(&bob).await.unwrap();
+        assert_eq!(get_user_names(&handler, None).await.len(), 3);
+        handler.get_user_details(&bob).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_cache_skips_the_database() {
+        let mut handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await)
+            .with_cache(&cache_options());
+        insert_user_no_password(&handler, "bob").await;
+        let queries = count_queries(&mut handler.sql_pool);
+        assert_eq!(get_user_names(&handler, None).await, vec!["bob"]);
+        let after_first_read = queries.load(Ordering::Relaxed);
+        assert_ne!(after_first_read, 0);
+        assert_eq!(get_user_names(&handler, None).await, vec!["bob"]);
+        // Only the generation of the database is read.
+        assert_eq!(queries.load(Ordering::Relaxed), after_first_read + 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_sees_the_writes_of_other_processes() {
+        let fixture = TestFixture::new().await;
+        let handler = fixture.handler.clone().with_cache(&cache_options());
+        // Another process on the same database, e.g. a `lldap` command, with its own cache.
+        let other_process = SqlBackendHandler::new(get_default_config(), fixture.handler.sql_pool)
+            .with_cache(&cache_options());
+        let bob = UserId::new("bob");
+        assert_eq!(
+            handler.get_user_details(&bob).await.unwrap().display_name,
+            Some("display bob".to_owned())
+        );
+        assert_eq!(get_user_names(&handler, None).await.len(), 4);
+        other_process
+            .update_user(UpdateUserRequest {
+                user_id: bob.clone(),
+                display_name: Some("Bobby".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.get_user_details(&bob).await.unwrap().display_name,
+            Some("Bobby".to_owned())
+        );
+        insert_user_no_password(&other_process, "NewBoi").await;
+        assert_eq!(get_user_names(&handler, None).await.len(), 5);
+    }
 }
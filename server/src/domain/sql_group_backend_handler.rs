@@ -1,138 +1,125 @@
 use crate::domain::{
     error::{DomainError, Result},
+    filter::{self, FilterExpr, Groups},
     handler::{
         CreateGroupRequest, GroupBackendHandler, GroupListerBackendHandler, GroupRequestFilter,
         UpdateGroupRequest,
     },
-    model::{self, GroupColumn, MembershipColumn},
+    model::{self, GroupColumn, MembershipColumn, UserColumn},
+    read_cache::CacheKey,
+    read_model::Change,
     sql_backend_handler::SqlBackendHandler,
-    types::{AttributeName, AttributeValue, Group, GroupDetails, GroupId, Serialized, Uuid},
+    sql_tables::DbConnection,
+    sql_user_backend_handler::to_value,
+    types::{AttributeValue, Group, GroupDetails, GroupId, Uuid},
+    validation::{
+        validate_group_name, validate_length, MAX_GROUP_DESCRIPTION_LENGTH, MAX_GROUP_NOTES_LENGTH,
+    },
 };
 use async_trait::async_trait;
 use sea_orm::{
-    sea_query::{Alias, Cond, Expr, Func, IntoCondition, OnConflict, SimpleExpr},
-    ActiveModelTrait, ColumnTrait, DatabaseTransaction, EntityTrait, QueryFilter, QueryOrder,
-    QuerySelect, QueryTrait, Set, TransactionTrait,
+    sea_query::{Cond, IntoCondition, OnConflict, SimpleExpr},
+    ActiveModelTrait, ColumnTrait, DatabaseTransaction, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect, QueryTrait, Set, TransactionTrait,
 };
 use tracing::instrument;
 
-fn attribute_condition(name: AttributeName, value: Serialized) -> Cond {
-    Expr::in_subquery(
-        Expr::col(GroupColumn::GroupId.as_column_ref()),
-        model::GroupAttributes::find()
-            .select_only()
-            .column(model::GroupAttributesColumn::GroupId)
-            .filter(model::GroupAttributesColumn::AttributeName.eq(name))
-            .filter(model::GroupAttributesColumn::Value.eq(value))
-            .into_query(),
-    )
-    .into_condition()
+fn get_group_filter_expr(filter: GroupRequestFilter) -> Cond {
+    filter::to_condition(FilterExpr::<Groups>::from(filter))
 }
 
-fn get_group_filter_expr(filter: GroupRequestFilter) -> Cond {
-    use GroupRequestFilter::*;
-    let group_table = Alias::new("groups");
-    match filter {
-        And(fs) => {
-            if fs.is_empty() {
-                SimpleExpr::Value(true.into()).into_condition()
-            } else {
-                fs.into_iter()
-                    .fold(Cond::all(), |c, f| c.add(get_group_filter_expr(f)))
-            }
-        }
-        Or(fs) => {
-            if fs.is_empty() {
-                SimpleExpr::Value(false.into()).into_condition()
-            } else {
-                fs.into_iter()
-                    .fold(Cond::any(), |c, f| c.add(get_group_filter_expr(f)))
-            }
-        }
-        Not(f) => get_group_filter_expr(*f).not(),
-        DisplayName(name) => GroupColumn::LowercaseDisplayName
-            .eq(name.as_str().to_lowercase())
-            .into_condition(),
-        GroupId(id) => GroupColumn::GroupId.eq(id.0).into_condition(),
-        Uuid(uuid) => GroupColumn::Uuid.eq(uuid.to_string()).into_condition(),
-        // WHERE (group_id in (SELECT group_id FROM memberships WHERE user_id = user))
-        Member(user) => GroupColumn::GroupId
-            .in_subquery(
-                model::Membership::find()
-                    .select_only()
-                    .column(MembershipColumn::GroupId)
-                    .filter(MembershipColumn::UserId.eq(user))
-                    .into_query(),
-            )
-            .into_condition(),
-        DisplayNameSubString(filter) => SimpleExpr::FunctionCall(Func::lower(Expr::col((
-            group_table,
-            GroupColumn::LowercaseDisplayName,
-        ))))
-        .like(filter.to_sql_filter())
-        .into_condition(),
-        AttributeEquality(name, value) => attribute_condition(name, value),
+fn validate_description_and_notes(description: Option<&str>, notes: Option<&str>) -> Result<()> {
+    if let Some(description) = description {
+        validate_length("description", description, MAX_GROUP_DESCRIPTION_LENGTH)?;
+    }
+    if let Some(notes) = notes {
+        validate_length("notes", notes, MAX_GROUP_NOTES_LENGTH)?;
+    }
+    Ok(())
+}
+
+/// Fetches the groups with a fixed number of queries, whatever the number of groups: their members
+/// and attributes are each fetched for all the groups at once.
+pub(crate) async fn fetch_groups(
+    connection: &DbConnection,
+    filters: Option<GroupRequestFilter>,
+) -> Result<Vec<Group>> {
+    let filters = filters
+        .map(|f| {
+            GroupColumn::GroupId
+                .in_subquery(
+                    model::Group::find()
+                        .find_also_linked(model::memberships::GroupToUser)
+                        .select_only()
+                        .column(GroupColumn::GroupId)
+                        .filter(get_group_filter_expr(f))
+                        .into_query(),
+                )
+                .into_condition()
+        })
+        .unwrap_or_else(|| SimpleExpr::Value(true.into()).into_condition());
+    let group_ids = || {
+        model::Group::find()
+            .filter(filters.clone())
+            .select_only()
+            .column(GroupColumn::GroupId)
+            .into_query()
+    };
+    let mut groups: Vec<Group> = model::Group::find()
+        .filter(filters.clone())
+        .order_by_asc(GroupColumn::GroupId)
+        .all(connection)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    use itertools::Itertools; // For take_while_ref
+                              // The memberships of deleted users are kept until they are purged, but hidden.
+    let memberships = model::Membership::find()
+        .inner_join(model::User)
+        .filter(UserColumn::DeletedAt.is_null())
+        .filter(MembershipColumn::GroupId.in_subquery(group_ids()))
+        .order_by_asc(MembershipColumn::GroupId)
+        .order_by_asc(MembershipColumn::UserId)
+        .all(connection)
+        .await?;
+    let mut memberships_iter = memberships.into_iter().peekable();
+    for group in groups.iter_mut() {
+        group.users = memberships_iter
+            .take_while_ref(|m| m.group_id == group.id)
+            .map(|m| m.user_id)
+            .collect();
+    }
+    let attributes = model::GroupAttributes::find()
+        .filter(model::GroupAttributesColumn::GroupId.in_subquery(group_ids()))
+        .order_by_asc(model::GroupAttributesColumn::GroupId)
+        .order_by_asc(model::GroupAttributesColumn::AttributeName)
+        .all(connection)
+        .await?;
+    let mut attributes_iter = attributes.into_iter().peekable();
+    for group in groups.iter_mut() {
+        group.attributes = attributes_iter
+            .take_while_ref(|u| u.group_id == group.id)
+            .map(AttributeValue::from)
+            .collect();
     }
+    groups.sort_by(|g1, g2| g1.display_name.cmp(&g2.display_name));
+    Ok(groups)
 }
 
 #[async_trait]
 impl GroupListerBackendHandler for SqlBackendHandler {
     #[instrument(skip(self), level = "debug", ret, err)]
     async fn list_groups(&self, filters: Option<GroupRequestFilter>) -> Result<Vec<Group>> {
-        let filters = filters
-            .map(|f| {
-                GroupColumn::GroupId
-                    .in_subquery(
-                        model::Group::find()
-                            .find_also_linked(model::memberships::GroupToUser)
-                            .select_only()
-                            .column(GroupColumn::GroupId)
-                            .filter(get_group_filter_expr(f))
-                            .into_query(),
-                    )
-                    .into_condition()
-            })
-            .unwrap_or_else(|| SimpleExpr::Value(true.into()).into_condition());
-        let results = model::Group::find()
-            .order_by_asc(GroupColumn::GroupId)
-            .find_with_related(model::Membership)
-            .filter(filters.clone())
-            .all(&self.sql_pool)
-            .await?;
-        let mut groups: Vec<_> = results
-            .into_iter()
-            .map(|(group, users)| {
-                let users: Vec<_> = users.into_iter().map(|u| u.user_id).collect();
-                Group {
-                    users,
-                    ..group.into()
-                }
-            })
-            .collect();
-        let attributes = model::GroupAttributes::find()
-            .filter(
-                model::GroupAttributesColumn::GroupId.in_subquery(
-                    model::Group::find()
-                        .filter(filters)
-                        .select_only()
-                        .column(model::groups::Column::GroupId)
-                        .into_query(),
-                ),
-            )
-            .order_by_asc(model::GroupAttributesColumn::GroupId)
-            .order_by_asc(model::GroupAttributesColumn::AttributeName)
-            .all(&self.sql_pool)
-            .await?;
-        let mut attributes_iter = attributes.into_iter().peekable();
-        use itertools::Itertools; // For take_while_ref
-        for group in groups.iter_mut() {
-            group.attributes = attributes_iter
-                .take_while_ref(|u| u.group_id == group.id)
-                .map(AttributeValue::from)
-                .collect();
+        if let Some(groups) = self.read_from_model(|model| model.list_groups(filters.as_ref())) {
+            return Ok(groups);
         }
-        groups.sort_by(|g1, g2| g1.display_name.cmp(&g2.display_name));
-        Ok(groups)
+        self.cached(CacheKey::Groups(filters.clone()), || async {
+            let _timer = self.time_query("list_groups");
+            self.read(|connection| fetch_groups(connection, filters.clone()))
+                .await
+        })
+        .await
     }
 }
 
@@ -140,6 +127,10 @@ impl GroupListerBackendHandler for SqlBackendHandler {
 impl GroupBackendHandler for SqlBackendHandler {
     #[instrument(skip(self), level = "debug", ret, err)]
     async fn get_group_details(&self, group_id: GroupId) -> Result<GroupDetails> {
+        if let Some(group) = self.read_from_model(|model| model.get_group_details(group_id)) {
+            return group;
+        }
+        let _timer = self.time_query("get_group_details");
         let mut group_details = model::Group::find_by_id(group_id)
             .one(&self.sql_pool)
             .await?
@@ -156,29 +147,47 @@ impl GroupBackendHandler for SqlBackendHandler {
 
     #[instrument(skip(self), level = "debug", err, fields(group_id = ?request.group_id))]
     async fn update_group(&self, request: UpdateGroupRequest) -> Result<()> {
-        Ok(self
-            .sql_pool
+        let _timer = self.time_query("update_group");
+        self.check_writable()?;
+        if let Some(name) = &request.display_name {
+            validate_group_name(name.as_str())?;
+        }
+        validate_description_and_notes(request.description.as_deref(), request.notes.as_deref())?;
+        let group_id = request.group_id;
+        self.sql_pool
             .transaction::<_, (), DomainError>(|transaction| {
                 Box::pin(
                     async move { Self::update_group_with_transaction(request, transaction).await },
                 )
             })
-            .await?)
+            .await?;
+        self.after_write(Change::Group(group_id)).await;
+        Ok(())
     }
 
     #[instrument(skip(self), level = "debug", ret, err)]
     async fn create_group(&self, request: CreateGroupRequest) -> Result<GroupId> {
-        let now = chrono::Utc::now().naive_utc();
+        let _timer = self.time_query("create_group");
+        self.check_writable()?;
+        validate_group_name(request.display_name.as_str())?;
+        validate_description_and_notes(request.description.as_deref(), request.notes.as_deref())?;
+        let now = chrono::Utc::now();
         let uuid = Uuid::from_name_and_date(request.display_name.as_str(), &now);
         let lower_display_name = request.display_name.as_str().to_lowercase();
+        let mut members = request.members;
+        members.sort();
+        members.dedup();
+        let added_members = members.clone();
         let new_group = model::groups::ActiveModel {
             display_name: Set(request.display_name),
             lowercase_display_name: Set(lower_display_name),
             creation_date: Set(now),
             uuid: Set(uuid),
+            description: Set(request.description.filter(|s| !s.is_empty())),
+            notes: Set(request.notes.filter(|s| !s.is_empty())),
             ..Default::default()
         };
-        Ok(self
+        let group_id = self
             .sql_pool
             .transaction::<_, GroupId, DomainError>(|transaction| {
                 Box::pin(async move {
@@ -209,14 +218,43 @@ impl GroupBackendHandler for SqlBackendHandler {
                             .exec(transaction)
                             .await?;
                     }
+                    // Any error from here on rolls back the group creation as well.
+                    if !members.is_empty() {
+                        let existing_users = model::User::find()
+                            .filter(UserColumn::UserId.is_in(members.iter().cloned()))
+                            .filter(UserColumn::DeletedAt.is_null())
+                            .count(transaction)
+                            .await?;
+                        if existing_users != members.len() as u64 {
+                            return Err(DomainError::EntityNotFound(format!(
+                                "No such user among {:?}",
+                                members
+                            )));
+                        }
+                        model::Membership::insert_many(members.iter().map(|user_id| {
+                            model::memberships::ActiveModel {
+                                user_id: Set(user_id.clone()),
+                                group_id: Set(group_id),
+                            }
+                        }))
+                        .exec(transaction)
+                        .await?;
+                    }
                     Ok(group_id)
                 })
             })
-            .await?)
+            .await?;
+        self.after_write(Change::Group(group_id)).await;
+        for user_id in added_members {
+            self.after_write(Change::User(user_id)).await;
+        }
+        Ok(group_id)
     }
 
     #[instrument(skip(self), level = "debug", err)]
     async fn delete_group(&self, group_id: GroupId) -> Result<()> {
+        let _timer = self.time_query("delete_group");
+        self.check_writable()?;
         let res = model::Group::delete_by_id(group_id)
             .exec(&self.sql_pool)
             .await?;
@@ -226,6 +264,7 @@ impl GroupBackendHandler for SqlBackendHandler {
                 group_id
             )));
         }
+        self.after_write(Change::Group(group_id)).await;
         Ok(())
     }
 }
@@ -243,6 +282,8 @@ impl SqlBackendHandler {
             group_id: Set(request.group_id),
             display_name: request.display_name.map(Set).unwrap_or_default(),
             lowercase_display_name: lower_display_name.map(Set).unwrap_or_default(),
+            description: to_value(&request.description),
+            notes: to_value(&request.notes),
             ..Default::default()
         };
         update_group.update(transaction).await?;
@@ -311,7 +352,7 @@ mod tests {
     use crate::domain::{
         handler::{CreateAttributeRequest, SchemaBackendHandler, SubStringFilter},
         sql_backend_handler::tests::*,
-        types::{AttributeType, GroupName, Serialized, UserId},
+        types::{AttributeName, AttributeType, GroupName, Serialized, UserId},
     };
     use pretty_assertions::assert_eq;
 
@@ -354,6 +395,33 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_list_groups_query_count_does_not_depend_on_groups() {
+        let mut fixture = TestFixture::new().await;
+        let queries = count_queries(&mut fixture.handler.sql_pool);
+        assert_eq!(fixture.handler.list_groups(None).await.unwrap().len(), 3);
+        let few_groups_queries = queries.load(Ordering::Relaxed);
+        assert!(few_groups_queries <= 3, "{} queries", few_groups_queries);
+
+        for i in 0..20 {
+            let group_id = insert_group(&fixture.handler, &format!("group{}", i)).await;
+            insert_membership(&fixture.handler, group_id, "bob").await;
+            insert_membership(&fixture.handler, group_id, "patrick").await;
+        }
+        queries.store(0, Ordering::Relaxed);
+        let groups = fixture.handler.list_groups(None).await.unwrap();
+        assert_eq!(groups.len(), 23);
+        assert_eq!(
+            groups
+                .iter()
+                .find(|g| g.display_name == "group7".into())
+                .unwrap()
+                .users,
+            vec![UserId::new("bob"), UserId::new("patrick")]
+        );
+        assert_eq!(queries.load(Ordering::Relaxed), few_groups_queries);
+    }
+
     #[tokio::test]
     async fn test_list_groups_simple_filter() {
         let fixture = TestFixture::new().await;
@@ -438,6 +506,8 @@ mod tests {
             .update_group(UpdateGroupRequest {
                 group_id: fixture.groups[0],
                 display_name: None,
+                description: None,
+                notes: None,
                 delete_attributes: Vec::new(),
                 insert_attributes: vec![AttributeValue {
                     name: "gid".into(),
@@ -487,6 +557,8 @@ mod tests {
             .update_group(UpdateGroupRequest {
                 group_id: fixture.groups[0],
                 display_name: Some("Awesomest Group".into()),
+                description: None,
+                notes: None,
                 delete_attributes: Vec::new(),
                 insert_attributes: Vec::new(),
             })
@@ -500,6 +572,79 @@ mod tests {
         assert_eq!(details.display_name, "Awesomest Group".into());
     }
 
+    #[tokio::test]
+    async fn test_update_group_description_and_notes() {
+        let fixture = TestFixture::new().await;
+        let update = |description: Option<&str>, notes: Option<&str>| UpdateGroupRequest {
+            group_id: fixture.groups[0],
+            display_name: None,
+            description: description.map(str::to_owned),
+            notes: notes.map(str::to_owned),
+            delete_attributes: Vec::new(),
+            insert_attributes: Vec::new(),
+        };
+        fixture
+            .handler
+            .update_group(update(Some("Grants app-x"), Some("Ask Alice")))
+            .await
+            .unwrap();
+        assert_eq!(
+            get_group_ids(
+                &fixture.handler,
+                Some(GroupRequestFilter::SubString(
+                    GroupColumn::Description,
+                    SubStringFilter {
+                        initial: None,
+                        any: vec!["APP".to_owned()],
+                        final_: None,
+                    },
+                )),
+            )
+            .await,
+            vec![fixture.groups[0]]
+        );
+        assert_eq!(
+            get_group_ids(
+                &fixture.handler,
+                Some(GroupRequestFilter::Equality(
+                    GroupColumn::Notes,
+                    "ask alice".to_owned()
+                )),
+            )
+            .await,
+            vec![fixture.groups[0]]
+        );
+        // Only the given fields are updated, the empty string removes the value.
+        fixture
+            .handler
+            .update_group(update(None, Some("")))
+            .await
+            .unwrap();
+        let details = fixture
+            .handler
+            .get_group_details(fixture.groups[0])
+            .await
+            .unwrap();
+        assert_eq!(details.description.as_deref(), Some("Grants app-x"));
+        assert_eq!(details.notes, None);
+        assert_eq!(
+            get_group_ids(
+                &fixture.handler,
+                Some(GroupRequestFilter::Present(GroupColumn::Notes)),
+            )
+            .await,
+            Vec::<GroupId>::new()
+        );
+        fixture
+            .handler
+            .update_group(update(
+                Some(&"a".repeat(MAX_GROUP_DESCRIPTION_LENGTH + 1)),
+                None,
+            ))
+            .await
+            .unwrap_err();
+    }
+
     #[tokio::test]
     async fn test_delete_group() {
         let fixture = TestFixture::new().await;
@@ -540,10 +685,13 @@ mod tests {
             .handler
             .create_group(CreateGroupRequest {
                 display_name: "New Group".into(),
+                description: Some("The new ones".to_owned()),
+                notes: Some(String::new()),
                 attributes: vec![AttributeValue {
                     name: "new_attribute".into(),
                     value: Serialized::from("value"),
                 }],
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -553,6 +701,8 @@ mod tests {
             .await
             .unwrap();
         assert_eq!(group_details.display_name, "New Group".into());
+        assert_eq!(group_details.description.as_deref(), Some("The new ones"));
+        assert_eq!(group_details.notes, None);
         assert_eq!(
             group_details.attributes,
             vec![AttributeValue {
@@ -586,6 +736,8 @@ mod tests {
             .update_group(UpdateGroupRequest {
                 group_id,
                 display_name: None,
+                description: None,
+                notes: None,
                 delete_attributes: Vec::new(),
                 insert_attributes: attributes.clone(),
             })
@@ -598,6 +750,8 @@ mod tests {
             .update_group(UpdateGroupRequest {
                 group_id,
                 display_name: None,
+                description: None,
+                notes: None,
                 delete_attributes: vec!["new_attribute".into()],
                 insert_attributes: Vec::new(),
             })
@@ -627,4 +781,72 @@ mod tests {
             .await
             .unwrap_err();
     }
+
+    #[tokio::test]
+    async fn test_create_group_with_members() {
+        let fixture = TestFixture::new().await;
+        let group_id = fixture
+            .handler
+            .create_group(CreateGroupRequest {
+                display_name: "New Group".into(),
+                members: vec![UserId::new("patrick"), UserId::new("bob")],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let groups = fixture
+            .handler
+            .list_groups(Some(GroupRequestFilter::GroupId(group_id)))
+            .await
+            .unwrap();
+        assert_eq!(
+            groups[0].users,
+            vec![UserId::new("bob"), UserId::new("patrick")]
+        );
+        // A missing member rolls back the whole creation.
+        fixture
+            .handler
+            .create_group(CreateGroupRequest {
+                display_name: "Other Group".into(),
+                members: vec![UserId::new("bob"), UserId::new("nobody")],
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert!(fixture
+            .handler
+            .list_groups(Some(GroupRequestFilter::DisplayName("Other Group".into())))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_create_group_invalid_name() {
+        let fixture = TestFixture::new().await;
+        let err = fixture
+            .handler
+            .create_group(CreateGroupRequest {
+                display_name: "dev,ops".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid group name \"dev,ops\": the character ',' is not allowed in group names"
+        );
+        fixture
+            .handler
+            .update_group(UpdateGroupRequest {
+                group_id: fixture.groups[0],
+                display_name: Some(" Best Group".into()),
+                description: None,
+                notes: None,
+                delete_attributes: Vec::new(),
+                insert_attributes: Vec::new(),
+            })
+            .await
+            .unwrap_err();
+    }
 }
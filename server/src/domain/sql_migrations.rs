@@ -2,25 +2,29 @@ use crate::domain::{
     sql_tables::{DbConnection, SchemaVersion, LAST_SCHEMA_VERSION},
     types::{AttributeType, GroupId, JpegPhoto, Serialized, UserId, Uuid},
 };
+use chrono::{FixedOffset, NaiveDateTime, TimeZone, Utc};
 use itertools::Itertools;
 use sea_orm::{
     sea_query::{
         self, all, Alias, BinOper, BlobSize::Blob, ColumnDef, Expr, ForeignKey, ForeignKeyAction,
-        Func, Index, Query, SimpleExpr, Table, Value,
+        Func, Index, IntoIden, Query, SimpleExpr, Table, Value,
     },
-    ConnectionTrait, DatabaseTransaction, DbErr, DeriveIden, FromQueryResult, Iden, Order,
-    Statement, TransactionTrait,
+    ConnectionTrait, DatabaseTransaction, DbBackend, DbErr, DeriveIden, FromQueryResult, Iden,
+    Order, Statement, TransactionTrait,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tracing::{error, info, instrument, warn};
 
 #[derive(DeriveIden, PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
 pub enum Users {
     Table,
     UserId,
+    UserIdAsEntered,
     Email,
     LowercaseEmail,
     DisplayName,
+    LowercaseDisplayName,
     FirstName,
     LastName,
     Avatar,
@@ -29,6 +33,19 @@ pub enum Users {
     TotpSecret,
     MfaType,
     Uuid,
+    DeletedAt,
+    LastLogin,
+    PasswordChangedAt,
+    LegacyPasswordHash,
+    PreferredLanguage,
+    Hosts,
+    Phone,
+    Mobile,
+    PostalAddress,
+    Locality,
+    StateOrProvince,
+    ExpiresAt,
+    ExpiryWarningSentAt,
 }
 
 #[derive(DeriveIden, PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
@@ -39,6 +56,8 @@ pub enum Groups {
     LowercaseDisplayName,
     CreationDate,
     Uuid,
+    Description,
+    Notes,
 }
 
 #[derive(DeriveIden, Clone, Copy)]
@@ -66,6 +85,7 @@ pub enum UserAttributes {
     UserAttributeUserId,
     UserAttributeName,
     UserAttributeValue,
+    UserAttributeValueHash,
 }
 
 #[allow(clippy::enum_variant_names)] // The table names are generated from the enum.
@@ -102,6 +122,17 @@ pub enum GroupObjectClasses {
     ObjectClass,
 }
 
+/// Contains the temporary tokens sent by email: to reset the password, to accept an invitation, or
+/// to verify a new email address (with `new_email` set).
+#[derive(DeriveIden, Clone, Copy)]
+pub enum PasswordResetTokens {
+    Table,
+    Token,
+    UserId,
+    ExpiryDate,
+    NewEmail,
+}
+
 // Metadata about the SQL DB.
 #[derive(DeriveIden)]
 pub enum Metadata {
@@ -110,6 +141,8 @@ pub enum Metadata {
     Version,
     PrivateKeyHash,
     PrivateKeyLocation,
+    // Incremented by each write, for the caches of the other processes.
+    CacheGeneration,
 }
 
 #[derive(FromQueryResult, PartialEq, Eq, Debug)]
@@ -117,9 +150,11 @@ pub struct JustSchemaVersion {
     pub version: SchemaVersion,
 }
 
-#[instrument(skip_all, level = "debug", ret)]
-pub async fn get_schema_version(pool: &DbConnection) -> Option<SchemaVersion> {
-    JustSchemaVersion::find_by_statement(
+/// Same as `get_schema_version`, but reports the database errors.
+pub async fn try_get_schema_version(
+    pool: &impl ConnectionTrait,
+) -> Result<Option<SchemaVersion>, DbErr> {
+    Ok(JustSchemaVersion::find_by_statement(
         pool.get_database_backend().build(
             Query::select()
                 .from(Metadata::Table)
@@ -127,10 +162,13 @@ pub async fn get_schema_version(pool: &DbConnection) -> Option<SchemaVersion> {
         ),
     )
     .one(pool)
-    .await
-    .ok()
-    .flatten()
-    .map(|j| j.version)
+    .await?
+    .map(|j| j.version))
+}
+
+#[instrument(skip_all, level = "debug", ret)]
+pub async fn get_schema_version(pool: &impl ConnectionTrait) -> Option<SchemaVersion> {
+    try_get_schema_version(pool).await.ok().flatten()
 }
 
 pub async fn upgrade_to_v1(pool: &DbConnection) -> std::result::Result<(), sea_orm::DbErr> {
@@ -395,13 +433,130 @@ pub async fn upgrade_to_v1(pool: &DbConnection) -> std::result::Result<(), sea_o
     Ok(())
 }
 
-async fn replace_column<I: Iden + Copy + 'static, const N: usize>(
+/// Arbitrary key for the Postgres advisory lock serializing the migrations.
+const MIGRATION_LOCK_ID: i64 = 0x6c6c_6461_70;
+/// Name of the MySQL lock serializing the migrations.
+const MIGRATION_LOCK_NAME: &str = "lldap_migrations";
+/// How long to wait for another instance to finish migrating, in seconds.
+const MIGRATION_LOCK_TIMEOUT_SECS: i64 = 600;
+
+/// Settings of the migrations that depend on the installation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MigrationOptions {
+    /// The offset of the timestamps stored without a time zone, before the schema version 21.
+    /// LLDAP always stored them in UTC.
+    pub legacy_timestamps_offset: FixedOffset,
+}
+
+impl Default for MigrationOptions {
+    fn default() -> Self {
+        Self {
+            legacy_timestamps_offset: FixedOffset::east_opt(0).unwrap(),
+        }
+    }
+}
+
+/// A transaction in which a migration is run, holding the database-wide migration lock.
+///
+/// In dry-run mode, the statements are printed as they are executed, and the transaction is
+/// rolled back at the end. The whole chain of migrations then runs in a single transaction, so
+/// that each of them sees the changes of the previous ones.
+pub struct MigrationTransaction {
     transaction: DatabaseTransaction,
+    dry_run: bool,
+    options: MigrationOptions,
+}
+
+impl MigrationTransaction {
+    async fn begin(
+        pool: &DbConnection,
+        dry_run: bool,
+        options: MigrationOptions,
+    ) -> Result<Self, DbErr> {
+        let transaction = pool.begin().await?;
+        let builder = transaction.get_database_backend();
+        match builder {
+            DbBackend::Postgres => {
+                // Released automatically at the end of the transaction.
+                transaction
+                    .execute(Statement::from_string(
+                        builder,
+                        format!("SELECT pg_advisory_xact_lock({})", MIGRATION_LOCK_ID),
+                    ))
+                    .await?;
+            }
+            DbBackend::MySql => {
+                // Named locks are not transactional, and DDL statements commit implicitly in
+                // MySQL: the lock is released explicitly in `finish`.
+                let acquired = transaction
+                    .query_one(Statement::from_string(
+                        builder,
+                        format!(
+                            "SELECT GET_LOCK('{}', {})",
+                            MIGRATION_LOCK_NAME, MIGRATION_LOCK_TIMEOUT_SECS
+                        ),
+                    ))
+                    .await?
+                    .and_then(|r| r.try_get_by_index::<Option<i64>>(0).ok().flatten())
+                    == Some(1);
+                if !acquired {
+                    return Err(DbErr::Migration(
+                        "Timed out waiting for another instance to finish migrating the database"
+                            .to_owned(),
+                    ));
+                }
+            }
+            // Writes are serialized by the database file lock.
+            DbBackend::Sqlite => (),
+        }
+        Ok(Self {
+            transaction,
+            dry_run,
+            options,
+        })
+    }
+
+    /// Executes a statement that modifies the database, and prints it in dry-run mode.
+    pub async fn execute(&self, statement: Statement) -> Result<(), DbErr> {
+        if self.dry_run {
+            println!("{};", statement);
+        }
+        self.transaction.execute(statement).await.map(|_| ())
+    }
+
+    async fn finish(self) -> Result<(), DbErr> {
+        let builder = self.transaction.get_database_backend();
+        if builder == DbBackend::MySql {
+            self.transaction
+                .execute(Statement::from_string(
+                    builder,
+                    format!("DO RELEASE_LOCK('{}')", MIGRATION_LOCK_NAME),
+                ))
+                .await?;
+        }
+        if self.dry_run {
+            self.transaction.rollback().await
+        } else {
+            self.transaction.commit().await
+        }
+    }
+}
+
+impl std::ops::Deref for MigrationTransaction {
+    type Target = DatabaseTransaction;
+
+    fn deref(&self) -> &Self::Target {
+        &self.transaction
+    }
+}
+
+async fn replace_column<I: Iden + Copy + 'static, const N: usize>(
+    transaction: MigrationTransaction,
     table_name: I,
     column_name: I,
     mut new_column: ColumnDef,
     update_values: [Statement; N],
-) -> Result<DatabaseTransaction, DbErr> {
+) -> Result<MigrationTransaction, DbErr> {
     // Update the definition of a column (in a compatible way). Due to Sqlite, this is more complicated:
     //  - rename the column to a temporary name
     //  - create the column with the new definition
@@ -449,7 +604,7 @@ async fn replace_column<I: Iden + Copy + 'static, const N: usize>(
     Ok(transaction)
 }
 
-async fn migrate_to_v2(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+async fn migrate_to_v2(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
     let builder = transaction.get_database_backend();
     // Allow nulls in DisplayName, and change empty string to null.
     let transaction = replace_column(
@@ -470,7 +625,7 @@ async fn migrate_to_v2(transaction: DatabaseTransaction) -> Result<DatabaseTrans
     Ok(transaction)
 }
 
-async fn migrate_to_v3(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+async fn migrate_to_v3(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
     let builder = transaction.get_database_backend();
     // Allow nulls in First and LastName. Users who created their DB in 0.4.1 have the not null constraint.
     let transaction = replace_column(
@@ -513,7 +668,7 @@ async fn migrate_to_v3(transaction: DatabaseTransaction) -> Result<DatabaseTrans
     Ok(transaction)
 }
 
-async fn migrate_to_v4(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+async fn migrate_to_v4(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
     let builder = transaction.get_database_backend();
     // Make emails and UUIDs unique.
     if let Err(e) = transaction
@@ -606,7 +761,7 @@ Conflicting emails:
     Ok(transaction)
 }
 
-async fn migrate_to_v5(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+async fn migrate_to_v5(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
     let builder = transaction.get_database_backend();
     transaction
         .execute(
@@ -853,7 +1008,7 @@ async fn migrate_to_v5(transaction: DatabaseTransaction) -> Result<DatabaseTrans
                 Users::Avatar,
             ]),
         ))
-        .all(&transaction)
+        .all(&*transaction)
         .await?
         {
             if let Some(name) = &user.first_name {
@@ -896,7 +1051,7 @@ async fn migrate_to_v5(transaction: DatabaseTransaction) -> Result<DatabaseTrans
     Ok(transaction)
 }
 
-async fn migrate_to_v6(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+async fn migrate_to_v6(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
     let builder = transaction.get_database_backend();
     transaction
         .execute(
@@ -943,7 +1098,7 @@ async fn migrate_to_v6(transaction: DatabaseTransaction) -> Result<DatabaseTrans
     Ok(transaction)
 }
 
-async fn migrate_to_v7(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+async fn migrate_to_v7(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
     let builder = transaction.get_database_backend();
     transaction
         .execute(
@@ -966,7 +1121,7 @@ async fn migrate_to_v7(transaction: DatabaseTransaction) -> Result<DatabaseTrans
     Ok(transaction)
 }
 
-async fn migrate_to_v8(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+async fn migrate_to_v8(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
     let builder = transaction.get_database_backend();
     // Remove duplicate memberships.
     #[derive(FromQueryResult)]
@@ -993,7 +1148,7 @@ async fn migrate_to_v8(transaction: DatabaseTransaction) -> Result<DatabaseTrans
                 )]),
         ),
     )
-    .all(&transaction)
+    .all(&*transaction)
     .await?
     .into_iter()
     .map(
@@ -1045,7 +1200,7 @@ async fn migrate_to_v8(transaction: DatabaseTransaction) -> Result<DatabaseTrans
     Ok(transaction)
 }
 
-async fn migrate_to_v9(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+async fn migrate_to_v9(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
     let builder = transaction.get_database_backend();
     transaction
         .execute(
@@ -1090,7 +1245,73 @@ async fn migrate_to_v9(transaction: DatabaseTransaction) -> Result<DatabaseTrans
     Ok(transaction)
 }
 
-async fn migrate_to_v10(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+/// Returns the emails used by several users, ignoring the case, with the ids of these users.
+/// Empty emails are not considered to be conflicting.
+pub async fn find_conflicting_emails<C: ConnectionTrait>(
+    connection: &C,
+) -> Result<Vec<(String, Vec<UserId>)>, DbErr> {
+    let builder = connection.get_database_backend();
+    let lower_email = || SimpleExpr::from(Func::lower(Expr::col(Users::Email)));
+    let rows = connection
+        .query_all(
+            builder.build(
+                Query::select()
+                    .from(Users::Table)
+                    .column(Users::UserId)
+                    .expr_as(lower_email(), Users::LowercaseEmail)
+                    .and_where(Expr::col(Users::Email).ne(""))
+                    .and_where(
+                        Expr::expr(lower_email()).in_subquery(
+                            Query::select()
+                                .from(Users::Table)
+                                .expr(lower_email())
+                                .add_group_by([lower_email()])
+                                .cond_having(all![Expr::gt(
+                                    Expr::expr(Func::count(Expr::col(Users::UserId))),
+                                    1
+                                )])
+                                .take(),
+                        ),
+                    )
+                    .order_by_expr(lower_email(), Order::Asc)
+                    .order_by(Users::UserId, Order::Asc),
+            ),
+        )
+        .await?;
+    let mut conflicts: Vec<(String, Vec<UserId>)> = Vec::new();
+    for row in rows {
+        let email = row.try_get::<String>("", &Users::LowercaseEmail.to_string())?;
+        let user = row.try_get::<UserId>("", &Users::UserId.to_string())?;
+        match conflicts.last_mut() {
+            Some((last_email, users)) if *last_email == email => users.push(user),
+            _ => conflicts.push((email, vec![user])),
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Fails with the list of the conflicting users if some emails are used several times.
+async fn check_conflicting_emails(transaction: &MigrationTransaction) -> Result<(), DbErr> {
+    let conflicts = find_conflicting_emails(&**transaction).await?;
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    error!(
+        "Found several users with the same (case-insensitive) email. Please change the emails of the duplicates before upgrading.\n\nConflicting emails:"
+    );
+    for (email, users) in &conflicts {
+        warn!("Email: {email}");
+        for user in users {
+            warn!("    User: {}", user.as_str());
+        }
+    }
+    Err(DbErr::Migration(format!(
+        "{} emails are used by several users",
+        conflicts.len()
+    )))
+}
+
+async fn migrate_to_v10(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
     let builder = transaction.get_database_backend();
     if let Err(e) = transaction
         .execute(
@@ -1110,7 +1331,8 @@ async fn migrate_to_v10(transaction: DatabaseTransaction) -> Result<DatabaseTran
         );
         return Err(e);
     }
-    if let Err(e) = transaction
+    check_conflicting_emails(&transaction).await?;
+    transaction
         .execute(
             builder.build(
                 Index::create()
@@ -1121,12 +1343,633 @@ async fn migrate_to_v10(transaction: DatabaseTransaction) -> Result<DatabaseTran
                     .unique(),
             ),
         )
-        .await
-    {
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v11(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    // MySQL and MariaDB can default to latin1 or the 3-byte "utf8" charset, with a collation that
+    // ignores the case, and to DATETIME without fractional seconds. Align them with the other
+    // backends: full UTF-8 with a binary collation (case-insensitive lookups go through the
+    // lowercase columns), and microsecond precision for the creation dates.
+    if transaction.get_database_backend() != sea_orm::DatabaseBackend::MySql {
+        return Ok(transaction);
+    }
+    let builder = transaction.get_database_backend();
+    let raw = |sql: &str| Statement::from_string(builder, sql.to_owned());
+    #[derive(FromQueryResult)]
+    struct TableName {
+        table_name: String,
+    }
+    transaction
+        .execute(raw("SET FOREIGN_KEY_CHECKS = 0"))
+        .await?;
+    // Tables created later (e.g. the JWT tables on a fresh install) inherit this.
+    transaction
+        .execute(raw(
+            "ALTER DATABASE CHARACTER SET utf8mb4 COLLATE utf8mb4_bin",
+        ))
+        .await?;
+    let tables = TableName::find_by_statement(raw(
+        "SELECT table_name AS table_name FROM information_schema.tables \
+         WHERE table_schema = DATABASE() AND table_type = 'BASE TABLE'",
+    ))
+    .all(&*transaction)
+    .await?;
+    for TableName { table_name } in tables {
+        info!("Converting table {} to utf8mb4", table_name);
+        transaction
+            .execute(raw(&format!(
+                "ALTER TABLE `{}` CONVERT TO CHARACTER SET utf8mb4 COLLATE utf8mb4_bin",
+                table_name
+            )))
+            .await?;
+    }
+    transaction
+        .execute(
+            builder.build(
+                Table::alter().table(Users::Table).modify_column(
+                    ColumnDef::new(Users::CreationDate)
+                        .custom(Alias::new("DATETIME(6)"))
+                        .not_null(),
+                ),
+            ),
+        )
+        .await?;
+    transaction
+        .execute(
+            builder.build(
+                Table::alter().table(Groups::Table).modify_column(
+                    ColumnDef::new(Groups::CreationDate)
+                        .custom(Alias::new("DATETIME(6)"))
+                        .not_null(),
+                ),
+            ),
+        )
+        .await?;
+    transaction
+        .execute(raw("SET FOREIGN_KEY_CHECKS = 1"))
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v12(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Deleted users are kept until they are purged, to be able to restore them.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::DeletedAt).date_time().null()),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v13(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // NULL means "never", for the existing users as well.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::LastLogin).date_time().null()),
+            ),
+        )
+        .await?;
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::PasswordChangedAt).date_time().null()),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v14(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    transaction
+        .execute(
+            builder.build(
+                Table::alter().table(Users::Table).add_column(
+                    ColumnDef::new(Users::LegacyPasswordHash)
+                        .string_len(255)
+                        .null(),
+                ),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v15(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    check_conflicting_emails(&transaction).await?;
+    // The case-sensitive index is superseded by the one on the lowercase emails. Empty emails are
+    // stored as NULL in the lowercase column, so that they don't conflict with each other.
+    for index in ["unique-user-email", "unique-user-lower-email"] {
+        transaction
+            .execute(builder.build(Index::drop().name(index).table(Users::Table)))
+            .await?;
+    }
+    let transaction = replace_column(
+        transaction,
+        Users::Table,
+        Users::LowercaseEmail,
+        ColumnDef::new(Users::LowercaseEmail)
+            .string_len(255)
+            .null()
+            .to_owned(),
+        [builder.build(
+            Query::update()
+                .table(Users::Table)
+                .value(Users::LowercaseEmail, Option::<String>::None)
+                .and_where(Expr::col(Users::Email).eq("")),
+        )],
+    )
+    .await?;
+    transaction
+        .execute(
+            builder.build(
+                Index::create()
+                    .if_not_exists()
+                    .name("unique-user-lower-email")
+                    .table(Users::Table)
+                    .col(Users::LowercaseEmail)
+                    .unique(),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v16(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    transaction
+        .execute(
+            builder.build(
+                Table::alter().table(Users::Table).add_column(
+                    ColumnDef::new(Users::PreferredLanguage)
+                        .string_len(35)
+                        .null(),
+                ),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v17(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // The table used to be created after the migrations, so it doesn't exist yet in a new
+    // database.
+    transaction
+        .execute(
+            builder.build(
+                Table::create()
+                    .table(PasswordResetTokens::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PasswordResetTokens::Token)
+                            .string_len(255)
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(PasswordResetTokens::UserId)
+                            .string_len(255)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PasswordResetTokens::ExpiryDate)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("PasswordResetTokensUserForeignKey")
+                            .from(PasswordResetTokens::Table, PasswordResetTokens::UserId)
+                            .to(Users::Table, Users::UserId)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    ),
+            ),
+        )
+        .await?;
+    transaction
+        .execute(
+            builder.build(
+                Table::alter().table(PasswordResetTokens::Table).add_column(
+                    ColumnDef::new(PasswordResetTokens::NewEmail)
+                        .string_len(255)
+                        .null(),
+                ),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+/// Creates the indexes of a lowercase column, for the case-insensitive equality filters (unless
+/// `equality_index` is false, when the column already has one) and prefix filters
+/// (`LIKE 'abc%'`). A LIKE can only use an index comparing the strings the way it does: ignoring
+/// the case on SQLite, byte by byte on Postgres, where this index also serves the equality
+/// filters. On MySQL, the regular index serves both.
+async fn create_lowercase_indexes<T: Iden + Copy + 'static, C: Iden + Copy + 'static>(
+    transaction: &MigrationTransaction,
+    name: &str,
+    table: T,
+    column: C,
+    equality_index: bool,
+) -> Result<(), DbErr> {
+    let builder = transaction.get_database_backend();
+    if equality_index && builder != DbBackend::Postgres {
+        transaction
+            .execute(
+                builder.build(
+                    Index::create()
+                        .if_not_exists()
+                        .name(name)
+                        .table(table)
+                        .col(column),
+                ),
+            )
+            .await?;
+    }
+    let prefix_index = match builder {
+        DbBackend::Sqlite => "COLLATE NOCASE",
+        DbBackend::Postgres => "text_pattern_ops",
+        DbBackend::MySql => return Ok(()),
+    };
+    transaction
+        .execute(Statement::from_string(
+            builder,
+            format!(
+                r#"CREATE INDEX IF NOT EXISTS "{}-prefix" ON "{}" ("{}" {})"#,
+                name,
+                table.to_string(),
+                column.to_string(),
+                prefix_index
+            ),
+        ))
+        .await?;
+    Ok(())
+}
+
+async fn migrate_to_v18(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Lowercase copy of the display names, for the case-insensitive filters. Empty display names
+    // are NULL, like in the display name column.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter().table(Users::Table).add_column(
+                    ColumnDef::new(Users::LowercaseDisplayName)
+                        .string_len(255)
+                        .null(),
+                ),
+            ),
+        )
+        .await?;
+    transaction
+        .execute(builder.build(Query::update().table(Users::Table).value(
+            Users::LowercaseDisplayName,
+            Func::lower(Expr::col(Users::DisplayName)),
+        )))
+        .await?;
+    create_lowercase_indexes(
+        &transaction,
+        "user-lower-display-name",
+        Users::Table,
+        Users::LowercaseDisplayName,
+        true,
+    )
+    .await?;
+    // The user IDs are stored in lowercase, and the emails have a unique index.
+    create_lowercase_indexes(&transaction, "user-id", Users::Table, Users::UserId, false).await?;
+    create_lowercase_indexes(
+        &transaction,
+        "user-lower-email",
+        Users::Table,
+        Users::LowercaseEmail,
+        false,
+    )
+    .await?;
+    create_lowercase_indexes(
+        &transaction,
+        "group-lower-display-name",
+        Groups::Table,
+        Groups::LowercaseDisplayName,
+        true,
+    )
+    .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v19(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Hash of the attribute values, to revalidate the avatars without reading them.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter().table(UserAttributes::Table).add_column(
+                    ColumnDef::new(UserAttributes::UserAttributeValueHash)
+                        .string_len(64)
+                        .null(),
+                ),
+            ),
+        )
+        .await?;
+    let rows = transaction
+        .query_all(
+            builder.build(Query::select().from(UserAttributes::Table).columns([
+                UserAttributes::UserAttributeUserId,
+                UserAttributes::UserAttributeName,
+                UserAttributes::UserAttributeValue,
+            ])),
+        )
+        .await?;
+    for row in rows {
+        let user_id =
+            row.try_get::<UserId>("", &UserAttributes::UserAttributeUserId.to_string())?;
+        let name = row.try_get::<String>("", &UserAttributes::UserAttributeName.to_string())?;
+        let value =
+            row.try_get::<Serialized>("", &UserAttributes::UserAttributeValue.to_string())?;
+        transaction
+            .execute(
+                builder.build(
+                    Query::update()
+                        .table(UserAttributes::Table)
+                        .value(UserAttributes::UserAttributeValueHash, value.content_hash())
+                        .and_where(Expr::col(UserAttributes::UserAttributeUserId).eq(user_id))
+                        .and_where(Expr::col(UserAttributes::UserAttributeName).eq(name)),
+                ),
+            )
+            .await?;
+    }
+    Ok(transaction)
+}
+
+/// Returns the user IDs that are the same once all their letters are lowercased, and not only
+/// the ASCII ones, with the IDs as stored.
+pub async fn find_conflicting_user_ids<C: ConnectionTrait>(
+    connection: &C,
+) -> Result<Vec<(UserId, Vec<String>)>, DbErr> {
+    let builder = connection.get_database_backend();
+    let rows = connection
+        .query_all(
+            builder.build(
+                Query::select()
+                    .from(Users::Table)
+                    .column(Users::UserId)
+                    .order_by(Users::UserId, Order::Asc),
+            ),
+        )
+        .await?;
+    // `UserId` is hashed by its lowercase form.
+    let mut user_ids: HashMap<UserId, Vec<String>> = HashMap::new();
+    for row in rows {
+        let stored = row.try_get::<String>("", &Users::UserId.to_string())?;
+        user_ids
+            .entry(UserId::new(&stored))
+            .or_default()
+            .push(stored);
+    }
+    Ok(user_ids
+        .into_iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .sorted_by(|(a, _), (b, _)| a.cmp(b))
+        .collect())
+}
+
+async fn migrate_to_v20(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // The user IDs were only lowercased for the ASCII letters: "JÖHN" was stored as "jÖhn".
+    let conflicts = find_conflicting_user_ids(&*transaction).await?;
+    if !conflicts.is_empty() {
         error!(
-            r#"Found several users with the same (case-insensitive) email. Please delete the duplicates"#
+            "Found several users with the same (case-insensitive) user ID. Please rename or delete the duplicates with the previous version before upgrading.\n\nConflicting user IDs:"
         );
-        return Err(e);
+        for (user_id, ids) in &conflicts {
+            warn!("User ID: {}", user_id.as_str());
+            for id in ids {
+                warn!("    Stored as: {}", id);
+            }
+        }
+        return Err(DbErr::Migration(format!(
+            "{} user IDs are used by several users",
+            conflicts.len()
+        )));
+    }
+    // The key column holds the lowercase form, the user IDs are displayed as they were stored.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter().table(Users::Table).add_column(
+                    ColumnDef::new(Users::UserIdAsEntered)
+                        .string_len(255)
+                        .null(),
+                ),
+            ),
+        )
+        .await?;
+    transaction
+        .execute(
+            builder.build(
+                Query::update()
+                    .table(Users::Table)
+                    .value(Users::UserIdAsEntered, Expr::col(Users::UserId)),
+            ),
+        )
+        .await?;
+    let rows = transaction
+        .query_all(builder.build(Query::select().from(Users::Table).column(Users::UserId)))
+        .await?;
+    // The JWT tables are created after the migrations, they don't exist yet in a new database.
+    let mut jwt_tables = Vec::new();
+    for table in ["jwt_refresh_storage", "jwt_storage"] {
+        if table_exists(&transaction, table).await? {
+            jwt_tables.push(Alias::new(table));
+        }
+    }
+    for row in rows {
+        let stored = row.try_get::<String>("", &Users::UserId.to_string())?;
+        let user_id = UserId::new(&stored);
+        if user_id.as_str() == stored {
+            continue;
+        }
+        // The foreign keys update the other tables, when they are enforced.
+        let rename = (stored.as_str(), user_id.as_str());
+        rename_user_id(&transaction, Users::Table, Users::UserId, rename).await?;
+        rename_user_id(
+            &transaction,
+            Memberships::Table,
+            Memberships::UserId,
+            rename,
+        )
+        .await?;
+        rename_user_id(
+            &transaction,
+            UserAttributes::Table,
+            UserAttributes::UserAttributeUserId,
+            rename,
+        )
+        .await?;
+        rename_user_id(
+            &transaction,
+            PasswordResetTokens::Table,
+            PasswordResetTokens::UserId,
+            rename,
+        )
+        .await?;
+        // The sessions stay valid, and the revoked tokens stay revoked.
+        for table in &jwt_tables {
+            rename_user_id(&transaction, table.clone(), Alias::new("user_id"), rename).await?;
+        }
+    }
+    Ok(transaction)
+}
+
+async fn rename_user_id<T: Iden + 'static, C: Iden + Clone + 'static>(
+    transaction: &MigrationTransaction,
+    table: T,
+    column: C,
+    (from, to): (&str, &str),
+) -> Result<(), DbErr> {
+    let builder = transaction.get_database_backend();
+    transaction
+        .execute(
+            builder.build(
+                Query::update()
+                    .table(table)
+                    .value(column.clone(), to)
+                    .and_where(Expr::col(column).eq(from)),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+/// The column type of the timestamps, stored in UTC: `TIMESTAMP WITH TIME ZONE` on Postgres, where
+/// a plain `TIMESTAMP` would be converted from the session time zone, and `DATETIME` elsewhere.
+pub fn utc_timestamp_column<T: IntoIden>(name: T, builder: DbBackend) -> ColumnDef {
+    let mut column = ColumnDef::new(name);
+    match builder {
+        DbBackend::Postgres => column.timestamp_with_time_zone(),
+        DbBackend::MySql | DbBackend::Sqlite => column.date_time(),
+    };
+    column
+}
+
+async fn table_exists(transaction: &MigrationTransaction, table: &str) -> Result<bool, DbErr> {
+    let builder = transaction.get_database_backend();
+    let query = match builder {
+        DbBackend::Postgres => "SELECT 1 FROM information_schema.tables WHERE table_schema = current_schema() AND table_name = $1",
+        DbBackend::MySql => "SELECT 1 FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name = ?",
+        DbBackend::Sqlite => "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?",
+    };
+    Ok(transaction
+        .query_one(Statement::from_sql_and_values(
+            builder,
+            query,
+            [table.into()],
+        ))
+        .await?
+        .is_some())
+}
+
+/// Converts the timestamps stored without a time zone, at the offset of the migration options, to
+/// UTC timestamps.
+async fn migrate_to_v21(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    let offset_secs = transaction
+        .options
+        .legacy_timestamps_offset
+        .local_minus_utc();
+    let mut columns = vec![
+        (Users::Table.to_string(), Users::CreationDate.to_string()),
+        (Users::Table.to_string(), Users::DeletedAt.to_string()),
+        (Users::Table.to_string(), Users::LastLogin.to_string()),
+        (
+            Users::Table.to_string(),
+            Users::PasswordChangedAt.to_string(),
+        ),
+        (Groups::Table.to_string(), Groups::CreationDate.to_string()),
+        (
+            PasswordResetTokens::Table.to_string(),
+            PasswordResetTokens::ExpiryDate.to_string(),
+        ),
+    ];
+    // The JWT tables are created after the migrations, they don't exist yet in a new database.
+    for table in ["jwt_refresh_storage", "jwt_storage"] {
+        if table_exists(&transaction, table).await? {
+            columns.push((table.to_owned(), "expiry_date".to_owned()));
+        }
+    }
+    for (table, column) in columns {
+        match builder {
+            DbBackend::Postgres => {
+                transaction
+                    .execute(Statement::from_string(
+                        builder,
+                        format!(
+                            r#"ALTER TABLE "{table}" ALTER COLUMN "{column}" TYPE TIMESTAMP WITH TIME ZONE USING ("{column}" - INTERVAL '{offset_secs} seconds') AT TIME ZONE 'UTC'"#
+                        ),
+                    ))
+                    .await?;
+            }
+            // The DATETIME columns stay naive, and are now always in UTC.
+            DbBackend::MySql => {
+                if offset_secs != 0 {
+                    transaction
+                        .execute(Statement::from_string(
+                            builder,
+                            format!(
+                                "UPDATE `{table}` SET `{column}` = `{column}` - INTERVAL {offset_secs} SECOND"
+                            ),
+                        ))
+                        .await?;
+                }
+            }
+            // The values are text: they are rewritten in the format of the UTC timestamps, so
+            // that they compare correctly with the new ones.
+            DbBackend::Sqlite => {
+                let (table, column) = (Alias::new(table), Alias::new(column));
+                let rows = transaction
+                    .query_all(
+                        builder.build(
+                            Query::select()
+                                .from(table.clone())
+                                .expr_as(Expr::cust("rowid"), Alias::new("row_id"))
+                                .column(column.clone())
+                                .and_where(Expr::col(column.clone()).is_not_null()),
+                        ),
+                    )
+                    .await?;
+                for row in rows {
+                    let row_id = row.try_get::<i64>("", "row_id")?;
+                    let date = row.try_get::<NaiveDateTime>("", &column.to_string())?;
+                    let date = Utc
+                        .from_utc_datetime(&(date - chrono::Duration::seconds(offset_secs.into())));
+                    transaction
+                        .execute(
+                            builder.build(
+                                Query::update()
+                                    .table(table.clone())
+                                    .value(column.clone(), date)
+                                    .and_where(Expr::cust("rowid").eq(row_id)),
+                            ),
+                        )
+                        .await?;
+                }
+            }
+        }
     }
     Ok(transaction)
 }
@@ -1135,15 +1978,140 @@ async fn migrate_to_v10(transaction: DatabaseTransaction) -> Result<DatabaseTran
 macro_rules! to_sync {
     ($l:ident) => {
         move |transaction| -> std::pin::Pin<
-            Box<dyn std::future::Future<Output = Result<DatabaseTransaction, DbErr>>>,
+            Box<dyn std::future::Future<Output = Result<MigrationTransaction, DbErr>>>,
         > { Box::pin($l(transaction)) }
     };
 }
 
+#[cfg(test)]
 pub async fn migrate_from_version(
     pool: &DbConnection,
     version: SchemaVersion,
     last_version: SchemaVersion,
+) -> anyhow::Result<()> {
+    migrate_from_version_with_options(pool, version, last_version, MigrationOptions::default())
+        .await
+}
+
+pub async fn migrate_from_version_with_options(
+    pool: &DbConnection,
+    version: SchemaVersion,
+    last_version: SchemaVersion,
+    options: MigrationOptions,
+) -> anyhow::Result<()> {
+    run_migrations(pool, version, last_version, false, options).await
+}
+
+/// Adds the hosts a user may log into, for the host-based access control.
+async fn migrate_to_v22(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::Hosts).text().null()),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v23(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Separate statements, SQLite only supports one column per ALTER TABLE.
+    for column in [Groups::Description, Groups::Notes] {
+        transaction
+            .execute(
+                builder.build(
+                    Table::alter()
+                        .table(Groups::Table)
+                        .add_column(ColumnDef::new(column).text().null()),
+                ),
+            )
+            .await?;
+    }
+    Ok(transaction)
+}
+
+/// Adds the phone numbers and the address of the users, for the address books.
+async fn migrate_to_v24(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Separate statements, SQLite only supports one column per ALTER TABLE.
+    for column in [
+        Users::Phone,
+        Users::Mobile,
+        Users::PostalAddress,
+        Users::Locality,
+        Users::StateOrProvince,
+    ] {
+        transaction
+            .execute(
+                builder.build(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(ColumnDef::new(column).text().null()),
+                ),
+            )
+            .await?;
+    }
+    Ok(transaction)
+}
+
+/// Adds the expiration date of the accounts, and when the user was warned of it.
+async fn migrate_to_v25(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    for column in [Users::ExpiresAt, Users::ExpiryWarningSentAt] {
+        transaction
+            .execute(
+                builder.build(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(utc_timestamp_column(column, builder).null()),
+                ),
+            )
+            .await?;
+    }
+    Ok(transaction)
+}
+
+/// Adds the generation of the data, bumped by the writes of every process: their read caches
+/// are cleared when it changes.
+async fn migrate_to_v26(transaction: MigrationTransaction) -> Result<MigrationTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    transaction
+        .execute(
+            builder.build(
+                Table::alter().table(Metadata::Table).add_column(
+                    ColumnDef::new(Metadata::CacheGeneration)
+                        .big_integer()
+                        .not_null()
+                        .default(0),
+                ),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+/// Prints the statements that would be run to migrate from `version` to `last_version`, without
+/// modifying the database: they are run in a transaction that is rolled back. Not supported on
+/// MySQL, where the schema changes commit implicitly.
+pub async fn print_migrations_from_version(
+    pool: &DbConnection,
+    version: SchemaVersion,
+    last_version: SchemaVersion,
+    options: MigrationOptions,
+) -> anyhow::Result<()> {
+    run_migrations(pool, version, last_version, true, options).await
+}
+
+async fn run_migrations(
+    pool: &DbConnection,
+    version: SchemaVersion,
+    last_version: SchemaVersion,
+    dry_run: bool,
+    options: MigrationOptions,
 ) -> anyhow::Result<()> {
     match version.cmp(&last_version) {
         std::cmp::Ordering::Less => (),
@@ -1161,12 +2129,57 @@ pub async fn migrate_from_version(
         to_sync!(migrate_to_v8),
         to_sync!(migrate_to_v9),
         to_sync!(migrate_to_v10),
+        to_sync!(migrate_to_v11),
+        to_sync!(migrate_to_v12),
+        to_sync!(migrate_to_v13),
+        to_sync!(migrate_to_v14),
+        to_sync!(migrate_to_v15),
+        to_sync!(migrate_to_v16),
+        to_sync!(migrate_to_v17),
+        to_sync!(migrate_to_v18),
+        to_sync!(migrate_to_v19),
+        to_sync!(migrate_to_v20),
+        to_sync!(migrate_to_v21),
+        to_sync!(migrate_to_v22),
+        to_sync!(migrate_to_v23),
+        to_sync!(migrate_to_v24),
+        to_sync!(migrate_to_v25),
+        to_sync!(migrate_to_v26),
     ];
     assert_eq!(migrations.len(), (LAST_SCHEMA_VERSION.0 - 1) as usize);
+    // A dry run goes through the whole chain in one transaction, rolled back at the end.
+    let mut dry_run_transaction = None;
+    if dry_run {
+        if pool.get_database_backend() == DbBackend::MySql {
+            anyhow::bail!(
+                "The migrations cannot be dry-run on MySQL, where the schema changes commit implicitly. Back up the database before migrating it instead"
+            );
+        }
+        dry_run_transaction = Some(MigrationTransaction::begin(pool, true, options).await?);
+    }
     for migration in 2..=last_version.0 {
         if version < SchemaVersion(migration) && SchemaVersion(migration) <= last_version {
-            info!("Upgrading DB schema to version {}", migration);
-            let transaction = pool.begin().await?;
+            let transaction = match dry_run_transaction.take() {
+                Some(transaction) => {
+                    println!("-- Upgrading DB schema to version {}", migration);
+                    transaction
+                }
+                None => {
+                    let transaction = MigrationTransaction::begin(pool, false, options).await?;
+                    // Another instance could have run the migration while we were waiting for
+                    // the lock.
+                    if get_schema_version(&*transaction)
+                        .await
+                        .is_some_and(|v| v >= SchemaVersion(migration))
+                    {
+                        info!("DB schema already at version {}", migration);
+                        transaction.finish().await?;
+                        continue;
+                    }
+                    info!("Upgrading DB schema to version {}", migration);
+                    transaction
+                }
+            };
             let transaction = migrations[(migration - 2) as usize](transaction).await?;
             let builder = transaction.get_database_backend();
             transaction
@@ -1178,8 +2191,15 @@ pub async fn migrate_from_version(
                     ),
                 )
                 .await?;
-            transaction.commit().await?;
+            if dry_run {
+                dry_run_transaction = Some(transaction);
+            } else {
+                transaction.finish().await?;
+            }
         }
     }
+    if let Some(transaction) = dry_run_transaction {
+        transaction.finish().await?;
+    }
     Ok(())
 }
@@ -1,23 +1,38 @@
 use super::{
     error::{DomainError, Result},
     handler::{BindRequest, LoginHandler},
+    legacy_password,
     model::{self, UserColumn},
     opaque_handler::{login, registration, OpaqueHandler},
+    read_model::Change,
     sql_backend_handler::SqlBackendHandler,
     types::UserId,
 };
+use crate::infra::configuration::LoginIdentifier;
 use async_trait::async_trait;
 use base64::Engine;
 use lldap_auth::opaque;
-use sea_orm::{ActiveModelTrait, ActiveValue, EntityTrait, QuerySelect};
+use sea_orm::{
+    sea_query::{Cond, Expr},
+    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, QueryFilter, QuerySelect,
+};
 use secstr::SecUtf8;
-use tracing::{debug, instrument};
+use tracing::{debug, instrument, warn};
 
 type SqlOpaqueHandler = SqlBackendHandler;
 
+/// Minimum delay between two updates of a user's last login timestamp.
+const LAST_LOGIN_UPDATE_INTERVAL_SECS: i64 = 5 * 60;
+
+/// The message of all the failed binds, whatever the reason: it doesn't tell whether the user
+/// exists.
+const INVALID_CREDENTIALS: &str = "Invalid credentials";
+
+/// Without a password file, the check runs against a dummy one and fails, with the same work as
+/// for a wrong password.
 #[instrument(skip_all, level = "debug", err, fields(username = %username.as_str()))]
 fn passwords_match(
-    password_file_bytes: &[u8],
+    password_file_bytes: Option<&[u8]>,
     clear_password: &str,
     server_setup: &opaque::server::ServerSetup,
     username: &UserId,
@@ -26,12 +41,14 @@ fn passwords_match(
     let mut rng = rand::rngs::OsRng;
     let client_login_start_result = client::login::start_login(clear_password, &mut rng)?;
 
-    let password_file = server::ServerRegistration::deserialize(password_file_bytes)
+    let password_file = password_file_bytes
+        .map(server::ServerRegistration::deserialize)
+        .transpose()
         .map_err(opaque::AuthenticationError::ProtocolError)?;
     let server_login_start_result = server::login::start_login(
         &mut rng,
         server_setup,
-        Some(password_file),
+        password_file,
         client_login_start_result.message,
         username,
     )?;
@@ -51,8 +68,10 @@ impl SqlBackendHandler {
 
     #[instrument(skip(self), level = "debug", err)]
     async fn get_password_file_for_user(&self, user_id: UserId) -> Result<Option<Vec<u8>>> {
+        let _timer = self.time_query("get_password_file_for_user");
         // Fetch the previously registered password file from the DB.
         Ok(model::User::find_by_id(user_id)
+            .filter(UserColumn::DeletedAt.is_null())
             .select_only()
             .column(UserColumn::PasswordHash)
             .into_tuple::<(Option<Vec<u8>>,)>()
@@ -60,36 +79,197 @@ impl SqlBackendHandler {
             .await?
             .and_then(|u| u.0))
     }
+
+    /// The user logging in as `name`. With `login_identifier = "email"`, that's the user with this
+    /// email, or else the user with this ID: the user IDs keep working after switching. The
+    /// users without an email never match an email.
+    #[instrument(skip(self), level = "debug", err)]
+    async fn resolve_login_name(&self, name: &UserId) -> Result<UserId> {
+        if self.config.login_identifier != LoginIdentifier::Email || !name.as_str().contains('@') {
+            return Ok(name.clone());
+        }
+        let _timer = self.time_query("resolve_login_name");
+        Ok(model::User::find()
+            .filter(UserColumn::LowercaseEmail.eq(name.as_str()))
+            .filter(UserColumn::DeletedAt.is_null())
+            .select_only()
+            .column(UserColumn::UserId)
+            .into_tuple::<(UserId,)>()
+            .one(&self.sql_pool)
+            .await?
+            .map(|(user_id,)| user_id)
+            .unwrap_or_else(|| name.clone()))
+    }
+
+    /// The OPAQUE password file and the imported hash of the user, in one query: the binds do
+    /// the same queries whether the user exists or not.
+    #[instrument(skip(self), level = "debug", err)]
+    async fn get_password_hashes(
+        &self,
+        user_id: &UserId,
+    ) -> Result<(Option<Vec<u8>>, Option<String>)> {
+        let _timer = self.time_query("get_password_hashes");
+        Ok(model::User::find_by_id(user_id.clone())
+            .filter(UserColumn::DeletedAt.is_null())
+            .select_only()
+            .column(UserColumn::PasswordHash)
+            .column(UserColumn::LegacyPasswordHash)
+            .into_tuple::<(Option<Vec<u8>>, Option<String>)>()
+            .one(&self.sql_pool)
+            .await?
+            .unwrap_or_default())
+    }
+
+    /// Records a successful login. To limit the writes, the timestamp is only updated if the
+    /// previous one is older than `LAST_LOGIN_UPDATE_INTERVAL_SECS`.
+    #[instrument(skip(self), level = "debug")]
+    async fn record_login(&self, user_id: &UserId) {
+        // The replicas don't write: their logins are not recorded.
+        if self.is_read_only() {
+            return;
+        }
+        let now = chrono::Utc::now();
+        let threshold = now - chrono::Duration::seconds(LAST_LOGIN_UPDATE_INTERVAL_SECS);
+        match model::User::update_many()
+            .col_expr(UserColumn::LastLogin, Expr::value(now))
+            .filter(UserColumn::UserId.eq(user_id))
+            .filter(
+                Cond::any()
+                    .add(UserColumn::LastLogin.is_null())
+                    .add(UserColumn::LastLogin.lt(threshold)),
+            )
+            .exec(&self.sql_pool)
+            .await
+        {
+            Ok(res) if res.rows_affected > 0 => {
+                self.after_write(Change::User(user_id.clone())).await
+            }
+            Ok(_) => {}
+            Err(e) => warn!(r#"Could not record the login of "{}": {}"#, user_id, e),
+        }
+    }
+
+    /// Checks the password against the user's OPAQUE password file, on the password check pool.
+    async fn password_file_matches(
+        &self,
+        password_file: Option<Vec<u8>>,
+        request: &BindRequest,
+    ) -> Result<bool> {
+        let server_setup = self.config.get_server_setup().clone();
+        let password = request.password.clone();
+        let username = request.name.clone();
+        match self
+            .password_checks
+            .run(move || {
+                passwords_match(
+                    password_file.as_deref(),
+                    &password,
+                    &server_setup,
+                    &username,
+                )
+            })
+            .await
+        {
+            Ok(()) => Ok(true),
+            Err(e @ DomainError::ServerBusy(_)) => Err(e),
+            Err(e) => {
+                debug!(r#"Invalid password for "{}": {}"#, &request.name, e);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Checks the password against an imported hash, on the password check pool.
+    async fn legacy_password_hash_matches(&self, hash: String, password: &str) -> Result<bool> {
+        let password = password.to_owned();
+        self.password_checks
+            .run(move || Ok(legacy_password::verify(&hash, &password)))
+            .await
+    }
+
+    /// Checks the password of the user. The unknown users and the users without a password get
+    /// a dummy OPAQUE check, so that the failure takes as long as for a wrong password.
+    async fn verify_password(&self, request: &BindRequest) -> Result<PasswordMatch> {
+        match self.get_password_hashes(&request.name).await? {
+            (Some(password_file), _) => {
+                if self
+                    .password_file_matches(Some(password_file), request)
+                    .await?
+                {
+                    return Ok(PasswordMatch::Opaque);
+                }
+                debug!(r#"Invalid password for "{}""#, &request.name);
+            }
+            (None, Some(legacy_hash)) => {
+                if self
+                    .legacy_password_hash_matches(legacy_hash, &request.password)
+                    .await?
+                {
+                    return Ok(PasswordMatch::Legacy);
+                }
+                debug!(r#"Invalid password for "{}""#, &request.name);
+            }
+            (None, None) => {
+                self.password_file_matches(None, request).await?;
+                debug!(
+                    r#"User "{}" doesn't exist or has no password"#,
+                    &request.name
+                );
+            }
+        }
+        Ok(PasswordMatch::NoMatch)
+    }
+}
+
+/// See `SqlBackendHandler::verify_password`.
+enum PasswordMatch {
+    NoMatch,
+    Opaque,
+    /// An imported hash, to replace with an OPAQUE password file.
+    Legacy,
 }
 
 #[async_trait]
 impl LoginHandler for SqlBackendHandler {
     #[instrument(skip_all, level = "debug", err)]
-    async fn bind(&self, request: BindRequest) -> Result<()> {
-        if let Some(password_hash) = self
-            .get_password_file_for_user(request.name.clone())
-            .await?
-        {
-            if let Err(e) = passwords_match(
-                &password_hash,
-                &request.password,
-                self.config.get_server_setup(),
-                &request.name,
-            ) {
-                debug!(r#"Invalid password for "{}": {}"#, &request.name, e);
-            } else {
-                return Ok(());
+    async fn bind(&self, request: BindRequest) -> Result<UserId> {
+        let request = BindRequest {
+            name: self.resolve_login_name(&request.name).await?,
+            ..request
+        };
+        match self.verify_password(&request).await? {
+            PasswordMatch::NoMatch => {
+                return Err(DomainError::AuthenticationError(
+                    INVALID_CREDENTIALS.to_owned(),
+                ))
+            }
+            PasswordMatch::Opaque => {}
+            PasswordMatch::Legacy if self.is_read_only() => {}
+            PasswordMatch::Legacy => {
+                // Now that we know the password, replace the imported hash with an OPAQUE
+                // password file.
+                register_password(
+                    self,
+                    request.name.clone(),
+                    &SecUtf8::from(request.password.as_str()),
+                )
+                .await?;
             }
-        } else {
-            debug!(
-                r#"User "{}" doesn't exist or has no password"#,
-                &request.name
-            );
         }
-        Err(DomainError::AuthenticationError(format!(
-            " for user '{}'",
-            request.name
-        )))
+        self.record_login(&request.name).await;
+        Ok(request.name)
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn check_password(&self, request: BindRequest) -> Result<bool> {
+        let request = BindRequest {
+            name: self.resolve_login_name(&request.name).await?,
+            ..request
+        };
+        Ok(!matches!(
+            self.verify_password(&request).await?,
+            PasswordMatch::NoMatch
+        ))
     }
 }
 
@@ -100,7 +280,7 @@ impl OpaqueHandler for SqlOpaqueHandler {
         &self,
         request: login::ClientLoginStartRequest,
     ) -> Result<login::ServerLoginStartResponse> {
-        let user_id = request.username;
+        let user_id = self.resolve_login_name(&request.username).await?;
         let maybe_password_file = self
             .get_password_file_for_user(user_id.clone())
             .await?
@@ -111,15 +291,21 @@ impl OpaqueHandler for SqlOpaqueHandler {
             })
             .transpose()?;
 
-        let mut rng = rand::rngs::OsRng;
+        let server_setup = self.config.get_server_setup().clone();
+        let username = user_id.clone();
         // Get the CredentialResponse for the user, or a dummy one if no user/no password.
-        let start_response = opaque::server::login::start_login(
-            &mut rng,
-            self.config.get_server_setup(),
-            maybe_password_file,
-            request.login_start_request,
-            &user_id,
-        )?;
+        let start_response = self
+            .password_checks
+            .run(move || {
+                Ok(opaque::server::login::start_login(
+                    &mut rand::rngs::OsRng,
+                    &server_setup,
+                    maybe_password_file,
+                    request.login_start_request,
+                    &username,
+                )?)
+            })
+            .await?;
         let secret_key = self.get_orion_secret_key()?;
         let server_data = login::ServerData {
             username: user_id,
@@ -145,9 +331,17 @@ impl OpaqueHandler for SqlOpaqueHandler {
         )?)?;
         // Finish the login: this makes sure the client data is correct, and gives a session key we
         // don't need.
-        let _session_key =
-            opaque::server::login::finish_login(server_login, request.credential_finalization)?
-                .session_key;
+        let _session_key = self
+            .password_checks
+            .run(move || {
+                Ok(opaque::server::login::finish_login(
+                    server_login,
+                    request.credential_finalization,
+                )?)
+            })
+            .await?
+            .session_key;
+        self.record_login(&username).await;
 
         Ok(username)
     }
@@ -157,12 +351,20 @@ impl OpaqueHandler for SqlOpaqueHandler {
         &self,
         request: registration::ClientRegistrationStartRequest,
     ) -> Result<registration::ServerRegistrationStartResponse> {
+        self.check_writable()?;
         // Generate the server-side key and derive the data to send back.
-        let start_response = opaque::server::registration::start_registration(
-            self.config.get_server_setup(),
-            request.registration_start_request,
-            &request.username,
-        )?;
+        let server_setup = self.config.get_server_setup().clone();
+        let username = request.username.clone();
+        let start_response = self
+            .password_checks
+            .run(move || {
+                Ok(opaque::server::registration::start_registration(
+                    &server_setup,
+                    request.registration_start_request,
+                    &username,
+                )?)
+            })
+            .await?;
         let secret_key = self.get_orion_secret_key()?;
         let server_data = registration::ServerData {
             username: request.username,
@@ -179,6 +381,8 @@ impl OpaqueHandler for SqlOpaqueHandler {
         &self,
         request: registration::ClientRegistrationFinishRequest,
     ) -> Result<()> {
+        let _timer = self.time_query("registration_finish");
+        self.check_writable()?;
         let secret_key = self.get_orion_secret_key()?;
         let registration::ServerData { username } = bincode::deserialize(&orion::aead::open(
             &secret_key,
@@ -189,11 +393,14 @@ impl OpaqueHandler for SqlOpaqueHandler {
             opaque::server::registration::get_password_file(request.registration_upload);
         // Set the user password to the new password.
         let user_update = model::users::ActiveModel {
-            user_id: ActiveValue::Set(username),
+            user_id: ActiveValue::Set(username.clone()),
             password_hash: ActiveValue::Set(Some(password_file.serialize())),
+            password_changed_at: ActiveValue::Set(Some(chrono::Utc::now())),
+            legacy_password_hash: ActiveValue::Set(None),
             ..Default::default()
         };
         user_update.update(&self.sql_pool).await?;
+        self.after_write(Change::User(username)).await;
         Ok(())
     }
 }
@@ -215,11 +422,17 @@ pub(crate) async fn register_password(
             registration_start_request: registration_start.message,
         })
         .await?;
-    let registration_finish = opaque::client::registration::finish_registration(
-        registration_start.state,
-        start_response.registration_response,
-        &mut rng,
-    )?;
+    // The client side of the registration is the expensive part: it stretches the password.
+    let registration_finish = opaque_handler
+        .password_checks
+        .run(move || {
+            Ok(opaque::client::registration::finish_registration(
+                registration_start.state,
+                start_response.registration_response,
+                &mut rand::rngs::OsRng,
+            )?)
+        })
+        .await?;
     opaque_handler
         .registration_finish(ClientRegistrationFinishRequest {
             server_data: start_response.server_data,
@@ -228,16 +441,40 @@ pub(crate) async fn register_password(
         .await
 }
 
+/// Sets a password hash imported from another LDAP server. It is only used if the user doesn't
+/// have a password yet.
+#[instrument(skip_all, level = "debug", err, fields(username = %username.as_str()))]
+pub(crate) async fn set_legacy_password_hash(
+    handler: &SqlBackendHandler,
+    username: &UserId,
+    hash: &str,
+) -> Result<()> {
+    handler.check_writable()?;
+    let user_update = model::users::ActiveModel {
+        user_id: ActiveValue::Set(username.clone()),
+        legacy_password_hash: ActiveValue::Set(Some(hash.to_owned())),
+        ..Default::default()
+    };
+    user_update.update(&handler.sql_pool).await?;
+    handler.after_write(Change::User(username.clone())).await;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::sql_backend_handler::tests::*;
+    use crate::domain::{
+        handler::{CreateUserRequest, UserBackendHandler},
+        password_check_pool::PasswordCheckPool,
+        sql_backend_handler::tests::*,
+    };
+    use std::time::Duration;
 
     async fn attempt_login(
         opaque_handler: &SqlOpaqueHandler,
         username: &str,
         password: &str,
-    ) -> Result<()> {
+    ) -> Result<UserId> {
         let mut rng = rand::rngs::OsRng;
         use login::*;
         let login_start = opaque::client::login::start_login(password, &mut rng)?;
@@ -255,9 +492,9 @@ mod tests {
             .login_finish(ClientLoginFinishRequest {
                 server_data: start_response.server_data,
                 credential_finalization: login_finish.message,
+                remember_me: None,
             })
-            .await?;
-        Ok(())
+            .await
     }
 
     #[tokio::test]
@@ -315,6 +552,325 @@ mod tests {
             .unwrap_err();
     }
 
+    fn email_login_config() -> crate::infra::configuration::Configuration {
+        let mut config = get_default_config();
+        config.login_identifier = LoginIdentifier::Email;
+        config
+    }
+
+    #[tokio::test]
+    async fn test_bind_with_email() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlOpaqueHandler::new(email_login_config(), sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        // A user ID that looks like an email, without an email.
+        handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("carol@example.com"),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        register_password(
+            &handler,
+            UserId::new("carol@example.com"),
+            &SecUtf8::from("carol00"),
+        )
+        .await
+        .unwrap();
+        let bind = |name: &str, password: &str| {
+            handler.bind(BindRequest {
+                name: UserId::new(name),
+                password: password.to_string(),
+            })
+        };
+        assert_eq!(
+            bind("Bob@Bob.bob", "bob00").await.unwrap(),
+            UserId::new("bob")
+        );
+        // The user IDs keep working.
+        assert_eq!(bind("bob", "bob00").await.unwrap(), UserId::new("bob"));
+        assert_eq!(
+            bind("carol@example.com", "carol00").await.unwrap(),
+            UserId::new("carol@example.com")
+        );
+        bind("bob@bob.bob", "wrong_password").await.unwrap_err();
+        bind("unknown@bob.bob", "bob00").await.unwrap_err();
+        assert!(handler
+            .check_password(BindRequest {
+                name: UserId::new("bob@bob.bob"),
+                password: "bob00".to_string(),
+            })
+            .await
+            .unwrap());
+
+        // Without the option, the emails are not login names.
+        let handler = SqlOpaqueHandler::new(get_default_config(), sql_pool);
+        handler
+            .bind(BindRequest {
+                name: UserId::new("bob@bob.bob"),
+                password: "bob00".to_string(),
+            })
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_opaque_login_with_email() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlOpaqueHandler::new(email_login_config(), sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        assert_eq!(
+            attempt_login(&handler, "bob@bob.bob", "bob00")
+                .await
+                .unwrap(),
+            UserId::new("bob")
+        );
+        assert_eq!(
+            attempt_login(&handler, "bob", "bob00").await.unwrap(),
+            UserId::new("bob")
+        );
+        attempt_login(&handler, "bob@bob.bob", "wrong_password")
+            .await
+            .unwrap_err();
+        let handler = SqlOpaqueHandler::new(get_default_config(), sql_pool);
+        attempt_login(&handler, "bob@bob.bob", "bob00")
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_bind_failures_are_indistinguishable() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlOpaqueHandler::new(config, sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        let bind = |name: &str| {
+            handler.bind(BindRequest {
+                name: UserId::new(name),
+                password: "wrong_password".to_string(),
+            })
+        };
+        let unknown_user = bind("andrew").await.unwrap_err().to_string();
+        let wrong_password = bind("bob").await.unwrap_err().to_string();
+        assert_eq!(unknown_user, wrong_password);
+
+        // Interleaved, so that a slower period affects both alike.
+        let mut unknown_user = Vec::new();
+        let mut wrong_password = Vec::new();
+        for _ in 0..15 {
+            let start = std::time::Instant::now();
+            bind("andrew").await.unwrap_err();
+            unknown_user.push(start.elapsed());
+            let start = std::time::Instant::now();
+            bind("bob").await.unwrap_err();
+            wrong_password.push(start.elapsed());
+        }
+        let median = |mut durations: Vec<Duration>| {
+            durations.sort();
+            durations[durations.len() / 2]
+        };
+        let (unknown_user, wrong_password) = (median(unknown_user), median(wrong_password));
+        assert!(
+            unknown_user * 2 > wrong_password && wrong_password * 2 > unknown_user,
+            "median bind failure: {:?} for an unknown user, {:?} for a wrong password",
+            unknown_user,
+            wrong_password
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_password() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlOpaqueHandler::new(config, sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        let check = |name: &str, password: &str| {
+            handler.check_password(BindRequest {
+                name: UserId::new(name),
+                password: password.to_string(),
+            })
+        };
+
+        assert!(check("bob", "bob00").await.unwrap());
+        assert!(!check("bob", "wrong_password").await.unwrap());
+        assert!(!check("andrew", "bob00").await.unwrap());
+        // Unlike a bind, checking the password is not a login.
+        assert_eq!(
+            handler
+                .get_user_details(&UserId::new("bob"))
+                .await
+                .unwrap()
+                .last_login,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bind_user_with_legacy_password_hash() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlOpaqueHandler::new(config, sql_pool.clone());
+        insert_user_no_password(&handler, "bob").await;
+        let bob = UserId::new("bob");
+        // {SSHA} of "password".
+        set_legacy_password_hash(&handler, &bob, "{SSHA}yI6cZwQadOA1e+/f+T+H3eCQQhRzYWx0")
+            .await
+            .unwrap();
+        handler
+            .bind(BindRequest {
+                name: bob.clone(),
+                password: "wrong_password".to_string(),
+            })
+            .await
+            .unwrap_err();
+        handler
+            .bind(BindRequest {
+                name: bob.clone(),
+                password: "password".to_string(),
+            })
+            .await
+            .unwrap();
+        // The hash was replaced by an OPAQUE password file.
+        assert_eq!(handler.get_password_hashes(&bob).await.unwrap().1, None);
+        assert!(handler
+            .get_password_file_for_user(bob.clone())
+            .await
+            .unwrap()
+            .is_some());
+        attempt_login(&handler, "bob", "password").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bind_when_busy() {
+        let sql_pool = get_initialized_db().await;
+        let mut handler = SqlOpaqueHandler::new(get_default_config(), sql_pool);
+        insert_user(&handler, "bob", "bob00").await;
+        handler.password_checks = PasswordCheckPool::with_max_wait(1, Duration::from_millis(10));
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let running = tokio::spawn({
+            let password_checks = handler.password_checks.clone();
+            async move {
+                password_checks
+                    .run(move || {
+                        released.recv().unwrap();
+                        Ok(())
+                    })
+                    .await
+            }
+        });
+        let bind = || {
+            handler.bind(BindRequest {
+                name: UserId::new("bob"),
+                password: "bob00".to_string(),
+            })
+        };
+        // Let the blocked check take the only slot.
+        tokio::task::yield_now().await;
+        // Refused without checking the password, for the client to retry.
+        assert!(matches!(bind().await, Err(DomainError::ServerBusy(_))));
+        release.send(()).unwrap();
+        running.await.unwrap().unwrap();
+        bind().await.unwrap();
+    }
+
+    /// Run with `cargo test -- --ignored`. Compares the 99th percentile of the latency of the
+    /// user lists during a flood of binds, with the password checks on the async workers (as they
+    /// used to be) and on the password check pool. The latencies are logged at the info level.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    #[ignore]
+    async fn test_bind_flood_search_latency() {
+        use crate::domain::handler::{UserListerBackendHandler, UserRequestFilter};
+        use std::{
+            sync::atomic::{AtomicBool, Ordering},
+            sync::Arc,
+            time::Instant,
+        };
+        crate::infra::logging::init_for_tests();
+        const BINDERS: usize = 16;
+        const SEARCHES: usize = 200;
+        let handler = SqlOpaqueHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user(&handler, "bob", "bob00").await;
+        for i in 0..20 {
+            insert_user_no_password(&handler, &format!("user{}", i)).await;
+        }
+        let password_file = handler
+            .get_password_file_for_user(UserId::new("bob"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let search_p99 = |offloaded: bool| {
+            let handler = handler.clone();
+            let password_file = password_file.clone();
+            async move {
+                let stop = Arc::new(AtomicBool::new(false));
+                let binders = (0..BINDERS)
+                    .map(|_| {
+                        let (handler, password_file, stop) =
+                            (handler.clone(), password_file.clone(), stop.clone());
+                        tokio::spawn(async move {
+                            let request = BindRequest {
+                                name: UserId::new("bob"),
+                                password: "bob00".to_string(),
+                            };
+                            while !stop.load(Ordering::Relaxed) {
+                                if offloaded {
+                                    let _ = handler
+                                        .password_file_matches(
+                                            Some(password_file.clone()),
+                                            &request,
+                                        )
+                                        .await;
+                                } else {
+                                    let _ = passwords_match(
+                                        Some(&password_file),
+                                        &request.password,
+                                        handler.config.get_server_setup(),
+                                        &request.name,
+                                    );
+                                }
+                                tokio::task::yield_now().await;
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let mut latencies = Vec::with_capacity(SEARCHES);
+                for _ in 0..SEARCHES {
+                    let start = Instant::now();
+                    let filter = UserRequestFilter::UserId(UserId::new("user1"));
+                    tokio::spawn({
+                        let handler = handler.clone();
+                        async move { handler.list_users(Some(filter), false).await.unwrap() }
+                    })
+                    .await
+                    .unwrap();
+                    latencies.push(start.elapsed());
+                }
+                stop.store(true, Ordering::Relaxed);
+                for binder in binders {
+                    binder.await.unwrap();
+                }
+                latencies.sort();
+                latencies[SEARCHES * 99 / 100]
+            }
+        };
+        let inline = search_p99(false).await;
+        let offloaded = search_p99(true).await;
+        tracing::info!(
+            ?inline,
+            ?offloaded,
+            "p99 search latency during a bind flood, on the async workers and on the password \
+            check pool"
+        );
+        assert!(
+            offloaded < inline,
+            "p99 search latency: {:?} with the password check pool, {:?} without",
+            offloaded,
+            inline
+        );
+    }
+
     #[tokio::test]
     async fn test_user_no_password() {
         let sql_pool = get_initialized_db().await;
@@ -5,6 +5,7 @@ use crate::domain::{
         SchemaBackendHandler,
     },
     model,
+    read_model::Change,
     sql_backend_handler::SqlBackendHandler,
     types::{AttributeName, LdapObjectClass},
 };
@@ -16,6 +17,7 @@ use sea_orm::{
 #[async_trait]
 impl ReadSchemaBackendHandler for SqlBackendHandler {
     async fn get_schema(&self) -> Result<Schema> {
+        let _timer = self.time_query("get_schema");
         Ok(self
             .sql_pool
             .transaction::<_, Schema, DomainError>(|transaction| {
@@ -28,6 +30,7 @@ impl ReadSchemaBackendHandler for SqlBackendHandler {
 #[async_trait]
 impl SchemaBackendHandler for SqlBackendHandler {
     async fn add_user_attribute(&self, request: CreateAttributeRequest) -> Result<()> {
+        self.check_writable()?;
         let new_attribute = model::user_attribute_schema::ActiveModel {
             attribute_name: Set(request.name),
             attribute_type: Set(request.attribute_type),
@@ -37,10 +40,12 @@ impl SchemaBackendHandler for SqlBackendHandler {
             is_hardcoded: Set(false),
         };
         new_attribute.insert(&self.sql_pool).await?;
+        self.after_write(Change::All).await;
         Ok(())
     }
 
     async fn add_group_attribute(&self, request: CreateAttributeRequest) -> Result<()> {
+        self.check_writable()?;
         let new_attribute = model::group_attribute_schema::ActiveModel {
             attribute_name: Set(request.name),
             attribute_type: Set(request.attribute_type),
@@ -50,24 +55,30 @@ impl SchemaBackendHandler for SqlBackendHandler {
             is_hardcoded: Set(false),
         };
         new_attribute.insert(&self.sql_pool).await?;
+        self.after_write(Change::All).await;
         Ok(())
     }
 
     async fn delete_user_attribute(&self, name: &AttributeName) -> Result<()> {
+        self.check_writable()?;
         model::UserAttributeSchema::delete_by_id(name.clone())
             .exec(&self.sql_pool)
             .await?;
+        self.after_write(Change::All).await;
         Ok(())
     }
 
     async fn delete_group_attribute(&self, name: &AttributeName) -> Result<()> {
+        self.check_writable()?;
         model::GroupAttributeSchema::delete_by_id(name.clone())
             .exec(&self.sql_pool)
             .await?;
+        self.after_write(Change::All).await;
         Ok(())
     }
 
     async fn add_user_object_class(&self, name: &LdapObjectClass) -> Result<()> {
+        self.check_writable()?;
         let mut name_key = name.to_string();
         name_key.make_ascii_lowercase();
         model::user_object_classes::ActiveModel {
@@ -76,10 +87,12 @@ impl SchemaBackendHandler for SqlBackendHandler {
         }
         .insert(&self.sql_pool)
         .await?;
+        self.after_write(Change::All).await;
         Ok(())
     }
 
     async fn add_group_object_class(&self, name: &LdapObjectClass) -> Result<()> {
+        self.check_writable()?;
         let mut name_key = name.to_string();
         name_key.make_ascii_lowercase();
         model::group_object_classes::ActiveModel {
@@ -88,20 +101,25 @@ impl SchemaBackendHandler for SqlBackendHandler {
         }
         .insert(&self.sql_pool)
         .await?;
+        self.after_write(Change::All).await;
         Ok(())
     }
 
     async fn delete_user_object_class(&self, name: &LdapObjectClass) -> Result<()> {
+        self.check_writable()?;
         model::UserObjectClasses::delete_by_id(name.as_str().to_ascii_lowercase())
             .exec(&self.sql_pool)
             .await?;
+        self.after_write(Change::All).await;
         Ok(())
     }
 
     async fn delete_group_object_class(&self, name: &LdapObjectClass) -> Result<()> {
+        self.check_writable()?;
         model::GroupObjectClasses::delete_by_id(name.as_str().to_ascii_lowercase())
             .exec(&self.sql_pool)
             .await?;
+        self.after_write(Change::All).await;
         Ok(())
     }
 }
@@ -1,19 +1,27 @@
-use crate::domain::sql_migrations::{
-    get_schema_version, migrate_from_version, upgrade_to_v1, Metadata,
+use crate::domain::{
+    model,
+    sql_migrations::{
+        get_schema_version, migrate_from_version_with_options, print_migrations_from_version,
+        upgrade_to_v1, Metadata, MigrationOptions, Users,
+    },
 };
+use anyhow::bail;
 use sea_orm::{
-    sea_query::Query, ConnectionTrait, DeriveValueType, Iden, QueryResult, TryGetable, Value,
+    sea_query::{Expr, Query},
+    ConnectionTrait, DbErr, DeriveValueType, EntityName, EntityTrait, Iden, QueryResult,
+    TryGetable, Value,
 };
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
 pub type DbConnection = sea_orm::DatabaseConnection;
 
 #[derive(Copy, PartialEq, Eq, Debug, Clone, PartialOrd, Ord, DeriveValueType)]
 pub struct SchemaVersion(pub i16);
 
-pub const LAST_SCHEMA_VERSION: SchemaVersion = SchemaVersion(10);
+pub const LAST_SCHEMA_VERSION: SchemaVersion = SchemaVersion(26);
 
-#[derive(Copy, PartialEq, Eq, Debug, Clone, PartialOrd, Ord)]
+#[derive(Copy, PartialEq, Eq, Debug, Clone, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct PrivateKeyHash(pub [u8; 32]);
 
 impl TryGetable for PrivateKeyHash {
@@ -44,6 +52,14 @@ impl From<PrivateKeyHash> for Value {
 }
 
 pub async fn init_table(pool: &DbConnection) -> anyhow::Result<()> {
+    init_table_with_options(pool, MigrationOptions::default()).await
+}
+
+/// Creates the schema, or upgrades it with the given options.
+async fn init_table_with_options(
+    pool: &DbConnection,
+    options: MigrationOptions,
+) -> anyhow::Result<()> {
     let version = {
         if let Some(version) = get_schema_version(pool).await {
             version
@@ -52,7 +68,143 @@ pub async fn init_table(pool: &DbConnection) -> anyhow::Result<()> {
             SchemaVersion(1)
         }
     };
-    migrate_from_version(pool, version, LAST_SCHEMA_VERSION).await?;
+    migrate_from_version_with_options(pool, version, LAST_SCHEMA_VERSION, options).await?;
+    Ok(())
+}
+
+/// Whether the database contains tables created by a version of LLDAP predating the schema
+/// versioning.
+async fn has_unversioned_tables(pool: &DbConnection) -> bool {
+    pool.query_one(
+        pool.get_database_backend().build(
+            Query::select()
+                .from(Users::Table)
+                .column(Users::UserId)
+                .limit(1),
+        ),
+    )
+    .await
+    .is_ok()
+}
+
+/// Whether the database has none of the LLDAP tables.
+pub async fn is_empty_database(pool: &DbConnection) -> bool {
+    get_schema_version(pool).await.is_none() && !has_unversioned_tables(pool).await
+}
+
+/// Returns the current schema version if it needs to be upgraded, i.e. if `lldap migrate` has
+/// something to do on a non-empty database.
+pub async fn get_pending_migration(pool: &DbConnection) -> Option<SchemaVersion> {
+    let version = match get_schema_version(pool).await {
+        Some(version) => version,
+        None if has_unversioned_tables(pool).await => SchemaVersion(0),
+        None => return None,
+    };
+    (version < LAST_SCHEMA_VERSION).then_some(version)
+}
+
+/// Reads a row of each table with all the columns this version of LLDAP uses, to report a schema
+/// that doesn't match its version (e.g. modified by hand) at startup rather than in the middle of
+/// a request.
+pub async fn check_tables(pool: &DbConnection) -> anyhow::Result<()> {
+    async fn check<E: EntityTrait>(pool: &DbConnection, errors: &mut Vec<String>) {
+        if let Err(e) = E::find().one(pool).await {
+            errors.push(format!("table `{}`: {}", E::default().table_name(), e));
+        }
+    }
+    let mut errors = Vec::new();
+    check::<model::User>(pool, &mut errors).await;
+    check::<model::Group>(pool, &mut errors).await;
+    check::<model::Membership>(pool, &mut errors).await;
+    check::<model::UserAttributeSchema>(pool, &mut errors).await;
+    check::<model::UserAttributes>(pool, &mut errors).await;
+    check::<model::GroupAttributeSchema>(pool, &mut errors).await;
+    check::<model::GroupAttributes>(pool, &mut errors).await;
+    check::<model::UserObjectClasses>(pool, &mut errors).await;
+    check::<model::GroupObjectClasses>(pool, &mut errors).await;
+    check::<model::PasswordResetTokens>(pool, &mut errors).await;
+    if !errors.is_empty() {
+        bail!(
+            "The database schema doesn't match its version ({}), it may have been modified by hand or by an incompatible version of LLDAP. Restore a backup of the database, or start with --skip-db-checks at your own risk.\n{}",
+            LAST_SCHEMA_VERSION.0,
+            errors.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Checks that the database schema is the one expected by this binary, creating it from scratch
+/// if the database is empty.
+///
+/// An outdated schema is only upgraded if `auto_migrate` is set, with these options, otherwise
+/// `lldap migrate` has to be run first. With `skip_checks`, a newer schema or a damaged one are
+/// only logged.
+pub async fn check_or_init_table(
+    pool: &DbConnection,
+    auto_migrate: Option<MigrationOptions>,
+    skip_checks: bool,
+) -> anyhow::Result<()> {
+    let version = match get_schema_version(pool).await {
+        Some(version) => version,
+        None if has_unversioned_tables(pool).await => SchemaVersion(0),
+        None => return init_table(pool).await,
+    };
+    if version > LAST_SCHEMA_VERSION {
+        if skip_checks {
+            warn!(
+                "The database schema (version {}) is newer than the one supported by this version of LLDAP (version {}), expect errors.",
+                version.0,
+                LAST_SCHEMA_VERSION.0
+            );
+            return Ok(());
+        }
+        bail!(
+            "The database schema (version {}) is newer than the one supported by this version of LLDAP (version {}). Downgrading is not supported, please upgrade LLDAP or restore a backup of the database.",
+            version.0,
+            LAST_SCHEMA_VERSION.0
+        );
+    }
+    if version < LAST_SCHEMA_VERSION {
+        let Some(options) = auto_migrate else {
+            bail!(
+                "The database schema (version {}) needs to be upgraded to version {}. Back up the database, then run `lldap migrate` (use `--dry-run` to see the changes), or set run_migrations_on_startup = true.",
+                version.0,
+                LAST_SCHEMA_VERSION.0
+            );
+        };
+        init_table_with_options(pool, options).await?;
+    }
+    if skip_checks {
+        warn!("Skipping the database checks");
+        return Ok(());
+    }
+    check_tables(pool).await
+}
+
+/// Applies the pending migrations, or prints the corresponding statements if `dry_run` is set:
+/// they are then run in a transaction that is rolled back, which MySQL doesn't support.
+pub async fn migrate(
+    pool: &DbConnection,
+    options: MigrationOptions,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    if !dry_run {
+        return init_table_with_options(pool, options).await;
+    }
+    match get_schema_version(pool).await {
+        Some(version) => {
+            print_migrations_from_version(pool, version, LAST_SCHEMA_VERSION, options).await?;
+        }
+        None if has_unversioned_tables(pool).await => {
+            println!("-- Database created by an unversioned LLDAP, stamping it with version 1");
+            print_migrations_from_version(pool, SchemaVersion(1), LAST_SCHEMA_VERSION, options)
+                .await?;
+        }
+        None => println!(
+            "-- Empty database, creating the schema version {}",
+            LAST_SCHEMA_VERSION.0
+        ),
+    }
     Ok(())
 }
 
@@ -121,17 +273,48 @@ pub async fn set_private_key_info(pool: &DbConnection, info: PrivateKeyInfo) ->
     Ok(())
 }
 
+/// The generation of the data, see `bump_cache_generation`.
+pub async fn get_cache_generation(pool: &DbConnection) -> Result<u64, DbErr> {
+    Ok(pool
+        .query_one(
+            pool.get_database_backend().build(
+                Query::select()
+                    .column(Metadata::CacheGeneration)
+                    .from(Metadata::Table),
+            ),
+        )
+        .await?
+        .map(|r| r.try_get_by_index::<i64>(0))
+        .transpose()?
+        .unwrap_or_default() as u64)
+}
+
+/// Marks the cached reads of all the processes using the database as outdated.
+pub async fn bump_cache_generation(pool: &DbConnection) -> Result<(), DbErr> {
+    pool.execute(
+        pool.get_database_backend()
+            .build(Query::update().table(Metadata::Table).value(
+                Metadata::CacheGeneration,
+                Expr::col(Metadata::CacheGeneration).add(1),
+            )),
+    )
+    .await?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::domain::{
-        sql_migrations,
-        types::{GroupId, JpegPhoto, Serialized, Uuid},
+        sql_migrations::{self, migrate_from_version},
+        types::{GroupId, JpegPhoto, Serialized, UserId, Uuid},
     };
     use pretty_assertions::assert_eq;
 
     use super::*;
     use chrono::prelude::*;
-    use sea_orm::{ConnectionTrait, Database, DbBackend, FromQueryResult};
+    use sea_orm::{
+        ColumnTrait, ConnectionTrait, Database, DbBackend, FromQueryResult, QueryFilter, QueryOrder,
+    };
     use tracing::error;
 
     async fn get_in_memory_db() -> DbConnection {
@@ -167,7 +350,7 @@ mod tests {
         #[derive(FromQueryResult, PartialEq, Eq, Debug)]
         struct ShortUserDetails {
             display_name: String,
-            creation_date: chrono::NaiveDateTime,
+            creation_date: chrono::DateTime<Utc>,
         }
         let result = ShortUserDetails::find_by_statement(raw_statement(
             r#"SELECT display_name, creation_date FROM users WHERE user_id = "bôb""#,
@@ -180,7 +363,7 @@ mod tests {
             result,
             ShortUserDetails {
                 display_name: "Bob Bobbersön".to_owned(),
-                creation_date: Utc.timestamp_opt(0, 0).unwrap().naive_utc(),
+                creation_date: Utc.timestamp_opt(0, 0).unwrap(),
             }
         );
     }
@@ -550,6 +733,409 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_migration_to_v18() {
+        crate::infra::logging::init_for_tests();
+        let sql_pool = get_in_memory_db().await;
+        upgrade_to_v1(&sql_pool).await.unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(1), SchemaVersion(17))
+            .await
+            .unwrap();
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO users (user_id, email, lowercase_email, display_name, creation_date, uuid)
+                       VALUES ("bob", "bob@bob.com", "bob@bob.com", "Bob Bobbington", "1970-01-01 00:00:00", "a02eaf13-48a7-30f6-a3d4-040ff7c52b04"),
+                              ("john", "john@bob.com", "john@bob.com", NULL, "1970-01-01 00:00:00", "986765a5-3f03-389e-b47b-536b2d6e1bec")"#,
+            ))
+            .await
+            .unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(17), SchemaVersion(18))
+            .await
+            .unwrap();
+        #[derive(FromQueryResult, PartialEq, Eq, Debug)]
+        struct ShortUserDetails {
+            user_id: String,
+            lowercase_display_name: Option<String>,
+        }
+        assert_eq!(
+            ShortUserDetails::find_by_statement(raw_statement(
+                r#"SELECT user_id, lowercase_display_name FROM users ORDER BY user_id"#,
+            ))
+            .all(&sql_pool)
+            .await
+            .unwrap(),
+            vec![
+                ShortUserDetails {
+                    user_id: "bob".to_owned(),
+                    lowercase_display_name: Some("bob bobbington".to_owned()),
+                },
+                ShortUserDetails {
+                    user_id: "john".to_owned(),
+                    lowercase_display_name: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migration_to_v19() {
+        crate::infra::logging::init_for_tests();
+        let sql_pool = get_in_memory_db().await;
+        upgrade_to_v1(&sql_pool).await.unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(1), SchemaVersion(18))
+            .await
+            .unwrap();
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO users (user_id, email, lowercase_email, display_name, creation_date, uuid)
+                       VALUES ("bob", "bob@bob.com", "bob@bob.com", "Bob Bobbington", "1970-01-01 00:00:00", "a02eaf13-48a7-30f6-a3d4-040ff7c52b04")"#,
+            ))
+            .await
+            .unwrap();
+        sql_pool
+            .execute(sea_orm::Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                r#"INSERT INTO user_attributes (user_attribute_user_id, user_attribute_name, user_attribute_value)
+                       VALUES (?, ?, ?), (?, ?, ?)"#,
+                [
+                    "bob".into(),
+                    "first_name".into(),
+                    Serialized::from("Bob").into(),
+                    "bob".into(),
+                    "avatar".into(),
+                    Serialized::from(&JpegPhoto::for_tests()).into(),
+                ],
+            ))
+            .await
+            .unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(18), SchemaVersion(19))
+            .await
+            .unwrap();
+        #[derive(FromQueryResult, PartialEq, Eq, Debug)]
+        struct AttributeHash {
+            user_attribute_name: String,
+            user_attribute_value_hash: Option<String>,
+        }
+        assert_eq!(
+            AttributeHash::find_by_statement(raw_statement(
+                r#"SELECT user_attribute_name, user_attribute_value_hash FROM user_attributes ORDER BY user_attribute_name"#,
+            ))
+            .all(&sql_pool)
+            .await
+            .unwrap(),
+            vec![
+                AttributeHash {
+                    user_attribute_name: "avatar".to_owned(),
+                    user_attribute_value_hash: Some(
+                        Serialized::from(&JpegPhoto::for_tests()).content_hash()
+                    ),
+                },
+                AttributeHash {
+                    user_attribute_name: "first_name".to_owned(),
+                    user_attribute_value_hash: Some(Serialized::from("Bob").content_hash()),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migration_to_v20() {
+        crate::infra::logging::init_for_tests();
+        let sql_pool = get_in_memory_db().await;
+        upgrade_to_v1(&sql_pool).await.unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(1), SchemaVersion(19))
+            .await
+            .unwrap();
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO users (user_id, email, lowercase_email, display_name, creation_date, uuid)
+                       VALUES ("bob", "bob@bob.com", "bob@bob.com", "", "1970-01-01 00:00:00", "a02eaf13-48a7-30f6-a3d4-040ff7c52b04"),
+                              ("jÖhn", "john@bob.com", "john@bob.com", "", "1970-01-01 00:00:00", "986765a5-3f03-389e-b47b-536b2d6e1bec")"#,
+            ))
+            .await
+            .unwrap();
+        sql_pool
+            .execute(sea_orm::Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                r#"INSERT INTO user_attributes (user_attribute_user_id, user_attribute_name, user_attribute_value)
+                       VALUES (?, ?, ?)"#,
+                [
+                    "jÖhn".into(),
+                    "first_name".into(),
+                    Serialized::from("John").into(),
+                ],
+            ))
+            .await
+            .unwrap();
+        crate::infra::jwt_sql_tables::init_table(&sql_pool)
+            .await
+            .unwrap();
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO jwt_storage (jwt_hash, user_id, expiry_date, blacklisted)
+                       VALUES (1, "jÖhn", "2030-01-01 00:00:00", true)"#,
+            ))
+            .await
+            .unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(19), SchemaVersion(20))
+            .await
+            .unwrap();
+        #[derive(FromQueryResult, PartialEq, Eq, Debug)]
+        struct ShortUserDetails {
+            user_id: String,
+        }
+        assert_eq!(
+            ShortUserDetails::find_by_statement(raw_statement(
+                r#"SELECT user_id FROM users ORDER BY user_id"#,
+            ))
+            .all(&sql_pool)
+            .await
+            .unwrap(),
+            vec![
+                ShortUserDetails {
+                    user_id: "bob".to_owned(),
+                },
+                ShortUserDetails {
+                    user_id: "jöhn".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(
+            ShortUserDetails::find_by_statement(raw_statement(
+                r#"SELECT user_attribute_user_id AS user_id FROM user_attributes"#,
+            ))
+            .all(&sql_pool)
+            .await
+            .unwrap(),
+            vec![ShortUserDetails {
+                user_id: "jöhn".to_owned(),
+            }]
+        );
+        // The revoked token of the user stays revoked.
+        assert_eq!(
+            ShortUserDetails::find_by_statement(raw_statement(
+                r#"SELECT user_id FROM jwt_storage"#,
+            ))
+            .all(&sql_pool)
+            .await
+            .unwrap(),
+            vec![ShortUserDetails {
+                user_id: "jöhn".to_owned(),
+            }]
+        );
+        // The user IDs are still displayed as they were stored.
+        assert_eq!(
+            ShortUserDetails::find_by_statement(raw_statement(
+                r#"SELECT user_id_as_entered AS user_id FROM users ORDER BY user_id"#,
+            ))
+            .all(&sql_pool)
+            .await
+            .unwrap(),
+            vec![
+                ShortUserDetails {
+                    user_id: "bob".to_owned(),
+                },
+                ShortUserDetails {
+                    user_id: "jÖhn".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migration_user_id_conflicts() {
+        crate::infra::logging::init_for_tests();
+        let sql_pool = get_in_memory_db().await;
+        upgrade_to_v1(&sql_pool).await.unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(1), SchemaVersion(19))
+            .await
+            .unwrap();
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO users (user_id, email, lowercase_email, display_name, creation_date, uuid)
+                       VALUES ("jÖhn", "john@bob.com", "john@bob.com", "", "1970-01-01 00:00:00", "a02eaf13-48a7-30f6-a3d4-040ff7c52b04"),
+                              ("jöhn", "john2@bob.com", "john2@bob.com", "", "1970-01-01 00:00:00", "986765a5-3f03-389e-b47b-536b2d6e1bec")"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            sql_migrations::find_conflicting_user_ids(&sql_pool)
+                .await
+                .unwrap(),
+            vec![(
+                UserId::new("JÖHN"),
+                vec!["jÖhn".to_owned(), "jöhn".to_owned()]
+            )]
+        );
+        migrate_from_version(&sql_pool, SchemaVersion(19), SchemaVersion(20))
+            .await
+            .expect_err("migration should fail");
+        sql_pool
+            .execute(raw_statement(r#"DELETE FROM users WHERE user_id = "jÖhn""#))
+            .await
+            .unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(19), SchemaVersion(20))
+            .await
+            .unwrap();
+    }
+
+    async fn get_db_at_v20_with_timestamps() -> DbConnection {
+        let sql_pool = get_in_memory_db().await;
+        upgrade_to_v1(&sql_pool).await.unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(1), SchemaVersion(20))
+            .await
+            .unwrap();
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO users (user_id, email, lowercase_email, display_name, creation_date, uuid, last_login)
+                       VALUES ("bob", "bob@bob.com", "bob@bob.com", "", "2023-01-01 10:00:00", "a02eaf13-48a7-30f6-a3d4-040ff7c52b04", "2023-01-02 12:30:00.500"),
+                              ("john", "john@bob.com", "john@bob.com", "", "2023-01-01 10:00:00", "986765a5-3f03-389e-b47b-536b2d6e1bec", NULL)"#,
+            ))
+            .await
+            .unwrap();
+        sql_pool
+    }
+
+    #[tokio::test]
+    async fn test_migration_to_v21() {
+        let sql_pool = get_db_at_v20_with_timestamps().await;
+        migrate_from_version(&sql_pool, SchemaVersion(20), SchemaVersion(21))
+            .await
+            .unwrap();
+        let users = model::User::find()
+            .order_by_asc(model::users::Column::UserId)
+            .all(&sql_pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            users
+                .iter()
+                .map(|u| (u.creation_date, u.last_login))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    Utc.with_ymd_and_hms(2023, 1, 1, 10, 0, 0).unwrap(),
+                    Some(
+                        Utc.with_ymd_and_hms(2023, 1, 2, 12, 30, 0).unwrap()
+                            + chrono::Duration::milliseconds(500)
+                    )
+                ),
+                (Utc.with_ymd_and_hms(2023, 1, 1, 10, 0, 0).unwrap(), None),
+            ]
+        );
+        // Stored in the same format as the new values, so that they compare as text.
+        #[derive(FromQueryResult, PartialEq, Eq, Debug)]
+        struct Dates {
+            creation_date: String,
+        }
+        assert_eq!(
+            Dates::find_by_statement(raw_statement(
+                r#"SELECT creation_date FROM users WHERE user_id = "bob""#,
+            ))
+            .one(&sql_pool)
+            .await
+            .unwrap()
+            .unwrap()
+            .creation_date,
+            "2023-01-01T10:00:00+00:00"
+        );
+        let logged_in_before = |date: DateTime<Utc>| {
+            model::User::find()
+                .filter(model::users::Column::LastLogin.lt(date))
+                .all(&sql_pool)
+        };
+        assert_eq!(
+            logged_in_before(Utc.with_ymd_and_hms(2023, 1, 2, 12, 30, 0).unwrap())
+                .await
+                .unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            logged_in_before(Utc.with_ymd_and_hms(2023, 1, 2, 12, 30, 1).unwrap())
+                .await
+                .unwrap(),
+            vec![users[0].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migration_to_v21_with_legacy_offset() {
+        let sql_pool = get_db_at_v20_with_timestamps().await;
+        sql_migrations::migrate_from_version_with_options(
+            &sql_pool,
+            SchemaVersion(20),
+            SchemaVersion(21),
+            MigrationOptions {
+                legacy_timestamps_offset: FixedOffset::east_opt(2 * 3600).unwrap(),
+            },
+        )
+        .await
+        .unwrap();
+        let user = model::User::find_by_id(UserId::new("john"))
+            .one(&sql_pool)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            user.creation_date,
+            Utc.with_ymd_and_hms(2023, 1, 1, 8, 0, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migration_email_uniqueness() {
+        crate::infra::logging::init_for_tests();
+        let sql_pool = get_in_memory_db().await;
+        upgrade_to_v1(&sql_pool).await.unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(1), SchemaVersion(9))
+            .await
+            .unwrap();
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO users (user_id, email, lowercase_email, display_name, creation_date, uuid)
+                       VALUES ("bob", "Bob@bob.com", "bob@bob.com", "", "1970-01-01 00:00:00", "a02eaf13-48a7-30f6-a3d4-040ff7c52b04"),
+                              ("bob2", "bob@BOB.com", "bob@bob.com", "", "1970-01-01 00:00:00", "986765a5-3f03-389e-b47b-536b2d6e1bec")"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            sql_migrations::find_conflicting_emails(&sql_pool)
+                .await
+                .unwrap(),
+            vec![(
+                "bob@bob.com".to_owned(),
+                vec![UserId::new("bob"), UserId::new("bob2")]
+            )]
+        );
+        migrate_from_version(&sql_pool, SchemaVersion(9), LAST_SCHEMA_VERSION)
+            .await
+            .expect_err("migration should fail");
+        sql_pool
+            .execute(raw_statement(
+                r#"UPDATE users SET email = "", lowercase_email = "" WHERE user_id = "bob2""#,
+            ))
+            .await
+            .unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(9), LAST_SCHEMA_VERSION)
+            .await
+            .unwrap();
+        // Empty emails don't conflict with each other.
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO users (user_id, email, display_name, creation_date, uuid)
+                       VALUES ("bob3", "", "", "1970-01-01 00:00:00", "2d4d5fb4-b2b1-3a1c-9e4c-3a6d1d5b1c0f")"#,
+            ))
+            .await
+            .unwrap();
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO users (user_id, email, lowercase_email, display_name, creation_date, uuid)
+                       VALUES ("bob4", "BOB@bob.com", "bob@bob.com", "", "1970-01-01 00:00:00", "7e5a1f0c-52a3-3b4a-8f5c-0c9d2a6e4b11")"#,
+            ))
+            .await
+            .expect_err("the lowercase emails should be unique");
+    }
+
     #[tokio::test]
     async fn test_too_high_version() {
         let sql_pool = get_in_memory_db().await;
@@ -568,4 +1154,85 @@ mod tests {
             .unwrap();
         assert!(init_table(&sql_pool).await.is_err());
     }
+
+    #[tokio::test]
+    async fn test_check_outdated_version() {
+        crate::infra::logging::init_for_tests();
+        let sql_pool = get_in_memory_db().await;
+        upgrade_to_v1(&sql_pool).await.unwrap();
+        assert_eq!(
+            get_pending_migration(&sql_pool).await,
+            Some(SchemaVersion(1))
+        );
+        assert!(check_or_init_table(&sql_pool, None, false).await.is_err());
+        migrate(&sql_pool, MigrationOptions::default(), true)
+            .await
+            .unwrap();
+        assert_eq!(
+            sql_migrations::get_schema_version(&sql_pool).await.unwrap(),
+            SchemaVersion(1)
+        );
+        check_or_init_table(&sql_pool, Some(MigrationOptions::default()), false)
+            .await
+            .unwrap();
+        assert_eq!(
+            sql_migrations::get_schema_version(&sql_pool).await.unwrap(),
+            LAST_SCHEMA_VERSION
+        );
+        assert_eq!(get_pending_migration(&sql_pool).await, None);
+        check_or_init_table(&sql_pool, None, false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check_unversioned_tables() {
+        let sql_pool = get_in_memory_db().await;
+        sql_pool
+            .execute(raw_statement(
+                r#"CREATE TABLE users ( user_id TEXT PRIMARY KEY, display_name TEXT, first_name TEXT NOT NULL, last_name TEXT, avatar BLOB, creation_date TEXT, email TEXT);"#,
+            ))
+            .await
+            .unwrap();
+        assert!(check_or_init_table(&sql_pool, None, false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_too_high_version() {
+        let sql_pool = get_in_memory_db().await;
+        init_table(&sql_pool).await.unwrap();
+        sql_pool
+            .execute(raw_statement(r#"UPDATE metadata SET version = 127"#))
+            .await
+            .unwrap();
+        assert!(
+            check_or_init_table(&sql_pool, Some(MigrationOptions::default()), false)
+                .await
+                .is_err()
+        );
+        check_or_init_table(&sql_pool, None, true).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check_damaged_schema() {
+        let sql_pool = get_in_memory_db().await;
+        init_table(&sql_pool).await.unwrap();
+        check_or_init_table(&sql_pool, None, false).await.unwrap();
+        sql_pool
+            .execute(raw_statement(r#"DROP TABLE user_object_classes"#))
+            .await
+            .unwrap();
+        let error = check_or_init_table(&sql_pool, None, false)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("table `user_object_classes`"), "{}", error);
+        check_or_init_table(&sql_pool, None, true).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_is_empty_database() {
+        let sql_pool = get_in_memory_db().await;
+        assert!(is_empty_database(&sql_pool).await);
+        init_table(&sql_pool).await.unwrap();
+        assert!(!is_empty_database(&sql_pool).await);
+    }
 }
@@ -1,107 +1,220 @@
 use crate::domain::{
     error::{DomainError, Result},
+    filter::{self, FilterExpr, Users},
     handler::{
-        CreateUserRequest, UpdateUserRequest, UserBackendHandler, UserListerBackendHandler,
-        UserRequestFilter,
+        CreateUserRequest, DeleteUserRequest, EmptiedGroup, EmptyGroupPolicy, UpdateUserRequest,
+        UserBackendHandler, UserListerBackendHandler, UserRequestFilter,
     },
-    model::{self, GroupColumn, UserColumn},
+    model::{self, GroupColumn, MembershipColumn, UserColumn},
+    read_cache::CacheKey,
+    read_model::Change,
     sql_backend_handler::SqlBackendHandler,
+    sql_tables::DbConnection,
     types::{
-        AttributeName, AttributeValue, GroupDetails, GroupId, Serialized, User, UserAndGroups,
-        UserId, Uuid,
+        AttributeName, AttributeType, AttributeValue, DeletedUser, Email, GroupDetails, GroupId,
+        Serialized, User, UserAndGroups, UserId, Uuid,
+    },
+    validation::{
+        normalize_email, normalize_hosts, normalize_language_tag, normalize_phone_number,
+        validate_email, validate_expiration_date, validate_host, validate_language_tag,
+        validate_length, validate_phone_number, validate_user_id, MAX_LOCALITY_LENGTH,
+        MAX_POSTAL_ADDRESS_LENGTH, MAX_USER_ID_LENGTH,
     },
 };
+use crate::infra::configuration::LoginIdentifier;
 use async_trait::async_trait;
 use sea_orm::{
-    sea_query::{
-        query::OnConflict, Alias, Cond, Expr, Func, IntoColumnRef, IntoCondition, SimpleExpr,
-    },
-    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseTransaction, EntityTrait, IntoActiveValue,
-    ModelTrait, QueryFilter, QueryOrder, QuerySelect, QueryTrait, Set, TransactionTrait,
+    sea_query::{query::OnConflict, Cond, Expr, IntoCondition, SelectStatement, SimpleExpr},
+    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseTransaction, EntityTrait,
+    IntoActiveValue, ModelTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, QueryTrait,
+    Set, TransactionTrait,
 };
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use tracing::instrument;
 
-fn attribute_condition(name: AttributeName, value: Serialized) -> Cond {
-    Expr::in_subquery(
-        Expr::col(UserColumn::UserId.as_column_ref()),
-        model::UserAttributes::find()
-            .select_only()
-            .column(model::UserAttributesColumn::UserId)
-            .filter(model::UserAttributesColumn::AttributeName.eq(name))
-            .filter(model::UserAttributesColumn::Value.eq(value))
-            .into_query(),
-    )
-    .into_condition()
+/// Marks the user as deleted, and closes its sessions. Fails if there is no such active user.
+async fn mark_user_deleted(transaction: &DatabaseTransaction, user_id: &UserId) -> Result<()> {
+    let res = model::User::update_many()
+        .col_expr(UserColumn::DeletedAt, Expr::value(chrono::Utc::now()))
+        .filter(UserColumn::UserId.eq(user_id))
+        .filter(UserColumn::DeletedAt.is_null())
+        .exec(transaction)
+        .await?;
+    if res.rows_affected == 0 {
+        return Err(DomainError::EntityNotFound(format!(
+            "No such user: '{}'",
+            user_id
+        )));
+    }
+    model::JwtRefreshStorage::delete_many()
+        .filter(model::JwtRefreshStorageColumn::UserId.eq(user_id))
+        .exec(transaction)
+        .await?;
+    // The sessions already open end with the account, like after a logout.
+    model::JwtStorage::update_many()
+        .col_expr(model::JwtStorageColumn::Blacklisted, Expr::value(true))
+        .filter(model::JwtStorageColumn::UserId.eq(user_id))
+        .exec(transaction)
+        .await?;
+    model::PasswordResetTokens::delete_many()
+        .filter(model::PasswordResetTokensColumn::UserId.eq(user_id))
+        .exec(transaction)
+        .await?;
+    Ok(())
 }
 
-fn user_id_subcondition(filter: Cond) -> Cond {
-    Expr::in_subquery(
-        Expr::col(UserColumn::UserId.as_column_ref()),
-        model::User::find()
-            .find_also_linked(model::memberships::UserToGroup)
+/// The groups that the user, if active, is the last active member of, sorted by name.
+async fn find_groups_emptied_by_deletion<C: ConnectionTrait>(
+    connection: &C,
+    user_id: &UserId,
+) -> Result<Vec<model::groups::Model>> {
+    let active_memberships = || {
+        model::Membership::find()
+            .inner_join(model::User)
+            .filter(UserColumn::DeletedAt.is_null())
             .select_only()
-            .column(UserColumn::UserId)
-            .filter(filter)
-            .into_query(),
-    )
-    .into_condition()
+            .column(MembershipColumn::GroupId)
+    };
+    Ok(model::Group::find()
+        .filter(
+            GroupColumn::GroupId.in_subquery(
+                active_memberships()
+                    .filter(MembershipColumn::UserId.eq(user_id))
+                    .into_query(),
+            ),
+        )
+        .filter(
+            GroupColumn::GroupId.not_in_subquery(
+                active_memberships()
+                    .filter(MembershipColumn::UserId.ne(user_id))
+                    .into_query(),
+            ),
+        )
+        .order_by_asc(GroupColumn::LowercaseDisplayName)
+        .all(connection)
+        .await?)
 }
 
-fn get_user_filter_expr(filter: UserRequestFilter) -> Cond {
-    use UserRequestFilter::*;
-    let group_table = Alias::new("r1");
-    fn get_repeated_filter(
-        fs: Vec<UserRequestFilter>,
-        condition: Cond,
-        default_value: bool,
-    ) -> Cond {
-        if fs.is_empty() {
-            SimpleExpr::Value(default_value.into()).into_condition()
-        } else {
-            fs.into_iter()
-                .map(get_user_filter_expr)
-                .fold(condition, Cond::add)
+/// The value of the lowercase email column: empty emails are stored as NULL, to be exempt from
+/// the uniqueness constraint.
+fn to_lowercase_email(email: &Email) -> Option<String> {
+    Some(email.as_str().to_lowercase()).filter(|e| !e.is_empty())
+}
+
+/// Fails with `EmailAlreadyInUse` if another user (including the deleted ones, until they are
+/// purged) has the same email, ignoring the case.
+async fn check_email_available<C: ConnectionTrait>(
+    connection: &C,
+    email: &Email,
+    user_id: &UserId,
+) -> Result<()> {
+    let lowercase_email = match to_lowercase_email(email) {
+        Some(e) => e,
+        None => return Ok(()),
+    };
+    let other_user = model::User::find()
+        .filter(UserColumn::LowercaseEmail.eq(lowercase_email))
+        .filter(UserColumn::UserId.ne(user_id))
+        .one(connection)
+        .await?;
+    match other_user {
+        Some(_) => Err(DomainError::EmailAlreadyInUse(email.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// The names of the user attributes of type `JpegPhoto`.
+fn photo_attribute_names() -> sea_orm::sea_query::SelectStatement {
+    model::UserAttributeSchema::find()
+        .select_only()
+        .column(model::UserAttributeSchemaColumn::AttributeName)
+        .filter(model::UserAttributeSchemaColumn::AttributeType.eq(AttributeType::JpegPhoto))
+        .into_query()
+}
+
+pub(crate) fn get_user_filter_expr(filter: UserRequestFilter) -> Cond {
+    filter::to_condition(FilterExpr::<Users>::from(filter))
+}
+
+/// Validates and normalizes the email. An empty email is kept: the user has no email.
+fn to_email(email: &Email) -> Result<Email> {
+    let email = normalize_email(email.as_str());
+    validate_email(&email)?;
+    Ok(email.into())
+}
+
+/// The start of the user IDs derived from an email: its local part, without the characters that
+/// are not allowed in the user IDs, and short enough to add a number.
+fn user_id_prefix_from_email(email: &str) -> String {
+    let local_part = email
+        .rsplit_once('@')
+        .map_or(email, |(local_part, _)| local_part);
+    let prefix = local_part
+        .chars()
+        .filter(|c| c.is_alphanumeric() || ['.', '_', '-'].contains(c))
+        .take(MAX_USER_ID_LENGTH - 4)
+        .collect::<String>()
+        .to_lowercase();
+    if prefix.is_empty() {
+        "user".to_owned()
+    } else {
+        prefix
+    }
+}
+
+/// Validates and normalizes the language tag. An empty tag is kept as is, to remove the language.
+fn to_language_tag(tag: Option<String>) -> Result<Option<String>> {
+    tag.map(|tag| {
+        if tag.is_empty() {
+            return Ok(tag);
         }
+        validate_language_tag(&tag)?;
+        Ok(normalize_language_tag(&tag))
+    })
+    .transpose()
+}
+
+/// Validates and normalizes the hosts, into the value of the hosts column.
+fn to_hosts_column(hosts: &[String]) -> Result<Option<String>> {
+    let hosts = normalize_hosts(hosts);
+    for host in &hosts {
+        validate_host(host)?;
     }
-    match filter {
-        And(fs) => get_repeated_filter(fs, Cond::all(), true),
-        Or(fs) => get_repeated_filter(fs, Cond::any(), false),
-        Not(f) => get_user_filter_expr(*f).not(),
-        UserId(user_id) => ColumnTrait::eq(&UserColumn::UserId, user_id).into_condition(),
-        Equality(column, value) => {
-            if column == UserColumn::UserId {
-                panic!("User id should be wrapped")
-            } else if column == UserColumn::Email {
-                ColumnTrait::eq(&UserColumn::LowercaseEmail, value.as_str().to_lowercase())
-                    .into_condition()
-            } else {
-                ColumnTrait::eq(&column, value).into_condition()
+    Ok(model::users::hosts_to_column(&hosts))
+}
+
+/// Validates and normalizes a phone number. An empty number is kept as is, to remove it.
+fn to_phone_number(phone: Option<String>, require_e164: bool) -> Result<Option<String>> {
+    phone
+        .map(|phone| {
+            if phone.is_empty() {
+                return Ok(phone);
             }
-        }
-        AttributeEquality(column, value) => attribute_condition(column, value),
-        MemberOf(group) => user_id_subcondition(
-            Expr::col((group_table, GroupColumn::LowercaseDisplayName))
-                .eq(group.as_str().to_lowercase())
-                .into_condition(),
-        ),
-        MemberOfId(group_id) => user_id_subcondition(
-            Expr::col((group_table, GroupColumn::GroupId))
-                .eq(group_id)
-                .into_condition(),
-        ),
-        UserIdSubString(filter) => UserColumn::UserId
-            .like(filter.to_sql_filter())
-            .into_condition(),
-        SubString(col, filter) => {
-            SimpleExpr::FunctionCall(Func::lower(Expr::col(col.as_column_ref())))
-                .like(filter.to_sql_filter())
-                .into_condition()
+            let phone = normalize_phone_number(&phone);
+            validate_phone_number(&phone, require_e164)?;
+            Ok(phone)
+        })
+        .transpose()
+}
+
+fn validate_address(
+    postal_address: &Option<String>,
+    locality: &Option<String>,
+    state_or_province: &Option<String>,
+) -> Result<()> {
+    for (field, value, max_length) in [
+        ("postal address", postal_address, MAX_POSTAL_ADDRESS_LENGTH),
+        ("locality", locality, MAX_LOCALITY_LENGTH),
+        ("state or province", state_or_province, MAX_LOCALITY_LENGTH),
+    ] {
+        if let Some(value) = value {
+            validate_length(field, value, max_length)?;
         }
     }
+    Ok(())
 }
 
-fn to_value(opt_name: &Option<String>) -> ActiveValue<Option<String>> {
+pub(crate) fn to_value(opt_name: &Option<String>) -> ActiveValue<Option<String>> {
     match opt_name {
         None => ActiveValue::NotSet,
         Some(name) => ActiveValue::Set(if name.is_empty() {
@@ -112,72 +225,364 @@ fn to_value(opt_name: &Option<String>) -> ActiveValue<Option<String>> {
     }
 }
 
+/// The value of the lowercase display name column, like `to_value` for the display name.
+fn to_lowercase_value(opt_name: &Option<String>) -> ActiveValue<Option<String>> {
+    to_value(&opt_name.as_ref().map(|name| name.to_lowercase()))
+}
+
+/// See `UserListerBackendHandler::list_users_page`.
+struct UserPage {
+    after: Option<UserId>,
+    limit: u64,
+}
+
+/// Restricts a query to the listed users. The users of a page are listed by ID rather than with
+/// a subquery: MySQL doesn't support a LIMIT in a subquery.
+fn of_listed_users<C: ColumnTrait>(
+    column: C,
+    page_user_ids: Option<&[UserId]>,
+    user_ids: impl FnOnce() -> SelectStatement,
+) -> SimpleExpr {
+    match page_user_ids {
+        Some(ids) => column.is_in(ids),
+        None => column.in_subquery(user_ids()),
+    }
+}
+
+/// Fetches the users with a fixed number of queries, whatever the number of users: their groups
+/// and attributes are each fetched for all the users at once.
+async fn fetch_users(
+    connection: &DbConnection,
+    filters: Option<UserRequestFilter>,
+    get_groups: bool,
+    page: Option<UserPage>,
+) -> Result<Vec<UserAndGroups>> {
+    let mut filters = Cond::all()
+        .add(
+            filters
+                .map(get_user_filter_expr)
+                .unwrap_or_else(|| SimpleExpr::Value(true.into()).into_condition()),
+        )
+        .add(UserColumn::DeletedAt.is_null());
+    if let Some(after) = page.as_ref().and_then(|p| p.after.clone()) {
+        filters = filters.add(UserColumn::UserId.gt(&after));
+    }
+    let user_ids = || {
+        model::User::find()
+            .filter(filters.clone())
+            .select_only()
+            .column(UserColumn::UserId)
+            .into_query()
+    };
+    let mut query = model::User::find()
+        .filter(filters.clone())
+        .order_by_asc(UserColumn::UserId);
+    if let Some(page) = &page {
+        query = query.limit(page.limit);
+    }
+    let mut users: Vec<_> = query
+        .all(connection)
+        .await?
+        .into_iter()
+        .map(|user| UserAndGroups {
+            user: user.into(),
+            groups: None,
+        })
+        .collect();
+    if users.is_empty() {
+        return Ok(users);
+    }
+    let page_user_ids = page.map(|_| {
+        users
+            .iter()
+            .map(|u| u.user.user_id.clone())
+            .collect::<Vec<_>>()
+    });
+    let page_user_ids = page_user_ids.as_deref();
+    use itertools::Itertools; // For take_while_ref
+    if get_groups {
+        let memberships = model::Membership::find()
+            .find_also_related(model::Group)
+            .filter(of_listed_users(
+                MembershipColumn::UserId,
+                page_user_ids,
+                user_ids,
+            ))
+            .order_by_asc(MembershipColumn::UserId)
+            .order_by_asc(GroupColumn::DisplayName)
+            .all(connection)
+            .await?;
+        let mut memberships_iter = memberships.into_iter().peekable();
+        for user in users.iter_mut() {
+            user.groups = Some(
+                memberships_iter
+                    .take_while_ref(|(membership, _)| membership.user_id == user.user.user_id)
+                    .filter_map(|(_, group)| group)
+                    .map(GroupDetails::from)
+                    .collect(),
+            );
+        }
+    }
+
+    // At this point, the users don't have attributes, we need to populate it with another query.
+    let attributes = model::UserAttributes::find()
+        .filter(of_listed_users(
+            model::UserAttributesColumn::UserId,
+            page_user_ids,
+            user_ids,
+        ))
+        .filter(model::UserAttributesColumn::AttributeName.not_in_subquery(photo_attribute_names()))
+        .order_by_asc(model::UserAttributesColumn::UserId)
+        .order_by_asc(model::UserAttributesColumn::AttributeName)
+        .all(connection)
+        .await?;
+    let mut attributes_iter = attributes.into_iter().peekable();
+    for user in users.iter_mut() {
+        user.user.attributes = attributes_iter
+            .take_while_ref(|u| u.user_id == user.user.user_id)
+            .map(AttributeValue::from)
+            .collect();
+    }
+    Ok(users)
+}
+
+/// The users with their groups, for the read model.
+pub(crate) async fn fetch_users_and_groups(
+    connection: &DbConnection,
+    filters: Option<UserRequestFilter>,
+) -> Result<Vec<UserAndGroups>> {
+    fetch_users(connection, filters, true, None).await
+}
+
+async fn fetch_deleted_users(connection: &DbConnection) -> Result<Vec<DeletedUser>> {
+    let mut users: Vec<_> = model::User::find()
+        .filter(UserColumn::DeletedAt.is_not_null())
+        .order_by_asc(UserColumn::UserId)
+        .all(connection)
+        .await?
+        .into_iter()
+        .map(|user| DeletedUser {
+            deletion_date: user.deleted_at.unwrap_or_default(),
+            user: user.into(),
+        })
+        .collect();
+    let attributes = model::UserAttributes::find()
+        .filter(
+            model::UserAttributesColumn::UserId.in_subquery(
+                model::User::find()
+                    .filter(UserColumn::DeletedAt.is_not_null())
+                    .select_only()
+                    .column(UserColumn::UserId)
+                    .into_query(),
+            ),
+        )
+        .filter(model::UserAttributesColumn::AttributeName.not_in_subquery(photo_attribute_names()))
+        .order_by_asc(model::UserAttributesColumn::UserId)
+        .order_by_asc(model::UserAttributesColumn::AttributeName)
+        .all(connection)
+        .await?;
+    let mut attributes_iter = attributes.into_iter().peekable();
+    use itertools::Itertools; // For take_while_ref
+    for deleted in users.iter_mut() {
+        deleted.user.attributes = attributes_iter
+            .take_while_ref(|u| u.user_id == deleted.user.user_id)
+            .map(AttributeValue::from)
+            .collect();
+    }
+    Ok(users)
+}
+
+async fn fetch_user_photos(
+    connection: &DbConnection,
+    user_ids: &[UserId],
+) -> Result<HashMap<UserId, Vec<AttributeValue>>> {
+    let mut photos = HashMap::<UserId, Vec<AttributeValue>>::new();
+    if user_ids.is_empty() {
+        return Ok(photos);
+    }
+    for attribute in model::UserAttributes::find()
+        .filter(model::UserAttributesColumn::UserId.is_in(user_ids))
+        .filter(model::UserAttributesColumn::AttributeName.in_subquery(photo_attribute_names()))
+        .order_by_asc(model::UserAttributesColumn::AttributeName)
+        .all(connection)
+        .await?
+    {
+        photos
+            .entry(attribute.user_id.clone())
+            .or_default()
+            .push(AttributeValue::from(attribute));
+    }
+    Ok(photos)
+}
+
 #[async_trait]
 impl UserListerBackendHandler for SqlBackendHandler {
     #[instrument(skip(self), level = "debug", ret, err)]
     async fn list_users(
         &self,
         filters: Option<UserRequestFilter>,
-        // To simplify the query, we always fetch groups. TODO: cleanup.
-        _get_groups: bool,
+        get_groups: bool,
     ) -> Result<Vec<UserAndGroups>> {
-        let filters = filters
-            .map(get_user_filter_expr)
-            .unwrap_or_else(|| SimpleExpr::Value(true.into()).into_condition());
-        let mut users: Vec<_> = model::User::find()
-            .filter(filters.clone())
+        if let Some(users) =
+            self.read_from_model(|model| model.list_users(filters.as_ref(), get_groups, None, None))
+        {
+            return Ok(users);
+        }
+        self.cached(CacheKey::Users(filters.clone(), get_groups), || async {
+            let _timer = self.time_query("list_users");
+            self.read(|connection| fetch_users(connection, filters.clone(), get_groups, None))
+                .await
+        })
+        .await
+    }
+
+    /// Not cached: the pages are for the large searches, that would push everything else out of
+    /// the cache.
+    #[instrument(skip(self), level = "debug", err)]
+    async fn list_users_page(
+        &self,
+        filters: Option<UserRequestFilter>,
+        get_groups: bool,
+        after: Option<UserId>,
+        limit: u64,
+    ) -> Result<Vec<UserAndGroups>> {
+        if let Some(users) = self.read_from_model(|model| {
+            model.list_users(filters.as_ref(), get_groups, after.as_ref(), Some(limit))
+        }) {
+            return Ok(users);
+        }
+        let _timer = self.time_query("list_users_page");
+        self.read(|connection| {
+            fetch_users(
+                connection,
+                filters.clone(),
+                get_groups,
+                Some(UserPage {
+                    after: after.clone(),
+                    limit,
+                }),
+            )
+        })
+        .await
+    }
+
+    #[instrument(skip(self), level = "debug", ret, err)]
+    async fn list_deleted_users(&self) -> Result<Vec<DeletedUser>> {
+        let _timer = self.time_query("list_deleted_users");
+        self.read(fetch_deleted_users).await
+    }
+
+    #[instrument(skip_all, level = "debug", err, fields(num_users = user_ids.len()))]
+    async fn get_user_photos(
+        &self,
+        user_ids: &[UserId],
+    ) -> Result<HashMap<UserId, Vec<AttributeValue>>> {
+        let _timer = self.time_query("get_user_photos");
+        self.read(|connection| fetch_user_photos(connection, user_ids))
+            .await
+    }
+}
+
+impl SqlBackendHandler {
+    /// The active users expiring between `now` and `until` who weren't warned yet, see
+    /// `mark_expiry_warning_sent`. Without their attributes.
+    pub async fn list_users_to_warn_of_expiration(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        until: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<User>> {
+        let _timer = self.time_query("list_users_to_warn_of_expiration");
+        Ok(model::User::find()
+            .filter(UserColumn::DeletedAt.is_null())
+            .filter(UserColumn::ExpiresAt.gt(now))
+            .filter(UserColumn::ExpiresAt.lte(until))
+            .filter(UserColumn::ExpiryWarningSentAt.is_null())
             .order_by_asc(UserColumn::UserId)
-            .find_with_linked(model::memberships::UserToGroup)
-            .order_by_asc(SimpleExpr::Column(
-                (Alias::new("r1"), GroupColumn::DisplayName).into_column_ref(),
-            ))
             .all(&self.sql_pool)
             .await?
             .into_iter()
-            .map(|(user, groups)| UserAndGroups {
-                user: user.into(),
-                groups: Some(groups.into_iter().map(Into::<GroupDetails>::into).collect()),
-            })
-            .collect();
+            .map(User::from)
+            .collect())
+    }
 
-        // At this point, the users don't have attributes, we need to populate it with another query.
-        let attributes = model::UserAttributes::find()
-            .filter(
-                model::UserAttributesColumn::UserId.in_subquery(
-                    model::User::find()
-                        .filter(filters)
-                        .select_only()
-                        .column(model::users::Column::UserId)
-                        .into_query(),
-                ),
+    /// Records that the user was warned of the expiration of their account, until the expiration
+    /// date changes. Returns false if they already were, e.g. by another instance.
+    pub async fn mark_expiry_warning_sent(&self, user_id: &UserId) -> Result<bool> {
+        let _timer = self.time_query("mark_expiry_warning_sent");
+        self.check_writable()?;
+        let res = model::User::update_many()
+            .col_expr(
+                UserColumn::ExpiryWarningSentAt,
+                Expr::value(chrono::Utc::now()),
             )
-            .order_by_asc(model::UserAttributesColumn::UserId)
-            .order_by_asc(model::UserAttributesColumn::AttributeName)
-            .all(&self.sql_pool)
+            .filter(UserColumn::UserId.eq(user_id))
+            .filter(UserColumn::ExpiryWarningSentAt.is_null())
+            .exec(&self.sql_pool)
             .await?;
-        let mut attributes_iter = attributes.into_iter().peekable();
-        use itertools::Itertools; // For take_while_ref
-        for user in users.iter_mut() {
-            user.user.attributes = attributes_iter
-                .take_while_ref(|u| u.user_id == user.user.user_id)
-                .map(AttributeValue::from)
-                .collect();
-        }
-        Ok(users)
+        Ok(res.rows_affected > 0)
+    }
+
+    /// The ID of a user created from their email: the local part, followed by the first number
+    /// that makes it unique ("alice", "alice2", "alice3"...). The IDs of the deleted users are
+    /// taken until they are purged.
+    async fn derive_user_id(&self, email: &Email) -> Result<UserId> {
+        let prefix = user_id_prefix_from_email(email.as_str());
+        let taken = model::User::find()
+            .filter(UserColumn::UserId.starts_with(&prefix))
+            .select_only()
+            .column(UserColumn::UserId)
+            .into_tuple::<(UserId,)>()
+            .all(&self.sql_pool)
+            .await?
+            .into_iter()
+            .map(|(user_id,)| user_id)
+            .collect::<HashSet<_>>();
+        Ok(std::iter::once(prefix.clone())
+            .chain((2..).map(|n| format!("{}{}", prefix, n)))
+            .map(|user_id| UserId::new(&user_id))
+            .find(|user_id| !taken.contains(user_id))
+            .expect("There is always a free user ID"))
+    }
+
+    /// Finds a user that is not marked as deleted.
+    async fn find_active_user(&self, user_id: &UserId) -> Result<model::users::Model> {
+        model::User::find_by_id(user_id.to_owned())
+            .filter(UserColumn::DeletedAt.is_null())
+            .one(&self.sql_pool)
+            .await?
+            .ok_or_else(|| DomainError::EntityNotFound(user_id.to_string()))
     }
-}
 
-impl SqlBackendHandler {
     async fn update_user_with_transaction(
         transaction: &DatabaseTransaction,
         request: UpdateUserRequest,
     ) -> Result<()> {
-        let lower_email = request.email.as_ref().map(|s| s.as_str().to_lowercase());
+        let email = request.email.as_ref().map(to_email).transpose()?;
+        if let Some(email) = &email {
+            check_email_available(transaction, email, &request.user_id).await?;
+        }
+        let preferred_language = to_language_tag(request.preferred_language)?;
+        let hosts = request.hosts.as_deref().map(to_hosts_column).transpose()?;
+        let lower_email = email.as_ref().map(to_lowercase_email);
         let update_user = model::users::ActiveModel {
             user_id: ActiveValue::Set(request.user_id.clone()),
-            email: request.email.map(ActiveValue::Set).unwrap_or_default(),
+            email: email.map(ActiveValue::Set).unwrap_or_default(),
             lowercase_email: lower_email.map(ActiveValue::Set).unwrap_or_default(),
             display_name: to_value(&request.display_name),
+            lowercase_display_name: to_lowercase_value(&request.display_name),
+            preferred_language: to_value(&preferred_language),
+            hosts: hosts.map(ActiveValue::Set).unwrap_or_default(),
+            phone: to_value(&request.phone),
+            mobile: to_value(&request.mobile),
+            postal_address: to_value(&request.postal_address),
+            locality: to_value(&request.locality),
+            state_or_province: to_value(&request.state_or_province),
+            expires_at: request.expires_at.map(ActiveValue::Set).unwrap_or_default(),
+            // A new expiration date gets a new warning.
+            expiry_warning_sent_at: request
+                .expires_at
+                .map(|_| ActiveValue::Set(None))
+                .unwrap_or_default(),
             ..Default::default()
         };
         let to_serialized_value = |s: &Option<String>| match s.as_ref().map(|s| s.as_str()) {
@@ -188,16 +593,16 @@ impl SqlBackendHandler {
         let mut update_user_attributes = Vec::new();
         let mut remove_user_attributes = Vec::new();
         let mut process_serialized =
-            |value: ActiveValue<Serialized>, attribute_name: AttributeName| match &value {
+            |value: ActiveValue<Serialized>, attribute_name: AttributeName| match value {
                 ActiveValue::NotSet => {
                     remove_user_attributes.push(attribute_name);
                 }
-                ActiveValue::Set(_) => {
-                    update_user_attributes.push(model::user_attributes::ActiveModel {
-                        user_id: Set(request.user_id.clone()),
-                        attribute_name: Set(attribute_name),
+                ActiveValue::Set(value) => {
+                    update_user_attributes.push(model::user_attributes::ActiveModel::new(
+                        request.user_id.clone(),
+                        attribute_name,
                         value,
-                    })
+                    ))
                 }
                 _ => unreachable!(),
             };
@@ -254,7 +659,10 @@ impl SqlBackendHandler {
                         model::UserAttributesColumn::UserId,
                         model::UserAttributesColumn::AttributeName,
                     ])
-                    .update_column(model::UserAttributesColumn::Value)
+                    .update_columns([
+                        model::UserAttributesColumn::Value,
+                        model::UserAttributesColumn::ValueHash,
+                    ])
                     .to_owned(),
                 )
                 .exec(transaction)
@@ -268,75 +676,134 @@ impl SqlBackendHandler {
 impl UserBackendHandler for SqlBackendHandler {
     #[instrument(skip_all, level = "debug", ret, fields(user_id = ?user_id.as_str()))]
     async fn get_user_details(&self, user_id: &UserId) -> Result<User> {
-        let mut user = User::from(
-            model::User::find_by_id(user_id.to_owned())
-                .one(&self.sql_pool)
-                .await?
-                .ok_or_else(|| DomainError::EntityNotFound(user_id.to_string()))?,
-        );
-        let attributes = model::UserAttributes::find()
-            .filter(model::UserAttributesColumn::UserId.eq(user_id))
-            .order_by_asc(model::UserAttributesColumn::AttributeName)
-            .all(&self.sql_pool)
-            .await?;
-        user.attributes = attributes.into_iter().map(AttributeValue::from).collect();
-        Ok(user)
+        self.cached(CacheKey::UserDetails(user_id.clone()), || async {
+            let _timer = self.time_query("get_user_details");
+            let mut user = User::from(self.find_active_user(user_id).await?);
+            let attributes = model::UserAttributes::find()
+                .filter(model::UserAttributesColumn::UserId.eq(user_id))
+                .order_by_asc(model::UserAttributesColumn::AttributeName)
+                .all(&self.sql_pool)
+                .await?;
+            user.attributes = attributes.into_iter().map(AttributeValue::from).collect();
+            Ok(user)
+        })
+        .await
     }
 
     #[instrument(skip_all, level = "debug", ret, err, fields(user_id = ?user_id.as_str()))]
     async fn get_user_groups(&self, user_id: &UserId) -> Result<HashSet<GroupDetails>> {
-        let user = model::User::find_by_id(user_id.to_owned())
+        if let Some(groups) = self.read_from_model(|model| model.get_user_groups(user_id)) {
+            return groups;
+        }
+        self.cached(CacheKey::UserGroups(user_id.clone()), || async {
+            let _timer = self.time_query("get_user_groups");
+            let user = self.find_active_user(user_id).await?;
+            Ok(HashSet::from_iter(
+                user.find_linked(model::memberships::UserToGroup)
+                    .all(&self.sql_pool)
+                    .await?
+                    .into_iter()
+                    .map(Into::<GroupDetails>::into),
+            ))
+        })
+        .await
+    }
+
+    #[instrument(skip_all, level = "debug", ret, err, fields(user_id = ?user_id.as_str()))]
+    async fn get_user_avatar_hash(&self, user_id: &UserId) -> Result<Option<String>> {
+        let _timer = self.time_query("get_user_avatar_hash");
+        Ok(model::UserAttributes::find()
+            .select_only()
+            .column(model::UserAttributesColumn::ValueHash)
+            .inner_join(model::User)
+            .filter(UserColumn::DeletedAt.is_null())
+            .filter(model::UserAttributesColumn::UserId.eq(user_id))
+            .filter(model::UserAttributesColumn::AttributeName.eq("avatar"))
+            .into_tuple::<Option<String>>()
             .one(&self.sql_pool)
             .await?
-            .ok_or_else(|| DomainError::EntityNotFound(user_id.to_string()))?;
-        Ok(HashSet::from_iter(
-            user.find_linked(model::memberships::UserToGroup)
-                .all(&self.sql_pool)
-                .await?
-                .into_iter()
-                .map(Into::<GroupDetails>::into),
-        ))
+            .flatten())
     }
 
     #[instrument(skip(self), level = "debug", err, fields(user_id = ?request.user_id.as_str()))]
-    async fn create_user(&self, request: CreateUserRequest) -> Result<()> {
-        let now = chrono::Utc::now().naive_utc();
+    async fn create_user(&self, request: CreateUserRequest) -> Result<UserId> {
+        let _timer = self.time_query("create_user");
+        self.check_writable()?;
+        let request = if request.user_id.as_str().is_empty()
+            && !request.email.as_str().is_empty()
+            && self.config.login_identifier == LoginIdentifier::Email
+        {
+            CreateUserRequest {
+                user_id: self.derive_user_id(&request.email).await?,
+                ..request
+            }
+        } else {
+            request
+        };
+        validate_user_id(request.user_id.as_str())?;
+        let user_id = request.user_id.clone();
+        let preferred_language = to_language_tag(request.preferred_language)?;
+        let hosts = to_hosts_column(&request.hosts)?;
+        let require_e164 = self.config.require_e164_phone_numbers;
+        let phone = to_phone_number(request.phone, require_e164)?;
+        let mobile = to_phone_number(request.mobile, require_e164)?;
+        validate_address(
+            &request.postal_address,
+            &request.locality,
+            &request.state_or_province,
+        )?;
+        if let Some(expires_at) = &request.expires_at {
+            validate_expiration_date(expires_at)?;
+        }
+        let now = chrono::Utc::now();
         let uuid = Uuid::from_name_and_date(request.user_id.as_str(), &now);
-        let lower_email = request.email.as_str().to_lowercase();
+        let email = to_email(&request.email)?;
+        let lower_email = to_lowercase_email(&email);
         let new_user = model::users::ActiveModel {
             user_id: Set(request.user_id.clone()),
-            email: Set(request.email),
+            user_id_as_entered: Set(Some(request.user_id.as_entered().to_owned())),
+            email: Set(email.clone()),
             lowercase_email: Set(lower_email),
             display_name: to_value(&request.display_name),
+            lowercase_display_name: to_lowercase_value(&request.display_name),
+            preferred_language: to_value(&preferred_language),
+            hosts: Set(hosts),
+            phone: to_value(&phone),
+            mobile: to_value(&mobile),
+            postal_address: to_value(&request.postal_address),
+            locality: to_value(&request.locality),
+            state_or_province: to_value(&request.state_or_province),
+            expires_at: Set(request.expires_at),
             creation_date: ActiveValue::Set(now),
             uuid: ActiveValue::Set(uuid),
             ..Default::default()
         };
         let mut new_user_attributes = Vec::new();
         if let Some(first_name) = request.first_name {
-            new_user_attributes.push(model::user_attributes::ActiveModel {
-                user_id: Set(request.user_id.clone()),
-                attribute_name: Set("first_name".into()),
-                value: Set(Serialized::from(&first_name)),
-            });
+            new_user_attributes.push(model::user_attributes::ActiveModel::new(
+                request.user_id.clone(),
+                "first_name".into(),
+                Serialized::from(&first_name),
+            ));
         }
         if let Some(last_name) = request.last_name {
-            new_user_attributes.push(model::user_attributes::ActiveModel {
-                user_id: Set(request.user_id.clone()),
-                attribute_name: Set("last_name".into()),
-                value: Set(Serialized::from(&last_name)),
-            });
+            new_user_attributes.push(model::user_attributes::ActiveModel::new(
+                request.user_id.clone(),
+                "last_name".into(),
+                Serialized::from(&last_name),
+            ));
         }
         if let Some(avatar) = request.avatar {
-            new_user_attributes.push(model::user_attributes::ActiveModel {
-                user_id: Set(request.user_id.clone()),
-                attribute_name: Set("avatar".into()),
-                value: Set(Serialized::from(&avatar)),
-            });
+            new_user_attributes.push(model::user_attributes::ActiveModel::new(
+                request.user_id.clone(),
+                "avatar".into(),
+                Serialized::from(&avatar),
+            ));
         }
         self.sql_pool
             .transaction::<_, (), DomainError>(|transaction| {
                 Box::pin(async move {
+                    check_email_available(transaction, &email, &request.user_id).await?;
                     let schema = Self::get_schema_with_transaction(transaction).await?;
                     for attribute in request.attributes {
                         if schema
@@ -344,11 +811,11 @@ impl UserBackendHandler for SqlBackendHandler {
                             .get_attribute_type(&attribute.name)
                             .is_some()
                         {
-                            new_user_attributes.push(model::user_attributes::ActiveModel {
-                                user_id: Set(request.user_id.clone()),
-                                attribute_name: Set(attribute.name),
-                                value: Set(attribute.value),
-                            });
+                            new_user_attributes.push(model::user_attributes::ActiveModel::new(
+                                request.user_id.clone(),
+                                attribute.name,
+                                attribute.value,
+                            ));
                         } else {
                             return Err(DomainError::InternalError(format!(
                                 "Attribute name {} doesn't exist in the user schema,
@@ -363,15 +830,57 @@ impl UserBackendHandler for SqlBackendHandler {
                             .exec(transaction)
                             .await?;
                     }
+                    // Any error from here on rolls back the user creation as well.
+                    let mut groups = request.groups;
+                    groups.sort();
+                    groups.dedup();
+                    if !groups.is_empty() {
+                        let existing_groups = model::Group::find()
+                            .filter(GroupColumn::GroupId.is_in(groups.iter().copied()))
+                            .count(transaction)
+                            .await?;
+                        if existing_groups != groups.len() as u64 {
+                            return Err(DomainError::EntityNotFound(format!(
+                                "No such group among {:?}",
+                                groups
+                            )));
+                        }
+                        model::Membership::insert_many(groups.into_iter().map(|group_id| {
+                            model::memberships::ActiveModel {
+                                user_id: Set(request.user_id.clone()),
+                                group_id: Set(group_id),
+                            }
+                        }))
+                        .exec(transaction)
+                        .await?;
+                    }
                     Ok(())
                 })
             })
             .await?;
-        Ok(())
+        self.after_write(Change::User(user_id.clone())).await;
+        Ok(user_id)
     }
 
     #[instrument(skip(self), level = "debug", err, fields(user_id = ?request.user_id.as_str()))]
     async fn update_user(&self, request: UpdateUserRequest) -> Result<()> {
+        let _timer = self.time_query("update_user");
+        self.check_writable()?;
+        let user_id = request.user_id.clone();
+        let require_e164 = self.config.require_e164_phone_numbers;
+        let request = UpdateUserRequest {
+            phone: to_phone_number(request.phone, require_e164)?,
+            mobile: to_phone_number(request.mobile, require_e164)?,
+            ..request
+        };
+        validate_address(
+            &request.postal_address,
+            &request.locality,
+            &request.state_or_province,
+        )?;
+        if let Some(Some(expires_at)) = &request.expires_at {
+            validate_expiration_date(expires_at)?;
+        }
         self.sql_pool
             .transaction::<_, (), DomainError>(|transaction| {
                 Box::pin(
@@ -379,11 +888,66 @@ impl UserBackendHandler for SqlBackendHandler {
                 )
             })
             .await?;
+        self.after_write(Change::User(user_id)).await;
         Ok(())
     }
 
     #[instrument(skip_all, level = "debug", err, fields(user_id = ?user_id.as_str()))]
     async fn delete_user(&self, user_id: &UserId) -> Result<()> {
+        let _timer = self.time_query("delete_user");
+        self.check_writable()?;
+        let change = Change::User(user_id.clone());
+        let user_id = user_id.clone();
+        self.sql_pool
+            .transaction::<_, (), DomainError>(|transaction| {
+                Box::pin(async move { mark_user_deleted(transaction, &user_id).await })
+            })
+            .await?;
+        self.after_write(change).await;
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug", err, fields(user_id = ?user_id.as_str()))]
+    async fn restore_user(&self, user_id: &UserId) -> Result<()> {
+        let _timer = self.time_query("restore_user");
+        self.check_writable()?;
+        let res = model::User::update_many()
+            .col_expr(
+                UserColumn::DeletedAt,
+                Expr::value(Option::<chrono::DateTime<chrono::Utc>>::None),
+            )
+            .filter(UserColumn::UserId.eq(user_id))
+            .filter(UserColumn::DeletedAt.is_not_null())
+            .exec(&self.sql_pool)
+            .await?;
+        if res.rows_affected == 0 {
+            return Err(DomainError::EntityNotFound(format!(
+                "No such deleted user: '{}'",
+                user_id
+            )));
+        }
+        // Otherwise an expired user would be deleted again right away.
+        model::User::update_many()
+            .col_expr(
+                UserColumn::ExpiresAt,
+                Expr::value(Option::<chrono::DateTime<chrono::Utc>>::None),
+            )
+            .col_expr(
+                UserColumn::ExpiryWarningSentAt,
+                Expr::value(Option::<chrono::DateTime<chrono::Utc>>::None),
+            )
+            .filter(UserColumn::UserId.eq(user_id))
+            .filter(UserColumn::ExpiresAt.lte(chrono::Utc::now()))
+            .exec(&self.sql_pool)
+            .await?;
+        self.after_write(Change::User(user_id.clone())).await;
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug", err, fields(user_id = ?user_id.as_str()))]
+    async fn permanently_delete_user(&self, user_id: &UserId) -> Result<()> {
+        let _timer = self.time_query("permanently_delete_user");
+        self.check_writable()?;
         let res = model::User::delete_by_id(user_id.clone())
             .exec(&self.sql_pool)
             .await?;
@@ -393,31 +957,157 @@ impl UserBackendHandler for SqlBackendHandler {
                 user_id
             )));
         }
+        self.after_write(Change::User(user_id.clone())).await;
         Ok(())
     }
 
+    #[instrument(skip_all, level = "debug", err, fields(user_id = ?request.user_id.as_str()))]
+    async fn delete_user_with_policy(
+        &self,
+        request: DeleteUserRequest,
+    ) -> Result<Vec<EmptiedGroup>> {
+        let _timer = self.time_query("delete_user_with_policy");
+        self.check_writable()?;
+        let change = Change::User(request.user_id.clone());
+        let emptied = self
+            .sql_pool
+            .transaction::<_, Vec<EmptiedGroup>, DomainError>(|transaction| {
+                Box::pin(async move {
+                    let DeleteUserRequest {
+                        user_id,
+                        permanent,
+                        empty_group_policy,
+                    } = request;
+                    let groups = find_groups_emptied_by_deletion(transaction, &user_id).await?;
+                    if groups
+                        .iter()
+                        .any(|g| g.lowercase_display_name == "lldap_admin")
+                    {
+                        return Err(DomainError::LastAdmin(user_id.to_string()));
+                    }
+                    if empty_group_policy == EmptyGroupPolicy::FailIfLastMember
+                        && !groups.is_empty()
+                    {
+                        return Err(DomainError::LastGroupMember(
+                            user_id.to_string(),
+                            groups.iter().map(|g| g.display_name.to_string()).collect(),
+                        ));
+                    }
+                    if permanent {
+                        let res = model::User::delete_by_id(user_id.clone())
+                            .exec(transaction)
+                            .await?;
+                        if res.rows_affected == 0 {
+                            return Err(DomainError::EntityNotFound(format!(
+                                "No such user: '{}'",
+                                user_id
+                            )));
+                        }
+                    } else {
+                        mark_user_deleted(transaction, &user_id).await?;
+                    }
+                    let deleted = empty_group_policy == EmptyGroupPolicy::DeleteEmpty;
+                    if deleted && !groups.is_empty() {
+                        model::Group::delete_many()
+                            .filter(GroupColumn::GroupId.is_in(groups.iter().map(|g| g.group_id)))
+                            .exec(transaction)
+                            .await?;
+                    }
+                    Ok(groups
+                        .into_iter()
+                        .map(|g| EmptiedGroup {
+                            group_id: g.group_id,
+                            display_name: g.display_name,
+                            deleted,
+                        })
+                        .collect())
+                })
+            })
+            .await?;
+        self.after_write(change).await;
+        for group in emptied.iter().filter(|g| g.deleted) {
+            self.after_write(Change::Group(group.group_id)).await;
+        }
+        Ok(emptied)
+    }
+
+    #[instrument(skip_all, level = "debug", ret, err, fields(user_id = ?user_id.as_str()))]
+    async fn get_groups_emptied_by_deletion(&self, user_id: &UserId) -> Result<Vec<GroupDetails>> {
+        let _timer = self.time_query("get_groups_emptied_by_deletion");
+        Ok(find_groups_emptied_by_deletion(&self.sql_pool, user_id)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
     #[instrument(skip_all, level = "debug", err, fields(user_id = ?user_id.as_str(), group_id))]
-    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()> {
+    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<bool> {
+        let _timer = self.time_query("add_user_to_group");
+        self.check_writable()?;
         let new_membership = model::memberships::ActiveModel {
             user_id: ActiveValue::Set(user_id.clone()),
             group_id: ActiveValue::Set(group_id),
         };
-        new_membership.insert(&self.sql_pool).await?;
-        Ok(())
+        let inserted = model::Membership::insert(new_membership)
+            .on_conflict(
+                OnConflict::columns([MembershipColumn::UserId, MembershipColumn::GroupId])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.sql_pool)
+            .await?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+        self.after_write(Change::User(user_id.clone())).await;
+        Ok(true)
     }
 
     #[instrument(skip_all, level = "debug", err, fields(user_id = ?user_id.as_str(), group_id))]
-    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()> {
-        let res = model::Membership::delete_by_id((user_id.clone(), group_id))
-            .exec(&self.sql_pool)
+    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<bool> {
+        let _timer = self.time_query("remove_user_from_group");
+        self.check_writable()?;
+        let member = user_id.clone();
+        let removed = self
+            .sql_pool
+            .transaction::<_, bool, DomainError>(|transaction| {
+                Box::pin(async move {
+                    let res = model::Membership::delete_by_id((member.clone(), group_id))
+                        .exec(transaction)
+                        .await?;
+                    if res.rows_affected == 0 {
+                        return Ok(false);
+                    }
+                    let is_admin_group = model::Group::find_by_id(group_id)
+                        .filter(GroupColumn::LowercaseDisplayName.eq("lldap_admin"))
+                        .count(transaction)
+                        .await?
+                        > 0;
+                    if is_admin_group {
+                        let remaining = model::Membership::find()
+                            .inner_join(model::User)
+                            .filter(UserColumn::DeletedAt.is_null())
+                            .filter(MembershipColumn::GroupId.eq(group_id))
+                            .count(transaction)
+                            .await?;
+                        let member_is_active = model::User::find_by_id(member.clone())
+                            .filter(UserColumn::DeletedAt.is_null())
+                            .count(transaction)
+                            .await?
+                            > 0;
+                        if remaining == 0 && member_is_active {
+                            return Err(DomainError::LastAdmin(member.to_string()));
+                        }
+                    }
+                    Ok(true)
+                })
+            })
             .await?;
-        if res.rows_affected == 0 {
-            return Err(DomainError::EntityNotFound(format!(
-                "No such membership: '{}' -> {:?}",
-                user_id, group_id
-            )));
+        if removed {
+            self.after_write(Change::User(user_id.clone())).await;
         }
-        Ok(())
+        Ok(removed)
     }
 }
 
@@ -425,10 +1115,11 @@ impl UserBackendHandler for SqlBackendHandler {
 mod tests {
     use super::*;
     use crate::domain::{
-        handler::SubStringFilter,
+        handler::{GroupListerBackendHandler, SubStringFilter},
         sql_backend_handler::tests::*,
         types::{JpegPhoto, UserColumn},
     };
+    use chrono::TimeZone;
     use pretty_assertions::{assert_eq, assert_ne};
 
     #[tokio::test]
@@ -463,6 +1154,94 @@ mod tests {
         assert_eq!(users, vec!["bob"]);
     }
 
+    #[tokio::test]
+    async fn test_list_users_display_name_filter_ignores_case() {
+        let fixture = TestFixture::new().await;
+        let users = get_user_names(
+            &fixture.handler,
+            Some(UserRequestFilter::Equality(
+                UserColumn::DisplayName,
+                "Display BOB".to_string(),
+            )),
+        )
+        .await;
+        assert_eq!(users, vec!["bob"]);
+        let users = get_user_names(
+            &fixture.handler,
+            Some(UserRequestFilter::SubString(
+                UserColumn::DisplayName,
+                SubStringFilter {
+                    initial: Some("DISPLAY P".to_owned()),
+                    any: vec![],
+                    final_: None,
+                },
+            )),
+        )
+        .await;
+        assert_eq!(users, vec!["patrick"]);
+    }
+
+    /// The SQLite query plan of the user list with this filter.
+    async fn query_plan(handler: &SqlBackendHandler, filter: UserRequestFilter) -> String {
+        use sea_orm::{DbBackend, Statement};
+        let query = model::User::find()
+            .filter(get_user_filter_expr(filter))
+            .build(DbBackend::Sqlite);
+        handler
+            .sql_pool
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                format!("EXPLAIN QUERY PLAN {}", query.sql),
+                query.values.map(|v| v.0).unwrap_or_default(),
+            ))
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.try_get::<String>("", "detail").unwrap())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[tokio::test]
+    async fn test_filters_use_the_lowercase_indexes() {
+        let fixture = TestFixture::new().await;
+        let prefix = |initial: &str| SubStringFilter {
+            initial: Some(initial.to_owned()),
+            any: vec![],
+            final_: None,
+        };
+        for (filter, index) in [
+            (
+                UserRequestFilter::Equality(UserColumn::DisplayName, "Display Bob".to_owned()),
+                "user-lower-display-name",
+            ),
+            (
+                UserRequestFilter::Equality(UserColumn::Email, "Bob@bob.bob".to_owned()),
+                "unique-user-lower-email",
+            ),
+            (
+                UserRequestFilter::SubString(UserColumn::DisplayName, prefix("Disp")),
+                "user-lower-display-name-prefix",
+            ),
+            (
+                UserRequestFilter::SubString(UserColumn::Email, prefix("Bo")),
+                "user-lower-email-prefix",
+            ),
+            (
+                UserRequestFilter::UserIdSubString(prefix("Bo")),
+                "user-id-prefix",
+            ),
+        ] {
+            let plan = query_plan(&fixture.handler, filter.clone()).await;
+            assert!(
+                plan.contains(&format!("USING INDEX {} (", index)),
+                "{:?}: {}",
+                filter,
+                plan
+            );
+        }
+    }
+
     #[tokio::test]
     async fn test_list_users_other_filter() {
         let fixture = TestFixture::new().await;
@@ -527,21 +1306,79 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_list_users_false_filter() {
+    async fn test_list_users_substring_filter_escapes_wildcards() {
         let fixture = TestFixture::new().await;
         let users = get_user_names(
             &fixture.handler,
-            Some(UserRequestFilter::Not(Box::new(UserRequestFilter::And(
-                vec![],
-            )))),
+            Some(UserRequestFilter::UserIdSubString(SubStringFilter {
+                initial: None,
+                any: vec!["%".to_owned()],
+                final_: None,
+            })),
         )
         .await;
         assert_eq!(users, Vec::<String>::new());
-    }
-
-    #[tokio::test]
-    async fn test_list_users_member_of() {
-        let fixture = TestFixture::new().await;
+        let users = get_user_names(
+            &fixture.handler,
+            Some(UserRequestFilter::SubString(
+                UserColumn::DisplayName,
+                SubStringFilter {
+                    initial: Some("_".to_owned()),
+                    any: vec![],
+                    final_: None,
+                },
+            )),
+        )
+        .await;
+        assert_eq!(users, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn test_list_users_false_filter() {
+        let fixture = TestFixture::new().await;
+        let users = get_user_names(
+            &fixture.handler,
+            Some(UserRequestFilter::Not(Box::new(UserRequestFilter::And(
+                vec![],
+            )))),
+        )
+        .await;
+        assert_eq!(users, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn test_list_users_last_login_before() {
+        let fixture = TestFixture::new().await;
+        let date = |d: u32| chrono::Utc.with_ymd_and_hms(2023, 1, d, 0, 0, 0).unwrap();
+        for (user, last_login) in [("bob", date(1)), ("patrick", date(20))] {
+            model::User::update_many()
+                .col_expr(UserColumn::LastLogin, Expr::value(last_login))
+                .col_expr(UserColumn::PasswordChangedAt, Expr::value(last_login))
+                .filter(UserColumn::UserId.eq(user))
+                .exec(&fixture.handler.sql_pool)
+                .await
+                .unwrap();
+        }
+        let users = get_user_names(
+            &fixture.handler,
+            Some(UserRequestFilter::LastLoginBefore(date(10))),
+        )
+        .await;
+        // Users who never logged in are included.
+        assert_eq!(users, vec!["bob", "john", "nogroup"]);
+        let users = get_user_names(
+            &fixture.handler,
+            Some(UserRequestFilter::Not(Box::new(
+                UserRequestFilter::PasswordChangedBefore(date(10)),
+            ))),
+        )
+        .await;
+        assert_eq!(users, vec!["patrick"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_member_of() {
+        let fixture = TestFixture::new().await;
         let users = get_user_names(
             &fixture.handler,
             Some(UserRequestFilter::MemberOf("Best Group".into())),
@@ -716,6 +1553,35 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_list_users_query_count_does_not_depend_on_users() {
+        let mut fixture = TestFixture::new().await;
+        let queries = count_queries(&mut fixture.handler.sql_pool);
+        assert_eq!(
+            fixture.handler.list_users(None, true).await.unwrap().len(),
+            4
+        );
+        let few_users_queries = queries.load(Ordering::Relaxed);
+        assert!(few_users_queries <= 3, "{} queries", few_users_queries);
+
+        for i in 0..20 {
+            let user = format!("user{}", i);
+            insert_user_no_password(&fixture.handler, &user).await;
+            insert_membership(&fixture.handler, fixture.groups[i % 2], &user).await;
+        }
+        queries.store(0, Ordering::Relaxed);
+        let users = fixture.handler.list_users(None, true).await.unwrap();
+        assert_eq!(users.len(), 24);
+        assert!(users.iter().all(|u| u.groups.is_some()));
+        assert_eq!(queries.load(Ordering::Relaxed), few_users_queries);
+
+        // Without the groups, their query is skipped.
+        queries.store(0, Ordering::Relaxed);
+        let users = fixture.handler.list_users(None, false).await.unwrap();
+        assert!(users.iter().all(|u| u.groups.is_none()));
+        assert_eq!(queries.load(Ordering::Relaxed), few_users_queries - 1);
+    }
+
     #[tokio::test]
     async fn test_list_users_groups_have_different_creation_date_than_users() {
         let fixture = TestFixture::new().await;
@@ -766,6 +1632,8 @@ mod tests {
         {
             let user = handler.get_user_details(&UserId::new("bOb")).await.unwrap();
             assert_eq!(user.user_id.as_str(), "bob");
+            // Stored as entered, for display.
+            assert_eq!(user.user_id.as_entered(), "Bob");
         }
         {
             handler
@@ -808,6 +1676,117 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_delete_user_blacklists_jwts() {
+        use crate::infra::tcp_backend_handler::TcpBackendHandler;
+        let fixture = TestFixture::new().await;
+        let expiry = chrono::Utc::now() + chrono::Duration::days(1);
+        for (user, jwt_hash) in [("bob", 1), ("bob", 2), ("patrick", 3)] {
+            fixture
+                .handler
+                .register_jwt(&UserId::new(user), jwt_hash, expiry)
+                .await
+                .unwrap();
+        }
+        fixture
+            .handler
+            .delete_user(&UserId::new("bob"))
+            .await
+            .unwrap();
+        assert_eq!(
+            fixture.handler.get_jwt_blacklist().await.unwrap(),
+            [1, 2].into_iter().collect()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_deleted_user() {
+        use crate::domain::handler::GroupListerBackendHandler;
+        let fixture = TestFixture::new().await;
+        let bob = UserId::new("bob");
+        fixture.handler.delete_user(&bob).await.unwrap();
+        fixture
+            .handler
+            .get_user_details(&bob)
+            .await
+            .expect_err("Deleted users should be hidden");
+        let best_group = fixture
+            .handler
+            .list_groups(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|g| g.id == fixture.groups[0])
+            .unwrap();
+        assert_eq!(best_group.users, vec![UserId::new("patrick")]);
+        // The ID can't be reused until the user is purged.
+        fixture
+            .handler
+            .create_user(CreateUserRequest {
+                user_id: bob.clone(),
+                email: "new_bob@bob.bob".into(),
+                ..Default::default()
+            })
+            .await
+            .expect_err("Should have failed");
+
+        let deleted_users = fixture.handler.list_deleted_users().await.unwrap();
+        assert_eq!(deleted_users.len(), 1);
+        assert_eq!(deleted_users[0].user.user_id, bob);
+        assert_eq!(deleted_users[0].user.attributes.len(), 2);
+
+        fixture.handler.restore_user(&bob).await.unwrap();
+        assert_eq!(
+            get_user_names(&fixture.handler, None).await,
+            vec!["bob", "john", "nogroup", "patrick"]
+        );
+        assert_eq!(
+            get_user_names(
+                &fixture.handler,
+                Some(UserRequestFilter::MemberOfId(fixture.groups[0]))
+            )
+            .await,
+            vec!["bob", "patrick"]
+        );
+        assert!(fixture
+            .handler
+            .list_deleted_users()
+            .await
+            .unwrap()
+            .is_empty());
+        fixture
+            .handler
+            .restore_user(&bob)
+            .await
+            .expect_err("The user is not deleted anymore");
+    }
+
+    #[tokio::test]
+    async fn test_permanently_delete_user() {
+        let fixture = TestFixture::new().await;
+        let bob = UserId::new("bob");
+        fixture.handler.delete_user(&bob).await.unwrap();
+        fixture.handler.permanently_delete_user(&bob).await.unwrap();
+        assert!(fixture
+            .handler
+            .list_deleted_users()
+            .await
+            .unwrap()
+            .is_empty());
+        fixture
+            .handler
+            .restore_user(&bob)
+            .await
+            .expect_err("Should have failed");
+        insert_user_no_password(&fixture.handler, "bob").await;
+        assert!(fixture
+            .handler
+            .get_user_groups(&bob)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
     #[tokio::test]
     async fn test_get_user_groups() {
         let fixture = TestFixture::new().await;
@@ -831,6 +1810,137 @@ mod tests {
         assert_eq!(get_group_ids("nogroup").await, vec![]);
     }
 
+    #[tokio::test]
+    async fn test_update_user_preferred_language() {
+        let fixture = TestFixture::new().await;
+        let update = |language: &str| UpdateUserRequest {
+            user_id: UserId::new("bob"),
+            preferred_language: Some(language.to_owned()),
+            ..Default::default()
+        };
+        fixture.handler.update_user(update("de")).await.unwrap();
+        let error = fixture
+            .handler
+            .update_user(update("german"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, DomainError::ValidationError(_)),
+            "{}",
+            error
+        );
+        let language = || async {
+            fixture
+                .handler
+                .get_user_details(&UserId::new("bob"))
+                .await
+                .unwrap()
+                .preferred_language
+        };
+        assert_eq!(language().await.as_deref(), Some("de"));
+        // An empty string removes it.
+        fixture.handler.update_user(update("")).await.unwrap();
+        assert_eq!(language().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_update_user_hosts() {
+        let fixture = TestFixture::new().await;
+        let update = |hosts: &[&str]| UpdateUserRequest {
+            user_id: UserId::new("bob"),
+            hosts: Some(hosts.iter().map(|host| host.to_string()).collect()),
+            ..Default::default()
+        };
+        let hosts = || async {
+            fixture
+                .handler
+                .get_user_details(&UserId::new("bob"))
+                .await
+                .unwrap()
+                .hosts
+        };
+        fixture
+            .handler
+            .update_user(update(&["Web01", "db02", "web01"]))
+            .await
+            .unwrap();
+        assert_eq!(hosts().await, vec!["db02".to_owned(), "web01".to_owned()]);
+        let error = fixture
+            .handler
+            .update_user(update(&["web01,db02"]))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, DomainError::ValidationError(_)),
+            "{}",
+            error
+        );
+        // Not setting the hosts keeps them.
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                display_name: Some("Bob".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(hosts().await, vec!["db02".to_owned(), "web01".to_owned()]);
+        // An empty list removes them.
+        fixture.handler.update_user(update(&[])).await.unwrap();
+        assert!(hosts().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_update_user_phone_number() {
+        let fixture = TestFixture::new().await;
+        let update = |phone: &str| UpdateUserRequest {
+            user_id: UserId::new("bob"),
+            phone: Some(phone.to_owned()),
+            ..Default::default()
+        };
+        let phone = || async {
+            fixture
+                .handler
+                .get_user_details(&UserId::new("bob"))
+                .await
+                .unwrap()
+                .phone
+        };
+        fixture
+            .handler
+            .update_user(update("030 / 123.45.67"))
+            .await
+            .unwrap();
+        assert_eq!(phone().await.as_deref(), Some("0301234567"));
+        let error = fixture
+            .handler
+            .update_user(update("call me"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, DomainError::ValidationError(_)),
+            "{}",
+            error
+        );
+        // With E.164, only the international numbers are accepted.
+        let mut config = get_default_config();
+        config.require_e164_phone_numbers = true;
+        let e164_handler = SqlBackendHandler::new(config, fixture.handler.sql_pool.clone());
+        e164_handler
+            .update_user(update("030 1234567"))
+            .await
+            .unwrap_err();
+        e164_handler
+            .update_user(update("+49 30 1234567"))
+            .await
+            .unwrap();
+        assert_eq!(phone().await.as_deref(), Some("+49301234567"));
+        // An empty string removes it.
+        fixture.handler.update_user(update("")).await.unwrap();
+        assert_eq!(phone().await, None);
+    }
+
     #[tokio::test]
     async fn test_update_user_all_values() {
         let fixture = TestFixture::new().await;
@@ -839,11 +1949,21 @@ mod tests {
             .handler
             .update_user(UpdateUserRequest {
                 user_id: UserId::new("bob"),
-                email: Some("email".into()),
+                email: Some("bob@example.com".into()),
                 display_name: Some("display_name".to_string()),
                 first_name: Some("first_name".to_string()),
                 last_name: Some("last_name".to_string()),
                 avatar: Some(JpegPhoto::for_tests()),
+                preferred_language: Some("FR-ca".to_string()),
+                hosts: Some(vec!["Web01".to_string()]),
+                phone: Some("+1 (555) 010-9999".to_string()),
+                mobile: Some("+1 555 010 8888".to_string()),
+                postal_address: Some("1 Main Street\nSpringfield".to_string()),
+                locality: Some("Springfield".to_string()),
+                state_or_province: Some("Oregon".to_string()),
+                expires_at: Some(Some(
+                    chrono::Utc.with_ymd_and_hms(2100, 1, 1, 0, 0, 0).unwrap(),
+                )),
                 delete_attributes: Vec::new(),
                 insert_attributes: Vec::new(),
             })
@@ -855,8 +1975,22 @@ mod tests {
             .get_user_details(&UserId::new("bob"))
             .await
             .unwrap();
-        assert_eq!(user.email, "email".into());
+        assert_eq!(user.email, "bob@example.com".into());
         assert_eq!(user.display_name.unwrap(), "display_name");
+        assert_eq!(user.preferred_language.as_deref(), Some("fr-CA"));
+        assert_eq!(user.hosts, vec!["web01".to_owned()]);
+        assert_eq!(user.phone.as_deref(), Some("+15550109999"));
+        assert_eq!(user.mobile.as_deref(), Some("+15550108888"));
+        assert_eq!(
+            user.postal_address.as_deref(),
+            Some("1 Main Street\nSpringfield")
+        );
+        assert_eq!(user.locality.as_deref(), Some("Springfield"));
+        assert_eq!(user.state_or_province.as_deref(), Some("Oregon"));
+        assert_eq!(
+            user.expires_at,
+            Some(chrono::Utc.with_ymd_and_hms(2100, 1, 1, 0, 0, 0).unwrap())
+        );
         assert_eq!(
             user.attributes,
             vec![
@@ -1067,6 +2201,136 @@ mod tests {
         assert!(!user.attributes.contains(&avatar));
     }
 
+    #[tokio::test]
+    async fn test_avatar_hash() {
+        let fixture = TestFixture::new().await;
+        let bob = UserId::new("bob");
+        let get_hash = || fixture.handler.get_user_avatar_hash(&bob);
+        assert_eq!(get_hash().await.unwrap(), None);
+
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: bob.clone(),
+                avatar: Some(JpegPhoto::for_tests()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let hash = get_hash().await.unwrap();
+        assert_eq!(
+            hash,
+            Some(Serialized::from(&JpegPhoto::for_tests()).content_hash())
+        );
+
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: bob.clone(),
+                insert_attributes: vec![AttributeValue {
+                    name: "avatar".into(),
+                    value: Serialized::from("another picture"),
+                }],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let new_hash = get_hash().await.unwrap();
+        assert_eq!(
+            new_hash,
+            Some(Serialized::from("another picture").content_hash())
+        );
+        assert_ne!(new_hash, hash);
+
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: bob.clone(),
+                avatar: Some(JpegPhoto::null()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(get_hash().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_without_photos() {
+        let fixture = TestFixture::new().await;
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                avatar: Some(JpegPhoto::for_tests()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let users = fixture.handler.list_users(None, false).await.unwrap();
+        let bob = users
+            .iter()
+            .find(|u| u.user.user_id == UserId::new("bob"))
+            .unwrap();
+        assert!(bob
+            .user
+            .attributes
+            .iter()
+            .all(|a| a.name != AttributeName::from("avatar")));
+
+        let photos = fixture
+            .handler
+            .get_user_photos(&[UserId::new("bob"), UserId::new("patrick")])
+            .await
+            .unwrap();
+        assert_eq!(
+            photos,
+            HashMap::from([(
+                UserId::new("bob"),
+                vec![AttributeValue {
+                    name: "avatar".into(),
+                    value: Serialized::from(&JpegPhoto::for_tests()),
+                }]
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_users_query_skips_photos() {
+        let mut fixture = TestFixture::new().await;
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                avatar: Some(JpegPhoto::for_tests()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let queries = record_queries(&mut fixture.handler.sql_pool);
+        fetch_users(&fixture.handler.sql_pool, None, true, None)
+            .await
+            .unwrap();
+        let queries = queries.lock().unwrap().clone();
+        let attribute_queries = queries
+            .iter()
+            .filter(|q| q.contains(r#"FROM "user_attributes""#))
+            .collect::<Vec<_>>();
+        assert_eq!(attribute_queries.len(), 1, "{:#?}", queries);
+        // The photos are excluded by the query itself, not after loading them.
+        assert!(
+            attribute_queries[0].contains(r#""user_attribute_name" NOT IN (SELECT"#)
+                && attribute_queries[0].contains(r#""attribute_type" = 'JpegPhoto'"#),
+            "{}",
+            attribute_queries[0]
+        );
+        assert!(
+            queries.iter().all(|q| !q.contains("avatar")),
+            "{:#?}",
+            queries
+        );
+    }
+
     #[tokio::test]
     async fn test_create_user_all_values() {
         let fixture = TestFixture::new().await;
@@ -1075,15 +2339,24 @@ mod tests {
             .handler
             .create_user(CreateUserRequest {
                 user_id: UserId::new("james"),
-                email: "email".into(),
+                email: "james@example.com".into(),
                 display_name: Some("display_name".to_string()),
                 first_name: None,
                 last_name: Some("last_name".to_string()),
                 avatar: Some(JpegPhoto::for_tests()),
+                preferred_language: None,
+                hosts: Vec::new(),
+                phone: Some("555-0100".to_string()),
+                mobile: None,
+                postal_address: None,
+                locality: Some("Springfield".to_string()),
+                state_or_province: None,
+                expires_at: None,
                 attributes: vec![AttributeValue {
                     name: "first_name".into(),
                     value: Serialized::from("First Name"),
                 }],
+                groups: Vec::new(),
             })
             .await
             .unwrap();
@@ -1093,8 +2366,10 @@ mod tests {
             .get_user_details(&UserId::new("james"))
             .await
             .unwrap();
-        assert_eq!(user.email, "email".into());
+        assert_eq!(user.email, "james@example.com".into());
         assert_eq!(user.display_name.unwrap(), "display_name");
+        assert_eq!(user.phone.as_deref(), Some("5550100"));
+        assert_eq!(user.locality.as_deref(), Some("Springfield"));
         assert_eq!(
             user.attributes,
             vec![
@@ -1145,21 +2420,341 @@ mod tests {
             .expect_err("Should have failed");
     }
 
+    /// Makes john the last active member of three groups: bob, the other member of "Shared", is
+    /// deleted.
+    async fn insert_groups_of_john(handler: &SqlBackendHandler) -> Vec<GroupId> {
+        let groups = vec![
+            insert_group(handler, "Shared").await,
+            insert_group(handler, "Solo A").await,
+            insert_group(handler, "Solo B").await,
+        ];
+        for group in &groups {
+            insert_membership(handler, *group, "john").await;
+        }
+        insert_membership(handler, groups[0], "bob").await;
+        handler.delete_user(&UserId::new("bob")).await.unwrap();
+        groups
+    }
+
+    async fn get_group_names(handler: &SqlBackendHandler) -> Vec<String> {
+        handler
+            .list_groups(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|g| g.display_name.to_string())
+            .collect()
+    }
+
+    fn delete_john(empty_group_policy: EmptyGroupPolicy) -> DeleteUserRequest {
+        DeleteUserRequest {
+            user_id: UserId::new("john"),
+            permanent: false,
+            empty_group_policy,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_keep_empty_groups() {
+        let fixture = TestFixture::new().await;
+        let groups = insert_groups_of_john(&fixture.handler).await;
+        assert_eq!(
+            fixture
+                .handler
+                .get_groups_emptied_by_deletion(&UserId::new("john"))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|g| g.group_id)
+                .collect::<Vec<_>>(),
+            groups
+        );
+        let emptied = fixture
+            .handler
+            .delete_user_with_policy(delete_john(EmptyGroupPolicy::Keep))
+            .await
+            .unwrap();
+        assert_eq!(
+            emptied,
+            vec![
+                EmptiedGroup {
+                    group_id: groups[0],
+                    display_name: "Shared".into(),
+                    deleted: false,
+                },
+                EmptiedGroup {
+                    group_id: groups[1],
+                    display_name: "Solo A".into(),
+                    deleted: false,
+                },
+                EmptiedGroup {
+                    group_id: groups[2],
+                    display_name: "Solo B".into(),
+                    deleted: false,
+                },
+            ]
+        );
+        assert_eq!(
+            get_user_names(&fixture.handler, None).await,
+            vec!["nogroup", "patrick"]
+        );
+        assert_eq!(
+            get_group_names(&fixture.handler).await,
+            vec![
+                "Best Group",
+                "Empty Group",
+                "Shared",
+                "Solo A",
+                "Solo B",
+                "Worst Group"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_delete_empty_groups() {
+        let fixture = TestFixture::new().await;
+        insert_groups_of_john(&fixture.handler).await;
+        let emptied = fixture
+            .handler
+            .delete_user_with_policy(delete_john(EmptyGroupPolicy::DeleteEmpty))
+            .await
+            .unwrap();
+        assert_eq!(emptied.len(), 3);
+        assert!(emptied.iter().all(|g| g.deleted));
+        // "Worst Group" still has patrick, "Empty Group" wasn't emptied by the deletion.
+        assert_eq!(
+            get_group_names(&fixture.handler).await,
+            vec!["Best Group", "Empty Group", "Worst Group"]
+        );
+        assert_eq!(
+            get_user_names(&fixture.handler, None).await,
+            vec!["nogroup", "patrick"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_fail_if_last_member() {
+        let fixture = TestFixture::new().await;
+        insert_groups_of_john(&fixture.handler).await;
+        let err = fixture
+            .handler
+            .delete_user_with_policy(delete_john(EmptyGroupPolicy::FailIfLastMember))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, DomainError::LastGroupMember(user, groups)
+                if user == "john" && groups == &["Shared", "Solo A", "Solo B"]),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            get_user_names(&fixture.handler, None).await,
+            vec!["john", "nogroup", "patrick"]
+        );
+        assert_eq!(get_group_names(&fixture.handler).await.len(), 6);
+        // Without groups to empty, the deletion goes through.
+        assert_eq!(
+            fixture
+                .handler
+                .delete_user_with_policy(DeleteUserRequest {
+                    user_id: UserId::new("nogroup"),
+                    permanent: true,
+                    empty_group_policy: EmptyGroupPolicy::FailIfLastMember,
+                })
+                .await
+                .unwrap(),
+            Vec::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_last_admin_whatever_the_policy() {
+        let fixture = TestFixture::new().await;
+        insert_groups_of_john(&fixture.handler).await;
+        let admin_group = insert_group(&fixture.handler, "lldap_admin").await;
+        insert_membership(&fixture.handler, admin_group, "john").await;
+        for policy in [
+            EmptyGroupPolicy::Keep,
+            EmptyGroupPolicy::DeleteEmpty,
+            EmptyGroupPolicy::FailIfLastMember,
+        ] {
+            let err = fixture
+                .handler
+                .delete_user_with_policy(delete_john(policy))
+                .await
+                .unwrap_err();
+            assert!(matches!(&err, DomainError::LastAdmin(user) if user == "john"));
+        }
+        assert_eq!(get_group_names(&fixture.handler).await.len(), 7);
+        insert_membership(&fixture.handler, admin_group, "patrick").await;
+        assert_eq!(
+            fixture
+                .handler
+                .delete_user_with_policy(delete_john(EmptyGroupPolicy::DeleteEmpty))
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(get_group_names(&fixture.handler)
+            .await
+            .contains(&"lldap_admin".to_owned()));
+    }
+
     #[tokio::test]
     async fn test_remove_user_from_group_not_found() {
         let fixture = TestFixture::new().await;
 
-        fixture
+        assert!(!fixture
             .handler
             .remove_user_from_group(&UserId::new("not found"), fixture.groups[0])
             .await
-            .expect_err("Should have failed");
+            .unwrap());
 
-        fixture
+        assert!(!fixture
             .handler
             .remove_user_from_group(&UserId::new("not found"), GroupId(16242))
             .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_membership_changes_are_idempotent() {
+        let fixture = TestFixture::new().await;
+        let handler = &fixture.handler;
+        let (bob, group) = (UserId::new("bob"), fixture.groups[1]);
+        let members = || get_user_names(handler, Some(UserRequestFilter::MemberOfId(group)));
+
+        // Add, absent.
+        assert!(handler.add_user_to_group(&bob, group).await.unwrap());
+        assert_eq!(members().await, vec!["bob", "john", "patrick"]);
+        // Add, present.
+        assert!(!handler.add_user_to_group(&bob, group).await.unwrap());
+        assert_eq!(members().await, vec!["bob", "john", "patrick"]);
+        // Remove, present.
+        assert!(handler.remove_user_from_group(&bob, group).await.unwrap());
+        assert_eq!(members().await, vec!["john", "patrick"]);
+        // Remove, absent.
+        assert!(!handler.remove_user_from_group(&bob, group).await.unwrap());
+        assert_eq!(members().await, vec!["john", "patrick"]);
+    }
+
+    #[tokio::test]
+    async fn test_remove_last_admin() {
+        let fixture = TestFixture::new().await;
+        let handler = &fixture.handler;
+        let admin_group = insert_group(handler, "lldap_admin").await;
+        insert_membership(handler, admin_group, "bob").await;
+        insert_membership(handler, admin_group, "patrick").await;
+        insert_membership(handler, admin_group, "john").await;
+        handler.delete_user(&UserId::new("john")).await.unwrap();
+
+        assert!(handler
+            .remove_user_from_group(&UserId::new("patrick"), admin_group)
+            .await
+            .unwrap());
+        // John is deleted: Bob is the last active admin.
+        let err = handler
+            .remove_user_from_group(&UserId::new("bob"), admin_group)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, DomainError::LastAdmin(user) if user == "bob"),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            err.to_string(),
+            "Cannot remove `bob` from lldap_admin: it is the last active admin"
+        );
+        assert_eq!(
+            get_user_names(handler, Some(UserRequestFilter::MemberOfId(admin_group))).await,
+            vec!["bob"]
+        );
+        // The deleted users can still be removed.
+        assert!(handler
+            .remove_user_from_group(&UserId::new("john"), admin_group)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_create_user_with_groups() {
+        let fixture = TestFixture::new().await;
+
+        fixture
+            .handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("james"),
+                email: "james@bob.bob".into(),
+                groups: vec![fixture.groups[0], fixture.groups[1], fixture.groups[0]],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let mut groups = fixture
+            .handler
+            .get_user_groups(&UserId::new("james"))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|g| g.group_id)
+            .collect::<Vec<_>>();
+        groups.sort();
+        assert_eq!(groups, vec![fixture.groups[0], fixture.groups[1]]);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_rolls_back_on_error() {
+        let fixture = TestFixture::new().await;
+
+        // The user and its attributes are inserted before the group check fails.
+        fixture
+            .handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("james"),
+                email: "james@bob.bob".into(),
+                first_name: Some("James".to_owned()),
+                groups: vec![fixture.groups[0], GroupId(16242)],
+                ..Default::default()
+            })
+            .await
             .expect_err("Should have failed");
+
+        fixture
+            .handler
+            .get_user_details(&UserId::new("james"))
+            .await
+            .expect_err("The user should not exist");
+        assert_eq!(
+            model::UserAttributes::find()
+                .filter(model::UserAttributesColumn::UserId.eq(UserId::new("james")))
+                .count(&fixture.handler.sql_pool)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            model::Membership::find()
+                .filter(model::MembershipColumn::UserId.eq(UserId::new("james")))
+                .count(&fixture.handler.sql_pool)
+                .await
+                .unwrap(),
+            0
+        );
+        // Nothing is left over to conflict with a retry.
+        fixture
+            .handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("james"),
+                email: "james@bob.bob".into(),
+                groups: vec![fixture.groups[0]],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
     }
 
     #[tokio::test]
@@ -1170,20 +2765,227 @@ mod tests {
             .handler
             .create_user(CreateUserRequest {
                 user_id: UserId::new("james"),
-                email: "email".into(),
+                email: "james@example.com".into(),
                 ..Default::default()
             })
             .await
             .unwrap();
 
-        fixture
+        let err = fixture
             .handler
             .create_user(CreateUserRequest {
                 user_id: UserId::new("john"),
-                email: "eMail".into(),
+                email: "James@Example.com".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, DomainError::EmailAlreadyInUse(email) if email == "James@example.com"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_user_duplicate_email() {
+        let fixture = TestFixture::new().await;
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                email: Some("bob@bob.bob".into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        // Changing the case of one's own email is fine.
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                email: Some("Bob@bob.bob".into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let err = fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("patrick"),
+                email: Some("BOB@BOB.BOB".into()),
                 ..Default::default()
             })
             .await
             .unwrap_err();
+        assert!(
+            matches!(err, DomainError::EmailAlreadyInUse(_)),
+            "{:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_user_invalid_id() {
+        let fixture = TestFixture::new().await;
+        for user_id in ["bob smith", "doe,john", "", "a=b"] {
+            let err = fixture
+                .handler
+                .create_user(CreateUserRequest {
+                    user_id: UserId::new(user_id),
+                    email: format!("{}@bob.bob", user_id.len()).into(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap_err();
+            assert!(matches!(err, DomainError::ValidationError(_)), "{:?}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_users_with_empty_emails() {
+        let fixture = TestFixture::new().await;
+        for user_id in ["james", "jane"] {
+            fixture
+                .handler
+                .create_user(CreateUserRequest {
+                    user_id: UserId::new(user_id),
+                    email: "".into(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_user_derives_id_from_email() {
+        let sql_pool = get_initialized_db().await;
+        let mut config = get_default_config();
+        config.login_identifier = LoginIdentifier::Email;
+        let handler = SqlBackendHandler::new(config, sql_pool);
+        insert_user_no_password(&handler, "bob").await;
+        let create = |email: &'static str| {
+            handler.create_user(CreateUserRequest {
+                email: email.into(),
+                ..Default::default()
+            })
+        };
+        assert_eq!(
+            create("Bob@example.com").await.unwrap(),
+            UserId::new("bob2")
+        );
+        assert_eq!(create("bob@other.com").await.unwrap(), UserId::new("bob3"));
+        assert_eq!(
+            create("alice+work@example.com").await.unwrap(),
+            UserId::new("alicework")
+        );
+        // The deleted users keep their IDs until they are purged.
+        handler
+            .delete_user(&UserId::new("alicework"))
+            .await
+            .unwrap();
+        assert_eq!(
+            create("alice.work@example.com").await.unwrap(),
+            UserId::new("alice.work")
+        );
+        assert_eq!(
+            create("alicework@example.org").await.unwrap(),
+            UserId::new("alicework2")
+        );
+        // An explicit user ID is kept.
+        assert_eq!(
+            handler
+                .create_user(CreateUserRequest {
+                    user_id: UserId::new("Carol"),
+                    email: "carol@example.com".into(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap(),
+            UserId::new("carol")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_user_without_id_in_user_id_mode() {
+        let fixture = TestFixture::new().await;
+        let err = fixture
+            .handler
+            .create_user(CreateUserRequest {
+                email: "dave@example.com".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DomainError::ValidationError(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_emails() {
+        let fixture = TestFixture::new().await;
+        let err = fixture
+            .handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("james"),
+                email: "not-an-email".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid email \"not-an-email\": missing domain or user"
+        );
+        fixture
+            .handler
+            .get_user_details(&UserId::new("james"))
+            .await
+            .unwrap_err();
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                email: Some("bob@".into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_emails_are_normalized() {
+        let fixture = TestFixture::new().await;
+        fixture
+            .handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("james"),
+                email: " James@Example.COM ".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let user = fixture
+            .handler
+            .get_user_details(&UserId::new("james"))
+            .await
+            .unwrap();
+        assert_eq!(user.email.as_str(), "James@example.com");
+        // Whitespace only is no email.
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("james"),
+                email: Some("  ".into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let user = fixture
+            .handler
+            .get_user_details(&UserId::new("james"))
+            .await
+            .unwrap();
+        assert_eq!(user.email.as_str(), "");
     }
 }
@@ -1,7 +1,7 @@
 use std::cmp::Ordering;
 
 use base64::Engine;
-use chrono::{NaiveDateTime, TimeZone};
+use chrono::{DateTime, Utc};
 use lldap_auth::types::CaseInsensitiveString;
 use sea_orm::{
     entity::IntoActiveValue,
@@ -20,18 +20,11 @@ pub use lldap_auth::types::UserId;
 pub struct Uuid(String);
 
 impl Uuid {
-    pub fn from_name_and_date(name: &str, creation_date: &NaiveDateTime) -> Self {
+    pub fn from_name_and_date(name: &str, creation_date: &DateTime<Utc>) -> Self {
         Uuid(
             uuid::Uuid::new_v3(
                 &uuid::Uuid::NAMESPACE_X500,
-                &[
-                    name.as_bytes(),
-                    chrono::Utc
-                        .from_utc_datetime(creation_date)
-                        .to_rfc3339()
-                        .as_bytes(),
-                ]
-                .concat(),
+                &[name.as_bytes(), creation_date.to_rfc3339().as_bytes()].concat(),
             )
             .to_string(),
         )
@@ -122,6 +115,18 @@ impl Serialized {
     pub fn expect<'a, T: Deserialize<'a>>(&'a self, message: &str) -> T {
         self.convert_to().expect(message)
     }
+
+    /// The size of the serialized value, in bytes.
+    pub fn byte_len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// A hash of the serialized value, stored next to it to tell whether it changed without
+    /// reading it, e.g. for the ETags of the avatars.
+    pub fn content_hash(&self) -> String {
+        use sha2::{Digest, Sha256};
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(&self.0))
+    }
 }
 
 fn compare_str_case_insensitive(s1: &str, s2: &str) -> Ordering {
@@ -408,26 +413,63 @@ pub struct User {
     pub user_id: UserId,
     pub email: Email,
     pub display_name: Option<String>,
-    pub creation_date: NaiveDateTime,
+    pub creation_date: DateTime<Utc>,
     pub uuid: Uuid,
+    pub last_login: Option<DateTime<Utc>>,
+    pub password_changed_at: Option<DateTime<Utc>>,
+    /// BCP 47 language tag, for the emails.
+    pub preferred_language: Option<String>,
+    /// The hosts the user may log into, normalized, "*" for all of them.
+    pub hosts: Vec<String>,
+    /// The phone number, normalized: digits with an optional leading '+'. A single value, even
+    /// though LDAP allows several `telephoneNumber`s.
+    pub phone: Option<String>,
+    /// The mobile phone number, normalized like the phone number.
+    pub mobile: Option<String>,
+    /// The postal address, possibly on several lines.
+    pub postal_address: Option<String>,
+    /// The city or town ("l" over LDAP).
+    pub locality: Option<String>,
+    /// The state or province ("st" over LDAP).
+    pub state_or_province: Option<String>,
+    /// The date the account is disabled (deleted) at, if any.
+    pub expires_at: Option<DateTime<Utc>>,
     pub attributes: Vec<AttributeValue>,
 }
 
 #[cfg(test)]
 impl Default for User {
     fn default() -> Self {
-        let epoch = chrono::Utc.timestamp_opt(0, 0).unwrap().naive_utc();
+        use chrono::TimeZone;
+        let epoch = Utc.timestamp_opt(0, 0).unwrap();
         User {
             user_id: UserId::default(),
             email: Email::default(),
             display_name: None,
             creation_date: epoch,
             uuid: Uuid::from_name_and_date("", &epoch),
+            last_login: None,
+            password_changed_at: None,
+            preferred_language: None,
+            hosts: Vec::new(),
+            phone: None,
+            mobile: None,
+            postal_address: None,
+            locality: None,
+            state_or_province: None,
+            expires_at: None,
             attributes: Vec::new(),
         }
     }
 }
 
+/// A user that was deleted, but not purged yet: it can still be restored.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct DeletedUser {
+    pub user: User,
+    pub deletion_date: DateTime<Utc>,
+}
+
 #[derive(
     Debug,
     Copy,
@@ -510,12 +552,14 @@ impl ValueType for AttributeType {
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct Group {
     pub id: GroupId,
     pub display_name: GroupName,
-    pub creation_date: NaiveDateTime,
+    pub creation_date: DateTime<Utc>,
     pub uuid: Uuid,
+    pub description: Option<String>,
+    pub notes: Option<String>,
     pub users: Vec<UserId>,
     pub attributes: Vec<AttributeValue>,
 }
@@ -524,8 +568,10 @@ pub struct Group {
 pub struct GroupDetails {
     pub group_id: GroupId,
     pub display_name: GroupName,
-    pub creation_date: NaiveDateTime,
+    pub creation_date: DateTime<Utc>,
     pub uuid: Uuid,
+    pub description: Option<String>,
+    pub notes: Option<String>,
     pub attributes: Vec<AttributeValue>,
 }
 
@@ -563,4 +609,12 @@ mod tests {
         assert_eq!(SERIALIZED_I64_LEN, Serialized::from(&i64::MIN).0.len());
         assert_eq!(SERIALIZED_I64_LEN, Serialized::from(&-1000i64).0.len());
     }
+
+    #[test]
+    fn test_serialized_content_hash() {
+        let hash = Serialized::from("abcd").content_hash();
+        assert_eq!(hash.len(), 43);
+        assert_eq!(hash, Serialized::from("abcd").content_hash());
+        assert_ne!(hash, Serialized::from("abce").content_hash());
+    }
 }
@@ -0,0 +1,469 @@
+//! Rules for the user ids and group names, to make sure that they produce valid, unambiguous DNs,
+//! and for the other user fields with a fixed syntax: the emails, the language tags, the hosts and
+//! the phone numbers. The free-text fields, like the descriptions of the groups or the postal
+//! addresses, only have a maximum length.
+//!
+//! User ids are case-insensitive: they are lowercased when they are created (see `UserId`).
+
+use crate::domain::error::{DomainError, Result};
+
+pub const MAX_USER_ID_LENGTH: usize = 64;
+pub const MAX_GROUP_NAME_LENGTH: usize = 255;
+pub const MAX_LANGUAGE_TAG_LENGTH: usize = 35;
+/// The longest path accepted by SMTP (RFC 5321), without the angle brackets.
+pub const MAX_EMAIL_LENGTH: usize = 254;
+/// The size of the name columns: display name, first and last names.
+pub const MAX_NAME_LENGTH: usize = 255;
+/// The longest value of a string attribute, in characters.
+pub const MAX_ATTRIBUTE_VALUE_LENGTH: usize = 4096;
+/// The longest group description, in characters.
+pub const MAX_GROUP_DESCRIPTION_LENGTH: usize = 1024;
+/// The longest admin notes of a group, in characters.
+pub const MAX_GROUP_NOTES_LENGTH: usize = MAX_ATTRIBUTE_VALUE_LENGTH;
+/// The longest domain name (RFC 1035), without the final dot.
+pub const MAX_HOST_LENGTH: usize = 253;
+/// The host that stands for all of them, with `ldap_host_wildcard`.
+pub const HOST_WILDCARD: &str = "*";
+/// The longest normalized phone number. E.164 numbers have at most 15 digits, the rest is for the
+/// local numbers with an extension.
+pub const MAX_PHONE_NUMBER_LENGTH: usize = 32;
+/// The longest postal address, in characters.
+pub const MAX_POSTAL_ADDRESS_LENGTH: usize = 1024;
+/// The longest locality or state, in characters.
+pub const MAX_LOCALITY_LENGTH: usize = MAX_NAME_LENGTH;
+
+/// The separators allowed when entering a phone number, removed by `normalize_phone_number`.
+const PHONE_NUMBER_SEPARATORS: &[char] = &[' ', '-', '.', '(', ')', '/'];
+
+/// Characters that have a special meaning in DNs (RFC 4514).
+const DN_SPECIAL_CHARACTERS: &[char] = &[',', '+', '"', '\\', '<', '>', ';', '='];
+
+fn describe(c: char) -> String {
+    if c.is_whitespace() || c.is_control() {
+        format!("{:?} (U+{:04X})", c, c as u32)
+    } else {
+        format!("'{}'", c)
+    }
+}
+
+/// User ids may only contain letters, digits, and the characters '.', '_', '-' and '@'.
+pub fn check_user_id(user_id: &str) -> std::result::Result<(), String> {
+    if user_id.is_empty() {
+        return Err("the user id is empty".to_owned());
+    }
+    let length = user_id.chars().count();
+    if length > MAX_USER_ID_LENGTH {
+        return Err(format!(
+            "the user id is {} characters long, the maximum is {}",
+            length, MAX_USER_ID_LENGTH
+        ));
+    }
+    if let Some(c) = user_id
+        .chars()
+        .find(|c| !c.is_alphanumeric() && !['.', '_', '-', '@'].contains(c))
+    {
+        return Err(format!(
+            "the character {} is not allowed in user ids (only letters, digits, '.', '_', '-' and '@' are)",
+            describe(c)
+        ));
+    }
+    Ok(())
+}
+
+/// Group names can contain spaces, but not at the start or the end, and no control characters
+/// or characters that have a special meaning in DNs.
+pub fn check_group_name(name: &str) -> std::result::Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("the group name is empty".to_owned());
+    }
+    let length = name.chars().count();
+    if length > MAX_GROUP_NAME_LENGTH {
+        return Err(format!(
+            "the group name is {} characters long, the maximum is {}",
+            length, MAX_GROUP_NAME_LENGTH
+        ));
+    }
+    if name.starts_with(char::is_whitespace) || name.ends_with(char::is_whitespace) {
+        return Err("the group name starts or ends with a space".to_owned());
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| c.is_control() || DN_SPECIAL_CHARACTERS.contains(c))
+    {
+        return Err(format!(
+            "the character {} is not allowed in group names",
+            describe(c)
+        ));
+    }
+    Ok(())
+}
+
+/// Emails must be valid SMTP addresses, e.g. `bob@example.com`. The empty email is allowed: it is
+/// an account without email, which can't reset its password or be found by email.
+pub fn check_email(email: &str) -> std::result::Result<(), String> {
+    if email.is_empty() {
+        return Ok(());
+    }
+    if email.len() > MAX_EMAIL_LENGTH {
+        return Err(format!(
+            "the email is {} characters long, the maximum is {}",
+            email.len(),
+            MAX_EMAIL_LENGTH
+        ));
+    }
+    email
+        .parse::<lettre::Address>()
+        .map(|_| ())
+        .map_err(|e| e.to_string().to_lowercase())
+}
+
+/// Trims the email, and lowercases the domain: only the local part can be case-sensitive.
+pub fn normalize_email(email: &str) -> String {
+    let email = email.trim();
+    match email.rsplit_once('@') {
+        Some((local, domain)) => format!("{}@{}", local, domain.to_lowercase()),
+        None => email.to_owned(),
+    }
+}
+
+/// Language tags follow the BCP 47 syntax: a 2 or 3 letters language (ISO 639), followed by
+/// subtags of 1 to 8 letters or digits, e.g. "fr", "de-CH" or "zh-Hant-TW". The subtags are not checked against
+/// the registry.
+pub fn check_language_tag(tag: &str) -> std::result::Result<(), String> {
+    if tag.len() > MAX_LANGUAGE_TAG_LENGTH {
+        return Err(format!(
+            "the language tag is {} characters long, the maximum is {}",
+            tag.len(),
+            MAX_LANGUAGE_TAG_LENGTH
+        ));
+    }
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or_default();
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(format!(
+            "\"{}\" is not a language, expected 2 or 3 letters like \"en\"",
+            language
+        ));
+    }
+    if let Some(subtag) = subtags
+        .find(|s| !(1..=8).contains(&s.len()) || !s.chars().all(|c| c.is_ascii_alphanumeric()))
+    {
+        return Err(format!(
+            "the subtag \"{}\" is not 1 to 8 letters or digits",
+            subtag
+        ));
+    }
+    Ok(())
+}
+
+/// The conventional case of a valid language tag: "zh-hant-tw" becomes "zh-Hant-TW".
+pub fn normalize_language_tag(tag: &str) -> String {
+    tag.split('-')
+        .enumerate()
+        .map(|(i, subtag)| match subtag.len() {
+            _ if i == 0 => subtag.to_ascii_lowercase(),
+            2 if subtag.chars().all(|c| c.is_ascii_alphabetic()) => subtag.to_ascii_uppercase(),
+            4 if subtag.chars().all(|c| c.is_ascii_alphabetic()) => {
+                subtag[..1].to_ascii_uppercase() + &subtag[1..].to_ascii_lowercase()
+            }
+            _ => subtag.to_ascii_lowercase(),
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Hosts are hostnames or fully qualified domain names: letters, digits, '-' and '.', e.g.
+/// "web01" or "web01.example.com". The wildcard "*" is allowed on its own.
+pub fn check_host(host: &str) -> std::result::Result<(), String> {
+    if host == HOST_WILDCARD {
+        return Ok(());
+    }
+    if host.is_empty() {
+        return Err("the host is empty".to_owned());
+    }
+    if host.len() > MAX_HOST_LENGTH {
+        return Err(format!(
+            "the host is {} characters long, the maximum is {}",
+            host.len(),
+            MAX_HOST_LENGTH
+        ));
+    }
+    if let Some(c) = host
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !['-', '.'].contains(c))
+    {
+        return Err(format!(
+            "the character {} is not allowed in hosts (only letters, digits, '-' and '.' are, or \"*\" alone)",
+            describe(c)
+        ));
+    }
+    Ok(())
+}
+
+/// Hostnames are case-insensitive: the hosts are lowercased, sorted and deduplicated.
+pub fn normalize_hosts<S: AsRef<str>>(hosts: &[S]) -> Vec<String> {
+    let mut hosts = hosts
+        .iter()
+        .map(|host| host.as_ref().trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+/// Phone numbers are stored without their formatting: "+1 (555) 010-9999" becomes
+/// "+15550109999".
+pub fn normalize_phone_number(phone: &str) -> String {
+    phone
+        .trim()
+        .chars()
+        .filter(|c| !PHONE_NUMBER_SEPARATORS.contains(c))
+        .collect()
+}
+
+/// Normalized phone numbers are digits with an optional leading '+'. With `require_e164`, they
+/// must be in the international E.164 format: '+', then up to 15 digits, not starting with 0.
+pub fn check_phone_number(phone: &str, require_e164: bool) -> std::result::Result<(), String> {
+    let digits = phone.strip_prefix('+').unwrap_or(phone);
+    if digits.is_empty() {
+        return Err("the phone number has no digits".to_owned());
+    }
+    if phone.len() > MAX_PHONE_NUMBER_LENGTH {
+        return Err(format!(
+            "the phone number is {} characters long, the maximum is {}",
+            phone.len(),
+            MAX_PHONE_NUMBER_LENGTH
+        ));
+    }
+    if let Some(c) = digits.chars().find(|c| !c.is_ascii_digit()) {
+        return Err(format!(
+            "the character {} is not allowed in phone numbers (only digits, an optional leading '+' and the separators \" -.()/\" are)",
+            describe(c)
+        ));
+    }
+    if require_e164 && (!phone.starts_with('+') || digits.starts_with('0') || digits.len() > 15) {
+        return Err(
+            "the phone number is not in the international E.164 format, e.g. \"+15550109999\""
+                .to_owned(),
+        );
+    }
+    Ok(())
+}
+
+pub fn validate_user_id(user_id: &str) -> Result<()> {
+    check_user_id(user_id).map_err(|e| {
+        DomainError::ValidationError(format!("Invalid user id \"{}\": {}", user_id, e))
+    })
+}
+
+pub fn validate_group_name(name: &str) -> Result<()> {
+    check_group_name(name).map_err(|e| {
+        DomainError::ValidationError(format!("Invalid group name \"{}\": {}", name, e))
+    })
+}
+
+pub fn validate_email(email: &str) -> Result<()> {
+    check_email(email)
+        .map_err(|e| DomainError::ValidationError(format!("Invalid email \"{}\": {}", email, e)))
+}
+
+pub fn validate_language_tag(tag: &str) -> Result<()> {
+    check_language_tag(tag).map_err(|e| {
+        DomainError::ValidationError(format!("Invalid language tag \"{}\": {}", tag, e))
+    })
+}
+
+pub fn validate_host(host: &str) -> Result<()> {
+    check_host(host)
+        .map_err(|e| DomainError::ValidationError(format!("Invalid host \"{}\": {}", host, e)))
+}
+
+pub fn validate_phone_number(phone: &str, require_e164: bool) -> Result<()> {
+    check_phone_number(phone, require_e164).map_err(|e| {
+        DomainError::ValidationError(format!("Invalid phone number \"{}\": {}", phone, e))
+    })
+}
+
+/// An account can't be created, nor updated, to expire in the past.
+pub fn validate_expiration_date(date: &chrono::DateTime<chrono::Utc>) -> Result<()> {
+    if *date <= chrono::Utc::now() {
+        return Err(DomainError::ValidationError(format!(
+            "Invalid expiration date {}: it is in the past",
+            date.to_rfc3339()
+        )));
+    }
+    Ok(())
+}
+
+pub fn validate_length(field: &str, value: &str, max_length: usize) -> Result<()> {
+    let length = value.chars().count();
+    if length > max_length {
+        return Err(DomainError::ValidationError(format!(
+            "Invalid {}: it is {} characters long, the maximum is {}",
+            field, length, max_length
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_check_user_id() {
+        for user_id in ["bob", "Bob.Smith", "bob_smith-2", "bob@example.com", "bôb"] {
+            check_user_id(user_id).unwrap();
+        }
+        assert_eq!(
+            check_user_id("bob "),
+            Err("the character ' ' (U+0020) is not allowed in user ids (only letters, digits, '.', '_', '-' and '@' are)".to_owned())
+        );
+        assert_eq!(
+            check_user_id("doe,john").unwrap_err(),
+            "the character ',' is not allowed in user ids (only letters, digits, '.', '_', '-' and '@' are)"
+        );
+        check_user_id("").unwrap_err();
+        check_user_id("bob\t").unwrap_err();
+        check_user_id("a=b").unwrap_err();
+        check_user_id(&"a".repeat(MAX_USER_ID_LENGTH)).unwrap();
+        check_user_id(&"a".repeat(MAX_USER_ID_LENGTH + 1)).unwrap_err();
+    }
+
+    #[test]
+    fn test_check_group_name() {
+        for name in ["lldap_admin", "Best Group", "Développeurs", "dev/ops (EU)"] {
+            check_group_name(name).unwrap();
+        }
+        assert_eq!(
+            check_group_name("dev,ops").unwrap_err(),
+            "the character ',' is not allowed in group names"
+        );
+        assert_eq!(
+            check_group_name(" admins").unwrap_err(),
+            "the group name starts or ends with a space"
+        );
+        check_group_name("admins ").unwrap_err();
+        check_group_name("   ").unwrap_err();
+        check_group_name("a\nb").unwrap_err();
+        check_group_name("a+b").unwrap_err();
+    }
+
+    #[test]
+    fn test_check_email() {
+        for email in [
+            "",
+            "bob@example.com",
+            "bob.smith+lldap@mail.example.co.uk",
+            "bob@localhost",
+            "bob@[127.0.0.1]",
+        ] {
+            check_email(email).unwrap();
+        }
+        assert_eq!(
+            check_email("not-an-email").unwrap_err(),
+            "missing domain or user"
+        );
+        check_email("bob@").unwrap_err();
+        check_email("@example.com").unwrap_err();
+        check_email("bob smith@example.com").unwrap_err();
+        check_email("bob@example..com").unwrap_err();
+        check_email(&format!("bob@{}.com", "a".repeat(MAX_EMAIL_LENGTH))).unwrap_err();
+    }
+
+    #[test]
+    fn test_normalize_email() {
+        assert_eq!(normalize_email(" Bob@Example.COM "), "Bob@example.com");
+        assert_eq!(normalize_email("a@b@Example.com"), "a@b@example.com");
+        assert_eq!(normalize_email("  "), "");
+        assert_eq!(normalize_email("bob"), "bob");
+    }
+
+    #[test]
+    fn test_check_language_tag() {
+        for tag in [
+            "en",
+            "fr-CA",
+            "de-1996",
+            "zh-Hant-TW",
+            "sr-Latn-RS",
+            "es-419",
+        ] {
+            check_language_tag(tag).unwrap();
+        }
+        assert_eq!(
+            check_language_tag("english").unwrap_err(),
+            "\"english\" is not a language, expected 2 or 3 letters like \"en\""
+        );
+        assert_eq!(
+            check_language_tag("fr_FR").unwrap_err(),
+            "\"fr_FR\" is not a language, expected 2 or 3 letters like \"en\""
+        );
+        check_language_tag("").unwrap_err();
+        check_language_tag("e").unwrap_err();
+        check_language_tag("fr-").unwrap_err();
+        check_language_tag("de-ch-toolongsubtag").unwrap_err();
+        check_language_tag(&format!("en{}", "-a".repeat(17))).unwrap_err();
+    }
+
+    #[test]
+    fn test_normalize_language_tag() {
+        assert_eq!(normalize_language_tag("FR-ca"), "fr-CA");
+        assert_eq!(normalize_language_tag("zh-hant-tw"), "zh-Hant-TW");
+        assert_eq!(normalize_language_tag("ES-419"), "es-419");
+        assert_eq!(normalize_language_tag("de-CH-1996"), "de-CH-1996");
+    }
+
+    #[test]
+    fn test_check_host() {
+        for host in ["web01", "WEB01.example.com", "db-2", "*"] {
+            check_host(host).unwrap();
+        }
+        assert_eq!(
+            check_host("web01,db02").unwrap_err(),
+            "the character ',' is not allowed in hosts (only letters, digits, '-' and '.' are, or \"*\" alone)"
+        );
+        check_host("").unwrap_err();
+        check_host("web*").unwrap_err();
+        check_host("web_01").unwrap_err();
+        check_host("web 01").unwrap_err();
+        check_host(&"a".repeat(MAX_HOST_LENGTH + 1)).unwrap_err();
+    }
+
+    #[test]
+    fn test_normalize_hosts() {
+        assert_eq!(
+            normalize_hosts(&["Web01", "db02", "web01", " *"]),
+            vec!["*", "db02", "web01"]
+        );
+        assert!(normalize_hosts::<String>(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_normalize_phone_number() {
+        assert_eq!(normalize_phone_number(" +1 (555) 010-9999"), "+15550109999");
+        assert_eq!(normalize_phone_number("030/123.45.67"), "0301234567");
+        assert_eq!(normalize_phone_number("+33 6 12 ext 3"), "+33612ext3");
+    }
+
+    #[test]
+    fn test_check_phone_number() {
+        for phone in ["+15550109999", "0301234567", "112"] {
+            check_phone_number(phone, false).unwrap();
+        }
+        check_phone_number("+15550109999", true).unwrap();
+        assert_eq!(
+            check_phone_number("+33612ext3", false).unwrap_err(),
+            "the character 'e' is not allowed in phone numbers (only digits, an optional leading '+' and the separators \" -.()/\" are)"
+        );
+        check_phone_number("", false).unwrap_err();
+        check_phone_number("+", false).unwrap_err();
+        check_phone_number("1+2", false).unwrap_err();
+        check_phone_number(&"1".repeat(MAX_PHONE_NUMBER_LENGTH + 1), false).unwrap_err();
+        // Not international.
+        check_phone_number("0301234567", true).unwrap_err();
+        check_phone_number("+0301234567", true).unwrap_err();
+        check_phone_number("+1234567890123456", true).unwrap_err();
+    }
+}
@@ -1,20 +1,26 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
 use crate::domain::{
     error::Result,
     handler::{
         AttributeSchema, BackendHandler, CreateAttributeRequest, CreateGroupRequest,
-        CreateUserRequest, GroupBackendHandler, GroupListerBackendHandler, GroupRequestFilter,
-        ReadSchemaBackendHandler, Schema, SchemaBackendHandler, UpdateGroupRequest,
-        UpdateUserRequest, UserBackendHandler, UserListerBackendHandler, UserRequestFilter,
+        CreateUserRequest, DeleteUserRequest, EmptiedGroup, GroupBackendHandler,
+        GroupListerBackendHandler, GroupRequestFilter, ReadSchemaBackendHandler, Schema,
+        SchemaBackendHandler, UpdateGroupRequest, UpdateUserRequest, UserBackendHandler,
+        UserListerBackendHandler, UserRequestFilter,
     },
+    ldap::user::get_user_column_visibility_key,
     schema::PublicSchema,
     types::{
-        AttributeName, Group, GroupDetails, GroupId, GroupName, LdapObjectClass, User,
-        UserAndGroups, UserId,
+        AttributeName, AttributeValue, DeletedUser, Group, GroupDetails, GroupId, GroupName,
+        LdapObjectClass, User, UserAndGroups, UserId,
     },
 };
 
@@ -26,6 +32,27 @@ pub enum Permission {
     Regular,
 }
 
+/// Who is allowed to read a given user attribute.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttributeVisibility {
+    /// Visible to anyone who can see the user.
+    Public,
+    /// Visible to the user themselves, the admins and the readonly users.
+    #[serde(rename = "self")]
+    SelfOnly,
+    /// Visible to the admins and the readonly users.
+    AdminOnly,
+    /// Visible only to the admins.
+    Sensitive,
+}
+
+/// The attributes needed to identify an account: they are always public.
+const IDENTIFYING_USER_ATTRIBUTES: &[&str] = &["user_id", "display_name", "creation_date", "uuid"];
+
+/// The user attributes that only the admins can modify, whatever their visibility.
+const ADMIN_WRITABLE_USER_ATTRIBUTES: &[&str] = &["hosts", "expires_at"];
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ValidationResults {
     pub user: UserId,
@@ -61,6 +88,34 @@ impl ValidationResults {
             || &self.user == user
     }
 
+    #[must_use]
+    pub fn can_read_attribute(&self, user: &UserId, visibility: AttributeVisibility) -> bool {
+        match visibility {
+            AttributeVisibility::Public => self.can_read(user),
+            AttributeVisibility::SelfOnly => {
+                self.permission == Permission::Admin
+                    || self.permission == Permission::Readonly
+                    || &self.user == user
+            }
+            AttributeVisibility::AdminOnly => {
+                self.permission == Permission::Admin || self.permission == Permission::Readonly
+            }
+            AttributeVisibility::Sensitive => self.permission == Permission::Admin,
+        }
+    }
+
+    /// Whether the attribute is visible on every user, and not only on the current one.
+    #[must_use]
+    pub fn can_read_attribute_of_all(&self, visibility: AttributeVisibility) -> bool {
+        match visibility {
+            AttributeVisibility::Public => self.can_read_all(),
+            AttributeVisibility::SelfOnly | AttributeVisibility::AdminOnly => {
+                self.permission == Permission::Admin || self.permission == Permission::Readonly
+            }
+            AttributeVisibility::Sensitive => self.permission == Permission::Admin,
+        }
+    }
+
     #[must_use]
     pub fn can_change_password(&self, user: &UserId, user_is_admin: bool) -> bool {
         self.permission == Permission::Admin
@@ -72,12 +127,36 @@ impl ValidationResults {
     pub fn can_write(&self, user: &UserId) -> bool {
         self.permission == Permission::Admin || &self.user == user
     }
+
+    /// Whether an attribute with the given visibility can be modified on the user: the admins can
+    /// modify all of them, the password managers the ones they can see on the users who aren't
+    /// admins, and the other users the ones they can see on themselves.
+    #[must_use]
+    pub fn can_write_attribute(
+        &self,
+        user: &UserId,
+        user_is_admin: bool,
+        visibility: AttributeVisibility,
+    ) -> bool {
+        match self.permission {
+            Permission::Admin => true,
+            Permission::PasswordManager => {
+                !user_is_admin && self.can_read_attribute(user, visibility)
+            }
+            Permission::Readonly | Permission::Regular => {
+                &self.user == user && self.can_read_attribute(user, visibility)
+            }
+        }
+    }
 }
 
 #[async_trait]
 pub trait UserReadableBackendHandler: ReadSchemaBackendHandler {
     async fn get_user_details(&self, user_id: &UserId) -> Result<User>;
     async fn get_user_groups(&self, user_id: &UserId) -> Result<HashSet<GroupDetails>>;
+    /// The `JpegPhoto` attributes of the user, for the users coming from a list.
+    async fn get_user_photo_attributes(&self, user_id: &UserId) -> Result<Vec<AttributeValue>>;
+    async fn get_user_avatar_hash(&self, user_id: &UserId) -> Result<Option<String>>;
     async fn get_schema(&self) -> Result<PublicSchema>;
 }
 
@@ -104,10 +183,18 @@ pub trait AdminBackendHandler:
     + UserWriteableBackendHandler
     + SchemaBackendHandler
 {
-    async fn create_user(&self, request: CreateUserRequest) -> Result<()>;
+    async fn create_user(&self, request: CreateUserRequest) -> Result<UserId>;
     async fn delete_user(&self, user_id: &UserId) -> Result<()>;
-    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
-    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
+    async fn restore_user(&self, user_id: &UserId) -> Result<()>;
+    async fn permanently_delete_user(&self, user_id: &UserId) -> Result<()>;
+    async fn delete_user_with_policy(
+        &self,
+        request: DeleteUserRequest,
+    ) -> Result<Vec<EmptiedGroup>>;
+    async fn get_groups_emptied_by_deletion(&self, user_id: &UserId) -> Result<Vec<GroupDetails>>;
+    async fn list_deleted_users(&self) -> Result<Vec<DeletedUser>>;
+    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<bool>;
+    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<bool>;
     async fn update_group(&self, request: UpdateGroupRequest) -> Result<()>;
     async fn create_group(&self, request: CreateGroupRequest) -> Result<GroupId>;
     async fn delete_group(&self, group_id: GroupId) -> Result<()>;
@@ -129,6 +216,18 @@ impl<Handler: BackendHandler> UserReadableBackendHandler for Handler {
     async fn get_user_groups(&self, user_id: &UserId) -> Result<HashSet<GroupDetails>> {
         <Handler as UserBackendHandler>::get_user_groups(self, user_id).await
     }
+    async fn get_user_photo_attributes(&self, user_id: &UserId) -> Result<Vec<AttributeValue>> {
+        Ok(<Handler as UserListerBackendHandler>::get_user_photos(
+            self,
+            std::slice::from_ref(user_id),
+        )
+        .await?
+        .remove(user_id)
+        .unwrap_or_default())
+    }
+    async fn get_user_avatar_hash(&self, user_id: &UserId) -> Result<Option<String>> {
+        <Handler as UserBackendHandler>::get_user_avatar_hash(self, user_id).await
+    }
     async fn get_schema(&self) -> Result<PublicSchema> {
         Ok(PublicSchema::from(
             <Handler as ReadSchemaBackendHandler>::get_schema(self).await?,
@@ -161,16 +260,34 @@ impl<Handler: BackendHandler> UserWriteableBackendHandler for Handler {
 }
 #[async_trait]
 impl<Handler: BackendHandler> AdminBackendHandler for Handler {
-    async fn create_user(&self, request: CreateUserRequest) -> Result<()> {
+    async fn create_user(&self, request: CreateUserRequest) -> Result<UserId> {
         <Handler as UserBackendHandler>::create_user(self, request).await
     }
     async fn delete_user(&self, user_id: &UserId) -> Result<()> {
         <Handler as UserBackendHandler>::delete_user(self, user_id).await
     }
-    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()> {
+    async fn restore_user(&self, user_id: &UserId) -> Result<()> {
+        <Handler as UserBackendHandler>::restore_user(self, user_id).await
+    }
+    async fn permanently_delete_user(&self, user_id: &UserId) -> Result<()> {
+        <Handler as UserBackendHandler>::permanently_delete_user(self, user_id).await
+    }
+    async fn delete_user_with_policy(
+        &self,
+        request: DeleteUserRequest,
+    ) -> Result<Vec<EmptiedGroup>> {
+        <Handler as UserBackendHandler>::delete_user_with_policy(self, request).await
+    }
+    async fn get_groups_emptied_by_deletion(&self, user_id: &UserId) -> Result<Vec<GroupDetails>> {
+        <Handler as UserBackendHandler>::get_groups_emptied_by_deletion(self, user_id).await
+    }
+    async fn list_deleted_users(&self) -> Result<Vec<DeletedUser>> {
+        <Handler as UserListerBackendHandler>::list_deleted_users(self).await
+    }
+    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<bool> {
         <Handler as UserBackendHandler>::add_user_to_group(self, user_id, group_id).await
     }
-    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()> {
+    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<bool> {
         <Handler as UserBackendHandler>::remove_user_from_group(self, user_id, group_id).await
     }
     async fn update_group(&self, request: UpdateGroupRequest) -> Result<()> {
@@ -210,12 +327,16 @@ impl<Handler: BackendHandler> AdminBackendHandler for Handler {
 
 pub struct AccessControlledBackendHandler<Handler> {
     handler: Handler,
+    attribute_visibility: Arc<HashMap<AttributeName, AttributeVisibility>>,
+    bind_scopes: Arc<HashMap<UserId, Vec<GroupName>>>,
 }
 
 impl<Handler: Clone> Clone for AccessControlledBackendHandler<Handler> {
     fn clone(&self) -> Self {
         Self {
             handler: self.handler.clone(),
+            attribute_visibility: self.attribute_visibility.clone(),
+            bind_scopes: self.bind_scopes.clone(),
         }
     }
 }
@@ -224,11 +345,143 @@ impl<Handler> AccessControlledBackendHandler<Handler> {
     pub fn unsafe_get_handler(&self) -> &Handler {
         &self.handler
     }
+
+    /// Overrides the default visibility of the given user attributes.
+    pub fn with_attribute_visibility(
+        mut self,
+        attribute_visibility: HashMap<AttributeName, AttributeVisibility>,
+    ) -> Self {
+        self.attribute_visibility = Arc::new(attribute_visibility);
+        self
+    }
+
+    /// Limits what the given accounts can list to the members of some groups, and these groups.
+    /// The admins are never limited.
+    pub fn with_bind_scopes(mut self, bind_scopes: HashMap<UserId, Vec<GroupName>>) -> Self {
+        self.bind_scopes = Arc::new(bind_scopes);
+        self
+    }
+
+    /// Returns the visibility of a user attribute, by its schema name (e.g. "mail", "avatar").
+    /// The group memberships of a user are controlled by the "groups" pseudo-attribute. The
+    /// attributes that are not configured are public.
+    pub fn get_attribute_visibility(&self, attribute: &AttributeName) -> AttributeVisibility {
+        if IDENTIFYING_USER_ATTRIBUTES.contains(&attribute.as_str()) {
+            return AttributeVisibility::Public;
+        }
+        self.attribute_visibility
+            .get(attribute)
+            .copied()
+            .unwrap_or(AttributeVisibility::Public)
+    }
+
+    #[must_use]
+    pub fn can_read_user_attribute(
+        &self,
+        validation_result: &ValidationResults,
+        user_id: &UserId,
+        attribute: &AttributeName,
+    ) -> bool {
+        validation_result.can_read_attribute(user_id, self.get_attribute_visibility(attribute))
+    }
+
+    /// Whether the current user can modify the attribute of the user. On top of
+    /// `ValidationResults::can_write_attribute`, the non-admins can only modify the attributes
+    /// that are editable in the schema, and never the hosts or the expiration date, as through
+    /// GraphQL.
+    #[must_use]
+    pub fn can_write_user_attribute(
+        &self,
+        validation_result: &ValidationResults,
+        user_id: &UserId,
+        user_is_admin: bool,
+        attribute: &AttributeName,
+        schema: &PublicSchema,
+    ) -> bool {
+        if validation_result.is_admin() {
+            return true;
+        }
+        let is_editable = schema
+            .get_schema()
+            .user_attributes
+            .get_attribute_schema(attribute)
+            .map_or(true, |a| a.is_editable);
+        is_editable
+            && !ADMIN_WRITABLE_USER_ATTRIBUTES.contains(&attribute.as_str())
+            && validation_result.can_write_attribute(
+                user_id,
+                user_is_admin,
+                self.get_attribute_visibility(attribute),
+            )
+    }
+
+    /// Restricts the conditions of the filter on the attributes hidden from the current user, so
+    /// that the results of a search don't reveal their values.
+    #[must_use]
+    pub fn restrict_user_filter_to_visible_attributes(
+        &self,
+        validation_result: &ValidationResults,
+        filter: UserRequestFilter,
+    ) -> UserRequestFilter {
+        restrict_user_filter_to_visible_attributes(
+            filter,
+            validation_result,
+            &self.attribute_visibility,
+        )
+    }
+}
+
+/// A condition on an attribute that the current user can only see on themselves only matches
+/// them, and a condition on an attribute they cannot see at all matches nobody. The attributes
+/// that are not configured are public, and the users who cannot see the public attributes of the
+/// others are already limited to themselves.
+fn restrict_user_filter_to_visible_attributes(
+    filter: UserRequestFilter,
+    validation_result: &ValidationResults,
+    attribute_visibility: &HashMap<AttributeName, AttributeVisibility>,
+) -> UserRequestFilter {
+    use UserRequestFilter::*;
+    let restrict = |filter| {
+        restrict_user_filter_to_visible_attributes(filter, validation_result, attribute_visibility)
+    };
+    let attribute = match &filter {
+        And(filters) => return And(filters.iter().cloned().map(restrict).collect()),
+        Or(filters) => return Or(filters.iter().cloned().map(restrict).collect()),
+        Not(filter) => return Not(Box::new(restrict(*filter.clone()))),
+        Equality(column, _) | SubString(column, _) | Present(column) => {
+            get_user_column_visibility_key(column)
+        }
+        AttributeEquality(name, _) | AttributePresent(name) => Some(name.clone()),
+        MemberOf(_) | MemberOfId(_) | MemberOfAny => Some("groups".into()),
+        LastLoginBefore(_) => Some("last_login".into()),
+        PasswordChangedBefore(_) => Some("password_changed_at".into()),
+        ExpiresBefore(_) => Some("expires_at".into()),
+        Host(_) => Some("hosts".into()),
+        UserId(_) | UserIdSubString(_) => None,
+    };
+    let visibility = match attribute
+        .filter(|a| !IDENTIFYING_USER_ATTRIBUTES.contains(&a.as_str()))
+        .and_then(|a| attribute_visibility.get(&a).copied())
+    {
+        Some(visibility) => visibility,
+        None => return filter,
+    };
+    if validation_result.can_read_attribute_of_all(visibility) {
+        filter
+    } else if validation_result.can_read_attribute(&validation_result.user, visibility) {
+        And(vec![filter, UserId(validation_result.user.clone())])
+    } else {
+        UserRequestFilter::from(false)
+    }
 }
 
 impl<Handler: BackendHandler> AccessControlledBackendHandler<Handler> {
     pub fn new(handler: Handler) -> Self {
-        Self { handler }
+        Self {
+            handler,
+            attribute_visibility: Arc::default(),
+            bind_scopes: Arc::default(),
+        }
     }
 
     pub fn get_admin_handler(
@@ -255,6 +508,19 @@ impl<Handler: BackendHandler> AccessControlledBackendHandler<Handler> {
             .then_some(&self.handler)
     }
 
+    /// The handler to modify some attributes of a user, each of them checked with
+    /// `can_write_user_attribute`.
+    pub fn get_user_attribute_writeable_handler(
+        &self,
+        validation_result: &ValidationResults,
+        user_id: &UserId,
+        user_is_admin: bool,
+    ) -> Option<&impl UserWriteableBackendHandler> {
+        (validation_result.can_write(user_id)
+            || (validation_result.permission == Permission::PasswordManager && !user_is_admin))
+            .then_some(&self.handler)
+    }
+
     pub fn get_readable_handler(
         &self,
         validation_result: &ValidationResults,
@@ -269,12 +535,19 @@ impl<Handler: BackendHandler> AccessControlledBackendHandler<Handler> {
     ) -> UserRestrictedListerBackendHandler<'_, Handler> {
         UserRestrictedListerBackendHandler {
             handler: &self.handler,
+            validation_result: validation_result.clone(),
+            attribute_visibility: &self.attribute_visibility,
             user_filter: if validation_result.can_read_all() {
                 None
             } else {
                 info!("Unprivileged search, limiting results");
                 Some(validation_result.user.clone())
             },
+            group_scope: if validation_result.is_admin() {
+                None
+            } else {
+                self.bind_scopes.get(&validation_result.user).cloned()
+            },
         }
     }
 
@@ -310,7 +583,11 @@ impl<Handler: BackendHandler> AccessControlledBackendHandler<Handler> {
 
 pub struct UserRestrictedListerBackendHandler<'a, Handler> {
     handler: &'a Handler,
+    validation_result: ValidationResults,
+    attribute_visibility: &'a HashMap<AttributeName, AttributeVisibility>,
     pub user_filter: Option<UserId>,
+    /// Only the members of these groups, and these groups, are listed.
+    pub group_scope: Option<Vec<GroupName>>,
 }
 
 #[async_trait]
@@ -330,6 +607,54 @@ impl<'a, Handler: ReadSchemaBackendHandler + Sync> ReadSchemaBackendHandler
     }
 }
 
+impl<'a, Handler> UserRestrictedListerBackendHandler<'a, Handler> {
+    fn restrict_user_filters(
+        &self,
+        filters: Option<UserRequestFilter>,
+    ) -> Option<UserRequestFilter> {
+        let user_filter = self
+            .user_filter
+            .as_ref()
+            .map(|u| UserRequestFilter::UserId(u.clone()));
+        let scope_filter = self.group_scope.as_ref().map(|groups| {
+            UserRequestFilter::Or(
+                groups
+                    .iter()
+                    .cloned()
+                    .map(UserRequestFilter::MemberOf)
+                    .collect(),
+            )
+        });
+        let mut filters = filters
+            .map(|f| {
+                restrict_user_filter_to_visible_attributes(
+                    f,
+                    &self.validation_result,
+                    self.attribute_visibility,
+                )
+            })
+            .into_iter()
+            .chain(user_filter)
+            .chain(scope_filter)
+            .collect::<Vec<_>>();
+        match filters.len() {
+            0 => None,
+            1 => filters.pop(),
+            _ => Some(UserRequestFilter::And(filters)),
+        }
+    }
+
+    /// Drops the groups outside of the scope from the memberships of the users.
+    fn restrict_user_groups(&self, mut users: Vec<UserAndGroups>) -> Vec<UserAndGroups> {
+        if let Some(scope) = &self.group_scope {
+            for groups in users.iter_mut().filter_map(|u| u.groups.as_mut()) {
+                groups.retain(|g| scope.contains(&g.display_name));
+            }
+        }
+        users
+    }
+}
+
 #[async_trait]
 impl<'a, Handler: UserListerBackendHandler + Sync> UserListerBackendHandler
     for UserRestrictedListerBackendHandler<'a, Handler>
@@ -339,17 +664,52 @@ impl<'a, Handler: UserListerBackendHandler + Sync> UserListerBackendHandler
         filters: Option<UserRequestFilter>,
         get_groups: bool,
     ) -> Result<Vec<UserAndGroups>> {
-        let user_filter = self
-            .user_filter
-            .as_ref()
-            .map(|u| UserRequestFilter::UserId(u.clone()));
-        let filters = match (filters, user_filter) {
-            (None, None) => None,
-            (None, u) => u,
-            (f, None) => f,
-            (Some(f), Some(u)) => Some(UserRequestFilter::And(vec![f, u])),
-        };
-        self.handler.list_users(filters, get_groups).await
+        Ok(self.restrict_user_groups(
+            self.handler
+                .list_users(self.restrict_user_filters(filters), get_groups)
+                .await?,
+        ))
+    }
+
+    async fn list_users_page(
+        &self,
+        filters: Option<UserRequestFilter>,
+        get_groups: bool,
+        after: Option<UserId>,
+        limit: u64,
+    ) -> Result<Vec<UserAndGroups>> {
+        Ok(self.restrict_user_groups(
+            self.handler
+                .list_users_page(
+                    self.restrict_user_filters(filters),
+                    get_groups,
+                    after,
+                    limit,
+                )
+                .await?,
+        ))
+    }
+
+    async fn list_deleted_users(&self) -> Result<Vec<DeletedUser>> {
+        if self.user_filter.is_some() || self.group_scope.is_some() {
+            return Ok(Vec::new());
+        }
+        self.handler.list_deleted_users().await
+    }
+
+    async fn get_user_photos(
+        &self,
+        user_ids: &[UserId],
+    ) -> Result<HashMap<UserId, Vec<AttributeValue>>> {
+        match &self.user_filter {
+            None => self.handler.get_user_photos(user_ids).await,
+            Some(user) if user_ids.contains(user) => {
+                self.handler
+                    .get_user_photos(std::slice::from_ref(user))
+                    .await
+            }
+            Some(_) => Ok(HashMap::new()),
+        }
     }
 }
 
@@ -362,11 +722,24 @@ impl<'a, Handler: GroupListerBackendHandler + Sync> GroupListerBackendHandler
             .user_filter
             .as_ref()
             .map(|u| GroupRequestFilter::Member(u.clone()));
-        let filters = match (filters, group_filter) {
-            (None, None) => None,
-            (None, u) => u,
-            (f, None) => f,
-            (Some(f), Some(u)) => Some(GroupRequestFilter::And(vec![f, u])),
+        let scope_filter = self.group_scope.as_ref().map(|groups| {
+            GroupRequestFilter::Or(
+                groups
+                    .iter()
+                    .cloned()
+                    .map(GroupRequestFilter::DisplayName)
+                    .collect(),
+            )
+        });
+        let mut filters = filters
+            .into_iter()
+            .chain(group_filter)
+            .chain(scope_filter)
+            .collect::<Vec<_>>();
+        let filters = match filters.len() {
+            0 => None,
+            1 => filters.pop(),
+            _ => Some(GroupRequestFilter::And(filters)),
         };
         self.handler.list_groups(filters).await
     }
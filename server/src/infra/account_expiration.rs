@@ -0,0 +1,343 @@
+//! Expiration of the accounts: the users whose `expires_at` has passed are deleted, and can be
+//! restored until they are purged like the other deleted users. They are warned beforehand, see
+//! `AccountExpirationOptions`. Only on the primary: a replica gets the deletions from it.
+
+use crate::{
+    domain::{
+        handler::{UserBackendHandler, UserListerBackendHandler, UserRequestFilter},
+        sql_backend_handler::SqlBackendHandler,
+        types::{User, UserId},
+    },
+    infra::{
+        configuration::{Configuration, MailOptions},
+        mail,
+        mail_queue::MailQueue,
+        mail_templates::MailTemplates,
+    },
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use lettre::message::Mailbox;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tracing::{error, info, instrument, warn};
+
+/// How often the expired accounts are looked for.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+pub trait ExpirationNotifier: Send + Sync {
+    /// Warns that the account of the user, which has an expiration date, expires soon.
+    fn warn_of_expiration(&self, user: &User, now: DateTime<Utc>) -> Result<()>;
+}
+
+pub struct MailExpirationNotifier {
+    pub server_url: url::Url,
+    pub admin_email: Option<Mailbox>,
+    pub mail_options: MailOptions,
+    pub mail_templates: Arc<MailTemplates>,
+    pub mail_queue: MailQueue,
+}
+
+impl ExpirationNotifier for MailExpirationNotifier {
+    fn warn_of_expiration(&self, user: &User, now: DateTime<Utc>) -> Result<()> {
+        mail::send_account_expiring_emails(
+            user,
+            now,
+            self.admin_email.as_ref(),
+            &self.server_url,
+            &self.mail_options,
+            &self.mail_templates,
+            &self.mail_queue,
+        )
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ExpirationSummary {
+    pub expired: usize,
+    pub warned: usize,
+}
+
+pub struct AccountExpiration {
+    backend_handler: SqlBackendHandler,
+    /// None without emails, or with `warning_days = 0`.
+    notifier: Option<(Box<dyn ExpirationNotifier>, chrono::Duration)>,
+}
+
+impl AccountExpiration {
+    pub fn new(
+        config: &Configuration,
+        backend_handler: SqlBackendHandler,
+        mail_queue: MailQueue,
+    ) -> Result<Self> {
+        let options = &config.account_expiration;
+        let notifier = if config.smtp_options.enable_password_reset && options.warning_days > 0 {
+            let mail_options = config.smtp_options.clone();
+            let mail_templates = MailTemplates::new(
+                mail_options.templates_dir.as_deref(),
+                mail_options.logo_file.as_deref(),
+                &mail_options.default_language,
+            )
+            .context("while loading the email templates")?;
+            Some((
+                Box::new(MailExpirationNotifier {
+                    server_url: config.public_url(),
+                    admin_email: options.admin_email.clone(),
+                    mail_options,
+                    mail_templates: Arc::new(mail_templates),
+                    mail_queue,
+                }) as Box<dyn ExpirationNotifier>,
+                chrono::Duration::days(options.warning_days.into()),
+            ))
+        } else {
+            None
+        };
+        Ok(Self {
+            backend_handler,
+            notifier,
+        })
+    }
+
+    /// Deletes the expired accounts, then warns the users whose account expires soon. Each user
+    /// is warned once per expiration date, even if sending the email fails: the warning is
+    /// recorded first.
+    #[instrument(skip_all)]
+    pub async fn run(&self, now: DateTime<Utc>) -> Result<ExpirationSummary> {
+        let mut summary = ExpirationSummary::default();
+        let expired = self
+            .backend_handler
+            .list_users(Some(UserRequestFilter::ExpiresBefore(now)), false)
+            .await?;
+        if !expired.is_empty() {
+            let mut admins = self
+                .backend_handler
+                .list_users(
+                    Some(UserRequestFilter::MemberOf("lldap_admin".into())),
+                    false,
+                )
+                .await?
+                .into_iter()
+                .map(|u| u.user.user_id)
+                .collect::<HashSet<UserId>>();
+            for user in expired.into_iter().map(|u| u.user) {
+                let is_admin = admins.contains(&user.user_id);
+                if is_admin && admins.len() == 1 {
+                    warn!(
+                        "The account {} expired, but it is the last admin: it is kept",
+                        user.user_id
+                    );
+                    continue;
+                }
+                match self.backend_handler.delete_user(&user.user_id).await {
+                    Ok(()) => {
+                        info!(
+                            "Deleted the account {}: it expired on {}",
+                            user.user_id,
+                            user.expires_at.unwrap_or(now).to_rfc3339()
+                        );
+                        admins.remove(&user.user_id);
+                        summary.expired += 1;
+                    }
+                    Err(e) => error!(
+                        "Could not delete the expired account {}: {}",
+                        user.user_id, e
+                    ),
+                }
+            }
+        }
+        if let Some((notifier, warning)) = &self.notifier {
+            let users = self
+                .backend_handler
+                .list_users_to_warn_of_expiration(now, now + *warning)
+                .await?;
+            for user in users {
+                if !self
+                    .backend_handler
+                    .mark_expiry_warning_sent(&user.user_id)
+                    .await?
+                {
+                    continue;
+                }
+                match notifier.warn_of_expiration(&user, now) {
+                    Ok(()) => summary.warned += 1,
+                    Err(e) => error!(
+                        "Could not warn of the expiration of the account {}: {:#}",
+                        user.user_id, e
+                    ),
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    pub fn start(self, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                if let Err(e) = self.run(Utc::now()).await {
+                    error!("Could not check the account expirations: {:#}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        handler::{CreateUserRequest, UpdateUserRequest},
+        model::{self, UserColumn},
+        sql_backend_handler::tests::*,
+    };
+    use pretty_assertions::assert_eq;
+    use sea_orm::{sea_query::Expr, ColumnTrait, EntityTrait, QueryFilter};
+    use std::sync::Mutex;
+
+    struct FakeNotifier(Arc<Mutex<Vec<String>>>);
+
+    impl ExpirationNotifier for FakeNotifier {
+        fn warn_of_expiration(&self, user: &User, _: DateTime<Utc>) -> Result<()> {
+            self.0.lock().unwrap().push(user.user_id.to_string());
+            Ok(())
+        }
+    }
+
+    fn expiration(handler: &SqlBackendHandler) -> (AccountExpiration, Arc<Mutex<Vec<String>>>) {
+        let warned = Arc::new(Mutex::new(Vec::new()));
+        (
+            AccountExpiration {
+                backend_handler: handler.clone(),
+                notifier: Some((
+                    Box::new(FakeNotifier(warned.clone())),
+                    chrono::Duration::days(7),
+                )),
+            },
+            warned,
+        )
+    }
+
+    /// Bypasses the validation, which refuses the past dates.
+    async fn set_expiration(handler: &SqlBackendHandler, user: &str, date: DateTime<Utc>) {
+        model::User::update_many()
+            .col_expr(UserColumn::ExpiresAt, Expr::value(date))
+            .filter(UserColumn::UserId.eq(user))
+            .exec(&handler.sql_pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expire_and_warn() {
+        let fixture = TestFixture::new().await;
+        let handler = &fixture.handler;
+        let now = Utc::now();
+        set_expiration(handler, "bob", now - chrono::Duration::hours(1)).await;
+        set_expiration(handler, "patrick", now + chrono::Duration::days(3)).await;
+        set_expiration(handler, "john", now + chrono::Duration::days(30)).await;
+        let (expiration, warned) = expiration(handler);
+        assert_eq!(
+            expiration.run(now).await.unwrap(),
+            ExpirationSummary {
+                expired: 1,
+                warned: 1
+            }
+        );
+        assert_eq!(
+            get_user_names(handler, None).await,
+            vec!["john", "nogroup", "patrick"]
+        );
+        assert_eq!(*warned.lock().unwrap(), vec!["patrick"]);
+        // Nothing new: no second warning.
+        assert_eq!(
+            expiration.run(now).await.unwrap(),
+            ExpirationSummary::default()
+        );
+        // A new date gets a new warning.
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("patrick"),
+                expires_at: Some(Some(now + chrono::Duration::days(5))),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(expiration.run(now).await.unwrap().warned, 1);
+        let later = now + chrono::Duration::days(24);
+        assert_eq!(
+            expiration.run(later).await.unwrap(),
+            ExpirationSummary {
+                expired: 1,
+                warned: 1
+            }
+        );
+        assert_eq!(*warned.lock().unwrap(), vec!["patrick", "patrick", "john"]);
+        assert_eq!(get_user_names(handler, None).await, vec!["john", "nogroup"]);
+    }
+
+    #[tokio::test]
+    async fn test_restore_expired_user() {
+        let fixture = TestFixture::new().await;
+        let handler = &fixture.handler;
+        let now = Utc::now();
+        set_expiration(handler, "bob", now - chrono::Duration::hours(1)).await;
+        let (expiration, _) = expiration(handler);
+        expiration.run(now).await.unwrap();
+        handler.restore_user(&UserId::new("bob")).await.unwrap();
+        assert_eq!(
+            handler
+                .get_user_details(&UserId::new("bob"))
+                .await
+                .unwrap()
+                .expires_at,
+            None
+        );
+        assert_eq!(
+            expiration.run(now).await.unwrap(),
+            ExpirationSummary::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keep_last_admin() {
+        let fixture = TestFixture::new().await;
+        let handler = &fixture.handler;
+        let admin_group = insert_group(handler, "lldap_admin").await;
+        insert_membership(handler, admin_group, "bob").await;
+        let now = Utc::now();
+        set_expiration(handler, "bob", now - chrono::Duration::hours(1)).await;
+        let (expiration, _) = expiration(handler);
+        assert_eq!(
+            expiration.run(now).await.unwrap(),
+            ExpirationSummary::default()
+        );
+        insert_membership(handler, admin_group, "patrick").await;
+        assert_eq!(expiration.run(now).await.unwrap().expired, 1);
+    }
+
+    #[tokio::test]
+    async fn test_refuse_past_expiration() {
+        let fixture = TestFixture::new().await;
+        let past = Utc::now() - chrono::Duration::days(1);
+        fixture
+            .handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("james"),
+                email: "james@example.com".into(),
+                expires_at: Some(past),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                expires_at: Some(Some(past)),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+    }
+}
@@ -26,12 +26,18 @@ use lldap_auth::{login, password_reset, registration, JWTClaims};
 use crate::{
     domain::{
         error::DomainError,
-        handler::{BackendHandler, BindRequest, LoginHandler, UserRequestFilter},
+        handler::{
+            BackendHandler, BindRequest, LoginHandler, UpdateUserRequest, UserRequestFilter,
+        },
         opaque_handler::OpaqueHandler,
         types::{GroupDetails, GroupName, UserColumn, UserId},
     },
     infra::{
-        access_control::{ReadonlyBackendHandler, UserReadableBackendHandler, ValidationResults},
+        access_control::{
+            ReadonlyBackendHandler, UserReadableBackendHandler, UserWriteableBackendHandler,
+            ValidationResults,
+        },
+        configuration::LoginIdentifier,
         tcp_backend_handler::*,
         tcp_server::{error_to_http_response, AppState, TcpError, TcpResult},
     },
@@ -52,18 +58,20 @@ async fn create_jwt<Handler: TcpBackendHandler>(
     handler: &Handler,
     key: &Hmac<Sha512>,
     user: &UserId,
+    login: Option<String>,
     groups: HashSet<GroupDetails>,
 ) -> SignedToken {
     let claims = JWTClaims {
         exp: Utc::now() + chrono::Duration::days(1),
         iat: Utc::now(),
         user: user.to_string(),
+        login,
         groups: groups
             .into_iter()
             .map(|g| g.display_name.into_string())
             .collect(),
     };
-    let expiry = claims.exp.naive_utc();
+    let expiry = claims.exp;
     let header = jwt::Header {
         algorithm: jwt::AlgorithmType::Hs512,
         ..Default::default()
@@ -76,6 +84,26 @@ async fn create_jwt<Handler: TcpBackendHandler>(
     token
 }
 
+/// The `login` claim of the tokens: the email of the user with `login_identifier = "email"`.
+async fn get_login_claim<Backend>(
+    data: &AppState<Backend>,
+    user: &UserId,
+) -> TcpResult<Option<String>>
+where
+    Backend: BackendHandler,
+{
+    if data.login_identifier != LoginIdentifier::Email {
+        return Ok(None);
+    }
+    let email = data
+        .get_readonly_handler()
+        .get_user_details(user)
+        .await?
+        .email
+        .into_string();
+    Ok(Some(email).filter(|email| !email.is_empty()))
+}
+
 fn parse_refresh_token(token: &str) -> TcpResult<(u64, UserId)> {
     match token.split_once('+') {
         None => Err(DomainError::AuthenticationError("Invalid refresh token".to_string()).into()),
@@ -106,33 +134,32 @@ where
 {
     let jwt_key = &data.jwt_key;
     let (refresh_token_hash, user) = get_refresh_token(request)?;
-    let found = data
+    let expiry_date = data
         .get_tcp_handler()
         .check_token(refresh_token_hash, &user)
-        .await?;
-    if !found {
-        return Err(TcpError::DomainError(DomainError::AuthenticationError(
-            "Invalid refresh token".to_string(),
-        )));
-    }
+        .await?
+        .ok_or_else(|| {
+            TcpError::DomainError(DomainError::AuthenticationError(
+                "Invalid refresh token".to_string(),
+            ))
+        })?;
+    // The short-lived refresh tokens are the ones created without "remember me": the new
+    // session token shouldn't outlive the browser either.
+    let session_only =
+        expiry_date - Utc::now() <= data.session_options.refresh_token_validity(false);
     let mut path = data.server_url.path().to_string();
     if !path.ends_with('/') {
         path.push('/');
     };
     let groups = data.get_readonly_handler().get_user_groups(&user).await?;
-    let token = create_jwt(data.get_tcp_handler(), jwt_key, &user, groups).await;
+    let login = get_login_claim(&data, &user).await?;
+    let token = create_jwt(data.get_tcp_handler(), jwt_key, &user, login, groups).await;
     Ok(HttpResponse::Ok()
-        .cookie(
-            Cookie::build("token", token.as_str())
-                .max_age(1.days())
-                .path(&path)
-                .http_only(true)
-                .same_site(SameSite::Strict)
-                .finish(),
-        )
+        .cookie(token_cookie(&data, &path, token.as_str(), session_only))
         .json(&login::ServerLoginResponse {
             token: token.as_str().to_owned(),
             refresh_token: None,
+            session_only,
         }))
 }
 
@@ -159,7 +186,24 @@ where
     let user_string = request
         .match_info()
         .get("user_id")
-        .ok_or_else(|| TcpError::BadRequest("Missing user ID".to_string()))?;
+        .ok_or_else(|| TcpError::BadRequest("Missing user ID".to_string()))?
+        .to_owned();
+    // The response is the same whatever happens, so that it doesn't tell whether the user
+    // exists: the lookup, the token and the email happen after it, so that its timing doesn't
+    // either.
+    actix_web::rt::spawn(async move {
+        if let Err(e) = start_password_reset(&data, &user_string).await {
+            warn!("Error starting the password reset: {:#?}", e);
+        }
+    });
+    Ok(())
+}
+
+/// The reason why there is no reset only goes to the logs.
+async fn start_password_reset<Backend>(data: &AppState<Backend>, user_string: &str) -> TcpResult<()>
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
     let user_results = data
         .get_readonly_handler()
         .list_users(
@@ -171,13 +215,26 @@ where
         )
         .await?;
     if user_results.is_empty() {
+        debug!(
+            r#"No user matching "{}" for the password reset"#,
+            user_string
+        );
         return Ok(());
     } else if user_results.len() > 1 {
-        return Err(TcpError::InternalServerError(
-            "Ambiguous user id or email".to_owned(),
-        ));
+        warn!(
+            r#"Ambiguous user id or email "{}" for the password reset"#,
+            user_string
+        );
+        return Ok(());
     }
     let user = &user_results[0].user;
+    if user.email.as_str().is_empty() {
+        debug!(
+            r#"User "{}" has no email for the password reset"#,
+            user.user_id
+        );
+        return Ok(());
+    }
     let token = match data
         .get_tcp_handler()
         .start_password_reset(&user.user_id)
@@ -187,22 +244,15 @@ where
         Some(token) => token,
     };
     if let Err(e) = super::mail::send_password_reset_email(
-        user.display_name
-            .as_deref()
-            .unwrap_or_else(|| user.user_id.as_str()),
-        user.email.as_str(),
+        user,
         &token,
         &data.server_url,
         &data.mail_options,
-    )
-    .await
-    {
+        &data.mail_templates,
+        &data.mail_queue,
+    ) {
         warn!("Error sending email: {:#?}", e);
         info!("Reset token: {}", token);
-        return Err(TcpError::InternalServerError(format!(
-            "Could not send email: {}",
-            e
-        )));
     }
     Ok(())
 }
@@ -245,7 +295,15 @@ where
         .delete_password_reset_token(token)
         .await;
     let groups = HashSet::new();
-    let token = create_jwt(data.get_tcp_handler(), &data.jwt_key, &user_id, groups).await;
+    let login = get_login_claim(&data, &user_id).await?;
+    let token = create_jwt(
+        data.get_tcp_handler(),
+        &data.jwt_key,
+        &user_id,
+        login,
+        groups,
+    )
+    .await;
     let mut path = data.server_url.path().to_string();
     if !path.ends_with('/') {
         path.push('/');
@@ -257,6 +315,7 @@ where
                 // Cookie is only valid to reset the password.
                 .path(format!("{}auth", path))
                 .http_only(true)
+                .secure(data.secure_cookies)
                 .same_site(SameSite::Strict)
                 .finish(),
         )
@@ -278,6 +337,76 @@ where
         .unwrap_or_else(error_to_http_response)
 }
 
+/// Applies the change of email address verified by the link, and notifies the old address.
+#[instrument(skip_all, level = "debug")]
+async fn post_email_verification<Backend>(
+    data: web::Data<AppState<Backend>>,
+    request: HttpRequest,
+) -> TcpResult<()>
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let token = request
+        .match_info()
+        .get("token")
+        .ok_or_else(|| TcpError::BadRequest("Missing verification token".to_owned()))?;
+    let (user_id, new_email) = data
+        .get_tcp_handler()
+        .finish_email_change(token)
+        .await
+        .map_err(|e| {
+            debug!("Email verification token error: {e:#}");
+            TcpError::NotFoundError("Wrong or expired verification link".to_owned())
+        })?;
+    let handler = data.get_user_writeable_handler();
+    let user = handler.get_user_details(&user_id).await?;
+    handler
+        .update_user(UpdateUserRequest {
+            user_id,
+            email: Some(new_email.as_str().into()),
+            ..Default::default()
+        })
+        .await?;
+    if !user.email.as_str().is_empty() {
+        if let Err(e) = super::mail::send_email_changed_email(
+            &user,
+            &new_email,
+            &data.server_url,
+            &data.mail_options,
+            &data.mail_templates,
+            &data.mail_queue,
+        ) {
+            warn!(
+                "Could not notify {} of the email change: {:#}",
+                user.user_id, e
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn post_email_verification_handler<Backend>(
+    data: web::Data<AppState<Backend>>,
+    request: HttpRequest,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    post_email_verification(data, request)
+        .await
+        .map(|()| HttpResponse::Ok().finish())
+        .unwrap_or_else(error_to_http_response)
+}
+
+async fn get_login_options_handler<Backend>(
+    data: web::Data<AppState<Backend>>,
+) -> web::Json<login::LoginOptions> {
+    web::Json(login::LoginOptions {
+        remember_me_default: data.session_options.remember_me_default,
+        login_with_email: data.login_identifier == LoginIdentifier::Email,
+    })
+}
+
 #[instrument(skip_all, level = "debug")]
 async fn get_logout<Backend>(
     data: web::Data<AppState<Backend>>,
@@ -305,6 +434,7 @@ where
                 .max_age(0.days())
                 .path(&path)
                 .http_only(true)
+                .secure(data.secure_cookies)
                 .same_site(SameSite::Strict)
                 .finish(),
         )
@@ -313,6 +443,7 @@ where
                 .max_age(0.days())
                 .path(format!("{}auth", path))
                 .http_only(true)
+                .secure(data.secure_cookies)
                 .same_site(SameSite::Strict)
                 .finish(),
         )
@@ -352,10 +483,31 @@ where
         .unwrap_or_else(error_to_api_response)
 }
 
+/// The cookie with the session token. Without a max age when `session_only`, so that the
+/// browser drops it when it's closed.
+fn token_cookie<'c, Backend>(
+    data: &AppState<Backend>,
+    path: &str,
+    token: &'c str,
+    session_only: bool,
+) -> Cookie<'c> {
+    let mut cookie = Cookie::build("token", token)
+        .path(path.to_owned())
+        .http_only(true)
+        .secure(data.secure_cookies)
+        .same_site(SameSite::Strict)
+        .finish();
+    if !session_only {
+        cookie.set_max_age(1.days());
+    }
+    cookie
+}
+
 #[instrument(skip_all, level = "debug")]
 async fn get_login_successful_response<Backend>(
     data: &web::Data<AppState<Backend>>,
     name: &UserId,
+    remember_me: bool,
 ) -> TcpResult<HttpResponse>
 where
     Backend: TcpBackendHandler + BackendHandler,
@@ -363,33 +515,34 @@ where
     // The authentication was successful, we need to fetch the groups to create the JWT
     // token.
     let groups = data.get_readonly_handler().get_user_groups(name).await?;
-    let (refresh_token, max_age) = data.get_tcp_handler().create_refresh_token(name).await?;
-    let token = create_jwt(data.get_tcp_handler(), &data.jwt_key, name, groups).await;
+    let validity = data.session_options.refresh_token_validity(remember_me);
+    let refresh_token = data
+        .get_tcp_handler()
+        .create_refresh_token(name, validity)
+        .await?;
+    let login = get_login_claim(data, name).await?;
+    let token = create_jwt(data.get_tcp_handler(), &data.jwt_key, name, login, groups).await;
     let refresh_token_plus_name = refresh_token + "+" + name.as_str();
     let mut path = data.server_url.path().to_string();
     if !path.ends_with('/') {
         path.push('/');
     };
+    let mut refresh_cookie = Cookie::build("refresh_token", refresh_token_plus_name.clone())
+        .path(format!("{}auth", path))
+        .http_only(true)
+        .secure(data.secure_cookies)
+        .same_site(SameSite::Strict)
+        .finish();
+    if remember_me {
+        refresh_cookie.set_max_age(validity.num_days().days());
+    }
     Ok(HttpResponse::Ok()
-        .cookie(
-            Cookie::build("token", token.as_str())
-                .max_age(1.days())
-                .path(&path)
-                .http_only(true)
-                .same_site(SameSite::Strict)
-                .finish(),
-        )
-        .cookie(
-            Cookie::build("refresh_token", refresh_token_plus_name.clone())
-                .max_age(max_age.num_days().days())
-                .path(format!("{}auth", path))
-                .http_only(true)
-                .same_site(SameSite::Strict)
-                .finish(),
-        )
+        .cookie(token_cookie(data, &path, token.as_str(), !remember_me))
+        .cookie(refresh_cookie)
         .json(&login::ServerLoginResponse {
             token: token.as_str().to_owned(),
             refresh_token: Some(refresh_token_plus_name),
+            session_only: !remember_me,
         }))
 }
 
@@ -401,11 +554,10 @@ async fn opaque_login_finish<Backend>(
 where
     Backend: TcpBackendHandler + BackendHandler + OpaqueHandler + 'static,
 {
-    let name = data
-        .get_opaque_handler()
-        .login_finish(request.into_inner())
-        .await?;
-    get_login_successful_response(&data, &name).await
+    let request = request.into_inner();
+    let remember_me = request.remember_me.unwrap_or(true);
+    let name = data.get_opaque_handler().login_finish(request).await?;
+    get_login_successful_response(&data, &name, remember_me).await
 }
 
 async fn opaque_login_finish_handler<Backend>(
@@ -428,13 +580,17 @@ async fn simple_login<Backend>(
 where
     Backend: TcpBackendHandler + BackendHandler + OpaqueHandler + LoginHandler + 'static,
 {
-    let login::ClientSimpleLoginRequest { username, password } = request.into_inner();
+    let login::ClientSimpleLoginRequest {
+        username,
+        password,
+        remember_me,
+    } = request.into_inner();
     let bind_request = BindRequest {
-        name: username.clone(),
+        name: username,
         password,
     };
-    data.get_login_handler().bind(bind_request).await?;
-    get_login_successful_response(&data, &username).await
+    let user_id = data.get_login_handler().bind(bind_request).await?;
+    get_login_successful_response(&data, &user_id, remember_me.unwrap_or(true)).await
 }
 
 async fn simple_login_handler<Backend>(
@@ -457,9 +613,8 @@ async fn post_authorize<Backend>(
 where
     Backend: TcpBackendHandler + BackendHandler + LoginHandler + 'static,
 {
-    let name = request.name.clone();
-    data.get_login_handler().bind(request.into_inner()).await?;
-    get_login_successful_response(&data, &name).await
+    let user_id = data.get_login_handler().bind(request.into_inner()).await?;
+    get_login_successful_response(&data, &user_id, true).await
 }
 
 async fn post_authorize_handler<Backend>(
@@ -620,6 +775,11 @@ pub(crate) fn check_if_token_is_valid<Backend: BackendHandler>(
     token_str: &str,
 ) -> Result<ValidationResults, actix_web::Error> {
     let token: Token<_> = VerifyWithKey::verify_with_key(token_str, &state.jwt_key)
+        .or_else(|e| match &state.previous_jwt_key {
+            // Signed before a rotation of the secret.
+            Some(key) => VerifyWithKey::verify_with_key(token_str, key),
+            None => Err(e),
+        })
         .map_err(|_| ErrorUnauthorized("Invalid JWT"))?;
     if token.claims().exp.lt(&Utc::now()) {
         return Err(ErrorUnauthorized("Expired JWT"));
@@ -660,6 +820,10 @@ where
         .service(
             web::resource("/simple/login").route(web::post().to(simple_login_handler::<Backend>)),
         )
+        .service(
+            web::resource("/login_options")
+                .route(web::get().to(get_login_options_handler::<Backend>)),
+        )
         .service(web::resource("/refresh").route(web::get().to(get_refresh_handler::<Backend>)))
         .service(web::resource("/logout").route(web::get().to(get_logout_handler::<Backend>)))
         .service(
@@ -682,6 +846,173 @@ where
         .service(
             web::resource("/reset/step2/{token}")
                 .route(web::get().to(get_password_reset_step2_handler::<Backend>)),
+        )
+        .service(
+            web::resource("/email/verify/{token}")
+                .route(web::post().to(post_email_verification_handler::<Backend>)),
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        model,
+        sql_backend_handler::{tests::*, SqlBackendHandler},
+    };
+    use actix_web::{http::StatusCode, test, App};
+    use pretty_assertions::assert_eq;
+    use sea_orm::EntityTrait;
+    use std::time::Duration;
+
+    async fn make_state(
+        login_identifier: LoginIdentifier,
+    ) -> web::Data<AppState<SqlBackendHandler>> {
+        let mut config = get_default_config();
+        config.login_identifier = login_identifier;
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        insert_user(&handler, "bob", "bob00").await;
+        let mut state = AppState::new_for_tests(handler);
+        state.login_identifier = login_identifier;
+        web::Data::new(state)
+    }
+
+    /// The claims of the session token of a successful login, or the status of the failure.
+    async fn simple_login(
+        state: &web::Data<AppState<SqlBackendHandler>>,
+        username: &str,
+        password: &str,
+    ) -> Result<JWTClaims, StatusCode> {
+        let app = test::init_service(App::new().app_data(state.clone()).service(
+            web::scope("/auth").configure(|cfg| configure_server::<SqlBackendHandler>(cfg, false)),
+        ))
+        .await;
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/auth/simple/login")
+                .set_json(serde_json::json!({ "username": username, "password": password }))
+                .to_request(),
+        )
+        .await;
+        if response.status() != StatusCode::OK {
+            return Err(response.status());
+        }
+        let response: login::ServerLoginResponse = test::read_body_json(response).await;
+        let token: Token<_> =
+            VerifyWithKey::verify_with_key(response.token.as_str(), &state.jwt_key).unwrap();
+        Ok(token.claims().clone())
+    }
+
+    #[actix_web::test]
+    async fn test_login_with_email() {
+        let state = make_state(LoginIdentifier::Email).await;
+        let claims = simple_login(&state, "Bob@bob.bob", "bob00").await.unwrap();
+        assert_eq!(claims.user, "bob");
+        assert_eq!(claims.login.as_deref(), Some("bob@bob.bob"));
+        // The user ID keeps working.
+        let claims = simple_login(&state, "bob", "bob00").await.unwrap();
+        assert_eq!(claims.user, "bob");
+        assert_eq!(claims.login.as_deref(), Some("bob@bob.bob"));
+        assert_eq!(
+            simple_login(&state, "bob@bob.bob", "wrong").await.err(),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_login_with_user_id() {
+        let state = make_state(LoginIdentifier::UserId).await;
+        let claims = simple_login(&state, "bob", "bob00").await.unwrap();
+        assert_eq!(claims.user, "bob");
+        assert_eq!(claims.login, None);
+        assert_eq!(
+            simple_login(&state, "bob@bob.bob", "bob00").await.err(),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    /// The reset tokens, once the resets started after the responses created `count` of them.
+    async fn wait_for_reset_tokens(
+        state: &web::Data<AppState<SqlBackendHandler>>,
+        count: usize,
+    ) -> Vec<model::password_reset_tokens::Model> {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let tokens = model::PasswordResetTokens::find()
+                    .all(&state.backend_handler.unsafe_get_handler().sql_pool)
+                    .await
+                    .unwrap();
+                if tokens.len() >= count {
+                    return tokens;
+                }
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("Timed out waiting for {} password reset token(s)", count))
+    }
+
+    #[actix_web::test]
+    async fn test_password_reset_response_does_not_depend_on_the_user() {
+        let state = make_state(LoginIdentifier::UserId).await;
+        let app = test::init_service(App::new().app_data(state.clone()).service(
+            web::scope("/auth").configure(|cfg| configure_server::<SqlBackendHandler>(cfg, true)),
+        ))
+        .await;
+        let mut responses = Vec::new();
+        for user in ["bob", "unknown", "bob@bob.bob", "unknown@bob.bob"] {
+            let response = test::call_service(
+                &app,
+                test::TestRequest::post()
+                    .uri(&format!("/auth/reset/step1/{}", user))
+                    .to_request(),
+            )
+            .await;
+            let status = response.status();
+            responses.push((status, test::read_body(response).await));
+        }
+        assert_eq!(responses[0].0, StatusCode::OK);
+        for response in &responses[1..] {
+            assert_eq!(response, &responses[0]);
+        }
+        // The resets of the known user still happen, after the response.
+        let tokens = wait_for_reset_tokens(&state, 2).await;
+        assert!(tokens.iter().all(|t| t.user_id == UserId::new("bob")));
+    }
+
+    #[actix_web::test]
+    async fn test_password_reset_with_email() {
+        let state = make_state(LoginIdentifier::Email).await;
+        let app = test::init_service(App::new().app_data(state.clone()).service(
+            web::scope("/auth").configure(|cfg| configure_server::<SqlBackendHandler>(cfg, true)),
+        ))
+        .await;
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/auth/reset/step1/Bob@bob.bob")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let tokens = wait_for_reset_tokens(&state, 1).await;
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].user_id, UserId::new("bob"));
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("/auth/reset/step2/{}", tokens[0].token))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: password_reset::ServerPasswordResetResponse =
+            test::read_body_json(response).await;
+        assert_eq!(response.user_id, "bob");
+        let token: Token<_> =
+            VerifyWithKey::verify_with_key(response.token.as_str(), &state.jwt_key).unwrap();
+        assert_eq!(token.claims().login.as_deref(), Some("bob@bob.bob"));
+    }
+}
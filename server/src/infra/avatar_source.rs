@@ -0,0 +1,380 @@
+//! Avatars fetched from an external source, e.g. the official headshots on a web server, with
+//! `avatar_source_url_template = "https://photos.example.com/{user_id}.jpg"`. The new users get
+//! theirs when they are created; `refreshAvatarFromSource` and `lldap refresh_avatars` fetch them
+//! again later.
+//!
+//! Only the scheme, host and port of the template can be reached: the user ID is percent-encoded
+//! in the URL, and the redirects elsewhere are refused.
+
+use crate::{
+    domain::{
+        handler::{UpdateUserRequest, UserBackendHandler},
+        types::{JpegPhoto, UserId},
+    },
+    infra::{configuration::Configuration, graphql::api::AvatarLimits},
+};
+use anyhow::{ensure, Context, Result};
+use async_trait::async_trait;
+use std::time::Duration;
+use tracing::{info, instrument, warn};
+use url::Url;
+
+/// Replaced with the user ID in the template.
+pub const USER_ID_PLACEHOLDER: &str = "{user_id}";
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REDIRECTS: usize = 3;
+/// The largest image downloaded, before it is downscaled to the avatar limits.
+const MAX_DOWNLOAD_SIZE: usize = 10 << 20;
+const JPEG_QUALITY: u8 = 90;
+
+#[derive(Clone)]
+pub struct AvatarSource {
+    template: String,
+    origin: url::Origin,
+    client: reqwest::Client,
+    limits: AvatarLimits,
+}
+
+impl std::fmt::Debug for AvatarSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AvatarSource")
+            .field("template", &self.template)
+            .finish()
+    }
+}
+
+impl AvatarSource {
+    /// None without `avatar_source_url_template`.
+    pub fn from_config(config: &Configuration) -> Result<Option<Self>> {
+        config
+            .avatar_source_url_template
+            .as_deref()
+            .map(|template| Self::new(template, AvatarLimits::from_config(config)))
+            .transpose()
+            .context("Invalid avatar_source_url_template")
+    }
+
+    pub fn new(template: &str, limits: AvatarLimits) -> Result<Self> {
+        ensure!(
+            template.contains(USER_ID_PLACEHOLDER),
+            "`{}` doesn't contain {}",
+            template,
+            USER_ID_PLACEHOLDER
+        );
+        let parse = |user_id: &str| Url::parse(&template.replace(USER_ID_PLACEHOLDER, user_id));
+        let url = parse("user").context(format!("`{}` is not a valid URL", template))?;
+        ensure!(
+            matches!(url.scheme(), "http" | "https") && url.has_host(),
+            "`{}` is not an HTTP or HTTPS URL",
+            template
+        );
+        let origin = url.origin();
+        ensure!(
+            parse("other").map(|u| u.origin()).ok() == Some(origin.clone()),
+            "{} can only be in the path or the query of `{}`",
+            USER_ID_PLACEHOLDER,
+            template
+        );
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .redirect(same_origin_redirects(origin.clone()))
+            .build()?;
+        Ok(Self {
+            template: template.to_owned(),
+            origin,
+            client,
+            limits,
+        })
+    }
+
+    /// The URL of the avatar of the user, always on the host of the template.
+    pub fn url(&self, user_id: &UserId) -> Result<Url> {
+        let url = Url::parse(
+            &self
+                .template
+                .replace(USER_ID_PLACEHOLDER, &urlencoding::encode(user_id.as_str())),
+        )?;
+        ensure!(
+            url.origin() == self.origin,
+            "The URL of the avatar of {} is not on the host of the template",
+            user_id
+        );
+        Ok(url)
+    }
+
+    #[instrument(skip(self), level = "debug", err)]
+    pub async fn fetch(&self, user_id: &UserId) -> Result<JpegPhoto> {
+        let url = self.url(user_id)?;
+        let mut response = self
+            .client
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context(format!("Could not fetch {}", url))?;
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .context(format!("Could not fetch {}", url))?
+        {
+            ensure!(
+                bytes.len() + chunk.len() <= MAX_DOWNLOAD_SIZE,
+                "The image at {} is over {} MB",
+                url,
+                MAX_DOWNLOAD_SIZE >> 20
+            );
+            bytes.extend_from_slice(&chunk);
+        }
+        prepare_avatar(bytes, &self.limits).context(format!("Invalid avatar at {}", url))
+    }
+}
+
+/// Follows the redirects on the same scheme, host and port only.
+fn same_origin_redirects(origin: url::Origin) -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() > MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if attempt.url().origin() == origin {
+            attempt.follow()
+        } else {
+            let error = format!("refused the redirect to {}", attempt.url());
+            attempt.error(error)
+        }
+    })
+}
+
+/// Downscales the image to fit in the largest dimension, as the web UI does before uploading,
+/// then checks it like the uploaded avatars.
+pub fn prepare_avatar(bytes: Vec<u8>, limits: &AvatarLimits) -> Result<JpegPhoto> {
+    let image = image::load_from_memory_with_format(&bytes, image::ImageFormat::Jpeg)
+        .context("Not a valid JPEG image")?;
+    let max_dimension = u32::try_from(limits.max_dimension).unwrap_or_default();
+    let bytes = if image.width() > max_dimension || image.height() > max_dimension {
+        let mut resized = std::io::Cursor::new(Vec::new());
+        image
+            .thumbnail(max_dimension, max_dimension)
+            .write_to(&mut resized, image::ImageOutputFormat::Jpeg(JPEG_QUALITY))?;
+        resized.into_inner()
+    } else {
+        bytes
+    };
+    limits.check(&bytes)?;
+    JpegPhoto::try_from(bytes)
+}
+
+#[async_trait]
+pub trait AvatarImporter: Send + Sync {
+    /// Fetches the avatar of the user from the source, and replaces theirs with it.
+    async fn import_avatar(&self, user_id: &UserId) -> Result<()>;
+    /// For the new users: imports the avatar in a background task, without failing the
+    /// creation. The failures are only logged.
+    fn import_avatar_in_background(&self, user_id: UserId);
+}
+
+#[derive(Clone)]
+pub struct SourceAvatarImporter<Backend> {
+    pub backend_handler: Backend,
+    pub source: AvatarSource,
+}
+
+#[async_trait]
+impl<Backend: UserBackendHandler + Clone + Send + Sync + 'static> AvatarImporter
+    for SourceAvatarImporter<Backend>
+{
+    async fn import_avatar(&self, user_id: &UserId) -> Result<()> {
+        let avatar = self.source.fetch(user_id).await?;
+        self.backend_handler
+            .update_user(UpdateUserRequest {
+                user_id: user_id.clone(),
+                avatar: Some(avatar),
+                ..Default::default()
+            })
+            .await?;
+        info!("Imported the avatar of {} from the source", user_id);
+        Ok(())
+    }
+
+    fn import_avatar_in_background(&self, user_id: UserId) {
+        let importer = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = importer.import_avatar(&user_id).await {
+                warn!("Could not import the avatar of {}: {:#}", user_id, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sql_backend_handler::tests::*;
+    use image::{ImageOutputFormat, Rgb, RgbImage};
+    use pretty_assertions::assert_eq;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const LIMITS: AvatarLimits = AvatarLimits {
+        max_size_kb: 1024,
+        max_dimension: 512,
+    };
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        RgbImage::from_pixel(width, height, Rgb([200, 100, 50]))
+            .write_to(&mut bytes, ImageOutputFormat::Jpeg(80))
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    fn dimensions(photo: &JpegPhoto) -> (u32, u32) {
+        let image = image::load_from_memory_with_format(
+            &photo.clone().into_bytes(),
+            image::ImageFormat::Jpeg,
+        )
+        .unwrap();
+        (image.width(), image.height())
+    }
+
+    /// Serves a picture at /bob.jpg, a redirect to it at /same.jpg, and a redirect to another
+    /// host at /other.jpg.
+    async fn start_photo_server() -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let len = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..len]).to_string();
+                let path = request.split(' ').nth(1).unwrap_or_default().to_owned();
+                let (status, headers, body) = match path.as_str() {
+                    "/bob.jpg" => ("200 OK", String::new(), jpeg(1024, 768)),
+                    "/same.jpg" => ("302 Found", "Location: /bob.jpg\r\n".to_owned(), vec![]),
+                    "/other.jpg" => (
+                        "302 Found",
+                        format!("Location: http://localhost:{}/bob.jpg\r\n", port),
+                        vec![],
+                    ),
+                    _ => ("404 Not Found", String::new(), vec![]),
+                };
+                let head = format!(
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    headers,
+                    body.len()
+                );
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(&body).await.unwrap();
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        for template in [
+            "https://photos.example.com/avatar.jpg",
+            "ftp://photos.example.com/{user_id}.jpg",
+            "https://{user_id}.example.com/avatar.jpg",
+            "https://photos.example.com:{user_id}/avatar.jpg",
+            "file:///photos/{user_id}.jpg",
+            "not a url {user_id}",
+        ] {
+            AvatarSource::new(template, LIMITS).expect_err(template);
+        }
+    }
+
+    #[test]
+    fn test_url_encodes_the_user_id() {
+        let source =
+            AvatarSource::new("https://photos.example.com/{user_id}.jpg?s=1", LIMITS).unwrap();
+        assert_eq!(
+            source.url(&UserId::new("bob")).unwrap().as_str(),
+            "https://photos.example.com/bob.jpg?s=1"
+        );
+        assert_eq!(
+            source.url(&UserId::new("../x@evil.com#")).unwrap().as_str(),
+            "https://photos.example.com/..%2Fx%40evil.com%23.jpg?s=1"
+        );
+    }
+
+    #[test]
+    fn test_prepare_avatar() {
+        assert_eq!(
+            dimensions(&prepare_avatar(jpeg(1024, 768), &LIMITS).unwrap()),
+            (512, 384)
+        );
+        assert_eq!(
+            dimensions(&prepare_avatar(jpeg(100, 200), &LIMITS).unwrap()),
+            (100, 200)
+        );
+        prepare_avatar(b"not an image".to_vec(), &LIMITS).unwrap_err();
+        prepare_avatar(
+            jpeg(1024, 768),
+            &AvatarLimits {
+                max_size_kb: 1,
+                max_dimension: 512,
+            },
+        )
+        .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_and_redirects() {
+        let port = start_photo_server().await;
+        let source = |name: &str| {
+            AvatarSource::new(&format!("http://127.0.0.1:{}/{}.jpg", port, name), LIMITS).unwrap()
+        };
+        let avatar = source("{user_id}")
+            .fetch(&UserId::new("bob"))
+            .await
+            .unwrap();
+        assert_eq!(dimensions(&avatar), (512, 384));
+        source("{user_id}")
+            .fetch(&UserId::new("unknown"))
+            .await
+            .unwrap_err();
+        source("{user_id}")
+            .fetch(&UserId::new("same"))
+            .await
+            .unwrap();
+        // localhost is not the host of the template.
+        source("{user_id}")
+            .fetch(&UserId::new("other"))
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_import_avatar() {
+        let port = start_photo_server().await;
+        let fixture = TestFixture::new().await;
+        let importer = SourceAvatarImporter {
+            backend_handler: fixture.handler.clone(),
+            source: AvatarSource::new(
+                &format!("http://127.0.0.1:{}/{{user_id}}.jpg", port),
+                LIMITS,
+            )
+            .unwrap(),
+        };
+        importer.import_avatar(&UserId::new("bob")).await.unwrap();
+        assert!(fixture
+            .handler
+            .get_user_avatar_hash(&UserId::new("bob"))
+            .await
+            .unwrap()
+            .is_some());
+        importer
+            .import_avatar(&UserId::new("patrick"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            fixture
+                .handler
+                .get_user_avatar_hash(&UserId::new("patrick"))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
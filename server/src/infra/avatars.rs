@@ -0,0 +1,263 @@
+//! `/avatars/{user_id}`: the avatars as plain images, for the `<img>` tags of the frontend. The
+//! `ETag` is the hash stored with the avatar, so that a revalidation doesn't read the image.
+
+use crate::{
+    domain::{
+        handler::BackendHandler,
+        types::{JpegPhoto, UserId},
+    },
+    infra::{
+        access_control::UserReadableBackendHandler,
+        auth_service::check_if_token_is_valid,
+        tcp_server::{error_to_http_response, AppState, TcpError, TcpResult},
+    },
+};
+use actix_web::{
+    http::header::{self, CacheControl, CacheDirective, EntityTag, IfNoneMatch},
+    web, HttpResponse,
+};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use tracing::instrument;
+
+/// Short, and only in the browser: the avatars change rarely, but the permissions can.
+const MAX_AGE_SECS: u32 = 60;
+
+fn is_cached(if_none_match: Option<&IfNoneMatch>, etag: &EntityTag) -> bool {
+    match if_none_match {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => false,
+    }
+}
+
+fn cache_headers(response: &mut actix_web::HttpResponseBuilder, etag: EntityTag) {
+    response
+        .insert_header(header::ETag(etag))
+        .insert_header(CacheControl(vec![
+            CacheDirective::Private,
+            CacheDirective::MaxAge(MAX_AGE_SECS),
+        ]));
+}
+
+#[instrument(skip_all, level = "debug", fields(user_id = %user_id))]
+async fn get_avatar<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+    user_id: UserId,
+    if_none_match: Option<IfNoneMatch>,
+) -> TcpResult<HttpResponse>
+where
+    Backend: BackendHandler + 'static,
+{
+    let validation_result = check_if_token_is_valid(&data, bearer.token())
+        .map_err(|e| TcpError::UnauthorizedError(e.to_string()))?;
+    let handler = data
+        .backend_handler
+        .get_readable_handler(&validation_result, &user_id)
+        .filter(|_| {
+            data.backend_handler.can_read_user_attribute(
+                &validation_result,
+                &user_id,
+                &"avatar".into(),
+            )
+        })
+        .ok_or_else(|| {
+            TcpError::UnauthorizedError("Unauthorized access to the avatar".to_owned())
+        })?;
+    let not_found = || TcpError::NotFoundError(format!("No avatar for {}", user_id));
+    let hash = handler
+        .get_user_avatar_hash(&user_id)
+        .await?
+        .ok_or_else(not_found)?;
+    let stored_etag = EntityTag::new_strong(hash);
+    if is_cached(if_none_match.as_ref(), &stored_etag) {
+        let mut response = HttpResponse::NotModified();
+        cache_headers(&mut response, stored_etag);
+        return Ok(response.finish());
+    }
+    // The avatar may have changed since the hash was read: the ETag comes from what is served.
+    let avatar = handler
+        .get_user_photo_attributes(&user_id)
+        .await?
+        .into_iter()
+        .find(|attribute| attribute.name.as_str() == "avatar")
+        .ok_or_else(not_found)?;
+    let etag = EntityTag::new_strong(avatar.value.content_hash());
+    let photo = avatar.value.unwrap::<JpegPhoto>();
+    if photo.is_empty() {
+        return Err(not_found());
+    }
+    let mut response = HttpResponse::Ok();
+    cache_headers(&mut response, etag);
+    Ok(response.content_type("image/jpeg").body(photo.into_bytes()))
+}
+
+async fn get_avatar_handler<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+    path: web::Path<String>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+) -> HttpResponse
+where
+    Backend: BackendHandler + 'static,
+{
+    get_avatar(
+        data,
+        bearer,
+        UserId::new(&path.into_inner()),
+        if_none_match.map(web::Header::into_inner),
+    )
+    .await
+    .unwrap_or_else(error_to_http_response)
+}
+
+pub(crate) fn configure<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: BackendHandler + 'static,
+{
+    cfg.route("/{user_id}", web::get().to(get_avatar_handler::<Backend>));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        handler::{UpdateUserRequest, UserBackendHandler},
+        sql_backend_handler::{tests::*, SqlBackendHandler},
+        types::Serialized,
+    };
+    use actix_web::{http::StatusCode, test, App};
+    use chrono::Utc;
+    use jwt::SignWithKey;
+    use lldap_auth::JWTClaims;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashSet;
+
+    fn token(state: &AppState<SqlBackendHandler>, user: &str) -> String {
+        let claims = JWTClaims {
+            exp: Utc::now() + chrono::Duration::days(1),
+            iat: Utc::now(),
+            user: user.to_owned(),
+            groups: HashSet::new(),
+            login: None,
+        };
+        let header = jwt::Header {
+            algorithm: jwt::AlgorithmType::Hs512,
+            ..Default::default()
+        };
+        jwt::Token::new(header, claims)
+            .sign_with_key(&state.jwt_key)
+            .unwrap()
+            .as_str()
+            .to_owned()
+    }
+
+    async fn set_avatar(handler: &SqlBackendHandler, avatar: JpegPhoto) {
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                avatar: Some(avatar),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    struct Response {
+        status: StatusCode,
+        etag: Option<String>,
+        body: Vec<u8>,
+    }
+
+    async fn get(
+        state: web::Data<AppState<SqlBackendHandler>>,
+        user: &str,
+        if_none_match: Option<&str>,
+    ) -> Response {
+        let token = token(&state, user);
+        let app = test::init_service(
+            App::new()
+                .app_data(state)
+                .service(web::scope("/avatars").configure(configure::<SqlBackendHandler>)),
+        )
+        .await;
+        let mut request = test::TestRequest::get()
+            .uri("/avatars/bob")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)));
+        if let Some(etag) = if_none_match {
+            request = request.insert_header((header::IF_NONE_MATCH, etag));
+        }
+        let response = test::call_service(&app, request.to_request()).await;
+        let status = response.status();
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .map(|v| v.to_str().unwrap().to_owned());
+        if status.is_success() || status == StatusCode::NOT_MODIFIED {
+            assert_eq!(
+                response.headers().get(header::CACHE_CONTROL).unwrap(),
+                "private, max-age=60"
+            );
+        }
+        let body = test::read_body(response).await.to_vec();
+        Response { status, etag, body }
+    }
+
+    #[actix_web::test]
+    async fn test_avatar_negotiation() {
+        let fixture = TestFixture::new().await;
+        let state = web::Data::new(AppState::new_for_tests(fixture.handler.clone()));
+        assert_eq!(
+            get(state.clone(), "bob", None).await.status,
+            StatusCode::NOT_FOUND
+        );
+
+        set_avatar(&fixture.handler, JpegPhoto::for_tests()).await;
+        let response = get(state.clone(), "bob", None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, JpegPhoto::for_tests().into_bytes());
+        let etag = response.etag.unwrap();
+        assert_eq!(
+            etag,
+            format!(
+                "\"{}\"",
+                Serialized::from(&JpegPhoto::for_tests()).content_hash()
+            )
+        );
+
+        let response = get(state.clone(), "bob", Some(&etag)).await;
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(response.etag.as_ref(), Some(&etag));
+        assert!(response.body.is_empty());
+        assert_eq!(
+            get(state.clone(), "bob", Some("\"other\", *")).await.status,
+            StatusCode::NOT_MODIFIED
+        );
+
+        // A new avatar invalidates the cached one.
+        let mut bytes = JpegPhoto::for_tests().into_bytes();
+        // Still a valid JPEG: the data after the end marker is ignored.
+        bytes.extend_from_slice(b"new");
+        set_avatar(
+            &fixture.handler,
+            JpegPhoto::try_from(bytes.clone()).unwrap(),
+        )
+        .await;
+        let response = get(state.clone(), "bob", Some(&etag)).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, bytes);
+        assert_ne!(response.etag, Some(etag));
+    }
+
+    #[actix_web::test]
+    async fn test_avatar_permissions() {
+        let fixture = TestFixture::new().await;
+        set_avatar(&fixture.handler, JpegPhoto::for_tests()).await;
+        let state = web::Data::new(AppState::new_for_tests(fixture.handler.clone()));
+        assert_eq!(get(state.clone(), "bob", None).await.status, StatusCode::OK);
+        assert_eq!(
+            get(state, "patrick", None).await.status,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -0,0 +1,511 @@
+use crate::{
+    domain::{
+        model::{self, UserColumn},
+        sql_tables::{get_private_key_info, DbConnection, PrivateKeyHash, LAST_SCHEMA_VERSION},
+        types::{AttributeType, UserId},
+    },
+    infra::configuration::Configuration,
+};
+use anyhow::{bail, ensure, Context, Result};
+use lldap_auth::opaque::server::ServerRegistration;
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseTransaction, DbBackend, DbErr,
+    EntityTrait, IntoActiveModel, Iterable, QueryFilter, QuerySelect, Statement, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    path::Path,
+};
+use tracing::{info, instrument, warn};
+
+const DATA_FILE_NAME: &str = "lldap_data.json";
+const SERVER_KEY_FILE_NAME: &str = "server_key";
+const BACKUP_FORMAT_VERSION: u32 = 1;
+// Stay well below the limit of bound parameters per statement of SQLite.
+const INSERT_BATCH_SIZE: usize = 100;
+
+/// The contents of all the tables needed to restore an LLDAP instance. The sessions and password
+/// reset tokens are not included.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct BackupData {
+    pub format_version: u32,
+    pub lldap_version: String,
+    pub schema_version: i16,
+    /// Hash of the private key that was used to create the password records.
+    pub private_key_hash: Option<PrivateKeyHash>,
+    pub users: Vec<model::users::Model>,
+    pub groups: Vec<model::groups::Model>,
+    pub memberships: Vec<model::memberships::Model>,
+    pub user_attribute_schema: Vec<model::user_attribute_schema::Model>,
+    pub user_attributes: Vec<model::user_attributes::Model>,
+    pub user_object_classes: Vec<model::user_object_classes::Model>,
+    pub group_attribute_schema: Vec<model::group_attribute_schema::Model>,
+    pub group_attributes: Vec<model::group_attributes::Model>,
+    pub group_object_classes: Vec<model::group_object_classes::Model>,
+}
+
+/// Reads the whole database in a single transaction, to get a consistent snapshot even if the
+/// server is running.
+#[instrument(skip(pool), level = "debug", err)]
+pub async fn dump(pool: &DbConnection, exclude_avatars: bool) -> Result<BackupData> {
+    let private_key_hash = get_private_key_info(pool)
+        .await?
+        .map(|info| info.private_key_hash);
+    let transaction = pool.begin().await?;
+    let user_attribute_schema = model::UserAttributeSchema::find().all(&transaction).await?;
+    let group_attribute_schema = model::GroupAttributeSchema::find()
+        .all(&transaction)
+        .await?;
+    let mut user_attributes = model::UserAttributes::find().all(&transaction).await?;
+    let mut group_attributes = model::GroupAttributes::find().all(&transaction).await?;
+    if exclude_avatars {
+        let user_pictures = user_attribute_schema
+            .iter()
+            .filter(|a| a.attribute_type == AttributeType::JpegPhoto)
+            .map(|a| &a.attribute_name)
+            .collect::<HashSet<_>>();
+        user_attributes.retain(|a| !user_pictures.contains(&a.attribute_name));
+        let group_pictures = group_attribute_schema
+            .iter()
+            .filter(|a| a.attribute_type == AttributeType::JpegPhoto)
+            .map(|a| &a.attribute_name)
+            .collect::<HashSet<_>>();
+        group_attributes.retain(|a| !group_pictures.contains(&a.attribute_name));
+    }
+    let data = BackupData {
+        format_version: BACKUP_FORMAT_VERSION,
+        lldap_version: env!("CARGO_PKG_VERSION").to_owned(),
+        schema_version: LAST_SCHEMA_VERSION.0,
+        private_key_hash,
+        users: model::User::find().all(&transaction).await?,
+        groups: model::Group::find().all(&transaction).await?,
+        memberships: model::Membership::find().all(&transaction).await?,
+        user_attribute_schema,
+        user_attributes,
+        user_object_classes: model::UserObjectClasses::find().all(&transaction).await?,
+        group_attribute_schema,
+        group_attributes,
+        group_object_classes: model::GroupObjectClasses::find().all(&transaction).await?,
+    };
+    transaction.commit().await?;
+    Ok(data)
+}
+
+async fn insert_all<A>(
+    transaction: &DatabaseTransaction,
+    models: Vec<<A::Entity as EntityTrait>::Model>,
+) -> Result<(), DbErr>
+where
+    A: ActiveModelTrait + Send,
+    <A::Entity as EntityTrait>::Model: IntoActiveModel<A>,
+{
+    for batch in models.chunks(INSERT_BATCH_SIZE) {
+        A::Entity::insert_many(
+            batch
+                .iter()
+                .cloned()
+                .map(IntoActiveModel::into_active_model),
+        )
+        .exec_without_returning(transaction)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Checks that the password records can be read. They are only usable with the key that
+/// created them, which is checked separately.
+fn check_password_files(data: &BackupData) -> Result<()> {
+    for user in &data.users {
+        if let Some(password_file) = &user.password_hash {
+            ServerRegistration::deserialize(password_file).map_err(|e| {
+                anyhow::anyhow!("Invalid password record for user {}: {}", user.user_id, e)
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the data can be imported by this version, and that its password records are
+/// valid.
+fn check_importable(data: &BackupData) -> Result<()> {
+    ensure!(
+        data.format_version <= BACKUP_FORMAT_VERSION,
+        "The archive was created by a newer version of LLDAP ({})",
+        data.lldap_version
+    );
+    ensure!(
+        data.schema_version <= LAST_SCHEMA_VERSION.0,
+        "The archive was created by a newer version of LLDAP ({}), with database schema version {}",
+        data.lldap_version,
+        data.schema_version
+    );
+    check_password_files(data)
+}
+
+/// The archives of older versions don't have the hashes.
+fn with_value_hashes(
+    user_attributes: Vec<model::user_attributes::Model>,
+) -> Vec<model::user_attributes::Model> {
+    user_attributes
+        .into_iter()
+        .map(|a| model::user_attributes::Model {
+            value_hash: Some(a.value.content_hash()),
+            ..a
+        })
+        .collect()
+}
+
+async fn replace_schema(transaction: &DatabaseTransaction, data: &mut BackupData) -> Result<()> {
+    model::UserAttributeSchema::delete_many()
+        .exec(transaction)
+        .await?;
+    model::GroupAttributeSchema::delete_many()
+        .exec(transaction)
+        .await?;
+    model::UserObjectClasses::delete_many()
+        .exec(transaction)
+        .await?;
+    model::GroupObjectClasses::delete_many()
+        .exec(transaction)
+        .await?;
+    insert_all::<model::user_attribute_schema::ActiveModel>(
+        transaction,
+        std::mem::take(&mut data.user_attribute_schema),
+    )
+    .await?;
+    insert_all::<model::group_attribute_schema::ActiveModel>(
+        transaction,
+        std::mem::take(&mut data.group_attribute_schema),
+    )
+    .await?;
+    insert_all::<model::user_object_classes::ActiveModel>(
+        transaction,
+        std::mem::take(&mut data.user_object_classes),
+    )
+    .await?;
+    insert_all::<model::group_object_classes::ActiveModel>(
+        transaction,
+        std::mem::take(&mut data.group_object_classes),
+    )
+    .await?;
+    Ok(())
+}
+
+/// The group ids are inserted explicitly, the sequence has to catch up.
+async fn update_group_id_sequence(transaction: &DatabaseTransaction) -> Result<()> {
+    if transaction.get_database_backend() == DbBackend::Postgres {
+        transaction
+            .execute(Statement::from_string(
+                DbBackend::Postgres,
+                "SELECT setval(pg_get_serial_sequence('groups', 'group_id'), COALESCE(MAX(group_id), 0) + 1, false) FROM groups".to_owned(),
+            ))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Restores the data in a database that has no users nor groups. The schema must already be
+/// up to date.
+#[instrument(skip_all, level = "debug", err)]
+pub async fn restore(pool: &DbConnection, mut data: BackupData) -> Result<()> {
+    check_importable(&data)?;
+    let transaction = pool.begin().await?;
+    if model::User::find().one(&transaction).await?.is_some()
+        || model::Group::find().one(&transaction).await?.is_some()
+    {
+        bail!("The database is not empty, refusing to import into it");
+    }
+    info!(
+        "Importing {} users and {} groups",
+        data.users.len(),
+        data.groups.len()
+    );
+    // The default attributes were created along with the schema, they are part of the backup.
+    replace_schema(&transaction, &mut data).await?;
+    insert_all::<model::users::ActiveModel>(&transaction, data.users).await?;
+    insert_all::<model::groups::ActiveModel>(&transaction, data.groups).await?;
+    insert_all::<model::memberships::ActiveModel>(&transaction, data.memberships).await?;
+    insert_all::<model::user_attributes::ActiveModel>(
+        &transaction,
+        with_value_hashes(data.user_attributes),
+    )
+    .await?;
+    insert_all::<model::group_attributes::ActiveModel>(&transaction, data.group_attributes).await?;
+    update_group_id_sequence(&transaction).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Makes the directory an exact copy of the data, in a single transaction, for a replica. The
+/// users are updated in place rather than recreated, so that their sessions survive; the users
+/// that are not in the data are deleted.
+#[instrument(skip_all, level = "debug", err)]
+pub async fn mirror(pool: &DbConnection, mut data: BackupData) -> Result<()> {
+    check_importable(&data)?;
+    let transaction = pool.begin().await?;
+    // Everything but the users is recreated. Deleting the groups also deletes the memberships
+    // and the group attributes.
+    model::UserAttributes::delete_many()
+        .exec(&transaction)
+        .await?;
+    model::Membership::delete_many().exec(&transaction).await?;
+    model::Group::delete_many().exec(&transaction).await?;
+    replace_schema(&transaction, &mut data).await?;
+
+    let user_ids = data
+        .users
+        .iter()
+        .map(|u| u.user_id.clone())
+        .collect::<HashSet<_>>();
+    let removed_users = model::User::find()
+        .select_only()
+        .column(UserColumn::UserId)
+        .into_tuple::<UserId>()
+        .all(&transaction)
+        .await?
+        .into_iter()
+        .filter(|user_id| !user_ids.contains(user_id))
+        .collect::<Vec<_>>();
+    for batch in removed_users.chunks(INSERT_BATCH_SIZE) {
+        model::User::delete_many()
+            .filter(UserColumn::UserId.is_in(batch.iter().cloned()))
+            .exec(&transaction)
+            .await?;
+    }
+    // Two users may have swapped their emails: the unique index is only checked once all the
+    // users have their new email.
+    model::User::update_many()
+        .col_expr(
+            UserColumn::LowercaseEmail,
+            Expr::value(Option::<String>::None),
+        )
+        .exec(&transaction)
+        .await?;
+    for batch in data.users.chunks(INSERT_BATCH_SIZE) {
+        model::User::insert_many(
+            batch
+                .iter()
+                .cloned()
+                .map(IntoActiveModel::into_active_model),
+        )
+        .on_conflict(
+            OnConflict::column(UserColumn::UserId)
+                .update_columns(UserColumn::iter().filter(|c| *c != UserColumn::UserId))
+                .to_owned(),
+        )
+        .exec_without_returning(&transaction)
+        .await?;
+    }
+
+    insert_all::<model::groups::ActiveModel>(&transaction, data.groups).await?;
+    insert_all::<model::memberships::ActiveModel>(&transaction, data.memberships).await?;
+    insert_all::<model::user_attributes::ActiveModel>(
+        &transaction,
+        with_value_hashes(data.user_attributes),
+    )
+    .await?;
+    insert_all::<model::group_attributes::ActiveModel>(&transaction, data.group_attributes).await?;
+    update_group_id_sequence(&transaction).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+fn append_file<W: Write>(
+    archive: &mut tar::Builder<W>,
+    name: &str,
+    contents: &[u8],
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    header.set_cksum();
+    archive.append_data(&mut header, name, contents)
+}
+
+/// Writes the data and the server key to a gzipped tar archive. Since it contains the server
+/// key, the archive is only readable by its owner.
+pub fn write_archive(path: &Path, data: &BackupData, server_key: &[u8]) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Could not create the archive `{}`", path.display()))?;
+    if cfg!(unix) {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
+        file,
+        flate2::Compression::default(),
+    ));
+    append_file(&mut archive, DATA_FILE_NAME, &serde_json::to_vec(data)?)?;
+    append_file(&mut archive, SERVER_KEY_FILE_NAME, server_key)?;
+    archive.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Reads an archive created by `write_archive`, returning the data and the server key.
+pub fn read_archive(path: &Path) -> Result<(BackupData, Vec<u8>)> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Could not open the archive `{}`", path.display()))?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    let mut data = None;
+    let mut server_key = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        match name.as_str() {
+            DATA_FILE_NAME => {
+                data = Some(
+                    serde_json::from_slice(&contents)
+                        .with_context(|| format!("while parsing `{}`", DATA_FILE_NAME))?,
+                )
+            }
+            SERVER_KEY_FILE_NAME => server_key = Some(contents),
+            _ => warn!("Ignoring unknown file in the archive: {}", name),
+        }
+    }
+    match (data, server_key) {
+        (Some(data), Some(server_key)) => Ok((data, server_key)),
+        _ => bail!(
+            "Invalid archive: it should contain `{}` and `{}`",
+            DATA_FILE_NAME,
+            SERVER_KEY_FILE_NAME
+        ),
+    }
+}
+
+/// Checks that the configured server key is the one from the archive, and that it is the one
+/// that created the password records of the archive.
+pub fn check_server_key(
+    data: &BackupData,
+    server_key: &[u8],
+    config: &Configuration,
+) -> Result<()> {
+    ensure!(
+        config.get_server_setup().serialize().as_slice() == server_key,
+        "The configured server key is not the one from the archive. Remove the `key_seed` and point `key_file` to a non-existent file to restore the key from the archive, or extract its `{}` file",
+        SERVER_KEY_FILE_NAME
+    );
+    if let Some(private_key_hash) = &data.private_key_hash {
+        ensure!(
+            private_key_hash == &config.get_private_key_info().private_key_hash,
+            "The server key in the archive is not the one that was used for the passwords"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        handler::{BindRequest, LoginHandler, UserBackendHandler, UserRequestFilter},
+        sql_backend_handler::{tests::*, SqlBackendHandler},
+        types::{JpegPhoto, Serialized, UserId},
+    };
+    use pretty_assertions::assert_eq;
+
+    async fn set_avatar(handler: &SqlBackendHandler, user_id: &str) {
+        model::UserAttributes::insert(model::user_attributes::ActiveModel::new(
+            UserId::new(user_id),
+            "avatar".into(),
+            Serialized::from(&JpegPhoto::for_tests()),
+        ))
+        .exec(&handler.sql_pool)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_export_import_round_trip() {
+        let fixture = TestFixture::new().await;
+        insert_user(&fixture.handler, "alice", "alice_pass").await;
+        set_avatar(&fixture.handler, "alice").await;
+        let data = dump(&fixture.handler.sql_pool, false).await.unwrap();
+        assert_eq!(data.users.len(), 5);
+        assert_eq!(data.groups.len(), 3);
+
+        let file = std::env::temp_dir().join(format!("lldap-test-{}.tar.gz", uuid::Uuid::new_v4()));
+        let server_key = fixture.handler.config.get_server_setup().serialize();
+        write_archive(&file, &data, &server_key).unwrap();
+        let (read_data, read_key) = read_archive(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(read_data, data);
+        assert_eq!(read_key, server_key.to_vec());
+
+        // Import in a fresh database, with the same server key.
+        let handler =
+            SqlBackendHandler::new(fixture.handler.config.clone(), get_initialized_db().await);
+        restore(&handler.sql_pool, read_data).await.unwrap();
+        assert_eq!(
+            get_user_names(&handler, None).await,
+            vec!["alice", "bob", "john", "nogroup", "patrick"]
+        );
+        assert_eq!(
+            get_user_names(
+                &handler,
+                Some(UserRequestFilter::MemberOf("Best Group".into()))
+            )
+            .await,
+            vec!["bob", "patrick"]
+        );
+        handler
+            .bind(BindRequest {
+                name: UserId::new("alice"),
+                password: "alice_pass".to_owned(),
+            })
+            .await
+            .unwrap();
+        let alice = handler
+            .get_user_details(&UserId::new("alice"))
+            .await
+            .unwrap();
+        assert!(alice.attributes.iter().any(|a| a.name.as_str() == "avatar"));
+        // New groups don't collide with the imported ones.
+        insert_group(&handler, "New Group").await;
+    }
+
+    #[tokio::test]
+    async fn test_export_exclude_avatars() {
+        let fixture = TestFixture::new().await;
+        set_avatar(&fixture.handler, "bob").await;
+        let data = dump(&fixture.handler.sql_pool, true).await.unwrap();
+        assert!(!data
+            .user_attributes
+            .iter()
+            .any(|a| a.attribute_name.as_str() == "avatar"));
+        assert!(data
+            .user_attributes
+            .iter()
+            .any(|a| a.attribute_name.as_str() == "first_name"));
+    }
+
+    #[tokio::test]
+    async fn test_import_into_non_empty_database() {
+        let fixture = TestFixture::new().await;
+        let data = dump(&fixture.handler.sql_pool, false).await.unwrap();
+        let error = restore(&fixture.handler.sql_pool, data).await.unwrap_err();
+        assert!(error.to_string().contains("not empty"), "{}", error);
+    }
+
+    #[test]
+    fn test_check_server_key_mismatch() {
+        let config = get_default_config();
+        let other_config = get_default_config();
+        let server_key = config.get_server_setup().serialize();
+        let mut data = BackupData {
+            private_key_hash: Some(config.get_private_key_info().private_key_hash),
+            ..Default::default()
+        };
+        check_server_key(&data, &server_key, &config).unwrap();
+        // The configured key is different.
+        check_server_key(&data, &server_key, &other_config).unwrap_err();
+        // The passwords were created with another key.
+        data.private_key_hash = Some(other_config.get_private_key_info().private_key_hash);
+        check_server_key(&data, &server_key, &config).unwrap_err();
+    }
+}
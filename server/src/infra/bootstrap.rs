@@ -0,0 +1,509 @@
+//! Declarative management of the users and groups: `lldap bootstrap --file bootstrap.toml`, or
+//! `bootstrap_file` in the configuration to apply the file at every startup.
+//!
+//! The file lists the groups, and the users with their groups. Applying it creates what is
+//! missing and updates the fields that changed. With `prune`, the users, groups and memberships
+//! that are not in the file are removed, except the admin user and the built-in `lldap_*` groups.
+//!
+//! The passwords in the file (or in the `password_file` of the users) are only used to create the
+//! users: changing them afterwards has no effect, and they can be removed from the file once
+//! applied.
+
+use crate::{
+    domain::{
+        handler::{GroupListerBackendHandler, UserListerBackendHandler},
+        sql_backend_handler::SqlBackendHandler,
+        types::{Email, GroupName, User, UserId},
+        validation::{normalize_email, validate_email},
+    },
+    infra::{
+        change_plan::{Change, FieldChange, InitialPassword, Plan, UserField, UserToCreate},
+        provisioning::check_new_password,
+        secret_input::read_secret_file,
+    },
+};
+use anyhow::{ensure, Context, Result};
+use figment::{
+    providers::{Format, Json, Toml},
+    Figment,
+};
+use secstr::SecUtf8;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+/// Never deleted by `prune`.
+const PROTECTED_GROUPS: [&str; 3] = [
+    "lldap_admin",
+    "lldap_password_manager",
+    "lldap_strict_readonly",
+];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BootstrapFile {
+    #[serde(default)]
+    pub groups: Vec<GroupSpec>,
+    #[serde(default)]
+    pub users: Vec<UserSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupSpec {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserSpec {
+    pub id: String,
+    pub email: String,
+    pub display_name: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    /// Only used to create the user.
+    pub password: Option<SecUtf8>,
+    /// File containing the password instead, relative to the bootstrap file.
+    pub password_file: Option<PathBuf>,
+    /// Email an invitation to choose a password when creating the user.
+    #[serde(default)]
+    pub invite: bool,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+/// Reads a TOML file, or a JSON one with the `.json` extension.
+pub fn read_file(path: &Path) -> Result<BootstrapFile> {
+    // The figment providers ignore the missing files.
+    ensure!(
+        path.is_file(),
+        "The file `{}` doesn't exist",
+        path.display()
+    );
+    let figment = if path.extension().is_some_and(|e| e == "json") {
+        Figment::from(Json::file(path))
+    } else {
+        Figment::from(Toml::file(path))
+    };
+    let mut file: BootstrapFile = figment
+        .extract()
+        .context(format!("while reading `{}`", path.display()))?;
+    read_password_files(&mut file, path.parent().unwrap_or_else(|| Path::new("")))
+        .context(format!("in `{}`", path.display()))?;
+    validate(&file).context(format!("in `{}`", path.display()))?;
+    Ok(file)
+}
+
+fn read_password_files(file: &mut BootstrapFile, dir: &Path) -> Result<()> {
+    for user in &mut file.users {
+        if let Some(password_file) = user.password_file.take() {
+            ensure!(
+                user.password.is_none(),
+                "The user {} has both a password and a password_file",
+                user.id
+            );
+            user.password = Some(
+                read_secret_file(&dir.join(password_file))
+                    .context(format!("for the user {}", user.id))?,
+            );
+        }
+    }
+    Ok(())
+}
+
+fn validate(file: &BootstrapFile) -> Result<()> {
+    let mut user_ids = HashSet::new();
+    for user in &file.users {
+        ensure!(
+            user_ids.insert(UserId::new(&user.id)),
+            "The user {} is listed twice",
+            user.id
+        );
+        ensure!(
+            !(user.invite && user.password.is_some()),
+            "The user {} has both a password and an invitation",
+            user.id
+        );
+        if let Some(password) = &user.password {
+            check_new_password(password).context(format!("for the user {}", user.id))?;
+        }
+        validate_email(&normalize_email(&user.email))
+            .context(format!("for the user {}", user.id))?;
+    }
+    let mut group_names = HashSet::new();
+    for group in &file.groups {
+        ensure!(
+            group_names.insert(GroupName::from(group.name.as_str())),
+            "The group {} is listed twice",
+            group.name
+        );
+    }
+    Ok(())
+}
+
+fn string_attribute(user: &User, name: &str) -> Option<String> {
+    user.attributes
+        .iter()
+        .find(|a| a.name.as_str() == name)
+        .map(|a| a.value.unwrap::<String>())
+}
+
+fn field_changes(user: &User, spec: &UserSpec) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    if user.email != Email::from(normalize_email(&spec.email)) {
+        changes.push(FieldChange {
+            field: UserField::Email,
+            old: Some(user.email.to_string()),
+            new: spec.email.clone(),
+        });
+    }
+    // The fields missing from the file are left as they are.
+    for (field, old, new) in [
+        (
+            UserField::DisplayName,
+            user.display_name.clone(),
+            &spec.display_name,
+        ),
+        (
+            UserField::FirstName,
+            string_attribute(user, "first_name"),
+            &spec.first_name,
+        ),
+        (
+            UserField::LastName,
+            string_attribute(user, "last_name"),
+            &spec.last_name,
+        ),
+    ] {
+        if let Some(new) = new {
+            if old.as_ref() != Some(new) {
+                changes.push(FieldChange {
+                    field,
+                    old,
+                    new: new.clone(),
+                });
+            }
+        }
+    }
+    changes
+}
+
+fn user_to_create(spec: &UserSpec) -> UserToCreate {
+    UserToCreate {
+        user_id: UserId::new(&spec.id),
+        email: spec.email.as_str().into(),
+        display_name: spec.display_name.clone(),
+        first_name: spec.first_name.clone(),
+        last_name: spec.last_name.clone(),
+        avatar: None,
+        hosts: Vec::new(),
+        phone: None,
+        mobile: None,
+        postal_address: None,
+        locality: None,
+        state_or_province: None,
+        password: match (&spec.password, spec.invite) {
+            (Some(password), _) => InitialPassword::Password(password.clone()),
+            (None, true) => InitialPassword::Invite,
+            (None, false) => InitialPassword::None,
+        },
+    }
+}
+
+/// Compares the file with the database. `admin` is the admin user of the configuration, which is
+/// never removed.
+pub async fn plan(
+    handler: &SqlBackendHandler,
+    file: &BootstrapFile,
+    prune: bool,
+    admin: &UserId,
+) -> Result<Plan> {
+    let existing_groups = handler
+        .list_groups(None)
+        .await?
+        .into_iter()
+        .map(|g| g.display_name)
+        .collect::<HashSet<_>>();
+    let existing_users = handler
+        .list_users(None, true)
+        .await?
+        .into_iter()
+        .map(|u| (u.user.user_id.clone(), u))
+        .collect::<HashMap<_, _>>();
+    // The groups of the users are created as well.
+    let mut desired_groups = Vec::<GroupName>::new();
+    for name in file
+        .groups
+        .iter()
+        .map(|g| &g.name)
+        .chain(file.users.iter().flat_map(|u| &u.groups))
+    {
+        let name = GroupName::from(name.as_str());
+        if !desired_groups.contains(&name) {
+            desired_groups.push(name);
+        }
+    }
+
+    let mut creations = Vec::new();
+    let mut updates = Vec::new();
+    let mut additions = Vec::new();
+    let mut removals = Vec::new();
+    for name in &desired_groups {
+        if !existing_groups.contains(name) {
+            creations.push(Change::CreateGroup {
+                group: name.clone(),
+                description: None,
+            });
+        }
+    }
+    for spec in &file.users {
+        let user_id = UserId::new(&spec.id);
+        let desired = spec
+            .groups
+            .iter()
+            .map(|g| GroupName::from(g.as_str()))
+            .collect::<HashSet<_>>();
+        let current = match existing_users.get(&user_id) {
+            None => {
+                creations.push(Change::CreateUser(user_to_create(spec)));
+                HashSet::new()
+            }
+            Some(existing) => {
+                let fields = field_changes(&existing.user, spec);
+                if !fields.is_empty() {
+                    updates.push(Change::UpdateUser {
+                        user_id: user_id.clone(),
+                        changes: fields,
+                    });
+                }
+                existing
+                    .groups
+                    .iter()
+                    .flatten()
+                    .map(|g| g.display_name.clone())
+                    .collect()
+            }
+        };
+        for group in spec.groups.iter().map(|g| GroupName::from(g.as_str())) {
+            if !current.contains(&group) {
+                additions.push(Change::AddMembership {
+                    user_id: user_id.clone(),
+                    group,
+                });
+            }
+        }
+        if prune {
+            for group in current.difference(&desired) {
+                if &user_id == admin && group.as_str() == "lldap_admin" {
+                    continue;
+                }
+                removals.push(Change::RemoveMembership {
+                    user_id: user_id.clone(),
+                    group: group.clone(),
+                });
+            }
+        }
+    }
+    let mut deletions = Vec::new();
+    if prune {
+        let listed = file
+            .users
+            .iter()
+            .map(|u| UserId::new(&u.id))
+            .collect::<HashSet<_>>();
+        let mut users = existing_users
+            .keys()
+            .filter(|id| !listed.contains(*id) && *id != admin)
+            .cloned()
+            .collect::<Vec<_>>();
+        users.sort();
+        deletions.extend(
+            users
+                .into_iter()
+                .map(|user_id| Change::DeleteUser { user_id }),
+        );
+        let mut groups = existing_groups
+            .iter()
+            .filter(|g| !desired_groups.contains(g) && !PROTECTED_GROUPS.contains(&g.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        groups.sort();
+        deletions.extend(
+            groups
+                .into_iter()
+                .map(|group| Change::DeleteGroup { group }),
+        );
+    }
+    Ok(Plan {
+        changes: [creations, updates, additions, removals, deletions].concat(),
+        warnings: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::{
+            handler::{BindRequest, LoginHandler, UserBackendHandler},
+            sql_backend_handler::tests::*,
+        },
+        infra::change_plan::apply,
+    };
+    use pretty_assertions::assert_eq;
+
+    const FILE: &str = r#"
+[[groups]]
+name = "staff"
+
+[[users]]
+id = "bob"
+email = "bob@example.com"
+display_name = "Bob"
+password = "bob's password"
+groups = ["staff", "vpn"]
+
+[[users]]
+id = "carol"
+email = "carol@example.com"
+first_name = "Carol"
+"#;
+
+    fn parse(contents: &str) -> BootstrapFile {
+        let file = Figment::from(Toml::string(contents)).extract().unwrap();
+        validate(&file).unwrap();
+        file
+    }
+
+    fn lines(plan: &Plan) -> Vec<String> {
+        plan.changes.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        let admin = UserId::new("admin");
+        insert_user_no_password(&handler, "admin").await;
+        insert_user_no_password(&handler, "dave").await;
+        insert_group(&handler, "old_group").await;
+        insert_group(&handler, "lldap_admin").await;
+
+        let file = parse(FILE);
+        let changes = plan(&handler, &file, false, &admin).await.unwrap();
+        let expected_summary = changes.summary();
+        assert_eq!(
+            lines(&changes),
+            vec![
+                "+ group staff",
+                "+ group vpn",
+                "+ user bob <bob@example.com> with a password",
+                "+ user carol <carol@example.com>",
+                "+ bob in group staff",
+                "+ bob in group vpn",
+            ]
+        );
+        assert_eq!(
+            apply(&handler, changes, None, None).await.unwrap(),
+            expected_summary
+        );
+        handler
+            .bind(BindRequest {
+                name: UserId::new("bob"),
+                password: "bob's password".to_owned(),
+            })
+            .await
+            .unwrap();
+        // Idempotent.
+        assert_eq!(
+            plan(&handler, &file, false, &admin).await.unwrap(),
+            Plan::default()
+        );
+
+        let file = parse(
+            &FILE
+                .replace("bob@example.com", "bob@example.org")
+                .replace(r#"groups = ["staff", "vpn"]"#, r#"groups = ["staff"]"#),
+        );
+        let changes = plan(&handler, &file, true, &admin).await.unwrap();
+        let expected_summary = changes.summary();
+        assert_eq!(
+            lines(&changes),
+            vec![
+                r#"~ user bob: email "bob@example.com" -> "bob@example.org""#,
+                "- bob from group vpn",
+                "- user dave",
+                "- group old_group",
+                "- group vpn",
+            ]
+        );
+        assert_eq!(
+            apply(&handler, changes, None, None).await.unwrap(),
+            expected_summary
+        );
+        // The plan was the full effect of the run.
+        assert_eq!(
+            plan(&handler, &file, true, &admin).await.unwrap(),
+            Plan::default()
+        );
+        assert_eq!(
+            handler
+                .get_user_details(&UserId::new("bob"))
+                .await
+                .unwrap()
+                .email
+                .as_str(),
+            "bob@example.org"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let file = |contents: &str| {
+            let file: BootstrapFile = Figment::from(Toml::string(contents)).extract().unwrap();
+            validate(&file)
+        };
+        file(FILE).unwrap();
+        file("[[users]]\nid = \"a\"\nemail = \"a@a\"\n[[users]]\nid = \"A\"\nemail = \"b@b\"")
+            .unwrap_err();
+        file("[[users]]\nid = \"a\"\nemail = \"a@a\"\npassword = \"short\"").unwrap_err();
+        file("[[users]]\nid = \"a\"\nemail = \"not-an-email\"").unwrap_err();
+        file("[[users]]\nid = \"a\"\nemail = \" A@Example.com \"").unwrap();
+        file("[[users]]\nid = \"a\"\nemail = \"a@a\"\npassword = \"long enough\"\ninvite = true")
+            .unwrap_err();
+        Figment::from(Toml::string(
+            "[[users]]\nid = \"a\"\nemail = \"a@a\"\nmail = \"typo\"",
+        ))
+        .extract::<BootstrapFile>()
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_password_file() {
+        let dir = std::env::temp_dir().join(format!("lldap-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("bob_password"), "bob's password\n").unwrap();
+        let path = dir.join("bootstrap.toml");
+        std::fs::write(
+            &path,
+            "[[users]]\nid = \"bob\"\nemail = \"bob@bob\"\npassword_file = \"bob_password\"",
+        )
+        .unwrap();
+        let file = read_file(&path);
+        std::fs::write(
+            &path,
+            "[[users]]\nid = \"bob\"\nemail = \"bob@bob\"\npassword = \"long enough\"\npassword_file = \"bob_password\"",
+        )
+        .unwrap();
+        let both = read_file(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let file = file.unwrap();
+        assert_eq!(
+            file.users[0].password.as_ref().unwrap().unsecure(),
+            "bob's password"
+        );
+        both.unwrap_err();
+    }
+}
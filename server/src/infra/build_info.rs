@@ -0,0 +1,150 @@
+//! What was built, to debug mismatched deployments: `lldap --version`, `lldap version`, the
+//! `serverInfo` GraphQL query and `/api/version`. Captured by `build.rs`.
+
+use crate::domain::sql_tables::LAST_SCHEMA_VERSION;
+use actix_web::HttpResponse;
+use chrono::TimeZone;
+use serde::Serialize;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, juniper::GraphQLObject)]
+pub struct ComponentVersion {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, juniper::GraphQLObject)]
+pub struct BuildInfo {
+    pub version: String,
+    /// Unknown when built outside of a git checkout.
+    pub git_commit: Option<String>,
+    /// Whether there were uncommitted changes.
+    pub git_dirty: Option<bool>,
+    /// RFC 3339.
+    pub build_date: Option<String>,
+    pub target: String,
+    /// The optional cargo features compiled in.
+    pub features: Vec<String>,
+    pub tls_backend: String,
+    pub database_drivers: Vec<String>,
+    /// The versions of the protocol libraries and of the database schema.
+    pub components: Vec<ComponentVersion>,
+}
+
+fn non_empty(value: Option<&'static str>) -> Option<String> {
+    value.filter(|v| !v.is_empty()).map(str::to_owned)
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+impl BuildInfo {
+    pub fn get() -> &'static BuildInfo {
+        static BUILD_INFO: OnceLock<BuildInfo> = OnceLock::new();
+        BUILD_INFO.get_or_init(|| BuildInfo {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            git_commit: non_empty(option_env!("LLDAP_GIT_COMMIT")),
+            git_dirty: option_env!("LLDAP_GIT_DIRTY").and_then(|d| d.parse().ok()),
+            build_date: option_env!("LLDAP_BUILD_TIMESTAMP")
+                .and_then(|t| t.parse().ok())
+                .and_then(|t| chrono::Utc.timestamp_opt(t, 0).single())
+                .map(|t| t.to_rfc3339()),
+            target: non_empty(option_env!("LLDAP_BUILD_TARGET"))
+                .unwrap_or_else(|| "unknown".to_owned()),
+            features: [
+                ("systemd", cfg!(feature = "systemd")),
+                ("otel", cfg!(feature = "otel")),
+            ]
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(feature, _)| feature.to_owned())
+            .collect(),
+            tls_backend: "rustls".to_owned(),
+            // sea-orm is built with "sqlx-all".
+            database_drivers: strings(&["sqlite", "postgres", "mysql"]),
+            components: option_env!("LLDAP_COMPONENT_VERSIONS")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|component| component.split_once('='))
+                .map(|(name, version)| ComponentVersion {
+                    name: name.to_owned(),
+                    version: version.to_owned(),
+                })
+                .chain(std::iter::once(ComponentVersion {
+                    name: "database_schema".to_owned(),
+                    version: LAST_SCHEMA_VERSION.0.to_string(),
+                }))
+                .collect(),
+        })
+    }
+
+    /// For `lldap --version`.
+    pub fn long_version() -> &'static str {
+        static LONG_VERSION: OnceLock<String> = OnceLock::new();
+        // clap prints the name itself.
+        LONG_VERSION.get_or_init(|| {
+            Self::get()
+                .to_string()
+                .trim_start_matches("lldap ")
+                .trim_end()
+                .to_owned()
+        })
+    }
+}
+
+impl std::fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "lldap {}", self.version)?;
+        match (&self.git_commit, self.git_dirty) {
+            (Some(commit), Some(true)) => writeln!(f, "commit: {} (dirty)", commit)?,
+            (Some(commit), _) => writeln!(f, "commit: {}", commit)?,
+            (None, _) => writeln!(f, "commit: unknown")?,
+        }
+        writeln!(
+            f,
+            "build date: {}",
+            self.build_date.as_deref().unwrap_or("unknown")
+        )?;
+        writeln!(f, "target: {}", self.target)?;
+        if self.features.is_empty() {
+            writeln!(f, "features: none")?;
+        } else {
+            writeln!(f, "features: {}", self.features.join(", "))?;
+        }
+        writeln!(f, "tls: {}", self.tls_backend)?;
+        writeln!(f, "database drivers: {}", self.database_drivers.join(", "))?;
+        for component in &self.components {
+            writeln!(f, "{}: {}", component.name, component.version)?;
+        }
+        Ok(())
+    }
+}
+
+/// `/api/version`, without authentication, for the probes that can't run the binary.
+pub(crate) async fn version_handler() -> HttpResponse {
+    HttpResponse::Ok().json(BuildInfo::get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        let info = BuildInfo::get();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        // Set by build.rs, even outside of git.
+        assert!(info.build_date.is_some());
+        assert_ne!(info.target, "unknown");
+        assert!(info.components.iter().any(|c| c.name == "ldap3_proto"));
+        let text = info.to_string();
+        assert!(
+            text.starts_with(&format!("lldap {}\n", info.version)),
+            "{}",
+            text
+        );
+        assert!(text.contains("database_schema: "), "{}", text);
+        assert!(BuildInfo::long_version().starts_with(&format!("{}\ncommit: ", info.version)));
+    }
+}
@@ -0,0 +1,441 @@
+//! The changes made in bulk by `bootstrap` and `import_ldif`. They are computed first against
+//! the database, then either printed with `--dry-run` or applied as they are: the dry run shows
+//! exactly what the real run does.
+
+use crate::{
+    domain::{
+        handler::{
+            CreateGroupRequest, CreateUserRequest, GroupBackendHandler, GroupListerBackendHandler,
+            UpdateUserRequest, UserBackendHandler,
+        },
+        sql_backend_handler::SqlBackendHandler,
+        sql_opaque_handler::{register_password, set_legacy_password_hash},
+        types::{Email, GroupId, GroupName, JpegPhoto, UserId},
+    },
+    infra::{avatar_source::AvatarImporter, cli::PlanOutput, invitation::InvitationSender},
+};
+use anyhow::{Context, Result};
+use secstr::SecUtf8;
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InitialPassword {
+    None,
+    Password(SecUtf8),
+    /// Imported from another LDAP server, see `legacy_password`.
+    LegacyHash(String),
+    /// Email an invitation to choose a password.
+    Invite,
+}
+
+/// Only the kind of password: the plans are printed.
+impl Serialize for InitialPassword {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            InitialPassword::None => serializer.serialize_none(),
+            InitialPassword::Password(_) => serializer.serialize_str("password"),
+            InitialPassword::LegacyHash(_) => serializer.serialize_str("legacy_hash"),
+            InitialPassword::Invite => serializer.serialize_str("invite"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserToCreate {
+    pub user_id: UserId,
+    pub email: Email,
+    pub display_name: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    #[serde(skip)]
+    pub avatar: Option<JpegPhoto>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mobile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postal_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locality: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_or_province: Option<String>,
+    pub password: InitialPassword,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, strum::IntoStaticStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum UserField {
+    Email,
+    DisplayName,
+    FirstName,
+    LastName,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldChange {
+    pub field: UserField,
+    pub old: Option<String>,
+    pub new: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Change {
+    CreateGroup {
+        group: GroupName,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+    },
+    CreateUser(UserToCreate),
+    UpdateUser {
+        user_id: UserId,
+        changes: Vec<FieldChange>,
+    },
+    AddMembership {
+        user_id: UserId,
+        group: GroupName,
+    },
+    RemoveMembership {
+        user_id: UserId,
+        group: GroupName,
+    },
+    DeleteUser {
+        user_id: UserId,
+    },
+    DeleteGroup {
+        group: GroupName,
+    },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::CreateGroup { group, .. } => write!(f, "+ group {}", group),
+            Change::CreateUser(user) => {
+                write!(f, "+ user {} <{}>", user.user_id, user.email)?;
+                match user.password {
+                    InitialPassword::None => Ok(()),
+                    InitialPassword::Password(_) => write!(f, " with a password"),
+                    InitialPassword::LegacyHash(_) => write!(f, " with an imported password"),
+                    InitialPassword::Invite => write!(f, " with an invitation"),
+                }
+            }
+            Change::UpdateUser { user_id, changes } => {
+                write!(f, "~ user {}:", user_id)?;
+                for (i, change) in changes.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(
+                        f,
+                        "{}{} {:?} -> {:?}",
+                        separator,
+                        <&str>::from(change.field),
+                        change.old.as_deref().unwrap_or_default(),
+                        change.new
+                    )?;
+                }
+                Ok(())
+            }
+            Change::AddMembership { user_id, group } => {
+                write!(f, "+ {} in group {}", user_id, group)
+            }
+            Change::RemoveMembership { user_id, group } => {
+                write!(f, "- {} from group {}", user_id, group)
+            }
+            Change::DeleteUser { user_id } => write!(f, "- user {}", user_id),
+            Change::DeleteGroup { group } => write!(f, "- group {}", group),
+        }
+    }
+}
+
+/// The changes, in the order they are applied, and what will be skipped.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Plan {
+    pub changes: Vec<Change>,
+    pub warnings: Vec<String>,
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+impl Plan {
+    /// What `apply` reports once the plan is applied.
+    pub fn summary(&self) -> Summary {
+        let mut summary = Summary::default();
+        for change in &self.changes {
+            summary.count(change);
+        }
+        summary
+    }
+
+    /// Prints the plan for `--dry-run`. The warnings are logged in the text output.
+    pub fn print(&self, output: PlanOutput) -> Result<()> {
+        match output {
+            PlanOutput::Text => {
+                for warning in &self.warnings {
+                    warn!("{}", warning);
+                }
+                print!("{}", self);
+            }
+            PlanOutput::Json => println!("{}", serde_json::to_string_pretty(self)?),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub created_groups: usize,
+    pub created_users: usize,
+    pub updated_users: usize,
+    pub added_memberships: usize,
+    pub removed_memberships: usize,
+    pub deleted_users: usize,
+    pub deleted_groups: usize,
+}
+
+impl Summary {
+    fn count(&mut self, change: &Change) {
+        match change {
+            Change::CreateGroup { .. } => self.created_groups += 1,
+            Change::CreateUser(_) => self.created_users += 1,
+            Change::UpdateUser { .. } => self.updated_users += 1,
+            Change::AddMembership { .. } => self.added_memberships += 1,
+            Change::RemoveMembership { .. } => self.removed_memberships += 1,
+            Change::DeleteUser { .. } => self.deleted_users += 1,
+            Change::DeleteGroup { .. } => self.deleted_groups += 1,
+        }
+    }
+
+    pub fn print(&self, output: PlanOutput) -> Result<()> {
+        match output {
+            PlanOutput::Text => info!("{}", self),
+            PlanOutput::Json => println!("{}", serde_json::to_string_pretty(self)?),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Created {} groups and {} users, updated {} users, added {} and removed {} memberships, deleted {} users and {} groups",
+            self.created_groups,
+            self.created_users,
+            self.updated_users,
+            self.added_memberships,
+            self.removed_memberships,
+            self.deleted_users,
+            self.deleted_groups
+        )
+    }
+}
+
+fn group_id(group_ids: &HashMap<GroupName, GroupId>, group: &GroupName) -> Result<GroupId> {
+    group_ids
+        .get(group)
+        .copied()
+        .context(format!("The group {} doesn't exist", group))
+}
+
+async fn apply_change(
+    handler: &SqlBackendHandler,
+    change: Change,
+    group_ids: &mut HashMap<GroupName, GroupId>,
+    invitation_sender: Option<&dyn InvitationSender>,
+    avatar_importer: Option<&dyn AvatarImporter>,
+) -> Result<()> {
+    match change {
+        Change::CreateGroup { group, description } => {
+            let id = handler
+                .create_group(CreateGroupRequest {
+                    display_name: group.clone(),
+                    description,
+                    ..Default::default()
+                })
+                .await?;
+            group_ids.insert(group, id);
+        }
+        Change::CreateUser(user) => {
+            let user_id = user.user_id;
+            let has_avatar = user.avatar.is_some();
+            handler
+                .create_user(CreateUserRequest {
+                    user_id: user_id.clone(),
+                    email: user.email,
+                    display_name: user.display_name,
+                    first_name: user.first_name,
+                    last_name: user.last_name,
+                    avatar: user.avatar,
+                    hosts: user.hosts,
+                    phone: user.phone,
+                    mobile: user.mobile,
+                    postal_address: user.postal_address,
+                    locality: user.locality,
+                    state_or_province: user.state_or_province,
+                    ..Default::default()
+                })
+                .await?;
+            match user.password {
+                InitialPassword::None => {}
+                InitialPassword::Password(password) => {
+                    register_password(handler, user_id, &password).await?
+                }
+                InitialPassword::LegacyHash(hash) => {
+                    set_legacy_password_hash(handler, &user_id, &hash).await?
+                }
+                InitialPassword::Invite => match invitation_sender {
+                    Some(sender) => {
+                        sender
+                            .send_invitation(&handler.get_user_details(&user_id).await?)
+                            .await?
+                    }
+                    None => warn!("Not inviting {}: the password reset is disabled", user_id),
+                },
+            }
+            if let (false, Some(importer)) = (has_avatar, avatar_importer) {
+                // The user is created anyway.
+                if let Err(e) = importer.import_avatar(&user_id).await {
+                    warn!("Could not import the avatar of {}: {:#}", user_id, e);
+                }
+            }
+        }
+        Change::UpdateUser { user_id, changes } => {
+            let mut request = UpdateUserRequest {
+                user_id,
+                ..Default::default()
+            };
+            for FieldChange { field, new, .. } in changes {
+                match field {
+                    UserField::Email => request.email = Some(new.into()),
+                    UserField::DisplayName => request.display_name = Some(new),
+                    UserField::FirstName => request.first_name = Some(new),
+                    UserField::LastName => request.last_name = Some(new),
+                }
+            }
+            handler.update_user(request).await?;
+        }
+        Change::AddMembership { user_id, group } => {
+            let group_id = group_id(group_ids, &group)?;
+            handler.add_user_to_group(&user_id, group_id).await?;
+        }
+        Change::RemoveMembership { user_id, group } => {
+            let group_id = group_id(group_ids, &group)?;
+            handler.remove_user_from_group(&user_id, group_id).await?;
+        }
+        Change::DeleteUser { user_id } => handler.delete_user(&user_id).await?,
+        Change::DeleteGroup { group } => {
+            let group_id = group_id(group_ids, &group)?;
+            handler.delete_group(group_id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Applies the changes in order, and stops at the first error. Without an `invitation_sender`
+/// (no password reset), the invitations are skipped with a warning. With an `avatar_importer`,
+/// the new users without an avatar get theirs from the source, or a warning.
+pub async fn apply(
+    handler: &SqlBackendHandler,
+    plan: Plan,
+    invitation_sender: Option<&dyn InvitationSender>,
+    avatar_importer: Option<&dyn AvatarImporter>,
+) -> Result<Summary> {
+    let mut group_ids = handler
+        .list_groups(None)
+        .await?
+        .into_iter()
+        .map(|g| (g.display_name, g.id))
+        .collect();
+    let mut summary = Summary::default();
+    for change in plan.changes {
+        info!("{}", change);
+        summary.count(&change);
+        let context = format!("while applying `{}`", change);
+        apply_change(
+            handler,
+            change,
+            &mut group_ids,
+            invitation_sender,
+            avatar_importer,
+        )
+        .await
+        .context(context)?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_json_output() {
+        let plan = Plan {
+            changes: vec![
+                Change::CreateUser(UserToCreate {
+                    user_id: UserId::new("bob"),
+                    email: "bob@example.com".into(),
+                    display_name: None,
+                    first_name: None,
+                    last_name: None,
+                    avatar: None,
+                    hosts: Vec::new(),
+                    phone: None,
+                    mobile: None,
+                    postal_address: None,
+                    locality: None,
+                    state_or_province: None,
+                    password: InitialPassword::Password(SecUtf8::from("secret password")),
+                }),
+                Change::UpdateUser {
+                    user_id: UserId::new("carol"),
+                    changes: vec![FieldChange {
+                        field: UserField::DisplayName,
+                        old: None,
+                        new: "Carol".to_owned(),
+                    }],
+                },
+            ],
+            warnings: vec!["A warning".to_owned()],
+        };
+        assert_eq!(
+            serde_json::to_value(&plan).unwrap(),
+            serde_json::json!({
+                "changes": [
+                    {
+                        "action": "create_user",
+                        "user_id": "bob",
+                        "email": "bob@example.com",
+                        "display_name": null,
+                        "first_name": null,
+                        "last_name": null,
+                        "password": "password",
+                    },
+                    {
+                        "action": "update_user",
+                        "user_id": "carol",
+                        "changes": [{"field": "display_name", "old": null, "new": "Carol"}],
+                    },
+                ],
+                "warnings": ["A warning"],
+            })
+        );
+        assert_eq!(
+            plan.to_string(),
+            "+ user bob <bob@example.com> with a password\n~ user carol: display_name \"\" -> \"Carol\"\n"
+        );
+    }
+}
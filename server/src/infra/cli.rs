@@ -1,15 +1,21 @@
-use clap::{builder::EnumValueParser, Parser};
+use anyhow::{Context, Result};
+use clap::{builder::EnumValueParser, CommandFactory, FromArgMatches, Parser, ValueHint};
 use lettre::message::Mailbox;
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use url::Url;
 
-use crate::infra::database_string::DatabaseUrl;
+use crate::infra::{
+    build_info::BuildInfo,
+    database_string::DatabaseUrl,
+    db_cleaner::PruneTarget,
+    secret_input::{deprecated_secret_args, PasswordInputOpts, SecretSource},
+};
 
 /// lldap is a lightweight LDAP server
 #[derive(Debug, Parser, Clone)]
 #[clap(version, author)]
 pub struct CLIOpts {
-    /// Export
     #[clap(subcommand)]
     pub command: Command,
 }
@@ -25,13 +31,64 @@ pub enum Command {
     Run(RunOpts),
     /// Test whether the LDAP and GraphQL server are responsive.
     #[clap(name = "healthcheck")]
-    HealthCheck(RunOpts),
+    HealthCheck(HealthCheckOpts),
     /// Send a test email.
     #[clap(name = "send_test_email")]
     SendTestEmail(TestEmailOpts),
     /// Create database schema.
     #[clap(name = "create_schema")]
     CreateSchema(RunOpts),
+    /// Apply the pending database schema migrations.
+    #[clap(name = "migrate")]
+    Migrate(MigrateOpts),
+    /// Export the whole database and the server key to an archive, for backups.
+    #[clap(name = "export")]
+    Export(ExportOpts),
+    /// Restore an archive created by `export` into an empty database.
+    #[clap(name = "import")]
+    Import(ImportOpts),
+    /// Import the users and groups from an LDIF dump of another LDAP server.
+    #[clap(name = "import_ldif")]
+    ImportLdif(ImportLdifOpts),
+    /// Create or update the users listed in a CSV file.
+    #[clap(name = "import_csv")]
+    ImportCsv(ImportCsvOpts),
+    /// Report the existing users and groups that don't follow the current naming and email rules.
+    #[clap(name = "check_db")]
+    CheckDb(CheckDbOpts),
+    /// Delete the expired sessions and tokens, and the deleted users, past their retention.
+    #[clap(name = "prune")]
+    Prune(PruneOpts),
+    /// Download the avatars of the users from `avatar_source_url_template`.
+    #[clap(name = "refresh_avatars")]
+    RefreshAvatars(RefreshAvatarsOpts),
+    /// Create a user. Exits with code 3 if it already exists.
+    #[clap(name = "create_user")]
+    CreateUser(CreateUserOpts),
+    /// Create a group. Exits with code 3 if it already exists.
+    #[clap(name = "create_group")]
+    CreateGroup(CreateGroupOpts),
+    /// Set a new password for the admin user of the configuration, with the server stopped.
+    #[clap(name = "reset_admin_password")]
+    ResetAdminPassword(ResetAdminPasswordOpts),
+    /// Create and update the users, groups and memberships listed in a file.
+    #[clap(name = "bootstrap")]
+    Bootstrap(BootstrapOpts),
+    /// Replace the JWT secret of the configuration with a random one, with the server stopped.
+    #[clap(name = "rotate_jwt_secret")]
+    RotateJwtSecret(RotateJwtSecretOpts),
+    /// Print the version with the build information: git commit, build date, features...
+    #[clap(name = "version")]
+    Version(VersionOpts),
+    /// Print a configuration file with every setting and its description.
+    #[clap(name = "generate_config")]
+    GenerateConfig(GenerateConfigOpts),
+    /// Print the shell completion script.
+    #[clap(name = "completions")]
+    Completions(CompletionsOpts),
+    /// Write the man pages, one per subcommand.
+    #[clap(name = "manpages")]
+    Manpages(ManpagesOpts),
 }
 
 #[derive(Debug, Parser, Clone)]
@@ -41,7 +98,8 @@ pub struct GeneralConfigOpts {
         short,
         long,
         default_value = "lldap_config.toml",
-        env = "LLDAP_CONFIG_FILE"
+        env = "LLDAP_CONFIG_FILE",
+        value_hint = ValueHint::FilePath
     )]
     pub config_file: String,
 
@@ -59,36 +117,41 @@ pub struct RunOpts {
     /// It will be created if it doesn't exist.
     /// Alternatively, you can set `server_key_seed`. If `server_key_seed` is given,
     /// `server_key_file` will be ignored.
-    #[clap(long, env = "LLDAP_SERVER_KEY_FILE")]
+    #[clap(long, env = "LLDAP_SERVER_KEY_FILE", value_hint = ValueHint::FilePath)]
     pub server_key_file: Option<String>,
 
     /// Seed used to generate the private server key.
     /// Takes precedence over `server_key_file`.
-    #[clap(long, env = "LLDAP_SERVER_KEY_SEED")]
+    /// Deprecated on the command line: use the environment variable.
+    #[clap(long, env = "LLDAP_SERVER_KEY_SEED", hide_env_values = true)]
     pub server_key_seed: Option<String>,
 
     /// Change ldap host. Default: "0.0.0.0"
-    #[clap(long, env = "LLDAP_LDAP_HOST")]
+    #[clap(long, env = "LLDAP_LDAP_HOST", value_hint = ValueHint::Hostname)]
     pub ldap_host: Option<String>,
 
     /// Change ldap port. Default: 3890
-    #[clap(long, env = "LLDAP_LDAP_PORT")]
+    #[clap(long, env = "LLDAP_LDAP_PORT", value_name = "PORT")]
     pub ldap_port: Option<u16>,
 
     /// Change HTTP API host. Default: "0.0.0.0"
-    #[clap(long, env = "LLDAP_HTTP_HOST")]
+    #[clap(long, env = "LLDAP_HTTP_HOST", value_hint = ValueHint::Hostname)]
     pub http_host: Option<String>,
 
     /// Change HTTP API port. Default: 17170
-    #[clap(long, env = "LLDAP_HTTP_PORT")]
+    #[clap(long, env = "LLDAP_HTTP_PORT", value_name = "PORT")]
     pub http_port: Option<u16>,
 
     /// URL of the server, for password reset links.
-    #[clap(long, env = "LLDAP_HTTP_URL")]
+    #[clap(long, env = "LLDAP_HTTP_URL", value_hint = ValueHint::Url)]
     pub http_url: Option<Url>,
 
+    /// Path under which to serve the web UI and the API, e.g. /lldap. Default: none
+    #[clap(long, env = "LLDAP_HTTP_PATH_PREFIX")]
+    pub http_path_prefix: Option<String>,
+
     /// Database connection URL
-    #[clap(short, long, env = "LLDAP_DATABASE_URL")]
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
     pub database_url: Option<DatabaseUrl>,
 
     /// Force admin password reset to the config value.
@@ -99,11 +162,47 @@ pub struct RunOpts {
     #[clap(long, env = "LLDAP_FORCE_UPDATE_PRIVATE_KEY")]
     pub force_update_private_key: Option<bool>,
 
+    /// Upgrade the database schema at startup if needed, even if `run_migrations_on_startup` is
+    /// disabled in the config.
+    #[clap(long, env = "LLDAP_AUTO_MIGRATE")]
+    pub auto_migrate: bool,
+
+    /// Start even if the database schema doesn't look right. For emergencies
+    /// only, e.g. to run an older version after a failed upgrade.
+    #[clap(long, env = "LLDAP_SKIP_DB_CHECKS")]
+    pub skip_db_checks: bool,
+
     #[clap(flatten)]
     pub smtp_opts: SmtpOpts,
 
     #[clap(flatten)]
     pub ldaps_opts: LdapsOpts,
+
+    #[clap(flatten)]
+    pub http_tls_opts: HttpTlsOpts,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct HealthCheckOpts {
+    #[clap(flatten)]
+    pub run_opts: RunOpts,
+
+    /// Only check the HTTP API, not the LDAP and LDAPS ports.
+    #[clap(long)]
+    pub skip_ldap: bool,
+
+    /// Bind as the admin user of the configuration before the LDAP search, instead of searching
+    /// anonymously.
+    #[clap(long)]
+    pub ldap_bind: bool,
+
+    /// Warn when a certificate expires in fewer days than this.
+    #[clap(long, default_value = "14")]
+    pub cert_expiry_warning_days: u32,
+
+    /// Timeout of each check, in milliseconds.
+    #[clap(long, default_value = "2000")]
+    pub timeout_ms: u64,
 }
 
 #[derive(Debug, Parser, Clone)]
@@ -112,13 +211,369 @@ pub struct TestEmailOpts {
     pub general_config: GeneralConfigOpts,
 
     /// Email address to send an email to.
-    #[clap(long, env = "LLDAP_TEST_EMAIL_TO")]
+    #[clap(long, env = "LLDAP_TEST_EMAIL_TO", value_hint = ValueHint::EmailAddress)]
     pub to: String,
 
+    /// Which email to send: the real templates are rendered with example values and a token that
+    /// doesn't work.
+    #[clap(long, value_enum, default_value = "test")]
+    pub template: TestEmailTemplate,
+
+    /// Print the rendered email instead of sending it.
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Render the email in this language, e.g. "fr". Default: smtp_options.default_language
+    #[clap(long)]
+    pub language: Option<String>,
+
+    /// File containing the SMTP password, to try it before changing the configuration. Takes
+    /// precedence over the password of the configuration.
+    #[clap(long, value_hint = ValueHint::FilePath, conflicts_with = "smtp_password_stdin")]
+    pub smtp_password_file: Option<PathBuf>,
+
+    /// Read the SMTP password from the standard input.
+    #[clap(long)]
+    pub smtp_password_stdin: bool,
+
     #[clap(flatten)]
     pub smtp_opts: SmtpOpts,
 }
 
+impl TestEmailOpts {
+    pub fn smtp_password_source(&self) -> SecretSource<'_> {
+        SecretSource {
+            file: self.smtp_password_file.as_deref(),
+            stdin: self.smtp_password_stdin,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TestEmailTemplate {
+    Test,
+    PasswordReset,
+    Invite,
+    EmailChange,
+    EmailChanged,
+    AccountExpiring,
+    AccountExpiringAdmin,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct MigrateOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+
+    /// Print the SQL statements that would be run, without modifying the database.
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct ExportOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Path to the file that contains the private server key.
+    #[clap(long, env = "LLDAP_SERVER_KEY_FILE", value_hint = ValueHint::FilePath)]
+    pub server_key_file: Option<String>,
+
+    /// Seed used to generate the private server key.
+    #[clap(long, env = "LLDAP_SERVER_KEY_SEED", hide_env_values = true)]
+    pub server_key_seed: Option<String>,
+
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+
+    /// Path of the archive to create, e.g. "backup.tar.gz".
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
+    pub output: String,
+
+    /// Leave out the pictures (e.g. avatars) to keep the archive small.
+    #[clap(long)]
+    pub exclude_avatars: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct ImportOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Path to the file that contains the private server key.
+    /// If it doesn't exist, it will be restored from the archive.
+    #[clap(long, env = "LLDAP_SERVER_KEY_FILE", value_hint = ValueHint::FilePath)]
+    pub server_key_file: Option<String>,
+
+    /// Seed used to generate the private server key.
+    /// It must generate the same key as the one in the archive.
+    #[clap(long, env = "LLDAP_SERVER_KEY_SEED", hide_env_values = true)]
+    pub server_key_seed: Option<String>,
+
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+
+    /// Path of the archive created by `export`.
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
+    pub input: String,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct ImportLdifOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+
+    /// Path of the LDIF file, e.g. the output of `ldapsearch -LLL`.
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
+    pub file: String,
+
+    /// Print the changes, without modifying the database.
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Format of the changes printed with `--dry-run`, and of the summary.
+    #[clap(long, value_enum, default_value = "text")]
+    pub output: PlanOutput,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct ImportCsvOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+
+    /// Path of the CSV file. The first line is the header.
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
+    pub file: PathBuf,
+
+    /// Path of the TOML file mapping the columns to the user fields.
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
+    pub mapping: PathBuf,
+
+    /// Update the users that already exist, instead of skipping them.
+    #[clap(long)]
+    pub update_existing: bool,
+
+    /// Abort on the first invalid row, and don't import anything if any row is invalid.
+    #[clap(long)]
+    pub strict: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct CheckDbOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct PruneOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+
+    /// What to prune, comma-separated. Default: everything.
+    #[clap(long, value_enum, value_delimiter = ',')]
+    pub what: Vec<PruneTarget>,
+
+    /// Prune what expired (or was deleted) longer ago than this, e.g. "90d", "12h", or "0d" for
+    /// everything expired. Default: the `[retention]` configuration.
+    #[clap(long, value_parser = parse_age)]
+    pub older_than: Option<chrono::Duration>,
+
+    /// Only count the rows to prune.
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct RefreshAvatarsOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+
+    /// Only download the avatars of the users without one.
+    #[clap(long)]
+    pub only_missing: bool,
+}
+
+/// A number of seconds, minutes, hours, days or weeks, e.g. "90d".
+fn parse_age(age: &str) -> Result<chrono::Duration, String> {
+    let unit_start = age
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("Missing unit in \"{}\": expected e.g. \"90d\"", age))?;
+    let (value, unit) = age.split_at(unit_start);
+    let value = value
+        .parse::<i64>()
+        .map_err(|_| format!("Invalid duration \"{}\": expected e.g. \"90d\"", age))?;
+    match unit {
+        "s" => Ok(chrono::Duration::seconds(value)),
+        "m" => Ok(chrono::Duration::minutes(value)),
+        "h" => Ok(chrono::Duration::hours(value)),
+        "d" => Ok(chrono::Duration::days(value)),
+        "w" => Ok(chrono::Duration::weeks(value)),
+        _ => Err(format!(
+            "Invalid unit \"{}\": expected s, m, h, d or w",
+            unit
+        )),
+    }
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct ResetAdminPasswordOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+
+    /// The new password, from `--password-file` or `--password-stdin`. Default: prompt for it.
+    #[clap(flatten)]
+    pub password: PasswordInputOpts,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct BootstrapOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+
+    /// TOML file (or JSON with the .json extension) listing the groups and users.
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
+    pub file: PathBuf,
+
+    /// Also delete the users, groups and memberships that are not in the file, except the admin
+    /// user and the lldap_* groups.
+    #[clap(long)]
+    pub prune: bool,
+
+    /// Print the changes, without modifying the database.
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Format of the changes printed with `--dry-run`, and of the summary.
+    #[clap(long, value_enum, default_value = "text")]
+    pub output: PlanOutput,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlanOutput {
+    Text,
+    Json,
+}
+
+/// Where to create the users and groups.
+#[derive(Debug, Parser, Clone)]
+#[clap(next_help_heading = Some("HTTP API"))]
+pub struct ProvisioningOpts {
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+
+    /// URL of a running server, e.g. "https://lldap.example.com". Without it, the database of
+    /// the configuration is modified directly: the server should be stopped.
+    #[clap(long, env = "LLDAP_URL", value_hint = ValueHint::Url)]
+    pub url: Option<Url>,
+
+    /// Admin user to log in with, with `--url`.
+    #[clap(long, default_value = "admin")]
+    pub admin_username: String,
+
+    /// File containing the password of the admin user, with `--url`. Default: prompt for it.
+    #[clap(long, value_hint = ValueHint::FilePath, conflicts_with = "admin_password_stdin")]
+    pub admin_password_file: Option<PathBuf>,
+
+    /// Read the password of the admin user from the standard input.
+    #[clap(long)]
+    pub admin_password_stdin: bool,
+}
+
+impl ProvisioningOpts {
+    pub fn admin_password_source(&self) -> SecretSource<'_> {
+        SecretSource {
+            file: self.admin_password_file.as_deref(),
+            stdin: self.admin_password_stdin,
+        }
+    }
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct CreateUserOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    #[clap(flatten)]
+    pub provisioning: ProvisioningOpts,
+
+    /// ID of the user, used to log in.
+    #[clap(long)]
+    pub user_id: String,
+
+    /// Email address of the user.
+    #[clap(long, value_hint = ValueHint::EmailAddress)]
+    pub email: String,
+
+    /// Name shown in the web UI, e.g. "Bob Smith".
+    #[clap(long)]
+    pub display_name: Option<String>,
+
+    /// First name (givenName).
+    #[clap(long)]
+    pub first_name: Option<String>,
+
+    /// Last name (sn).
+    #[clap(long)]
+    pub last_name: Option<String>,
+
+    /// The password, from `--password-file` or `--password-stdin`. Without it, the user can't log
+    /// in until a password is set, e.g. through a password reset.
+    #[clap(flatten)]
+    pub password: PasswordInputOpts,
+
+    /// Add the user to this group, which must exist. Can be repeated.
+    #[clap(long = "group")]
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct CreateGroupOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    #[clap(flatten)]
+    pub provisioning: ProvisioningOpts,
+
+    /// Name of the group.
+    #[clap(long)]
+    pub name: String,
+}
+
 #[derive(Debug, Parser, Clone)]
 #[clap(next_help_heading = Some("LDAPS"))]
 pub struct LdapsOpts {
@@ -127,18 +582,46 @@ pub struct LdapsOpts {
     pub ldaps_enabled: Option<bool>,
 
     /// Change ldap ssl port. Default: 6360
-    #[clap(long, env = "LLDAP_LDAPS_OPTIONS__PORT")]
+    #[clap(long, env = "LLDAP_LDAPS_OPTIONS__PORT", value_name = "PORT")]
     pub ldaps_port: Option<u16>,
 
+    /// Offer StartTLS on the LDAP port, with the LDAPS certificate. Default: false.
+    #[clap(long, env = "LLDAP_LDAPS_OPTIONS__START_TLS")]
+    pub ldaps_start_tls: Option<bool>,
+
     /// Ldaps certificate file. Default: cert.pem
-    #[clap(long, env = "LLDAP_LDAPS_OPTIONS__CERT_FILE")]
+    #[clap(long, env = "LLDAP_LDAPS_OPTIONS__CERT_FILE", value_hint = ValueHint::FilePath)]
     pub ldaps_cert_file: Option<String>,
 
     /// Ldaps certificate key file. Default: key.pem
-    #[clap(long, env = "LLDAP_LDAPS_OPTIONS__KEY_FILE")]
+    #[clap(long, env = "LLDAP_LDAPS_OPTIONS__KEY_FILE", value_hint = ValueHint::FilePath)]
     pub ldaps_key_file: Option<String>,
 }
 
+#[derive(Debug, Parser, Clone)]
+#[clap(next_help_heading = Some("HTTPS"))]
+pub struct HttpTlsOpts {
+    /// Serve the web UI and the API over HTTPS. Default: false.
+    #[clap(long, env = "LLDAP_HTTP_TLS__ENABLED")]
+    pub http_tls_enabled: Option<bool>,
+
+    /// Separate port for HTTPS. Default: HTTPS on the HTTP port.
+    #[clap(long, env = "LLDAP_HTTP_TLS__HTTPS_PORT", value_name = "PORT")]
+    pub https_port: Option<u16>,
+
+    /// Redirect the HTTP port to the HTTPS one. Default: false
+    #[clap(long, env = "LLDAP_HTTP_TLS__REDIRECT_HTTP")]
+    pub http_tls_redirect_http: Option<bool>,
+
+    /// HTTPS certificate file. Default: cert.pem
+    #[clap(long, env = "LLDAP_HTTP_TLS__CERT_FILE", value_hint = ValueHint::FilePath)]
+    pub http_tls_cert_file: Option<String>,
+
+    /// HTTPS certificate key file. Default: key.pem
+    #[clap(long, env = "LLDAP_HTTP_TLS__KEY_FILE", value_hint = ValueHint::FilePath)]
+    pub http_tls_key_file: Option<String>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, clap::ValueEnum)]
 #[serde(rename_all = "UPPERCASE")]
 #[clap(rename_all = "UPPERCASE")]
@@ -164,18 +647,18 @@ pub struct SmtpOpts {
     pub smtp_reply_to: Option<Mailbox>,
 
     /// SMTP server.
-    #[clap(long, env = "LLDAP_SMTP_OPTIONS__SERVER")]
+    #[clap(long, env = "LLDAP_SMTP_OPTIONS__SERVER", value_hint = ValueHint::Hostname)]
     pub smtp_server: Option<String>,
 
-    /// SMTP port, 587 by default.
-    #[clap(long, env = "LLDAP_SMTP_OPTIONS__PORT")]
+    /// SMTP port. Default: 25, 587 or 465 depending on the encryption.
+    #[clap(long, env = "LLDAP_SMTP_OPTIONS__PORT", value_name = "PORT")]
     pub smtp_port: Option<u16>,
 
     /// SMTP user.
     #[clap(long, env = "LLDAP_SMTP_OPTIONS__USER")]
     pub smtp_user: Option<String>,
 
-    /// SMTP password.
+    /// SMTP password. Deprecated on the command line: use the environment variable.
     #[clap(long, env = "LLDAP_SMTP_OPTIONS__PASSWORD", hide_env_values = true)]
     pub smtp_password: Option<String>,
 
@@ -183,17 +666,197 @@ pub struct SmtpOpts {
     #[clap(long, env = "LLDAP_SMTP_OPTIONS__TLS_REQUIRED", hide = true)]
     pub smtp_tls_required: Option<bool>,
 
+    /// SMTP encryption: NONE, TLS or STARTTLS. Default: TLS
     #[clap(long, env = "LLDAP_SMTP_OPTIONS__SMTP_ENCRYPTION", value_parser = EnumValueParser::<SmtpEncryption>::new(), ignore_case = true)]
     pub smtp_encryption: Option<SmtpEncryption>,
 }
 
+#[derive(Debug, Parser, Clone)]
+pub struct RotateJwtSecretOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Database connection URL
+    #[clap(short, long, env = "LLDAP_DATABASE_URL", value_hint = ValueHint::Url)]
+    pub database_url: Option<DatabaseUrl>,
+
+    /// Move the current secret to `jwt_secret_previous`, so that the users stay logged in.
+    #[clap(long, conflicts_with = "revoke_all_sessions")]
+    pub keep_previous: bool,
+
+    /// Log out everyone: delete all the refresh tokens and issued tokens.
+    #[clap(long)]
+    pub revoke_all_sessions: bool,
+
+    /// Run even though the server is running. It keeps using the old secret until it restarts.
+    #[clap(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct VersionOpts {
+    /// Print it as JSON, like `/api/version`.
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct GenerateConfigOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Print the current configuration, from the configuration file and the environment, with
+    /// the secrets redacted. Default: the default configuration.
+    #[clap(long)]
+    pub from_current: bool,
+
+    /// Output to a file. If not specified, the config is printed to the standard output.
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
+    pub output_file: Option<PathBuf>,
+}
+
 #[derive(Debug, Parser, Clone)]
 pub struct ExportGraphQLSchemaOpts {
     /// Output to a file. If not specified, the config is printed to the standard output.
-    #[clap(short, long)]
+    #[clap(short, long, value_hint = ValueHint::FilePath)]
     pub output_file: Option<String>,
 }
 
+#[derive(Debug, Parser, Clone)]
+pub struct CompletionsOpts {
+    /// The shell to complete the commands in.
+    #[clap(value_enum)]
+    pub shell: clap_complete::Shell,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct ManpagesOpts {
+    /// Directory to write the pages to, created if needed.
+    #[clap(long, default_value = "man", value_hint = ValueHint::DirPath)]
+    pub out_dir: PathBuf,
+}
+
+pub fn print_completions(shell: clap_complete::Shell, out: &mut dyn std::io::Write) {
+    let mut command = CLIOpts::command();
+    clap_complete::generate(shell, &mut command, "lldap", out);
+}
+
+/// Writes `lldap.1`, and `lldap-<subcommand>.1` for each subcommand, e.g. for a package.
+pub fn write_manpages(out_dir: &Path) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(out_dir).context(format!(
+        "Could not create the directory `{}`",
+        out_dir.display()
+    ))?;
+    let mut command = CLIOpts::command();
+    command.build();
+    let mut pages = vec![("lldap".to_owned(), command.clone())];
+    for subcommand in command.get_subcommands() {
+        let name = format!("lldap-{}", subcommand.get_name());
+        pages.push((name.clone(), subcommand.clone().name(name)));
+    }
+    let mut written = Vec::new();
+    for (name, page) in pages {
+        let mut buffer = Vec::new();
+        clap_mangen::Man::new(page).render(&mut buffer)?;
+        let path = out_dir.join(format!("{}.1", name));
+        std::fs::write(&path, buffer)
+            .context(format!("Could not write the file `{}`", path.display()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
 pub fn init() -> CLIOpts {
-    CLIOpts::parse()
+    let opts = CLIOpts::from_arg_matches(
+        &CLIOpts::command()
+            .long_version(BuildInfo::long_version())
+            .get_matches(),
+    )
+    .unwrap_or_else(|e| e.exit());
+    // Before the logging is set up, like the deprecations of the configuration.
+    for warning in deprecated_secret_args(std::env::args_os()) {
+        eprintln!("{}", warning);
+    }
+    opts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_definitions() {
+        CLIOpts::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90d"), Ok(chrono::Duration::days(90)));
+        assert_eq!(parse_age("12h"), Ok(chrono::Duration::hours(12)));
+        assert_eq!(parse_age("0d"), Ok(chrono::Duration::zero()));
+        assert!(parse_age("90").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("-1d").is_err());
+        assert!(parse_age("3 days").is_err());
+        let opts = CLIOpts::try_parse_from([
+            "lldap",
+            "prune",
+            "--what",
+            "sessions,reset-tokens",
+            "--older-than",
+            "2w",
+        ])
+        .unwrap();
+        match opts.command {
+            Command::Prune(opts) => {
+                assert_eq!(
+                    opts.what,
+                    vec![PruneTarget::Sessions, PruneTarget::ResetTokens]
+                );
+                assert_eq!(opts.older_than, Some(chrono::Duration::weeks(2)));
+            }
+            command => panic!("{:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_completions() {
+        for shell in [
+            clap_complete::Shell::Bash,
+            clap_complete::Shell::Zsh,
+            clap_complete::Shell::Fish,
+            clap_complete::Shell::PowerShell,
+        ] {
+            let mut out = Vec::new();
+            print_completions(shell, &mut out);
+            let script = String::from_utf8(out).unwrap();
+            for flag in [
+                "reset_admin_password",
+                "config-file",
+                "ldaps-cert-file",
+                "dry-run",
+            ] {
+                assert!(
+                    script.contains(flag),
+                    "{:?} doesn't mention {}",
+                    shell,
+                    flag
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_manpages() {
+        let dir = std::env::temp_dir().join(format!("lldap-test-{}", uuid::Uuid::new_v4()));
+        let written = write_manpages(&dir).unwrap();
+        assert!(written.contains(&dir.join("lldap.1")));
+        let run = std::fs::read_to_string(dir.join("lldap-run.1")).unwrap();
+        // The dashes are escaped in roff.
+        assert!(run.replace("\\-", "-").contains("--ldap-port"), "{}", run);
+        assert!(std::fs::read_to_string(dir.join("lldap-send_test_email.1"))
+            .unwrap()
+            .contains("template"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,184 @@
+//! The IP address of the client, behind the trusted reverse proxies.
+//!
+//! The `X-Forwarded-For` and `Forwarded` headers are only read when the connection comes from a
+//! trusted proxy, since anyone can set them.
+
+use actix_http::header::{self, HeaderMap};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+/// The IP address of the client of the current HTTP request, in the request extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+fn is_trusted(ip: &IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    trusted_proxies.iter().any(|net| net.contains(ip))
+}
+
+/// Parses a node from the headers: "192.0.2.1", "192.0.2.1:4711", "2001:db8::1" or
+/// "[2001:db8::1]:4711". Obfuscated identifiers and "unknown" are not supported.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|a| a.ip()))
+}
+
+/// The chain of addresses, from the client to the last proxy before ours.
+fn forwarded_chain(headers: &HeaderMap) -> Vec<&str> {
+    let x_forwarded_for = headers
+        .get_all("x-forwarded-for")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect::<Vec<_>>();
+    if !x_forwarded_for.is_empty() {
+        return x_forwarded_for;
+    }
+    // Forwarded: for=192.0.2.60;proto=http;by=203.0.113.43, for="[2001:db8:cafe::17]:4711"
+    headers
+        .get_all(header::FORWARDED)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("for").then_some(value)
+            })
+        })
+        .collect()
+}
+
+/// Returns the client address: the peer address, unless it is a trusted proxy. In that case, the
+/// rightmost address of the forwarded chain that is not a trusted proxy.
+pub fn resolve_client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let mut client = peer;
+    if !is_trusted(&peer, trusted_proxies) {
+        return client;
+    }
+    for node in forwarded_chain(headers).into_iter().rev() {
+        match parse_node(node) {
+            Some(ip) => {
+                client = ip;
+                if !is_trusted(&ip, trusted_proxies) {
+                    break;
+                }
+            }
+            // Nothing to the left of an invalid node can be trusted.
+            None => break,
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_http::header::{HeaderName, HeaderValue};
+    use pretty_assertions::assert_eq;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(
+                HeaderName::from_static(*name),
+                HeaderValue::from_static(*value),
+            );
+        }
+        map
+    }
+
+    fn trusted() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()]
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let spoofed = headers(&[
+            ("x-forwarded-for", "203.0.113.7"),
+            ("forwarded", "for=203.0.113.8"),
+        ]);
+        assert_eq!(
+            resolve_client_ip(ip("198.51.100.1"), &spoofed, &trusted()),
+            ip("198.51.100.1")
+        );
+        // Without any trusted proxy, the headers are always ignored.
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &spoofed, &[]),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn test_chained_proxies() {
+        // The client prepended a fake address, the proxies appended the real ones.
+        let chain = headers(&[("x-forwarded-for", "1.2.3.4, 203.0.113.7, 10.1.1.1")]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &chain, &trusted()),
+            ip("203.0.113.7")
+        );
+        // Several headers are one list.
+        let chain = headers(&[
+            ("x-forwarded-for", "1.2.3.4, 203.0.113.7"),
+            ("x-forwarded-for", "10.1.1.1"),
+        ]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &chain, &trusted()),
+            ip("203.0.113.7")
+        );
+        // Only trusted proxies: the leftmost one.
+        let chain = headers(&[("x-forwarded-for", "10.2.2.2, 10.1.1.1")]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &chain, &trusted()),
+            ip("10.2.2.2")
+        );
+        // No header: the proxy itself.
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &HeaderMap::new(), &trusted()),
+            ip("10.0.0.1")
+        );
+        // An invalid node stops the chain.
+        let chain = headers(&[("x-forwarded-for", "203.0.113.7, garbage, 10.1.1.1")]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &chain, &trusted()),
+            ip("10.1.1.1")
+        );
+    }
+
+    #[test]
+    fn test_ipv6() {
+        let chain = headers(&[("x-forwarded-for", "2001:db8::17, fd00::2")]);
+        assert_eq!(
+            resolve_client_ip(ip("fd00::1"), &chain, &trusted()),
+            ip("2001:db8::17")
+        );
+        let chain = headers(&[("x-forwarded-for", "[2001:db8::17]:4711, [fd00::2]:443")]);
+        assert_eq!(
+            resolve_client_ip(ip("fd00::1"), &chain, &trusted()),
+            ip("2001:db8::17")
+        );
+    }
+
+    #[test]
+    fn test_forwarded_header() {
+        let chain = headers(&[(
+            "forwarded",
+            "for=192.0.2.60;proto=http;by=203.0.113.43, For=\"[2001:db8:cafe::17]:4711\"",
+        )]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &chain, &trusted()),
+            ip("2001:db8:cafe::17")
+        );
+        let chain = headers(&[("forwarded", "for=192.0.2.60, for=10.1.1.1;proto=https")]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &chain, &trusted()),
+            ip("192.0.2.60")
+        );
+    }
+}
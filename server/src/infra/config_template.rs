@@ -0,0 +1,261 @@
+//! `lldap generate_config`: writes a configuration file with every setting, each one commented
+//! with the doc comment of its field in `configuration`.
+
+use crate::infra::configuration::{
+    AccountExpirationOptions, CacheOptions, Configuration, CorsOptions, DatabaseOptions,
+    HttpTlsOptions, LdapsOptions, MailOptions, OtelOptions, PasswordPolicyOptions,
+    ReplicationOptions, RetentionOptions, SessionOptions, SqliteOptions,
+};
+use anyhow::{Context, Result};
+use documented::DocumentedFields;
+use serde_json::{Map, Value};
+
+/// The secrets, and their placeholder in the default configuration.
+const SECRETS: [(&str, &str); 6] = [
+    ("/jwt_secret", "REPLACE_WITH_RANDOM"),
+    ("/jwt_secret_previous", "REPLACE_WITH_RANDOM"),
+    ("/ldap_user_pass", "REPLACE_WITH_PASSWORD"),
+    ("/key_seed", "REPLACE_WITH_RANDOM"),
+    ("/smtp_options/password", "REPLACE_WITH_PASSWORD"),
+    ("/replication/token", "REPLACE_WITH_RANDOM"),
+];
+
+/// Same as the `Debug` output of the secrets.
+const REDACTED: &str = "***SECRET***";
+
+/// The doc comments of the fields of a section, e.g. "smtp_options". The new sections have to be
+/// added here.
+fn field_docs(section: &str, field: &str) -> Option<&'static str> {
+    match section {
+        "" => Configuration::get_field_docs(field),
+        "smtp_options" => MailOptions::get_field_docs(field),
+        "database_options" => DatabaseOptions::get_field_docs(field),
+        "database_options.sqlite" => SqliteOptions::get_field_docs(field),
+        "cache" => CacheOptions::get_field_docs(field),
+        "ldaps_options" => LdapsOptions::get_field_docs(field),
+        "http_tls" => HttpTlsOptions::get_field_docs(field),
+        "cors" => CorsOptions::get_field_docs(field),
+        "password_policy" => PasswordPolicyOptions::get_field_docs(field),
+        "session" => SessionOptions::get_field_docs(field),
+        "otel" => OtelOptions::get_field_docs(field),
+        "replication" => ReplicationOptions::get_field_docs(field),
+        "retention" => RetentionOptions::get_field_docs(field),
+        "account_expiration" => AccountExpirationOptions::get_field_docs(field),
+        _ => return None,
+    }
+    .ok()
+}
+
+fn is_section(path: &str) -> bool {
+    matches!(
+        path,
+        "smtp_options"
+            | "database_options"
+            | "database_options.sqlite"
+            | "cache"
+            | "ldaps_options"
+            | "http_tls"
+            | "cors"
+            | "password_policy"
+            | "session"
+            | "otel"
+            | "replication"
+            | "retention"
+            | "account_expiration"
+    )
+}
+
+fn join(section: &str, key: &str) -> String {
+    if section.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", section, key)
+    }
+}
+
+fn toml_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        key.to_owned()
+    } else {
+        Value::String(key.to_owned()).to_string()
+    }
+}
+
+fn toml_value(value: &Value) -> String {
+    match value {
+        // The JSON escapes are valid in the TOML basic strings.
+        Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_) => value.to_string(),
+        Value::Array(values) => format!(
+            "[{}]",
+            values.iter().map(toml_value).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Object(map) if map.values().all(Value::is_null) => "{}".to_owned(),
+        Value::Object(map) => format!(
+            "{{ {} }}",
+            map.iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| format!("{} = {}", toml_key(k), toml_value(v)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn write_comment(output: &mut String, docs: Option<&str>) {
+    for line in docs.unwrap_or_default().lines() {
+        output.push_str(format!("## {}", line).trim_end());
+        output.push('\n');
+    }
+}
+
+fn write_section(output: &mut String, section: &str, map: &Map<String, Value>) {
+    // The plain values first: they would belong to the previous table otherwise.
+    for (key, value) in map.iter().filter(|(k, _)| !is_section(&join(section, k))) {
+        write_comment(output, field_docs(section, key));
+        match value {
+            // Not set by default.
+            Value::Null => output.push_str(&format!("#{} =\n", toml_key(key))),
+            _ => output.push_str(&format!("{} = {}\n", toml_key(key), toml_value(value))),
+        }
+    }
+    for (key, value) in map.iter() {
+        let path = join(section, key);
+        if let (true, Value::Object(map)) = (is_section(&path), value) {
+            output.push('\n');
+            write_comment(output, field_docs(section, key));
+            output.push_str(&format!("[{}]\n", path));
+            write_section(output, &path, map);
+        }
+    }
+}
+
+/// The configuration file for `config`. With `redact`, the secrets are replaced with
+/// "***SECRET***", otherwise with placeholders.
+pub fn generate(config: &Configuration, redact: bool) -> Result<String> {
+    let mut value = serde_json::to_value(config).context("while serializing the configuration")?;
+    for (pointer, placeholder) in SECRETS {
+        if let Some(secret) = value.pointer_mut(pointer) {
+            if secret.as_str().is_some_and(|s| !s.is_empty()) {
+                *secret = Value::String(if redact { REDACTED } else { placeholder }.to_owned());
+            }
+        }
+    }
+    // The database URLs can contain a password: use their redacted `Debug` form.
+    value["database_url"] = Value::String(format!("{:?}", config.database_url));
+    if let Some(url) = &config.database_read_url {
+        value["database_read_url"] = Value::String(format!("{:?}", url));
+    }
+    let mut output = String::from(if redact {
+        "## LLDAP configuration, generated by `lldap generate_config --from-current`.\n## The secrets were replaced with \"***SECRET***\": set them again before using this file.\n\n"
+    } else {
+        "## LLDAP configuration, generated by `lldap generate_config`.\n## Every setting has its default value. They can also be set with environment variables,\n## e.g. LLDAP_SMTP_OPTIONS__SERVER for smtp_options.server.\n\n"
+    });
+    write_section(
+        &mut output,
+        "",
+        value.as_object().context("Unexpected configuration")?,
+    );
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::types::{AttributeName, GroupName, UserId},
+        infra::{access_control::AttributeVisibility, graphql::api::GraphQLIntrospection},
+    };
+    use figment::{
+        providers::{Format, Toml},
+        Figment,
+    };
+    use pretty_assertions::assert_eq;
+    use secstr::SecUtf8;
+
+    fn parse(contents: &str) -> Configuration {
+        // Without the defaults: every setting has to be in the file.
+        Figment::from(Toml::string(contents))
+            .extract()
+            .unwrap_or_else(|e| panic!("{}\n{}", e, contents))
+    }
+
+    fn to_json(config: &Configuration) -> Value {
+        serde_json::to_value(config).unwrap()
+    }
+
+    #[test]
+    fn test_defaults_round_trip() {
+        let defaults = Configuration::defaults();
+        let parsed = parse(&generate(&defaults, false).unwrap());
+        let mut expected = defaults;
+        expected.jwt_secret = SecUtf8::from("REPLACE_WITH_RANDOM");
+        expected.ldap_user_pass = SecUtf8::from("REPLACE_WITH_PASSWORD");
+        assert_eq!(to_json(&parsed), to_json(&expected));
+    }
+
+    #[test]
+    fn test_current_round_trip() {
+        let mut config = Configuration::defaults();
+        config.key_seed = Some(SecUtf8::from("seed"));
+        config.smtp_options.port = Some(2525);
+        config.smtp_options.password = SecUtf8::from("smtp password");
+        config.smtp_options.from = Some("LLDAP <lldap@example.com>".parse().unwrap());
+        config.cors.allowed_origins = vec!["https://admin.example.com".to_owned()];
+        config.otel.sample_ratio = 0.25;
+        config.trusted_proxies = vec!["10.0.0.0/8".parse().unwrap()];
+        config.ignored_user_attributes = vec![AttributeName::from("quote \" and \\")];
+        config
+            .attribute_visibility
+            .insert(AttributeName::from("mail"), AttributeVisibility::Public);
+        config.ldap_bind_scopes.insert(
+            UserId::new("nextcloud_bind"),
+            vec![
+                GroupName::from("nextcloud_users"),
+                GroupName::from("admins"),
+            ],
+        );
+        config.http_path_prefix = "/lldap".to_owned();
+        config.graphql_introspection_enabled = GraphQLIntrospection::AdminsOnly;
+        config.replication.primary_url = Some("https://lldap.example.com".parse().unwrap());
+        config.replication.token = Some(SecUtf8::from("replication token"));
+        config.account_expiration.admin_email =
+            Some("LLDAP admins <admins@example.com>".parse().unwrap());
+        let parsed = parse(&generate(&config, true).unwrap());
+        config.jwt_secret = SecUtf8::from(REDACTED);
+        config.ldap_user_pass = SecUtf8::from(REDACTED);
+        config.key_seed = Some(SecUtf8::from(REDACTED));
+        config.smtp_options.password = SecUtf8::from(REDACTED);
+        config.replication.token = Some(SecUtf8::from(REDACTED));
+        assert_eq!(to_json(&parsed), to_json(&config));
+    }
+
+    #[test]
+    fn test_redacts_the_database_password() {
+        let mut config = Configuration::defaults();
+        config.database_url = "postgres://lldap:hunter2@db/lldap".into();
+        let generated = generate(&config, true).unwrap();
+        assert!(!generated.contains("hunter2"), "{}", generated);
+    }
+
+    #[test]
+    fn test_every_setting_is_commented() {
+        let generated = generate(&Configuration::defaults(), false).unwrap();
+        let lines = generated.lines().collect::<Vec<_>>();
+        for (i, line) in lines.iter().enumerate().skip(1) {
+            if !line.is_empty() && !line.starts_with("##") {
+                assert!(
+                    lines[i - 1].starts_with("##"),
+                    "No description for `{}`",
+                    line
+                );
+            }
+        }
+        assert!(generated.contains("\n[smtp_options]\n"));
+        assert!(generated.contains("\n[database_options.sqlite]\n"));
+        assert!(generated.contains("\n#key_seed =\n"));
+    }
+}
@@ -0,0 +1,252 @@
+//! CORS headers for the API and the authentication endpoints, for admin UIs hosted on other
+//! origins. Without any allowed origin, nothing is added and the browsers only allow same-origin
+//! requests.
+
+use crate::infra::configuration::CorsOptions;
+use actix_http::header::{self, HeaderValue};
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    HttpResponse,
+};
+use futures::future::{ok, Ready};
+use futures_util::FutureExt;
+use std::{
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+pub struct Cors {
+    options: Rc<CorsOptions>,
+}
+
+impl Cors {
+    pub fn new(options: CorsOptions) -> Self {
+        Self {
+            options: Rc::new(options),
+        }
+    }
+}
+
+impl<S> Transform<S, ServiceRequest> for Cors
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = CorsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CorsMiddleware {
+            service,
+            options: self.options.clone(),
+        })
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: S,
+    options: Rc<CorsOptions>,
+}
+
+fn add_headers(options: &CorsOptions, headers: &mut header::HeaderMap, allowed_origin: &str) {
+    if let Ok(value) = HeaderValue::from_str(allowed_origin) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+    }
+    if options.allow_credentials {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+}
+
+impl<S> CorsMiddleware<S> {
+    /// The value of the Access-Control-Allow-Origin header, if the origin is allowed.
+    fn allowed_origin(&self, origin: &str) -> Option<String> {
+        if self.options.allowed_origins.iter().any(|o| o == origin) {
+            Some(origin.to_owned())
+        } else if self.options.allowed_origins.iter().any(|o| o == "*") {
+            Some("*".to_owned())
+        } else {
+            None
+        }
+    }
+
+    fn preflight_response(&self, allowed_origin: &str) -> HttpResponse {
+        let mut response = HttpResponse::NoContent()
+            .insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, "GET, POST, OPTIONS"))
+            .insert_header((
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                self.options.allowed_headers.join(", "),
+            ))
+            .insert_header((
+                header::ACCESS_CONTROL_MAX_AGE,
+                self.options.max_age_secs.to_string(),
+            ))
+            .finish();
+        add_headers(&self.options, response.headers_mut(), allowed_origin);
+        response
+    }
+}
+
+impl<S> Service<ServiceRequest> for CorsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn core::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let origin = match req.headers().get(header::ORIGIN) {
+            Some(origin) if !self.options.allowed_origins.is_empty() => {
+                origin.to_str().unwrap_or_default().to_owned()
+            }
+            _ => return Box::pin(self.service.call(req)),
+        };
+        let allowed_origin = self.allowed_origin(&origin);
+        let is_preflight = req.method() == Method::OPTIONS
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if is_preflight {
+            let response = match &allowed_origin {
+                Some(allowed_origin) => self.preflight_response(allowed_origin),
+                None => HttpResponse::Forbidden().finish(),
+            };
+            return async move { Ok(req.into_response(response)) }.boxed_local();
+        }
+        let allowed_origin = match allowed_origin {
+            Some(allowed_origin) => allowed_origin,
+            // The browser will block the response.
+            None => return Box::pin(self.service.call(req)),
+        };
+        let options = self.options.clone();
+        let response = self.service.call(req);
+        async move {
+            let mut response = response.await?;
+            add_headers(&options, response.headers_mut(), &allowed_origin);
+            Ok(response)
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+    use pretty_assertions::assert_eq;
+
+    fn options() -> CorsOptions {
+        CorsOptions {
+            allowed_origins: vec!["https://admin.example.com".to_owned()],
+            ..Default::default()
+        }
+    }
+
+    async fn call(
+        options: CorsOptions,
+        request: test::TestRequest,
+    ) -> (StatusCode, Vec<(String, String)>) {
+        let app = test::init_service(App::new().service(
+            web::scope("/api").wrap(Cors::new(options)).route(
+                "/graphql",
+                web::post().to(|| async { HttpResponse::Ok().finish() }),
+            ),
+        ))
+        .await;
+        let response = test::call_service(&app, request.to_request()).await;
+        let mut headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| {
+                name.as_str().starts_with("access-control-") || name.as_str() == "vary"
+            })
+            .map(|(name, value)| (name.as_str().to_owned(), value.to_str().unwrap().to_owned()))
+            .collect::<Vec<_>>();
+        headers.sort();
+        (response.status(), headers)
+    }
+
+    fn header(name: &str, value: &str) -> (String, String) {
+        (name.to_owned(), value.to_owned())
+    }
+
+    #[actix_web::test]
+    async fn test_same_origin_only_by_default() {
+        let request = test::TestRequest::post()
+            .uri("/api/graphql")
+            .insert_header((header::ORIGIN, "https://admin.example.com"));
+        assert_eq!(
+            call(CorsOptions::default(), request).await,
+            (StatusCode::OK, vec![])
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_allowed_origin() {
+        let request = test::TestRequest::post()
+            .uri("/api/graphql")
+            .insert_header((header::ORIGIN, "https://admin.example.com"));
+        assert_eq!(
+            call(options(), request).await,
+            (
+                StatusCode::OK,
+                vec![
+                    header("access-control-allow-credentials", "true"),
+                    header("access-control-allow-origin", "https://admin.example.com"),
+                    header("vary", "Origin"),
+                ]
+            )
+        );
+        let request = test::TestRequest::post()
+            .uri("/api/graphql")
+            .insert_header((header::ORIGIN, "https://evil.example.com"));
+        assert_eq!(call(options(), request).await, (StatusCode::OK, vec![]));
+    }
+
+    #[actix_web::test]
+    async fn test_preflight() {
+        let preflight = |origin: &str| {
+            test::TestRequest::default()
+                .method(Method::OPTIONS)
+                .uri("/api/graphql")
+                .insert_header((header::ORIGIN, origin))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+        };
+        assert_eq!(
+            call(options(), preflight("https://admin.example.com")).await,
+            (
+                StatusCode::NO_CONTENT,
+                vec![
+                    header("access-control-allow-credentials", "true"),
+                    header(
+                        "access-control-allow-headers",
+                        "Authorization, Content-Type"
+                    ),
+                    header("access-control-allow-methods", "GET, POST, OPTIONS"),
+                    header("access-control-allow-origin", "https://admin.example.com"),
+                    header("access-control-max-age", "3600"),
+                    header("vary", "Origin"),
+                ]
+            )
+        );
+        assert_eq!(
+            call(options(), preflight("https://evil.example.com")).await,
+            (StatusCode::FORBIDDEN, vec![])
+        );
+    }
+}
@@ -0,0 +1,844 @@
+//! `lldap import_csv`: creates the users listed in a CSV file, e.g. an HR export, and with
+//! `--update-existing`, updates the ones that already exist. A mapping file assigns the columns
+//! to the fields of the users:
+//!
+//! ```toml
+//! id = "username"
+//! email = "email"
+//! first_name = "First name"
+//! last_name = "Last name"
+//! # The hosts the user may log into, several per cell separated by `list_delimiter`.
+//! hosts = "Hosts"
+//! # Columns of group names, several per cell separated by `list_delimiter`.
+//! groups = ["groups"]
+//! list_delimiter = ";"
+//!
+//! [attributes]
+//! # Custom user attribute = column. The lists are separated by `list_delimiter` too.
+//! department = "Department"
+//! ```
+//!
+//! The users are created and updated the same way as through GraphQL: the same checks of the
+//! attributes against the schema, and the creation with the memberships in one transaction. The
+//! invalid rows are reported with their line number, without stopping the import unless
+//! `strict`.
+
+use crate::domain::{
+    deserialize::deserialize_attribute,
+    handler::{
+        AttributeList, CreateUserRequest, GroupListerBackendHandler, ReadSchemaBackendHandler,
+        UpdateUserRequest, UserBackendHandler, UserListerBackendHandler,
+    },
+    sql_backend_handler::SqlBackendHandler,
+    types::{AttributeName, AttributeValue, Email, GroupId, GroupName, User, UserId},
+    validation::{
+        normalize_hosts, normalize_phone_number, validate_email, validate_host,
+        validate_phone_number, validate_user_id,
+    },
+};
+use crate::infra::avatar_source::AvatarImporter;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use figment::{
+    providers::{Format, Toml},
+    Figment,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+};
+use tracing::{info, warn};
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_list_delimiter() -> String {
+    ";".to_owned()
+}
+
+/// The column of each field. Only `id` and `email` are required.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvMapping {
+    pub id: String,
+    pub email: String,
+    pub display_name: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    /// The column with the hosts the user may log into.
+    pub hosts: Option<String>,
+    pub phone: Option<String>,
+    pub mobile: Option<String>,
+    pub postal_address: Option<String>,
+    pub locality: Option<String>,
+    pub state_or_province: Option<String>,
+    /// The columns with group names. The groups must exist.
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// The custom attributes, by name, and their column.
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+    /// Between the columns.
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    /// Between the values of the group columns and of the list attributes.
+    #[serde(default = "default_list_delimiter")]
+    pub list_delimiter: String,
+}
+
+pub fn read_mapping(path: &Path) -> Result<CsvMapping> {
+    // The figment providers ignore the missing files.
+    ensure!(
+        path.is_file(),
+        "The file `{}` doesn't exist",
+        path.display()
+    );
+    let mapping: CsvMapping = Figment::from(Toml::file(path))
+        .extract()
+        .context(format!("while reading `{}`", path.display()))?;
+    ensure!(
+        mapping.delimiter.is_ascii(),
+        "The delimiter must be an ASCII character"
+    );
+    ensure!(
+        !mapping.list_delimiter.is_empty(),
+        "The list_delimiter can't be empty"
+    );
+    Ok(mapping)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportOptions {
+    /// Update the users that already exist, instead of skipping them.
+    pub update_existing: bool,
+    /// Stop at the first invalid row. The rows are all checked before anything is written.
+    pub strict: bool,
+}
+
+/// A row that was not imported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RowError {
+    pub line: u64,
+    pub error: String,
+}
+
+impl std::fmt::Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ImportSummary {
+    pub created: usize,
+    pub updated: usize,
+    /// Already there: without `update_existing`, or unchanged.
+    pub skipped: usize,
+    pub failed: Vec<RowError>,
+}
+
+impl std::fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Created {} users, updated {}, skipped {}, failed {}",
+            self.created,
+            self.updated,
+            self.skipped,
+            self.failed.len()
+        )
+    }
+}
+
+/// A valid row.
+#[derive(Debug)]
+struct CsvUser {
+    line: u64,
+    user_id: UserId,
+    email: Email,
+    display_name: Option<String>,
+    /// Normalized. None for an empty cell, which doesn't change them.
+    hosts: Option<Vec<String>>,
+    /// The phone numbers are normalized. Like the display name, the empty cells don't change the
+    /// values.
+    phone: Option<String>,
+    mobile: Option<String>,
+    postal_address: Option<String>,
+    locality: Option<String>,
+    state_or_province: Option<String>,
+    /// Including the first and last names.
+    attributes: Vec<AttributeValue>,
+    groups: Vec<(GroupName, GroupId)>,
+}
+
+/// The index of each column of the mapping in the header.
+struct Columns {
+    id: usize,
+    email: usize,
+    display_name: Option<usize>,
+    first_name: Option<usize>,
+    last_name: Option<usize>,
+    hosts: Option<usize>,
+    phone: Option<usize>,
+    mobile: Option<usize>,
+    postal_address: Option<usize>,
+    locality: Option<usize>,
+    state_or_province: Option<usize>,
+    groups: Vec<usize>,
+    attributes: Vec<(String, usize)>,
+}
+
+impl Columns {
+    fn new(mapping: &CsvMapping, headers: &csv::StringRecord) -> Result<Self> {
+        let index = |column: &str| {
+            headers
+                .iter()
+                .position(|h| h.trim() == column)
+                .ok_or_else(|| anyhow!("The column `{}` is not in the header of the file", column))
+        };
+        let optional = |column: &Option<String>| column.as_deref().map(index).transpose();
+        Ok(Self {
+            id: index(&mapping.id)?,
+            email: index(&mapping.email)?,
+            display_name: optional(&mapping.display_name)?,
+            first_name: optional(&mapping.first_name)?,
+            last_name: optional(&mapping.last_name)?,
+            hosts: optional(&mapping.hosts)?,
+            phone: optional(&mapping.phone)?,
+            mobile: optional(&mapping.mobile)?,
+            postal_address: optional(&mapping.postal_address)?,
+            locality: optional(&mapping.locality)?,
+            state_or_province: optional(&mapping.state_or_province)?,
+            groups: mapping
+                .groups
+                .iter()
+                .map(|c| index(c))
+                .collect::<Result<_>>()?,
+            attributes: mapping
+                .attributes
+                .iter()
+                .map(|(name, c)| Ok((name.clone(), index(c)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+struct RowParser<'a> {
+    mapping: &'a CsvMapping,
+    columns: Columns,
+    user_attributes: &'a AttributeList,
+    groups: &'a HashMap<GroupName, GroupId>,
+}
+
+impl RowParser<'_> {
+    fn split(&self, cell: &str) -> Vec<String> {
+        cell.split(self.mapping.list_delimiter.as_str())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn attribute(&self, name: &str, cell: &str) -> Result<AttributeValue> {
+        let is_list = self
+            .user_attributes
+            .get_attribute_type(&AttributeName::from(name))
+            .is_some_and(|(_, is_list)| is_list);
+        let values = if is_list {
+            self.split(cell)
+        } else {
+            vec![cell.to_owned()]
+        };
+        deserialize_attribute(self.user_attributes, name, &values, true)
+    }
+
+    fn parse(&self, line: u64, record: &csv::StringRecord) -> Result<CsvUser> {
+        let cell = |index: usize| record.get(index).map(str::trim).filter(|c| !c.is_empty());
+        let optional_cell = |index: Option<usize>| index.and_then(cell);
+        let id = cell(self.columns.id).context("The user id is empty")?;
+        validate_user_id(id)?;
+        let email = cell(self.columns.email).context("The email is empty")?;
+        validate_email(email)?;
+        let mut attributes = Vec::new();
+        let names = [
+            ("first_name", self.columns.first_name),
+            ("last_name", self.columns.last_name),
+        ];
+        for (name, index) in names
+            .into_iter()
+            .map(|(name, index)| (name.to_owned(), index))
+            .chain(
+                self.columns
+                    .attributes
+                    .iter()
+                    .map(|(name, index)| (name.clone(), Some(*index))),
+            )
+        {
+            if let Some(value) = optional_cell(index) {
+                attributes.push(self.attribute(&name, value)?);
+            }
+        }
+        let hosts = optional_cell(self.columns.hosts)
+            .map(|c| {
+                let hosts = normalize_hosts(&self.split(c));
+                hosts.iter().try_for_each(|host| validate_host(host))?;
+                Ok::<_, anyhow::Error>(hosts)
+            })
+            .transpose()?;
+        let phone_number = |index: Option<usize>| {
+            optional_cell(index)
+                .map(|c| {
+                    let phone = normalize_phone_number(c);
+                    validate_phone_number(&phone, false)?;
+                    Ok::<_, anyhow::Error>(phone)
+                })
+                .transpose()
+        };
+        let text = |index: Option<usize>| optional_cell(index).map(str::to_owned);
+        let mut groups = Vec::new();
+        for &index in &self.columns.groups {
+            for name in optional_cell(Some(index))
+                .map(|c| self.split(c))
+                .unwrap_or_default()
+            {
+                let name = GroupName::from(name.as_str());
+                let id = *self
+                    .groups
+                    .get(&name)
+                    .ok_or_else(|| anyhow!("The group {} doesn't exist", name))?;
+                if !groups.iter().any(|(_, g)| *g == id) {
+                    groups.push((name, id));
+                }
+            }
+        }
+        Ok(CsvUser {
+            line,
+            user_id: UserId::new(id),
+            email: email.into(),
+            display_name: optional_cell(self.columns.display_name).map(str::to_owned),
+            hosts,
+            phone: phone_number(self.columns.phone)?,
+            mobile: phone_number(self.columns.mobile)?,
+            // The line breaks of the addresses can be in the quoted cells.
+            postal_address: text(self.columns.postal_address),
+            locality: text(self.columns.locality),
+            state_or_province: text(self.columns.state_or_province),
+            attributes,
+            groups,
+        })
+    }
+}
+
+/// Reads and checks all the rows. The invalid ones are returned as errors.
+fn parse_rows(
+    parser: &RowParser<'_>,
+    reader: &mut csv::Reader<impl std::io::Read>,
+) -> (Vec<CsvUser>, Vec<RowError>) {
+    let mut users = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen = HashMap::<UserId, u64>::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                errors.push(RowError {
+                    line: e.position().map(|p| p.line()).unwrap_or_default(),
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let user = parser.parse(line, &record).and_then(|user| {
+            if let Some(first) = seen.insert(user.user_id.clone(), line) {
+                bail!("The user {} is already on line {}", user.user_id, first);
+            }
+            Ok(user)
+        });
+        match user {
+            Ok(user) => users.push(user),
+            Err(e) => errors.push(RowError {
+                line,
+                error: format!("{:#}", e),
+            }),
+        }
+    }
+    (users, errors)
+}
+
+/// The update of an existing user, if anything changed.
+fn user_update(existing: &User, user: &CsvUser) -> Option<UpdateUserRequest> {
+    let mut request = UpdateUserRequest {
+        user_id: user.user_id.clone(),
+        ..Default::default()
+    };
+    let mut changed = false;
+    if existing.email != user.email {
+        request.email = Some(user.email.clone());
+        changed = true;
+    }
+    if user.display_name.is_some() && existing.display_name != user.display_name {
+        request.display_name = user.display_name.clone();
+        changed = true;
+    }
+    if user
+        .hosts
+        .as_ref()
+        .is_some_and(|hosts| *hosts != existing.hosts)
+    {
+        request.hosts = user.hosts.clone();
+        changed = true;
+    }
+    for (new, current, field) in [
+        (&user.phone, &existing.phone, &mut request.phone),
+        (&user.mobile, &existing.mobile, &mut request.mobile),
+        (
+            &user.postal_address,
+            &existing.postal_address,
+            &mut request.postal_address,
+        ),
+        (&user.locality, &existing.locality, &mut request.locality),
+        (
+            &user.state_or_province,
+            &existing.state_or_province,
+            &mut request.state_or_province,
+        ),
+    ] {
+        if new.is_some() && new != current {
+            *field = new.clone();
+            changed = true;
+        }
+    }
+    for attribute in &user.attributes {
+        let current = existing
+            .attributes
+            .iter()
+            .find(|a| a.name == attribute.name)
+            .map(|a| &a.value);
+        if current != Some(&attribute.value) {
+            request.insert_attributes.push(attribute.clone());
+            changed = true;
+        }
+    }
+    changed.then_some(request)
+}
+
+async fn import_user(
+    handler: &SqlBackendHandler,
+    user: CsvUser,
+    existing: Option<&(User, HashSet<GroupId>)>,
+    options: ImportOptions,
+    avatar_importer: Option<&dyn AvatarImporter>,
+    summary: &mut ImportSummary,
+) -> Result<()> {
+    let Some((existing, existing_groups)) = existing else {
+        info!("Creating the user {}", user.user_id);
+        let user_id = user.user_id.clone();
+        handler
+            .create_user(CreateUserRequest {
+                user_id: user.user_id,
+                email: user.email,
+                display_name: user.display_name,
+                hosts: user.hosts.unwrap_or_default(),
+                phone: user.phone,
+                mobile: user.mobile,
+                postal_address: user.postal_address,
+                locality: user.locality,
+                state_or_province: user.state_or_province,
+                attributes: user.attributes,
+                groups: user.groups.into_iter().map(|(_, id)| id).collect(),
+                ..Default::default()
+            })
+            .await?;
+        summary.created += 1;
+        if let Some(importer) = avatar_importer {
+            if let Err(e) = importer.import_avatar(&user_id).await {
+                warn!("Could not import the avatar of {}: {:#}", user_id, e);
+            }
+        }
+        return Ok(());
+    };
+    if !options.update_existing {
+        summary.skipped += 1;
+        return Ok(());
+    }
+    let update = user_update(existing, &user);
+    let new_groups = user
+        .groups
+        .iter()
+        .filter(|(_, id)| !existing_groups.contains(id))
+        .collect::<Vec<_>>();
+    if update.is_none() && new_groups.is_empty() {
+        summary.skipped += 1;
+        return Ok(());
+    }
+    info!("Updating the user {}", user.user_id);
+    if let Some(update) = update {
+        handler.update_user(update).await?;
+    }
+    for (name, id) in new_groups {
+        handler
+            .add_user_to_group(&user.user_id, *id)
+            .await
+            .context(format!("while adding the user to the group {}", name))?;
+    }
+    summary.updated += 1;
+    Ok(())
+}
+
+/// Imports the CSV file. With `strict`, the first invalid row stops the import with an error;
+/// otherwise the failed rows are in the summary. With an `avatar_importer`, the new users get
+/// their avatar from the source, or a warning.
+pub async fn import(
+    handler: &SqlBackendHandler,
+    mapping: &CsvMapping,
+    csv: impl std::io::Read,
+    options: ImportOptions,
+    avatar_importer: Option<&dyn AvatarImporter>,
+) -> Result<ImportSummary> {
+    let schema = handler.get_schema().await?;
+    for name in mapping.attributes.keys() {
+        ensure!(
+            schema
+                .user_attributes
+                .get_attribute_schema(&AttributeName::from(name.as_str()))
+                .is_some(),
+            "The attribute {} of the mapping is not in the user schema",
+            name
+        );
+    }
+    let groups = handler
+        .list_groups(None)
+        .await?
+        .into_iter()
+        .map(|g| (g.display_name, g.id))
+        .collect::<HashMap<_, _>>();
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(mapping.delimiter as u8)
+        .flexible(true)
+        .from_reader(csv);
+    let parser = RowParser {
+        mapping,
+        columns: Columns::new(mapping, reader.headers()?)?,
+        user_attributes: &schema.user_attributes,
+        groups: &groups,
+    };
+    let (users, mut errors) = parse_rows(&parser, &mut reader);
+    if options.strict {
+        if let Some(error) = errors.first() {
+            bail!("Nothing was imported: {}", error);
+        }
+    }
+    let existing_users = handler
+        .list_users(None, true)
+        .await?
+        .into_iter()
+        .map(|u| {
+            let groups = u.groups.unwrap_or_default().into_iter().map(|g| g.group_id);
+            (u.user.user_id.clone(), (u.user, groups.collect()))
+        })
+        .collect::<HashMap<_, _>>();
+    let mut summary = ImportSummary::default();
+    for user in users {
+        let (line, user_id) = (user.line, user.user_id.clone());
+        if let Err(e) = import_user(
+            handler,
+            user,
+            existing_users.get(&user_id),
+            options,
+            avatar_importer,
+            &mut summary,
+        )
+        .await
+        {
+            let error = RowError {
+                line,
+                error: format!("{:#}", e),
+            };
+            if options.strict {
+                bail!("Stopped at {}", error);
+            }
+            errors.push(error);
+        }
+    }
+    errors.sort_by_key(|e| e.line);
+    summary.failed = errors;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        handler::{CreateAttributeRequest, SchemaBackendHandler},
+        sql_backend_handler::tests::*,
+        types::{AttributeType, Serialized},
+    };
+    use pretty_assertions::assert_eq;
+
+    const MAPPING: &str = r#"
+id = "username"
+email = "email"
+first_name = "First name"
+groups = ["groups"]
+
+[attributes]
+department = "Department"
+"#;
+
+    const CSV: &str = "username,email,First name,Department,groups
+bob,bob@example.com,Bob,Sales,staff;vpn
+carol,carol@example.com,Carol,,
+bad user,bad@example.com,,,
+dave,dave@example.com,,,nonexistent
+";
+
+    fn mapping(contents: &str) -> CsvMapping {
+        Figment::from(Toml::string(contents)).extract().unwrap()
+    }
+
+    async fn setup() -> SqlBackendHandler {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        handler
+            .add_user_attribute(CreateAttributeRequest {
+                name: "department".into(),
+                attribute_type: AttributeType::String,
+                is_list: false,
+                is_visible: true,
+                is_editable: true,
+            })
+            .await
+            .unwrap();
+        insert_group(&handler, "staff").await;
+        insert_group(&handler, "vpn").await;
+        handler
+    }
+
+    async fn group_names(handler: &SqlBackendHandler, user: &str) -> Vec<String> {
+        let mut groups = handler
+            .get_user_groups(&UserId::new(user))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|g| g.display_name.into_string())
+            .collect::<Vec<_>>();
+        groups.sort();
+        groups
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let handler = &setup().await;
+        let summary = import(
+            handler,
+            &mapping(MAPPING),
+            CSV.as_bytes(),
+            ImportOptions::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.created, 2);
+        assert_eq!(
+            summary.failed.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![4, 5]
+        );
+        assert!(
+            summary.failed[1].error.contains("nonexistent"),
+            "{}",
+            summary.failed[1]
+        );
+        assert_eq!(group_names(handler, "bob").await, vec!["staff", "vpn"]);
+        let bob = handler.get_user_details(&UserId::new("bob")).await.unwrap();
+        assert!(bob
+            .attributes
+            .iter()
+            .any(|a| a.name.as_str() == "department"
+                && a.value == Serialized::from(&"Sales".to_owned())));
+
+        // Again: everything exists.
+        let csv = "username,email,First name,Department,groups\nbob,bob@example.org,Bob,Sales,staff\ncarol,carol@example.com,Carol,,vpn\n";
+        let summary = import(
+            handler,
+            &mapping(MAPPING),
+            csv.as_bytes(),
+            ImportOptions::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!((summary.created, summary.skipped), (0, 2));
+        let summary = import(
+            handler,
+            &mapping(MAPPING),
+            csv.as_bytes(),
+            ImportOptions {
+                update_existing: true,
+                strict: false,
+            },
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!((summary.updated, summary.skipped), (2, 0));
+        assert_eq!(
+            handler
+                .get_user_details(&UserId::new("bob"))
+                .await
+                .unwrap()
+                .email,
+            Email::from("bob@example.org")
+        );
+        assert_eq!(group_names(handler, "carol").await, vec!["vpn"]);
+    }
+
+    #[tokio::test]
+    async fn test_strict() {
+        let handler = &setup().await;
+        let error = import(
+            handler,
+            &mapping(MAPPING),
+            CSV.as_bytes(),
+            ImportOptions {
+                update_existing: false,
+                strict: true,
+            },
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("line 4"), "{}", error);
+        // Checked before writing anything.
+        handler
+            .get_user_details(&UserId::new("bob"))
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_mapping_errors() {
+        let handler = setup().await;
+        let missing_column = mapping("id = \"username\"\nemail = \"mail\"");
+        import(
+            &handler,
+            &missing_column,
+            CSV.as_bytes(),
+            ImportOptions::default(),
+            None,
+        )
+        .await
+        .unwrap_err();
+        let unknown_attribute =
+            mapping("id = \"username\"\nemail = \"email\"\n[attributes]\nsalary = \"Department\"");
+        import(
+            &handler,
+            &unknown_attribute,
+            CSV.as_bytes(),
+            ImportOptions::default(),
+            None,
+        )
+        .await
+        .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_import_hosts() {
+        let handler = &setup().await;
+        let mapping = mapping("id = \"username\"\nemail = \"email\"\nhosts = \"Hosts\"");
+        let csv = "username,email,Hosts\nbob,bob@example.com,Web01;db02\ncarol,carol@example.com,*\ndave,dave@example.com,web_01\n";
+        let summary = import(
+            handler,
+            &mapping,
+            csv.as_bytes(),
+            ImportOptions::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.created, 2);
+        assert_eq!(
+            summary.failed.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![4]
+        );
+        let hosts = |user: &'static str| async move {
+            handler
+                .get_user_details(&UserId::new(user))
+                .await
+                .unwrap()
+                .hosts
+        };
+        assert_eq!(hosts("bob").await, vec!["db02", "web01"]);
+        assert_eq!(hosts("carol").await, vec!["*"]);
+        // An empty cell keeps the hosts.
+        let csv = "username,email,Hosts\nbob,bob@example.com,\ncarol,carol@example.com,web01\n";
+        let summary = import(
+            handler,
+            &mapping,
+            csv.as_bytes(),
+            ImportOptions {
+                update_existing: true,
+                strict: false,
+            },
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!((summary.updated, summary.skipped), (1, 1));
+        assert_eq!(hosts("bob").await, vec!["db02", "web01"]);
+        assert_eq!(hosts("carol").await, vec!["web01"]);
+    }
+
+    #[tokio::test]
+    async fn test_import_phone_and_address() {
+        let handler = &setup().await;
+        let mapping = mapping(
+            "id = \"username\"\nemail = \"email\"\nphone = \"Phone\"\npostal_address = \"Address\"\nlocality = \"City\"",
+        );
+        let csv = "username,email,Phone,Address,City\nbob,bob@example.com,+1 (555) 010-9999,\"1 Main Street\nSpringfield\",Springfield\ncarol,carol@example.com,call me,,\n";
+        let summary = import(
+            handler,
+            &mapping,
+            csv.as_bytes(),
+            ImportOptions::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.created, 1);
+        assert_eq!(
+            summary.failed.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![4]
+        );
+        let bob = handler.get_user_details(&UserId::new("bob")).await.unwrap();
+        assert_eq!(bob.phone.as_deref(), Some("+15550109999"));
+        assert_eq!(
+            bob.postal_address.as_deref(),
+            Some("1 Main Street\nSpringfield")
+        );
+        assert_eq!(bob.locality.as_deref(), Some("Springfield"));
+        // The formatting of the phone numbers is not a change.
+        let csv =
+            "username,email,Phone,Address,City\nbob,bob@example.com,+1 555 010 9999,,Shelbyville\n";
+        let summary = import(
+            handler,
+            &mapping,
+            csv.as_bytes(),
+            ImportOptions {
+                update_existing: true,
+                strict: false,
+            },
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.updated, 1);
+        let bob = handler.get_user_details(&UserId::new("bob")).await.unwrap();
+        assert_eq!(bob.phone.as_deref(), Some("+15550109999"));
+        assert_eq!(
+            bob.postal_address.as_deref(),
+            Some("1 Main Street\nSpringfield")
+        );
+        assert_eq!(bob.locality.as_deref(), Some("Shelbyville"));
+    }
+}
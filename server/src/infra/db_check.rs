@@ -0,0 +1,117 @@
+//! Finds the data that the current rules would reject, e.g. created by an older version, so that
+//! it can be fixed by hand rather than breaking in unexpected places.
+
+use crate::domain::{
+    model,
+    sql_migrations::find_conflicting_emails,
+    sql_tables::DbConnection,
+    validation::{check_email, check_group_name, check_user_id},
+};
+use anyhow::Result;
+use sea_orm::{EntityTrait, QueryOrder};
+
+/// Returns a description of each problem found in the database.
+pub async fn find_problems(pool: &DbConnection) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    for user in model::User::find()
+        .order_by_asc(model::UserColumn::UserId)
+        .all(pool)
+        .await?
+    {
+        if let Err(e) = check_user_id(user.user_id.as_str()) {
+            problems.push(format!("User \"{}\": {}", user.user_id, e));
+        }
+        // The untrimmed emails are reported too: they were stored as typed.
+        if let Err(e) = check_email(user.email.as_str()) {
+            problems.push(format!(
+                "User \"{}\": invalid email \"{}\": {}",
+                user.user_id, user.email, e
+            ));
+        }
+    }
+    for group in model::Group::find()
+        .order_by_asc(model::GroupColumn::GroupId)
+        .all(pool)
+        .await?
+    {
+        if let Err(e) = check_group_name(group.display_name.as_str()) {
+            problems.push(format!(
+                "Group \"{}\" (id {}): {}",
+                group.display_name, group.group_id.0, e
+            ));
+        }
+    }
+    for (email, users) in find_conflicting_emails(pool).await? {
+        problems.push(format!(
+            "Email \"{}\" is used by several users: {}",
+            email,
+            users
+                .iter()
+                .map(|u| u.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        sql_backend_handler::tests::*,
+        types::{GroupName, UserId, Uuid},
+    };
+    use pretty_assertions::assert_eq;
+    use sea_orm::{ActiveModelTrait, Set};
+
+    #[tokio::test]
+    async fn test_find_problems() {
+        let fixture = TestFixture::new().await;
+        let pool = &fixture.handler.sql_pool;
+        assert_eq!(find_problems(pool).await.unwrap(), Vec::<String>::new());
+        // Insert the data directly, bypassing the validation.
+        let now = chrono::Utc::now();
+        model::users::ActiveModel {
+            user_id: Set(UserId::new("doe, john")),
+            email: Set("doe@bob.bob".into()),
+            lowercase_email: Set(Some("doe@bob.bob".to_owned())),
+            creation_date: Set(now),
+            uuid: Set(Uuid::from_name_and_date("doe, john", &now)),
+            ..Default::default()
+        }
+        .insert(pool)
+        .await
+        .unwrap();
+        model::users::ActiveModel {
+            user_id: Set(UserId::new("jane")),
+            email: Set("jane at bob.bob".into()),
+            lowercase_email: Set(Some("jane at bob.bob".to_owned())),
+            creation_date: Set(now),
+            uuid: Set(Uuid::from_name_and_date("jane", &now)),
+            ..Default::default()
+        }
+        .insert(pool)
+        .await
+        .unwrap();
+        let group_id = model::groups::ActiveModel {
+            display_name: Set(GroupName::from("dev;ops ")),
+            lowercase_display_name: Set("dev;ops ".to_owned()),
+            creation_date: Set(now),
+            uuid: Set(Uuid::from_name_and_date("dev;ops ", &now)),
+            ..Default::default()
+        }
+        .insert(pool)
+        .await
+        .unwrap()
+        .group_id;
+        assert_eq!(
+            find_problems(pool).await.unwrap(),
+            vec![
+                "User \"doe, john\": the character ',' is not allowed in user ids (only letters, digits, '.', '_', '-' and '@' are)".to_owned(),
+                "User \"jane\": invalid email \"jane at bob.bob\": missing domain or user".to_owned(),
+                format!("Group \"dev;ops \" (id {}): the group name starts or ends with a space", group_id.0),
+            ]
+        );
+    }
+}
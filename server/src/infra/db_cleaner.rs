@@ -1,17 +1,200 @@
-use crate::domain::{
-    model::{self, JwtRefreshStorageColumn, JwtStorageColumn, PasswordResetTokensColumn},
-    sql_tables::DbConnection,
+use crate::{
+    domain::{
+        model::{
+            self, JwtRefreshStorageColumn, JwtStorageColumn, PasswordResetTokensColumn, UserColumn,
+        },
+        sql_tables::DbConnection,
+        types::UserId,
+    },
+    infra::configuration::Configuration,
 };
 use actix::prelude::{Actor, AsyncContext, Context};
 use cron::Schedule;
-use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
-use std::{str::FromStr, time::Duration};
+use sea_orm::{
+    sea_query::SimpleExpr, ColumnTrait, DbErr, EntityTrait, PaginatorTrait, QueryFilter,
+    QuerySelect, TryGetableMany,
+};
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use strum::IntoEnumIterator;
 use tracing::{error, info, instrument};
 
+/// The tables that keep growing, and what is pruned from them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum, strum::EnumIter)]
+pub enum PruneTarget {
+    /// The expired sessions (refresh tokens).
+    Sessions,
+    /// The expired session tokens, including the revoked ones.
+    Tokens,
+    /// The expired password reset, invitation and email change links.
+    ResetTokens,
+    /// The users deleted for longer than `purge_deleted_after_days`.
+    DeletedUsers,
+}
+
+impl PruneTarget {
+    pub fn table(self) -> &'static str {
+        match self {
+            PruneTarget::Sessions => "jwt_refresh_storage",
+            PruneTarget::Tokens => "jwt_storage",
+            PruneTarget::ResetTokens => "password_reset_tokens",
+            PruneTarget::DeletedUsers => "users",
+        }
+    }
+
+    /// How long the rows are kept after they expired, or the user was deleted. None for the
+    /// deleted users of a replica: they are purged on the primary, then replicated.
+    pub fn retention(self, config: &Configuration) -> Option<chrono::Duration> {
+        let days = match self {
+            PruneTarget::Sessions => config.retention.sessions_days,
+            PruneTarget::Tokens => config.retention.tokens_days,
+            PruneTarget::ResetTokens => config.retention.reset_tokens_days,
+            PruneTarget::DeletedUsers if config.replication.is_replica() => return None,
+            PruneTarget::DeletedUsers => config.purge_deleted_after_days,
+        };
+        Some(chrono::Duration::days(days.into()))
+    }
+}
+
+impl std::fmt::Display for PruneTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PruneTarget::Sessions => "expired sessions",
+            PruneTarget::Tokens => "expired session tokens",
+            PruneTarget::ResetTokens => "expired password reset tokens",
+            PruneTarget::DeletedUsers => "deleted users",
+        })
+    }
+}
+
+/// The rows pruned by the scheduler since the start, by table, for the metrics.
+#[derive(Clone, Default)]
+pub struct PruneStats(Arc<Mutex<BTreeMap<PruneTarget, u64>>>);
+
+impl PruneStats {
+    pub(crate) fn record(&self, target: PruneTarget, rows: u64) {
+        *self.0.lock().unwrap().entry(target).or_default() += rows;
+    }
+
+    /// Every target, including the ones without pruned rows.
+    pub fn get(&self) -> Vec<(PruneTarget, u64)> {
+        let pruned = self.0.lock().unwrap();
+        PruneTarget::iter()
+            .map(|target| (target, pruned.get(&target).copied().unwrap_or_default()))
+            .collect()
+    }
+}
+
+/// Deletes the rows of `target` that expired (or were deleted) before `cutoff`, `batch_size` at
+/// a time, or only counts them with `dry_run`. The rows that didn't expire yet are kept whatever
+/// the cutoff.
+pub async fn prune(
+    sql_pool: &DbConnection,
+    target: PruneTarget,
+    cutoff: chrono::DateTime<chrono::Utc>,
+    batch_size: u64,
+    dry_run: bool,
+) -> Result<u64, DbErr> {
+    let cutoff = cutoff.min(chrono::Utc::now());
+    match target {
+        PruneTarget::Sessions => {
+            delete_in_batches::<model::JwtRefreshStorage, i64>(
+                sql_pool,
+                JwtRefreshStorageColumn::RefreshTokenHash,
+                JwtRefreshStorageColumn::ExpiryDate.lt(cutoff),
+                batch_size,
+                dry_run,
+            )
+            .await
+        }
+        PruneTarget::Tokens => {
+            delete_in_batches::<model::JwtStorage, i64>(
+                sql_pool,
+                JwtStorageColumn::JwtHash,
+                JwtStorageColumn::ExpiryDate.lt(cutoff),
+                batch_size,
+                dry_run,
+            )
+            .await
+        }
+        PruneTarget::ResetTokens => {
+            delete_in_batches::<model::PasswordResetTokens, String>(
+                sql_pool,
+                PasswordResetTokensColumn::Token,
+                PasswordResetTokensColumn::ExpiryDate.lt(cutoff),
+                batch_size,
+                dry_run,
+            )
+            .await
+        }
+        // The attributes, memberships and sessions are deleted along with the user.
+        PruneTarget::DeletedUsers => {
+            delete_in_batches::<model::User, UserId>(
+                sql_pool,
+                UserColumn::UserId,
+                UserColumn::DeletedAt.lt(cutoff),
+                batch_size,
+                dry_run,
+            )
+            .await
+        }
+    }
+}
+
+async fn delete_in_batches<E, K>(
+    sql_pool: &DbConnection,
+    key: E::Column,
+    condition: SimpleExpr,
+    batch_size: u64,
+    dry_run: bool,
+) -> Result<u64, DbErr>
+where
+    E: EntityTrait,
+    E::Model: Sync,
+    K: TryGetableMany + Into<sea_orm::Value>,
+{
+    if dry_run {
+        return E::find().filter(condition).count(sql_pool).await;
+    }
+    let mut deleted = 0;
+    loop {
+        // Postgres has no DELETE with a LIMIT, and SQLite only with a build option.
+        let keys = E::find()
+            .select_only()
+            .column(key)
+            .filter(condition.clone())
+            .limit(batch_size)
+            .into_tuple::<K>()
+            .all(sql_pool)
+            .await?;
+        let selected = keys.len() as u64;
+        if selected == 0 {
+            break;
+        }
+        deleted += E::delete_many()
+            .filter(key.is_in(keys))
+            .exec(sql_pool)
+            .await?
+            .rows_affected;
+        if selected < batch_size {
+            break;
+        }
+    }
+    Ok(deleted)
+}
+
 // Define actor
 pub struct Scheduler {
     schedule: Schedule,
     sql_pool: DbConnection,
+    /// How long to keep the rows of each target, see `PruneTarget::retention`.
+    retention: Vec<(PruneTarget, chrono::Duration)>,
+    batch_size: u64,
+    stats: PruneStats,
 }
 
 // Provide Actor implementation for our actor
@@ -32,13 +215,27 @@ impl Actor for Scheduler {
 }
 
 impl Scheduler {
-    pub fn new(cron_expression: &str, sql_pool: DbConnection) -> Self {
-        let schedule = Schedule::from_str(cron_expression).unwrap();
-        Self { schedule, sql_pool }
+    /// The schedule has been checked with the `[retention]` configuration.
+    pub fn new(config: &Configuration, sql_pool: DbConnection, stats: PruneStats) -> Self {
+        let schedule = Schedule::from_str(&config.retention.schedule).unwrap();
+        Self {
+            schedule,
+            sql_pool,
+            retention: PruneTarget::iter()
+                .filter_map(|target| Some((target, target.retention(config)?)))
+                .collect(),
+            batch_size: config.retention.batch_size,
+            stats,
+        }
     }
 
     fn schedule_task(&self, ctx: &mut Context<Self>) {
-        let future = actix::fut::wrap_future::<_, Self>(Self::cleanup_db(self.sql_pool.clone()));
+        let future = actix::fut::wrap_future::<_, Self>(Self::cleanup_db(
+            self.sql_pool.clone(),
+            self.retention.clone(),
+            self.batch_size,
+            self.stats.clone(),
+        ));
         ctx.spawn(future);
 
         ctx.run_later(self.duration_until_next(), move |this, ctx| {
@@ -47,28 +244,23 @@ impl Scheduler {
     }
 
     #[instrument(skip_all)]
-    async fn cleanup_db(sql_pool: DbConnection) {
-        if let Err(e) = model::JwtRefreshStorage::delete_many()
-            .filter(JwtRefreshStorageColumn::ExpiryDate.lt(chrono::Utc::now().naive_utc()))
-            .exec(&sql_pool)
-            .await
-        {
-            error!("DB error while cleaning up JWT refresh tokens: {}", e);
+    async fn cleanup_db(
+        sql_pool: DbConnection,
+        retention: Vec<(PruneTarget, chrono::Duration)>,
+        batch_size: u64,
+        stats: PruneStats,
+    ) {
+        let now = chrono::Utc::now();
+        for (target, keep) in retention {
+            match prune(&sql_pool, target, now - keep, batch_size, false).await {
+                Ok(0) => (),
+                Ok(rows) => {
+                    stats.record(target, rows);
+                    info!("Pruned {} {}", rows, target);
+                }
+                Err(e) => error!("DB error while pruning the {}: {}", target, e),
+            }
         }
-        if let Err(e) = model::JwtStorage::delete_many()
-            .filter(JwtStorageColumn::ExpiryDate.lt(chrono::Utc::now().naive_utc()))
-            .exec(&sql_pool)
-            .await
-        {
-            error!("DB error while cleaning up JWT storage: {}", e);
-        };
-        if let Err(e) = model::PasswordResetTokens::delete_many()
-            .filter(PasswordResetTokensColumn::ExpiryDate.lt(chrono::Utc::now().naive_utc()))
-            .exec(&sql_pool)
-            .await
-        {
-            error!("DB error while cleaning up password reset tokens: {}", e);
-        };
     }
 
     fn duration_until_next(&self) -> Duration {
@@ -78,3 +270,139 @@ impl Scheduler {
         duration_until.to_std().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{handler::UserBackendHandler, sql_backend_handler::tests::*};
+    use pretty_assertions::assert_eq;
+    use sea_orm::{ActiveModelTrait, QueryOrder, Set};
+
+    async fn insert_session(
+        pool: &DbConnection,
+        hash: i64,
+        expiry_date: chrono::DateTime<chrono::Utc>,
+    ) {
+        model::jwt_refresh_storage::ActiveModel {
+            refresh_token_hash: Set(hash),
+            user_id: Set(UserId::new("bob")),
+            expiry_date: Set(expiry_date),
+        }
+        .insert(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn sessions(pool: &DbConnection) -> Vec<i64> {
+        model::JwtRefreshStorage::find()
+            .select_only()
+            .column(JwtRefreshStorageColumn::RefreshTokenHash)
+            .order_by_asc(JwtRefreshStorageColumn::RefreshTokenHash)
+            .into_tuple::<i64>()
+            .all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_prune_in_batches() {
+        let fixture = TestFixture::new().await;
+        let pool = &fixture.handler.sql_pool;
+        let now = chrono::Utc::now();
+        for hash in 1..=5 {
+            insert_session(pool, hash, now - chrono::Duration::days(2)).await;
+        }
+        insert_session(pool, 6, now - chrono::Duration::hours(1)).await;
+        insert_session(pool, 7, now + chrono::Duration::days(1)).await;
+        let cutoff = now - chrono::Duration::days(1);
+        assert_eq!(
+            prune(pool, PruneTarget::Sessions, cutoff, 2, true)
+                .await
+                .unwrap(),
+            5
+        );
+        assert_eq!(sessions(pool).await.len(), 7);
+        assert_eq!(
+            prune(pool, PruneTarget::Sessions, cutoff, 2, false)
+                .await
+                .unwrap(),
+            5
+        );
+        assert_eq!(sessions(pool).await, vec![6, 7]);
+        // The sessions that didn't expire yet are kept.
+        assert_eq!(
+            prune(
+                pool,
+                PruneTarget::Sessions,
+                now + chrono::Duration::days(2),
+                2,
+                false
+            )
+            .await
+            .unwrap(),
+            1
+        );
+        assert_eq!(sessions(pool).await, vec![7]);
+    }
+
+    #[tokio::test]
+    async fn test_prune_deleted_users() {
+        let fixture = TestFixture::new().await;
+        let pool = &fixture.handler.sql_pool;
+        fixture
+            .handler
+            .delete_user(&UserId::new("bob"))
+            .await
+            .unwrap();
+        let prune_older_than = |days| {
+            prune(
+                pool,
+                PruneTarget::DeletedUsers,
+                chrono::Utc::now() - chrono::Duration::days(days),
+                10,
+                false,
+            )
+        };
+        assert_eq!(prune_older_than(30).await.unwrap(), 0);
+        assert_eq!(prune_older_than(-1).await.unwrap(), 1);
+        // The users that are not deleted are kept.
+        assert_eq!(prune_older_than(-1).await.unwrap(), 0);
+        assert!(fixture
+            .handler
+            .get_user_details(&UserId::new("patrick"))
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_retention() {
+        let mut config = get_default_config();
+        config.retention.sessions_days = 7;
+        assert_eq!(
+            PruneTarget::Sessions.retention(&config),
+            Some(chrono::Duration::days(7))
+        );
+        assert_eq!(
+            PruneTarget::DeletedUsers.retention(&config),
+            Some(chrono::Duration::days(30))
+        );
+        config.replication.primary_url = Some("https://lldap.example.com".parse().unwrap());
+        assert_eq!(PruneTarget::DeletedUsers.retention(&config), None);
+    }
+
+    #[test]
+    fn test_prune_stats() {
+        let stats = PruneStats::default();
+        stats.record(PruneTarget::Tokens, 3);
+        stats.record(PruneTarget::Tokens, 2);
+        assert_eq!(
+            stats.get(),
+            vec![
+                (PruneTarget::Sessions, 0),
+                (PruneTarget::Tokens, 5),
+                (PruneTarget::ResetTokens, 0),
+                (PruneTarget::DeletedUsers, 0),
+            ]
+        );
+    }
+}
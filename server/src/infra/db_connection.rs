@@ -0,0 +1,372 @@
+use crate::{
+    domain::sql_tables::DbConnection,
+    infra::{
+        configuration::{DatabaseOptions, SqliteOptions},
+        database_string::DatabaseUrl,
+    },
+};
+use anyhow::{Context, Result};
+use rand::Rng;
+use sea_orm::{
+    sqlx::{
+        self,
+        sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+        ConnectOptions as _,
+    },
+    ConnectOptions, Database, DatabaseConnection, DbErr, RuntimeErr, SqlxSqliteConnector,
+};
+use std::{
+    future::Future,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(250);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Whether the error means that the database server is not (yet) reachable, as opposed to a
+/// configuration error like wrong credentials or a missing database.
+pub(crate) fn is_transient_error(error: &DbErr) -> bool {
+    let sqlx_error = match error {
+        DbErr::Conn(RuntimeErr::SqlxError(e)) => e,
+        DbErr::ConnectionAcquire(_) => return true,
+        _ => return false,
+    };
+    match sqlx_error {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(e) => matches!(
+            e.code().as_deref(),
+            // Postgres: the database system is starting up / shutting down.
+            Some("57P03") | Some("57P01")
+            // MySQL/MariaDB: too many connections, server shutdown in progress.
+            | Some("08004") | Some("1040") | Some("1053")
+        ),
+        _ => false,
+    }
+}
+
+/// Runs `attempt` until it succeeds, returns a non-transient error, or `timeout` has elapsed.
+///
+/// The delay between the attempts grows exponentially, with some jitter to avoid several
+/// instances retrying in lockstep.
+async fn retry_with_backoff<T, E, F, Fut>(
+    timeout: Duration,
+    is_transient: impl Fn(&E) -> bool,
+    mut attempt: F,
+) -> std::result::Result<T, E>
+where
+    E: std::fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, E>>,
+{
+    let deadline = Instant::now() + timeout;
+    let mut delay = INITIAL_RETRY_DELAY;
+    let mut attempt_number = 1;
+    loop {
+        match attempt().await {
+            Ok(result) => return Ok(result),
+            Err(e) if is_transient(&e) && Instant::now() + delay < deadline => {
+                info!(
+                    "Could not connect to the database (attempt {}): {}. Retrying in {:?}",
+                    attempt_number, e, delay
+                );
+            }
+            Err(e) => return Err(e),
+        }
+        let jitter = rand::thread_rng().gen_range(0..=delay.as_millis() as u64 / 2);
+        tokio::time::sleep(delay + Duration::from_millis(jitter)).await;
+        delay = std::cmp::min(delay * 2, MAX_RETRY_DELAY);
+        attempt_number += 1;
+    }
+}
+
+const MAX_CONNECTIONS: u32 = 5;
+
+/// Opens a pool of SQLite connections, each configured with the given pragmas.
+async fn connect_sqlite(
+    database_url: &DatabaseUrl,
+    options: &SqliteOptions,
+) -> std::result::Result<DbConnection, DbErr> {
+    let invalid_option =
+        |name: &str, e: sqlx::Error| DbErr::Custom(format!("Invalid SQLite {}: {}", name, e));
+    let connect_options = SqliteConnectOptions::from_str(&database_url.to_string())
+        .map_err(|e| invalid_option("database URL", e))?
+        .journal_mode(
+            FromStr::from_str(&options.journal_mode)
+                .map_err(|e| invalid_option("journal_mode", e))?,
+        )
+        .synchronous(
+            FromStr::from_str(&options.synchronous)
+                .map_err(|e| invalid_option("synchronous", e))?,
+        )
+        .busy_timeout(Duration::from_millis(options.busy_timeout_ms))
+        .foreign_keys(options.foreign_keys)
+        .log_statements(log::LevelFilter::Debug);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(MAX_CONNECTIONS)
+        .connect_with(connect_options)
+        .await
+        .map_err(|e| DbErr::Conn(RuntimeErr::SqlxError(e)))?;
+    Ok(SqlxSqliteConnector::from_sqlx_sqlite_pool(pool))
+}
+
+async fn connect_once(
+    database_url: &DatabaseUrl,
+    options: &DatabaseOptions,
+) -> std::result::Result<DbConnection, DbErr> {
+    if database_url.is_sqlite() {
+        return connect_sqlite(database_url, &options.sqlite).await;
+    }
+    let mut sql_opt = ConnectOptions::new(database_url.to_string());
+    sql_opt
+        .max_connections(MAX_CONNECTIONS)
+        .sqlx_logging(true)
+        .sqlx_logging_level(log::LevelFilter::Debug);
+    Database::connect(sql_opt).await
+}
+
+/// Connects to the database, retrying for up to `startup_timeout` while the server is not
+/// reachable.
+pub async fn connect(
+    database_url: &DatabaseUrl,
+    options: &DatabaseOptions,
+    startup_timeout: Duration,
+) -> Result<DbConnection> {
+    retry_with_backoff(startup_timeout, is_transient_error, || {
+        connect_once(database_url, options)
+    })
+    .await
+    .with_context(|| format!("while connecting to the database {:?}", database_url))
+}
+
+/// Connects to the read replica. If it can't be reached, logs why and returns None: all the
+/// queries then go to the primary.
+pub async fn connect_read_replica(
+    database_url: &DatabaseUrl,
+    options: &DatabaseOptions,
+) -> Option<DbConnection> {
+    match connect_once(database_url, options).await {
+        Ok(pool) => {
+            info!("Using the read replica {:?}", database_url);
+            Some(pool)
+        }
+        Err(e) => {
+            warn!(
+                "Could not connect to the read replica {:?}, using the primary database instead: {}",
+                database_url, e
+            );
+            None
+        }
+    }
+}
+
+pub(crate) struct PoolStatus {
+    /// Open connections, idle or in use.
+    pub size: u32,
+    pub idle: usize,
+}
+
+/// The size of the connection pool, or None if `pool` is not backed by an sqlx pool.
+pub(crate) fn pool_status(pool: &DbConnection) -> Option<PoolStatus> {
+    fn status<DB: sqlx::Database>(pool: &sqlx::Pool<DB>) -> PoolStatus {
+        PoolStatus {
+            size: pool.size(),
+            idle: pool.num_idle(),
+        }
+    }
+    match pool {
+        DatabaseConnection::SqlxSqlitePoolConnection(_) => {
+            Some(status(pool.get_sqlite_connection_pool()))
+        }
+        DatabaseConnection::SqlxPostgresPoolConnection(_) => {
+            Some(status(pool.get_postgres_connection_pool()))
+        }
+        DatabaseConnection::SqlxMySqlPoolConnection(_) => {
+            Some(status(pool.get_mysql_connection_pool()))
+        }
+        _ => None,
+    }
+}
+
+/// Acquires a connection from the pool and releases it right away, returning how long it took
+/// to get it, like any query would have to wait at that moment. Err(waited) if it failed, e.g.
+/// after the pool's acquire timeout. None if `pool` is not backed by an sqlx pool.
+pub(crate) async fn time_acquire(
+    pool: &DbConnection,
+) -> Option<std::result::Result<Duration, Duration>> {
+    async fn time<DB: sqlx::Database>(
+        pool: &sqlx::Pool<DB>,
+    ) -> std::result::Result<Duration, Duration> {
+        let start = Instant::now();
+        match pool.acquire().await {
+            Ok(_) => Ok(start.elapsed()),
+            Err(_) => Err(start.elapsed()),
+        }
+    }
+    match pool {
+        DatabaseConnection::SqlxSqlitePoolConnection(_) => {
+            Some(time(pool.get_sqlite_connection_pool()).await)
+        }
+        DatabaseConnection::SqlxPostgresPoolConnection(_) => {
+            Some(time(pool.get_postgres_connection_pool()).await)
+        }
+        DatabaseConnection::SqlxMySqlPoolConnection(_) => {
+            Some(time(pool.get_mysql_connection_pool()).await)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn test_retry_until_listener_is_up() {
+        // Reserve a port, then free it until the "database" starts.
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let server = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(600)).await;
+            let listener = TcpListener::bind(address).await.unwrap();
+            listener.accept().await.unwrap();
+        });
+        let attempts = AtomicUsize::new(0);
+        retry_with_backoff(
+            Duration::from_secs(10),
+            |_: &std::io::Error| true,
+            || {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                TcpStream::connect(address)
+            },
+        )
+        .await
+        .unwrap();
+        assert!(attempts.load(Ordering::Relaxed) > 1);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up_after_timeout() {
+        let attempts = AtomicUsize::new(0);
+        let start = Instant::now();
+        let result: std::result::Result<(), _> = retry_with_backoff(
+            Duration::from_millis(500),
+            |_: &String| true,
+            || {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                async { Err("not reachable".to_owned()) }
+            },
+        )
+        .await;
+        assert_eq!(result, Err("not reachable".to_owned()));
+        assert!(attempts.load(Ordering::Relaxed) > 1);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_on_permanent_error() {
+        let attempts = AtomicUsize::new(0);
+        let result: std::result::Result<(), _> = retry_with_backoff(
+            Duration::from_secs(10),
+            |e: &String| e != "authentication failed",
+            || {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                async { Err("authentication failed".to_owned()) }
+            },
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+    }
+
+    struct TempSqliteFile(std::path::PathBuf);
+
+    impl TempSqliteFile {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("lldap-test-{}.db", uuid::Uuid::new_v4())))
+        }
+
+        fn url(&self) -> DatabaseUrl {
+            DatabaseUrl::from(format!("sqlite://{}?mode=rwc", self.0.display()).as_str())
+        }
+    }
+
+    impl Drop for TempSqliteFile {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm", "-journal"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Writes from a second connection while a write transaction is open, and reads while the
+    /// writes are pending.
+    async fn concurrent_writes(options: SqliteOptions) -> std::result::Result<(), DbErr> {
+        use sea_orm::{ConnectionTrait, Statement, TransactionTrait};
+        let file = TempSqliteFile::new();
+        let pool = connect_sqlite(&file.url(), &options).await.unwrap();
+        let statement =
+            |sql: &str| Statement::from_string(sea_orm::DbBackend::Sqlite, sql.to_owned());
+        pool.execute(statement("CREATE TABLE t (id INTEGER)"))
+            .await
+            .unwrap();
+        let transaction = pool.begin().await.unwrap();
+        transaction
+            .execute(statement("INSERT INTO t VALUES (1)"))
+            .await
+            .unwrap();
+        let (writes, reads, _) = tokio::join!(
+            futures_util::future::try_join_all(
+                (0..4).map(|i| pool.execute(statement(&format!("INSERT INTO t VALUES ({})", i))))
+            ),
+            futures_util::future::try_join_all(
+                (0..4).map(|_| pool.query_all(statement("SELECT id FROM t")))
+            ),
+            async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                transaction.commit().await.unwrap();
+            }
+        );
+        writes?;
+        reads?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_concurrent_reads_and_writes() {
+        concurrent_writes(SqliteOptions::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_concurrent_writes_without_busy_timeout() {
+        let error = concurrent_writes(SqliteOptions {
+            journal_mode: "DELETE".to_owned(),
+            busy_timeout_ms: 0,
+            ..SqliteOptions::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(
+            error.to_string().contains("database is locked"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_sqlite_database_is_not_transient() {
+        let error = Database::connect("sqlite:///doesnt/exist/users.db?mode=rw")
+            .await
+            .unwrap_err();
+        assert!(!is_transient_error(&error));
+    }
+}
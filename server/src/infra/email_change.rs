@@ -0,0 +1,75 @@
+//! Changes of email address: the new address gets a single-use verification link, and the change
+//! only applies once it is clicked. The old address is then notified.
+
+use crate::{
+    domain::types::{User, UserId},
+    infra::{
+        configuration::MailOptions,
+        mail,
+        mail_queue::MailQueue,
+        mail_templates::MailTemplates,
+        sql_backend_handler::EMAIL_CHANGE_TOKEN_VALIDITY_HOURS,
+        tcp_backend_handler::{PendingEmailChange, TcpBackendHandler},
+    },
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::instrument;
+
+#[async_trait]
+pub trait EmailChangeVerifier: Send + Sync {
+    /// Emails a verification link to the new address. It replaces the previous pending change.
+    async fn start_email_change(&self, user: &User, new_email: &str) -> Result<()>;
+    async fn get_pending_email_change(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<PendingEmailChange>>;
+    async fn cancel_email_change(&self, user_id: &UserId) -> Result<()>;
+}
+
+pub struct MailEmailChangeVerifier<Backend> {
+    pub backend_handler: Backend,
+    pub server_url: url::Url,
+    pub mail_options: MailOptions,
+    pub mail_templates: Arc<MailTemplates>,
+    pub mail_queue: MailQueue,
+}
+
+#[async_trait]
+impl<Backend: TcpBackendHandler + Send> EmailChangeVerifier for MailEmailChangeVerifier<Backend> {
+    #[instrument(skip_all, level = "debug", fields(user_id = %user.user_id), err)]
+    async fn start_email_change(&self, user: &User, new_email: &str) -> Result<()> {
+        let token = self
+            .backend_handler
+            .start_email_change(
+                &user.user_id,
+                new_email,
+                chrono::Duration::hours(EMAIL_CHANGE_TOKEN_VALIDITY_HOURS),
+            )
+            .await?;
+        mail::send_email_change_email(
+            user,
+            new_email,
+            &token,
+            &self.server_url,
+            &self.mail_options,
+            &self.mail_templates,
+            &self.mail_queue,
+        )
+    }
+
+    async fn get_pending_email_change(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<PendingEmailChange>> {
+        Ok(self
+            .backend_handler
+            .get_pending_email_change(user_id)
+            .await?)
+    }
+
+    async fn cancel_email_change(&self, user_id: &UserId) -> Result<()> {
+        Ok(self.backend_handler.cancel_email_change(user_id).await?)
+    }
+}
@@ -1,32 +1,207 @@
 use crate::{
-    domain::{handler::BackendHandler, types::UserId},
+    domain::{
+        error::DomainError,
+        handler::BackendHandler,
+        types::{AttributeName, UserId},
+    },
     infra::{
         access_control::{
             AccessControlledBackendHandler, AdminBackendHandler, ReadonlyBackendHandler,
             UserReadableBackendHandler, UserWriteableBackendHandler, ValidationResults,
         },
         auth_service::check_if_token_is_valid,
+        avatar_source::AvatarImporter,
         cli::ExportGraphQLSchemaOpts,
+        configuration::{Configuration, LoginIdentifier},
+        email_change::EmailChangeVerifier,
         graphql::{mutation::Mutation, query::Query},
+        invitation::InvitationSender,
+        request_id::{GraphQLOperation, RequestId},
+        security_status::SecurityChecker,
         tcp_server::AppState,
     },
 };
 use actix_web::FromRequest;
 use actix_web::HttpMessage;
-use actix_web::{error::JsonPayloadError, web, Error, HttpRequest, HttpResponse};
+use actix_web::{http::StatusCode, web, Error, HttpRequest, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use juniper::{
+    graphql_value,
     http::{
         graphiql::graphiql_source, playground::playground_source, GraphQLBatchRequest,
         GraphQLRequest,
     },
-    EmptySubscription, FieldError, RootNode, ScalarValue,
+    EmptySubscription, FieldError, FieldResult, RootNode, ScalarValue,
 };
-use tracing::debug;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, error};
 
 pub struct Context<Handler: BackendHandler> {
     pub handler: AccessControlledBackendHandler<Handler>,
     pub validation_result: ValidationResults,
+    /// None if the emails are not configured.
+    pub invitation_sender: Option<Arc<dyn InvitationSender>>,
+    /// None if the password reset is disabled: the email changes then apply directly.
+    pub email_change_verifier: Option<Arc<dyn EmailChangeVerifier>>,
+    pub avatar_limits: AvatarLimits,
+    /// None without `avatar_source_url_template`.
+    pub avatar_importer: Option<Arc<dyn AvatarImporter>>,
+    /// Lowercase, like the DNs served over LDAP.
+    pub ldap_base_dn: String,
+    pub security_checker: Arc<dyn SecurityChecker>,
+    pub login_identifier: LoginIdentifier,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, juniper::GraphQLObject)]
+/// The avatars accepted by the server.
+pub struct AvatarLimits {
+    /// In kilobytes.
+    pub max_size_kb: i32,
+    /// The largest width and height, in pixels. Only the web UI enforces it, by downscaling the
+    /// pictures before uploading them.
+    pub max_dimension: i32,
+}
+
+impl AvatarLimits {
+    pub fn from_config(config: &Configuration) -> Self {
+        Self {
+            max_size_kb: config.avatar_max_size_kb.try_into().unwrap_or(i32::MAX),
+            max_dimension: config.avatar_max_dimension.try_into().unwrap_or(i32::MAX),
+        }
+    }
+
+    /// Checks the size of a decoded avatar.
+    pub fn check(&self, bytes: &[u8]) -> anyhow::Result<()> {
+        let size_kb = bytes.len().div_ceil(1024);
+        if size_kb > self.max_size_kb as usize {
+            anyhow::bail!(
+                "The avatar is {} kB, over the limit of {} kB",
+                size_kb,
+                self.max_size_kb
+            );
+        }
+        Ok(())
+    }
+
+    /// The largest GraphQL request body: an avatar of the maximum size in base64, and 1 MB for
+    /// the rest of the request, e.g. a `createUsers` batch.
+    pub fn max_request_size(&self) -> usize {
+        let avatar_size = usize::try_from(self.max_size_kb)
+            .unwrap_or_default()
+            .saturating_mul(1024);
+        (avatar_size.div_ceil(3) * 4).saturating_add(1 << 20)
+    }
+}
+
+/// Who may send the introspection queries (`__schema` and `__type`), e.g. from the playground or
+/// to generate a client. In the configuration, `true`, `false` or `"admins_only"`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(try_from = "IntrospectionSetting", into = "IntrospectionSetting")]
+pub enum GraphQLIntrospection {
+    Enabled,
+    Disabled,
+    AdminsOnly,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum IntrospectionSetting {
+    Bool(bool),
+    Mode(String),
+}
+
+impl TryFrom<IntrospectionSetting> for GraphQLIntrospection {
+    type Error = String;
+
+    fn try_from(setting: IntrospectionSetting) -> Result<Self, Self::Error> {
+        match setting {
+            IntrospectionSetting::Bool(true) => Ok(Self::Enabled),
+            IntrospectionSetting::Bool(false) => Ok(Self::Disabled),
+            IntrospectionSetting::Mode(mode) => match mode.to_ascii_lowercase().as_str() {
+                "true" => Ok(Self::Enabled),
+                "false" => Ok(Self::Disabled),
+                "admins_only" => Ok(Self::AdminsOnly),
+                _ => Err(format!(
+                    "Invalid introspection setting \"{}\": expected true, false or \"admins_only\"",
+                    mode
+                )),
+            },
+        }
+    }
+}
+
+impl From<GraphQLIntrospection> for IntrospectionSetting {
+    fn from(introspection: GraphQLIntrospection) -> Self {
+        match introspection {
+            GraphQLIntrospection::Enabled => Self::Bool(true),
+            GraphQLIntrospection::Disabled => Self::Bool(false),
+            GraphQLIntrospection::AdminsOnly => Self::Mode("admins_only".to_owned()),
+        }
+    }
+}
+
+impl GraphQLIntrospection {
+    pub fn is_allowed(&self, validation_result: &ValidationResults) -> bool {
+        match self {
+            Self::Enabled => true,
+            Self::Disabled => false,
+            Self::AdminsOnly => validation_result.is_admin(),
+        }
+    }
+}
+
+/// Whether the query reads the schema, i.e. uses `__schema` or `__type` (but `__typename` is
+/// fine). The names are looked for outside of the comments and the strings, including in the
+/// fragments and behind aliases.
+fn is_introspection_query(query: &str) -> bool {
+    let mut chars = query.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '#' => while chars.next_if(|&(_, c)| c != '\n' && c != '\r').is_some() {},
+            '"' if query[start..].starts_with("\"\"\"") => {
+                chars.next();
+                chars.next();
+                while let Some((i, c)) = chars.next() {
+                    if c == '\\' && query[i..].starts_with("\\\"\"\"") {
+                        chars.nth(2);
+                    } else if query[i..].starts_with("\"\"\"") {
+                        chars.nth(1);
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' | '\n' => break,
+                        _ => (),
+                    }
+                }
+            }
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut end = start + 1;
+                while let Some((i, _)) =
+                    chars.next_if(|&(_, c)| c == '_' || c.is_ascii_alphanumeric())
+                {
+                    end = i + 1;
+                }
+                if matches!(&query[start..end], "__schema" | "__type") {
+                    return true;
+                }
+            }
+            _ => (),
+        }
+    }
+    false
+}
+
+/// An error with a "PERMISSION_DENIED" code.
+pub fn permission_denied(message: impl std::fmt::Display) -> FieldError {
+    FieldError::new(message, graphql_value!({ "code": "PERMISSION_DENIED" }))
 }
 
 pub fn field_error_callback<'a>(
@@ -35,7 +210,61 @@ pub fn field_error_callback<'a>(
 ) -> impl 'a + FnOnce() -> FieldError {
     move || {
         span.in_scope(|| debug!("Unauthorized"));
-        FieldError::from(error_message)
+        permission_denied(error_message)
+    }
+}
+
+/// Converts the errors that the client can fix or retry into errors with a "code" extension, for
+/// the frontend to recognize them. The internal errors are only logged: the response has an
+/// "INTERNAL_ERROR" code and the request ID, but not the details (e.g. the SQL).
+pub fn domain_error_to_field_error(error: DomainError) -> FieldError {
+    match error {
+        DomainError::EmailAlreadyInUse(email) => FieldError::new(
+            format!("Email already in use: {}", email),
+            graphql_value!({ "code": "EMAIL_ALREADY_IN_USE" }),
+        ),
+        DomainError::ValidationError(message) => {
+            FieldError::new(message, graphql_value!({ "code": "INVALID_INPUT" }))
+        }
+        DomainError::ServerBusy(message) => {
+            FieldError::new(message, graphql_value!({ "code": "SERVER_BUSY" }))
+        }
+        e @ DomainError::ReadOnlyReplica => FieldError::new(
+            e.to_string(),
+            graphql_value!({ "code": "READ_ONLY_REPLICA" }),
+        ),
+        e @ DomainError::LastAdmin(_) => {
+            FieldError::new(e.to_string(), graphql_value!({ "code": "LAST_ADMIN" }))
+        }
+        e @ DomainError::LastGroupMember(..) => FieldError::new(
+            e.to_string(),
+            graphql_value!({ "code": "LAST_GROUP_MEMBER" }),
+        ),
+        DomainError::EntityNotFound(message) => FieldError::new(
+            format!("Not found: {}", message),
+            graphql_value!({ "code": "NOT_FOUND" }),
+        ),
+        DomainError::Base64DecodeError(e) => FieldError::new(
+            format!("Invalid base64: {}", e),
+            graphql_value!({ "code": "INVALID_BASE64" }),
+        ),
+        e @ (DomainError::AuthenticationError(_) | DomainError::AuthenticationProtocolError(_)) => {
+            FieldError::new(
+                e.to_string(),
+                graphql_value!({ "code": "PERMISSION_DENIED" }),
+            )
+        }
+        e @ (DomainError::DatabaseError(_)
+        | DomainError::DatabaseTransactionError(_)
+        | DomainError::InternalError(_)
+        | DomainError::UnknownCryptoError(_)
+        | DomainError::BinarySerializationError(_)) => {
+            error!("GraphQL internal error: {}", e);
+            FieldError::new(
+                "Internal server error",
+                graphql_value!({ "code": "INTERNAL_ERROR" }),
+            )
+        }
     }
 }
 
@@ -45,6 +274,16 @@ impl<Handler: BackendHandler> Context<Handler> {
         Self {
             handler: AccessControlledBackendHandler::new(handler),
             validation_result,
+            invitation_sender: None,
+            email_change_verifier: None,
+            avatar_limits: AvatarLimits {
+                max_size_kb: 1024,
+                max_dimension: 512,
+            },
+            avatar_importer: None,
+            ldap_base_dn: "dc=example,dc=com".to_owned(),
+            security_checker: Arc::new(Vec::new()),
+            login_identifier: LoginIdentifier::UserId,
         }
     }
 
@@ -71,6 +310,29 @@ impl<Handler: BackendHandler> Context<Handler> {
         self.handler
             .get_readable_handler(&self.validation_result, user_id)
     }
+
+    pub fn can_read_user_attribute(&self, user_id: &UserId, attribute: &AttributeName) -> bool {
+        self.handler
+            .can_read_user_attribute(&self.validation_result, user_id, attribute)
+    }
+
+    /// Returns an error with a "FORBIDDEN" code if the attribute of the user is not visible to
+    /// the current user. This only nulls the field, rather than failing the whole query.
+    pub fn check_user_attribute_access(
+        &self,
+        user_id: &UserId,
+        attribute: &str,
+    ) -> FieldResult<()> {
+        if self.can_read_user_attribute(user_id, &AttributeName::from(attribute)) {
+            Ok(())
+        } else {
+            debug!(?user_id, attribute, "Unauthorized access to user attribute");
+            Err(FieldError::new(
+                format!("Unauthorized access to attribute {}", attribute),
+                graphql_value!({ "code": "FORBIDDEN" }),
+            ))
+        }
+    }
 }
 
 impl<Handler: BackendHandler> juniper::Context for Context<Handler> {}
@@ -127,18 +389,102 @@ struct GetGraphQLRequest {
     variables: Option<String>,
 }
 
-impl<S> From<GetGraphQLRequest> for GraphQLRequest<S>
-where
-    S: ScalarValue,
-{
-    fn from(get_req: GetGraphQLRequest) -> Self {
+impl GetGraphQLRequest {
+    fn into_graphql_request<S: ScalarValue>(self) -> serde_json::Result<GraphQLRequest<S>> {
         let GetGraphQLRequest {
             query,
             operation_name,
             variables,
-        } = get_req;
-        let variables = variables.map(|s| serde_json::from_str(&s).unwrap());
-        Self::new(query, operation_name, variables)
+        } = self;
+        let variables = variables.map(|s| serde_json::from_str(&s)).transpose()?;
+        Ok(GraphQLRequest::new(query, operation_name, variables))
+    }
+}
+
+/// A response in the GraphQL format for a request that couldn't be parsed, so that the clients
+/// get the same error shape (and the request ID) as for the errors in the resolvers.
+fn request_error_response(
+    req: &HttpRequest,
+    status: StatusCode,
+    code: &str,
+    message: impl std::fmt::Display,
+) -> HttpResponse {
+    let mut body = serde_json::json!({
+        "errors": [{ "message": message.to_string(), "extensions": { "code": code } }],
+    });
+    add_request_id_to_errors(req, &mut body);
+    HttpResponse::build(status).json(body)
+}
+
+fn invalid_request(req: &HttpRequest, message: impl std::fmt::Display) -> HttpResponse {
+    request_error_response(req, StatusCode::BAD_REQUEST, "INVALID_REQUEST", message)
+}
+
+/// Refuses the requests that read the schema, unless `allow_introspection`.
+fn check_introspection<'a, S: ScalarValue + 'a>(
+    req: &HttpRequest,
+    requests: impl IntoIterator<Item = &'a GraphQLRequest<S>>,
+    allow_introspection: bool,
+) -> Result<(), HttpResponse> {
+    if allow_introspection {
+        return Ok(());
+    }
+    let is_introspection = |request: &GraphQLRequest<S>| {
+        serde_json::to_value(request)
+            .ok()
+            .and_then(|request| request["query"].as_str().map(is_introspection_query))
+            // Refused if the query can't be read.
+            .unwrap_or(true)
+    };
+    if requests.into_iter().any(is_introspection) {
+        Err(request_error_response(
+            req,
+            StatusCode::FORBIDDEN,
+            "INTROSPECTION_DISABLED",
+            "The introspection queries are disabled on this server",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+async fn read_body(
+    req: &HttpRequest,
+    payload: &mut actix_http::Payload,
+) -> Result<String, HttpResponse> {
+    String::from_request(req, payload).await.map_err(|e| {
+        if e.as_response_error().status_code() == StatusCode::PAYLOAD_TOO_LARGE {
+            request_error_response(
+                req,
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "PAYLOAD_TOO_LARGE",
+                "The request is too large",
+            )
+        } else {
+            invalid_request(req, e)
+        }
+    })
+}
+
+async fn parse_post_request<S: ScalarValue>(
+    req: &HttpRequest,
+    payload: &mut actix_http::Payload,
+) -> Result<GraphQLBatchRequest<S>, HttpResponse> {
+    match req.content_type() {
+        "application/json" => {
+            let body = read_body(req, payload).await?;
+            serde_json::from_str::<GraphQLBatchRequest<S>>(&body)
+                .map_err(|e| invalid_request(req, format!("Invalid JSON request: {}", e)))
+        }
+        "application/graphql" => Ok(GraphQLBatchRequest::Single(GraphQLRequest::new(
+            read_body(req, payload).await?,
+            None,
+            None,
+        ))),
+        content_type => Err(invalid_request(
+            req,
+            format!("Unsupported content type: \"{}\"", content_type),
+        )),
     }
 }
 
@@ -147,6 +493,7 @@ pub async fn get_graphql_handler<Query, Mutation, Subscription, CtxT, S>(
     schema: &juniper::RootNode<'static, Query, Mutation, Subscription, S>,
     context: &CtxT,
     req: HttpRequest,
+    allow_introspection: bool,
 ) -> Result<HttpResponse, Error>
 where
     Query: juniper::GraphQLTypeAsync<S, Context = CtxT>,
@@ -158,10 +505,25 @@ where
     CtxT: Sync,
     S: ScalarValue + Send + Sync,
 {
-    let get_req = web::Query::<GetGraphQLRequest>::from_query(req.query_string())?;
-    let req = GraphQLRequest::from(get_req.into_inner());
-    let gql_response = req.execute(schema, context).await;
-    let body_response = serde_json::to_string(&gql_response)?;
+    let gql_req = match web::Query::<GetGraphQLRequest>::from_query(req.query_string())
+        .map_err(|e| e.to_string())
+        .and_then(|get_req| {
+            get_req
+                .into_inner()
+                .into_graphql_request()
+                .map_err(|e| format!("Invalid variables: {}", e))
+        }) {
+        Ok(gql_req) => gql_req,
+        Err(message) => return Ok(invalid_request(&req, message)),
+    };
+    record_operation_names(&req, [&gql_req]);
+    if let Err(response) = check_introspection(&req, [&gql_req], allow_introspection) {
+        return Ok(response);
+    }
+    let gql_response = gql_req.execute(schema, context).await;
+    let mut body_response = serde_json::to_value(&gql_response)?;
+    add_request_id_to_errors(&req, &mut body_response);
+    let body_response = serde_json::to_string(&body_response)?;
     let mut response = match gql_response.is_ok() {
         true => HttpResponse::Ok(),
         false => HttpResponse::BadRequest(),
@@ -177,6 +539,7 @@ pub async fn post_graphql_handler<Query, Mutation, Subscription, CtxT, S>(
     context: &CtxT,
     req: HttpRequest,
     mut payload: actix_http::Payload,
+    allow_introspection: bool,
 ) -> Result<HttpResponse, Error>
 where
     Query: juniper::GraphQLTypeAsync<S, Context = CtxT>,
@@ -188,22 +551,27 @@ where
     CtxT: Sync,
     S: ScalarValue + Send + Sync,
 {
-    let req = match req.content_type() {
-        "application/json" => {
-            let body = String::from_request(&req, &mut payload).await?;
-            serde_json::from_str::<GraphQLBatchRequest<S>>(&body)
-                .map_err(JsonPayloadError::Deserialize)
+    let gql_req = match parse_post_request(&req, &mut payload).await {
+        Ok(gql_req) => gql_req,
+        Err(response) => return Ok(response),
+    };
+    let checked = match &gql_req {
+        GraphQLBatchRequest::Single(single) => {
+            record_operation_names(&req, [single]);
+            check_introspection(&req, [single], allow_introspection)
         }
-        "application/graphql" => {
-            let body = String::from_request(&req, &mut payload).await?;
-            Ok(GraphQLBatchRequest::Single(GraphQLRequest::new(
-                body, None, None,
-            )))
+        GraphQLBatchRequest::Batch(batch) => {
+            record_operation_names(&req, batch);
+            check_introspection(&req, batch, allow_introspection)
         }
-        _ => Err(JsonPayloadError::ContentType),
-    }?;
-    let gql_batch_response = req.execute(schema, context).await;
-    let gql_response = serde_json::to_string(&gql_batch_response)?;
+    };
+    if let Err(response) = checked {
+        return Ok(response);
+    }
+    let gql_batch_response = gql_req.execute(schema, context).await;
+    let mut gql_response = serde_json::to_value(&gql_batch_response)?;
+    add_request_id_to_errors(&req, &mut gql_response);
+    let gql_response = serde_json::to_string(&gql_response)?;
     let mut response = match gql_batch_response.is_ok() {
         true => HttpResponse::Ok(),
         false => HttpResponse::BadRequest(),
@@ -211,6 +579,52 @@ where
     Ok(response.content_type("application/json").body(gql_response))
 }
 
+/// Stores the operation names in the request, for the access log.
+fn record_operation_names<'a, S: ScalarValue + 'a>(
+    req: &HttpRequest,
+    requests: impl IntoIterator<Item = &'a GraphQLRequest<S>>,
+) {
+    let names = requests
+        .into_iter()
+        .map(|r| r.operation_name().unwrap_or("anonymous"))
+        .collect::<Vec<_>>()
+        .join(",");
+    req.extensions_mut().insert(GraphQLOperation(names));
+}
+
+/// Adds the request ID to the extensions of the errors, for the users to report it.
+fn add_request_id_to_errors(req: &HttpRequest, response: &mut serde_json::Value) {
+    let request_id = match req.extensions().get::<RequestId>() {
+        Some(id) => id.0.clone(),
+        None => return,
+    };
+    let responses = match response {
+        serde_json::Value::Array(responses) => responses.iter_mut().collect::<Vec<_>>(),
+        response => vec![response],
+    };
+    for errors in responses
+        .into_iter()
+        .filter_map(|r| r.get_mut("errors"))
+        .filter_map(serde_json::Value::as_array_mut)
+    {
+        for error in errors
+            .iter_mut()
+            .filter_map(serde_json::Value::as_object_mut)
+        {
+            if let Some(extensions) = error
+                .entry("extensions")
+                .or_insert_with(|| serde_json::json!({}))
+                .as_object_mut()
+            {
+                extensions.insert(
+                    "requestId".to_owned(),
+                    serde_json::Value::String(request_id.clone()),
+                );
+            }
+        }
+    }
+}
+
 async fn graphql_route<Handler: BackendHandler + Clone>(
     req: actix_web::HttpRequest,
     payload: actix_web::web::Payload,
@@ -222,18 +636,38 @@ async fn graphql_route<Handler: BackendHandler + Clone>(
     let context = Context::<Handler> {
         handler: data.backend_handler.clone(),
         validation_result,
+        invitation_sender: data.invitation_sender.clone(),
+        email_change_verifier: data.email_change_verifier.clone(),
+        avatar_limits: data.avatar_limits,
+        avatar_importer: data.avatar_importer.clone(),
+        ldap_base_dn: data.ldap_base_dn.clone(),
+        security_checker: data.security_checker.clone(),
+        login_identifier: data.login_identifier,
     };
+    let allow_introspection = data
+        .graphql_introspection
+        .is_allowed(&context.validation_result);
     let schema = &schema();
     let context = &context;
     match *req.method() {
-        actix_http::Method::POST => post_graphql_handler(schema, context, req, inner_payload).await,
-        actix_http::Method::GET => get_graphql_handler(schema, context, req).await,
+        actix_http::Method::POST => {
+            post_graphql_handler(schema, context, req, inner_payload, allow_introspection).await
+        }
+        actix_http::Method::GET => {
+            get_graphql_handler(schema, context, req, allow_introspection).await
+        }
         _ => Err(actix_web::error::UrlGenerationError::ResourceNotFound.into()),
     }
 }
 
-pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
-where
+/// Serves the GraphQL API, with request bodies up to `max_request_size` bytes (see
+/// [`AvatarLimits::max_request_size`]), and the playground and GraphiQL pages if
+/// `enable_playground`.
+pub fn configure_endpoint<Backend>(
+    cfg: &mut web::ServiceConfig,
+    max_request_size: usize,
+    enable_playground: bool,
+) where
     Backend: BackendHandler + Clone + 'static,
 {
     let json_config = web::JsonConfig::default()
@@ -249,12 +683,362 @@ where
             .into()
         });
     cfg.app_data(json_config);
-    cfg.app_data(web::PayloadConfig::new(1 << 24)); // Max payload size: 16MB, allows for a 12MB image.
+    cfg.app_data(web::PayloadConfig::new(max_request_size));
     cfg.service(
         web::resource("/graphql")
             .route(web::post().to(graphql_route::<Backend>))
             .route(web::get().to(graphql_route::<Backend>)),
     );
-    cfg.service(web::resource("/graphql/playground").route(web::get().to(playground_route)));
-    cfg.service(web::resource("/graphql/graphiql").route(web::get().to(graphiql_route)));
+    if enable_playground {
+        cfg.service(web::resource("/graphql/playground").route(web::get().to(playground_route)));
+        cfg.service(web::resource("/graphql/graphiql").route(web::get().to(graphiql_route)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sql_backend_handler::{tests::*, SqlBackendHandler};
+    use actix_web::{http::header, test, App};
+    use chrono::Utc;
+    use jwt::SignWithKey;
+    use lldap_auth::JWTClaims;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn test_avatar_limits() {
+        let limits = AvatarLimits {
+            max_size_kb: 2,
+            max_dimension: 512,
+        };
+        assert!(limits.check(&[0; 2048]).is_ok());
+        assert_eq!(
+            limits.check(&[0; 2049]).unwrap_err().to_string(),
+            "The avatar is 3 kB, over the limit of 2 kB"
+        );
+    }
+
+    #[test]
+    fn test_max_request_size() {
+        let limits = AvatarLimits {
+            max_size_kb: 3,
+            max_dimension: 512,
+        };
+        assert_eq!(limits.max_request_size(), 4096 + (1 << 20));
+        let limits = AvatarLimits {
+            max_size_kb: i32::MAX,
+            max_dimension: 512,
+        };
+        assert!(limits.max_request_size() > 1 << 20);
+    }
+
+    #[test]
+    fn test_internal_errors_are_hidden() {
+        let error = domain_error_to_field_error(DomainError::DatabaseError(
+            sea_orm::DbErr::Custom("SELECT password_hash FROM users".to_owned()),
+        ));
+        assert_eq!(error.message(), "Internal server error");
+        assert_eq!(
+            error.extensions(),
+            &graphql_value!({ "code": "INTERNAL_ERROR" })
+        );
+        let error = domain_error_to_field_error(DomainError::EntityNotFound("bob".to_owned()));
+        assert_eq!(error.message(), "Not found: bob");
+        assert_eq!(error.extensions(), &graphql_value!({ "code": "NOT_FOUND" }));
+    }
+
+    fn token(state: &AppState<SqlBackendHandler>, user: &str, groups: &[&str]) -> String {
+        let claims = JWTClaims {
+            exp: Utc::now() + chrono::Duration::days(1),
+            iat: Utc::now(),
+            user: user.to_owned(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            login: None,
+        };
+        let header = jwt::Header {
+            algorithm: jwt::AlgorithmType::Hs512,
+            ..Default::default()
+        };
+        jwt::Token::new(header, claims)
+            .sign_with_key(&state.jwt_key)
+            .unwrap()
+            .as_str()
+            .to_owned()
+    }
+
+    /// Posts the body as bob, a regular user, and returns the status and the error code.
+    async fn post(content_type: &str, body: String) -> (StatusCode, serde_json::Value) {
+        let fixture = TestFixture::new().await;
+        let state = web::Data::new(AppState::new_for_tests(fixture.handler.clone()));
+        let token = token(&state, "bob", &[]);
+        let app = test::init_service(
+            App::new().app_data(state).service(
+                web::scope("/api")
+                    .configure(|cfg| configure_endpoint::<SqlBackendHandler>(cfg, 1024, true)),
+            ),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/api/graphql")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .set_payload(body)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let status = response.status();
+        let body: serde_json::Value = test::read_body_json(response).await;
+        (status, body["errors"][0]["extensions"]["code"].clone())
+    }
+
+    fn update_bob(input: serde_json::Value) -> String {
+        json!({
+            "query": "mutation($user: UpdateUserInput!) { updateUser(user: $user) { ok } }",
+            "variables": { "user": input },
+        })
+        .to_string()
+    }
+
+    #[actix_web::test]
+    async fn test_request_errors_have_a_code() {
+        assert_eq!(
+            post("application/json", "x".repeat(2048)).await,
+            (StatusCode::PAYLOAD_TOO_LARGE, json!("PAYLOAD_TOO_LARGE"))
+        );
+        assert_eq!(
+            post("application/json", "{\"query\": ".to_owned()).await,
+            (StatusCode::BAD_REQUEST, json!("INVALID_REQUEST"))
+        );
+        assert_eq!(
+            post("text/plain", "{ user(userId: \"bob\") { id } }".to_owned()).await,
+            (StatusCode::BAD_REQUEST, json!("INVALID_REQUEST"))
+        );
+        assert_eq!(
+            post(
+                "application/json",
+                json!({ "query": "{ securityStatus { code } }" }).to_string()
+            )
+            .await,
+            (StatusCode::OK, json!("PERMISSION_DENIED"))
+        );
+        assert_eq!(
+            post(
+                "application/json",
+                update_bob(json!({ "id": "bob", "displayName": "a".repeat(256) }))
+            )
+            .await,
+            (StatusCode::OK, json!("FIELD_TOO_LONG"))
+        );
+        assert_eq!(
+            post(
+                "application/json",
+                update_bob(json!({ "id": "bob", "avatar": "not base64!" }))
+            )
+            .await,
+            (StatusCode::OK, json!("INVALID_BASE64"))
+        );
+        assert_eq!(
+            post(
+                "application/json",
+                update_bob(json!({ "id": "bob", "skipEmailVerification": true }))
+            )
+            .await,
+            (StatusCode::OK, json!("PERMISSION_DENIED"))
+        );
+    }
+
+    /// Sends the request with the given settings, as bob, or as an admin with `as_admin`.
+    async fn call_with_settings(
+        enable_playground: bool,
+        introspection: GraphQLIntrospection,
+        as_admin: bool,
+        request: test::TestRequest,
+    ) -> StatusCode {
+        let fixture = TestFixture::new().await;
+        let mut state = AppState::new_for_tests(fixture.handler.clone());
+        state.graphql_introspection = introspection;
+        let state = web::Data::new(state);
+        let token = match as_admin {
+            true => token(&state, "admin", &["lldap_admin"]),
+            false => token(&state, "bob", &[]),
+        };
+        let app = test::init_service(App::new().app_data(state).service(
+            web::scope("/api").configure(|cfg| {
+                configure_endpoint::<SqlBackendHandler>(cfg, 1 << 20, enable_playground)
+            }),
+        ))
+        .await;
+        let request = request
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        test::call_service(&app, request).await.status()
+    }
+
+    #[actix_web::test]
+    async fn test_playground_can_be_disabled() {
+        for path in ["/api/graphql/playground", "/api/graphql/graphiql"] {
+            let get = || test::TestRequest::get().uri(path);
+            assert_eq!(
+                call_with_settings(true, GraphQLIntrospection::Enabled, false, get()).await,
+                StatusCode::OK
+            );
+            assert_eq!(
+                call_with_settings(false, GraphQLIntrospection::Enabled, false, get()).await,
+                StatusCode::NOT_FOUND
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_introspection_settings() {
+        let query = |query: &str| {
+            test::TestRequest::post()
+                .uri("/api/graphql")
+                .set_json(json!({ "query": query }))
+        };
+        let introspection = || query("{ __schema { queryType { name } } }");
+        let status = |introspection: GraphQLIntrospection, as_admin: bool, request| {
+            call_with_settings(true, introspection, as_admin, request)
+        };
+        assert_eq!(
+            status(GraphQLIntrospection::Enabled, false, introspection()).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(GraphQLIntrospection::Disabled, false, introspection()).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(GraphQLIntrospection::Disabled, true, introspection()).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(GraphQLIntrospection::AdminsOnly, false, introspection()).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(GraphQLIntrospection::AdminsOnly, true, introspection()).await,
+            StatusCode::OK
+        );
+        // The GET requests and the batches are checked too.
+        assert_eq!(
+            status(
+                GraphQLIntrospection::Disabled,
+                false,
+                test::TestRequest::get()
+                    .uri("/api/graphql?query=%7B__type(name:%22User%22)%7Bname%7D%7D")
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(
+                GraphQLIntrospection::Disabled,
+                false,
+                test::TestRequest::post()
+                    .uri("/api/graphql")
+                    .set_json(json!([
+                        { "query": "{ apiVersion }" },
+                        { "query": "{ __schema { types { name } } }" },
+                    ]))
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+        // The other queries still work, including with `__typename`.
+        assert_eq!(
+            status(
+                GraphQLIntrospection::Disabled,
+                false,
+                query("{ user(userId: \"bob\") { __typename id } }")
+            )
+            .await,
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn test_is_introspection_query() {
+        assert!(is_introspection_query("{ __schema { types { name } } }"));
+        assert!(is_introspection_query(
+            "query { t: __type(name: \"User\") { name } }"
+        ));
+        assert!(is_introspection_query(
+            "query { ...F } fragment F on Query { __schema { queryType { name } } }"
+        ));
+        assert!(is_introspection_query("{__schema{types{name}}}"));
+        assert!(!is_introspection_query(
+            "{ user(userId: \"bob\") { __typename id } }"
+        ));
+        assert!(!is_introspection_query("{ apiVersion } # __schema"));
+        assert!(!is_introspection_query(
+            "{ user(userId: \"__schema \\\" __type\") { id } }"
+        ));
+        assert!(!is_introspection_query(
+            "mutation { createGroup(name: \"\"\"__schema \\\"\"\" __type\"\"\") { id } }"
+        ));
+        assert!(!is_introspection_query("{ __schemas }"));
+    }
+
+    #[test]
+    fn test_introspection_setting() {
+        let parse =
+            |value: serde_json::Value| serde_json::from_value::<GraphQLIntrospection>(value);
+        assert_eq!(parse(json!(true)).unwrap(), GraphQLIntrospection::Enabled);
+        assert_eq!(parse(json!(false)).unwrap(), GraphQLIntrospection::Disabled);
+        assert_eq!(
+            parse(json!("admins_only")).unwrap(),
+            GraphQLIntrospection::AdminsOnly
+        );
+        assert_eq!(
+            parse(json!("false")).unwrap(),
+            GraphQLIntrospection::Disabled
+        );
+        assert!(parse(json!("admins")).is_err());
+        assert_eq!(
+            serde_json::to_value(GraphQLIntrospection::AdminsOnly).unwrap(),
+            json!("admins_only")
+        );
+        assert_eq!(
+            serde_json::to_value(GraphQLIntrospection::Disabled).unwrap(),
+            json!(false)
+        );
+    }
+
+    #[test]
+    fn test_add_request_id_to_errors() {
+        let req = actix_web::test::TestRequest::default().to_http_request();
+        req.extensions_mut()
+            .insert(RequestId("0123456789ab".to_owned()));
+        let mut response = json!([
+            { "data": { "user": null } },
+            {
+                "data": null,
+                "errors": [
+                    { "message": "Not found" },
+                    { "message": "Invalid", "extensions": { "code": "INVALID_INPUT" } },
+                ],
+            },
+        ]);
+        add_request_id_to_errors(&req, &mut response);
+        assert_eq!(
+            response,
+            json!([
+                { "data": { "user": null } },
+                {
+                    "data": null,
+                    "errors": [
+                        {
+                            "message": "Not found",
+                            "extensions": { "requestId": "0123456789ab" },
+                        },
+                        {
+                            "message": "Invalid",
+                            "extensions": { "code": "INVALID_INPUT", "requestId": "0123456789ab" },
+                        },
+                    ],
+                },
+            ])
+        );
+    }
 }
@@ -2,14 +2,20 @@ use std::sync::Arc;
 
 use crate::{
     domain::{
-        deserialize::deserialize_attribute_value,
+        deserialize,
+        error::DomainError,
         handler::{
             AttributeList, BackendHandler, CreateAttributeRequest, CreateGroupRequest,
-            CreateUserRequest, UpdateGroupRequest, UpdateUserRequest,
+            CreateUserRequest, DeleteUserRequest, EmptyGroupPolicy as DomainEmptyGroupPolicy,
+            UpdateGroupRequest, UpdateUserRequest, UserRequestFilter,
         },
         types::{
             AttributeName, AttributeType, AttributeValue as DomainAttributeValue, GroupId,
-            JpegPhoto, LdapObjectClass, UserId,
+            JpegPhoto, LdapObjectClass, UserColumn, UserId,
+        },
+        validation::{
+            check_email, normalize_email, MAX_ATTRIBUTE_VALUE_LENGTH, MAX_GROUP_DESCRIPTION_LENGTH,
+            MAX_GROUP_NOTES_LENGTH, MAX_NAME_LENGTH,
         },
     },
     infra::{
@@ -17,12 +23,19 @@ use crate::{
             AdminBackendHandler, ReadonlyBackendHandler, UserReadableBackendHandler,
             UserWriteableBackendHandler,
         },
-        graphql::api::{field_error_callback, Context},
+        configuration::LoginIdentifier,
+        graphql::api::{
+            domain_error_to_field_error, field_error_callback, permission_denied, Context,
+        },
+        invitation::InvitationSender,
     },
 };
 use anyhow::{anyhow, Context as AnyhowContext};
 use base64::Engine;
-use juniper::{graphql_object, FieldResult, GraphQLInputObject, GraphQLObject};
+use juniper::{
+    graphql_object, graphql_value, FieldError, FieldResult, GraphQLEnum, GraphQLInputObject,
+    GraphQLObject,
+};
 use tracing::{debug, debug_span, Instrument, Span};
 
 #[derive(PartialEq, Eq, Debug)]
@@ -64,14 +77,35 @@ pub struct CreateUserInput {
     last_name: Option<String>,
     /// Base64 encoded JpegPhoto.
     avatar: Option<String>,
+    /// BCP 47 language tag for the emails, e.g. "fr" or "de-CH".
+    preferred_language: Option<String>,
+    /// The hosts the user may log into, e.g. "web01", or "*" for all of them.
+    hosts: Option<Vec<String>>,
+    /// The phone number, e.g. "+1 555 010-9999". It is stored without the formatting.
+    phone: Option<String>,
+    /// The mobile phone number, like the phone number.
+    mobile: Option<String>,
+    /// The postal address, one line per line of the address.
+    postal_address: Option<String>,
+    /// The city or town.
+    locality: Option<String>,
+    state_or_province: Option<String>,
+    /// The date the account is disabled (deleted) at. It can't be in the past.
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
     /// User-defined attributes.
     attributes: Option<Vec<AttributeValue>>,
+    /// The ids of the groups to add the user to, atomically with the creation.
+    groups: Option<Vec<i32>>,
 }
 
 #[derive(PartialEq, Eq, Debug, GraphQLInputObject)]
 /// The details required to create a group.
 pub struct CreateGroupInput {
     display_name: String,
+    /// What the group is for, also served over LDAP.
+    description: Option<String>,
+    /// Free-text notes, only visible to the admins.
+    notes: Option<String>,
     /// User-defined attributes.
     attributes: Option<Vec<AttributeValue>>,
 }
@@ -86,12 +120,30 @@ pub struct UpdateUserInput {
     last_name: Option<String>,
     /// Base64 encoded JpegPhoto.
     avatar: Option<String>,
+    /// BCP 47 language tag for the emails. An empty string removes it.
+    preferred_language: Option<String>,
+    /// Admins only: replaces the hosts the user may log into. An empty list removes them.
+    hosts: Option<Vec<String>>,
+    /// For the phone numbers and the address fields, an empty string removes the value. The
+    /// users can only change the ones they can see.
+    phone: Option<String>,
+    mobile: Option<String>,
+    postal_address: Option<String>,
+    locality: Option<String>,
+    state_or_province: Option<String>,
+    /// Admins only: the date the account is disabled (deleted) at. It can't be in the past.
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Admins only: removes the expiration date.
+    remove_expiration: Option<bool>,
     /// Attribute names to remove.
     /// They are processed before insertions.
     remove_attributes: Option<Vec<String>>,
     /// Inserts or updates the given attributes.
     /// For lists, the entire list must be provided.
     insert_attributes: Option<Vec<AttributeValue>>,
+    /// Admins only: changes the email right away, instead of sending a verification link to the
+    /// new address.
+    skip_email_verification: Option<bool>,
 }
 
 #[derive(PartialEq, Eq, Debug, GraphQLInputObject)]
@@ -101,6 +153,10 @@ pub struct UpdateGroupInput {
     id: i32,
     /// The new display name.
     display_name: Option<String>,
+    /// The new description. An empty string removes it.
+    description: Option<String>,
+    /// The new admin notes. An empty string removes them.
+    notes: Option<String>,
     /// Attribute names to remove.
     /// They are processed before insertions.
     remove_attributes: Option<Vec<String>>,
@@ -109,6 +165,36 @@ pub struct UpdateGroupInput {
     insert_attributes: Option<Vec<AttributeValue>>,
 }
 
+/// The most users in one `createUsers` request.
+const MAX_CREATE_USERS: usize = 100;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, GraphQLEnum)]
+/// What `createUsers` does with the users that already exist.
+pub enum ExistingUserPolicy {
+    /// Leave them unchanged.
+    Skip,
+    /// Update the given fields, and add the missing group memberships.
+    Update,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, GraphQLEnum)]
+pub enum CreateUserStatus {
+    Created,
+    Updated,
+    /// Already there, with `onExisting: SKIP`.
+    Skipped,
+    Failed,
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// The outcome of one user of `createUsers`.
+pub struct CreateUserResult {
+    id: String,
+    status: CreateUserStatus,
+    /// Why the user failed.
+    error: Option<String>,
+}
+
 #[derive(PartialEq, Eq, Debug, GraphQLObject)]
 pub struct Success {
     ok: bool,
@@ -120,11 +206,64 @@ impl Success {
     }
 }
 
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// The outcome of `addUserToGroup` and `removeUserFromGroup`. They succeed when the membership
+/// is already as requested, without a change.
+pub struct MembershipChange {
+    ok: bool,
+    /// False if the user was already a member, for an addition, or not a member, for a removal.
+    changed: bool,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, GraphQLEnum)]
+/// What `deleteUser` does with the groups that the user is the last active member of. The
+/// `lldap_admin` group is never left without members, whatever the policy.
+pub enum EmptyGroupPolicy {
+    /// Keep them, empty.
+    Keep,
+    /// Delete them along with the user.
+    DeleteEmpty,
+    /// Refuse to delete the user, with a "LAST_GROUP_MEMBER" error.
+    FailIfLastMember,
+}
+
+impl From<EmptyGroupPolicy> for DomainEmptyGroupPolicy {
+    fn from(policy: EmptyGroupPolicy) -> Self {
+        match policy {
+            EmptyGroupPolicy::Keep => Self::Keep,
+            EmptyGroupPolicy::DeleteEmpty => Self::DeleteEmpty,
+            EmptyGroupPolicy::FailIfLastMember => Self::FailIfLastMember,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A group left without active members by `deleteUser`.
+pub struct EmptiedGroup {
+    id: i32,
+    display_name: String,
+    /// False if the group was kept, empty.
+    deleted: bool,
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// The outcome of `deleteUser`.
+pub struct UserDeletion {
+    ok: bool,
+    /// The groups that the user was the last active member of, and what happened to them.
+    emptied_groups: Vec<EmptiedGroup>,
+}
+
 #[graphql_object(context = Context<Handler>)]
 impl<Handler: BackendHandler> Mutation<Handler> {
+    /// With `sendInvite`, the user gets an email with a link to choose their password. With
+    /// `login_identifier = "email"`, the ID can be left empty to derive it from the email.
+    /// Without an avatar, it is imported in the background from `avatar_source_url_template`
+    /// if set.
     async fn create_user(
         context: &Context<Handler>,
         user: CreateUserInput,
+        send_invite: Option<bool>,
     ) -> FieldResult<super::query::User<Handler>> {
         let span = debug_span!("[GraphQL mutation] create_user");
         span.in_scope(|| {
@@ -133,38 +272,175 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         let handler = context
             .get_admin_handler()
             .ok_or_else(field_error_callback(&span, "Unauthorized user creation"))?;
-        let user_id = UserId::new(&user.id);
-        let avatar = user
-            .avatar
-            .map(|bytes| base64::engine::general_purpose::STANDARD.decode(bytes))
-            .transpose()
-            .context("Invalid base64 image")?
-            .map(JpegPhoto::try_from)
-            .transpose()
-            .context("Provided image is not a valid JPEG")?;
-        let schema = handler.get_schema().await?;
-        let attributes = user
-            .attributes
-            .unwrap_or_default()
-            .into_iter()
-            .map(|attr| deserialize_attribute(&schema.get_schema().user_attributes, attr, true))
-            .collect::<Result<Vec<_>, _>>()?;
-        handler
-            .create_user(CreateUserRequest {
-                user_id: user_id.clone(),
-                email: user.email.into(),
-                display_name: user.display_name,
-                first_name: user.first_name,
-                last_name: user.last_name,
-                avatar,
-                attributes,
-            })
+        // Checked before creating the user, to not leave it without a password.
+        let invitation_sender = if send_invite.unwrap_or(false) {
+            Some(get_invitation_sender(context)?)
+        } else {
+            None
+        };
+        let schema = handler
+            .get_schema()
+            .await
+            .map_err(domain_error_to_field_error)?;
+        let request = create_user_request(context, &schema.get_schema().user_attributes, user)?;
+        let has_avatar = request.avatar.is_some();
+        let user_id = handler
+            .create_user(request)
             .instrument(span.clone())
-            .await?;
-        let user_details = handler.get_user_details(&user_id).instrument(span).await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
+        if !has_avatar {
+            import_avatar_in_background(context, &user_id);
+        }
+        let user_details = handler
+            .get_user_details(&user_id)
+            .instrument(span.clone())
+            .await
+            .map_err(domain_error_to_field_error)?;
+        if let Some(invitation_sender) = invitation_sender {
+            invitation_sender
+                .send_invitation(&user_details)
+                .instrument(span)
+                .await
+                .map_err(|e| {
+                    anyhow!(
+                        "The user was created, but the invitation could not be sent: {:#}",
+                        e
+                    )
+                })?;
+        }
         super::query::User::<Handler>::from_user(user_details, Arc::new(schema))
     }
 
+    /// Creates several users at once, e.g. from a CSV import. Each user is created, updated or
+    /// skipped on its own, without stopping at the failures: the results are in the same order
+    /// as the users. At most 100 users per request.
+    async fn create_users(
+        context: &Context<Handler>,
+        users: Vec<CreateUserInput>,
+        on_existing: Option<ExistingUserPolicy>,
+    ) -> FieldResult<Vec<CreateUserResult>> {
+        let span = debug_span!("[GraphQL mutation] create_users");
+        span.in_scope(|| {
+            debug!(count = users.len(), ?on_existing);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(&span, "Unauthorized user creation"))?;
+        if users.len() > MAX_CREATE_USERS {
+            return Err(anyhow!(
+                "Too many users: at most {} per request, split them in batches",
+                MAX_CREATE_USERS
+            )
+            .into());
+        }
+        let on_existing = on_existing.unwrap_or(ExistingUserPolicy::Skip);
+        let schema = handler
+            .get_schema()
+            .await
+            .map_err(domain_error_to_field_error)?;
+        let mut results = Vec::with_capacity(users.len());
+        for user in users {
+            let id = user.id.clone();
+            let result = create_or_update_user(
+                context,
+                handler,
+                &schema.get_schema().user_attributes,
+                user,
+                on_existing,
+            )
+            .instrument(span.clone())
+            .await;
+            results.push(match result {
+                Ok((user_id, status)) => CreateUserResult {
+                    id: user_id.into_key(),
+                    status,
+                    error: None,
+                },
+                Err(e) => CreateUserResult {
+                    id,
+                    status: CreateUserStatus::Failed,
+                    error: Some(e.message().to_owned()),
+                },
+            });
+        }
+        Ok(results)
+    }
+
+    /// Sends a new invitation email to the user, the previous links stop working.
+    async fn send_invite(context: &Context<Handler>, user_id: String) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] send_invite");
+        span.in_scope(|| {
+            debug!(?user_id);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(&span, "Unauthorized invitation"))?;
+        let invitation_sender = get_invitation_sender(context)?;
+        let user = handler
+            .get_user_details(&UserId::new(&user_id))
+            .instrument(span.clone())
+            .await
+            .map_err(domain_error_to_field_error)?;
+        invitation_sender
+            .send_invitation(&user)
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
+    /// Replaces the avatar of the user with the one from `avatar_source_url_template`.
+    async fn refresh_avatar_from_source(
+        context: &Context<Handler>,
+        user_id: String,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] refresh_avatar_from_source");
+        span.in_scope(|| {
+            debug!(?user_id);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(&span, "Unauthorized avatar refresh"))?;
+        let importer = context.avatar_importer.clone().ok_or_else(|| {
+            FieldError::from("Cannot refresh the avatar: avatar_source_url_template is not set")
+        })?;
+        let user_id = UserId::new(&user_id);
+        // Not found, rather than a failed fetch.
+        handler
+            .get_user_details(&user_id)
+            .instrument(span.clone())
+            .await
+            .map_err(domain_error_to_field_error)?;
+        importer
+            .import_avatar(&user_id)
+            .instrument(span)
+            .await
+            .map_err(|e| anyhow!("Could not refresh the avatar: {:#}", e))?;
+        Ok(Success::new())
+    }
+
+    /// Cancels the change of email address waiting for verification: the link stops working.
+    async fn cancel_email_change(
+        context: &Context<Handler>,
+        user_id: String,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] cancel_email_change");
+        span.in_scope(|| {
+            debug!(?user_id);
+        });
+        let user_id = UserId::new(&user_id);
+        context
+            .get_writeable_handler(&user_id)
+            .ok_or_else(field_error_callback(&span, "Unauthorized email change"))?;
+        if let Some(verifier) = &context.email_change_verifier {
+            verifier
+                .cancel_email_change(&user_id)
+                .instrument(span)
+                .await?;
+        }
+        Ok(Success::new())
+    }
+
     async fn create_group(
         context: &Context<Handler>,
         name: String,
@@ -177,6 +453,8 @@ impl<Handler: BackendHandler> Mutation<Handler> {
             context,
             CreateGroupInput {
                 display_name: name,
+                description: None,
+                notes: None,
                 attributes: Some(Vec::new()),
             },
             span,
@@ -207,39 +485,147 @@ impl<Handler: BackendHandler> Mutation<Handler> {
             .get_writeable_handler(&user_id)
             .ok_or_else(field_error_callback(&span, "Unauthorized user update"))?;
         let is_admin = context.validation_result.is_admin();
-        let avatar = user
-            .avatar
-            .map(|bytes| base64::engine::general_purpose::STANDARD.decode(bytes))
-            .transpose()
-            .context("Invalid base64 image")?
-            .map(JpegPhoto::try_from)
-            .transpose()
-            .context("Provided image is not a valid JPEG")?;
-        let schema = handler.get_schema().await?;
+        let skip_email_verification = user.skip_email_verification.unwrap_or(false);
+        if skip_email_verification && !is_admin {
+            return Err(permission_denied(
+                "Only the admins can skip the email verification",
+            ));
+        }
+        if user.hosts.is_some() && !is_admin {
+            return Err(field_error(
+                "PERMISSION_DENIED",
+                "hosts",
+                "Permission denied: only the admins can change the hosts",
+            ));
+        }
+        let remove_expiration = user.remove_expiration.unwrap_or(false);
+        if (user.expires_at.is_some() || remove_expiration) && !is_admin {
+            return Err(field_error(
+                "PERMISSION_DENIED",
+                "expires_at",
+                "Permission denied: only the admins can change the expiration date",
+            ));
+        }
+        let expires_at = match (user.expires_at, remove_expiration) {
+            (Some(_), true) => {
+                return Err("Either set the expiration date or remove it, not both".into())
+            }
+            (Some(date), false) => Some(Some(date)),
+            (None, true) => Some(None),
+            (None, false) => None,
+        };
+        if !is_admin {
+            for (field, value) in [
+                ("phone", &user.phone),
+                ("mobile", &user.mobile),
+                ("postal_address", &user.postal_address),
+                ("locality", &user.locality),
+                ("state_or_province", &user.state_or_province),
+            ] {
+                if value.is_some() && !context.can_read_user_attribute(&user_id, &field.into()) {
+                    return Err(field_error(
+                        "PERMISSION_DENIED",
+                        field,
+                        format!("Permission denied: {} is not visible to the user", field),
+                    ));
+                }
+            }
+        }
+        check_names(
+            user.display_name.as_deref(),
+            user.first_name.as_deref(),
+            user.last_name.as_deref(),
+        )?;
+        let avatar = decode_avatar(context, user.avatar)?;
+        let email = user.email.map(email_input).transpose()?;
+        let schema = handler
+            .get_schema()
+            .await
+            .map_err(domain_error_to_field_error)?;
+        // With the emails enabled, a new address only applies once verified. Removing the email
+        // doesn't need a verification.
+        let (email, email_to_verify) = match (&context.email_change_verifier, email) {
+            (Some(verifier), Some(email)) => {
+                let current = handler
+                    .get_user_details(&user_id)
+                    .instrument(span.clone())
+                    .await
+                    .map_err(domain_error_to_field_error)?;
+                if current.email.as_str() == email {
+                    (None, None)
+                } else if skip_email_verification || email.is_empty() {
+                    verifier
+                        .cancel_email_change(&user_id)
+                        .instrument(span.clone())
+                        .await?;
+                    (Some(email), None)
+                } else {
+                    (None, Some((current, email)))
+                }
+            }
+            (_, email) => (email, None),
+        };
         let insert_attributes = user
             .insert_attributes
             .unwrap_or_default()
             .into_iter()
             .map(|attr| deserialize_attribute(&schema.get_schema().user_attributes, attr, is_admin))
             .collect::<Result<Vec<_>, _>>()?;
+        let remove_attributes = user
+            .remove_attributes
+            .unwrap_or_default()
+            .into_iter()
+            .map(AttributeName::from)
+            .collect::<Vec<_>>();
+        if !is_admin {
+            for name in &remove_attributes {
+                let editable = schema
+                    .get_schema()
+                    .user_attributes
+                    .get_attribute_schema(name)
+                    .is_some_and(|a| a.is_editable);
+                if !editable {
+                    return Err(field_error(
+                        "PERMISSION_DENIED",
+                        name.as_str(),
+                        format!(
+                            "Permission denied: Attribute {} is not editable by regular users",
+                            name
+                        ),
+                    ));
+                }
+            }
+        }
         handler
             .update_user(UpdateUserRequest {
                 user_id,
-                email: user.email.map(Into::into),
+                email: email.map(Into::into),
                 display_name: user.display_name,
                 first_name: user.first_name,
                 last_name: user.last_name,
                 avatar,
-                delete_attributes: user
-                    .remove_attributes
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(Into::into)
-                    .collect(),
+                preferred_language: user.preferred_language,
+                hosts: user.hosts,
+                phone: user.phone,
+                mobile: user.mobile,
+                postal_address: user.postal_address,
+                locality: user.locality,
+                state_or_province: user.state_or_province,
+                expires_at,
+                delete_attributes: remove_attributes,
                 insert_attributes,
             })
-            .instrument(span)
-            .await?;
+            .instrument(span.clone())
+            .await
+            .map_err(domain_error_to_field_error)?;
+        if let (Some(verifier), Some((current, new_email))) =
+            (&context.email_change_verifier, email_to_verify)
+        {
+            verifier
+                .start_email_change(&current, &new_email)
+                .instrument(span)
+                .await?;
+        }
         Ok(Success::new())
     }
 
@@ -258,7 +644,11 @@ impl<Handler: BackendHandler> Mutation<Handler> {
             span.in_scope(|| debug!("Cannot change lldap_admin group name"));
             return Err("Cannot change lldap_admin group name".into());
         }
-        let schema = handler.get_schema().await?;
+        check_group_texts(group.description.as_deref(), group.notes.as_deref())?;
+        let schema = handler
+            .get_schema()
+            .await
+            .map_err(domain_error_to_field_error)?;
         let insert_attributes = group
             .insert_attributes
             .unwrap_or_default()
@@ -269,6 +659,8 @@ impl<Handler: BackendHandler> Mutation<Handler> {
             .update_group(UpdateGroupRequest {
                 group_id: GroupId(group.id),
                 display_name: group.display_name.map(Into::into),
+                description: group.description,
+                notes: group.notes,
                 delete_attributes: group
                     .remove_attributes
                     .unwrap_or_default()
@@ -278,7 +670,8 @@ impl<Handler: BackendHandler> Mutation<Handler> {
                 insert_attributes,
             })
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         Ok(Success::new())
     }
 
@@ -286,7 +679,7 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         context: &Context<Handler>,
         user_id: String,
         group_id: i32,
-    ) -> FieldResult<Success> {
+    ) -> FieldResult<MembershipChange> {
         let span = debug_span!("[GraphQL mutation] add_user_to_group");
         span.in_scope(|| {
             debug!(?user_id, ?group_id);
@@ -297,18 +690,19 @@ impl<Handler: BackendHandler> Mutation<Handler> {
                 &span,
                 "Unauthorized group membership modification",
             ))?;
-        handler
+        let changed = handler
             .add_user_to_group(&UserId::new(&user_id), GroupId(group_id))
             .instrument(span)
-            .await?;
-        Ok(Success::new())
+            .await
+            .map_err(domain_error_to_field_error)?;
+        Ok(MembershipChange { ok: true, changed })
     }
 
     async fn remove_user_from_group(
         context: &Context<Handler>,
         user_id: String,
         group_id: i32,
-    ) -> FieldResult<Success> {
+    ) -> FieldResult<MembershipChange> {
         let span = debug_span!("[GraphQL mutation] remove_user_from_group");
         span.in_scope(|| {
             debug!(?user_id, ?group_id);
@@ -324,17 +718,26 @@ impl<Handler: BackendHandler> Mutation<Handler> {
             span.in_scope(|| debug!("Cannot remove admin rights for current user"));
             return Err("Cannot remove admin rights for current user".into());
         }
-        handler
+        let changed = handler
             .remove_user_from_group(&user_id, GroupId(group_id))
             .instrument(span)
-            .await?;
-        Ok(Success::new())
+            .await
+            .map_err(domain_error_to_field_error)?;
+        Ok(MembershipChange { ok: true, changed })
     }
 
-    async fn delete_user(context: &Context<Handler>, user_id: String) -> FieldResult<Success> {
+    /// Deletes a user. Unless `permanent` is set, the user can be restored with `restoreUser`
+    /// until it is purged. The groups it leaves without active members are kept by default,
+    /// see `emptyGroupPolicy`; a deleted group is not restored with the user.
+    async fn delete_user(
+        context: &Context<Handler>,
+        user_id: String,
+        permanent: Option<bool>,
+        empty_group_policy: Option<EmptyGroupPolicy>,
+    ) -> FieldResult<UserDeletion> {
         let span = debug_span!("[GraphQL mutation] delete_user");
         span.in_scope(|| {
-            debug!(?user_id);
+            debug!(?user_id, ?permanent, ?empty_group_policy);
         });
         let user_id = UserId::new(&user_id);
         let handler = context
@@ -344,7 +747,41 @@ impl<Handler: BackendHandler> Mutation<Handler> {
             span.in_scope(|| debug!("Cannot delete current user"));
             return Err("Cannot delete current user".into());
         }
-        handler.delete_user(&user_id).instrument(span).await?;
+        let emptied_groups = handler
+            .delete_user_with_policy(DeleteUserRequest {
+                user_id,
+                permanent: permanent.unwrap_or(false),
+                empty_group_policy: empty_group_policy.unwrap_or(EmptyGroupPolicy::Keep).into(),
+            })
+            .instrument(span)
+            .await
+            .map_err(domain_error_to_field_error)?;
+        Ok(UserDeletion {
+            ok: true,
+            emptied_groups: emptied_groups
+                .into_iter()
+                .map(|g| EmptiedGroup {
+                    id: g.group_id.0,
+                    display_name: g.display_name.to_string(),
+                    deleted: g.deleted,
+                })
+                .collect(),
+        })
+    }
+
+    async fn restore_user(context: &Context<Handler>, user_id: String) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] restore_user");
+        span.in_scope(|| {
+            debug!(?user_id);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(&span, "Unauthorized user restoration"))?;
+        handler
+            .restore_user(&UserId::new(&user_id))
+            .instrument(span)
+            .await
+            .map_err(domain_error_to_field_error)?;
         Ok(Success::new())
     }
 
@@ -363,7 +800,8 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         handler
             .delete_group(GroupId(group_id))
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         Ok(Success::new())
     }
 
@@ -394,7 +832,8 @@ impl<Handler: BackendHandler> Mutation<Handler> {
                 is_editable,
             })
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         Ok(Success::new())
     }
 
@@ -425,7 +864,8 @@ impl<Handler: BackendHandler> Mutation<Handler> {
                 is_editable,
             })
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         Ok(Success::new())
     }
 
@@ -444,19 +884,26 @@ impl<Handler: BackendHandler> Mutation<Handler> {
                 &span,
                 "Unauthorized attribute deletion",
             ))?;
-        let schema = handler.get_schema().await?;
+        let schema = handler
+            .get_schema()
+            .await
+            .map_err(domain_error_to_field_error)?;
         let attribute_schema = schema
             .get_schema()
             .user_attributes
             .get_attribute_schema(&name)
             .ok_or_else(|| anyhow!("Attribute {} is not defined in the schema", &name))?;
         if attribute_schema.is_hardcoded {
-            return Err(anyhow!("Permission denied: Attribute {} cannot be deleted", &name).into());
+            return Err(permission_denied(format!(
+                "Permission denied: Attribute {} cannot be deleted",
+                &name
+            )));
         }
         handler
             .delete_user_attribute(&name)
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         Ok(Success::new())
     }
 
@@ -475,19 +922,26 @@ impl<Handler: BackendHandler> Mutation<Handler> {
                 &span,
                 "Unauthorized attribute deletion",
             ))?;
-        let schema = handler.get_schema().await?;
+        let schema = handler
+            .get_schema()
+            .await
+            .map_err(domain_error_to_field_error)?;
         let attribute_schema = schema
             .get_schema()
             .group_attributes
             .get_attribute_schema(&name)
             .ok_or_else(|| anyhow!("Attribute {} is not defined in the schema", &name))?;
         if attribute_schema.is_hardcoded {
-            return Err(anyhow!("Permission denied: Attribute {} cannot be deleted", &name).into());
+            return Err(permission_denied(format!(
+                "Permission denied: Attribute {} cannot be deleted",
+                &name
+            )));
         }
         handler
             .delete_group_attribute(&name)
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         Ok(Success::new())
     }
 
@@ -508,7 +962,8 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         handler
             .add_user_object_class(&LdapObjectClass::from(name))
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         Ok(Success::new())
     }
 
@@ -529,7 +984,8 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         handler
             .add_group_object_class(&LdapObjectClass::from(name))
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         Ok(Success::new())
     }
 
@@ -550,7 +1006,8 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         handler
             .delete_user_object_class(&LdapObjectClass::from(name))
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         Ok(Success::new())
     }
 
@@ -571,11 +1028,227 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         handler
             .delete_group_object_class(&LdapObjectClass::from(name))
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         Ok(Success::new())
     }
 }
 
+fn decode_avatar<Handler: BackendHandler>(
+    context: &Context<Handler>,
+    avatar: Option<String>,
+) -> FieldResult<Option<JpegPhoto>> {
+    let Some(avatar) = avatar else {
+        return Ok(None);
+    };
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(avatar)
+        .map_err(|e| {
+            field_error(
+                "INVALID_BASE64",
+                "avatar",
+                format!("Invalid base64 image: {}", e),
+            )
+        })?;
+    context
+        .avatar_limits
+        .check(&bytes)
+        .map_err(|e| field_error("PAYLOAD_TOO_LARGE", "avatar", e))?;
+    JpegPhoto::try_from(bytes).map(Some).map_err(|e| {
+        attribute_field_error("avatar", e.context("Provided image is not a valid JPEG"))
+    })
+}
+
+/// Checks the names against the size of their columns.
+fn check_names(
+    display_name: Option<&str>,
+    first_name: Option<&str>,
+    last_name: Option<&str>,
+) -> FieldResult<()> {
+    for (field, value) in [
+        ("display_name", display_name),
+        ("first_name", first_name),
+        ("last_name", last_name),
+    ] {
+        if let Some(value) = value {
+            check_field_length(field, value, MAX_NAME_LENGTH)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_group_texts(description: Option<&str>, notes: Option<&str>) -> FieldResult<()> {
+    if let Some(description) = description {
+        check_field_length("description", description, MAX_GROUP_DESCRIPTION_LENGTH)?;
+    }
+    if let Some(notes) = notes {
+        check_field_length("notes", notes, MAX_GROUP_NOTES_LENGTH)?;
+    }
+    Ok(())
+}
+
+fn create_user_request<Handler: BackendHandler>(
+    context: &Context<Handler>,
+    user_attributes: &AttributeList,
+    user: CreateUserInput,
+) -> FieldResult<CreateUserRequest> {
+    check_names(
+        user.display_name.as_deref(),
+        user.first_name.as_deref(),
+        user.last_name.as_deref(),
+    )?;
+    let avatar = decode_avatar(context, user.avatar)?;
+    let attributes = user
+        .attributes
+        .unwrap_or_default()
+        .into_iter()
+        .map(|attr| deserialize_attribute(user_attributes, attr, true))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(CreateUserRequest {
+        user_id: UserId::new(&user.id),
+        email: email_input(user.email)?.into(),
+        display_name: user.display_name,
+        first_name: user.first_name,
+        last_name: user.last_name,
+        avatar,
+        preferred_language: user.preferred_language,
+        hosts: user.hosts.unwrap_or_default(),
+        phone: user.phone,
+        mobile: user.mobile,
+        postal_address: user.postal_address,
+        locality: user.locality,
+        state_or_province: user.state_or_province,
+        expires_at: user.expires_at,
+        attributes,
+        groups: user
+            .groups
+            .unwrap_or_default()
+            .into_iter()
+            .map(GroupId)
+            .collect(),
+    })
+}
+
+/// The existing user with the given email, for the users imported without an ID.
+async fn find_user_by_email(
+    handler: &impl AdminBackendHandler,
+    email: &str,
+) -> FieldResult<Option<UserId>> {
+    Ok(handler
+        .list_users(
+            Some(UserRequestFilter::Equality(
+                UserColumn::Email,
+                email.to_owned(),
+            )),
+            false,
+        )
+        .await
+        .map_err(domain_error_to_field_error)?
+        .into_iter()
+        .next()
+        .map(|user| user.user.user_id))
+}
+
+/// One user of `createUsers`, with the ID it ends up with.
+async fn create_or_update_user<Handler: BackendHandler>(
+    context: &Context<Handler>,
+    handler: &impl AdminBackendHandler,
+    user_attributes: &AttributeList,
+    user: CreateUserInput,
+    on_existing: ExistingUserPolicy,
+) -> FieldResult<(UserId, CreateUserStatus)> {
+    let mut request = create_user_request(context, user_attributes, user)?;
+    let exists = if request.user_id.as_str().is_empty() {
+        // Without an ID, the user is matched by their email.
+        match (context.login_identifier, request.email.as_str()) {
+            (LoginIdentifier::Email, email) if !email.is_empty() => {
+                match find_user_by_email(handler, email).await? {
+                    Some(user_id) => {
+                        request.user_id = user_id;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => false,
+        }
+    } else {
+        match handler.get_user_details(&request.user_id).await {
+            Ok(_) => true,
+            Err(DomainError::EntityNotFound(_)) => false,
+            Err(e) => return Err(domain_error_to_field_error(e)),
+        }
+    };
+    if !exists {
+        let has_avatar = request.avatar.is_some();
+        let user_id = handler
+            .create_user(request)
+            .await
+            .map_err(domain_error_to_field_error)?;
+        if !has_avatar {
+            import_avatar_in_background(context, &user_id);
+        }
+        return Ok((user_id, CreateUserStatus::Created));
+    }
+    let user_id = request.user_id.clone();
+    if on_existing == ExistingUserPolicy::Skip {
+        return Ok((user_id, CreateUserStatus::Skipped));
+    }
+    handler
+        .update_user(UpdateUserRequest {
+            user_id: request.user_id,
+            email: Some(request.email),
+            display_name: request.display_name,
+            first_name: request.first_name,
+            last_name: request.last_name,
+            avatar: request.avatar,
+            preferred_language: request.preferred_language,
+            // Like the attributes, the hosts are only replaced when given.
+            hosts: Some(request.hosts).filter(|hosts| !hosts.is_empty()),
+            phone: request.phone,
+            mobile: request.mobile,
+            postal_address: request.postal_address,
+            locality: request.locality,
+            state_or_province: request.state_or_province,
+            // Like the hosts, only replaced when given.
+            expires_at: request.expires_at.map(Some),
+            delete_attributes: Vec::new(),
+            insert_attributes: request.attributes,
+        })
+        .await
+        .map_err(domain_error_to_field_error)?;
+    // A no-op for the groups the user is already in.
+    for group in request.groups {
+        handler
+            .add_user_to_group(&user_id, group)
+            .await
+            .context(format!(
+                "The user was updated, but could not be added to the group {}",
+                group.0
+            ))?;
+    }
+    Ok((user_id, CreateUserStatus::Updated))
+}
+
+fn import_avatar_in_background<Handler: BackendHandler>(
+    context: &Context<Handler>,
+    user_id: &UserId,
+) {
+    if let Some(importer) = &context.avatar_importer {
+        importer.import_avatar_in_background(user_id.clone());
+    }
+}
+
+fn get_invitation_sender<Handler: BackendHandler>(
+    context: &Context<Handler>,
+) -> FieldResult<Arc<dyn InvitationSender>> {
+    context.invitation_sender.clone().ok_or_else(|| {
+        "Cannot send invitations: the emails are not configured (see smtp_options, and \
+        enable_password_reset)"
+            .into()
+    })
+}
+
 async fn create_group_with_details<Handler: BackendHandler>(
     context: &Context<Handler>,
     request: CreateGroupInput,
@@ -584,7 +1257,11 @@ async fn create_group_with_details<Handler: BackendHandler>(
     let handler = context
         .get_admin_handler()
         .ok_or_else(field_error_callback(&span, "Unauthorized group creation"))?;
-    let schema = handler.get_schema().await?;
+    check_group_texts(request.description.as_deref(), request.notes.as_deref())?;
+    let schema = handler
+        .get_schema()
+        .await
+        .map_err(domain_error_to_field_error)?;
     let attributes = request
         .attributes
         .unwrap_or_default()
@@ -593,37 +1270,92 @@ async fn create_group_with_details<Handler: BackendHandler>(
         .collect::<Result<Vec<_>, _>>()?;
     let request = CreateGroupRequest {
         display_name: request.display_name.into(),
+        description: request.description,
+        notes: request.notes,
         attributes,
+        ..Default::default()
     };
-    let group_id = handler.create_group(request).await?;
-    let group_details = handler.get_group_details(group_id).instrument(span).await?;
+    let group_id = handler
+        .create_group(request)
+        .await
+        .map_err(domain_error_to_field_error)?;
+    let group_details = handler
+        .get_group_details(group_id)
+        .instrument(span)
+        .await
+        .map_err(domain_error_to_field_error)?;
     super::query::Group::<Handler>::from_group_details(group_details, Arc::new(schema))
 }
 
+/// An error about one field of the input, with its name in the `field` extension so that
+/// clients can show it next to the right input.
+fn field_error(code: &str, name: &str, message: impl std::fmt::Display) -> FieldError {
+    FieldError::new(message, graphql_value!({ "code": code, "field": name }))
+}
+
+/// An invalid value of one attribute, see [`field_error`].
+fn attribute_field_error(name: &str, error: anyhow::Error) -> FieldError {
+    field_error("INVALID_INPUT", name, format!("{:#}", error))
+}
+
+fn check_field_length(name: &str, value: &str, max_length: usize) -> FieldResult<()> {
+    if value.chars().count() > max_length {
+        return Err(field_error(
+            "FIELD_TOO_LONG",
+            name,
+            format!(
+                "The value of {} is too long: the limit is {} characters",
+                name, max_length
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Normalizes and checks the email, like the backend does, for the error to point at the field.
+fn email_input(email: String) -> FieldResult<String> {
+    let email = normalize_email(&email);
+    check_email(&email).map_err(|e| {
+        attribute_field_error("email", anyhow!("Invalid email \"{}\": {}", email, e))
+    })?;
+    Ok(email)
+}
+
 fn deserialize_attribute(
     attribute_schema: &AttributeList,
     attribute: AttributeValue,
     is_admin: bool,
 ) -> FieldResult<DomainAttributeValue> {
-    let attribute_name = AttributeName::from(attribute.name.as_str());
-    let attribute_schema = attribute_schema
-        .get_attribute_schema(&attribute_name)
-        .ok_or_else(|| anyhow!("Attribute {} is not defined in the schema", attribute.name))?;
-    if !is_admin && !attribute_schema.is_editable {
-        return Err(anyhow!(
-            "Permission denied: Attribute {} is not editable by regular users",
-            attribute.name
-        )
-        .into());
+    if let Some(schema) =
+        attribute_schema.get_attribute_schema(&AttributeName::from(attribute.name.as_str()))
+    {
+        if !is_admin && !schema.is_editable {
+            return Err(field_error(
+                "PERMISSION_DENIED",
+                &attribute.name,
+                format!(
+                    "Permission denied: Attribute {} is not editable by regular users",
+                    attribute.name
+                ),
+            ));
+        }
+        if schema.attribute_type == AttributeType::String {
+            for value in &attribute.value {
+                check_field_length(&attribute.name, value, MAX_ATTRIBUTE_VALUE_LENGTH)?;
+            }
+        }
     }
-    let deserialized_values = deserialize_attribute_value(
+    deserialize::deserialize_attribute(
+        attribute_schema,
+        &attribute.name,
         &attribute.value,
-        attribute_schema.attribute_type,
-        attribute_schema.is_list,
+        is_admin,
     )
-    .context(format!("While deserializing attribute {}", attribute.name))?;
-    Ok(DomainAttributeValue {
-        name: attribute_name,
-        value: deserialized_values,
+    .map_err(|e| {
+        if e.chain().any(|c| c.is::<base64::DecodeError>()) {
+            field_error("INVALID_BASE64", &attribute.name, format!("{:#}", e))
+        } else {
+            attribute_field_error(&attribute.name, e)
+        }
     })
 }
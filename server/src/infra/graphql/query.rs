@@ -3,20 +3,25 @@ use std::sync::Arc;
 use crate::{
     domain::{
         deserialize::deserialize_attribute_value,
-        handler::{BackendHandler, ReadSchemaBackendHandler},
-        ldap::utils::{map_user_field, UserFieldType},
-        model::UserColumn,
+        handler::{BackendHandler, GroupRequestFilter, ReadSchemaBackendHandler, SubStringFilter},
+        ldap::utils::{group_dn, map_user_field, user_dn, UserFieldType},
+        model::{GroupColumn, UserColumn},
         schema::PublicSchema,
-        types::{AttributeType, GroupDetails, GroupId, JpegPhoto, LdapObjectClass, UserId},
+        types::{
+            AttributeName, AttributeType, GroupDetails, GroupId, JpegPhoto, LdapObjectClass, UserId,
+        },
     },
     infra::{
-        access_control::{ReadonlyBackendHandler, UserReadableBackendHandler},
-        graphql::api::{field_error_callback, Context},
+        access_control::{AdminBackendHandler, ReadonlyBackendHandler, UserReadableBackendHandler},
+        build_info::BuildInfo,
+        configuration::LoginIdentifier,
+        graphql::api::{domain_error_to_field_error, field_error_callback, AvatarLimits, Context},
+        security_status::SecurityIssue,
     },
 };
 use anyhow::Context as AnyhowContext;
 use chrono::{NaiveDateTime, TimeZone};
-use juniper::{graphql_object, FieldError, FieldResult, GraphQLInputObject};
+use juniper::{graphql_object, FieldError, FieldResult, GraphQLEnum, GraphQLInputObject};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, debug_span, Instrument, Span};
 
@@ -38,6 +43,14 @@ pub struct RequestFilter {
     eq: Option<EqualityConstraint>,
     member_of: Option<String>,
     member_of_id: Option<i32>,
+    /// Users who haven't logged in since that date, including those who never did.
+    last_login_before: Option<chrono::DateTime<chrono::Utc>>,
+    /// Users who haven't changed their password since that date, or never set one.
+    password_changed_before: Option<chrono::DateTime<chrono::Utc>>,
+    /// Users with an expiration date before that date.
+    expires_before: Option<chrono::DateTime<chrono::Utc>>,
+    /// Users whose id, email or display name contains the string, ignoring the case.
+    search: Option<String>,
 }
 
 impl RequestFilter {
@@ -49,8 +62,12 @@ impl RequestFilter {
             self.not,
             self.member_of,
             self.member_of_id,
+            self.last_login_before,
+            self.password_changed_before,
+            self.expires_before,
+            self.search,
         ) {
-            (Some(eq), None, None, None, None, None) => {
+            (Some(eq), None, None, None, None, None, None, None, None, None) => {
                 match map_user_field(&eq.field.as_str().into(), schema) {
                     UserFieldType::NoMatch => {
                         Err(format!("Unknown request filter: {}", &eq.field).into())
@@ -75,26 +92,51 @@ impl RequestFilter {
                     }
                 }
             }
-            (None, Some(any), None, None, None, None) => Ok(DomainRequestFilter::Or(
-                any.into_iter()
-                    .map(|f| f.try_into_domain_filter(schema))
-                    .collect::<FieldResult<Vec<_>>>()?,
-            )),
-            (None, None, Some(all), None, None, None) => Ok(DomainRequestFilter::And(
-                all.into_iter()
-                    .map(|f| f.try_into_domain_filter(schema))
-                    .collect::<FieldResult<Vec<_>>>()?,
-            )),
-            (None, None, None, Some(not), None, None) => Ok(DomainRequestFilter::Not(Box::new(
-                (*not).try_into_domain_filter(schema)?,
-            ))),
-            (None, None, None, None, Some(group), None) => {
+            (None, Some(any), None, None, None, None, None, None, None, None) => {
+                Ok(DomainRequestFilter::Or(
+                    any.into_iter()
+                        .map(|f| f.try_into_domain_filter(schema))
+                        .collect::<FieldResult<Vec<_>>>()?,
+                ))
+            }
+            (None, None, Some(all), None, None, None, None, None, None, None) => {
+                Ok(DomainRequestFilter::And(
+                    all.into_iter()
+                        .map(|f| f.try_into_domain_filter(schema))
+                        .collect::<FieldResult<Vec<_>>>()?,
+                ))
+            }
+            (None, None, None, Some(not), None, None, None, None, None, None) => Ok(
+                DomainRequestFilter::Not(Box::new((*not).try_into_domain_filter(schema)?)),
+            ),
+            (None, None, None, None, Some(group), None, None, None, None, None) => {
                 Ok(DomainRequestFilter::MemberOf(group.into()))
             }
-            (None, None, None, None, None, Some(group_id)) => {
+            (None, None, None, None, None, Some(group_id), None, None, None, None) => {
                 Ok(DomainRequestFilter::MemberOfId(GroupId(group_id)))
             }
-            (None, None, None, None, None, None) => {
+            (None, None, None, None, None, None, Some(date), None, None, None) => {
+                Ok(DomainRequestFilter::LastLoginBefore(date))
+            }
+            (None, None, None, None, None, None, None, Some(date), None, None) => {
+                Ok(DomainRequestFilter::PasswordChangedBefore(date))
+            }
+            (None, None, None, None, None, None, None, None, Some(date), None) => {
+                Ok(DomainRequestFilter::ExpiresBefore(date))
+            }
+            (None, None, None, None, None, None, None, None, None, Some(search)) => {
+                let filter = SubStringFilter {
+                    initial: None,
+                    any: vec![search],
+                    final_: None,
+                };
+                Ok(DomainRequestFilter::Or(vec![
+                    DomainRequestFilter::UserIdSubString(filter.clone()),
+                    DomainRequestFilter::SubString(UserColumn::Email, filter.clone()),
+                    DomainRequestFilter::SubString(UserColumn::DisplayName, filter),
+                ]))
+            }
+            (None, None, None, None, None, None, None, None, None, None) => {
                 Err("No field specified in request filter".into())
             }
             _ => Err("Multiple fields specified in request filter".into()),
@@ -108,6 +150,66 @@ pub struct EqualityConstraint {
     value: String,
 }
 
+#[derive(PartialEq, Eq, Debug, Clone, Copy, GraphQLEnum)]
+/// The fields by which the list of users can be sorted.
+pub enum UserSortField {
+    UserId,
+    Email,
+    DisplayName,
+    CreationDate,
+    LastLogin,
+    PasswordChangedAt,
+}
+
+impl UserSortField {
+    /// Sorts the users by the field. Users who never logged in, never set a password or have no
+    /// display name sort before everyone else.
+    fn sort(self, users: &mut [DomainUserAndGroups], descending: bool) {
+        match self {
+            UserSortField::UserId => users.sort_by(|a, b| a.user.user_id.cmp(&b.user.user_id)),
+            UserSortField::Email => users.sort_by(|a, b| a.user.email.cmp(&b.user.email)),
+            UserSortField::DisplayName => {
+                users.sort_by_cached_key(|u| u.user.display_name.as_ref().map(|n| n.to_lowercase()))
+            }
+            UserSortField::CreationDate => users.sort_by_key(|u| u.user.creation_date),
+            UserSortField::LastLogin => users.sort_by_key(|u| u.user.last_login),
+            UserSortField::PasswordChangedAt => users.sort_by_key(|u| u.user.password_changed_at),
+        }
+        if descending {
+            users.reverse();
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, juniper::GraphQLObject)]
+/// A misconfiguration weakening the security of the server.
+pub struct SecurityWarning {
+    code: SecurityIssue,
+    /// What is wrong.
+    message: String,
+    /// How to fix it.
+    remediation: String,
+}
+
+impl From<SecurityIssue> for SecurityWarning {
+    fn from(issue: SecurityIssue) -> Self {
+        Self {
+            code: issue,
+            message: issue.description().to_owned(),
+            remediation: issue.remediation().to_owned(),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, juniper::GraphQLObject)]
+/// A new email address waiting for verification: the change applies when the link sent to it is
+/// clicked.
+pub struct PendingEmailChange {
+    email: String,
+    /// The verification link stops working after this date.
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
 #[derive(PartialEq, Eq, Debug)]
 /// The top-level GraphQL query type.
 pub struct Query<Handler: BackendHandler> {
@@ -128,6 +230,35 @@ impl<Handler: BackendHandler> Query<Handler> {
         "1.0"
     }
 
+    /// The version of the server and how it was built.
+    fn server_info() -> BuildInfo {
+        BuildInfo::get().clone()
+    }
+
+    /// The avatars accepted by the server, for the web UI to crop and downscale them.
+    fn avatar_limits(context: &Context<Handler>) -> AvatarLimits {
+        context.avatar_limits
+    }
+
+    /// The misconfigurations weakening the security of the server. Admins only.
+    async fn security_status(context: &Context<Handler>) -> FieldResult<Vec<SecurityWarning>> {
+        let span = debug_span!("[GraphQL query] security_status");
+        if !context.validation_result.is_admin() {
+            return Err(field_error_callback(
+                &span,
+                "Unauthorized access to the security status",
+            )());
+        }
+        Ok(context
+            .security_checker
+            .security_issues()
+            .instrument(span)
+            .await
+            .into_iter()
+            .map(SecurityWarning::from)
+            .collect())
+    }
+
     pub async fn user(context: &Context<Handler>, user_id: String) -> FieldResult<User<Handler>> {
         use anyhow::Context;
         let span = debug_span!("[GraphQL query] user");
@@ -143,41 +274,94 @@ impl<Handler: BackendHandler> Query<Handler> {
                 "Unauthorized access to user data",
             ))?;
         let schema = Arc::new(self.get_schema(context, span.clone()).await?);
-        let user = handler.get_user_details(&user_id).instrument(span).await?;
+        let user = handler
+            .get_user_details(&user_id)
+            .instrument(span)
+            .await
+            .map_err(domain_error_to_field_error)?;
         User::<Handler>::from_user(user, schema)
     }
 
     async fn users(
         context: &Context<Handler>,
         #[graphql(name = "where")] filters: Option<RequestFilter>,
+        order_by: Option<UserSortField>,
+        descending: Option<bool>,
     ) -> FieldResult<Vec<User<Handler>>> {
         let span = debug_span!("[GraphQL query] users");
         span.in_scope(|| {
-            debug!(?filters);
+            debug!(?filters, ?order_by, ?descending);
         });
+        let (users, schema) = self
+            .list_sorted_users(context, span, filters, order_by, descending)
+            .await?;
+        users
+            .into_iter()
+            .map(|u| User::<Handler>::from_user_and_groups(u, schema.clone()))
+            .collect()
+    }
+
+    /// One page of the users, and how many users match the filters in total.
+    async fn users_page(
+        context: &Context<Handler>,
+        #[graphql(name = "where")] filters: Option<RequestFilter>,
+        order_by: Option<UserSortField>,
+        descending: Option<bool>,
+        offset: i32,
+        limit: i32,
+    ) -> FieldResult<UserPage<Handler>> {
+        let span = debug_span!("[GraphQL query] users_page");
+        span.in_scope(|| {
+            debug!(?filters, ?order_by, ?descending, offset, limit);
+        });
+        let offset = usize::try_from(offset).map_err(|_| "The offset can't be negative")?;
+        let limit = usize::try_from(limit).map_err(|_| "The limit can't be negative")?;
+        let (users, schema) = self
+            .list_sorted_users(context, span, filters, order_by, descending)
+            .await?;
+        Ok(UserPage {
+            total_count: users.len() as i32,
+            users: users
+                .into_iter()
+                .skip(offset)
+                .take(limit)
+                .map(|u| User::<Handler>::from_user_and_groups(u, schema.clone()))
+                .collect::<FieldResult<_>>()?,
+        })
+    }
+
+    /// The users that were deleted but not purged yet, and can be restored.
+    async fn deleted_users(context: &Context<Handler>) -> FieldResult<Vec<DeletedUser<Handler>>> {
+        let span = debug_span!("[GraphQL query] deleted_users");
         let handler = context
-            .get_readonly_handler()
+            .get_admin_handler()
             .ok_or_else(field_error_callback(
                 &span,
-                "Unauthorized access to user list",
+                "Unauthorized access to deleted users",
             ))?;
         let schema = Arc::new(self.get_schema(context, span.clone()).await?);
         let users = handler
-            .list_users(
-                filters
-                    .map(|f| f.try_into_domain_filter(&schema))
-                    .transpose()?,
-                false,
-            )
+            .list_deleted_users()
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         users
             .into_iter()
-            .map(|u| User::<Handler>::from_user_and_groups(u, schema.clone()))
+            .map(|u| {
+                Ok(DeletedUser {
+                    user: User::<Handler>::from_listed_user(u.user, schema.clone())?,
+                    deletion_date: u.deletion_date,
+                })
+            })
             .collect()
     }
 
-    async fn groups(context: &Context<Handler>) -> FieldResult<Vec<Group<Handler>>> {
+    /// The groups, or only those whose name or description contains `search`, ignoring the case.
+    /// The admins also search the notes.
+    async fn groups(
+        context: &Context<Handler>,
+        search: Option<String>,
+    ) -> FieldResult<Vec<Group<Handler>>> {
         let span = debug_span!("[GraphQL query] groups");
         let handler = context
             .get_readonly_handler()
@@ -186,7 +370,26 @@ impl<Handler: BackendHandler> Query<Handler> {
                 "Unauthorized access to group list",
             ))?;
         let schema = Arc::new(self.get_schema(context, span.clone()).await?);
-        let domain_groups = handler.list_groups(None).instrument(span).await?;
+        let filter = search.map(|search| {
+            let filter = SubStringFilter {
+                initial: None,
+                any: vec![search],
+                final_: None,
+            };
+            let mut filters = vec![
+                GroupRequestFilter::DisplayNameSubString(filter.clone()),
+                GroupRequestFilter::SubString(GroupColumn::Description, filter.clone()),
+            ];
+            if context.validation_result.is_admin() {
+                filters.push(GroupRequestFilter::SubString(GroupColumn::Notes, filter));
+            }
+            GroupRequestFilter::Or(filters)
+        });
+        let domain_groups = handler
+            .list_groups(filter)
+            .instrument(span)
+            .await
+            .map_err(domain_error_to_field_error)?;
         domain_groups
             .into_iter()
             .map(|g| Group::<Handler>::from_group(g, schema.clone()))
@@ -208,7 +411,8 @@ impl<Handler: BackendHandler> Query<Handler> {
         let group_details = handler
             .get_group_details(GroupId(group_id))
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         Group::<Handler>::from_group_details(group_details, schema.clone())
     }
 
@@ -231,7 +435,47 @@ impl<Handler: BackendHandler> Query<Handler> {
             .get_schema()
             .instrument(span)
             .await
-            .map(Into::<PublicSchema>::into)?)
+            .map(Into::<PublicSchema>::into)
+            .map_err(domain_error_to_field_error)?)
+    }
+
+    async fn list_sorted_users(
+        &self,
+        context: &Context<Handler>,
+        span: Span,
+        filters: Option<RequestFilter>,
+        order_by: Option<UserSortField>,
+        descending: Option<bool>,
+    ) -> FieldResult<(Vec<DomainUserAndGroups>, Arc<PublicSchema>)> {
+        let handler = context
+            .get_readonly_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized access to user list",
+            ))?;
+        let schema = Arc::new(self.get_schema(context, span.clone()).await?);
+        let mut users = handler
+            .list_users(
+                filters
+                    .map(|f| f.try_into_domain_filter(&schema))
+                    .transpose()?
+                    .map(|f| {
+                        context.handler.restrict_user_filter_to_visible_attributes(
+                            &context.validation_result,
+                            f,
+                        )
+                    }),
+                // The groups of all the users come in a single query, instead of one per user
+                // when the `groups` field is requested.
+                true,
+            )
+            .instrument(span)
+            .await
+            .map_err(domain_error_to_field_error)?;
+        if let Some(order_by) = order_by {
+            order_by.sort(&mut users, descending.unwrap_or(false));
+        }
+        Ok((users, schema))
     }
 }
 
@@ -242,6 +486,9 @@ pub struct User<Handler: BackendHandler> {
     attributes: Vec<AttributeValue<Handler>>,
     schema: Arc<PublicSchema>,
     groups: Option<Vec<Group<Handler>>>,
+    /// False for the users coming from a list, which doesn't include the `JpegPhoto` attributes:
+    /// they are then fetched when requested.
+    photos_loaded: bool,
     _phantom: std::marker::PhantomData<Box<Handler>>,
 }
 
@@ -258,9 +505,40 @@ impl<Handler: BackendHandler> User<Handler> {
                 .collect::<FieldResult<Vec<_>>>()?,
             schema,
             groups: None,
+            photos_loaded: true,
             _phantom: std::marker::PhantomData,
         })
     }
+
+    /// For the users returned by `list_users` or `list_deleted_users`.
+    pub fn from_listed_user(user: DomainUser, schema: Arc<PublicSchema>) -> FieldResult<Self> {
+        let mut user = Self::from_user(user, schema)?;
+        user.photos_loaded = false;
+        Ok(user)
+    }
+
+    async fn get_photo_attributes(
+        &self,
+        context: &Context<Handler>,
+    ) -> FieldResult<Vec<AttributeValue<Handler>>> {
+        let span = debug_span!("[GraphQL query] user::photos");
+        span.in_scope(|| {
+            debug!(user_id = ?self.user.user_id);
+        });
+        let handler = context
+            .get_readable_handler(&self.user.user_id)
+            .expect("We shouldn't be able to get there without readable permission");
+        handler
+            .get_user_photo_attributes(&self.user.user_id)
+            .instrument(span)
+            .await
+            .map_err(domain_error_to_field_error)?
+            .into_iter()
+            .map(|a| {
+                AttributeValue::<Handler>::from_schema(a, &self.schema.get_schema().user_attributes)
+            })
+            .collect()
+    }
 }
 
 impl<Handler: BackendHandler> User<Handler> {
@@ -268,7 +546,7 @@ impl<Handler: BackendHandler> User<Handler> {
         DomainUserAndGroups { user, groups }: DomainUserAndGroups,
         schema: Arc<PublicSchema>,
     ) -> FieldResult<Self> {
-        let mut user = Self::from_user(user, schema.clone())?;
+        let mut user = Self::from_listed_user(user, schema.clone())?;
         if let Some(groups) = groups {
             user.groups = Some(
                 groups
@@ -284,60 +562,199 @@ impl<Handler: BackendHandler> User<Handler> {
 #[graphql_object(context = Context<Handler>)]
 impl<Handler: BackendHandler> User<Handler> {
     fn id(&self) -> &str {
-        self.user.user_id.as_str()
+        self.user.user_id.as_entered()
     }
 
-    fn email(&self) -> &str {
-        self.user.email.as_str()
+    fn email(&self, context: &Context<Handler>) -> FieldResult<Option<&str>> {
+        context.check_user_attribute_access(&self.user.user_id, "mail")?;
+        // Null for the users without email.
+        Ok(Some(self.user.email.as_str()).filter(|email| !email.is_empty()))
     }
 
     fn display_name(&self) -> &str {
         self.user.display_name.as_deref().unwrap_or("")
     }
 
-    fn first_name(&self) -> &str {
-        self.user
-            .attributes
-            .iter()
-            .find(|a| a.name.as_str() == "first_name")
-            .map(|a| a.value.unwrap())
-            .unwrap_or("")
+    fn first_name(&self, context: &Context<Handler>) -> FieldResult<Option<&str>> {
+        context.check_user_attribute_access(&self.user.user_id, "first_name")?;
+        Ok(Some(
+            self.attributes
+                .iter()
+                .find(|a| a.attribute.name.as_str() == "first_name")
+                .map(|a| a.attribute.value.unwrap())
+                .unwrap_or(""),
+        ))
     }
 
-    fn last_name(&self) -> &str {
-        self.user
-            .attributes
-            .iter()
-            .find(|a| a.name.as_str() == "last_name")
-            .map(|a| a.value.unwrap())
-            .unwrap_or("")
+    fn last_name(&self, context: &Context<Handler>) -> FieldResult<Option<&str>> {
+        context.check_user_attribute_access(&self.user.user_id, "last_name")?;
+        Ok(Some(
+            self.attributes
+                .iter()
+                .find(|a| a.attribute.name.as_str() == "last_name")
+                .map(|a| a.attribute.value.unwrap())
+                .unwrap_or(""),
+        ))
     }
 
-    fn avatar(&self) -> Option<String> {
-        self.user
-            .attributes
+    async fn avatar(&self, context: &Context<Handler>) -> FieldResult<Option<String>> {
+        context.check_user_attribute_access(&self.user.user_id, "avatar")?;
+        let to_base64 =
+            |a: &AttributeValue<Handler>| String::from(&a.attribute.value.unwrap::<JpegPhoto>());
+        if self.photos_loaded {
+            return Ok(self
+                .attributes
+                .iter()
+                .find(|a| a.attribute.name.as_str() == "avatar")
+                .map(to_base64));
+        }
+        Ok(self
+            .get_photo_attributes(context)
+            .await?
             .iter()
-            .find(|a| a.name.as_str() == "avatar")
-            .map(|a| String::from(&a.value.unwrap::<JpegPhoto>()))
+            .find(|a| a.attribute.name.as_str() == "avatar")
+            .map(to_base64))
     }
 
     fn creation_date(&self) -> chrono::DateTime<chrono::Utc> {
-        chrono::Utc.from_utc_datetime(&self.user.creation_date)
+        self.user.creation_date
+    }
+
+    /// The last successful login, through LDAP or the web UI. Null if the user never logged in.
+    fn last_login(
+        &self,
+        context: &Context<Handler>,
+    ) -> FieldResult<Option<chrono::DateTime<chrono::Utc>>> {
+        context.check_user_attribute_access(&self.user.user_id, "last_login")?;
+        Ok(self.user.last_login)
+    }
+
+    /// The last time the password was set. Null if it was never set since this was tracked.
+    fn password_changed_at(
+        &self,
+        context: &Context<Handler>,
+    ) -> FieldResult<Option<chrono::DateTime<chrono::Utc>>> {
+        context.check_user_attribute_access(&self.user.user_id, "password_changed_at")?;
+        Ok(self.user.password_changed_at)
+    }
+
+    /// The date the account is disabled (deleted) at. Null if it doesn't expire.
+    fn expires_at(
+        &self,
+        context: &Context<Handler>,
+    ) -> FieldResult<Option<chrono::DateTime<chrono::Utc>>> {
+        context.check_user_attribute_access(&self.user.user_id, "expires_at")?;
+        Ok(self.user.expires_at)
     }
 
     fn uuid(&self) -> &str {
         self.user.uuid.as_str()
     }
 
+    /// The DN of the user, as served over LDAP.
+    fn dn(&self, context: &Context<Handler>) -> String {
+        user_dn(&self.user.user_id, &context.ldap_base_dn)
+    }
+
+    /// What the user logs in with: their email with `login_identifier = "email"`, unless they
+    /// have none, otherwise their ID.
+    fn login_identifier(&self, context: &Context<Handler>) -> &str {
+        match context.login_identifier {
+            LoginIdentifier::Email if !self.user.email.as_str().is_empty() => {
+                self.user.email.as_str()
+            }
+            _ => self.user.user_id.as_entered(),
+        }
+    }
+
+    /// BCP 47 language tag for the emails. Null to use the server default.
+    fn preferred_language(&self, context: &Context<Handler>) -> FieldResult<Option<&str>> {
+        context.check_user_attribute_access(&self.user.user_id, "preferred_language")?;
+        Ok(self.user.preferred_language.as_deref())
+    }
+
+    /// The hosts the user may log into, "*" for all of them. Served over LDAP as "host".
+    fn hosts(&self, context: &Context<Handler>) -> FieldResult<&[String]> {
+        context.check_user_attribute_access(&self.user.user_id, "hosts")?;
+        Ok(&self.user.hosts)
+    }
+
+    /// The phone number, without its formatting, e.g. "+15550109999". Served over LDAP as
+    /// "telephoneNumber", with a single value.
+    fn phone(&self, context: &Context<Handler>) -> FieldResult<Option<&str>> {
+        context.check_user_attribute_access(&self.user.user_id, "phone")?;
+        Ok(self.user.phone.as_deref())
+    }
+
+    /// The mobile phone number, like the phone number. Served over LDAP as "mobile".
+    fn mobile(&self, context: &Context<Handler>) -> FieldResult<Option<&str>> {
+        context.check_user_attribute_access(&self.user.user_id, "mobile")?;
+        Ok(self.user.mobile.as_deref())
+    }
+
+    /// The postal address, with one line per line of the address. Served over LDAP as
+    /// "postalAddress", with the lines separated by '$'.
+    fn postal_address(&self, context: &Context<Handler>) -> FieldResult<Option<&str>> {
+        context.check_user_attribute_access(&self.user.user_id, "postal_address")?;
+        Ok(self.user.postal_address.as_deref())
+    }
+
+    /// The city or town. Served over LDAP as "l".
+    fn locality(&self, context: &Context<Handler>) -> FieldResult<Option<&str>> {
+        context.check_user_attribute_access(&self.user.user_id, "locality")?;
+        Ok(self.user.locality.as_deref())
+    }
+
+    /// The state or province. Served over LDAP as "st".
+    fn state_or_province(&self, context: &Context<Handler>) -> FieldResult<Option<&str>> {
+        context.check_user_attribute_access(&self.user.user_id, "state_or_province")?;
+        Ok(self.user.state_or_province.as_deref())
+    }
+
+    /// The new email address waiting for verification. Only visible to the user and the admins.
+    async fn pending_email_change(
+        &self,
+        context: &Context<Handler>,
+    ) -> FieldResult<Option<PendingEmailChange>> {
+        let verifier = match (
+            &context.email_change_verifier,
+            context.get_writeable_handler(&self.user.user_id),
+        ) {
+            (Some(verifier), Some(_)) => verifier,
+            _ => return Ok(None),
+        };
+        let span = debug_span!("[GraphQL query] user::pending_email_change");
+        Ok(verifier
+            .get_pending_email_change(&self.user.user_id)
+            .instrument(span)
+            .await?
+            .map(|change| PendingEmailChange {
+                email: change.new_email,
+                expires_at: change.expiry_date,
+            }))
+    }
+
     /// User-defined attributes.
-    fn attributes(&self) -> &[AttributeValue<Handler>] {
-        &self.attributes
+    async fn attributes(
+        &self,
+        context: &Context<Handler>,
+    ) -> FieldResult<Vec<AttributeValue<Handler>>> {
+        let mut attributes = self.attributes.clone();
+        if !self.photos_loaded {
+            attributes.extend(self.get_photo_attributes(context).await?);
+            attributes.sort_by(|a, b| a.attribute.name.cmp(&b.attribute.name));
+        }
+        Ok(attributes
+            .into_iter()
+            .filter(|a| context.can_read_user_attribute(&self.user.user_id, &a.attribute.name))
+            .collect())
     }
 
     /// The groups to which this user belongs.
-    async fn groups(&self, context: &Context<Handler>) -> FieldResult<Vec<Group<Handler>>> {
+    async fn groups(&self, context: &Context<Handler>) -> FieldResult<Option<Vec<Group<Handler>>>> {
+        context.check_user_attribute_access(&self.user.user_id, "groups")?;
         if let Some(groups) = &self.groups {
-            return Ok(groups.clone());
+            return Ok(Some(groups.clone()));
         }
         let span = debug_span!("[GraphQL query] user::groups");
         span.in_scope(|| {
@@ -349,13 +766,77 @@ impl<Handler: BackendHandler> User<Handler> {
         let domain_groups = handler
             .get_user_groups(&self.user.user_id)
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
         let mut groups = domain_groups
             .into_iter()
             .map(|g| Group::<Handler>::from_group_details(g, self.schema.clone()))
             .collect::<FieldResult<Vec<Group<Handler>>>>()?;
         groups.sort_by(|g1, g2| g1.display_name.cmp(&g2.display_name));
-        Ok(groups)
+        Ok(Some(groups))
+    }
+
+    /// The groups that this user is the last active member of, that deleting the user would
+    /// leave empty: see `emptyGroupPolicy` in `deleteUser`. Only for the admins.
+    async fn groups_emptied_by_deletion(
+        &self,
+        context: &Context<Handler>,
+    ) -> FieldResult<Vec<Group<Handler>>> {
+        let span = debug_span!("[GraphQL query] user::groups_emptied_by_deletion");
+        span.in_scope(|| {
+            debug!(user_id = ?self.user.user_id);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized access to the groups emptied by deletion",
+            ))?;
+        handler
+            .get_groups_emptied_by_deletion(&self.user.user_id)
+            .instrument(span)
+            .await
+            .map_err(domain_error_to_field_error)?
+            .into_iter()
+            .map(|g| Group::<Handler>::from_group_details(g, self.schema.clone()))
+            .collect()
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+/// A page of the list of users.
+pub struct UserPage<Handler: BackendHandler> {
+    users: Vec<User<Handler>>,
+    total_count: i32,
+}
+
+#[graphql_object(context = Context<Handler>)]
+impl<Handler: BackendHandler> UserPage<Handler> {
+    fn users(&self) -> &[User<Handler>] {
+        &self.users
+    }
+
+    /// The number of users matching the filters, in all the pages.
+    fn total_count(&self) -> i32 {
+        self.total_count
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+/// A deleted user, that can still be restored.
+pub struct DeletedUser<Handler: BackendHandler> {
+    user: User<Handler>,
+    deletion_date: chrono::DateTime<chrono::Utc>,
+}
+
+#[graphql_object(context = Context<Handler>)]
+impl<Handler: BackendHandler> DeletedUser<Handler> {
+    fn user(&self) -> &User<Handler> {
+        &self.user
+    }
+
+    fn deletion_date(&self) -> chrono::DateTime<chrono::Utc> {
+        self.deletion_date
     }
 }
 
@@ -364,8 +845,10 @@ impl<Handler: BackendHandler> User<Handler> {
 pub struct Group<Handler: BackendHandler> {
     group_id: i32,
     display_name: String,
-    creation_date: chrono::NaiveDateTime,
+    creation_date: chrono::DateTime<chrono::Utc>,
     uuid: String,
+    description: Option<String>,
+    notes: Option<String>,
     attributes: Vec<AttributeValue<Handler>>,
     schema: Arc<PublicSchema>,
     _phantom: std::marker::PhantomData<Box<Handler>>,
@@ -381,6 +864,8 @@ impl<Handler: BackendHandler> Group<Handler> {
             display_name: group.display_name.to_string(),
             creation_date: group.creation_date,
             uuid: group.uuid.into_string(),
+            description: group.description,
+            notes: group.notes,
             attributes: group
                 .attributes
                 .into_iter()
@@ -402,6 +887,8 @@ impl<Handler: BackendHandler> Group<Handler> {
             display_name: group_details.display_name.to_string(),
             creation_date: group_details.creation_date,
             uuid: group_details.uuid.into_string(),
+            description: group_details.description,
+            notes: group_details.notes,
             attributes: group_details
                 .attributes
                 .into_iter()
@@ -422,6 +909,8 @@ impl<Handler: BackendHandler> Clone for Group<Handler> {
             display_name: self.display_name.clone(),
             creation_date: self.creation_date,
             uuid: self.uuid.clone(),
+            description: self.description.clone(),
+            notes: self.notes.clone(),
             attributes: self.attributes.clone(),
             schema: self.schema.clone(),
             _phantom: std::marker::PhantomData,
@@ -438,11 +927,30 @@ impl<Handler: BackendHandler> Group<Handler> {
         self.display_name.clone()
     }
     fn creation_date(&self) -> chrono::DateTime<chrono::Utc> {
-        chrono::Utc.from_utc_datetime(&self.creation_date)
+        self.creation_date
     }
     fn uuid(&self) -> String {
         self.uuid.clone()
     }
+    /// What the group is for, also served over LDAP.
+    fn description(&self) -> Option<String> {
+        self.description.clone()
+    }
+    /// Free-text notes about the group. Admins only.
+    fn notes(&self, context: &Context<Handler>) -> FieldResult<Option<String>> {
+        let span = debug_span!("[GraphQL query] group::notes");
+        if !context.validation_result.is_admin() {
+            return Err(field_error_callback(
+                &span,
+                "Unauthorized access to the group notes",
+            )());
+        }
+        Ok(self.notes.clone())
+    }
+    /// The DN of the group, as served over LDAP.
+    fn dn(&self, context: &Context<Handler>) -> String {
+        group_dn(&self.display_name, &context.ldap_base_dn)
+    }
 
     /// User-defined attributes.
     fn attributes(&self) -> &[AttributeValue<Handler>] {
@@ -464,12 +972,16 @@ impl<Handler: BackendHandler> Group<Handler> {
         let domain_users = handler
             .list_users(
                 Some(DomainRequestFilter::MemberOfId(GroupId(self.group_id))),
-                false,
+                true,
             )
             .instrument(span)
-            .await?;
+            .await
+            .map_err(domain_error_to_field_error)?;
+        // The members whose group memberships are hidden from the current user are left out.
+        let groups_attribute = AttributeName::from("groups");
         domain_users
             .into_iter()
+            .filter(|u| context.can_read_user_attribute(&u.user.user_id, &groups_attribute))
             .map(|u| User::<Handler>::from_user_and_groups(u, self.schema.clone()))
             .collect()
     }
@@ -685,7 +1197,7 @@ mod tests {
             types::{AttributeName, AttributeType, LdapObjectClass, Serialized},
         },
         infra::{
-            access_control::{Permission, ValidationResults},
+            access_control::{AttributeVisibility, Permission, ValidationResults},
             test_utils::{setup_default_schema, MockTestBackendHandler},
         },
     };
@@ -696,7 +1208,14 @@ mod tests {
     };
     use mockall::predicate::eq;
     use pretty_assertions::assert_eq;
-    use std::collections::HashSet;
+    use std::collections::{HashMap, HashSet};
+
+    fn visible_to_self_only(attributes: &[&str]) -> HashMap<AttributeName, AttributeVisibility> {
+        attributes
+            .iter()
+            .map(|a| (AttributeName::from(*a), AttributeVisibility::SelfOnly))
+            .collect()
+    }
 
     fn schema<'q, C, Q>(query_root: Q) -> RootNode<'q, Q, EmptyMutation<C>, EmptySubscription<C>>
     where
@@ -717,6 +1236,7 @@ mod tests {
             email
             creationDate
             uuid
+            dn
             attributes {
               name
               value
@@ -726,6 +1246,7 @@ mod tests {
               displayName
               creationDate
               uuid
+              dn
               attributes {
                 name
                 value
@@ -780,7 +1301,7 @@ mod tests {
                 Ok(DomainUser {
                     user_id: UserId::new("bob"),
                     email: "bob@bobbers.on".into(),
-                    creation_date: chrono::Utc.timestamp_millis_opt(42).unwrap().naive_utc(),
+                    creation_date: chrono::Utc.timestamp_millis_opt(42).unwrap(),
                     uuid: crate::uuid!("b1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
                     attributes: vec![
                         DomainAttributeValue {
@@ -799,8 +1320,10 @@ mod tests {
         groups.insert(GroupDetails {
             group_id: GroupId(3),
             display_name: "Bobbersons".into(),
-            creation_date: chrono::Utc.timestamp_nanos(42).naive_utc(),
+            creation_date: chrono::Utc.timestamp_nanos(42),
             uuid: crate::uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+            description: None,
+            notes: None,
             attributes: vec![DomainAttributeValue {
                 name: "club_name".into(),
                 value: Serialized::from("Gang of Four"),
@@ -809,8 +1332,10 @@ mod tests {
         groups.insert(GroupDetails {
             group_id: GroupId(7),
             display_name: "Jefferees".into(),
-            creation_date: chrono::Utc.timestamp_nanos(12).naive_utc(),
+            creation_date: chrono::Utc.timestamp_nanos(12),
             uuid: crate::uuid!("b1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+            description: None,
+            notes: None,
             attributes: Vec::new(),
         });
         mock.expect_get_user_groups()
@@ -831,6 +1356,7 @@ mod tests {
                         "email": "bob@bobbers.on",
                         "creationDate": "1970-01-01T00:00:00.042+00:00",
                         "uuid": "b1a2a3a4-b1b2-c1c2-d1d2-d3d4d5d6d7d8",
+                        "dn": "uid=bob,ou=people,dc=example,dc=com",
                         "attributes": [{
                             "name": "first_name",
                             "value": ["Bob"],
@@ -844,6 +1370,7 @@ mod tests {
                             "displayName": "Bobbersons",
                             "creationDate": "1970-01-01T00:00:00.000000042+00:00",
                             "uuid": "a1a2a3a4-b1b2-c1c2-d1d2-d3d4d5d6d7d8",
+                            "dn": "cn=Bobbersons,ou=groups,dc=example,dc=com",
                             "attributes": [{
                                 "name": "club_name",
                                 "value": ["Gang of Four"],
@@ -855,6 +1382,7 @@ mod tests {
                             "displayName": "Jefferees",
                             "creationDate": "1970-01-01T00:00:00.000000012+00:00",
                             "uuid": "b1a2a3a4-b1b2-c1c2-d1d2-d3d4d5d6d7d8",
+                            "dn": "cn=Jefferees,ou=groups,dc=example,dc=com",
                             "attributes": [],
                         }]
                     }
@@ -864,6 +1392,70 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn get_user_ignores_case() {
+        use crate::domain::sql_backend_handler::{tests::*, SqlBackendHandler};
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user_no_password(&handler, "JSmith").await;
+        insert_user_no_password(&handler, "jöhn").await;
+        let context =
+            Context::<SqlBackendHandler>::new_for_tests(handler, ValidationResults::admin());
+        let schema = schema(Query::<SqlBackendHandler>::new());
+        // The ID is displayed as entered.
+        for (user_id, expected) in [("jsmith", "JSmith"), ("JSMITH", "JSmith"), ("JÖHN", "jöhn")]
+        {
+            let query = format!(r#"{{ user(userId: "{}") {{ id }} }}"#, user_id);
+            assert_eq!(
+                execute(&query, None, &schema, &Variables::new(), &context).await,
+                Ok((graphql_value!({ "user": { "id": expected } }), vec![])),
+                "{}",
+                user_id
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn get_user_login_identifier() {
+        use crate::domain::{
+            handler::{CreateUserRequest, UserBackendHandler},
+            sql_backend_handler::{tests::*, SqlBackendHandler},
+        };
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user_no_password(&handler, "bob").await;
+        handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("noemail"),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let mut context =
+            Context::<SqlBackendHandler>::new_for_tests(handler, ValidationResults::admin());
+        let schema = schema(Query::<SqlBackendHandler>::new());
+        let query = r#"{ users { id loginIdentifier } }"#;
+        assert_eq!(
+            execute(query, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({ "users": [
+                    { "id": "bob", "loginIdentifier": "bob" },
+                    { "id": "noemail", "loginIdentifier": "noemail" },
+                ] }),
+                vec![]
+            ))
+        );
+        context.login_identifier = LoginIdentifier::Email;
+        assert_eq!(
+            execute(query, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({ "users": [
+                    { "id": "bob", "loginIdentifier": "bob@bob.bob" },
+                    { "id": "noemail", "loginIdentifier": "noemail" },
+                ] }),
+                vec![]
+            ))
+        );
+    }
+
     #[tokio::test]
     async fn list_users() {
         const QUERY: &str = r#"{
@@ -902,7 +1494,7 @@ mod tests {
                         Serialized::from("robert"),
                     ),
                 ]))),
-                eq(false),
+                eq(true),
             )
             .return_once(|_, _| {
                 Ok(vec![
@@ -950,6 +1542,66 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn users_page() {
+        const QUERY: &str = r#"{
+          usersPage(filters: {search: "Bob"}, orderBy: EMAIL, descending: true, offset: 1, limit: 1) {
+            users {
+              id
+            }
+            totalCount
+          }
+        }"#;
+
+        let mut mock = MockTestBackendHandler::new();
+        setup_default_schema(&mut mock);
+        let search = SubStringFilter {
+            initial: None,
+            any: vec!["Bob".to_owned()],
+            final_: None,
+        };
+        mock.expect_list_users()
+            .with(
+                eq(Some(DomainRequestFilter::Or(vec![
+                    DomainRequestFilter::UserIdSubString(search.clone()),
+                    DomainRequestFilter::SubString(UserColumn::Email, search.clone()),
+                    DomainRequestFilter::SubString(UserColumn::DisplayName, search),
+                ]))),
+                eq(true),
+            )
+            .return_once(|_, _| {
+                Ok(["bob", "bobby", "robert"]
+                    .into_iter()
+                    .map(|id| DomainUserAndGroups {
+                        user: DomainUser {
+                            user_id: UserId::new(id),
+                            email: format!("{}@bobbers.on", id).into(),
+                            ..Default::default()
+                        },
+                        groups: None,
+                    })
+                    .collect())
+            });
+
+        let context =
+            Context::<MockTestBackendHandler>::new_for_tests(mock, ValidationResults::admin());
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "usersPage": {
+                        "users": [{"id": "bobby"}],
+                        "totalCount": 3,
+                    }
+                }),
+                vec![]
+            ))
+        );
+    }
+
     #[tokio::test]
     async fn get_schema() {
         const QUERY: &str = r#"{
@@ -1106,6 +1758,142 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn security_status_is_for_admins() {
+        const QUERY: &str = r#"{
+          securityStatus {
+            code
+            remediation
+          }
+        }"#;
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        let mut context = Context::<MockTestBackendHandler>::new_for_tests(
+            MockTestBackendHandler::new(),
+            ValidationResults::admin(),
+        );
+        context.security_checker = Arc::new(vec![SecurityIssue::DefaultJwtSecret]);
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "securityStatus": [{
+                        "code": "DEFAULT_JWT_SECRET",
+                        "remediation": "Set jwt_secret (or LLDAP_JWT_SECRET) to a long random string, e.g. from `openssl rand -base64 32`, and restart.",
+                    }]
+                }),
+                vec![]
+            ))
+        );
+
+        context.validation_result = ValidationResults {
+            user: UserId::new("bob"),
+            permission: Permission::Readonly,
+        };
+        let (result, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(result, graphql_value!(None));
+        assert_eq!(
+            errors[0].error().message(),
+            "Unauthorized access to the security status"
+        );
+        assert_eq!(
+            errors[0].error().extensions(),
+            &graphql_value!({ "code": "PERMISSION_DENIED" })
+        );
+    }
+
+    #[tokio::test]
+    async fn group_notes_are_for_admins() {
+        const QUERY: &str = r#"{
+          groups(search: "app") {
+            displayName
+            description
+            notes
+          }
+        }"#;
+
+        let handler = |search_notes: bool| {
+            let mut mock = MockTestBackendHandler::new();
+            setup_default_schema(&mut mock);
+            let search = SubStringFilter {
+                initial: None,
+                any: vec!["app".to_owned()],
+                final_: None,
+            };
+            let mut filters = vec![
+                GroupRequestFilter::DisplayNameSubString(search.clone()),
+                GroupRequestFilter::SubString(GroupColumn::Description, search.clone()),
+            ];
+            if search_notes {
+                filters.push(GroupRequestFilter::SubString(GroupColumn::Notes, search));
+            }
+            mock.expect_list_groups()
+                .with(eq(Some(GroupRequestFilter::Or(filters))))
+                .times(1)
+                .return_once(|_| {
+                    Ok(vec![DomainGroup {
+                        id: GroupId(3),
+                        display_name: "app-x-rw".into(),
+                        creation_date: chrono::Utc.timestamp_nanos(42),
+                        uuid: crate::uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                        description: Some("Write access to app X".to_owned()),
+                        notes: Some("Ask Alice before adding anyone".to_owned()),
+                        users: Vec::new(),
+                        attributes: Vec::new(),
+                    }])
+                });
+            mock
+        };
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        let context = Context::<MockTestBackendHandler>::new_for_tests(
+            handler(true),
+            ValidationResults::admin(),
+        );
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "groups": [{
+                        "displayName": "app-x-rw",
+                        "description": "Write access to app X",
+                        "notes": "Ask Alice before adding anyone",
+                    }]
+                }),
+                vec![]
+            ))
+        );
+
+        let context = Context::<MockTestBackendHandler>::new_for_tests(
+            handler(false),
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Readonly,
+            },
+        );
+        let (result, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            graphql_value!(
+            {
+                "groups": [{
+                    "displayName": "app-x-rw",
+                    "description": "Write access to app X",
+                    "notes": None,
+                }]
+            })
+        );
+        assert_eq!(
+            errors[0].error().message(),
+            "Unauthorized access to the group notes"
+        );
+    }
+
     #[tokio::test]
     async fn regular_user_doesnt_see_non_visible_attributes() {
         const QUERY: &str = r#"{
@@ -1172,4 +1960,222 @@ mod tests {
             ))
         );
     }
+
+    #[tokio::test]
+    async fn password_manager_doesnt_see_restricted_attributes() {
+        const QUERY: &str = r#"{
+          users {
+            id
+            displayName
+            email
+            attributes {
+              name
+            }
+          }
+        }"#;
+
+        let mut mock = MockTestBackendHandler::new();
+        setup_default_schema(&mut mock);
+        mock.expect_list_users()
+            .with(eq(None), eq(true))
+            .return_once(|_, _| {
+                Ok(vec![DomainUserAndGroups {
+                    user: DomainUser {
+                        user_id: UserId::new("bob"),
+                        email: "bob@bobbers.on".into(),
+                        display_name: Some("Bob".to_string()),
+                        attributes: vec![DomainAttributeValue {
+                            name: "first_name".into(),
+                            value: Serialized::from("Bob"),
+                        }],
+                        ..Default::default()
+                    },
+                    groups: None,
+                }])
+            });
+        mock.expect_get_user_photos()
+            .withf(|user_ids| user_ids == [UserId::new("bob")])
+            .return_once(|_| Ok(std::collections::HashMap::new()));
+
+        let mut context = Context::<MockTestBackendHandler>::new_for_tests(
+            mock,
+            ValidationResults {
+                user: UserId::new("helpdesk"),
+                permission: Permission::PasswordManager,
+            },
+        );
+        context.handler = context
+            .handler
+            .with_attribute_visibility(visible_to_self_only(&["mail", "first_name"]));
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        let (result, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            graphql_value!(
+            {
+                "users": [
+                    {
+                        "id": "bob",
+                        "displayName": "Bob",
+                        "email": None,
+                        "attributes": [],
+                    },
+                ]
+            })
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].error().message(),
+            "Unauthorized access to attribute mail"
+        );
+    }
+
+    #[tokio::test]
+    async fn address_book_fields_follow_the_visibility() {
+        const QUERY: &str = r#"{
+          user(userId: "bob") {
+            id
+            phone
+            locality
+          }
+        }"#;
+
+        let handler = || {
+            let mut mock = MockTestBackendHandler::new();
+            setup_default_schema(&mut mock);
+            mock.expect_get_user_details()
+                .with(eq(UserId::new("bob")))
+                .return_once(|_| {
+                    Ok(DomainUser {
+                        user_id: UserId::new("bob"),
+                        phone: Some("+15550109999".to_owned()),
+                        locality: Some("Springfield".to_owned()),
+                        ..Default::default()
+                    })
+                });
+            mock
+        };
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        let visibility = visible_to_self_only(&["phone", "locality"]);
+        let mut context = Context::<MockTestBackendHandler>::new_for_tests(
+            handler(),
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Regular,
+            },
+        );
+        context.handler = context
+            .handler
+            .with_attribute_visibility(visibility.clone());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({
+                    "user": {
+                        "id": "bob",
+                        "phone": "+15550109999",
+                        "locality": "Springfield",
+                    }
+                }),
+                vec![]
+            ))
+        );
+        // Like the other attributes, they can be hidden from the others.
+        let mut context = Context::<MockTestBackendHandler>::new_for_tests(
+            handler(),
+            ValidationResults {
+                user: UserId::new("helpdesk"),
+                permission: Permission::PasswordManager,
+            },
+        );
+        context.handler = context.handler.with_attribute_visibility(visibility);
+        let (result, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            graphql_value!({
+                "user": {
+                    "id": "bob",
+                    "phone": None,
+                    "locality": None,
+                }
+            })
+        );
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].error().message(),
+            "Unauthorized access to attribute phone"
+        );
+    }
+
+    #[tokio::test]
+    async fn group_members_follow_the_visibility_of_groups() {
+        const QUERY: &str = r#"{
+          group(groupId: 3) {
+            users {
+              id
+            }
+          }
+        }"#;
+
+        let mut mock = MockTestBackendHandler::new();
+        setup_default_schema(&mut mock);
+        mock.expect_get_group_details()
+            .with(eq(GroupId(3)))
+            .return_once(|_| {
+                Ok(GroupDetails {
+                    group_id: GroupId(3),
+                    display_name: "Bobbersons".into(),
+                    creation_date: chrono::Utc.timestamp_nanos(42),
+                    uuid: crate::uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                    description: None,
+                    notes: None,
+                    attributes: Vec::new(),
+                })
+            });
+        mock.expect_list_users()
+            .with(
+                eq(Some(DomainRequestFilter::MemberOfId(GroupId(3)))),
+                eq(true),
+            )
+            .return_once(|_, _| {
+                Ok(["bob", "helpdesk"]
+                    .into_iter()
+                    .map(|user_id| DomainUserAndGroups {
+                        user: DomainUser {
+                            user_id: UserId::new(user_id),
+                            ..Default::default()
+                        },
+                        groups: None,
+                    })
+                    .collect())
+            });
+        let mut context = Context::<MockTestBackendHandler>::new_for_tests(
+            mock,
+            ValidationResults {
+                user: UserId::new("helpdesk"),
+                permission: Permission::PasswordManager,
+            },
+        );
+        context.handler = context
+            .handler
+            .with_attribute_visibility(visible_to_self_only(&["groups"]));
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({
+                    "group": {
+                        "users": [{"id": "helpdesk"}],
+                    }
+                }),
+                vec![]
+            ))
+        );
+    }
 }
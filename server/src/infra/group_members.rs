@@ -0,0 +1,432 @@
+//! `/api/groups/{group}/members.txt` and `.json`: the emails of the members of a group, e.g. for
+//! a mailing list manager. The `ETag` is a hash of the emails, so that a poll that finds no change
+//! doesn't transfer the list.
+
+use crate::{
+    domain::{
+        handler::{BackendHandler, GroupRequestFilter, UserRequestFilter},
+        types::{Group, GroupId},
+    },
+    infra::{
+        access_control::ReadonlyBackendHandler,
+        auth_service::check_if_token_is_valid,
+        tcp_server::{error_to_http_response, AppState, TcpError, TcpResult},
+    },
+};
+use actix_web::{
+    http::header::{self, CacheControl, CacheDirective, EntityTag, IfNoneMatch},
+    web, HttpResponse,
+};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use futures_util::stream;
+use tracing::instrument;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// One email per line.
+    Text,
+    /// A JSON array of the emails.
+    Json,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Text => "txt",
+            Format::Json => "json",
+        }
+    }
+}
+
+fn is_cached(if_none_match: Option<&IfNoneMatch>, etag: &EntityTag) -> bool {
+    match if_none_match {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => false,
+    }
+}
+
+fn cache_headers(response: &mut actix_web::HttpResponseBuilder, etag: EntityTag) {
+    response
+        .insert_header(header::ETag(etag))
+        .insert_header(CacheControl(vec![
+            CacheDirective::Private,
+            CacheDirective::NoCache,
+        ]));
+}
+
+/// The hash of the emails, in the order they are served. It changes with the memberships and with
+/// the emails of the members.
+fn emails_hash(emails: &[String]) -> String {
+    use base64::Engine;
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    for email in emails {
+        hasher.update(email.as_bytes());
+        hasher.update(b"\n");
+    }
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(hasher.finalize())
+}
+
+/// Finds the group by name, then by ID if the name is a number and no group has this name.
+async fn find_group(handler: &impl ReadonlyBackendHandler, group: &str) -> TcpResult<Group> {
+    let by_name = handler
+        .list_groups(Some(GroupRequestFilter::DisplayName(group.into())))
+        .await?;
+    let found = match (by_name.into_iter().next(), group.parse::<i32>()) {
+        (Some(found), _) => Some(found),
+        (None, Ok(id)) => handler
+            .list_groups(Some(GroupRequestFilter::GroupId(GroupId(id))))
+            .await?
+            .into_iter()
+            .next(),
+        (None, Err(_)) => None,
+    };
+    found.ok_or_else(|| TcpError::NotFoundError(format!("No such group: {}", group)))
+}
+
+/// The chunks of the body, one per email, so that a large group isn't copied into a single buffer.
+fn body_chunks(
+    emails: Vec<String>,
+    format: Format,
+) -> impl Iterator<Item = Result<web::Bytes, std::convert::Infallible>> {
+    let is_empty = emails.is_empty();
+    let (open, close) = match format {
+        Format::Text => (None, None),
+        Format::Json => (Some("["), Some(if is_empty { "]" } else { "\n]" })),
+    };
+    let lines = emails.into_iter().enumerate().map(move |(index, email)| {
+        web::Bytes::from(match format {
+            Format::Text => email + "\n",
+            Format::Json => format!(
+                "{}\n  {}",
+                if index == 0 { "" } else { "," },
+                serde_json::Value::String(email)
+            ),
+        })
+    });
+    open.map(web::Bytes::from_static)
+        .into_iter()
+        .chain(lines)
+        .chain(close.map(web::Bytes::from_static))
+        .map(Ok)
+}
+
+#[instrument(skip_all, level = "debug", fields(group = %group, format = ?format))]
+async fn get_members<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+    group: String,
+    format: Format,
+    if_none_match: Option<IfNoneMatch>,
+) -> TcpResult<HttpResponse>
+where
+    Backend: BackendHandler + 'static,
+{
+    let validation_result = check_if_token_is_valid(&data, bearer.token())
+        .map_err(|e| TcpError::UnauthorizedError(e.to_string()))?;
+    let handler = data
+        .backend_handler
+        .get_readonly_handler(&validation_result)
+        .ok_or_else(|| {
+            TcpError::UnauthorizedError("Unauthorized access to the group members".to_owned())
+        })?;
+    let group = find_group(handler, &group).await?;
+    // The deleted users are not listed.
+    let emails = handler
+        .list_users(Some(UserRequestFilter::MemberOfId(group.id)), false)
+        .await?
+        .into_iter()
+        .map(|u| u.user.email.into_string())
+        .collect::<Vec<_>>();
+    let etag = EntityTag::new_strong(format!("{}.{}", emails_hash(&emails), format.extension()));
+    if is_cached(if_none_match.as_ref(), &etag) {
+        let mut response = HttpResponse::NotModified();
+        cache_headers(&mut response, etag);
+        return Ok(response.finish());
+    }
+    let mut response = HttpResponse::Ok();
+    cache_headers(&mut response, etag);
+    Ok(response
+        .content_type(match format {
+            Format::Text => "text/plain; charset=utf-8",
+            Format::Json => "application/json",
+        })
+        .streaming(stream::iter(body_chunks(emails, format))))
+}
+
+async fn get_members_handler<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+    path: web::Path<String>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    format: Format,
+) -> HttpResponse
+where
+    Backend: BackendHandler + 'static,
+{
+    get_members(
+        data,
+        bearer,
+        path.into_inner(),
+        format,
+        if_none_match.map(web::Header::into_inner),
+    )
+    .await
+    .unwrap_or_else(|e| match e {
+        // The consumers are scripts: the unknown groups get a body they can parse.
+        TcpError::NotFoundError(message) => {
+            HttpResponse::NotFound().json(serde_json::json!({ "error": message }))
+        }
+        e => error_to_http_response(e),
+    })
+}
+
+async fn get_text_members_handler<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+    path: web::Path<String>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+) -> HttpResponse
+where
+    Backend: BackendHandler + 'static,
+{
+    get_members_handler(data, bearer, path, if_none_match, Format::Text).await
+}
+
+async fn get_json_members_handler<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+    path: web::Path<String>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+) -> HttpResponse
+where
+    Backend: BackendHandler + 'static,
+{
+    get_members_handler(data, bearer, path, if_none_match, Format::Json).await
+}
+
+pub(crate) fn configure<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: BackendHandler + 'static,
+{
+    cfg.route(
+        "/groups/{group}/members.txt",
+        web::get().to(get_text_members_handler::<Backend>),
+    )
+    .route(
+        "/groups/{group}/members.json",
+        web::get().to(get_json_members_handler::<Backend>),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        handler::{UpdateUserRequest, UserBackendHandler},
+        sql_backend_handler::{tests::*, SqlBackendHandler},
+        types::UserId,
+    };
+    use actix_web::{http::StatusCode, test, App};
+    use chrono::Utc;
+    use jwt::SignWithKey;
+    use lldap_auth::JWTClaims;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashSet;
+
+    fn token(state: &AppState<SqlBackendHandler>, user: &str, group: Option<&str>) -> String {
+        let claims = JWTClaims {
+            exp: Utc::now() + chrono::Duration::days(1),
+            iat: Utc::now(),
+            user: user.to_owned(),
+            groups: group.into_iter().map(str::to_owned).collect::<HashSet<_>>(),
+            login: None,
+        };
+        let header = jwt::Header {
+            algorithm: jwt::AlgorithmType::Hs512,
+            ..Default::default()
+        };
+        jwt::Token::new(header, claims)
+            .sign_with_key(&state.jwt_key)
+            .unwrap()
+            .as_str()
+            .to_owned()
+    }
+
+    struct Response {
+        status: StatusCode,
+        etag: Option<String>,
+        body: String,
+    }
+
+    async fn get(
+        state: web::Data<AppState<SqlBackendHandler>>,
+        group: Option<&str>,
+        uri: &str,
+        if_none_match: Option<&str>,
+    ) -> Response {
+        let token = token(&state, "admin", group);
+        let app = test::init_service(
+            App::new()
+                .app_data(state)
+                .service(web::scope("/api").configure(configure::<SqlBackendHandler>)),
+        )
+        .await;
+        let mut request = test::TestRequest::get()
+            .uri(uri)
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)));
+        if let Some(etag) = if_none_match {
+            request = request.insert_header((header::IF_NONE_MATCH, etag));
+        }
+        let response = test::call_service(&app, request.to_request()).await;
+        let status = response.status();
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .map(|v| v.to_str().unwrap().to_owned());
+        let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        Response { status, etag, body }
+    }
+
+    const ADMIN: Option<&str> = Some("lldap_admin");
+
+    #[actix_web::test]
+    async fn test_members() {
+        let fixture = TestFixture::new().await;
+        let state = web::Data::new(AppState::new_for_tests(fixture.handler.clone()));
+        let response = get(
+            state.clone(),
+            ADMIN,
+            "/api/groups/Best%20Group/members.txt",
+            None,
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, "bob@bob.bob\npatrick@bob.bob\n");
+        let etag = response.etag.unwrap();
+        // By ID, and in JSON.
+        let uri = format!("/api/groups/{}/members.json", fixture.groups[0].0);
+        let response = get(state.clone(), ADMIN, &uri, None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_str::<Vec<String>>(&response.body).unwrap(),
+            vec!["bob@bob.bob", "patrick@bob.bob"]
+        );
+        assert_ne!(response.etag.as_ref(), Some(&etag));
+
+        let response = get(
+            state.clone(),
+            ADMIN,
+            "/api/groups/best%20group/members.txt",
+            Some(&etag),
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        assert!(response.body.is_empty());
+
+        // A new email, and a deleted member, change the list.
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                email: Some("robert@bob.bob".into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        fixture
+            .handler
+            .delete_user(&UserId::new("patrick"))
+            .await
+            .unwrap();
+        let response = get(
+            state,
+            ADMIN,
+            "/api/groups/Best%20Group/members.txt",
+            Some(&etag),
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, "robert@bob.bob\n");
+        assert_ne!(response.etag, Some(etag));
+    }
+
+    #[actix_web::test]
+    async fn test_members_empty_group() {
+        let fixture = TestFixture::new().await;
+        let state = web::Data::new(AppState::new_for_tests(fixture.handler.clone()));
+        let response = get(
+            state.clone(),
+            ADMIN,
+            "/api/groups/Empty%20Group/members.txt",
+            None,
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, "");
+        let response = get(state, ADMIN, "/api/groups/Empty%20Group/members.json", None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, "[]");
+    }
+
+    #[actix_web::test]
+    async fn test_members_large_group() {
+        let fixture = TestFixture::new().await;
+        let group = insert_group(&fixture.handler, "Everyone").await;
+        let mut expected = Vec::new();
+        for i in 0..500 {
+            let name = format!("user{:03}", i);
+            insert_user_no_password(&fixture.handler, &name).await;
+            insert_membership(&fixture.handler, group, &name).await;
+            expected.push(format!("{}@bob.bob", name));
+        }
+        let state = web::Data::new(AppState::new_for_tests(fixture.handler.clone()));
+        let response = get(
+            state.clone(),
+            ADMIN,
+            "/api/groups/Everyone/members.txt",
+            None,
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body.lines().collect::<Vec<_>>(), expected);
+        let response = get(state, ADMIN, "/api/groups/Everyone/members.json", None).await;
+        assert_eq!(
+            serde_json::from_str::<Vec<String>>(&response.body).unwrap(),
+            expected
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_members_unknown_group() {
+        let fixture = TestFixture::new().await;
+        let state = web::Data::new(AppState::new_for_tests(fixture.handler.clone()));
+        for uri in [
+            "/api/groups/Nobody/members.txt",
+            "/api/groups/4242/members.json",
+        ] {
+            let response = get(state.clone(), ADMIN, uri, None).await;
+            assert_eq!(response.status, StatusCode::NOT_FOUND, "{}", uri);
+            let body = serde_json::from_str::<serde_json::Value>(&response.body).unwrap();
+            assert!(body["error"].as_str().unwrap().starts_with("No such group"));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_members_permissions() {
+        let fixture = TestFixture::new().await;
+        let state = web::Data::new(AppState::new_for_tests(fixture.handler.clone()));
+        let uri = "/api/groups/Best%20Group/members.txt";
+        assert_eq!(
+            get(state.clone(), Some("lldap_strict_readonly"), uri, None)
+                .await
+                .status,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get(state, None, uri, None).await.status,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! Endpoints for the liveness and readiness probes, e.g. of Kubernetes.
+//!
+//! `/health` only tells that the process answers, `/ready` that it can serve requests: the
+//! database is reachable and migrated, and the LDAP server accepts connections. `/ready` also
+//! reports the result of the SMTP check at startup, without affecting the readiness: the server
+//! can run without emails. On a replica, it reports the last synchronization with the primary: the
+//! replica is only ready once it has a copy of the directory, and stays ready with the lag
+//! growing when the primary can't be reached.
+
+use crate::{
+    domain::sql_tables::LAST_SCHEMA_VERSION,
+    infra::{
+        replication::{ReplicationCheck, ReplicationStatus},
+        tcp_backend_handler::TcpBackendHandler,
+    },
+};
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::{net::TcpStream, sync::Mutex};
+
+/// How long a readiness check result is reused, so that the probes don't hit the database.
+const READINESS_CACHE_DURATION: Duration = Duration::from_secs(5);
+const LDAP_CONNECTION_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Components {
+    pub database: bool,
+    pub migrations: bool,
+    pub ldap: bool,
+}
+
+/// The result of the last SMTP connectivity check, see `mail::check_smtp_connection`.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SmtpCheck {
+    pub reachable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub checked_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Shared between the SMTP check and the readiness probe. Empty until a check ran.
+#[derive(Clone, Debug, Default)]
+pub struct SmtpStatus(Arc<RwLock<Option<SmtpCheck>>>);
+
+impl SmtpStatus {
+    pub fn set(&self, check: SmtpCheck) {
+        *self.0.write().unwrap() = Some(check);
+    }
+
+    pub fn get(&self) -> Option<SmtpCheck> {
+        self.0.read().unwrap().clone()
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Readiness {
+    pub ready: bool,
+    pub components: Components,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp: Option<SmtpCheck>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replication: Option<ReplicationCheck>,
+}
+
+pub(crate) struct HealthState<Backend> {
+    backend_handler: Backend,
+    ldap_address: (String, u16),
+    smtp_status: SmtpStatus,
+    replication_status: Option<ReplicationStatus>,
+    last_check: Mutex<Option<(Instant, Readiness)>>,
+}
+
+impl<Backend: TcpBackendHandler> HealthState<Backend> {
+    pub fn new(
+        backend_handler: Backend,
+        ldap_address: (String, u16),
+        smtp_status: SmtpStatus,
+    ) -> Self {
+        Self {
+            backend_handler,
+            ldap_address,
+            smtp_status,
+            replication_status: None,
+            last_check: Mutex::new(None),
+        }
+    }
+
+    /// For a replica.
+    pub fn with_replication(mut self, replication_status: Option<ReplicationStatus>) -> Self {
+        self.replication_status = replication_status;
+        self
+    }
+
+    async fn check(&self) -> Readiness {
+        let (schema_version, ldap) = tokio::join!(
+            self.backend_handler.get_schema_version(),
+            tokio::time::timeout(
+                LDAP_CONNECTION_TIMEOUT,
+                TcpStream::connect((self.ldap_address.0.as_str(), self.ldap_address.1))
+            )
+        );
+        let components = Components {
+            database: schema_version.is_ok(),
+            migrations: matches!(schema_version, Ok(Some(version)) if version == LAST_SCHEMA_VERSION),
+            ldap: matches!(ldap, Ok(Ok(_))),
+        };
+        let replication = self
+            .replication_status
+            .as_ref()
+            .map(|status| status.check(chrono::Utc::now()));
+        Readiness {
+            ready: components.database
+                && components.migrations
+                && components.ldap
+                && replication
+                    .as_ref()
+                    .map_or(true, |check| check.last_sync_at.is_some()),
+            components,
+            smtp: self.smtp_status.get(),
+            replication,
+        }
+    }
+
+    /// Returns the last result if it is recent enough, otherwise runs the checks. Concurrent
+    /// probes wait for the same check.
+    pub async fn get_readiness(&self) -> Readiness {
+        let mut last_check = self.last_check.lock().await;
+        match &*last_check {
+            Some((time, readiness)) if time.elapsed() < READINESS_CACHE_DURATION => {
+                readiness.clone()
+            }
+            _ => {
+                let readiness = self.check().await;
+                *last_check = Some((Instant::now(), readiness.clone()));
+                readiness
+            }
+        }
+    }
+}
+
+async fn health() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "healthy": true }))
+}
+
+async fn ready<Backend: TcpBackendHandler + 'static>(
+    state: web::Data<HealthState<Backend>>,
+) -> HttpResponse {
+    let readiness = state.get_readiness().await;
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+pub(crate) fn configure<Backend: TcpBackendHandler + 'static>(
+    cfg: &mut web::ServiceConfig,
+    state: web::Data<HealthState<Backend>>,
+) {
+    cfg.app_data(state)
+        .route("/health", web::get().to(health))
+        .route("/ready", web::get().to(ready::<Backend>));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        sql_backend_handler::{tests::*, SqlBackendHandler},
+        sql_tables::DbConnection,
+    };
+    use actix_web::{http::StatusCode, test, App};
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+    use tokio::net::TcpListener;
+
+    async fn get<Backend: TcpBackendHandler + 'static>(
+        state: web::Data<HealthState<Backend>>,
+        path: &str,
+    ) -> (StatusCode, serde_json::Value) {
+        let app = test::init_service(App::new().configure(|cfg| configure(cfg, state))).await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri(path).to_request()).await;
+        let status = response.status();
+        (status, test::read_body_json(response).await)
+    }
+
+    async fn ldap_listener() -> (TcpListener, (String, u16)) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (listener, ("127.0.0.1".to_owned(), port))
+    }
+
+    fn handler(sql_pool: DbConnection) -> SqlBackendHandler {
+        SqlBackendHandler::new(get_default_config(), sql_pool)
+    }
+
+    #[actix_web::test]
+    async fn test_health() {
+        let (_listener, ldap_address) = ldap_listener().await;
+        // The process is healthy even if the database is not.
+        let state = web::Data::new(HealthState::new(
+            handler(get_in_memory_db().await),
+            ldap_address,
+            SmtpStatus::default(),
+        ));
+        assert_eq!(
+            get(state, "/health").await,
+            (StatusCode::OK, serde_json::json!({ "healthy": true }))
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_ready() {
+        let (_listener, ldap_address) = ldap_listener().await;
+        let state = web::Data::new(HealthState::new(
+            handler(get_initialized_db().await),
+            ldap_address,
+            SmtpStatus::default(),
+        ));
+        assert_eq!(
+            get(state, "/ready").await,
+            (
+                StatusCode::OK,
+                serde_json::json!({
+                    "ready": true,
+                    "components": { "database": true, "migrations": true, "ldap": true },
+                })
+            )
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_ready_with_smtp_failure() {
+        let (_listener, ldap_address) = ldap_listener().await;
+        let smtp_status = SmtpStatus::default();
+        let checked_at = chrono::Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        smtp_status.set(SmtpCheck {
+            reachable: false,
+            error: Some("Connection refused".to_owned()),
+            checked_at,
+        });
+        let state = web::Data::new(HealthState::new(
+            handler(get_initialized_db().await),
+            ldap_address,
+            smtp_status,
+        ));
+        // Still ready: the emails are not essential.
+        assert_eq!(
+            get(state, "/ready").await,
+            (
+                StatusCode::OK,
+                serde_json::json!({
+                    "ready": true,
+                    "components": { "database": true, "migrations": true, "ldap": true },
+                    "smtp": {
+                        "reachable": false,
+                        "error": "Connection refused",
+                        "checked_at": "2024-01-02T03:04:05Z",
+                    },
+                })
+            )
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_ready_replica() {
+        let (_listener, ldap_address) = ldap_listener().await;
+        let status = ReplicationStatus::new(&"https://primary".parse().unwrap());
+        let state = |sql_pool| {
+            web::Data::new(
+                HealthState::new(
+                    handler(sql_pool),
+                    ldap_address.clone(),
+                    SmtpStatus::default(),
+                )
+                .with_replication(Some(status.clone())),
+            )
+        };
+        // Not ready before the first synchronization.
+        status.record_failure("Connection refused".to_owned());
+        let (code, body) = get(state(get_initialized_db().await), "/ready").await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body["replication"],
+            serde_json::json!({
+                "primary_url": "https://primary/",
+                "last_sync_at": null,
+                "lag_secs": null,
+                "error": "Connection refused",
+            })
+        );
+        // Still ready when the primary goes away afterwards.
+        status.record_sync(chrono::Utc::now());
+        status.record_failure("Connection refused".to_owned());
+        let (code, body) = get(state(get_initialized_db().await), "/ready").await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["replication"]["lag_secs"], serde_json::json!(0));
+    }
+
+    #[actix_web::test]
+    async fn test_not_ready_without_migrations_or_ldap() {
+        let (listener, ldap_address) = ldap_listener().await;
+        drop(listener);
+        let sql_pool = get_in_memory_db().await;
+        crate::domain::sql_migrations::upgrade_to_v1(&sql_pool)
+            .await
+            .unwrap();
+        let state = web::Data::new(HealthState::new(
+            handler(sql_pool),
+            ldap_address,
+            SmtpStatus::default(),
+        ));
+        let (status, body) = get(state, "/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body["components"],
+            serde_json::json!({ "database": true, "migrations": false, "ldap": false })
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_not_ready_with_database_down() {
+        let (_listener, ldap_address) = ldap_listener().await;
+        let pool = sea_orm::sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        pool.close().await;
+        let sql_pool = sea_orm::SqlxSqliteConnector::from_sqlx_sqlite_pool(pool);
+        let state = web::Data::new(HealthState::new(
+            handler(sql_pool),
+            ldap_address,
+            SmtpStatus::default(),
+        ));
+        let (status, body) = get(state, "/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "ready": false,
+                "components": { "database": false, "migrations": false, "ldap": true },
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_readiness_is_cached() {
+        let (listener, ldap_address) = ldap_listener().await;
+        let state = HealthState::new(
+            handler(get_initialized_db().await),
+            ldap_address,
+            SmtpStatus::default(),
+        );
+        assert!(state.get_readiness().await.ready);
+        // The LDAP server going down is only noticed at the next check.
+        drop(listener);
+        assert!(state.get_readiness().await.ready);
+        *state.last_check.lock().await = None;
+        assert!(!state.get_readiness().await.ready);
+    }
+}
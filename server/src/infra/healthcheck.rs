@@ -1,19 +1,32 @@
-use crate::infra::{configuration::LdapsOptions, ldap_server::read_certificates};
+use crate::infra::{
+    configuration::{HttpTlsOptions, LdapsOptions},
+    tls::read_certificates,
+};
 use anyhow::{anyhow, bail, ensure, Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
 use futures_util::SinkExt;
 use ldap3_proto::{
     proto::{
-        LdapDerefAliases, LdapFilter, LdapMsg, LdapOp, LdapSearchRequest, LdapSearchResultEntry,
-        LdapSearchScope,
+        LdapBindCred, LdapBindRequest, LdapBindResponse, LdapDerefAliases, LdapFilter, LdapMsg,
+        LdapOp, LdapResultCode, LdapSearchRequest, LdapSearchResultEntry, LdapSearchScope,
     },
     LdapCodec,
 };
 use tokio::net::TcpStream;
 use tokio_rustls::TlsConnector as RustlsTlsConnector;
 use tokio_util::codec::{FramedRead, FramedWrite};
-use tracing::{debug, info, instrument};
+use tracing::{debug, info, instrument, warn};
+
+/// Credentials to bind with before the RootDSE search, instead of searching anonymously.
+pub struct LdapCredentials {
+    pub dn: String,
+    pub password: String,
+}
 
-async fn check_ldap_endpoint<Stream>(stream: Stream) -> Result<()>
+async fn check_ldap_endpoint<Stream>(
+    stream: Stream,
+    credentials: Option<&LdapCredentials>,
+) -> Result<()>
 where
     Stream: tokio::io::AsyncRead + tokio::io::AsyncWrite,
 {
@@ -22,6 +35,36 @@ where
     let mut requests = FramedRead::new(r, LdapCodec::default());
     let mut resp = FramedWrite::new(w, LdapCodec::default());
 
+    let no_answer = || anyhow!("No answer from LDAP server");
+    let invalid_answer = "Invalid answer from LDAP server";
+
+    if let Some(credentials) = credentials {
+        resp.send(LdapMsg {
+            msgid: 1,
+            op: LdapOp::BindRequest(LdapBindRequest {
+                dn: credentials.dn.clone(),
+                cred: LdapBindCred::Simple(credentials.password.clone()),
+            }),
+            ctrl: vec![],
+        })
+        .await?;
+        resp.flush().await?;
+        let msg = requests
+            .next()
+            .await
+            .ok_or_else(no_answer)?
+            .context(invalid_answer)?;
+        match msg.op {
+            LdapOp::BindResponse(LdapBindResponse { res, .. }) => ensure!(
+                res.code == LdapResultCode::Success,
+                "Could not bind as {}: {}",
+                credentials.dn,
+                res.message
+            ),
+            _ => bail!(invalid_answer),
+        }
+    }
+
     resp.send(LdapMsg {
         msgid: 0,
         op: LdapOp::SearchRequest(LdapSearchRequest {
@@ -39,9 +82,6 @@ where
     .await?;
     resp.flush().await?;
 
-    let no_answer = || anyhow!("No answer from LDAP server");
-    let invalid_answer = "Invalid answer from LDAP server";
-
     let msg = requests
         .next()
         .await
@@ -69,9 +109,13 @@ where
     Ok(())
 }
 
-#[instrument(level = "info", err)]
-pub async fn check_ldap(port: u16) -> Result<()> {
-    check_ldap_endpoint(TcpStream::connect(format!("localhost:{}", port)).await?).await
+#[instrument(skip(credentials), level = "info", err)]
+pub async fn check_ldap(port: u16, credentials: Option<&LdapCredentials>) -> Result<()> {
+    check_ldap_endpoint(
+        TcpStream::connect(format!("localhost:{}", port)).await?,
+        credentials,
+    )
+    .await
 }
 
 fn get_root_certificates() -> rustls::RootCertStore {
@@ -86,12 +130,13 @@ fn get_root_certificates() -> rustls::RootCertStore {
     root_store
 }
 
-fn get_tls_connector(ldaps_options: &LdapsOptions) -> Result<RustlsTlsConnector> {
+/// Client config that only accepts the certificate from the config file.
+fn get_client_config(cert_file: &str, key_file: &str) -> Result<rustls::ClientConfig> {
     let mut client_config = rustls::ClientConfig::builder()
         .with_safe_defaults()
         .with_root_certificates(get_root_certificates())
         .with_no_client_auth();
-    let (certs, _private_key) = read_certificates(ldaps_options)?;
+    let (certs, _private_key) = read_certificates(cert_file, key_file)?;
     // Check that the server cert is the one in the config file.
     struct CertificateVerifier {
         certificate: rustls::Certificate,
@@ -121,13 +166,21 @@ fn get_tls_connector(ldaps_options: &LdapsOptions) -> Result<RustlsTlsConnector>
     };
     dangerous_config.set_certificate_verifier(std::sync::Arc::new(CertificateVerifier {
         certificate: certs.first().expect("empty certificate chain").clone(),
-        certificate_path: ldaps_options.cert_file.clone(),
+        certificate_path: cert_file.to_owned(),
     }));
+    Ok(client_config)
+}
+
+pub(crate) fn get_tls_connector(ldaps_options: &LdapsOptions) -> Result<RustlsTlsConnector> {
+    let client_config = get_client_config(&ldaps_options.cert_file, &ldaps_options.key_file)?;
     Ok(std::sync::Arc::new(client_config).into())
 }
 
 #[instrument(skip_all, level = "info", err, fields(port = %ldaps_options.port))]
-pub async fn check_ldaps(ldaps_options: &LdapsOptions) -> Result<()> {
+pub async fn check_ldaps(
+    ldaps_options: &LdapsOptions,
+    credentials: Option<&LdapCredentials>,
+) -> Result<()> {
     if !ldaps_options.enabled {
         info!("LDAPS not enabled");
         return Ok(());
@@ -146,15 +199,86 @@ pub async fn check_ldaps(ldaps_options: &LdapsOptions) -> Result<()> {
             )
             .await
             .context("while connecting TLS")?,
+        credentials,
     )
     .await
 }
 
-#[instrument(level = "info", err)]
-pub async fn check_api(port: u16) -> Result<()> {
-    reqwest::get(format!("http://localhost:{}/health", port))
-        .await?
-        .error_for_status()?;
+fn check_expiry(
+    cert_file: &str,
+    not_after: DateTime<Utc>,
+    now: DateTime<Utc>,
+    warning_days: u32,
+) -> Result<()> {
+    ensure!(
+        not_after > now,
+        "The certificate {} expired on {}",
+        cert_file,
+        not_after
+    );
+    let days_left = (not_after - now).num_days();
+    if days_left < i64::from(warning_days) {
+        warn!(
+            "The certificate {} expires in {} days, on {}",
+            cert_file, days_left, not_after
+        );
+    }
+    Ok(())
+}
+
+/// Fails if the certificate has expired, and warns if it expires within `warning_days`.
+#[instrument(skip(key_file), level = "info", err)]
+pub fn check_certificate_expiry(cert_file: &str, key_file: &str, warning_days: u32) -> Result<()> {
+    let (certs, _private_key) = read_certificates(cert_file, key_file)?;
+    let certificate = certs.first().expect("empty certificate chain");
+    let (_, certificate) = x509_parser::parse_x509_certificate(&certificate.0)
+        .map_err(|e| anyhow!("Invalid certificate in {}: {}", cert_file, e))?;
+    let not_after = Utc
+        .timestamp_opt(certificate.validity().not_after.timestamp(), 0)
+        .single()
+        .ok_or_else(|| anyhow!("Invalid expiration date in {}", cert_file))?;
+    check_expiry(cert_file, not_after, Utc::now(), warning_days)
+}
+
+#[instrument(skip(tls_options), level = "info", err)]
+pub async fn check_api(port: u16, tls_options: &HttpTlsOptions) -> Result<()> {
+    if tls_options.enabled {
+        let client_config = get_client_config(&tls_options.cert_file, &tls_options.key_file)
+            .context("while preparing the tls connection")?;
+        let port = tls_options.https_port.unwrap_or(port);
+        reqwest::Client::builder()
+            .use_preconfigured_tls(client_config)
+            .build()?
+            .get(format!("https://localhost:{}/health", port))
+            .send()
+            .await?
+            .error_for_status()?;
+    } else {
+        reqwest::get(format!("http://localhost:{}/health", port))
+            .await?
+            .error_for_status()?;
+    }
     info!("Success");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_check_expiry() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        check_expiry("cert.pem", now + Duration::days(90), now, 14).unwrap();
+        // Expiring soon is only a warning.
+        check_expiry("cert.pem", now + Duration::days(3), now, 14).unwrap();
+        assert_eq!(
+            check_expiry("cert.pem", now - Duration::days(1), now, 14)
+                .unwrap_err()
+                .to_string(),
+            "The certificate cert.pem expired on 2023-11-13 22:13:20 UTC"
+        );
+    }
+}
@@ -0,0 +1,48 @@
+//! Invitation emails: a new user gets a single-use link to choose their password, through the
+//! same flow as a password reset.
+
+use crate::{
+    domain::types::User,
+    infra::{
+        configuration::MailOptions, mail, mail_queue::MailQueue, mail_templates::MailTemplates,
+        tcp_backend_handler::TcpBackendHandler,
+    },
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::instrument;
+
+#[async_trait]
+pub trait InvitationSender: Send + Sync {
+    /// Emails an invitation link to the user. The links sent previously are invalidated.
+    async fn send_invitation(&self, user: &User) -> Result<()>;
+}
+
+pub struct MailInvitationSender<Backend> {
+    pub backend_handler: Backend,
+    pub server_url: url::Url,
+    pub mail_options: MailOptions,
+    pub mail_templates: Arc<MailTemplates>,
+    pub mail_queue: MailQueue,
+}
+
+#[async_trait]
+impl<Backend: TcpBackendHandler + Send> InvitationSender for MailInvitationSender<Backend> {
+    #[instrument(skip_all, level = "debug", fields(user_id = %user.user_id), err)]
+    async fn send_invitation(&self, user: &User) -> Result<()> {
+        let validity = chrono::Duration::days(self.mail_options.invitation_validity_days.into());
+        let token = self
+            .backend_handler
+            .start_invitation(&user.user_id, validity)
+            .await?;
+        mail::send_invitation_email(
+            user,
+            &token,
+            &self.server_url,
+            &self.mail_options,
+            &self.mail_templates,
+            &self.mail_queue,
+        )
+    }
+}
@@ -0,0 +1,181 @@
+//! `lldap rotate_jwt_secret`: replaces the secret signing the session tokens.
+//!
+//! The refresh tokens are random and stored in the database, so they survive the rotation: the
+//! users get a token signed with the new secret at their next refresh. With `jwt_secret_previous`,
+//! the tokens signed with the old secret also stay valid until they expire, a day later.
+
+use crate::domain::{model, sql_tables::DbConnection};
+use anyhow::{Context, Result};
+use rand::{distributions::Alphanumeric, Rng};
+use sea_orm::EntityTrait;
+use secstr::SecUtf8;
+use std::path::Path;
+
+const SECRET_LENGTH: usize = 64;
+
+/// Where the secrets can be set in the environment. When they are, the configuration file
+/// doesn't matter.
+pub const ENV_VARIABLES: [&str; 2] = ["LLDAP_JWT_SECRET", "LLDAP_JWT_SECRET_FILE"];
+
+pub fn generate_secret() -> SecUtf8 {
+    SecUtf8::from(
+        rand::rngs::OsRng
+            .sample_iter(&Alphanumeric)
+            .take(SECRET_LENGTH)
+            .map(char::from)
+            .collect::<String>(),
+    )
+}
+
+pub fn is_set_by_env() -> bool {
+    ENV_VARIABLES
+        .iter()
+        .any(|name| std::env::var_os(name).is_some())
+}
+
+/// Whether the line is `key = ...`.
+fn assigns(line: &str, key: &str) -> bool {
+    line.trim_start()
+        .strip_prefix(key)
+        .is_some_and(|rest| rest.trim_start().starts_with('='))
+}
+
+/// Sets `key` at the top level of a TOML file and keeps the rest of the file, comments included.
+/// The value replaces the existing line, or else the commented out one (as in the generated
+/// configurations), or else goes before the first table.
+fn set_top_level_key(contents: &str, key: &str, value: &SecUtf8) -> String {
+    // The JSON escapes are valid in the TOML basic strings.
+    let line = format!(
+        "{} = {}",
+        key,
+        serde_json::Value::String(value.unsecure().to_owned())
+    );
+    let mut lines = contents.lines().collect::<Vec<_>>();
+    let top_level_end = lines
+        .iter()
+        .position(|l| l.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+    let top_level = &lines[..top_level_end];
+    let position = top_level.iter().position(|l| assigns(l, key)).or_else(|| {
+        top_level.iter().position(|l| {
+            l.trim_start()
+                .strip_prefix('#')
+                .is_some_and(|l| assigns(l, key))
+        })
+    });
+    match position {
+        Some(i) => lines[i] = &line,
+        None if top_level_end < lines.len() => {
+            lines.insert(top_level_end, "");
+            lines.insert(top_level_end, &line);
+        }
+        None => lines.push(&line),
+    }
+    let mut output = lines.join("\n");
+    output.push('\n');
+    output
+}
+
+/// Writes the new secret to the configuration file, and the old one to `jwt_secret_previous`.
+pub fn write_config_file(path: &Path, secret: &SecUtf8, previous: Option<&SecUtf8>) -> Result<()> {
+    let contents = std::fs::read_to_string(path).context(format!(
+        "Could not read the configuration `{}`",
+        path.display()
+    ))?;
+    let mut contents = set_top_level_key(&contents, "jwt_secret", secret);
+    if let Some(previous) = previous {
+        contents = set_top_level_key(&contents, "jwt_secret_previous", previous);
+    }
+    std::fs::write(path, contents).context(format!(
+        "Could not write the configuration `{}`",
+        path.display()
+    ))
+}
+
+/// Logs out everyone: deletes the refresh tokens and the issued tokens. Returns how many of each
+/// were deleted.
+pub async fn revoke_all_sessions(sql_pool: &DbConnection) -> Result<(u64, u64)> {
+    let refresh_tokens = model::JwtRefreshStorage::delete_many()
+        .exec(sql_pool)
+        .await?
+        .rows_affected;
+    let tokens = model::JwtStorage::delete_many()
+        .exec(sql_pool)
+        .await?
+        .rows_affected;
+    Ok((refresh_tokens, tokens))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::{
+            sql_backend_handler::{tests::*, SqlBackendHandler},
+            types::UserId,
+        },
+        infra::tcp_backend_handler::TcpBackendHandler,
+    };
+    use pretty_assertions::assert_eq;
+
+    fn secret(value: &str) -> SecUtf8 {
+        SecUtf8::from(value)
+    }
+
+    #[test]
+    fn test_set_top_level_key() {
+        assert_eq!(
+            set_top_level_key(
+                "## The secret.\njwt_secret = \"old\"\njwt_secret_previous = \"older\"\n[smtp_options]\njwt_secret = \"not this one\"\n",
+                "jwt_secret",
+                &secret("new")
+            ),
+            "## The secret.\njwt_secret = \"new\"\njwt_secret_previous = \"older\"\n[smtp_options]\njwt_secret = \"not this one\"\n"
+        );
+        assert_eq!(
+            set_top_level_key(
+                "## The secret.\n#jwt_secret = \"REPLACE_WITH_RANDOM\"\n",
+                "jwt_secret",
+                &secret("new")
+            ),
+            "## The secret.\njwt_secret = \"new\"\n"
+        );
+        assert_eq!(
+            set_top_level_key(
+                "http_port = 17170\n[smtp_options]\nport = 25\n",
+                "jwt_secret_previous",
+                &secret("quote \" and \\")
+            ),
+            "http_port = 17170\njwt_secret_previous = \"quote \\\" and \\\\\"\n\n[smtp_options]\nport = 25\n"
+        );
+        assert_eq!(
+            set_top_level_key("", "jwt_secret", &secret("new")),
+            "jwt_secret = \"new\"\n"
+        );
+    }
+
+    #[test]
+    fn test_generate_secret() {
+        let secret = generate_secret();
+        assert_eq!(secret.unsecure().len(), SECRET_LENGTH);
+        assert_ne!(secret, generate_secret());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_all_sessions() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool.clone());
+        insert_user_no_password(&handler, "bob").await;
+        let bob = UserId::new("bob");
+        handler
+            .create_refresh_token(&bob, chrono::Duration::days(30))
+            .await
+            .unwrap();
+        handler
+            .register_jwt(&bob, 42, chrono::Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(revoke_all_sessions(&sql_pool).await.unwrap(), (1, 1));
+        assert_eq!(revoke_all_sessions(&sql_pool).await.unwrap(), (0, 0));
+    }
+}
@@ -3,6 +3,7 @@ use sea_orm::{
     ConnectionTrait, DeriveIden,
 };
 
+use crate::domain::sql_migrations::utc_timestamp_column;
 pub use crate::domain::{sql_migrations::Users, sql_tables::DbConnection};
 
 /// Contains the refresh tokens for a given user.
@@ -24,15 +25,6 @@ pub enum JwtStorage {
     Blacklisted,
 }
 
-/// Contains the temporary tokens to reset the password, sent by email.
-#[derive(DeriveIden)]
-pub enum PasswordResetTokens {
-    Table,
-    Token,
-    UserId,
-    ExpiryDate,
-}
-
 /// This needs to be initialized after the domain tables are.
 pub async fn init_table(pool: &DbConnection) -> std::result::Result<(), sea_orm::DbErr> {
     let builder = pool.get_database_backend();
@@ -53,11 +45,7 @@ pub async fn init_table(pool: &DbConnection) -> std::result::Result<(), sea_orm:
                         .string_len(255)
                         .not_null(),
                 )
-                .col(
-                    ColumnDef::new(JwtRefreshStorage::ExpiryDate)
-                        .date_time()
-                        .not_null(),
-                )
+                .col(utc_timestamp_column(JwtRefreshStorage::ExpiryDate, builder).not_null())
                 .foreign_key(
                     ForeignKey::create()
                         .name("JwtRefreshStorageUserForeignKey")
@@ -86,11 +74,7 @@ pub async fn init_table(pool: &DbConnection) -> std::result::Result<(), sea_orm:
                         .string_len(255)
                         .not_null(),
                 )
-                .col(
-                    ColumnDef::new(JwtStorage::ExpiryDate)
-                        .date_time()
-                        .not_null(),
-                )
+                .col(utc_timestamp_column(JwtStorage::ExpiryDate, builder).not_null())
                 .col(
                     ColumnDef::new(JwtStorage::Blacklisted)
                         .boolean()
@@ -109,38 +93,5 @@ pub async fn init_table(pool: &DbConnection) -> std::result::Result<(), sea_orm:
     )
     .await?;
 
-    pool.execute(
-        builder.build(
-            Table::create()
-                .table(PasswordResetTokens::Table)
-                .if_not_exists()
-                .col(
-                    ColumnDef::new(PasswordResetTokens::Token)
-                        .string_len(255)
-                        .not_null()
-                        .primary_key(),
-                )
-                .col(
-                    ColumnDef::new(PasswordResetTokens::UserId)
-                        .string_len(255)
-                        .not_null(),
-                )
-                .col(
-                    ColumnDef::new(PasswordResetTokens::ExpiryDate)
-                        .date_time()
-                        .not_null(),
-                )
-                .foreign_key(
-                    ForeignKey::create()
-                        .name("PasswordResetTokensUserForeignKey")
-                        .from(PasswordResetTokens::Table, PasswordResetTokens::UserId)
-                        .to(Users::Table, Users::UserId)
-                        .on_delete(ForeignKeyAction::Cascade)
-                        .on_update(ForeignKeyAction::Cascade),
-                ),
-        ),
-    )
-    .await?;
-
     Ok(())
 }
@@ -1,26 +1,39 @@
 use crate::{
     domain::{
+        error::DomainError,
         handler::{
-            BackendHandler, BindRequest, CreateUserRequest, LoginHandler, ReadSchemaBackendHandler,
+            BackendHandler, BindRequest, GroupRequestFilter, LoginHandler,
+            ReadSchemaBackendHandler, UpdateUserRequest, UserRequestFilter,
         },
         ldap::{
-            error::{LdapError, LdapResult},
-            group::{convert_groups_to_ldap_op, get_groups_list},
-            user::{convert_users_to_ldap_op, get_user_list},
+            dn::DistinguishedName,
+            error::{backend_error_code, LdapError, LdapResult},
+            group::{convert_group_add, convert_groups_to_ldap_op, get_groups_list},
+            user::{
+                convert_user_add, convert_user_modifications, convert_users_to_ldap_op,
+                get_user_list, requests_photos, UserPages,
+            },
             utils::{
-                get_user_id_from_distinguished_name, is_subtree, parse_distinguished_name, LdapInfo,
+                get_group_id_from_distinguished_name, get_user_id_from_distinguished_name,
+                is_subtree, parse_distinguished_name, serialize_distinguished_name, LdapInfo,
             },
         },
         opaque_handler::OpaqueHandler,
         schema::PublicSchema,
-        types::{AttributeName, Email, Group, JpegPhoto, UserAndGroups, UserId},
+        types::{AttributeName, Group, GroupName, UserId},
     },
-    infra::access_control::{
-        AccessControlledBackendHandler, AdminBackendHandler, UserAndGroupListerBackendHandler,
-        UserReadableBackendHandler, ValidationResults,
+    infra::{
+        access_control::{
+            AccessControlledBackendHandler, AdminBackendHandler, ReadonlyBackendHandler,
+            UserAndGroupListerBackendHandler, UserReadableBackendHandler,
+            UserWriteableBackendHandler, ValidationResults,
+        },
+        configuration::LoginIdentifier,
     },
 };
 use anyhow::Result;
+use async_trait::async_trait;
+use itertools::Itertools;
 use ldap3_proto::proto::{
     LdapAddRequest, LdapBindCred, LdapBindRequest, LdapBindResponse, LdapCompareRequest,
     LdapDerefAliases, LdapExtendedRequest, LdapExtendedResponse, LdapFilter, LdapModify,
@@ -28,8 +41,7 @@ use ldap3_proto::proto::{
     LdapResult as LdapResultOp, LdapResultCode, LdapSearchRequest, LdapSearchResultEntry,
     LdapSearchScope,
 };
-use std::collections::HashMap;
-use tracing::{debug, instrument, warn};
+use tracing::{debug, debug_span, info, instrument, warn, Instrument};
 
 #[derive(Debug)]
 enum SearchScope {
@@ -44,12 +56,79 @@ enum SearchScope {
     Invalid,
 }
 
-enum InternalSearchResults {
-    UsersAndGroups(Vec<UserAndGroups>, Vec<Group>),
+enum InternalSearchResults<'a, Backend> {
+    /// The matching OUs, then the users and the groups.
+    UsersAndGroups(Vec<LdapOp>, Option<UserPages<'a, Backend>>, Vec<Group>),
     Raw(Vec<LdapOp>),
     Empty,
 }
 
+/// How many users a search fetches at once, see `UserPages`.
+const SEARCH_PAGE_SIZE: u64 = 500;
+
+const READ_ONLY_REPLICA: &str =
+    "Read-only replica: the changes have to be made on the primary server";
+
+/// The StartTLS extended operation (RFC 4511, section 4.14).
+pub const START_TLS_OID: &str = "1.3.6.1.4.1.1466.20037";
+
+/// Whether the connection is encrypted, or can be with StartTLS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TlsState {
+    /// Plain connection, without StartTLS.
+    #[default]
+    Unavailable,
+    /// Plain connection, that the client can upgrade with StartTLS.
+    Available,
+    /// StartTLS was accepted: the connection has to be upgraded before the next request.
+    Upgrading,
+    /// Encrypted, with LDAPS or StartTLS.
+    Established,
+}
+
+/// Where the responses to a request go, as they are produced: a search sends the entries of a
+/// page of users before fetching the next one.
+#[async_trait(?Send)]
+pub trait ResponseSink {
+    /// Returns false if the response could not be sent, e.g. the client disconnected: the rest
+    /// of the request is then skipped.
+    async fn send(&mut self, response: LdapOp) -> bool;
+}
+
+#[async_trait(?Send)]
+impl ResponseSink for Vec<LdapOp> {
+    async fn send(&mut self, response: LdapOp) -> bool {
+        self.push(response);
+        true
+    }
+}
+
+/// Sends the entries of a search, within its size limit.
+struct SearchEntrySender<'s, Sink> {
+    sink: &'s mut Sink,
+    size_limit: i32,
+    sent: i32,
+}
+
+impl<Sink: ResponseSink> SearchEntrySender<'_, Sink> {
+    async fn send(&mut self, entry: LdapOp) -> LdapResult<()> {
+        if self.size_limit > 0 && self.sent >= self.size_limit {
+            return Err(LdapError {
+                code: LdapResultCode::SizeLimitExceeded,
+                message: format!("More than {} entries match the search", self.size_limit),
+            });
+        }
+        if !self.sink.send(entry).await {
+            return Err(LdapError {
+                code: LdapResultCode::Other,
+                message: "Could not send the search results".to_string(),
+            });
+        }
+        self.sent += 1;
+        Ok(())
+    }
+}
+
 fn get_search_scope(
     base_dn: &[(String, String)],
     dn_parts: &[(String, String)],
@@ -95,6 +174,44 @@ fn get_search_scope(
     }
 }
 
+/// The OU entries only have an objectClass.
+fn make_ou_entry(dn: String, attributes: &[String]) -> LdapOp {
+    let with_object_class = attributes.is_empty()
+        || attributes
+            .iter()
+            .any(|a| a == "*" || a.eq_ignore_ascii_case("objectclass"));
+    LdapOp::SearchResultEntry(LdapSearchResultEntry {
+        dn,
+        attributes: with_object_class
+            .then(|| LdapPartialAttribute {
+                atype: "objectClass".to_owned(),
+                vals: vec![b"top".to_vec(), b"organizationalUnit".to_vec()],
+            })
+            .into_iter()
+            .collect(),
+    })
+}
+
+fn ou_matches_filter(filter: &LdapFilter) -> bool {
+    match filter {
+        LdapFilter::And(filters) => filters.iter().all(ou_matches_filter),
+        LdapFilter::Or(filters) => filters.iter().any(ou_matches_filter),
+        LdapFilter::Not(filter) => !ou_matches_filter(filter),
+        LdapFilter::Present(attribute) => matches!(
+            attribute.to_ascii_lowercase().as_str(),
+            "objectclass" | "dn" | "distinguishedname"
+        ),
+        LdapFilter::Equality(attribute, value) => {
+            attribute.eq_ignore_ascii_case("objectclass")
+                && matches!(
+                    value.to_ascii_lowercase().as_str(),
+                    "top" | "organizationalunit"
+                )
+        }
+        _ => false,
+    }
+}
+
 fn make_search_request<S: Into<String>>(
     base: &str,
     filter: LdapFilter,
@@ -134,6 +251,59 @@ fn make_add_error(code: LdapResultCode, message: String) -> LdapOp {
     })
 }
 
+fn make_del_response(code: LdapResultCode, message: String) -> LdapOp {
+    LdapOp::DelResponse(LdapResultOp {
+        code,
+        matcheddn: "".to_string(),
+        message,
+        referral: vec![],
+    })
+}
+
+/// The result code of a failed write to the backend.
+fn write_error_code(error: &DomainError) -> LdapResultCode {
+    match error {
+        DomainError::ValidationError(_) | DomainError::EmailAlreadyInUse(_) => {
+            LdapResultCode::ConstraintViolation
+        }
+        DomainError::EntityNotFound(_) => LdapResultCode::NoSuchObject,
+        DomainError::LastAdmin(_) | DomainError::LastGroupMember(..) => {
+            LdapResultCode::UnwillingToPerform
+        }
+        _ => backend_error_code(error, LdapResultCode::OperationsError),
+    }
+}
+
+/// The name of the group in the DN, as written: the normalized DN is lowercased.
+fn group_name_as_written(dn: &str) -> LdapResult<GroupName> {
+    Ok(GroupName::from(
+        DistinguishedName::parse(dn)?.0[0][0].value.as_str(),
+    ))
+}
+
+/// The group with exactly this name. The names that only differ by their case are not taken for
+/// each other, and several groups with the same name are an error rather than an arbitrary pick.
+async fn find_group_by_exact_name(
+    backend_handler: &impl ReadonlyBackendHandler,
+    name: &GroupName,
+) -> LdapResult<Option<Group>> {
+    let mut groups = backend_handler
+        .list_groups(Some(GroupRequestFilter::DisplayName(name.clone())))
+        .await
+        .map_err(|e| LdapError {
+            code: backend_error_code(&e, LdapResultCode::OperationsError),
+            message: format!("Could not get group `{}`: {:#}", name, e),
+        })?;
+    groups.retain(|g| g.display_name.as_str() == name.as_str());
+    if groups.len() > 1 {
+        return Err(LdapError {
+            code: LdapResultCode::OperationsError,
+            message: format!("{} groups are named `{}`", groups.len(), name),
+        });
+    }
+    Ok(groups.pop())
+}
+
 fn make_extended_response(code: LdapResultCode, message: String) -> LdapOp {
     LdapOp::ExtendedResponse(LdapExtendedResponse {
         res: LdapResultOp {
@@ -156,7 +326,12 @@ fn make_modify_response(code: LdapResultCode, message: String) -> LdapOp {
     })
 }
 
-fn root_dse_response(base_dn: &str) -> LdapOp {
+fn root_dse_response(base_dn: &str, start_tls: bool) -> LdapOp {
+    // Password modification extension.
+    let mut extensions = vec![b"1.3.6.1.4.1.4203.1.11.1".to_vec()];
+    if start_tls {
+        extensions.push(START_TLS_OID.as_bytes().to_vec());
+    }
     LdapOp::SearchResultEntry(LdapSearchResultEntry {
         dn: "".to_string(),
         attributes: vec![
@@ -180,8 +355,7 @@ fn root_dse_response(base_dn: &str) -> LdapOp {
             },
             LdapPartialAttribute {
                 atype: "supportedExtension".to_string(),
-                // Password modification extension.
-                vals: vec![b"1.3.6.1.4.1.4203.1.11.1".to_vec()],
+                vals: extensions,
             },
             LdapPartialAttribute {
                 atype: "supportedControl".to_string(),
@@ -212,6 +386,9 @@ pub struct LdapHandler<Backend> {
     user_info: Option<ValidationResults>,
     backend_handler: AccessControlledBackendHandler<Backend>,
     ldap_info: LdapInfo,
+    /// On a replica, the writes are refused, see `with_read_only`.
+    read_only: bool,
+    tls: TlsState,
 }
 
 impl<Backend: LoginHandler> LdapHandler<Backend> {
@@ -229,28 +406,66 @@ impl<Backend: OpaqueHandler> LdapHandler<Backend> {
 impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend> {
     pub fn new(
         backend_handler: AccessControlledBackendHandler<Backend>,
-        mut ldap_base_dn: String,
+        ldap_base_dn: String,
         ignored_user_attributes: Vec<AttributeName>,
         ignored_group_attributes: Vec<AttributeName>,
     ) -> Self {
-        ldap_base_dn.make_ascii_lowercase();
+        let base_dn = parse_distinguished_name(&ldap_base_dn).unwrap_or_else(|_| {
+            panic!(
+                "Invalid value for ldap_base_dn in configuration: {}",
+                ldap_base_dn
+            )
+        });
         Self {
             user_info: None,
             backend_handler,
             ldap_info: LdapInfo {
-                base_dn: parse_distinguished_name(&ldap_base_dn).unwrap_or_else(|_| {
-                    panic!(
-                        "Invalid value for ldap_base_dn in configuration: {}",
-                        ldap_base_dn
-                    )
-                }),
-                base_dn_str: ldap_base_dn,
+                base_dn_str: serialize_distinguished_name(&base_dn),
+                base_dn,
                 ignored_user_attributes,
                 ignored_group_attributes,
+                search_page_size: SEARCH_PAGE_SIZE,
+                host_wildcard: false,
+                login_identifier: LoginIdentifier::UserId,
             },
+            read_only: false,
+            tls: TlsState::Unavailable,
         }
     }
 
+    pub fn with_host_wildcard(mut self, host_wildcard: bool) -> Self {
+        self.ldap_info.host_wildcard = host_wildcard;
+        self
+    }
+
+    /// With `LoginIdentifier::Email`, the users are served with their email as uid, and can
+    /// bind with it.
+    pub fn with_login_identifier(mut self, login_identifier: LoginIdentifier) -> Self {
+        self.ldap_info.login_identifier = login_identifier;
+        self
+    }
+
+    /// Refuses the add, modify and password modify requests, for a replica: the changes have
+    /// to be made on the primary.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn with_tls(mut self, tls: TlsState) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    pub fn tls_state(&self) -> TlsState {
+        self.tls
+    }
+
+    /// After the TLS handshake that followed a StartTLS request.
+    pub fn set_tls_established(&mut self) {
+        self.tls = TlsState::Established;
+    }
+
     #[cfg(test)]
     pub fn new_for_tests(backend_handler: Backend, ldap_base_dn: &str) -> Self {
         Self::new(
@@ -264,7 +479,7 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
     #[instrument(skip_all, level = "debug", fields(dn = %request.dn))]
     pub async fn do_bind(&mut self, request: &LdapBindRequest) -> (LdapResultCode, String) {
         let user_id = match get_user_id_from_distinguished_name(
-            &request.dn.to_ascii_lowercase(),
+            &request.dn,
             &self.ldap_info.base_dn,
             &self.ldap_info.base_dn_str,
         ) {
@@ -282,12 +497,12 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         match self
             .get_login_handler()
             .bind(BindRequest {
-                name: user_id.clone(),
+                name: user_id,
                 password: password.clone(),
             })
             .await
         {
-            Ok(()) => {
+            Ok(user_id) => {
                 self.user_info = self
                     .backend_handler
                     .get_permissions_for_user(user_id)
@@ -296,6 +511,7 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                 debug!("Success!");
                 (LdapResultCode::Success, "".to_string())
             }
+            Err(DomainError::ServerBusy(e)) => (LdapResultCode::Busy, e),
             Err(_) => (LdapResultCode::InvalidCredentials, "".to_string()),
         }
     }
@@ -351,7 +567,7 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                             .get_user_groups(&uid)
                             .await
                             .map_err(|e| LdapError {
-                                code: LdapResultCode::OperationsError,
+                                code: backend_error_code(&e, LdapResultCode::OperationsError),
                                 message: format!(
                                     "Internal error while requesting user's groups: {:#?}",
                                     e
@@ -395,7 +611,36 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         }
     }
 
+    /// Accepts the StartTLS request if it is available: the server upgrades the connection after
+    /// sending the response.
+    fn do_start_tls(&mut self) -> LdapOp {
+        let (code, message) = match self.tls {
+            TlsState::Unavailable => (LdapResultCode::ProtocolError, "StartTLS is not enabled"),
+            TlsState::Upgrading | TlsState::Established => (
+                LdapResultCode::OperationsError,
+                "TLS is already established",
+            ),
+            TlsState::Available => {
+                self.tls = TlsState::Upgrading;
+                (LdapResultCode::Success, "")
+            }
+        };
+        LdapOp::ExtendedResponse(LdapExtendedResponse {
+            res: LdapResultOp {
+                code,
+                matcheddn: "".to_string(),
+                message: message.to_string(),
+                referral: vec![],
+            },
+            name: Some(START_TLS_OID.to_string()),
+            value: None,
+        })
+    }
+
     async fn do_extended_request(&mut self, request: &LdapExtendedRequest) -> Vec<LdapOp> {
+        if request.name == START_TLS_OID {
+            return vec![self.do_start_tls()];
+        }
         match LdapPasswordModifyRequest::try_from(request) {
             Ok(password_request) => self
                 .do_password_modification(&password_request)
@@ -408,50 +653,131 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         }
     }
 
-    async fn handle_modify_change(
-        &mut self,
-        user_id: UserId,
+    /// Checks the changes of the password in a modify request, allowed by the same policy as the
+    /// password modify extended operation, and returns the new password. It is either replaced or
+    /// added, possibly after deleting the current one: it can't be read, nor removed.
+    fn check_password_changes<'c>(
+        &self,
+        user_id: &UserId,
         credentials: &ValidationResults,
         user_is_admin: bool,
-        change: &LdapModify,
-    ) -> LdapResult<()> {
-        if change.modification.atype.to_ascii_lowercase() != "userpassword"
-            || change.operation != LdapModifyType::Replace
-        {
-            return Err(LdapError {
-                code: LdapResultCode::UnwillingToPerform,
-                message: format!(
-                    r#"Unsupported operation: `{:?}` for `{}`"#,
-                    change.operation, change.modification.atype
-                ),
-            });
-        }
-        if !credentials.can_change_password(&user_id, user_is_admin) {
+        changes: &[&'c LdapModify],
+    ) -> LdapResult<&'c [u8]> {
+        if !credentials.can_change_password(user_id, user_is_admin) {
             return Err(LdapError {
                 code: LdapResultCode::InsufficentAccessRights,
                 message: format!(
                     r#"User `{}` cannot modify the password of user `{}`"#,
-                    &credentials.user, &user_id
+                    &credentials.user, user_id
                 ),
             });
         }
-        if let [value] = &change.modification.vals.as_slice() {
-            self.change_password(self.get_opaque_handler(), user_id, value)
-                .await
-                .map_err(|e| LdapError {
-                    code: LdapResultCode::Other,
-                    message: format!("Error while changing the password: {:#?}", e),
-                })?;
-        } else {
-            return Err(LdapError {
-                code: LdapResultCode::InvalidAttributeSyntax,
+        let mut password = None;
+        for change in changes {
+            if change.operation == LdapModifyType::Delete {
+                password = None;
+                continue;
+            }
+            if change.operation != LdapModifyType::Replace
+                && change.operation != LdapModifyType::Add
+            {
+                return Err(LdapError {
+                    code: LdapResultCode::UnwillingToPerform,
+                    message: format!(
+                        r#"Unsupported operation: `{:?}` for `{}`"#,
+                        change.operation, change.modification.atype
+                    ),
+                });
+            }
+            match change.modification.vals.as_slice() {
+                [value] => password = Some(value.as_slice()),
+                _ => {
+                    return Err(LdapError {
+                        code: LdapResultCode::InvalidAttributeSyntax,
+                        message: format!(
+                            r#"Wrong number of values for password attribute: {}"#,
+                            change.modification.vals.len()
+                        ),
+                    })
+                }
+            }
+        }
+        password.ok_or_else(|| LdapError {
+            code: LdapResultCode::UnwillingToPerform,
+            message: "The password cannot be removed, only replaced".to_string(),
+        })
+    }
+
+    /// Whether the target of a modification is an admin: the password managers cannot modify
+    /// them. The users who cannot see the target cannot modify it anyway.
+    async fn is_admin_user(
+        &self,
+        credentials: &ValidationResults,
+        user_id: &UserId,
+    ) -> LdapResult<bool> {
+        let Some(backend_handler) = self
+            .backend_handler
+            .get_readable_handler(credentials, user_id)
+        else {
+            return Ok(false);
+        };
+        Ok(backend_handler
+            .get_user_groups(user_id)
+            .await
+            .map_err(|e| LdapError {
+                code: backend_error_code(&e, LdapResultCode::OperationsError),
+                message: format!("Internal error while requesting user's groups: {:#?}", e),
+            })?
+            .iter()
+            .any(|g| g.display_name == "lldap_admin".into()))
+    }
+
+    /// Checks the changes of the other attributes against `can_write_user_attribute`, and
+    /// returns the update of the user.
+    async fn get_user_update(
+        &self,
+        user_id: &UserId,
+        credentials: &ValidationResults,
+        user_is_admin: bool,
+        changes: &[&LdapModify],
+    ) -> LdapResult<UpdateUserRequest> {
+        let backend_handler = self
+            .backend_handler
+            .get_user_attribute_writeable_handler(credentials, user_id, user_is_admin)
+            .ok_or_else(|| LdapError {
+                code: LdapResultCode::InsufficentAccessRights,
                 message: format!(
-                    r#"Wrong number of values for password attribute: {}"#,
-                    change.modification.vals.len()
+                    r#"User `{}` cannot modify the attributes of user `{}`"#,
+                    &credentials.user, user_id
                 ),
-            });
-        }
-        Ok(())
+            })?;
+        let user = backend_handler
+            .get_user_details(user_id)
+            .await
+            .map_err(|e| LdapError {
+                code: match e {
+                    DomainError::EntityNotFound(_) => LdapResultCode::NoSuchObject,
+                    _ => backend_error_code(&e, LdapResultCode::OperationsError),
+                },
+                message: format!("Could not get user `{}`: {:#}", user_id, e),
+            })?;
+        let schema = UserReadableBackendHandler::get_schema(backend_handler)
+            .await
+            .map_err(|e| LdapError {
+                code: backend_error_code(&e, LdapResultCode::OperationsError),
+                message: format!("Unable to get schema: {:#}", e),
+            })?;
+        // The email changes of the non-admins are verified by mail, which LDAP cannot do.
+        convert_user_modifications(&user, changes, &self.ldap_info, &schema, |attribute| {
+            (credentials.is_admin() || attribute.as_str() != "mail")
+                && self.backend_handler.can_write_user_attribute(
+                    credentials,
+                    user_id,
+                    user_is_admin,
+                    attribute,
+                    &schema,
+                )
+        })
     }
 
     async fn handle_modify_request(
@@ -466,38 +792,86 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                 message: "No user currently bound".to_string(),
             })?
             .clone();
-        match get_user_id_from_distinguished_name(
+        let uid = get_user_id_from_distinguished_name(
             &request.dn,
             &self.ldap_info.base_dn,
             &self.ldap_info.base_dn_str,
-        ) {
-            Ok(uid) => {
-                let user_is_admin = self
-                    .backend_handler
-                    .get_readable_handler(&credentials, &uid)
-                    .expect("Unexpected permission error")
-                    .get_user_groups(&uid)
-                    .await
-                    .map_err(|e| LdapError {
-                        code: LdapResultCode::OperationsError,
-                        message: format!("Internal error while requesting user's groups: {:#?}", e),
-                    })?
-                    .iter()
-                    .any(|g| g.display_name == "lldap_admin".into());
-                for change in &request.changes {
-                    self.handle_modify_change(uid.clone(), &credentials, user_is_admin, change)
-                        .await?
-                }
-                Ok(vec![make_modify_response(
-                    LdapResultCode::Success,
-                    String::new(),
-                )])
-            }
-            Err(e) => Err(LdapError {
-                code: LdapResultCode::InvalidDNSyntax,
-                message: format!("Invalid username: {}", e),
-            }),
+        )
+        .map_err(|e| LdapError {
+            code: LdapResultCode::InvalidDNSyntax,
+            message: format!("Invalid username: {}", e),
+        })?;
+        let (password_changes, attribute_changes): (Vec<_>, Vec<_>) = request
+            .changes
+            .iter()
+            .partition(|c| c.modification.atype.eq_ignore_ascii_case("userpassword"));
+        let user_is_admin = self.is_admin_user(&credentials, &uid).await?;
+        // Everything is checked before applying the first change.
+        let update = if attribute_changes.is_empty() {
+            None
+        } else {
+            Some(
+                self.get_user_update(&uid, &credentials, user_is_admin, &attribute_changes)
+                    .await?,
+            )
+        };
+        let password = if password_changes.is_empty() {
+            None
+        } else {
+            Some(self.check_password_changes(
+                &uid,
+                &credentials,
+                user_is_admin,
+                &password_changes,
+            )?)
+        };
+        if let Some(update) = update {
+            let modified = attribute_changes
+                .iter()
+                .map(|c| c.modification.atype.as_str())
+                .unique()
+                .join(", ");
+            // Under a span, like the GraphQL mutations, so that the update is traced with its
+            // author.
+            let span = debug_span!(
+                "[LDAP modify] update_user",
+                user = %credentials.user,
+                target = %uid,
+                attributes = %modified
+            );
+            self.backend_handler
+                .get_user_attribute_writeable_handler(&credentials, &uid, user_is_admin)
+                .expect("Checked with the changes")
+                .update_user(update)
+                .instrument(span.clone())
+                .await
+                .map_err(|e| LdapError {
+                    code: write_error_code(&e),
+                    message: format!("Could not modify user `{}`: {:#}", uid, e),
+                })?;
+            span.in_scope(|| {
+                info!(
+                    "User `{}` modified the attributes of user `{}` over LDAP: {}",
+                    &credentials.user, &uid, modified
+                )
+            });
+        }
+        if let Some(password) = password {
+            self.change_password(self.get_opaque_handler(), uid.clone(), password)
+                .await
+                .map_err(|e| LdapError {
+                    code: LdapResultCode::Other,
+                    message: format!("Error while changing the password: {:#?}", e),
+                })?;
+            info!(
+                "User `{}` changed the password of user `{}` over LDAP",
+                &credentials.user, &uid
+            );
         }
+        Ok(vec![make_modify_response(
+            LdapResultCode::Success,
+            String::new(),
+        )])
     }
 
     async fn do_modify_request(&mut self, request: &LdapModifyRequest) -> Vec<LdapOp> {
@@ -510,27 +884,40 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         &mut self,
         request: &LdapSearchRequest,
     ) -> LdapResult<Vec<LdapOp>> {
+        let mut results = Vec::new();
+        self.do_search_or_dse_into(request, &mut results).await?;
+        Ok(results)
+    }
+
+    async fn do_search_or_dse_into(
+        &self,
+        request: &LdapSearchRequest,
+        sink: &mut impl ResponseSink,
+    ) -> LdapResult<()> {
         if request.base.is_empty() && request.scope == LdapSearchScope::Base {
             if let LdapFilter::Present(attribute) = &request.filter {
                 if attribute.to_ascii_lowercase() == "objectclass" {
                     debug!("rootDSE request");
-                    return Ok(vec![
-                        root_dse_response(&self.ldap_info.base_dn_str),
-                        make_search_success(),
-                    ]);
+                    sink.send(root_dse_response(
+                        &self.ldap_info.base_dn_str,
+                        self.tls == TlsState::Available,
+                    ))
+                    .await;
+                    sink.send(make_search_success()).await;
+                    return Ok(());
                 }
             }
         }
-        self.do_search(request).await
+        self.do_search_into(request, sink).await
     }
 
-    async fn do_search_internal(
-        &self,
-        backend_handler: &impl UserAndGroupListerBackendHandler,
-        request: &LdapSearchRequest,
-        schema: &PublicSchema,
-    ) -> LdapResult<InternalSearchResults> {
-        let dn_parts = parse_distinguished_name(&request.base.to_ascii_lowercase())?;
+    async fn do_search_internal<'a, Handler: UserAndGroupListerBackendHandler>(
+        &'a self,
+        backend_handler: &'a Handler,
+        request: &'a LdapSearchRequest,
+        schema: &'a PublicSchema,
+    ) -> LdapResult<InternalSearchResults<'a, Handler>> {
+        let dn_parts = parse_distinguished_name(&request.base)?;
         let scope = get_search_scope(&self.ldap_info.base_dn, &dn_parts, &request.scope);
         debug!(?request.base, ?scope);
         // Disambiguate the lifetimes.
@@ -541,15 +928,18 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             x
         }
 
-        let get_user_list = cast(|filter: &LdapFilter| async {
+        // The pages borrow the request and the backend, not the closure.
+        let get_user_list = cast(move |filter: &LdapFilter| async move {
             let need_groups = request
                 .attrs
                 .iter()
                 .any(|s| s.to_ascii_lowercase() == "memberof");
+            let need_photos = requests_photos(&request.attrs, schema);
             get_user_list(
                 &self.ldap_info,
                 filter,
                 need_groups,
+                need_photos,
                 &request.base,
                 backend_handler,
                 schema,
@@ -566,53 +956,72 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             )
             .await
         });
+        // A subtree search includes the OUs themselves, when they match the filter.
+        let ou_entries = |ous: &[&str]| -> Vec<LdapOp> {
+            if request.scope != LdapSearchScope::Subtree || !ou_matches_filter(&request.filter) {
+                return Vec::new();
+            }
+            ous.iter()
+                .map(|ou| {
+                    make_ou_entry(
+                        format!("ou={},{}", ou, self.ldap_info.base_dn_str),
+                        &request.attrs,
+                    )
+                })
+                .collect()
+        };
         Ok(match scope {
             SearchScope::Global => {
+                let ous = ou_entries(&["people", "groups"]);
                 let users = get_user_list(&request.filter).await;
                 let groups = get_group_list(&request.filter).await;
                 match (users, groups) {
                     (Ok(users), Err(e)) => {
                         warn!("Error while getting groups: {:#}", e);
-                        InternalSearchResults::UsersAndGroups(users, Vec::new())
+                        InternalSearchResults::UsersAndGroups(ous, Some(users), Vec::new())
                     }
                     (Err(e), Ok(groups)) => {
                         warn!("Error while getting users: {:#}", e);
-                        InternalSearchResults::UsersAndGroups(Vec::new(), groups)
+                        InternalSearchResults::UsersAndGroups(ous, None, groups)
                     }
-                    (Err(user_error), Err(_)) => {
-                        InternalSearchResults::Raw(vec![make_search_error(
-                            user_error.code,
-                            user_error.message,
-                        )])
+                    (Err(user_error), Err(_)) => return Err(user_error),
+                    (Ok(users), Ok(groups)) => {
+                        InternalSearchResults::UsersAndGroups(ous, Some(users), groups)
                     }
-                    (Ok(users), Ok(groups)) => InternalSearchResults::UsersAndGroups(users, groups),
                 }
             }
             SearchScope::Users => InternalSearchResults::UsersAndGroups(
-                get_user_list(&request.filter).await?,
+                ou_entries(&["people"]),
+                Some(get_user_list(&request.filter).await?),
                 Vec::new(),
             ),
             SearchScope::Groups => InternalSearchResults::UsersAndGroups(
-                Vec::new(),
+                ou_entries(&["groups"]),
+                None,
                 get_group_list(&request.filter).await?,
             ),
             SearchScope::User(filter) => {
                 let filter = LdapFilter::And(vec![request.filter.clone(), filter]);
-                InternalSearchResults::UsersAndGroups(get_user_list(&filter).await?, Vec::new())
+                InternalSearchResults::UsersAndGroups(
+                    Vec::new(),
+                    Some(get_user_list(&filter).await?),
+                    Vec::new(),
+                )
             }
             SearchScope::Group(filter) => {
                 let filter = LdapFilter::And(vec![request.filter.clone(), filter]);
-                InternalSearchResults::UsersAndGroups(Vec::new(), get_group_list(&filter).await?)
-            }
-            SearchScope::UserOuOnly | SearchScope::GroupOuOnly => {
-                InternalSearchResults::Raw(vec![LdapOp::SearchResultEntry(LdapSearchResultEntry {
-                    dn: request.base.clone(),
-                    attributes: vec![LdapPartialAttribute {
-                        atype: "objectClass".to_owned(),
-                        vals: vec![b"top".to_vec(), b"organizationalUnit".to_vec()],
-                    }],
-                })])
+                InternalSearchResults::UsersAndGroups(
+                    Vec::new(),
+                    None,
+                    get_group_list(&filter).await?,
+                )
             }
+            SearchScope::UserOuOnly | SearchScope::GroupOuOnly => InternalSearchResults::Raw(
+                ou_matches_filter(&request.filter)
+                    .then(|| make_ou_entry(request.base.clone(), &request.attrs))
+                    .into_iter()
+                    .collect(),
+            ),
             SearchScope::Unknown => {
                 warn!(
                     r#"The requested search tree "{}" matches neither the user subtree "ou=people,{}" nor the group subtree "ou=groups,{}""#,
@@ -631,8 +1040,21 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         })
     }
 
-    #[instrument(skip_all, level = "debug")]
     pub async fn do_search(&self, request: &LdapSearchRequest) -> LdapResult<Vec<LdapOp>> {
+        let mut results = Vec::new();
+        self.do_search_into(request, &mut results).await?;
+        Ok(results)
+    }
+
+    /// Sends the entries to `sink` as they are produced, then the `SearchResultDone`. An error
+    /// before the first entry is returned instead, like for the other requests; after that, it
+    /// ends the search with its error code.
+    #[instrument(skip_all, level = "debug")]
+    pub async fn do_search_into(
+        &self,
+        request: &LdapSearchRequest,
+        sink: &mut impl ResponseSink,
+    ) -> LdapResult<()> {
         let user_info = self.user_info.as_ref().ok_or_else(|| LdapError {
             code: LdapResultCode::InsufficentAccessRights,
             message: "No user currently bound".to_string(),
@@ -643,38 +1065,102 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
 
         let schema =
             PublicSchema::from(backend_handler.get_schema().await.map_err(|e| LdapError {
-                code: LdapResultCode::OperationsError,
+                code: backend_error_code(&e, LdapResultCode::OperationsError),
                 message: format!("Unable to get schema: {:#}", e),
             })?);
         let search_results = self
             .do_search_internal(&backend_handler, request, &schema)
             .await?;
-        let mut results = match search_results {
-            InternalSearchResults::UsersAndGroups(users, groups) => {
-                convert_users_to_ldap_op(users, &request.attrs, &self.ldap_info, &schema)
-                    .chain(convert_groups_to_ldap_op(
+        let mut entries = SearchEntrySender {
+            sink,
+            size_limit: request.sizelimit,
+            sent: 0,
+        };
+        let result = async {
+            match search_results {
+                InternalSearchResults::UsersAndGroups(ous, users, groups) => {
+                    for entry in ous {
+                        entries.send(entry).await?;
+                    }
+                    if let Some(mut users) = users {
+                        while let Some(page) = users.next_page().await? {
+                            for entry in convert_users_to_ldap_op(
+                                page,
+                                &request.attrs,
+                                &self.ldap_info,
+                                &schema,
+                                |user_id, attribute| {
+                                    self.backend_handler
+                                        .can_read_user_attribute(user_info, user_id, attribute)
+                                },
+                            ) {
+                                entries.send(entry).await?;
+                            }
+                        }
+                    }
+                    for entry in convert_groups_to_ldap_op(
                         groups,
                         &request.attrs,
                         &self.ldap_info,
-                        &backend_handler.user_filter,
                         &schema,
-                    ))
-                    .collect()
+                        |member| {
+                            backend_handler
+                                .user_filter
+                                .as_ref()
+                                .map(|f| member == f)
+                                .unwrap_or(true)
+                                && self.backend_handler.can_read_user_attribute(
+                                    user_info,
+                                    member,
+                                    &"groups".into(),
+                                )
+                        },
+                    ) {
+                        entries.send(entry).await?;
+                    }
+                }
+                InternalSearchResults::Raw(raw_results) => {
+                    for entry in raw_results {
+                        entries.send(entry).await?;
+                    }
+                }
+                InternalSearchResults::Empty => {}
             }
-            InternalSearchResults::Raw(raw_results) => raw_results,
-            InternalSearchResults::Empty => Vec::new(),
-        };
-        if !matches!(results.last(), Some(LdapOp::SearchResultDone(_))) {
-            results.push(make_search_success());
+            Ok::<_, LdapError>(())
+        }
+        .await;
+        entries
+            .sink
+            .send(match result {
+                Ok(()) => make_search_success(),
+                Err(e) => make_search_error(e.code, e.message),
+            })
+            .await;
+        Ok(())
+    }
+
+    async fn do_add_request(&self, request: LdapAddRequest) -> LdapResult<Vec<LdapOp>> {
+        if get_group_id_from_distinguished_name(
+            &request.dn,
+            &self.ldap_info.base_dn,
+            &self.ldap_info.base_dn_str,
+        )
+        .is_ok()
+        {
+            self.do_create_group(request).await
+        } else {
+            self.do_create_user(request).await
         }
-        Ok(results)
     }
 
     async fn do_create_user(&self, request: LdapAddRequest) -> LdapResult<Vec<LdapOp>> {
+        let credentials = self.user_info.as_ref().ok_or_else(|| LdapError {
+            code: LdapResultCode::InsufficentAccessRights,
+            message: "Unauthorized write".to_string(),
+        })?;
         let backend_handler = self
-            .user_info
-            .as_ref()
-            .and_then(|u| self.backend_handler.get_admin_handler(u))
+            .backend_handler
+            .get_admin_handler(credentials)
             .ok_or_else(|| LdapError {
                 code: LdapResultCode::InsufficentAccessRights,
                 message: "Unauthorized write".to_string(),
@@ -684,81 +1170,180 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             &self.ldap_info.base_dn,
             &self.ldap_info.base_dn_str,
         )?;
-        fn parse_attribute(mut attr: LdapPartialAttribute) -> LdapResult<(String, Vec<u8>)> {
-            if attr.vals.len() > 1 {
-                Err(LdapError {
-                    code: LdapResultCode::ConstraintViolation,
-                    message: format!("Expected a single value for attribute {}", attr.atype),
-                })
-            } else {
-                attr.atype.make_ascii_lowercase();
-                match attr.vals.pop() {
-                    Some(val) => Ok((attr.atype, val)),
-                    None => Err(LdapError {
-                        code: LdapResultCode::ConstraintViolation,
-                        message: format!("Missing value for attribute {}", attr.atype),
-                    }),
-                }
-            }
-        }
-        let attributes: HashMap<String, Vec<u8>> = request
-            .attributes
-            .into_iter()
-            .filter(|a| !a.atype.eq_ignore_ascii_case("objectclass"))
-            .map(parse_attribute)
-            .collect::<LdapResult<_>>()?;
-        fn decode_attribute_value(val: &[u8]) -> LdapResult<String> {
-            std::str::from_utf8(val)
-                .map_err(|e| LdapError {
-                    code: LdapResultCode::ConstraintViolation,
-                    message: format!(
-                        "Attribute value is invalid UTF-8: {:#?} (value {:?})",
-                        e, val
-                    ),
-                })
-                .map(str::to_owned)
+        let schema = UserReadableBackendHandler::get_schema(backend_handler)
+            .await
+            .map_err(|e| LdapError {
+                code: backend_error_code(&e, LdapResultCode::OperationsError),
+                message: format!("Unable to get schema: {:#}", e),
+            })?;
+        let create_request = convert_user_add(
+            user_id.clone(),
+            &request.attributes,
+            &self.ldap_info,
+            &schema,
+        )?;
+        if backend_handler.get_user_details(&user_id).await.is_ok() {
+            return Err(LdapError {
+                code: LdapResultCode::EntryAlreadyExists,
+                message: format!("User `{}` already exists", user_id),
+            });
         }
-        let get_attribute = |name| {
-            attributes
-                .get(name)
-                .map(Vec::as_slice)
-                .map(decode_attribute_value)
-        };
         backend_handler
-            .create_user(CreateUserRequest {
-                user_id,
-                email: Email::from(
-                    get_attribute("mail")
-                        .or_else(|| get_attribute("email"))
-                        .transpose()?
-                        .unwrap_or_default(),
-                ),
-                display_name: get_attribute("cn").transpose()?,
-                first_name: get_attribute("givenname").transpose()?,
-                last_name: get_attribute("sn").transpose()?,
-                avatar: attributes
-                    .get("avatar")
-                    .map(Vec::as_slice)
-                    .map(JpegPhoto::try_from)
-                    .transpose()
-                    .map_err(|e| LdapError {
-                        code: LdapResultCode::ConstraintViolation,
-                        message: format!("Invalid JPEG photo: {:#?}", e),
-                    })?,
-                ..Default::default()
-            })
+            .create_user(create_request)
             .await
             .map_err(|e| LdapError {
-                code: LdapResultCode::OperationsError,
-                message: format!("Could not create user: {:#?}", e),
+                code: write_error_code(&e),
+                message: format!("Could not create user `{}`: {:#}", user_id, e),
             })?;
+        info!(
+            "User `{}` created user `{}` over LDAP",
+            &credentials.user, &user_id
+        );
         Ok(vec![make_add_error(LdapResultCode::Success, String::new())])
     }
 
-    pub async fn do_compare(&mut self, request: LdapCompareRequest) -> LdapResult<Vec<LdapOp>> {
-        let req = make_search_request::<String>(
-            &self.ldap_info.base_dn_str,
-            LdapFilter::Equality("dn".to_string(), request.dn.to_string()),
+    async fn do_create_group(&self, request: LdapAddRequest) -> LdapResult<Vec<LdapOp>> {
+        let credentials = self.user_info.as_ref().ok_or_else(|| LdapError {
+            code: LdapResultCode::InsufficentAccessRights,
+            message: "Unauthorized write".to_string(),
+        })?;
+        let backend_handler = self
+            .backend_handler
+            .get_admin_handler(credentials)
+            .ok_or_else(|| LdapError {
+                code: LdapResultCode::InsufficentAccessRights,
+                message: "Unauthorized write".to_string(),
+            })?;
+        let display_name = group_name_as_written(&request.dn)?;
+        let schema = UserReadableBackendHandler::get_schema(backend_handler)
+            .await
+            .map_err(|e| LdapError {
+                code: backend_error_code(&e, LdapResultCode::OperationsError),
+                message: format!("Unable to get schema: {:#}", e),
+            })?;
+        let create_request = convert_group_add(
+            display_name.clone(),
+            &request.attributes,
+            &self.ldap_info,
+            &schema,
+        )?;
+        if find_group_by_exact_name(backend_handler, &display_name)
+            .await?
+            .is_some()
+        {
+            return Err(LdapError {
+                code: LdapResultCode::EntryAlreadyExists,
+                message: format!("Group `{}` already exists", display_name),
+            });
+        }
+        let members = create_request.members.len();
+        // The group is created with all its members or not at all.
+        backend_handler
+            .create_group(create_request)
+            .await
+            .map_err(|e| LdapError {
+                code: write_error_code(&e),
+                message: format!("Could not create group `{}`: {:#}", display_name, e),
+            })?;
+        info!(
+            "User `{}` created group `{}` over LDAP, with {} member(s)",
+            &credentials.user, &display_name, members
+        );
+        Ok(vec![make_add_error(LdapResultCode::Success, String::new())])
+    }
+
+    async fn do_delete_request(&self, dn: &str) -> LdapResult<Vec<LdapOp>> {
+        let credentials = self.user_info.as_ref().ok_or_else(|| LdapError {
+            code: LdapResultCode::InsufficentAccessRights,
+            message: "Unauthorized write".to_string(),
+        })?;
+        let backend_handler = self
+            .backend_handler
+            .get_admin_handler(credentials)
+            .ok_or_else(|| LdapError {
+                code: LdapResultCode::InsufficentAccessRights,
+                message: "Unauthorized write".to_string(),
+            })?;
+        let base_dn = &self.ldap_info.base_dn;
+        let base_dn_str = &self.ldap_info.base_dn_str;
+        if let Ok(user_id) = get_user_id_from_distinguished_name(dn, base_dn, base_dn_str) {
+            if user_id == credentials.user {
+                return Err(LdapError {
+                    code: LdapResultCode::UnwillingToPerform,
+                    message: "Cannot delete the current user".to_string(),
+                });
+            }
+            let admins = backend_handler
+                .list_users(
+                    Some(UserRequestFilter::MemberOf("lldap_admin".into())),
+                    false,
+                )
+                .await
+                .map_err(|e| LdapError {
+                    code: backend_error_code(&e, LdapResultCode::OperationsError),
+                    message: format!("Could not list the admins: {:#}", e),
+                })?;
+            if admins.len() <= 1 && admins.iter().any(|a| a.user.user_id == user_id) {
+                return Err(LdapError {
+                    code: LdapResultCode::UnwillingToPerform,
+                    message: format!("Cannot delete `{}`, the last admin", user_id),
+                });
+            }
+            backend_handler
+                .delete_user(&user_id)
+                .await
+                .map_err(|e| LdapError {
+                    code: write_error_code(&e),
+                    message: format!("Could not delete user `{}`: {:#}", user_id, e),
+                })?;
+            info!(
+                "User `{}` deleted user `{}` over LDAP",
+                &credentials.user, &user_id
+            );
+        } else if get_group_id_from_distinguished_name(dn, base_dn, base_dn_str).is_ok() {
+            let group_name = group_name_as_written(dn)?;
+            if group_name == "lldap_admin".into() {
+                return Err(LdapError {
+                    code: LdapResultCode::UnwillingToPerform,
+                    message: "Cannot delete the admin group".to_string(),
+                });
+            }
+            let group = find_group_by_exact_name(backend_handler, &group_name)
+                .await?
+                .ok_or_else(|| LdapError {
+                    code: LdapResultCode::NoSuchObject,
+                    message: format!("No such group: `{}`", group_name),
+                })?;
+            backend_handler
+                .delete_group(group.id)
+                .await
+                .map_err(|e| LdapError {
+                    code: write_error_code(&e),
+                    message: format!("Could not delete group `{}`: {:#}", group_name, e),
+                })?;
+            info!(
+                "User `{}` deleted group `{}` over LDAP",
+                &credentials.user, &group.display_name
+            );
+        } else {
+            return Err(LdapError {
+                code: LdapResultCode::InvalidDNSyntax,
+                message: format!(
+                    r#"Unexpected DN format. Got "{}", expected a user or a group under "{}""#,
+                    dn, base_dn_str
+                ),
+            });
+        }
+        Ok(vec![make_del_response(
+            LdapResultCode::Success,
+            String::new(),
+        )])
+    }
+
+    pub async fn do_compare(&mut self, request: LdapCompareRequest) -> LdapResult<Vec<LdapOp>> {
+        let req = make_search_request::<String>(
+            &self.ldap_info.base_dn_str,
+            LdapFilter::Equality("dn".to_string(), request.dn.to_string()),
             vec![request.atype.clone()],
         );
         let entries = self.do_search(&req).await?;
@@ -805,7 +1390,40 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
     }
 
     pub async fn handle_ldap_message(&mut self, ldap_op: LdapOp) -> Option<Vec<LdapOp>> {
-        Some(match ldap_op {
+        let mut responses = Vec::new();
+        self.handle_ldap_message_into(ldap_op, &mut responses)
+            .await
+            .then_some(responses)
+    }
+
+    /// Handles a request, sending the responses to `sink` as they are produced. Returns false
+    /// after an unbind, to close the connection.
+    pub async fn handle_ldap_message_into(
+        &mut self,
+        ldap_op: LdapOp,
+        sink: &mut impl ResponseSink,
+    ) -> bool {
+        let responses = match ldap_op {
+            LdapOp::ModifyRequest(_) if self.read_only => vec![make_modify_response(
+                LdapResultCode::UnwillingToPerform,
+                READ_ONLY_REPLICA.to_owned(),
+            )],
+            LdapOp::AddRequest(_) if self.read_only => vec![make_add_error(
+                LdapResultCode::UnwillingToPerform,
+                READ_ONLY_REPLICA.to_owned(),
+            )],
+            LdapOp::DelRequest(_) if self.read_only => vec![make_del_response(
+                LdapResultCode::UnwillingToPerform,
+                READ_ONLY_REPLICA.to_owned(),
+            )],
+            LdapOp::ExtendedRequest(request)
+                if self.read_only && LdapPasswordModifyRequest::try_from(&request).is_ok() =>
+            {
+                vec![make_extended_response(
+                    LdapResultCode::UnwillingToPerform,
+                    READ_ONLY_REPLICA.to_owned(),
+                )]
+            }
             LdapOp::BindRequest(request) => {
                 let (code, message) = self.do_bind(&request).await;
                 vec![LdapOp::BindResponse(LdapBindResponse {
@@ -818,21 +1436,27 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                     saslcreds: None,
                 })]
             }
-            LdapOp::SearchRequest(request) => self
-                .do_search_or_dse(&request)
-                .await
-                .unwrap_or_else(|e: LdapError| vec![make_search_error(e.code, e.message)]),
+            LdapOp::SearchRequest(request) => {
+                if let Err(e) = self.do_search_or_dse_into(&request, sink).await {
+                    sink.send(make_search_error(e.code, e.message)).await;
+                }
+                return true;
+            }
             LdapOp::UnbindRequest => {
                 self.user_info = None;
                 // No need to notify on unbind (per rfc4511)
-                return None;
+                return false;
             }
             LdapOp::ModifyRequest(request) => self.do_modify_request(&request).await,
             LdapOp::ExtendedRequest(request) => self.do_extended_request(&request).await,
             LdapOp::AddRequest(request) => self
-                .do_create_user(request)
+                .do_add_request(request)
                 .await
                 .unwrap_or_else(|e: LdapError| vec![make_add_error(e.code, e.message)]),
+            LdapOp::DelRequest(dn) => self
+                .do_delete_request(&dn)
+                .await
+                .unwrap_or_else(|e: LdapError| vec![make_del_response(e.code, e.message)]),
             LdapOp::CompareRequest(request) => self
                 .do_compare(request)
                 .await
@@ -841,7 +1465,13 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                 LdapResultCode::UnwillingToPerform,
                 format!("Unsupported operation: {:#?}", op),
             )],
-        })
+        };
+        for response in responses {
+            if !sink.send(response).await {
+                break;
+            }
+        }
+        true
     }
 }
 
@@ -849,29 +1479,58 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
 mod tests {
     use super::*;
     use crate::{
-        domain::{handler::*, types::*},
-        infra::test_utils::{setup_default_schema, MockTestBackendHandler},
+        domain::{
+            handler::*,
+            model::GroupColumn,
+            sql_backend_handler::{tests::*, SqlBackendHandler},
+            types::*,
+        },
+        infra::{
+            access_control::{AttributeVisibility, Permission},
+            test_utils::{setup_default_schema, MockTestBackendHandler},
+        },
         uuid,
     };
     use chrono::TimeZone;
     use ldap3_proto::proto::{LdapDerefAliases, LdapSearchScope, LdapSubstringFilter};
     use mockall::predicate::eq;
     use pretty_assertions::assert_eq;
-    use std::collections::HashSet;
+    use std::collections::{HashMap, HashSet};
     use tokio;
 
+    fn visible_to_self_only(attributes: &[&str]) -> HashMap<AttributeName, AttributeVisibility> {
+        attributes
+            .iter()
+            .map(|a| (AttributeName::from(*a), AttributeVisibility::SelfOnly))
+            .collect()
+    }
+
+    /// One level: the users, without the OU itself.
     fn make_user_search_request<S: Into<String>>(
         filter: LdapFilter,
         attrs: Vec<S>,
     ) -> LdapSearchRequest {
-        make_search_request::<S>("ou=people,Dc=example,dc=com", filter, attrs)
+        LdapSearchRequest {
+            scope: LdapSearchScope::OneLevel,
+            ..make_search_request::<S>("ou=people,Dc=example,dc=com", filter, attrs)
+        }
     }
 
     fn make_group_search_request<S: Into<String>>(
         filter: LdapFilter,
         attrs: Vec<S>,
     ) -> LdapSearchRequest {
-        make_search_request::<S>("ou=groups,dc=example,dc=com", filter, attrs)
+        LdapSearchRequest {
+            scope: LdapSearchScope::OneLevel,
+            ..make_search_request::<S>("ou=groups,dc=example,dc=com", filter, attrs)
+        }
+    }
+
+    fn make_ou_entries(request: &LdapSearchRequest) -> Vec<LdapOp> {
+        ["people", "groups"]
+            .iter()
+            .map(|ou| make_ou_entry(format!("ou={},dc=example,dc=com", ou), &request.attrs))
+            .collect()
     }
 
     async fn setup_bound_handler_with_group(
@@ -883,7 +1542,7 @@ mod tests {
                 name: UserId::new("test"),
                 password: "pass".to_string(),
             }))
-            .return_once(|_| Ok(()));
+            .return_once(|_| Ok(UserId::new("test")));
         let group = group.to_string();
         mock.expect_get_user_groups()
             .with(eq(UserId::new("test")))
@@ -892,8 +1551,10 @@ mod tests {
                 set.insert(GroupDetails {
                     group_id: GroupId(42),
                     display_name: group.into(),
-                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                     uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                    description: None,
+                    notes: None,
                     attributes: Vec::new(),
                 });
                 Ok(set)
@@ -938,7 +1599,7 @@ mod tests {
                 password: "pass".to_string(),
             }))
             .times(1)
-            .return_once(|_| Ok(()));
+            .return_once(|_| Ok(UserId::new("bob")));
         mock.expect_get_user_groups()
             .with(eq(UserId::new("bob")))
             .return_once(|_| Ok(HashSet::new()));
@@ -971,7 +1632,7 @@ mod tests {
                 password: "pass".to_string(),
             }))
             .times(1)
-            .return_once(|_| Ok(()));
+            .return_once(|_| Ok(UserId::new("test")));
         mock.expect_get_user_groups()
             .with(eq(UserId::new("test")))
             .return_once(|_| {
@@ -979,8 +1640,10 @@ mod tests {
                 set.insert(GroupDetails {
                     group_id: GroupId(42),
                     display_name: "lldap_admin".into(),
-                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                     uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                    description: None,
+                    notes: None,
                     attributes: Vec::new(),
                 });
                 Ok(set)
@@ -1051,6 +1714,186 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_search_password_manager_restricted_attributes() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users()
+            .with(eq(Some(true.into())), eq(false))
+            .times(1)
+            .return_once(|_, _| {
+                Ok(vec![UserAndGroups {
+                    user: User {
+                        user_id: UserId::new("bob"),
+                        email: "bob@bobmail.bob".into(),
+                        display_name: Some("Bôb Böbberson".to_string()),
+                        ..Default::default()
+                    },
+                    groups: None,
+                }])
+            });
+        let mut ldap_handler = setup_bound_password_manager_handler(mock).await;
+        ldap_handler.backend_handler = ldap_handler
+            .backend_handler
+            .with_attribute_visibility(visible_to_self_only(&["mail", "first_name"]));
+
+        let request = make_user_search_request(
+            LdapFilter::And(vec![]),
+            vec!["uid", "mail", "cn", "givenName"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "uid".to_string(),
+                            vals: vec![b"bob".to_vec()]
+                        },
+                        LdapPartialAttribute {
+                            atype: "cn".to_string(),
+                            vals: vec!["Bôb Böbberson".to_string().into_bytes()]
+                        },
+                    ],
+                }),
+                make_search_success(),
+            ]),
+        );
+    }
+
+    /// The DNs of the entries found, and the values of the attribute in each of them.
+    async fn search_attribute(
+        ldap_handler: &mut LdapHandler<SqlBackendHandler>,
+        request: &LdapSearchRequest,
+    ) -> Vec<(String, Vec<String>)> {
+        let mut entries = ldap_handler
+            .do_search_or_dse(request)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|op| match op {
+                LdapOp::SearchResultEntry(entry) => Some((
+                    entry.dn,
+                    entry
+                        .attributes
+                        .into_iter()
+                        .flat_map(|a| a.vals)
+                        .map(|v| String::from_utf8(v).unwrap())
+                        .collect(),
+                )),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
+    #[tokio::test]
+    async fn test_search_filter_on_hidden_attributes() {
+        let handler = TestFixture::new().await.handler;
+        let mut ldap_handler = LdapHandler::new_for_tests(handler, "dc=example,dc=com");
+        ldap_handler.backend_handler = ldap_handler
+            .backend_handler
+            .with_attribute_visibility(visible_to_self_only(&["mail", "groups"]));
+        ldap_handler.user_info = Some(ValidationResults {
+            user: UserId::new("patrick"),
+            permission: Permission::PasswordManager,
+        });
+        let find = |filter| make_user_search_request(filter, vec!["uid"]);
+        let mail =
+            |user: &str| LdapFilter::Equality("mail".to_string(), format!("{}@bob.bob", user));
+        let best_group = || {
+            LdapFilter::Equality(
+                "memberOf".to_string(),
+                "cn=Best Group,ou=groups,dc=example,dc=com".to_string(),
+            )
+        };
+        let found = |users: &[&str]| {
+            users
+                .iter()
+                .map(|u| {
+                    (
+                        format!("uid={},ou=people,dc=example,dc=com", u.to_lowercase()),
+                        vec![u.to_string()],
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        // The filters on the hidden attributes only match the bound user.
+        for (filter, users) in [
+            (mail("bob"), found(&[])),
+            (mail("patrick"), found(&["patrick"])),
+            (best_group(), found(&["patrick"])),
+            (
+                LdapFilter::Not(Box::new(mail("bob"))),
+                found(&["bob", "John", "NoGroup", "patrick"]),
+            ),
+            (
+                LdapFilter::Equality("uid".to_string(), "bob".to_string()),
+                found(&["bob"]),
+            ),
+        ] {
+            assert_eq!(
+                search_attribute(&mut ldap_handler, &find(filter.clone())).await,
+                users,
+                "{:?}",
+                filter
+            );
+        }
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        assert_eq!(
+            search_attribute(&mut ldap_handler, &find(mail("bob"))).await,
+            found(&["bob"])
+        );
+        assert_eq!(
+            search_attribute(&mut ldap_handler, &find(best_group())).await,
+            found(&["bob", "patrick"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_group_members_follow_the_visibility() {
+        let handler = TestFixture::new().await.handler;
+        let mut ldap_handler = LdapHandler::new_for_tests(handler, "dc=example,dc=com");
+        let request = make_group_search_request(
+            LdapFilter::Equality("cn".to_string(), "Best Group".to_string()),
+            vec!["member", "memberUid"],
+        );
+        let members = |users: &[&str]| {
+            vec![(
+                "cn=Best Group,ou=groups,dc=example,dc=com".to_string(),
+                users
+                    .iter()
+                    .map(|u| format!("uid={},ou=people,dc=example,dc=com", u))
+                    .chain(users.iter().map(|u| u.to_string()))
+                    .collect::<Vec<_>>(),
+            )]
+        };
+        ldap_handler.user_info = Some(ValidationResults {
+            user: UserId::new("patrick"),
+            permission: Permission::PasswordManager,
+        });
+        assert_eq!(
+            search_attribute(&mut ldap_handler, &request).await,
+            members(&["bob", "patrick"])
+        );
+        ldap_handler.backend_handler = ldap_handler
+            .backend_handler
+            .with_attribute_visibility(visible_to_self_only(&["groups"]));
+        assert_eq!(
+            search_attribute(&mut ldap_handler, &request).await,
+            members(&["patrick"])
+        );
+        ldap_handler.user_info = Some(ValidationResults {
+            user: UserId::new("patrick"),
+            permission: Permission::Readonly,
+        });
+        assert_eq!(
+            search_attribute(&mut ldap_handler, &request).await,
+            members(&["bob", "patrick"])
+        );
+    }
+
     #[tokio::test]
     async fn test_search_member_of() {
         let mut mock = MockTestBackendHandler::new();
@@ -1066,8 +1909,10 @@ mod tests {
                     groups: Some(vec![GroupDetails {
                         group_id: GroupId(42),
                         display_name: "rockstars".into(),
-                        creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                         uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                        description: None,
+                        notes: None,
                         attributes: Vec::new(),
                     }]),
                 }])
@@ -1236,10 +2081,6 @@ mod tests {
                         email: "jim@cricket.jim".into(),
                         display_name: Some("Jimminy Cricket".to_string()),
                         attributes: vec![
-                            AttributeValue {
-                                name: "avatar".into(),
-                                value: Serialized::from(&JpegPhoto::for_tests()),
-                            },
                             AttributeValue {
                                 name: "first_name".into(),
                                 value: Serialized::from("Jim"),
@@ -1250,15 +2091,31 @@ mod tests {
                             },
                         ],
                         uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
-                        creation_date: Utc
-                            .with_ymd_and_hms(2014, 7, 8, 9, 10, 11)
-                            .unwrap()
-                            .naive_utc(),
+                        creation_date: Utc.with_ymd_and_hms(2014, 7, 8, 9, 10, 11).unwrap(),
+                        last_login: None,
+                        password_changed_at: None,
+                        preferred_language: None,
+                        hosts: Vec::new(),
+                        phone: None,
+                        mobile: None,
+                        postal_address: None,
+                        locality: None,
+                        state_or_province: None,
+                        expires_at: None,
                     },
                     groups: None,
                 },
             ])
         });
+        mock.expect_get_user_photos().times(1).return_once(|_| {
+            Ok(HashMap::from([(
+                UserId::new("jim"),
+                vec![AttributeValue {
+                    name: "avatar".into(),
+                    value: Serialized::from(&JpegPhoto::for_tests()),
+                }],
+            )]))
+        });
         let mut ldap_handler = setup_bound_admin_handler(mock).await;
         let request = make_user_search_request(
             LdapFilter::And(vec![]),
@@ -1313,7 +2170,7 @@ mod tests {
                         },
                         LdapPartialAttribute {
                             atype: "createTimestamp".to_string(),
-                            vals: vec![b"1970-01-01T00:00:00+00:00".to_vec()]
+                            vals: vec![b"19700101000000Z".to_vec()]
                         },
                         LdapPartialAttribute {
                             atype: "entryUuid".to_string(),
@@ -1356,7 +2213,7 @@ mod tests {
                         },
                         LdapPartialAttribute {
                             atype: "createTimestamp".to_string(),
-                            vals: vec![b"2014-07-08T09:10:11+00:00".to_vec()]
+                            vals: vec![b"20140708091011Z".to_vec()]
                         },
                         LdapPartialAttribute {
                             atype: "entryUuid".to_string(),
@@ -1384,17 +2241,21 @@ mod tests {
                     Group {
                         id: GroupId(1),
                         display_name: "group_1".into(),
-                        creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                         users: vec![UserId::new("bob"), UserId::new("john")],
                         uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                        description: None,
+                        notes: None,
                         attributes: Vec::new(),
                     },
                     Group {
                         id: GroupId(3),
                         display_name: "BestGroup".into(),
-                        creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                         users: vec![UserId::new("john")],
                         uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                        description: None,
+                        notes: None,
                         attributes: Vec::new(),
                     },
                 ])
@@ -1419,7 +2280,7 @@ mod tests {
                     attributes: vec![
                         LdapPartialAttribute {
                             atype: "objectClass".to_string(),
-                            vals: vec![b"groupOfUniqueNames".to_vec(),]
+                            vals: vec![b"groupOfUniqueNames".to_vec(), b"groupOfNames".to_vec()]
                         },
                         LdapPartialAttribute {
                             atype: "cn".to_string(),
@@ -1447,7 +2308,7 @@ mod tests {
                     attributes: vec![
                         LdapPartialAttribute {
                             atype: "objectClass".to_string(),
-                            vals: vec![b"groupOfUniqueNames".to_vec(),]
+                            vals: vec![b"groupOfUniqueNames".to_vec(), b"groupOfNames".to_vec()]
                         },
                         LdapPartialAttribute {
                             atype: "cn".to_string(),
@@ -1499,9 +2360,11 @@ mod tests {
                 Ok(vec![Group {
                     display_name: "group_1".into(),
                     id: GroupId(1),
-                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                     users: vec![],
                     uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    description: None,
+                    notes: None,
                     attributes: Vec::new(),
                 }])
             });
@@ -1570,9 +2433,11 @@ mod tests {
                 Ok(vec![Group {
                     display_name: "group_1".into(),
                     id: GroupId(1),
-                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                     users: vec![],
                     uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    description: None,
+                    notes: None,
                     attributes: Vec::new(),
                 }])
             });
@@ -1599,6 +2464,82 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_search_groups_description() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups()
+            .with(eq(Some(GroupRequestFilter::Or(vec![
+                GroupRequestFilter::SubString(
+                    GroupColumn::Description,
+                    SubStringFilter {
+                        initial: Some("grants".to_owned()),
+                        any: vec![],
+                        final_: None,
+                    },
+                ),
+                GroupRequestFilter::Not(Box::new(GroupRequestFilter::Present(
+                    GroupColumn::Description,
+                ))),
+            ]))))
+            .times(1)
+            .return_once(|_| {
+                let group = |name: &str, description: Option<&str>| Group {
+                    display_name: name.into(),
+                    id: GroupId(1),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
+                    users: vec![],
+                    uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    description: description.map(str::to_owned),
+                    notes: Some("Not over LDAP".to_owned()),
+                    attributes: Vec::new(),
+                };
+                Ok(vec![
+                    group("app-x-rw", Some("Grants write access to app X")),
+                    group("empty", None),
+                ])
+            });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = make_group_search_request(
+            LdapFilter::Or(vec![
+                LdapFilter::Substring(
+                    "description".to_owned(),
+                    LdapSubstringFilter {
+                        initial: Some("grants".to_owned()),
+                        ..Default::default()
+                    },
+                ),
+                LdapFilter::Not(Box::new(LdapFilter::Present("description".to_owned()))),
+            ]),
+            vec!["cn", "description", "notes"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=app-x-rw,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "cn".to_string(),
+                            vals: vec![b"app-x-rw".to_vec()]
+                        },
+                        LdapPartialAttribute {
+                            atype: "description".to_string(),
+                            vals: vec![b"Grants write access to app X".to_vec()]
+                        },
+                    ],
+                }),
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=empty,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "cn".to_string(),
+                        vals: vec![b"empty".to_vec()]
+                    }],
+                }),
+                make_search_success(),
+            ])
+        );
+    }
+
     #[tokio::test]
     async fn test_search_group_as_scope() {
         let mut mock = MockTestBackendHandler::new();
@@ -1693,6 +2634,25 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_search_groups_busy() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups().times(1).return_once(|_| {
+            Err(crate::domain::error::DomainError::ServerBusy(
+                "Timed out waiting for a database connection".to_string(),
+            ))
+        });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = make_group_search_request(LdapFilter::And(vec![]), vec!["cn"]);
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Err(LdapError{
+                code: LdapResultCode::Busy,
+                message: r#"Error while listing groups "ou=groups,dc=example,dc=com": Server busy: `Timed out waiting for a database connection`"#.to_string()
+            })
+        );
+    }
+
     #[tokio::test]
     async fn test_search_groups_filter_error() {
         let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
@@ -1727,7 +2687,7 @@ mod tests {
                         true.into(),
                         false.into(),
                         true.into(),
-                        true.into(),
+                        UserRequestFilter::Present(UserColumn::UserId),
                         false.into(),
                         UserRequestFilter::AttributeEquality(
                             AttributeName::from("first_name"),
@@ -1859,7 +2819,7 @@ mod tests {
             ldap_handler.do_search_or_dse(&request).await,
             Err(LdapError {
                 code: LdapResultCode::InvalidDNSyntax,
-                message: "Missing DN value".to_string()
+                message: r#"Invalid DN "group_1": missing "=" after "group_1""#.to_string()
             })
         );
         let request = make_user_search_request(
@@ -2000,9 +2960,11 @@ mod tests {
                 Ok(vec![Group {
                     id: GroupId(1),
                     display_name: "group_1".into(),
-                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                     users: vec![UserId::new("bob"), UserId::new("john")],
                     uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    description: None,
+                    notes: None,
                     attributes: Vec::new(),
                 }])
             });
@@ -2014,41 +2976,48 @@ mod tests {
         );
         assert_eq!(
             ldap_handler.do_search_or_dse(&request).await,
-            Ok(vec![
-                LdapOp::SearchResultEntry(LdapSearchResultEntry {
-                    dn: "uid=bob_1,ou=people,dc=example,dc=com".to_string(),
-                    attributes: vec![
-                        LdapPartialAttribute {
-                            atype: "objectClass".to_string(),
-                            vals: vec![
-                                b"inetOrgPerson".to_vec(),
-                                b"posixAccount".to_vec(),
-                                b"mailAccount".to_vec(),
-                                b"person".to_vec(),
-                                b"customUserClass".to_vec(),
-                            ]
-                        },
-                        LdapPartialAttribute {
-                            atype: "cn".to_string(),
-                            vals: vec!["Bôb Böbberson".to_string().into_bytes()]
-                        },
-                    ],
-                }),
-                LdapOp::SearchResultEntry(LdapSearchResultEntry {
-                    dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
-                    attributes: vec![
-                        LdapPartialAttribute {
-                            atype: "objectClass".to_string(),
-                            vals: vec![b"groupOfUniqueNames".to_vec(),]
-                        },
-                        LdapPartialAttribute {
-                            atype: "cn".to_string(),
-                            vals: vec![b"group_1".to_vec()]
-                        },
-                    ],
-                }),
-                make_search_success(),
-            ])
+            Ok([
+                make_ou_entries(&request),
+                vec![
+                    LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                        dn: "uid=bob_1,ou=people,dc=example,dc=com".to_string(),
+                        attributes: vec![
+                            LdapPartialAttribute {
+                                atype: "objectClass".to_string(),
+                                vals: vec![
+                                    b"inetOrgPerson".to_vec(),
+                                    b"posixAccount".to_vec(),
+                                    b"mailAccount".to_vec(),
+                                    b"person".to_vec(),
+                                    b"customUserClass".to_vec(),
+                                ]
+                            },
+                            LdapPartialAttribute {
+                                atype: "cn".to_string(),
+                                vals: vec!["Bôb Böbberson".to_string().into_bytes()]
+                            },
+                        ],
+                    }),
+                    LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                        dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
+                        attributes: vec![
+                            LdapPartialAttribute {
+                                atype: "objectClass".to_string(),
+                                vals: vec![
+                                    b"groupOfUniqueNames".to_vec(),
+                                    b"groupOfNames".to_vec()
+                                ]
+                            },
+                            LdapPartialAttribute {
+                                atype: "cn".to_string(),
+                                vals: vec![b"group_1".to_vec()]
+                            },
+                        ],
+                    }),
+                    make_search_success(),
+                ]
+            ]
+            .concat())
         );
     }
 
@@ -2062,31 +3031,36 @@ mod tests {
                     user_id: UserId::new("bob_1"),
                     email: "bob@bobmail.bob".into(),
                     display_name: Some("Bôb Böbberson".to_string()),
-                    attributes: vec![
-                        AttributeValue {
-                            name: "avatar".into(),
-                            value: Serialized::from(&JpegPhoto::for_tests()),
-                        },
-                        AttributeValue {
-                            name: "last_name".into(),
-                            value: Serialized::from("Böbberson"),
-                        },
-                    ],
+                    attributes: vec![AttributeValue {
+                        name: "last_name".into(),
+                        value: Serialized::from("Böbberson"),
+                    }],
                     uuid: uuid!("b4ac75e0-2900-3e21-926c-2f732c26b3fc"),
                     ..Default::default()
                 },
                 groups: None,
             }])
         });
+        mock.expect_get_user_photos().returning(|_| {
+            Ok(HashMap::from([(
+                UserId::new("bob_1"),
+                vec![AttributeValue {
+                    name: "avatar".into(),
+                    value: Serialized::from(&JpegPhoto::for_tests()),
+                }],
+            )]))
+        });
         mock.expect_list_groups()
             .with(eq(Some(true.into())))
             .returning(|_| {
                 Ok(vec![Group {
                     id: GroupId(1),
                     display_name: "group_1".into(),
-                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                     users: vec![UserId::new("bob"), UserId::new("john")],
                     uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    description: None,
+                    notes: None,
                     attributes: Vec::new(),
                 }])
             });
@@ -2099,94 +3073,98 @@ mod tests {
         // all: "objectclass", "dn", "uid", "mail", "givenname", "sn", "cn"
         // Operational: "createtimestamp"
 
-        let expected_result = Ok(vec![
-            LdapOp::SearchResultEntry(LdapSearchResultEntry {
-                dn: "uid=bob_1,ou=people,dc=example,dc=com".to_string(),
-                attributes: vec![
-                    LdapPartialAttribute {
-                        atype: "objectclass".to_string(),
-                        vals: vec![
-                            b"inetOrgPerson".to_vec(),
-                            b"posixAccount".to_vec(),
-                            b"mailAccount".to_vec(),
-                            b"person".to_vec(),
-                            b"customUserClass".to_vec(),
-                        ],
-                    },
-                    LdapPartialAttribute {
-                        atype: "uid".to_string(),
-                        vals: vec![b"bob_1".to_vec()],
-                    },
-                    LdapPartialAttribute {
-                        atype: "mail".to_string(),
-                        vals: vec![b"bob@bobmail.bob".to_vec()],
-                    },
-                    LdapPartialAttribute {
-                        atype: "sn".to_string(),
-                        vals: vec!["Böbberson".to_string().into_bytes()],
-                    },
-                    LdapPartialAttribute {
-                        atype: "cn".to_string(),
-                        vals: vec!["Bôb Böbberson".to_string().into_bytes()],
-                    },
-                    LdapPartialAttribute {
-                        atype: "jpegPhoto".to_string(),
-                        vals: vec![JpegPhoto::for_tests().into_bytes()],
-                    },
-                    LdapPartialAttribute {
-                        atype: "createtimestamp".to_string(),
-                        vals: vec![chrono::Utc
-                            .timestamp_opt(0, 0)
-                            .unwrap()
-                            .to_rfc3339()
-                            .into_bytes()],
-                    },
-                    LdapPartialAttribute {
-                        atype: "entryuuid".to_string(),
-                        vals: vec![b"b4ac75e0-2900-3e21-926c-2f732c26b3fc".to_vec()],
-                    },
-                ],
-            }),
-            // "objectclass", "dn", "uid", "cn", "member", "uniquemember"
-            LdapOp::SearchResultEntry(LdapSearchResultEntry {
-                dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
-                attributes: vec![
-                    LdapPartialAttribute {
-                        atype: "objectclass".to_string(),
-                        vals: vec![b"groupOfUniqueNames".to_vec()],
-                    },
-                    // UID
-                    LdapPartialAttribute {
-                        atype: "uid".to_string(),
-                        vals: vec![b"group_1".to_vec()],
-                    },
-                    LdapPartialAttribute {
-                        atype: "cn".to_string(),
-                        vals: vec![b"group_1".to_vec()],
-                    },
-                    //member / uniquemember : "uid={},ou=people,{}"
-                    LdapPartialAttribute {
-                        atype: "member".to_string(),
-                        vals: vec![
-                            b"uid=bob,ou=people,dc=example,dc=com".to_vec(),
-                            b"uid=john,ou=people,dc=example,dc=com".to_vec(),
-                        ],
-                    },
-                    LdapPartialAttribute {
-                        atype: "uniquemember".to_string(),
-                        vals: vec![
-                            b"uid=bob,ou=people,dc=example,dc=com".to_vec(),
-                            b"uid=john,ou=people,dc=example,dc=com".to_vec(),
-                        ],
-                    },
-                    LdapPartialAttribute {
-                        atype: "entryuuid".to_string(),
-                        vals: vec![b"04ac75e0-2900-3e21-926c-2f732c26b3fc".to_vec()],
-                    },
-                ],
-            }),
-            make_search_success(),
-        ]);
+        let expected_result = Ok([
+            make_ou_entries(&request),
+            vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob_1,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "objectclass".to_string(),
+                            vals: vec![
+                                b"inetOrgPerson".to_vec(),
+                                b"posixAccount".to_vec(),
+                                b"mailAccount".to_vec(),
+                                b"person".to_vec(),
+                                b"customUserClass".to_vec(),
+                            ],
+                        },
+                        LdapPartialAttribute {
+                            atype: "uid".to_string(),
+                            vals: vec![b"bob_1".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "mail".to_string(),
+                            vals: vec![b"bob@bobmail.bob".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "sn".to_string(),
+                            vals: vec!["Böbberson".to_string().into_bytes()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "cn".to_string(),
+                            vals: vec!["Bôb Böbberson".to_string().into_bytes()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "jpegPhoto".to_string(),
+                            vals: vec![JpegPhoto::for_tests().into_bytes()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "createtimestamp".to_string(),
+                            vals: vec![b"19700101000000Z".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "entryuuid".to_string(),
+                            vals: vec![b"b4ac75e0-2900-3e21-926c-2f732c26b3fc".to_vec()],
+                        },
+                    ],
+                }),
+                // "objectclass", "dn", "uid", "cn", "member", "uniquemember", "memberuid"
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "objectclass".to_string(),
+                            vals: vec![b"groupOfUniqueNames".to_vec(), b"groupOfNames".to_vec()],
+                        },
+                        // UID
+                        LdapPartialAttribute {
+                            atype: "uid".to_string(),
+                            vals: vec![b"group_1".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "cn".to_string(),
+                            vals: vec![b"group_1".to_vec()],
+                        },
+                        //member / uniquemember : "uid={},ou=people,{}"
+                        LdapPartialAttribute {
+                            atype: "member".to_string(),
+                            vals: vec![
+                                b"uid=bob,ou=people,dc=example,dc=com".to_vec(),
+                                b"uid=john,ou=people,dc=example,dc=com".to_vec(),
+                            ],
+                        },
+                        LdapPartialAttribute {
+                            atype: "uniquemember".to_string(),
+                            vals: vec![
+                                b"uid=bob,ou=people,dc=example,dc=com".to_vec(),
+                                b"uid=john,ou=people,dc=example,dc=com".to_vec(),
+                            ],
+                        },
+                        LdapPartialAttribute {
+                            atype: "memberuid".to_string(),
+                            vals: vec![b"bob".to_vec(), b"john".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "entryuuid".to_string(),
+                            vals: vec![b"04ac75e0-2900-3e21-926c-2f732c26b3fc".to_vec()],
+                        },
+                    ],
+                }),
+                make_search_success(),
+            ],
+        ]
+        .concat());
 
         assert_eq!(
             ldap_handler.do_search_or_dse(&request).await,
@@ -2468,8 +3446,10 @@ mod tests {
         groups.insert(GroupDetails {
             group_id: GroupId(0),
             display_name: "lldap_admin".into(),
-            creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+            creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
             uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+            description: None,
+            notes: None,
             attributes: Vec::new(),
         });
         mock.expect_get_user_groups()
@@ -2519,6 +3499,333 @@ mod tests {
         );
     }
 
+    fn modify(operation: LdapModifyType, attribute: &str, values: &[&str]) -> LdapModify {
+        LdapModify {
+            operation,
+            modification: LdapPartialAttribute {
+                atype: attribute.to_owned(),
+                vals: values.iter().map(|v| v.as_bytes().to_vec()).collect(),
+            },
+        }
+    }
+
+    async fn do_modify_bob(
+        ldap_handler: &mut LdapHandler<SqlBackendHandler>,
+        changes: Vec<LdapModify>,
+    ) -> (LdapResultCode, String) {
+        let response = ldap_handler
+            .do_modify_request(&LdapModifyRequest {
+                dn: "uid=bob,ou=people,dc=example,dc=com".to_owned(),
+                changes,
+            })
+            .await;
+        match <[LdapOp; 1]>::try_from(response) {
+            Ok([LdapOp::ModifyResponse(response)]) => (response.code, response.message),
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_modify_user_attributes() {
+        use LdapModifyType::*;
+        let fixture = TestFixture::new().await;
+        let handler = fixture.handler;
+        handler
+            .add_user_attribute(CreateAttributeRequest {
+                name: "employee_number".into(),
+                attribute_type: AttributeType::Integer,
+                is_list: false,
+                is_visible: true,
+                is_editable: false,
+            })
+            .await
+            .unwrap();
+        let mut ldap_handler = LdapHandler::new_for_tests(handler.clone(), "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        assert_eq!(
+            do_modify_bob(
+                &mut ldap_handler,
+                vec![
+                    modify(Replace, "cn", &["Bob Smith"]),
+                    modify(Replace, "mail", &["Bob@Example.COM"]),
+                    modify(Add, "telephoneNumber", &["+1 555 0100"]),
+                    modify(Add, "host", &["a.example.com", "b.example.com"]),
+                    modify(Delete, "host", &["a.example.com"]),
+                    modify(Delete, "givenName", &[]),
+                    modify(Replace, "employee_number", &["42"]),
+                ],
+            )
+            .await,
+            (LdapResultCode::Success, String::new())
+        );
+        let bob = handler.get_user_details(&UserId::new("bob")).await.unwrap();
+        assert_eq!(bob.display_name.as_deref(), Some("Bob Smith"));
+        assert_eq!(bob.email.as_str(), "Bob@example.com");
+        assert_eq!(bob.phone.as_deref(), Some("+15550100"));
+        assert_eq!(bob.hosts, vec!["b.example.com"]);
+        assert!(!bob.attributes.iter().any(|a| a.name == "first_name".into()));
+        assert!(bob.attributes.contains(&AttributeValue {
+            name: "employee_number".into(),
+            value: Serialized::from(&42i64),
+        }));
+        // Each of these requests has a valid change followed by an invalid one: nothing is
+        // applied.
+        for (change, code) in [
+            (
+                modify(Replace, "mail", &["not-an-email"]),
+                LdapResultCode::InvalidAttributeSyntax,
+            ),
+            (
+                modify(Replace, "mail", &["a@example.com", "b@example.com"]),
+                LdapResultCode::ConstraintViolation,
+            ),
+            (
+                modify(Delete, "mail", &[]),
+                LdapResultCode::ConstraintViolation,
+            ),
+            (
+                modify(Replace, "mail", &["patrick@bob.bob"]),
+                LdapResultCode::ConstraintViolation,
+            ),
+            (
+                modify(Replace, "employee_number", &["forty-two"]),
+                LdapResultCode::InvalidAttributeSyntax,
+            ),
+            (
+                modify(Add, "host", &["b.example.com"]),
+                LdapResultCode::AttributeOrValueExists,
+            ),
+            (
+                modify(Delete, "host", &["c.example.com"]),
+                LdapResultCode::NoSuchAttribute,
+            ),
+            (
+                modify(Delete, "telephoneNumber", &["+1 555 0199"]),
+                LdapResultCode::NoSuchAttribute,
+            ),
+            (
+                modify(Replace, "jpegPhoto", &["not a photo"]),
+                LdapResultCode::InvalidAttributeSyntax,
+            ),
+            (
+                modify(Replace, "uid", &["robert"]),
+                LdapResultCode::UnwillingToPerform,
+            ),
+            (
+                modify(Replace, "entryUUID", &["abc"]),
+                LdapResultCode::UnwillingToPerform,
+            ),
+            (
+                modify(
+                    Add,
+                    "memberOf",
+                    &["cn=lldap_admin,ou=groups,dc=example,dc=com"],
+                ),
+                LdapResultCode::UnwillingToPerform,
+            ),
+            (
+                modify(Replace, "objectClass", &["person"]),
+                LdapResultCode::UnwillingToPerform,
+            ),
+            (
+                modify(Replace, "unknown", &["value"]),
+                LdapResultCode::UndefinedAttributeType,
+            ),
+            (
+                modify(Replace, "userPassword", &["one", "two"]),
+                LdapResultCode::InvalidAttributeSyntax,
+            ),
+        ] {
+            let (result, message) = do_modify_bob(
+                &mut ldap_handler,
+                vec![modify(Replace, "sn", &["Changed"]), change.clone()],
+            )
+            .await;
+            assert_eq!(result, code, "{:?}: {}", change, message);
+        }
+        let unchanged = handler.get_user_details(&UserId::new("bob")).await.unwrap();
+        assert_eq!(unchanged, bob);
+    }
+
+    #[tokio::test]
+    async fn test_modify_user_attributes_permissions() {
+        use LdapModifyType::*;
+        use LdapResultCode::*;
+        use Permission::*;
+        let handler = TestFixture::new().await.handler;
+        handler
+            .add_user_attribute(CreateAttributeRequest {
+                name: "employee_number".into(),
+                attribute_type: AttributeType::Integer,
+                is_list: false,
+                is_visible: true,
+                is_editable: false,
+            })
+            .await
+            .unwrap();
+        let admins = insert_group(&handler, "lldap_admin").await;
+        insert_membership(&handler, admins, "patrick").await;
+        let mut ldap_handler = LdapHandler::new_for_tests(handler.clone(), "dc=example,dc=com");
+        ldap_handler.backend_handler = ldap_handler
+            .backend_handler
+            .with_attribute_visibility(visible_to_self_only(&["phone"]));
+        let cn = |name| modify(Replace, "cn", &[name]);
+        let phone = || modify(Replace, "telephoneNumber", &["+1 555 0100"]);
+        let mail = || modify(Replace, "mail", &["bob@example.com"]);
+        let host = || modify(Add, "host", &["a.example.com"]);
+        let employee_number = || modify(Replace, "employee_number", &["42"]);
+        for (user, permission, change, code) in [
+            // The password managers can modify what they can see of the users who aren't admins.
+            ("john", PasswordManager, cn("Bob Smith"), Success),
+            ("john", PasswordManager, phone(), InsufficentAccessRights),
+            // The users can modify what they can see of themselves.
+            ("bob", Regular, cn("Robert"), Success),
+            ("bob", Regular, phone(), Success),
+            // Only the admins can modify the email, the hosts and the attributes that aren't
+            // editable.
+            ("john", PasswordManager, mail(), InsufficentAccessRights),
+            ("john", PasswordManager, host(), InsufficentAccessRights),
+            (
+                "john",
+                PasswordManager,
+                employee_number(),
+                InsufficentAccessRights,
+            ),
+            ("bob", Regular, mail(), InsufficentAccessRights),
+            ("bob", Regular, host(), InsufficentAccessRights),
+            ("bob", Regular, employee_number(), InsufficentAccessRights),
+            // The other users cannot modify anything.
+            ("patrick", Regular, cn("Patrick"), InsufficentAccessRights),
+            ("patrick", Readonly, cn("Patrick"), InsufficentAccessRights),
+        ] {
+            ldap_handler.user_info = Some(ValidationResults {
+                user: UserId::new(user),
+                permission,
+            });
+            let (result, message) = do_modify_bob(&mut ldap_handler, vec![change.clone()]).await;
+            assert_eq!(
+                result, code,
+                "{} {:?} {:?}: {}",
+                user, permission, change, message
+            );
+        }
+        let bob = handler.get_user_details(&UserId::new("bob")).await.unwrap();
+        assert_eq!(bob.display_name.as_deref(), Some("Robert"));
+        assert_eq!(bob.phone.as_deref(), Some("+15550100"));
+        assert_eq!(bob.email.as_str(), "bob@bob.bob");
+        assert!(bob.hosts.is_empty());
+        assert!(!bob
+            .attributes
+            .iter()
+            .any(|a| a.name == "employee_number".into()));
+        // The password managers cannot modify the admins.
+        ldap_handler.user_info = Some(ValidationResults {
+            user: UserId::new("john"),
+            permission: PasswordManager,
+        });
+        let response = ldap_handler
+            .do_modify_request(&LdapModifyRequest {
+                dn: "uid=patrick,ou=people,dc=example,dc=com".to_owned(),
+                changes: vec![cn("Patrick")],
+            })
+            .await;
+        match response.as_slice() {
+            [LdapOp::ModifyResponse(response)] => assert_eq!(
+                response.code, InsufficentAccessRights,
+                "{}",
+                response.message
+            ),
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        // The admins can modify everything.
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        assert_eq!(
+            do_modify_bob(&mut ldap_handler, vec![mail(), host(), employee_number()]).await,
+            (Success, String::new())
+        );
+    }
+
+    async fn bind_code(handler: &SqlBackendHandler, user: &str, password: &str) -> LdapResultCode {
+        LdapHandler::new_for_tests(handler.clone(), "dc=example,dc=com")
+            .do_bind(&LdapBindRequest {
+                dn: format!("uid={},ou=people,dc=example,dc=com", user),
+                cred: LdapBindCred::Simple(password.to_string()),
+            })
+            .await
+            .0
+    }
+
+    #[tokio::test]
+    async fn test_modify_password_as_password_manager() {
+        use LdapModifyType::*;
+        let handler = TestFixture::new().await.handler;
+        let admins = insert_group(&handler, "lldap_admin").await;
+        insert_membership(&handler, admins, "patrick").await;
+        let mut ldap_handler = LdapHandler::new_for_tests(handler.clone(), "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults {
+            user: UserId::new("john"),
+            permission: Permission::PasswordManager,
+        });
+        // Replaced, or deleted then added.
+        for changes in [
+            vec![modify(Replace, "userPassword", &["first"])],
+            vec![
+                modify(Delete, "userPassword", &[]),
+                modify(Add, "userPassword", &["second"]),
+            ],
+        ] {
+            assert_eq!(
+                do_modify_bob(&mut ldap_handler, changes).await,
+                (LdapResultCode::Success, String::new())
+            );
+        }
+        assert_eq!(
+            bind_code(&handler, "bob", "first").await,
+            LdapResultCode::InvalidCredentials
+        );
+        assert_eq!(
+            bind_code(&handler, "bob", "second").await,
+            LdapResultCode::Success
+        );
+        // Not the password of an admin.
+        let response = ldap_handler
+            .do_modify_request(&LdapModifyRequest {
+                dn: "uid=patrick,ou=people,dc=example,dc=com".to_owned(),
+                changes: vec![modify(Replace, "userPassword", &["third"])],
+            })
+            .await;
+        match response.as_slice() {
+            [LdapOp::ModifyResponse(response)] => assert_eq!(
+                response.code,
+                LdapResultCode::InsufficentAccessRights,
+                "{}",
+                response.message
+            ),
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        // Not the attributes reserved to the admins, and the password can't be removed: nothing
+        // is applied.
+        for (changes, code) in [
+            (
+                vec![
+                    modify(Replace, "userPassword", &["third"]),
+                    modify(Add, "host", &["a.example.com"]),
+                ],
+                LdapResultCode::InsufficentAccessRights,
+            ),
+            (
+                vec![modify(Delete, "userPassword", &[])],
+                LdapResultCode::UnwillingToPerform,
+            ),
+        ] {
+            assert_eq!(do_modify_bob(&mut ldap_handler, changes).await.0, code);
+        }
+        assert_eq!(
+            bind_code(&handler, "bob", "second").await,
+            LdapResultCode::Success
+        );
+    }
+
     #[tokio::test]
     async fn test_search_root_dse() {
         let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
@@ -2535,34 +3842,101 @@ mod tests {
         assert_eq!(
             ldap_handler.do_search_or_dse(&request).await,
             Ok(vec![
-                root_dse_response("dc=example,dc=com"),
+                root_dse_response("dc=example,dc=com", false),
                 make_search_success()
             ])
         );
     }
 
     #[tokio::test]
-    async fn test_create_user() {
-        let mut mock = MockTestBackendHandler::new();
-        mock.expect_create_user()
-            .with(eq(CreateUserRequest {
-                user_id: UserId::new("bob"),
-                email: "".into(),
-                display_name: Some("Bob".to_string()),
-                ..Default::default()
-            }))
-            .times(1)
-            .return_once(|_| Ok(()));
-        let ldap_handler = setup_bound_admin_handler(mock).await;
-        let request = LdapAddRequest {
-            dn: "uid=bob,ou=people,dc=example,dc=com".to_owned(),
-            attributes: vec![LdapPartialAttribute {
-                atype: "cn".to_owned(),
-                vals: vec![b"Bob".to_vec()],
-            }],
+    async fn test_start_tls() {
+        let start_tls = || {
+            LdapOp::ExtendedRequest(LdapExtendedRequest {
+                name: START_TLS_OID.to_string(),
+                value: None,
+            })
         };
-        assert_eq!(
-            ldap_handler.do_create_user(request).await,
+        let response = |code, message: &str| {
+            Some(vec![LdapOp::ExtendedResponse(LdapExtendedResponse {
+                res: LdapResultOp {
+                    code,
+                    matcheddn: "".to_string(),
+                    message: message.to_string(),
+                    referral: vec![],
+                },
+                name: Some(START_TLS_OID.to_string()),
+                value: None,
+            })])
+        };
+        let mut ldap_handler =
+            LdapHandler::new_for_tests(MockTestBackendHandler::new(), "dc=example,dc=com");
+        assert_eq!(
+            ldap_handler.handle_ldap_message(start_tls()).await,
+            response(LdapResultCode::ProtocolError, "StartTLS is not enabled")
+        );
+        assert_eq!(ldap_handler.tls_state(), TlsState::Unavailable);
+
+        let mut ldap_handler =
+            LdapHandler::new_for_tests(MockTestBackendHandler::new(), "dc=example,dc=com")
+                .with_tls(TlsState::Available);
+        let request = LdapSearchRequest {
+            base: "".to_string(),
+            scope: LdapSearchScope::Base,
+            aliases: LdapDerefAliases::Never,
+            sizelimit: 0,
+            timelimit: 0,
+            typesonly: false,
+            filter: LdapFilter::Present("objectClass".to_string()),
+            attrs: vec!["supportedExtension".to_string()],
+        };
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                root_dse_response("dc=example,dc=com", true),
+                make_search_success()
+            ])
+        );
+        assert_eq!(
+            ldap_handler.handle_ldap_message(start_tls()).await,
+            response(LdapResultCode::Success, "")
+        );
+        assert_eq!(ldap_handler.tls_state(), TlsState::Upgrading);
+        ldap_handler.set_tls_established();
+        assert_eq!(
+            ldap_handler.handle_ldap_message(start_tls()).await,
+            response(
+                LdapResultCode::OperationsError,
+                "TLS is already established"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_user() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_create_user()
+            .with(eq(CreateUserRequest {
+                user_id: UserId::new("bob"),
+                email: "".into(),
+                display_name: Some("Bob".to_string()),
+                ..Default::default()
+            }))
+            .times(1)
+            .return_once(|_| Ok(UserId::new("bob")));
+        mock.expect_get_user_details()
+            .with(eq(UserId::new("bob")))
+            .return_once(|_| Err(DomainError::EntityNotFound("bob".to_owned())));
+        setup_default_schema(&mut mock);
+        let ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapAddRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_owned(),
+            attributes: vec![LdapPartialAttribute {
+                atype: "cn".to_owned(),
+                vals: vec![b"Bob".to_vec()],
+            }],
+        };
+        assert_eq!(
+            ldap_handler.do_create_user(request).await,
             Ok(vec![make_add_error(LdapResultCode::Success, String::new())])
         );
     }
@@ -2594,7 +3968,11 @@ mod tests {
                 ..Default::default()
             }))
             .times(1)
-            .return_once(|_| Ok(()));
+            .return_once(|_| Ok(UserId::new("bob")));
+        mock.expect_get_user_details()
+            .with(eq(UserId::new("bob")))
+            .return_once(|_| Err(DomainError::EntityNotFound("bob".to_owned())));
+        setup_default_schema(&mut mock);
         let ldap_handler = setup_bound_admin_handler(mock).await;
         let request = LdapAddRequest {
             dn: "uid=bob,ou=people,dc=example,dc=com".to_owned(),
@@ -2619,11 +3997,336 @@ mod tests {
         );
     }
 
+    async fn do_add(
+        ldap_handler: &mut LdapHandler<SqlBackendHandler>,
+        dn: &str,
+        attributes: &[(&str, &[&str])],
+    ) -> (LdapResultCode, String) {
+        let request = LdapOp::AddRequest(LdapAddRequest {
+            dn: dn.to_owned(),
+            attributes: attributes
+                .iter()
+                .map(|(atype, vals)| LdapPartialAttribute {
+                    atype: atype.to_string(),
+                    vals: vals.iter().map(|v| v.as_bytes().to_vec()).collect(),
+                })
+                .collect(),
+        });
+        let response = ldap_handler.handle_ldap_message(request).await;
+        match response.map(<[LdapOp; 1]>::try_from) {
+            Some(Ok([LdapOp::AddResponse(response)])) => (response.code, response.message),
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+
+    async fn do_delete(
+        ldap_handler: &mut LdapHandler<SqlBackendHandler>,
+        dn: &str,
+    ) -> (LdapResultCode, String) {
+        let response = ldap_handler
+            .handle_ldap_message(LdapOp::DelRequest(dn.to_owned()))
+            .await;
+        match response.map(<[LdapOp; 1]>::try_from) {
+            Some(Ok([LdapOp::DelResponse(response)])) => (response.code, response.message),
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_user() {
+        let handler = TestFixture::new().await.handler;
+        let mut ldap_handler = LdapHandler::new_for_tests(handler.clone(), "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        assert_eq!(
+            do_add(
+                &mut ldap_handler,
+                "uid=alice,ou=people,dc=example,dc=com",
+                &[
+                    ("objectClass", &["top", "inetOrgPerson", "posixAccount"]),
+                    ("uid", &["Alice"]),
+                    ("cn", &["Alice Liddell"]),
+                    ("givenName", &["Alice"]),
+                    ("sn", &["Liddell"]),
+                    ("mail", &["alice@example.com"]),
+                    ("telephoneNumber", &["+1 555 0100"]),
+                    ("uidNumber", &["1001"]),
+                    ("homeDirectory", &["/home/alice"]),
+                ],
+            )
+            .await,
+            (LdapResultCode::Success, String::new())
+        );
+        let alice = handler
+            .get_user_details(&UserId::new("alice"))
+            .await
+            .unwrap();
+        assert_eq!(alice.email.as_str(), "alice@example.com");
+        assert_eq!(alice.display_name.as_deref(), Some("Alice Liddell"));
+        assert_eq!(alice.phone.as_deref(), Some("+15550100"));
+        assert!(alice.attributes.contains(&AttributeValue {
+            name: "last_name".into(),
+            value: Serialized::from("Liddell"),
+        }));
+        let mail: (&str, &[&str]) = ("mail", &["carol@example.com"]);
+        for (dn, attributes, code) in [
+            (
+                "uid=carol,ou=people,dc=example,dc=com",
+                vec![mail, ("objectClass", &["person", "groupOfNames"][..])],
+                LdapResultCode::ObjectClassViolation,
+            ),
+            (
+                "uid=carol,ou=people,dc=example,dc=com",
+                vec![mail, ("userPassword", &["secret"][..])],
+                LdapResultCode::UnwillingToPerform,
+            ),
+            (
+                "uid=carol,ou=people,dc=example,dc=com",
+                vec![mail, ("uid", &["dave"][..])],
+                LdapResultCode::NamingViolation,
+            ),
+            (
+                "uid=carol,ou=people,dc=example,dc=com",
+                vec![mail, ("entryUUID", &["abc"][..])],
+                LdapResultCode::UnwillingToPerform,
+            ),
+            (
+                "uid=carol,ou=people,dc=example,dc=com",
+                vec![mail, ("unknown", &["value"][..])],
+                LdapResultCode::UndefinedAttributeType,
+            ),
+            (
+                "uid=carol,ou=people,dc=example,dc=com",
+                vec![("cn", &["Carol"][..])],
+                LdapResultCode::ConstraintViolation,
+            ),
+            (
+                "uid=carol,ou=people,dc=example,dc=com",
+                vec![("mail", &["not-an-email"][..])],
+                LdapResultCode::InvalidAttributeSyntax,
+            ),
+            (
+                "uid=carol,ou=people,dc=example,dc=com",
+                vec![("mail", &["bob@bob.bob"][..])],
+                LdapResultCode::ConstraintViolation,
+            ),
+            (
+                "uid=bob,ou=people,dc=example,dc=com",
+                vec![("mail", &["bob@example.com"][..])],
+                LdapResultCode::EntryAlreadyExists,
+            ),
+        ] {
+            let (result, message) = do_add(&mut ldap_handler, dn, &attributes).await;
+            assert_eq!(result, code, "{:?}: {}", attributes, message);
+        }
+        assert!(handler
+            .get_user_details(&UserId::new("carol"))
+            .await
+            .is_err());
+        ldap_handler.user_info = Some(ValidationResults {
+            user: UserId::new("bob"),
+            permission: Permission::Regular,
+        });
+        assert_eq!(
+            do_add(
+                &mut ldap_handler,
+                "uid=carol,ou=people,dc=example,dc=com",
+                &[mail],
+            )
+            .await
+            .0,
+            LdapResultCode::InsufficentAccessRights
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_group() {
+        let handler = TestFixture::new().await.handler;
+        let mut ldap_handler = LdapHandler::new_for_tests(handler.clone(), "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        assert_eq!(
+            do_add(
+                &mut ldap_handler,
+                "cn=New Team,ou=groups,dc=example,dc=com",
+                &[
+                    ("objectClass", &["top", "groupOfNames"]),
+                    ("cn", &["new team"]),
+                    ("description", &["The new ones"]),
+                    ("member", &["uid=bob,ou=people,dc=example,dc=com"]),
+                    ("memberUid", &["patrick"]),
+                ],
+            )
+            .await,
+            (LdapResultCode::Success, String::new())
+        );
+        let groups = handler
+            .list_groups(Some(GroupRequestFilter::DisplayName("new team".into())))
+            .await
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].display_name.as_str(), "New Team");
+        assert_eq!(groups[0].description.as_deref(), Some("The new ones"));
+        assert_eq!(
+            groups[0].users,
+            vec![UserId::new("bob"), UserId::new("patrick")]
+        );
+        for (dn, attributes, code) in [
+            (
+                "cn=Other Team,ou=groups,dc=example,dc=com",
+                vec![("member", &["uid=nobody,ou=people,dc=example,dc=com"][..])],
+                LdapResultCode::NoSuchObject,
+            ),
+            (
+                "cn=Other Team,ou=groups,dc=example,dc=com",
+                vec![("objectClass", &["posixGroup"][..])],
+                LdapResultCode::ObjectClassViolation,
+            ),
+            (
+                "cn=Other Team,ou=groups,dc=example,dc=com",
+                vec![("cn", &["Another Team"][..])],
+                LdapResultCode::NamingViolation,
+            ),
+            (
+                "cn=Best Group,ou=groups,dc=example,dc=com",
+                vec![],
+                LdapResultCode::EntryAlreadyExists,
+            ),
+        ] {
+            let (result, message) = do_add(&mut ldap_handler, dn, &attributes).await;
+            assert_eq!(result, code, "{:?}: {}", attributes, message);
+        }
+        assert!(handler
+            .list_groups(Some(GroupRequestFilter::DisplayName("Other Team".into())))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_group_with_the_exact_name() {
+        let group = |id, name: &str| Group {
+            id: GroupId(id),
+            display_name: name.into(),
+            creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
+            users: vec![],
+            uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+            description: None,
+            notes: None,
+            attributes: Vec::new(),
+        };
+        let mut mock = MockTestBackendHandler::new();
+        // The names only differ by their case.
+        mock.expect_list_groups()
+            .returning(move |_| Ok(vec![group(1, "Team"), group(2, "team")]));
+        mock.expect_delete_group()
+            .with(eq(GroupId(2)))
+            .times(1)
+            .return_once(|_| Ok(()));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let delete = |dn: &str| LdapOp::DelRequest(dn.to_owned());
+        assert_eq!(
+            ldap_handler
+                .handle_ldap_message(delete("cn=team,ou=groups,dc=example,dc=com"))
+                .await,
+            Some(vec![make_del_response(
+                LdapResultCode::Success,
+                String::new()
+            )])
+        );
+        assert_eq!(
+            ldap_handler
+                .handle_ldap_message(delete("cn=TEAM,ou=groups,dc=example,dc=com"))
+                .await,
+            Some(vec![make_del_response(
+                LdapResultCode::NoSuchObject,
+                "No such group: `TEAM`".to_owned()
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_and_group() {
+        let fixture = TestFixture::new().await;
+        let handler = fixture.handler;
+        let admin_group = insert_group(&handler, "lldap_admin").await;
+        insert_membership(&handler, admin_group, "patrick").await;
+        let mut ldap_handler = LdapHandler::new_for_tests(handler.clone(), "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults {
+            user: UserId::new("bob"),
+            permission: Permission::Regular,
+        });
+        assert_eq!(
+            do_delete(&mut ldap_handler, "uid=john,ou=people,dc=example,dc=com")
+                .await
+                .0,
+            LdapResultCode::InsufficentAccessRights
+        );
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        for (dn, code) in [
+            (
+                "uid=admin,ou=people,dc=example,dc=com",
+                LdapResultCode::UnwillingToPerform,
+            ),
+            (
+                "uid=patrick,ou=people,dc=example,dc=com",
+                LdapResultCode::UnwillingToPerform,
+            ),
+            (
+                "cn=lldap_admin,ou=groups,dc=example,dc=com",
+                LdapResultCode::UnwillingToPerform,
+            ),
+            (
+                "uid=nobody,ou=people,dc=example,dc=com",
+                LdapResultCode::NoSuchObject,
+            ),
+            (
+                "cn=No Group,ou=groups,dc=example,dc=com",
+                LdapResultCode::NoSuchObject,
+            ),
+            (
+                "ou=people,dc=example,dc=com",
+                LdapResultCode::InvalidDNSyntax,
+            ),
+            (
+                "uid=john,ou=people,dc=example,dc=com",
+                LdapResultCode::Success,
+            ),
+            (
+                "cn=Best Group,ou=groups,dc=example,dc=com",
+                LdapResultCode::Success,
+            ),
+            // Already deleted.
+            (
+                "uid=john,ou=people,dc=example,dc=com",
+                LdapResultCode::NoSuchObject,
+            ),
+        ] {
+            let (result, message) = do_delete(&mut ldap_handler, dn).await;
+            assert_eq!(result, code, "{}: {}", dn, message);
+        }
+        assert_eq!(
+            get_user_names(&handler, None).await,
+            vec!["bob", "nogroup", "patrick"]
+        );
+        assert_eq!(
+            handler
+                .list_groups(None)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|g| g.id)
+                .collect::<Vec<_>>(),
+            vec![fixture.groups[1], fixture.groups[2], admin_group]
+        );
+    }
+
     #[tokio::test]
     async fn test_search_filter_non_attribute() {
         let mut mock = MockTestBackendHandler::new();
         mock.expect_list_users()
-            .with(eq(Some(true.into())), eq(false))
+            .with(
+                eq(Some(UserRequestFilter::Present(UserColumn::DisplayName))),
+                eq(false),
+            )
             .times(1)
             .return_once(|_, _| Ok(vec![]));
         let mut ldap_handler = setup_bound_admin_handler(mock).await;
@@ -2695,9 +4398,11 @@ mod tests {
             Ok(vec![Group {
                 id: GroupId(1),
                 display_name: "group".into(),
-                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                 users: vec![UserId::new("bob")],
                 uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                description: None,
+                notes: None,
                 attributes: Vec::new(),
             }])
         });
@@ -2789,9 +4494,11 @@ mod tests {
             Ok(vec![Group {
                 id: GroupId(1),
                 display_name: "group".into(),
-                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                 users: vec![UserId::new("bob")],
                 uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                description: None,
+                notes: None,
                 attributes: Vec::new(),
             }])
         });
@@ -2861,9 +4568,11 @@ mod tests {
             Ok(vec![Group {
                 id: GroupId(1),
                 display_name: "group".into(),
-                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap(),
                 users: vec![UserId::new("bob")],
                 uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                description: None,
+                notes: None,
                 attributes: vec![AttributeValue {
                     name: "club_name".into(),
                     value: Serialized::from("Breakfast Club"),
@@ -2908,35 +4617,1381 @@ mod tests {
         );
         assert_eq!(
             ldap_handler.do_search_or_dse(&request).await,
+            Ok([
+                make_ou_entries(&request),
+                vec![
+                    LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                        dn: "uid=test,ou=people,dc=example,dc=com".to_string(),
+                        attributes: vec![
+                            LdapPartialAttribute {
+                                atype: "uid".to_owned(),
+                                vals: vec![b"test".to_vec()],
+                            },
+                            LdapPartialAttribute {
+                                atype: "nickname".to_owned(),
+                                vals: vec![b"Bob the Builder".to_vec()],
+                            },
+                        ],
+                    }),
+                    LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                        dn: "cn=group,ou=groups,dc=example,dc=com".to_owned(),
+                        attributes: vec![
+                            LdapPartialAttribute {
+                                atype: "uid".to_owned(),
+                                vals: vec![b"group".to_vec()],
+                            },
+                            LdapPartialAttribute {
+                                atype: "club_name".to_owned(),
+                                vals: vec![b"Breakfast Club".to_vec()],
+                            },
+                        ],
+                    }),
+                    make_search_success()
+                ]
+            ]
+            .concat()),
+        );
+    }
+
+    fn user_with_id(user_id: &str) -> UserAndGroups {
+        UserAndGroups {
+            user: User {
+                user_id: UserId::new(user_id),
+                ..Default::default()
+            },
+            groups: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_size_limit() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users().returning(|_, _| {
             Ok(vec![
-                LdapOp::SearchResultEntry(LdapSearchResultEntry {
-                    dn: "uid=test,ou=people,dc=example,dc=com".to_string(),
-                    attributes: vec![
-                        LdapPartialAttribute {
-                            atype: "uid".to_owned(),
-                            vals: vec![b"test".to_vec()],
-                        },
-                        LdapPartialAttribute {
-                            atype: "nickname".to_owned(),
-                            vals: vec![b"Bob the Builder".to_vec()],
-                        },
-                    ],
-                }),
-                LdapOp::SearchResultEntry(LdapSearchResultEntry {
-                    dn: "cn=group,ou=groups,dc=example,dc=com".to_owned(),
-                    attributes: vec![
-                        LdapPartialAttribute {
-                            atype: "uid".to_owned(),
-                            vals: vec![b"group".to_vec()],
-                        },
-                        LdapPartialAttribute {
-                            atype: "club_name".to_owned(),
-                            vals: vec![b"Breakfast Club".to_vec()],
-                        },
-                    ],
-                }),
-                make_search_success()
-            ]),
+                user_with_id("bob"),
+                user_with_id("jim"),
+                user_with_id("tom"),
+            ])
+        });
+        let ldap_handler = setup_bound_admin_handler(mock).await;
+        let mut request = make_user_search_request(LdapFilter::And(vec![]), vec!["uid"]);
+        request.sizelimit = 2;
+        let results = ldap_handler.do_search(&request).await.unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results.last(),
+            Some(&make_search_error(
+                LdapResultCode::SizeLimitExceeded,
+                "More than 2 entries match the search".to_string()
+            ))
+        );
+        // Exactly at the limit.
+        request.sizelimit = 3;
+        let results = ldap_handler.do_search(&request).await.unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results.last(), Some(&make_search_success()));
+    }
+
+    /// Records how many queries were sent to the database when each entry was sent.
+    struct QueryCountingSink {
+        queries: std::sync::Arc<AtomicUsize>,
+        entries: Vec<usize>,
+        done: Option<LdapOp>,
+    }
+
+    #[async_trait(?Send)]
+    impl ResponseSink for QueryCountingSink {
+        async fn send(&mut self, response: LdapOp) -> bool {
+            match response {
+                LdapOp::SearchResultEntry(_) => {
+                    self.entries.push(self.queries.load(Ordering::Relaxed))
+                }
+                done => self.done = Some(done),
+            }
+            true
+        }
+    }
+
+    async fn search_all_users(
+        mut backend_handler: SqlBackendHandler,
+        page_size: u64,
+    ) -> QueryCountingSink {
+        let queries = count_queries(&mut backend_handler.sql_pool);
+        let mut ldap_handler = LdapHandler::new_for_tests(backend_handler, "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        ldap_handler.ldap_info.search_page_size = page_size;
+        let mut sink = QueryCountingSink {
+            queries,
+            entries: Vec::new(),
+            done: None,
+        };
+        ldap_handler
+            .do_search_into(
+                &make_user_search_request(LdapFilter::And(vec![]), vec!["uid"]),
+                &mut sink,
+            )
+            .await
+            .unwrap();
+        assert_eq!(sink.done, Some(make_search_success()));
+        sink
+    }
+
+    /// The largest number of entries sent between two queries, i.e. the largest page.
+    fn largest_page(entries: &[usize]) -> usize {
+        entries
+            .iter()
+            .group_by(|queries| **queries)
+            .into_iter()
+            .map(|(_, page)| page.count())
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn test_search_sends_each_page_before_fetching_the_next() {
+        let backend_handler =
+            SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        for i in 0..5 {
+            insert_user_no_password(&backend_handler, &format!("user{}", i)).await;
+        }
+        let sink = search_all_users(backend_handler, 2).await;
+        let entries = sink.entries;
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0], entries[1]);
+        assert!(entries[1] < entries[2]);
+        assert_eq!(entries[2], entries[3]);
+        assert!(entries[3] < entries[4]);
+        assert_eq!(largest_page(&entries), 2);
+    }
+
+    /// Breaks the database after the first entry.
+    struct BreakingSink {
+        sql_pool: crate::domain::sql_tables::DbConnection,
+        results: Vec<LdapOp>,
+    }
+
+    #[async_trait(?Send)]
+    impl ResponseSink for BreakingSink {
+        async fn send(&mut self, response: LdapOp) -> bool {
+            use sea_orm::ConnectionTrait;
+            if self.results.is_empty() {
+                self.sql_pool
+                    .execute_unprepared("ALTER TABLE users RENAME TO gone")
+                    .await
+                    .unwrap();
+            }
+            self.results.push(response);
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_error_after_the_first_page() {
+        let backend_handler =
+            SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        for i in 0..3 {
+            insert_user_no_password(&backend_handler, &format!("user{}", i)).await;
+        }
+        let mut sink = BreakingSink {
+            sql_pool: backend_handler.sql_pool.clone(),
+            results: Vec::new(),
+        };
+        let mut ldap_handler = LdapHandler::new_for_tests(backend_handler, "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        ldap_handler.ldap_info.search_page_size = 2;
+        ldap_handler
+            .do_search_into(
+                &make_user_search_request(LdapFilter::And(vec![]), vec!["uid"]),
+                &mut sink,
+            )
+            .await
+            .unwrap();
+        // The first page, then the error.
+        assert_eq!(sink.results.len(), 3);
+        match sink.results.last() {
+            Some(LdapOp::SearchResultDone(done)) => {
+                assert_eq!(done.code, LdapResultCode::Other);
+                assert!(
+                    done.message.starts_with("Error while searching user"),
+                    "{}",
+                    done.message
+                );
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_user_id_case_across_interfaces() {
+        let backend_handler =
+            SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        // Created with the case as entered, like through GraphQL.
+        insert_user(&backend_handler, "JSmith", "pass").await;
+        insert_user(&backend_handler, "jöhn", "pass").await;
+        for (dn, user_dn) in [
+            (
+                "uid=jsmith,ou=people,dc=example,dc=com",
+                "uid=jsmith,ou=people,dc=example,dc=com",
+            ),
+            (
+                "uid=JSmith,ou=people,dc=example,dc=com",
+                "uid=jsmith,ou=people,dc=example,dc=com",
+            ),
+            (
+                "UID=JÖHN,OU=People,DC=Example,DC=com",
+                "uid=jöhn,ou=people,dc=example,dc=com",
+            ),
+        ] {
+            let mut ldap_handler =
+                LdapHandler::new_for_tests(backend_handler.clone(), "dc=example,dc=com");
+            let (code, message) = ldap_handler
+                .do_bind(&LdapBindRequest {
+                    dn: dn.to_string(),
+                    cred: LdapBindCred::Simple("pass".to_string()),
+                })
+                .await;
+            assert_eq!(code, LdapResultCode::Success, "{}: {}", dn, message);
+            // The user can read their own entry, whatever the case of the filter.
+            let uid = dn.split(',').next().unwrap().split('=').nth(1).unwrap();
+            let results = ldap_handler
+                .do_search_or_dse(&make_user_search_request(
+                    LdapFilter::Equality("uid".to_string(), uid.to_uppercase()),
+                    vec!["uid"],
+                ))
+                .await
+                .unwrap();
+            match results.first() {
+                Some(LdapOp::SearchResultEntry(entry)) => assert_eq!(entry.dn, user_dn),
+                result => panic!("Unexpected result for {}: {:?}", dn, result),
+            }
+        }
+    }
+
+    /// Seeds a large SQLite database: run with `cargo test -- --ignored`. The entries are sent
+    /// one page at a time, so the memory used by the search doesn't grow with the directory.
+    #[tokio::test]
+    #[ignore]
+    async fn test_search_large_directory() {
+        use crate::domain::model;
+        use sea_orm::{ActiveValue::Set, EntityTrait};
+        const USERS: usize = 50_000;
+        let backend_handler =
+            SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        let now = chrono::Utc::now();
+        for chunk in (0..USERS).collect::<Vec<_>>().chunks(1000) {
+            model::User::insert_many(chunk.iter().map(|i| {
+                let user_id = format!("user{:05}", i);
+                model::users::ActiveModel {
+                    email: Set(format!("{}@example.com", user_id).into()),
+                    lowercase_email: Set(Some(format!("{}@example.com", user_id))),
+                    creation_date: Set(now),
+                    uuid: Set(Uuid::from_name_and_date(&user_id, &now)),
+                    user_id: Set(UserId::new(&user_id)),
+                    ..Default::default()
+                }
+            }))
+            .exec(&backend_handler.sql_pool)
+            .await
+            .unwrap();
+        }
+        let sink = search_all_users(backend_handler, SEARCH_PAGE_SIZE).await;
+        assert_eq!(sink.entries.len(), USERS);
+        assert_eq!(largest_page(&sink.entries), SEARCH_PAGE_SIZE as usize);
+    }
+
+    /// A directory with some attributes missing or empty, see `test_presence_filters`.
+    async fn setup_presence_handler() -> LdapHandler<SqlBackendHandler> {
+        let fixture = TestFixture::new().await;
+        let handler = fixture.handler;
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                email: Some("".into()),
+                display_name: Some("".to_owned()),
+                first_name: Some("".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("john"),
+                insert_attributes: vec![AttributeValue {
+                    name: "last_name".into(),
+                    value: Serialized::from(""),
+                }],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("patrick"),
+                avatar: Some(JpegPhoto::for_tests()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        insert_user(&handler, "secret", "password").await;
+        handler
+            .add_group_attribute(CreateAttributeRequest {
+                name: "club_name".into(),
+                attribute_type: AttributeType::String,
+                is_list: false,
+                is_visible: true,
+                is_editable: true,
+            })
+            .await
+            .unwrap();
+        for (group_id, club_name) in [
+            (fixture.groups[0], "Breakfast Club"),
+            (fixture.groups[1], ""),
+        ] {
+            handler
+                .update_group(UpdateGroupRequest {
+                    group_id,
+                    display_name: None,
+                    description: None,
+                    notes: None,
+                    delete_attributes: Vec::new(),
+                    insert_attributes: vec![AttributeValue {
+                        name: "club_name".into(),
+                        value: Serialized::from(club_name),
+                    }],
+                })
+                .await
+                .unwrap();
+        }
+        let mut ldap_handler = LdapHandler::new_for_tests(handler, "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        ldap_handler
+    }
+
+    async fn search_dns(
+        ldap_handler: &LdapHandler<SqlBackendHandler>,
+        base: &str,
+        scope: LdapSearchScope,
+        filter: LdapFilter,
+    ) -> Vec<String> {
+        let request = LdapSearchRequest {
+            scope,
+            ..make_search_request(base, filter, vec!["1.1"])
+        };
+        ldap_handler
+            .do_search(&request)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|op| match op {
+                LdapOp::SearchResultEntry(entry) => Some(entry.dn),
+                _ => None,
+            })
+            .sorted()
+            .collect()
+    }
+
+    fn present(attribute: &str) -> LdapFilter {
+        LdapFilter::Present(attribute.to_owned())
+    }
+
+    #[tokio::test]
+    async fn test_presence_filters() {
+        let ldap_handler = setup_presence_handler().await;
+        let users = |names: &[&str]| -> Vec<String> {
+            names
+                .iter()
+                .map(|name| format!("uid={},ou=people,dc=example,dc=com", name))
+                .collect()
+        };
+        let all_users = users(&["bob", "john", "nogroup", "patrick", "secret"]);
+        for (attribute, expected) in [
+            ("objectClass", all_users.clone()),
+            ("dn", all_users.clone()),
+            ("entryDN", all_users.clone()),
+            ("uid", all_users.clone()),
+            ("entryUUID", all_users.clone()),
+            ("createTimestamp", all_users.clone()),
+            ("modifyTimestamp", all_users.clone()),
+            ("mail", users(&["john", "nogroup", "patrick", "secret"])),
+            ("cn", users(&["john", "nogroup", "patrick", "secret"])),
+            (
+                "displayName",
+                users(&["john", "nogroup", "patrick", "secret"]),
+            ),
+            (
+                "givenName",
+                users(&["john", "nogroup", "patrick", "secret"]),
+            ),
+            ("sn", users(&["bob", "nogroup", "patrick", "secret"])),
+            ("jpegPhoto", users(&["patrick"])),
+            ("memberOf", users(&["bob", "john", "patrick"])),
+            ("authTimestamp", Vec::new()),
+            ("pwdChangedTime", users(&["secret"])),
+            ("host", Vec::new()),
+            ("unknown", Vec::new()),
+        ] {
+            assert_eq!(
+                search_dns(
+                    &ldap_handler,
+                    "ou=people,dc=example,dc=com",
+                    LdapSearchScope::OneLevel,
+                    present(attribute),
+                )
+                .await,
+                expected,
+                "{}",
+                attribute
+            );
+        }
+        let groups = |names: &[&str]| -> Vec<String> {
+            names
+                .iter()
+                .map(|name| format!("cn={},ou=groups,dc=example,dc=com", name))
+                .collect()
+        };
+        let all_groups = groups(&["Best Group", "Empty Group", "Worst Group"]);
+        for (attribute, expected) in [
+            ("objectClass", all_groups.clone()),
+            ("dn", all_groups.clone()),
+            ("entryDN", all_groups.clone()),
+            ("cn", all_groups.clone()),
+            ("uid", all_groups.clone()),
+            ("entryUUID", all_groups.clone()),
+            ("createTimestamp", all_groups.clone()),
+            ("member", groups(&["Best Group", "Worst Group"])),
+            ("uniqueMember", groups(&["Best Group", "Worst Group"])),
+            ("memberUid", groups(&["Best Group", "Worst Group"])),
+            ("club_name", groups(&["Best Group"])),
+            ("unknown", Vec::new()),
+        ] {
+            assert_eq!(
+                search_dns(
+                    &ldap_handler,
+                    "ou=groups,dc=example,dc=com",
+                    LdapSearchScope::OneLevel,
+                    present(attribute),
+                )
+                .await,
+                expected,
+                "{}",
+                attribute
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_member_filters() {
+        let fixture = TestFixture::new().await;
+        let mut ldap_handler = LdapHandler::new_for_tests(fixture.handler, "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        let groups = |names: &[&str]| -> Vec<String> {
+            names
+                .iter()
+                .map(|name| format!("cn={},ou=groups,dc=example,dc=com", name))
+                .collect()
+        };
+        let equality = |attribute: &str, value: &str| {
+            LdapFilter::Equality(attribute.to_owned(), value.to_owned())
+        };
+        for (filter, expected) in [
+            (
+                equality("member", "uid=bob,ou=people,dc=example,dc=com"),
+                groups(&["Best Group"]),
+            ),
+            // The DNs are compared normalized.
+            (
+                equality("uniqueMember", "UID=Patrick, OU=People,DC=Example, dc=COM"),
+                groups(&["Best Group", "Worst Group"]),
+            ),
+            (
+                equality("member", "uid = john ,ou=people,dc=example,dc=com"),
+                groups(&["Worst Group"]),
+            ),
+            (
+                equality("memberUid", "Patrick"),
+                groups(&["Best Group", "Worst Group"]),
+            ),
+            (equality("memberuid", "john"), groups(&["Worst Group"])),
+            (equality("memberUid", "nogroup"), Vec::new()),
+            // A memberUid is not a DN.
+            (
+                equality("memberUid", "uid=bob,ou=people,dc=example,dc=com"),
+                Vec::new(),
+            ),
+            (
+                LdapFilter::And(vec![
+                    equality("memberUid", "patrick"),
+                    equality("uniqueMember", "uid=bob,ou=people,dc=example,dc=com"),
+                ]),
+                groups(&["Best Group"]),
+            ),
+        ] {
+            assert_eq!(
+                search_dns(
+                    &ldap_handler,
+                    "ou=groups,dc=example,dc=com",
+                    LdapSearchScope::OneLevel,
+                    filter.clone(),
+                )
+                .await,
+                expected,
+                "{:?}",
+                filter
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_member_attributes() {
+        let fixture = TestFixture::new().await;
+        let mut ldap_handler = LdapHandler::new_for_tests(fixture.handler, "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        let expected = vec![
+            LdapPartialAttribute {
+                atype: "memberUid".to_owned(),
+                vals: vec![b"bob".to_vec(), b"patrick".to_vec()],
+            },
+            LdapPartialAttribute {
+                atype: "member".to_owned(),
+                vals: vec![
+                    b"uid=bob,ou=people,dc=example,dc=com".to_vec(),
+                    b"uid=patrick,ou=people,dc=example,dc=com".to_vec(),
+                ],
+            },
+            LdapPartialAttribute {
+                atype: "objectclass".to_owned(),
+                vals: vec![b"groupOfUniqueNames".to_vec(), b"groupOfNames".to_vec()],
+            },
+            LdapPartialAttribute {
+                atype: "uniquemember".to_owned(),
+                vals: vec![
+                    b"uid=bob,ou=people,dc=example,dc=com".to_vec(),
+                    b"uid=patrick,ou=people,dc=example,dc=com".to_vec(),
+                ],
+            },
+        ];
+        // Each attribute once, even when requested both by name and with "*".
+        for attributes in [
+            vec!["memberUid", "member", "objectclass", "uniquemember"],
+            vec!["memberUid", "*", "MEMBERUID", "member"],
+        ] {
+            let request = make_search_request(
+                "cn=Best Group,ou=groups,dc=example,dc=com",
+                LdapFilter::And(vec![]),
+                attributes.clone(),
+            );
+            let entry = ldap_handler
+                .do_search(&LdapSearchRequest {
+                    scope: LdapSearchScope::Base,
+                    ..request
+                })
+                .await
+                .unwrap()
+                .into_iter()
+                .find_map(|op| match op {
+                    LdapOp::SearchResultEntry(entry) => Some(entry),
+                    _ => None,
+                })
+                .unwrap();
+            assert_eq!(
+                entry
+                    .attributes
+                    .into_iter()
+                    .filter(|a| ["memberuid", "objectclass", "member", "uniquemember"]
+                        .contains(&a.atype.to_ascii_lowercase().as_str()))
+                    .collect::<Vec<_>>(),
+                expected,
+                "{:?}",
+                attributes
+            );
+        }
+    }
+
+    /// bob may log into web01 and db02, patrick into any host, the others into none.
+    async fn setup_host_handler(host_wildcard: bool) -> LdapHandler<SqlBackendHandler> {
+        let fixture = TestFixture::new().await;
+        for (user_id, hosts) in [("bob", vec!["web01", "DB02"]), ("patrick", vec!["*"])] {
+            fixture
+                .handler
+                .update_user(UpdateUserRequest {
+                    user_id: UserId::new(user_id),
+                    hosts: Some(hosts.into_iter().map(str::to_owned).collect()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        let mut ldap_handler = LdapHandler::new_for_tests(fixture.handler, "dc=example,dc=com")
+            .with_host_wildcard(host_wildcard);
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        ldap_handler
+    }
+
+    fn host(value: &str) -> LdapFilter {
+        LdapFilter::Equality("host".to_owned(), value.to_owned())
+    }
+
+    fn user_dns(names: &[&str]) -> Vec<String> {
+        names
+            .iter()
+            .map(|name| format!("uid={},ou=people,dc=example,dc=com", name))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_host_attribute() {
+        let mut ldap_handler = setup_host_handler(false).await;
+        let request = make_user_search_request(
+            LdapFilter::Equality("uid".to_owned(), "bob".to_owned()),
+            vec!["host", "objectClass"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "host".to_string(),
+                            vals: vec![b"db02".to_vec(), b"web01".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "objectClass".to_string(),
+                            vals: vec![
+                                b"inetOrgPerson".to_vec(),
+                                b"posixAccount".to_vec(),
+                                b"mailAccount".to_vec(),
+                                b"person".to_vec(),
+                                b"hostObject".to_vec(),
+                            ]
+                        },
+                    ]
+                }),
+                make_search_success()
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_host_filters_without_wildcard() {
+        let ldap_handler = setup_host_handler(false).await;
+        for (filter, expected) in [
+            (host("web01"), user_dns(&["bob"])),
+            (host("WEB01"), user_dns(&["bob"])),
+            (host("web"), Vec::new()),
+            (host("mail01"), Vec::new()),
+            // The wildcard is only matched by itself: the PAM module has to check it.
+            (host("*"), user_dns(&["patrick"])),
+            (host("web01,db02"), Vec::new()),
+            (present("host"), user_dns(&["bob", "patrick"])),
+            (
+                LdapFilter::Equality("objectClass".to_owned(), "hostObject".to_owned()),
+                user_dns(&["bob", "patrick"]),
+            ),
+        ] {
+            assert_eq!(
+                search_dns(
+                    &ldap_handler,
+                    "ou=people,dc=example,dc=com",
+                    LdapSearchScope::OneLevel,
+                    filter.clone(),
+                )
+                .await,
+                expected,
+                "{:?}",
+                filter
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_host_filters_with_wildcard() {
+        let ldap_handler = setup_host_handler(true).await;
+        for (filter, expected) in [
+            (host("web01"), user_dns(&["bob", "patrick"])),
+            (host("DB02"), user_dns(&["bob", "patrick"])),
+            // "*" matches any host, even an unknown one.
+            (host("mail01"), user_dns(&["patrick"])),
+            (host("*"), user_dns(&["patrick"])),
+            (
+                LdapFilter::And(vec![
+                    LdapFilter::Equality("uid".to_owned(), "bob".to_owned()),
+                    host("mail01"),
+                ]),
+                Vec::new(),
+            ),
+            (
+                LdapFilter::Not(Box::new(host("web01"))),
+                user_dns(&["john", "nogroup"]),
+            ),
+            (present("host"), user_dns(&["bob", "patrick"])),
+        ] {
+            assert_eq!(
+                search_dns(
+                    &ldap_handler,
+                    "ou=people,dc=example,dc=com",
+                    LdapSearchScope::OneLevel,
+                    filter.clone(),
+                )
+                .await,
+                expected,
+                "{:?}",
+                filter
+            );
+        }
+    }
+
+    /// bob has a phone number and an address, patrick only a mobile phone.
+    async fn setup_address_book_handler() -> LdapHandler<SqlBackendHandler> {
+        let fixture = TestFixture::new().await;
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                phone: Some("+1 555 010-9999".to_owned()),
+                postal_address: Some("1 Main Street\nSpringfield".to_owned()),
+                locality: Some("Springfield".to_owned()),
+                state_or_province: Some("Oregon".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("patrick"),
+                mobile: Some("+49 151 2345678".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let mut ldap_handler = LdapHandler::new_for_tests(fixture.handler, "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        ldap_handler
+    }
+
+    #[tokio::test]
+    async fn test_address_book_attributes() {
+        let mut ldap_handler = setup_address_book_handler().await;
+        let request = make_user_search_request(
+            LdapFilter::Equality("uid".to_owned(), "bob".to_owned()),
+            vec!["telephoneNumber", "mobile", "postalAddress", "l", "st"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "telephoneNumber".to_string(),
+                            vals: vec![b"+15550109999".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "postalAddress".to_string(),
+                            vals: vec![b"1 Main Street$Springfield".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "l".to_string(),
+                            vals: vec![b"Springfield".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "st".to_string(),
+                            vals: vec![b"Oregon".to_vec()],
+                        },
+                    ]
+                }),
+                make_search_success()
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_address_book_filters() {
+        let ldap_handler = setup_address_book_handler().await;
+        let substring = |field: &str, any: &str| {
+            LdapFilter::Substring(
+                field.to_owned(),
+                LdapSubstringFilter {
+                    initial: None,
+                    any: vec![any.to_owned()],
+                    final_: None,
+                },
+            )
+        };
+        let equality =
+            |field: &str, value: &str| LdapFilter::Equality(field.to_owned(), value.to_owned());
+        for (filter, expected) in [
+            // The formatting of the phone numbers is ignored.
+            (
+                equality("telephoneNumber", "+1 (555) 010-9999"),
+                user_dns(&["bob"]),
+            ),
+            (substring("telephoneNumber", "010 99"), user_dns(&["bob"])),
+            (equality("mobile", "+491512345678"), user_dns(&["patrick"])),
+            (equality("telephoneNumber", "+491512345678"), Vec::new()),
+            (
+                equality("postalAddress", "1 main street$springfield"),
+                user_dns(&["bob"]),
+            ),
+            (
+                substring("postalAddress", "street$spring"),
+                user_dns(&["bob"]),
+            ),
+            (equality("l", "SPRINGFIELD"), user_dns(&["bob"])),
+            (substring("st", "reg"), user_dns(&["bob"])),
+            (present("mobile"), user_dns(&["patrick"])),
+            (
+                LdapFilter::Not(Box::new(present("telephoneNumber"))),
+                user_dns(&["john", "nogroup", "patrick"]),
+            ),
+        ] {
+            assert_eq!(
+                search_dns(
+                    &ldap_handler,
+                    "ou=people,dc=example,dc=com",
+                    LdapSearchScope::OneLevel,
+                    filter.clone(),
+                )
+                .await,
+                expected,
+                "{:?}",
+                filter
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_presence_filters_negated() {
+        let ldap_handler = setup_presence_handler().await;
+        let not = |filter| LdapFilter::Not(Box::new(filter));
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "ou=people,dc=example,dc=com",
+                LdapSearchScope::OneLevel,
+                not(present("mail")),
+            )
+            .await,
+            vec!["uid=bob,ou=people,dc=example,dc=com"]
+        );
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "ou=people,dc=example,dc=com",
+                LdapSearchScope::OneLevel,
+                LdapFilter::And(vec![not(present("memberOf")), not(present("sn"))]),
+            )
+            .await,
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "ou=people,dc=example,dc=com",
+                LdapSearchScope::OneLevel,
+                not(present("memberOf")),
+            )
+            .await,
+            vec![
+                "uid=nogroup,ou=people,dc=example,dc=com",
+                "uid=secret,ou=people,dc=example,dc=com"
+            ]
+        );
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "ou=groups,dc=example,dc=com",
+                LdapSearchScope::OneLevel,
+                LdapFilter::Or(vec![not(present("member")), not(present("club_name"))]),
+            )
+            .await,
+            vec![
+                "cn=Empty Group,ou=groups,dc=example,dc=com",
+                "cn=Worst Group,ou=groups,dc=example,dc=com"
+            ]
+        );
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "ou=people,dc=example,dc=com",
+                LdapSearchScope::OneLevel,
+                not(present("unknown")),
+            )
+            .await
+            .len(),
+            5
+        );
+    }
+
+    #[tokio::test]
+    async fn test_presence_filters_include_the_ous() {
+        let ldap_handler = setup_presence_handler().await;
+        let dns = search_dns(
+            &ldap_handler,
+            "dc=example,dc=com",
+            LdapSearchScope::Subtree,
+            present("objectClass"),
+        )
+        .await;
+        assert_eq!(dns.len(), 2 + 5 + 3);
+        assert!(dns.contains(&"ou=people,dc=example,dc=com".to_owned()));
+        assert!(dns.contains(&"ou=groups,dc=example,dc=com".to_owned()));
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "ou=people,dc=example,dc=com",
+                LdapSearchScope::Subtree,
+                LdapFilter::Equality("objectClass".to_owned(), "organizationalUnit".to_owned()),
+            )
+            .await,
+            vec!["ou=people,dc=example,dc=com"]
+        );
+        // The OUs don't have the other attributes.
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "dc=example,dc=com",
+                LdapSearchScope::Subtree,
+                present("mail"),
+            )
+            .await
+            .len(),
+            4
+        );
+        // A base search of an OU only returns the OU.
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "ou=groups,dc=example,dc=com",
+                LdapSearchScope::Base,
+                present("objectClass"),
+            )
+            .await,
+            vec!["ou=groups,dc=example,dc=com"]
+        );
+        assert!(search_dns(
+            &ldap_handler,
+            "ou=groups,dc=example,dc=com",
+            LdapSearchScope::Base,
+            present("member"),
+        )
+        .await
+        .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_grandfathered_group_names_are_escaped() {
+        use crate::domain::model;
+        use sea_orm::{ActiveModelTrait, Set};
+        let fixture = TestFixture::new().await;
+        let handler = fixture.handler;
+        // Created before the validation of the group names: insert it directly.
+        let now = chrono::Utc::now();
+        let group_id = model::groups::ActiveModel {
+            display_name: Set(GroupName::from("dev,ops")),
+            lowercase_display_name: Set("dev,ops".to_owned()),
+            creation_date: Set(now),
+            uuid: Set(Uuid::from_name_and_date("dev,ops", &now)),
+            ..Default::default()
+        }
+        .insert(&handler.sql_pool)
+        .await
+        .unwrap()
+        .group_id;
+        insert_membership(&handler, group_id, "bob").await;
+        let mut ldap_handler = LdapHandler::new_for_tests(handler, "dc=example,dc=com");
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        let group_dn = r"cn=dev\,ops,ou=groups,dc=example,dc=com";
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "ou=groups,dc=example,dc=com",
+                LdapSearchScope::OneLevel,
+                LdapFilter::Equality("cn".to_owned(), "dev,ops".to_owned()),
+            )
+            .await,
+            vec![group_dn]
+        );
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                group_dn,
+                LdapSearchScope::Base,
+                present("objectClass"),
+            )
+            .await,
+            vec![group_dn]
+        );
+        // The escapes can be written differently, e.g. in hex.
+        for dn in [group_dn, r"cn=DEV\2Cops,ou=groups,dc=example,dc=com"] {
+            assert_eq!(
+                search_dns(
+                    &ldap_handler,
+                    "ou=people,dc=example,dc=com",
+                    LdapSearchScope::OneLevel,
+                    LdapFilter::Equality("memberOf".to_owned(), dn.to_owned()),
+                )
+                .await,
+                vec!["uid=bob,ou=people,dc=example,dc=com"]
+            );
+        }
+        let request = make_search_request(
+            "ou=people,dc=example,dc=com",
+            LdapFilter::Equality("uid".to_owned(), "bob".to_owned()),
+            vec!["memberOf"],
+        );
+        let member_of = ldap_handler
+            .do_search(&request)
+            .await
+            .unwrap()
+            .into_iter()
+            .find_map(|op| match op {
+                LdapOp::SearchResultEntry(entry) => Some(entry.attributes),
+                _ => None,
+            })
+            .unwrap()
+            .into_iter()
+            .flat_map(|attribute| attribute.vals)
+            .sorted()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            member_of,
+            vec![
+                b"cn=Best Group,ou=groups,dc=example,dc=com".to_vec(),
+                group_dn.as_bytes().to_vec(),
+            ]
+        );
+    }
+
+    /// With `login_identifier = "email"`: bob has a password, noemail has no email.
+    async fn setup_email_login_handler() -> LdapHandler<SqlBackendHandler> {
+        let mut config = get_default_config();
+        config.login_identifier = LoginIdentifier::Email;
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        insert_user(&handler, "bob", "bob00").await;
+        insert_user_no_password(&handler, "patrick").await;
+        handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("noemail"),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        LdapHandler::new_for_tests(handler, "dc=example,dc=com")
+            .with_login_identifier(LoginIdentifier::Email)
+    }
+
+    #[tokio::test]
+    async fn test_bind_with_email() {
+        let mut ldap_handler = setup_email_login_handler().await;
+        for (dn, password, expected) in [
+            (
+                "uid=Bob@bob.bob,ou=people,dc=example,dc=com",
+                "bob00",
+                LdapResultCode::Success,
+            ),
+            (
+                "uid=bob,ou=people,dc=example,dc=com",
+                "bob00",
+                LdapResultCode::Success,
+            ),
+            (
+                "uid=bob@bob.bob,ou=people,dc=example,dc=com",
+                "wrong",
+                LdapResultCode::InvalidCredentials,
+            ),
+            (
+                "uid=patrick@bob.bob,ou=people,dc=example,dc=com",
+                "bob00",
+                LdapResultCode::InvalidCredentials,
+            ),
+        ] {
+            let request = LdapBindRequest {
+                dn: dn.to_owned(),
+                cred: LdapBindCred::Simple(password.to_owned()),
+            };
+            assert_eq!(ldap_handler.do_bind(&request).await.0, expected, "{}", dn);
+        }
+        // The permissions are the ones of the user bound with their email.
+        let request = LdapBindRequest {
+            dn: "uid=bob@bob.bob,ou=people,dc=example,dc=com".to_owned(),
+            cred: LdapBindCred::Simple("bob00".to_owned()),
+        };
+        ldap_handler.do_bind(&request).await;
+        assert_eq!(
+            ldap_handler.user_info.as_ref().map(|info| &info.user),
+            Some(&UserId::new("bob"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_uid_is_email() {
+        let mut ldap_handler = setup_email_login_handler().await;
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        let request = make_user_search_request(LdapFilter::And(vec![]), vec!["uid", "mail"]);
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "uid".to_string(),
+                            vals: vec![b"bob@bob.bob".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "mail".to_string(),
+                            vals: vec![b"bob@bob.bob".to_vec()],
+                        },
+                    ],
+                }),
+                // Without an email, the uid stays the user ID.
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=noemail,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "uid".to_string(),
+                        vals: vec![b"noemail".to_vec()],
+                    }],
+                }),
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=patrick,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "uid".to_string(),
+                            vals: vec![b"patrick@bob.bob".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "mail".to_string(),
+                            vals: vec![b"patrick@bob.bob".to_vec()],
+                        },
+                    ],
+                }),
+                make_search_success(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_uid_filters_with_email() {
+        let mut ldap_handler = setup_email_login_handler().await;
+        ldap_handler.user_info = Some(ValidationResults::admin());
+        let uid = |value: &str| LdapFilter::Equality("uid".to_owned(), value.to_owned());
+        for (filter, expected) in [
+            (uid("Bob@bob.bob"), vec!["bob"]),
+            // The user IDs keep matching.
+            (uid("bob"), vec!["bob"]),
+            (uid("noemail"), vec!["noemail"]),
+            (
+                LdapFilter::Substring(
+                    "uid".to_owned(),
+                    LdapSubstringFilter {
+                        initial: None,
+                        any: vec![],
+                        final_: Some("@bob.bob".to_owned()),
+                    },
+                ),
+                vec!["bob", "patrick"],
+            ),
+            (
+                LdapFilter::Substring(
+                    "uid".to_owned(),
+                    LdapSubstringFilter {
+                        initial: Some("no".to_owned()),
+                        any: vec![],
+                        final_: None,
+                    },
+                ),
+                vec!["noemail"],
+            ),
+        ] {
+            let expected = expected
+                .into_iter()
+                .map(|user_id| format!("uid={},ou=people,dc=example,dc=com", user_id))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                search_dns(
+                    &ldap_handler,
+                    "ou=people,dc=example,dc=com",
+                    LdapSearchScope::OneLevel,
+                    filter.clone(),
+                )
+                .await,
+                expected,
+                "{:?}",
+                filter
+            );
+        }
+    }
+
+    /// The "app" account only sees "Best Group", i.e. bob and patrick. The admin has a scope too,
+    /// which is ignored.
+    async fn setup_scoped_handler(user_info: ValidationResults) -> LdapHandler<SqlBackendHandler> {
+        let fixture = TestFixture::new().await;
+        let bind_scopes = HashMap::from([
+            (UserId::new("app"), vec![GroupName::from("best group")]),
+            (UserId::new("admin"), vec![GroupName::from("Worst Group")]),
+        ]);
+        let mut ldap_handler = LdapHandler::new(
+            AccessControlledBackendHandler::new(fixture.handler).with_bind_scopes(bind_scopes),
+            "dc=example,dc=com".to_owned(),
+            vec![],
+            vec![],
+        );
+        ldap_handler.user_info = Some(user_info);
+        ldap_handler
+    }
+
+    #[tokio::test]
+    async fn test_bind_scope_filters_users() {
+        let ldap_handler = setup_scoped_handler(ValidationResults {
+            user: UserId::new("app"),
+            permission: Permission::Readonly,
+        })
+        .await;
+        let users = |names: &[&str]| -> Vec<String> {
+            names
+                .iter()
+                .map(|name| format!("uid={},ou=people,dc=example,dc=com", name))
+                .collect()
+        };
+        let eq = |attribute: &str, value: &str| {
+            LdapFilter::Equality(attribute.to_owned(), value.to_owned())
+        };
+        for filter in [
+            LdapFilter::And(vec![]),
+            LdapFilter::Present("objectClass".to_owned()),
+            LdapFilter::Or(vec![
+                eq("uid", "john"),
+                eq("uid", "bob"),
+                eq("uid", "patrick"),
+            ]),
+            LdapFilter::Not(Box::new(eq("uid", "nogroup"))),
+        ] {
+            assert_eq!(
+                search_dns(
+                    &ldap_handler,
+                    "ou=people,dc=example,dc=com",
+                    LdapSearchScope::OneLevel,
+                    filter.clone(),
+                )
+                .await,
+                users(&["bob", "patrick"]),
+                "{:?}",
+                filter
+            );
+        }
+        // No filter finds the users outside of the scope.
+        for filter in [
+            eq("uid", "john"),
+            eq("mail", "john@bob.bob"),
+            eq("cn", "display john"),
+            eq("dn", "uid=john,ou=people,dc=example,dc=com"),
+            eq("memberOf", "cn=Worst Group,ou=groups,dc=example,dc=com"),
+            LdapFilter::Not(Box::new(eq(
+                "memberOf",
+                "cn=Best Group,ou=groups,dc=example,dc=com",
+            ))),
+            LdapFilter::Substring(
+                "uid".to_owned(),
+                LdapSubstringFilter {
+                    initial: Some("no".to_owned()),
+                    any: vec![],
+                    final_: None,
+                },
+            ),
+        ] {
+            assert_eq!(
+                search_dns(
+                    &ldap_handler,
+                    "dc=example,dc=com",
+                    LdapSearchScope::Subtree,
+                    LdapFilter::And(vec![eq("objectClass", "person"), filter.clone()]),
+                )
+                .await,
+                Vec::<String>::new(),
+                "{:?}",
+                filter
+            );
+        }
+        for user in ["john", "nogroup"] {
+            assert_eq!(
+                search_dns(
+                    &ldap_handler,
+                    &format!("uid={},ou=people,dc=example,dc=com", user),
+                    LdapSearchScope::Base,
+                    LdapFilter::And(vec![]),
+                )
+                .await,
+                Vec::<String>::new()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_scope_filters_groups() {
+        let ldap_handler = setup_scoped_handler(ValidationResults {
+            user: UserId::new("App"),
+            permission: Permission::Readonly,
+        })
+        .await;
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "ou=groups,dc=example,dc=com",
+                LdapSearchScope::OneLevel,
+                LdapFilter::And(vec![]),
+            )
+            .await,
+            vec!["cn=Best Group,ou=groups,dc=example,dc=com"]
+        );
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "ou=groups,dc=example,dc=com",
+                LdapSearchScope::OneLevel,
+                LdapFilter::Equality("cn".to_owned(), "Worst Group".to_owned()),
+            )
+            .await,
+            Vec::<String>::new()
+        );
+        // Patrick is in both groups: only the one in the scope is listed.
+        let request = make_user_search_request(
+            LdapFilter::Equality("uid".to_owned(), "patrick".to_owned()),
+            vec!["memberOf"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=patrick,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "memberOf".to_string(),
+                        vals: vec![b"cn=Best Group,ou=groups,dc=example,dc=com".to_vec()],
+                    }],
+                }),
+                make_search_success(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bind_scope_ignored_for_admins() {
+        let ldap_handler = setup_scoped_handler(ValidationResults::admin()).await;
+        assert_eq!(
+            search_dns(
+                &ldap_handler,
+                "ou=people,dc=example,dc=com",
+                LdapSearchScope::OneLevel,
+                LdapFilter::And(vec![]),
+            )
+            .await,
+            vec![
+                "uid=bob,ou=people,dc=example,dc=com",
+                "uid=john,ou=people,dc=example,dc=com",
+                "uid=nogroup,ou=people,dc=example,dc=com",
+                "uid=patrick,ou=people,dc=example,dc=com",
+            ]
         );
     }
 }
@@ -6,20 +6,76 @@ use crate::{
     },
     infra::{
         access_control::AccessControlledBackendHandler,
-        configuration::{Configuration, LdapsOptions},
-        ldap_handler::LdapHandler,
+        configuration::{Configuration, LdapsOptions, LoginIdentifier},
+        ldap_handler::{LdapHandler, ResponseSink, TlsState},
+        request_id::new_request_id,
+        shutdown::ShutdownToken,
+        systemd::ActivatedSockets,
+        tls,
     },
 };
 use actix_rt::net::TcpStream;
 use actix_server::ServerBuilder;
 use actix_service::{fn_service, ServiceFactoryExt};
-use anyhow::{anyhow, Context, Result};
-use ldap3_proto::{control::LdapControl, proto::LdapMsg, proto::LdapOp, LdapCodec};
-use rustls::PrivateKey;
+use anyhow::{ensure, Context, Result};
+use async_trait::async_trait;
+use ldap3_proto::{
+    control::LdapControl,
+    proto::{LdapExtendedResponse, LdapMsg, LdapOp, LdapResult, LdapResultCode},
+    LdapCodec,
+};
 use tokio_rustls::TlsAcceptor as RustlsTlsAcceptor;
 use tokio_util::codec::{FramedRead, FramedWrite};
 use tracing::{debug, error, info, instrument};
 
+/// Writes the responses to a request as they are produced.
+struct ResponseWriter<'w, Writer> {
+    writer: &'w mut Writer,
+    msgid: i32,
+    /// The number of entries of a search, for the paged results control.
+    entries: i64,
+    error: Option<anyhow::Error>,
+}
+
+#[async_trait(?Send)]
+impl<Writer> ResponseSink for ResponseWriter<'_, Writer>
+where
+    Writer: futures_util::Sink<LdapMsg> + Unpin,
+    <Writer as futures_util::Sink<LdapMsg>>::Error: std::error::Error + Send + Sync + 'static,
+{
+    async fn send(&mut self, response: LdapOp) -> bool {
+        use futures_util::SinkExt;
+        if self.error.is_some() {
+            return false;
+        }
+        debug!(?response);
+        let controls = match &response {
+            LdapOp::SearchResultEntry(_) => {
+                self.entries += 1;
+                vec![]
+            }
+            LdapOp::SearchResultDone(_) => vec![LdapControl::SimplePagedResults {
+                size: self.entries,
+                cookie: vec![],
+            }],
+            _ => vec![],
+        };
+        if let Err(e) = self
+            .writer
+            .send(LdapMsg {
+                msgid: self.msgid,
+                op: response,
+                ctrl: controls,
+            })
+            .await
+        {
+            self.error = Some(anyhow::Error::new(e).context("while sending a response"));
+            return false;
+        }
+        true
+    }
+}
+
 #[instrument(skip_all, level = "info", name = "LDAP request")]
 async fn handle_ldap_message<Backend, Writer>(
     msg: Result<LdapMsg, std::io::Error>,
@@ -31,164 +87,225 @@ where
     Writer: futures_util::Sink<LdapMsg> + Unpin,
     <Writer as futures_util::Sink<LdapMsg>>::Error: std::error::Error + Send + Sync + 'static,
 {
-    use futures_util::SinkExt;
     let msg = msg.context("while receiving LDAP op")?;
     debug!(?msg);
-    match session.handle_ldap_message(msg.op).await {
-        None => return Ok(false),
-        Some(result) => {
-            if result.is_empty() {
-                debug!("No response");
-            }
-            let results: i64 = result.len().try_into().unwrap();
-            for response in result.into_iter() {
-                debug!(?response);
-                let controls = if matches!(response, LdapOp::SearchResultDone(_)) {
-                    vec![LdapControl::SimplePagedResults {
-                        size: results - 1, // Avoid counting SearchResultDone as a result
-                        cookie: vec![],
-                    }]
-                } else {
-                    vec![]
-                };
-                resp.send(LdapMsg {
-                    msgid: msg.msgid,
-                    op: response,
-                    ctrl: controls,
-                })
-                .await
-                .context("while sending a response: {:#}")?
-            }
+    let mut writer = ResponseWriter {
+        writer: resp,
+        msgid: msg.msgid,
+        entries: 0,
+        error: None,
+    };
+    let keep_going = session.handle_ldap_message_into(msg.op, &mut writer).await;
+    match writer.error {
+        Some(e) => Err(e),
+        None => Ok(keep_going),
+    }
+}
 
-            resp.flush()
-                .await
-                .context("while flushing responses: {:#}")?
-        }
+/// Notice of Disconnection (RFC 4511, section 4.4.1), sent to the idle clients when the server
+/// shuts down.
+fn notice_of_disconnection() -> LdapMsg {
+    LdapMsg {
+        msgid: 0,
+        op: LdapOp::ExtendedResponse(LdapExtendedResponse {
+            res: LdapResult {
+                code: LdapResultCode::Unavailable,
+                matcheddn: "".to_string(),
+                message: "The server is shutting down".to_string(),
+                referral: vec![],
+            },
+            name: Some("1.3.6.1.4.1.1466.20036".to_string()),
+            value: None,
+        }),
+        ctrl: vec![],
     }
-    Ok(true)
 }
 
-#[instrument(skip_all, level = "info", name = "LDAP session")]
-async fn handle_ldap_stream<Stream, Backend>(
+/// The encryption of a connection.
+#[derive(Clone)]
+enum Encryption {
+    /// Plain LDAP.
+    None,
+    /// Plain LDAP, that the client can upgrade with StartTLS.
+    StartTls(RustlsTlsAcceptor),
+    /// LDAPS: the stream is already encrypted.
+    Ldaps,
+}
+
+/// Serves the requests until the client disconnects, or the server shuts down. Returns the
+/// stream if the client requested StartTLS: it has to be upgraded before the next request.
+async fn serve_requests<Stream, Backend>(
     stream: Stream,
-    backend_handler: Backend,
-    ldap_base_dn: String,
-    ignored_user_attributes: Vec<AttributeName>,
-    ignored_group_attributes: Vec<AttributeName>,
-) -> Result<Stream>
+    session: &mut LdapHandler<Backend>,
+    shutdown: &ShutdownToken,
+) -> Result<Option<Stream>>
 where
     Backend: BackendHandler + LoginHandler + OpaqueHandler + 'static,
     Stream: tokio::io::AsyncRead + tokio::io::AsyncWrite + std::marker::Unpin,
 {
+    use futures_util::SinkExt;
     use tokio_stream::StreamExt;
     let (r, w) = tokio::io::split(stream);
     // Configure the codec etc.
     let mut requests = FramedRead::new(r, LdapCodec::default());
     let mut resp = FramedWrite::new(w, LdapCodec::default());
 
-    let mut session = LdapHandler::new(
-        AccessControlledBackendHandler::new(backend_handler),
-        ldap_base_dn,
-        ignored_user_attributes,
-        ignored_group_attributes,
-    );
-
-    while let Some(msg) = requests.next().await {
-        if !handle_ldap_message(msg, &mut resp, &mut session)
+    loop {
+        // A request in progress is always completed: the shutdown is only checked while waiting
+        // for the next one.
+        let msg = tokio::select! {
+            msg = requests.next() => msg,
+            _ = shutdown.cancelled() => {
+                debug!("Shutting down, closing the connection");
+                resp.send(notice_of_disconnection())
+                    .await
+                    .context("while sending the notice of disconnection")?;
+                return Ok(None);
+            }
+        };
+        let msg = match msg {
+            Some(msg) => msg,
+            None => return Ok(None),
+        };
+        if !handle_ldap_message(msg, &mut resp, session)
             .await
             .context("while handling incoming messages")?
         {
-            break;
+            return Ok(None);
+        }
+        if session.tls_state() == TlsState::Upgrading {
+            // The client must wait for the response before starting the handshake.
+            ensure!(
+                requests.read_buffer().is_empty(),
+                "Received a request after StartTLS, before the TLS handshake"
+            );
+            return Ok(Some(requests.into_inner().unsplit(resp.into_inner())));
         }
     }
-    Ok(requests.into_inner().unsplit(resp.into_inner()))
 }
 
-fn read_private_key(key_file: &str) -> Result<PrivateKey> {
-    use rustls_pemfile::{ec_private_keys, pkcs8_private_keys, rsa_private_keys};
-    use std::{fs::File, io::BufReader};
-    pkcs8_private_keys(&mut BufReader::new(File::open(key_file)?))
-        .map_err(anyhow::Error::from)
-        .and_then(|keys| {
-            keys.into_iter()
-                .next()
-                .ok_or_else(|| anyhow!("No PKCS8 key"))
-        })
-        .or_else(|_| {
-            rsa_private_keys(&mut BufReader::new(File::open(key_file)?))
-                .map_err(anyhow::Error::from)
-                .and_then(|keys| {
-                    keys.into_iter()
-                        .next()
-                        .ok_or_else(|| anyhow!("No PKCS1 key"))
-                })
-        })
-        .or_else(|_| {
-            ec_private_keys(&mut BufReader::new(File::open(key_file)?))
-                .map_err(anyhow::Error::from)
-                .and_then(|keys| keys.into_iter().next().ok_or_else(|| anyhow!("No EC key")))
-        })
-        .with_context(|| {
-            format!(
-                "Cannot read either PKCS1, PKCS8 or EC private key from {}",
-                key_file
-            )
-        })
-        .map(rustls::PrivateKey)
-}
+/// The connection ID has the same format as the HTTP request IDs.
+#[instrument(
+    skip_all,
+    level = "info",
+    name = "LDAP session",
+    fields(connection_id = %new_request_id())
+)]
+async fn handle_ldap_stream<Stream, Backend>(
+    stream: Stream,
+    backend_handler: AccessControlledBackendHandler<Backend>,
+    ldap_base_dn: String,
+    ignored_user_attributes: Vec<AttributeName>,
+    ignored_group_attributes: Vec<AttributeName>,
+    host_wildcard: bool,
+    login_identifier: LoginIdentifier,
+    read_only: bool,
+    shutdown: ShutdownToken,
+    encryption: Encryption,
+) -> Result<()>
+where
+    Backend: BackendHandler + LoginHandler + OpaqueHandler + 'static,
+    Stream: tokio::io::AsyncRead + tokio::io::AsyncWrite + std::marker::Unpin,
+{
+    let mut session = LdapHandler::new(
+        backend_handler,
+        ldap_base_dn,
+        ignored_user_attributes,
+        ignored_group_attributes,
+    )
+    .with_host_wildcard(host_wildcard)
+    .with_login_identifier(login_identifier)
+    .with_read_only(read_only)
+    .with_tls(match &encryption {
+        Encryption::None => TlsState::Unavailable,
+        Encryption::StartTls(_) => TlsState::Available,
+        Encryption::Ldaps => TlsState::Established,
+    });
 
-pub fn read_certificates(
-    ldaps_options: &LdapsOptions,
-) -> Result<(Vec<rustls::Certificate>, rustls::PrivateKey)> {
-    use std::{fs::File, io::BufReader};
-    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&ldaps_options.cert_file)?))?
-        .into_iter()
-        .map(rustls::Certificate)
-        .collect::<Vec<_>>();
-    let private_key = read_private_key(&ldaps_options.key_file)?;
-    Ok((certs, private_key))
+    let Some(stream) = serve_requests(stream, &mut session, &shutdown).await? else {
+        return Ok(());
+    };
+    // The handler only accepts StartTLS with an acceptor.
+    let Encryption::StartTls(tls_acceptor) = encryption else {
+        unreachable!()
+    };
+    debug!("StartTLS: upgrading the connection");
+    let tls_stream = tls_acceptor
+        .accept(stream)
+        .await
+        .context("during the StartTLS handshake")?;
+    session.set_tls_established();
+    serve_requests(tls_stream, &mut session, &shutdown).await?;
+    Ok(())
 }
 
 fn get_tls_acceptor(ldaps_options: &LdapsOptions) -> Result<RustlsTlsAcceptor> {
-    let (certs, private_key) = read_certificates(ldaps_options)?;
-    let server_config = std::sync::Arc::new(
-        rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(certs, private_key)?,
-    );
-    Ok(server_config.into())
+    let server_config = tls::get_server_config(&ldaps_options.cert_file, &ldaps_options.key_file)?;
+    Ok(std::sync::Arc::new(server_config).into())
 }
 
 pub fn build_ldap_server<Backend>(
     config: &Configuration,
     backend_handler: Backend,
     server_builder: ServerBuilder,
+    shutdown: ShutdownToken,
+    sockets: &mut ActivatedSockets,
 ) -> Result<ServerBuilder>
 where
     Backend: BackendHandler + LoginHandler + OpaqueHandler + Clone + 'static,
 {
     let context = (
-        backend_handler,
+        AccessControlledBackendHandler::new(backend_handler)
+            .with_attribute_visibility(config.attribute_visibility.clone())
+            // Only the LDAP searches are scoped: the web UI lists everything.
+            .with_bind_scopes(config.ldap_bind_scopes.clone()),
         config.ldap_base_dn.clone(),
         config.ignored_user_attributes.clone(),
         config.ignored_group_attributes.clone(),
+        config.ldap_host_wildcard,
+        config.login_identifier,
+        config.replication.is_replica(),
+        shutdown,
     );
 
     let context_for_tls = context.clone();
+    let encryption = if config.ldaps_options.start_tls {
+        Encryption::StartTls(
+            get_tls_acceptor(&config.ldaps_options)
+                .context("while setting up the StartTLS certificate")?,
+        )
+    } else {
+        Encryption::None
+    };
 
     let binder = move || {
         let context = context.clone();
+        let encryption = encryption.clone();
         fn_service(move |stream: TcpStream| {
             let context = context.clone();
+            let encryption = encryption.clone();
             async move {
-                let (handler, base_dn, ignored_user_attributes, ignored_group_attributes) = context;
+                let (
+                    handler,
+                    base_dn,
+                    ignored_user_attributes,
+                    ignored_group_attributes,
+                    host_wildcard,
+                    login_identifier,
+                    read_only,
+                    shutdown,
+                ) = context;
                 handle_ldap_stream(
                     stream,
                     handler,
                     base_dn,
                     ignored_user_attributes,
                     ignored_group_attributes,
+                    host_wildcard,
+                    login_identifier,
+                    read_only,
+                    shutdown,
+                    encryption,
                 )
                 .await
             }
@@ -197,8 +314,13 @@ where
     };
 
     info!("Starting the LDAP server on port {}", config.ldap_port);
-    let server_builder = server_builder
-        .bind("ldap", (config.ldap_host.clone(), config.ldap_port), binder)
+    let server_builder = sockets
+        .bind(
+            server_builder,
+            "ldap",
+            (config.ldap_host.clone(), config.ldap_port),
+            binder,
+        )
         .with_context(|| format!("while binding to the port {}", config.ldap_port));
     if config.ldaps_options.enabled {
         let tls_context = (
@@ -212,7 +334,16 @@ where
                 let tls_context = tls_context.clone();
                 async move {
                     let (
-                        (handler, base_dn, ignored_user_attributes, ignored_group_attributes),
+                        (
+                            handler,
+                            base_dn,
+                            ignored_user_attributes,
+                            ignored_group_attributes,
+                            host_wildcard,
+                            login_identifier,
+                            read_only,
+                            shutdown,
+                        ),
                         tls_acceptor,
                     ) = tls_context;
                     let tls_stream = tls_acceptor.accept(stream).await?;
@@ -222,6 +353,11 @@ where
                         base_dn,
                         ignored_user_attributes,
                         ignored_group_attributes,
+                        host_wildcard,
+                        login_identifier,
+                        read_only,
+                        shutdown,
+                        Encryption::Ldaps,
                     )
                     .await
                 }
@@ -234,14 +370,206 @@ where
             config.ldaps_options.port
         );
         server_builder.and_then(|s| {
-            s.bind(
-                "ldaps",
-                (config.ldap_host.clone(), config.ldaps_options.port),
-                tls_binder,
-            )
-            .with_context(|| format!("while binding to the port {}", config.ldaps_options.port))
+            sockets
+                .bind(
+                    s,
+                    "ldaps",
+                    (config.ldap_host.clone(), config.ldaps_options.port),
+                    tls_binder,
+                )
+                .with_context(|| format!("while binding to the port {}", config.ldaps_options.port))
         })
     } else {
         server_builder
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::types::UserId,
+        infra::{
+            configuration::ConfigurationBuilder, healthcheck::get_tls_connector,
+            ldap_handler::START_TLS_OID, test_utils::MockTestBackendHandler,
+        },
+    };
+    use futures_util::SinkExt;
+    use ldap3_proto::proto::{
+        LdapBindCred, LdapBindRequest, LdapBindResponse, LdapExtendedRequest,
+    };
+    use pretty_assertions::assert_eq;
+    use std::{collections::HashSet, time::Duration};
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::Framed;
+
+    fn slow_bind_handler() -> MockTestBackendHandler {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_clone().returning(slow_bind_handler);
+        mock.expect_bind().returning(|_| {
+            // Blocks the server's worker thread, not the test.
+            std::thread::sleep(Duration::from_millis(500));
+            Ok(UserId::new("test"))
+        });
+        mock.expect_get_user_groups()
+            .returning(|_| Ok(HashSet::new()));
+        mock
+    }
+
+    #[actix_rt::test]
+    async fn test_graceful_shutdown() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut config = ConfigurationBuilder::for_tests();
+        config.ldap_host = "127.0.0.1".to_owned();
+        config.ldap_port = port;
+        let shutdown = ShutdownToken::new();
+        let server = build_ldap_server(
+            &config,
+            slow_bind_handler(),
+            actix_server::Server::build(),
+            shutdown.clone(),
+            &mut ActivatedSockets::default(),
+        )
+        .unwrap()
+        .workers(1)
+        .shutdown_timeout(5)
+        .disable_signals()
+        .run();
+        let handle = server.handle();
+        let server = actix_rt::spawn(server);
+
+        let (r, w) = TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap()
+            .into_split();
+        let mut requests = FramedWrite::new(w, LdapCodec::default());
+        let mut responses = FramedRead::new(r, LdapCodec::default());
+        requests
+            .send(LdapMsg {
+                msgid: 1,
+                op: LdapOp::BindRequest(LdapBindRequest {
+                    dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                    cred: LdapBindCred::Simple("pass".to_string()),
+                }),
+                ctrl: vec![],
+            })
+            .await
+            .unwrap();
+        // Start shutting down while the bind is in progress.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.cancel();
+        let stop = actix_rt::spawn(handle.stop(true));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        TcpStream::connect(("127.0.0.1", port)).await.unwrap_err();
+
+        // The bind completes, then the connection is closed with a notice.
+        let response = responses.next().await.unwrap().unwrap();
+        assert_eq!(response.msgid, 1);
+        assert!(matches!(
+            response.op,
+            LdapOp::BindResponse(LdapBindResponse {
+                res: LdapResult {
+                    code: LdapResultCode::Success,
+                    ..
+                },
+                ..
+            })
+        ));
+        assert_eq!(
+            responses.next().await.unwrap().unwrap(),
+            notice_of_disconnection()
+        );
+        assert!(responses.next().await.is_none());
+        stop.await.unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    fn start_tls_handler() -> MockTestBackendHandler {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_clone().returning(start_tls_handler);
+        mock
+    }
+
+    #[actix_rt::test]
+    async fn test_start_tls() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut config = ConfigurationBuilder::for_tests();
+        config.ldap_host = "127.0.0.1".to_owned();
+        config.ldap_port = port;
+        config.ldaps_options.start_tls = true;
+        config.ldaps_options.cert_file = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/infra/testdata/localhost_cert.pem"
+        )
+        .to_owned();
+        config.ldaps_options.key_file = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/infra/testdata/localhost_key.pem"
+        )
+        .to_owned();
+        let server = build_ldap_server(
+            &config,
+            start_tls_handler(),
+            actix_server::Server::build(),
+            ShutdownToken::new(),
+            &mut ActivatedSockets::default(),
+        )
+        .unwrap()
+        .workers(1)
+        .disable_signals()
+        .run();
+        let handle = server.handle();
+        let server = actix_rt::spawn(server);
+        let start_tls = |msgid| LdapMsg {
+            msgid,
+            op: LdapOp::ExtendedRequest(LdapExtendedRequest {
+                name: START_TLS_OID.to_string(),
+                value: None,
+            }),
+            ctrl: vec![],
+        };
+        let response_code = |msg: LdapMsg| match msg.op {
+            LdapOp::ExtendedResponse(LdapExtendedResponse { res, name, .. }) => {
+                assert_eq!(name.as_deref(), Some(START_TLS_OID));
+                res.code
+            }
+            op => panic!("{:?}", op),
+        };
+
+        let mut client = Framed::new(
+            TcpStream::connect(("127.0.0.1", port)).await.unwrap(),
+            LdapCodec::default(),
+        );
+        client.send(start_tls(1)).await.unwrap();
+        let response = client.next().await.unwrap().unwrap();
+        assert_eq!(response.msgid, 1);
+        assert_eq!(response_code(response), LdapResultCode::Success);
+
+        // The same session goes on over TLS, with the certificate of the configuration.
+        let tls_stream = get_tls_connector(&config.ldaps_options)
+            .unwrap()
+            .connect(
+                rustls::ServerName::try_from("localhost").unwrap(),
+                client.into_inner(),
+            )
+            .await
+            .unwrap();
+        let mut client = Framed::new(tls_stream, LdapCodec::default());
+        client.send(start_tls(2)).await.unwrap();
+        let response = client.next().await.unwrap().unwrap();
+        assert_eq!(response.msgid, 2);
+        assert_eq!(response_code(response), LdapResultCode::OperationsError);
+        drop(client);
+
+        handle.stop(true).await;
+        server.await.unwrap().unwrap();
+    }
+}
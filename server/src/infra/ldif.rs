@@ -0,0 +1,179 @@
+//! A parser for the LDAP Data Interchange Format (RFC 2849), as produced by `ldapsearch -LLL` or
+//! `slapcat`. Only content records are supported, not change records.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdifEntry {
+    pub dn: String,
+    /// The attribute names are lowercased, and stripped of their options (e.g. ";binary").
+    pub attributes: Vec<(String, Vec<u8>)>,
+}
+
+impl LdifEntry {
+    /// All the values of the attribute.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.attributes
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// The first value of the attribute, if it is valid UTF-8.
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.get_all(name)
+            .next()
+            .and_then(|v| std::str::from_utf8(v).ok())
+    }
+
+    /// The lowercased object classes of the entry.
+    pub fn object_classes(&self) -> Vec<String> {
+        self.get_all("objectclass")
+            .map(|v| String::from_utf8_lossy(v).to_ascii_lowercase())
+            .collect()
+    }
+}
+
+/// Joins the continuation lines (starting with a space) to the previous line, and drops the
+/// comments. Returns the logical lines with the number of their first physical line.
+fn unfold_lines(contents: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    let mut in_comment = false;
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if let Some(continuation) = line.strip_prefix(' ') {
+            if in_comment {
+                continue;
+            }
+            if let Some((_, last)) = lines.last_mut() {
+                if !last.is_empty() {
+                    last.push_str(continuation);
+                    continue;
+                }
+            }
+        }
+        in_comment = line.starts_with('#');
+        if !in_comment {
+            lines.push((number + 1, line.to_owned()));
+        }
+    }
+    lines
+}
+
+/// Parses a line of the form "name: value", "name:: base64" or "name:< url".
+fn parse_line(line: &str) -> Result<(String, Vec<u8>)> {
+    let (name, value) = line
+        .split_once(':')
+        .ok_or_else(|| anyhow!("expected `attribute: value`, got `{}`", line))?;
+    // Drop the attribute options, like ";binary" or ";lang-en".
+    let name = name.split(';').next().unwrap().trim().to_ascii_lowercase();
+    let value = if let Some(encoded) = value.strip_prefix(':') {
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .with_context(|| format!("invalid base64 value for `{}`", name))?
+    } else if value.starts_with('<') {
+        bail!("values from URLs are not supported (attribute `{}`)", name)
+    } else {
+        value.trim_start_matches(' ').as_bytes().to_vec()
+    };
+    Ok((name, value))
+}
+
+pub fn parse(contents: &str) -> Result<Vec<LdifEntry>> {
+    let mut entries = Vec::new();
+    let mut current: Option<LdifEntry> = None;
+    for (number, line) in unfold_lines(contents) {
+        if line.is_empty() {
+            entries.extend(current.take());
+            continue;
+        }
+        let (name, value) = parse_line(&line).with_context(|| format!("on line {}", number))?;
+        match current.as_mut() {
+            None if name == "version" => {}
+            None if name == "dn" => {
+                current = Some(LdifEntry {
+                    dn: String::from_utf8(value)
+                        .with_context(|| format!("invalid dn on line {}", number))?,
+                    attributes: Vec::new(),
+                })
+            }
+            None => bail!("Expected a dn on line {}, got `{}`", number, name),
+            Some(_) if name == "changetype" => {
+                bail!("Change records are not supported (line {})", number)
+            }
+            Some(entry) => entry.attributes.push((name, value)),
+        }
+    }
+    entries.extend(current);
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_entries() {
+        let contents = r#"version: 1
+
+# People
+dn: uid=bob,ou=people,dc=example,dc=com
+objectClass: inetOrgPerson
+uid: bob
+cn: Bob
+ by Smith
+description:: w6l0w6k=
+jpegPhoto;binary:: AAEC
+
+dn: cn=group,ou=groups,dc=example,dc=com
+objectClass: groupOfNames
+member: uid=bob,ou=people,dc=ex
+ ample,dc=com
+"#;
+        let entries = parse(contents).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                LdifEntry {
+                    dn: "uid=bob,ou=people,dc=example,dc=com".to_owned(),
+                    attributes: vec![
+                        ("objectclass".to_owned(), b"inetOrgPerson".to_vec()),
+                        ("uid".to_owned(), b"bob".to_vec()),
+                        ("cn".to_owned(), b"Bobby Smith".to_vec()),
+                        ("description".to_owned(), "été".as_bytes().to_vec()),
+                        ("jpegphoto".to_owned(), vec![0, 1, 2]),
+                    ],
+                },
+                LdifEntry {
+                    dn: "cn=group,ou=groups,dc=example,dc=com".to_owned(),
+                    attributes: vec![
+                        ("objectclass".to_owned(), b"groupOfNames".to_vec()),
+                        (
+                            "member".to_owned(),
+                            b"uid=bob,ou=people,dc=example,dc=com".to_vec()
+                        ),
+                    ],
+                },
+            ]
+        );
+        assert_eq!(entries[0].get_str("cn"), Some("Bobby Smith"));
+        assert_eq!(entries[0].object_classes(), vec!["inetorgperson"]);
+    }
+
+    #[test]
+    fn test_parse_base64_dn_and_crlf() {
+        let entries = parse("dn:: dWlkPWLDtmIsZGM9ZXhhbXBsZQ==\r\nuid: b\r\n\r\n").unwrap();
+        assert_eq!(entries[0].dn, "uid=böb,dc=example");
+        assert_eq!(entries[0].get_str("uid"), Some("b"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        parse("uid: bob\n").unwrap_err();
+        parse("dn: uid=bob\nchangetype: delete\n").unwrap_err();
+        parse("dn: uid=bob\njpegPhoto:< file:///tmp/photo.jpg\n").unwrap_err();
+        parse("dn: uid=bob\ncn:: not base64!\n").unwrap_err();
+    }
+}
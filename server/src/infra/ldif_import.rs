@@ -0,0 +1,635 @@
+//! Imports the users and groups of an LDIF dump from another LDAP server.
+//!
+//! The import is done in two passes: first the users, then the groups, so that the group
+//! memberships can refer to users defined anywhere in the file.
+
+use crate::{
+    domain::{
+        handler::{CreateUserRequest, GroupListerBackendHandler, UserListerBackendHandler},
+        ldap::{dn::DistinguishedName, utils::from_postal_address_syntax},
+        legacy_password,
+        sql_backend_handler::SqlBackendHandler,
+        types::{GroupName, JpegPhoto, UserId},
+        validation::{
+            check_email, check_group_name, check_host, check_phone_number, check_user_id,
+            normalize_email, normalize_hosts, normalize_phone_number, MAX_GROUP_DESCRIPTION_LENGTH,
+            MAX_LOCALITY_LENGTH, MAX_POSTAL_ADDRESS_LENGTH,
+        },
+    },
+    infra::{
+        change_plan::{Change, InitialPassword, Plan, UserToCreate},
+        ldif::LdifEntry,
+    },
+};
+use anyhow::Result;
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+
+const USER_OBJECT_CLASSES: &[&str] = &["inetorgperson", "posixaccount", "person"];
+const GROUP_OBJECT_CLASSES: &[&str] = &["groupofnames", "groupofuniquenames", "posixgroup"];
+// Entries that only structure the tree, and are skipped silently.
+const CONTAINER_OBJECT_CLASSES: &[&str] = &["organizationalunit", "organization", "dcobject"];
+
+// Attributes that are mapped to lldap fields, or that are implied by the lldap schema.
+const USER_IMPORTED_ATTRIBUTES: &[&str] = &[
+    "objectclass",
+    "uid",
+    "mail",
+    "cn",
+    "displayname",
+    "givenname",
+    "sn",
+    "jpegphoto",
+    "userpassword",
+    "host",
+    "telephonenumber",
+    "mobile",
+    "postaladdress",
+    "l",
+    "st",
+];
+const GROUP_IMPORTED_ATTRIBUTES: &[&str] = &[
+    "objectclass",
+    "cn",
+    "description",
+    "member",
+    "uniquemember",
+    "memberuid",
+];
+
+#[derive(Debug)]
+pub struct UserPlan {
+    pub request: CreateUserRequest,
+    pub legacy_password_hash: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct GroupPlan {
+    pub name: GroupName,
+    pub description: Option<String>,
+    pub members: Vec<UserId>,
+}
+
+/// What will be created, and the data that will be dropped.
+#[derive(Debug, Default)]
+pub struct ImportPlan {
+    pub users: Vec<UserPlan>,
+    pub groups: Vec<GroupPlan>,
+    pub warnings: Vec<String>,
+}
+
+/// Normalizes a DN for comparisons: "UID=Bob, OU=People" -> "uid=bob,ou=people". The invalid
+/// DNs are only lowercased.
+fn normalize_dn(dn: &str) -> String {
+    DistinguishedName::parse(dn)
+        .map_or_else(|_| dn.to_lowercase(), |dn| dn.normalized().to_string())
+}
+
+fn has_any_class(classes: &[String], candidates: &[&str]) -> bool {
+    classes.iter().any(|c| candidates.contains(&c.as_str()))
+}
+
+fn warn_dropped_attributes(entry: &LdifEntry, imported: &[&str], warnings: &mut Vec<String>) {
+    let dropped = entry
+        .attributes
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .filter(|name| !imported.contains(name))
+        .unique()
+        .collect::<Vec<_>>();
+    if !dropped.is_empty() {
+        warnings.push(format!(
+            "{}: dropping attributes {}",
+            entry.dn,
+            dropped.join(", ")
+        ));
+    }
+}
+
+/// The first value of a single-valued field, with a warning if there are others.
+fn first_value(entry: &LdifEntry, attribute: &str, warnings: &mut Vec<String>) -> Option<String> {
+    let mut values = entry
+        .get_all(attribute)
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+        .filter(|value| !value.trim().is_empty());
+    let value = values.next()?;
+    if values.next().is_some() {
+        warnings.push(format!(
+            "{}: only keeping the first {}",
+            entry.dn, attribute
+        ));
+    }
+    Some(value)
+}
+
+fn plan_phone_number(
+    entry: &LdifEntry,
+    attribute: &str,
+    warnings: &mut Vec<String>,
+) -> Option<String> {
+    let phone = first_value(entry, attribute, warnings)?;
+    let normalized = normalize_phone_number(&phone);
+    match check_phone_number(&normalized, false) {
+        Ok(()) => Some(normalized),
+        Err(e) => {
+            warnings.push(format!(
+                "{}: dropping invalid {} \"{}\": {}",
+                entry.dn, attribute, phone, e
+            ));
+            None
+        }
+    }
+}
+
+fn plan_text(
+    entry: &LdifEntry,
+    attribute: &str,
+    max_length: usize,
+    warnings: &mut Vec<String>,
+) -> Option<String> {
+    let value = first_value(entry, attribute, warnings)?;
+    let length = value.chars().count();
+    if length > max_length {
+        warnings.push(format!(
+            "{}: dropping {}, it is {} characters long, the maximum is {}",
+            entry.dn, attribute, length, max_length
+        ));
+        return None;
+    }
+    Some(value)
+}
+
+fn plan_user(entry: &LdifEntry, warnings: &mut Vec<String>) -> Option<UserPlan> {
+    let user_id = match entry.get_str("uid") {
+        Some(uid) => {
+            if let Err(e) = check_user_id(uid) {
+                warnings.push(format!("{}: {}, skipping the user", entry.dn, e));
+                return None;
+            }
+            UserId::new(uid)
+        }
+        None => {
+            warnings.push(format!("{}: no uid, skipping the user", entry.dn));
+            return None;
+        }
+    };
+    let email = match entry.get_str("mail") {
+        Some(mail) => {
+            if let Err(e) = check_email(&normalize_email(mail)) {
+                warnings.push(format!(
+                    "{}: invalid mail \"{}\": {}, skipping the user",
+                    entry.dn, mail, e
+                ));
+                return None;
+            }
+            mail.into()
+        }
+        None => {
+            warnings.push(format!("{}: no mail, skipping the user", entry.dn));
+            return None;
+        }
+    };
+    let avatar =
+        entry
+            .get_all("jpegphoto")
+            .next()
+            .and_then(|photo| match JpegPhoto::try_from(photo) {
+                Ok(photo) => Some(photo),
+                Err(e) => {
+                    warnings.push(format!("{}: dropping invalid jpegPhoto: {}", entry.dn, e));
+                    None
+                }
+            });
+    let legacy_password_hash = entry.get_str("userpassword").and_then(|hash| {
+        if legacy_password::is_supported(hash) {
+            Some(hash.to_owned())
+        } else {
+            warnings.push(format!(
+                "{}: unsupported password scheme {}, the user will have to reset their password",
+                entry.dn,
+                legacy_password::get_scheme(hash).unwrap_or_else(|| "(cleartext)".to_owned())
+            ));
+            None
+        }
+    });
+    let hosts = normalize_hosts(
+        &entry
+            .get_all("host")
+            .map(|host| String::from_utf8_lossy(host).into_owned())
+            .filter(|host| match check_host(host.trim()) {
+                Ok(()) => true,
+                Err(e) => {
+                    warnings.push(format!(
+                        "{}: dropping invalid host \"{}\": {}",
+                        entry.dn, host, e
+                    ));
+                    false
+                }
+            })
+            .collect::<Vec<_>>(),
+    );
+    let phone = plan_phone_number(entry, "telephonenumber", warnings);
+    let mobile = plan_phone_number(entry, "mobile", warnings);
+    let postal_address = plan_text(entry, "postaladdress", MAX_POSTAL_ADDRESS_LENGTH, warnings)
+        .map(|address| from_postal_address_syntax(&address));
+    let locality = plan_text(entry, "l", MAX_LOCALITY_LENGTH, warnings);
+    let state_or_province = plan_text(entry, "st", MAX_LOCALITY_LENGTH, warnings);
+    warn_dropped_attributes(entry, USER_IMPORTED_ATTRIBUTES, warnings);
+    Some(UserPlan {
+        request: CreateUserRequest {
+            user_id,
+            email,
+            display_name: entry
+                .get_str("displayname")
+                .or_else(|| entry.get_str("cn"))
+                .map(str::to_owned),
+            first_name: entry.get_str("givenname").map(str::to_owned),
+            last_name: entry.get_str("sn").map(str::to_owned),
+            avatar,
+            hosts,
+            phone,
+            mobile,
+            postal_address,
+            locality,
+            state_or_province,
+            ..Default::default()
+        },
+        legacy_password_hash,
+    })
+}
+
+fn plan_group(
+    entry: &LdifEntry,
+    users_by_dn: &HashMap<String, UserId>,
+    groups_dns: &[String],
+    warnings: &mut Vec<String>,
+) -> Option<GroupPlan> {
+    let name = match entry.get_str("cn") {
+        Some(cn) => {
+            if let Err(e) = check_group_name(cn) {
+                warnings.push(format!("{}: {}, skipping the group", entry.dn, e));
+                return None;
+            }
+            GroupName::from(cn)
+        }
+        None => {
+            warnings.push(format!("{}: no cn, skipping the group", entry.dn));
+            return None;
+        }
+    };
+    let description = entry
+        .get_str("description")
+        .filter(|description| !description.is_empty())
+        .and_then(|description| {
+            let length = description.chars().count();
+            if length > MAX_GROUP_DESCRIPTION_LENGTH {
+                warnings.push(format!(
+                    "{}: dropping the description, it is {} characters long, the maximum is {}",
+                    entry.dn, length, MAX_GROUP_DESCRIPTION_LENGTH
+                ));
+                return None;
+            }
+            Some(description.to_owned())
+        });
+    let mut members = Vec::new();
+    for member_dn in entry.get_all("member").chain(entry.get_all("uniquemember")) {
+        let member_dn = normalize_dn(&String::from_utf8_lossy(member_dn));
+        if let Some(user_id) = users_by_dn.get(&member_dn) {
+            members.push(user_id.clone());
+        } else if groups_dns.contains(&member_dn) {
+            warnings.push(format!(
+                "{}: nested groups are not supported, ignoring member {}",
+                entry.dn, member_dn
+            ));
+        } else {
+            warnings.push(format!(
+                "{}: unknown member {}, ignoring it",
+                entry.dn, member_dn
+            ));
+        }
+    }
+    // The memberUid values are user ids, that may refer to users already in lldap.
+    members.extend(
+        entry
+            .get_all("memberuid")
+            .map(|uid| UserId::new(&String::from_utf8_lossy(uid))),
+    );
+    members.sort();
+    members.dedup();
+    warn_dropped_attributes(entry, GROUP_IMPORTED_ATTRIBUTES, warnings);
+    Some(GroupPlan {
+        name,
+        description,
+        members,
+    })
+}
+
+pub fn plan(entries: &[LdifEntry]) -> ImportPlan {
+    let mut plan = ImportPlan::default();
+    let mut users_by_dn = HashMap::new();
+    let mut group_entries = Vec::new();
+    // First pass: the users, so that the groups can refer to them.
+    for entry in entries {
+        let classes = entry.object_classes();
+        if has_any_class(&classes, USER_OBJECT_CLASSES) {
+            if let Some(user) = plan_user(entry, &mut plan.warnings) {
+                users_by_dn.insert(normalize_dn(&entry.dn), user.request.user_id.clone());
+                plan.users.push(user);
+            }
+        } else if has_any_class(&classes, GROUP_OBJECT_CLASSES) {
+            group_entries.push(entry);
+        } else if !has_any_class(&classes, CONTAINER_OBJECT_CLASSES) {
+            plan.warnings
+                .push(format!("{}: not a user nor a group, skipping it", entry.dn));
+        }
+    }
+    // Second pass: the groups.
+    let groups_dns = group_entries
+        .iter()
+        .map(|e| normalize_dn(&e.dn))
+        .collect::<Vec<_>>();
+    for entry in group_entries {
+        if let Some(group) = plan_group(entry, &users_by_dn, &groups_dns, &mut plan.warnings) {
+            plan.groups.push(group);
+        }
+    }
+    plan
+}
+
+/// The changes to the database: the users and groups that don't exist yet, and the missing
+/// memberships. Existing users and groups are not modified.
+pub async fn changes(handler: &SqlBackendHandler, import: ImportPlan) -> Result<Plan> {
+    let mut existing_users = handler
+        .list_users(None, false)
+        .await?
+        .into_iter()
+        .map(|u| u.user.user_id)
+        .collect::<HashSet<_>>();
+    let existing_groups = handler
+        .list_groups(None)
+        .await?
+        .into_iter()
+        .map(|g| (g.display_name, g.users))
+        .collect::<HashMap<_, _>>();
+    let mut plan = Plan {
+        changes: Vec::new(),
+        warnings: import.warnings,
+    };
+    for user in import.users {
+        let user_id = user.request.user_id.clone();
+        if existing_users.contains(&user_id) {
+            plan.warnings
+                .push(format!("User {} already exists, skipping it", user_id));
+            continue;
+        }
+        existing_users.insert(user_id.clone());
+        plan.changes.push(Change::CreateUser(UserToCreate {
+            user_id,
+            email: user.request.email,
+            display_name: user.request.display_name,
+            first_name: user.request.first_name,
+            last_name: user.request.last_name,
+            avatar: user.request.avatar,
+            hosts: user.request.hosts,
+            phone: user.request.phone,
+            mobile: user.request.mobile,
+            postal_address: user.request.postal_address,
+            locality: user.request.locality,
+            state_or_province: user.request.state_or_province,
+            password: match user.legacy_password_hash {
+                Some(hash) => InitialPassword::LegacyHash(hash),
+                None => InitialPassword::None,
+            },
+        }));
+    }
+    let mut memberships = Vec::new();
+    let mut created_groups = HashSet::new();
+    for group in import.groups {
+        let members = match existing_groups.get(&group.name) {
+            Some(members) => {
+                plan.warnings.push(format!(
+                    "Group {} already exists, only adding the members",
+                    group.name
+                ));
+                members.as_slice()
+            }
+            // Also if the group is listed twice in the file.
+            None if created_groups.contains(&group.name) => &[],
+            None => {
+                created_groups.insert(group.name.clone());
+                plan.changes.push(Change::CreateGroup {
+                    group: group.name.clone(),
+                    description: group.description,
+                });
+                &[]
+            }
+        };
+        for member in group.members {
+            if members.contains(&member) {
+                continue;
+            }
+            if !existing_users.contains(&member) {
+                plan.warnings.push(format!(
+                    "Unknown user {} in group {}, ignoring it",
+                    member, group.name
+                ));
+                continue;
+            }
+            memberships.push(Change::AddMembership {
+                user_id: member,
+                group: group.name.clone(),
+            });
+        }
+    }
+    // After all the groups are created.
+    plan.changes.extend(memberships);
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::{
+            handler::{
+                BindRequest, GroupRequestFilter, LoginHandler, UserBackendHandler,
+                UserRequestFilter,
+            },
+            sql_backend_handler::tests::*,
+        },
+        infra::{change_plan::apply, ldif::parse},
+    };
+    use pretty_assertions::assert_eq;
+
+    const DUMP: &str = r#"
+dn: dc=example,dc=com
+objectClass: dcObject
+
+dn: cn=admins,ou=groups,dc=example,dc=com
+objectClass: groupOfNames
+cn: admins
+description: The admins
+member: uid=Alice, ou=people,dc=example,dc=com
+member: cn=nested,ou=groups,dc=example,dc=com
+member: uid=ghost,ou=people,dc=example,dc=com
+
+dn: cn=nested,ou=groups,dc=example,dc=com
+objectClass: posixGroup
+cn: nested
+gidNumber: 1000
+memberUid: carol
+
+dn: uid=alice,ou=people,dc=example,dc=com
+objectClass: inetOrgPerson
+objectClass: posixAccount
+uid: alice
+mail: alice@example.com
+cn: Alice Liddell
+givenName: Alice
+sn: Liddell
+uidNumber: 1000
+telephoneNumber: +1 555 010-9999
+telephoneNumber: +1 555 010-0000
+postalAddress: 1 Main Street$Springfield
+l: Springfield
+userPassword: {SSHA}yI6cZwQadOA1e+/f+T+H3eCQQhRzYWx0
+
+dn: uid=dave,ou=people,dc=example,dc=com
+objectClass: inetOrgPerson
+objectClass: hostObject
+uid: dave
+mail: dave@example.com
+userPassword: {CRYPT}$6$salt$hash
+host: Web01
+host: db_02
+mobile: call me
+
+dn: uid=nomail,ou=people,dc=example,dc=com
+objectClass: person
+uid: nomail
+"#;
+
+    #[test]
+    fn test_plan() {
+        let plan = plan(&parse(DUMP).unwrap());
+        assert_eq!(
+            plan.users
+                .iter()
+                .map(|u| u.request.user_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["alice", "dave"]
+        );
+        let alice = &plan.users[0];
+        assert_eq!(alice.request.display_name.as_deref(), Some("Alice Liddell"));
+        assert_eq!(alice.request.last_name.as_deref(), Some("Liddell"));
+        assert!(alice.legacy_password_hash.is_some());
+        assert!(plan.users[1].legacy_password_hash.is_none());
+        assert_eq!(plan.users[1].request.hosts, vec!["web01"]);
+        assert_eq!(alice.request.phone.as_deref(), Some("+15550109999"));
+        assert_eq!(
+            alice.request.postal_address.as_deref(),
+            Some("1 Main Street\nSpringfield")
+        );
+        assert_eq!(alice.request.locality.as_deref(), Some("Springfield"));
+        assert_eq!(plan.users[1].request.mobile, None);
+        assert_eq!(
+            plan.groups,
+            vec![
+                GroupPlan {
+                    name: "admins".into(),
+                    description: Some("The admins".to_owned()),
+                    members: vec![UserId::new("alice")],
+                },
+                GroupPlan {
+                    name: "nested".into(),
+                    description: None,
+                    members: vec![UserId::new("carol")],
+                },
+            ]
+        );
+        assert_eq!(
+            plan.warnings,
+            vec![
+                "uid=alice,ou=people,dc=example,dc=com: only keeping the first telephonenumber",
+                "uid=alice,ou=people,dc=example,dc=com: dropping attributes uidnumber",
+                "uid=dave,ou=people,dc=example,dc=com: unsupported password scheme CRYPT, the user will have to reset their password",
+                "uid=dave,ou=people,dc=example,dc=com: dropping invalid host \"db_02\": the character '_' is not allowed in hosts (only letters, digits, '-' and '.' are, or \"*\" alone)",
+                "uid=dave,ou=people,dc=example,dc=com: dropping invalid mobile \"call me\": the character 'c' is not allowed in phone numbers (only digits, an optional leading '+' and the separators \" -.()/\" are)",
+                "uid=nomail,ou=people,dc=example,dc=com: no mail, skipping the user",
+                "cn=admins,ou=groups,dc=example,dc=com: nested groups are not supported, ignoring member cn=nested,ou=groups,dc=example,dc=com",
+                "cn=admins,ou=groups,dc=example,dc=com: unknown member uid=ghost,ou=people,dc=example,dc=com, ignoring it",
+                "cn=nested,ou=groups,dc=example,dc=com: dropping attributes gidnumber",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let fixture = TestFixture::new().await;
+        insert_user_no_password(&fixture.handler, "carol").await;
+        let planned = changes(&fixture.handler, plan(&parse(DUMP).unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(
+            planned.to_string(),
+            "+ user alice <alice@example.com> with an imported password\n\
+             + user dave <dave@example.com>\n\
+             + group admins\n\
+             + group nested\n\
+             + alice in group admins\n\
+             + carol in group nested\n"
+        );
+        let expected_summary = planned.summary();
+        assert_eq!(
+            apply(&fixture.handler, planned, None, None).await.unwrap(),
+            expected_summary
+        );
+        assert_eq!(
+            get_user_names(
+                &fixture.handler,
+                Some(UserRequestFilter::MemberOf("admins".into()))
+            )
+            .await,
+            vec!["alice"]
+        );
+        assert_eq!(
+            get_user_names(
+                &fixture.handler,
+                Some(UserRequestFilter::MemberOf("nested".into()))
+            )
+            .await,
+            vec!["carol"]
+        );
+        let admins = fixture
+            .handler
+            .list_groups(Some(GroupRequestFilter::DisplayName("admins".into())))
+            .await
+            .unwrap();
+        assert_eq!(admins[0].description.as_deref(), Some("The admins"));
+        assert_eq!(
+            fixture
+                .handler
+                .get_user_details(&UserId::new("dave"))
+                .await
+                .unwrap()
+                .hosts,
+            vec!["web01"]
+        );
+        fixture
+            .handler
+            .bind(BindRequest {
+                name: UserId::new("alice"),
+                password: "password".to_owned(),
+            })
+            .await
+            .unwrap();
+        // Everything was applied: importing again changes nothing.
+        let again = changes(&fixture.handler, plan(&parse(DUMP).unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(again.changes, Vec::new());
+        assert!(again
+            .warnings
+            .contains(&"User alice already exists, skipping it".to_owned()));
+    }
+}
@@ -1,11 +1,13 @@
-use crate::infra::configuration::Configuration;
+use crate::infra::{client_ip::ClientIp, configuration::Configuration, request_id::RequestId};
 use actix_web::{
     dev::{ServiceRequest, ServiceResponse},
-    Error,
+    Error, HttpMessage,
 };
-use tracing::{debug, error, Span};
+use tracing::{debug, error, warn, Span};
 use tracing_actix_web::RootSpanBuilder;
-use tracing_subscriber::{filter::EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{
+    filter::EnvFilter, layer::SubscriberExt, util::SubscriberInitExt, Layer, Registry,
+};
 
 /// We will define a custom root span builder to capture additional fields, specific
 /// to our application, on top of the ones provided by `DefaultRootSpanBuilder` out of the box.
@@ -13,10 +15,22 @@ pub struct CustomRootSpanBuilder;
 
 impl RootSpanBuilder for CustomRootSpanBuilder {
     fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.clone())
+            .unwrap_or_default();
+        let client_ip = request
+            .extensions()
+            .get::<ClientIp>()
+            .map(|ip| ip.0.to_string())
+            .unwrap_or_default();
         tracing::debug_span!(
             "HTTP request",
             method = request.method().to_string(),
-            uri = request.uri().to_string()
+            uri = request.uri().to_string(),
+            request_id,
+            client_ip
         )
     }
 
@@ -34,6 +48,61 @@ impl RootSpanBuilder for CustomRootSpanBuilder {
     }
 }
 
+/// The OpenTelemetry layer, if enabled. It has its own filter so that the traces include the
+/// request spans and the SQL statements regardless of the log level.
+#[cfg(feature = "otel")]
+fn otel_layer(
+    config: &Configuration,
+) -> anyhow::Result<Option<Box<dyn Layer<Registry> + Send + Sync>>> {
+    use anyhow::Context;
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::{
+        trace::{self, Sampler},
+        Resource,
+    };
+    let options = &config.otel;
+    if !options.enabled {
+        return Ok(None);
+    }
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&options.endpoint),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                    options.sample_ratio,
+                ))))
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    options.service_name.clone(),
+                )])),
+        )
+        // The batches are exported from a separate thread, so that `shutdown` can wait for them
+        // from the (single-threaded) actix runtime.
+        .install_batch(opentelemetry_sdk::runtime::TokioCurrentThread)
+        .context("while setting up the OpenTelemetry exporter")?;
+    // sqlx logs the statements with their placeholders, without the bound values. The exporter's
+    // own crates are silenced to avoid tracing the export of the traces.
+    let filter = EnvFilter::new(
+        "sqlx=warn,sqlx::query=debug,reqwest=warn,h2=warn,hyper=warn,tonic=warn,tower=warn,opentelemetry=warn,debug",
+    );
+    Ok(Some(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(filter)
+            .boxed(),
+    ))
+}
+
+#[cfg(not(feature = "otel"))]
+fn otel_layer(_: &Configuration) -> anyhow::Result<Option<Box<dyn Layer<Registry> + Send + Sync>>> {
+    Ok(None)
+}
+
 pub fn init(config: &Configuration) -> anyhow::Result<()> {
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
         EnvFilter::new(if config.verbose {
@@ -43,12 +112,21 @@ pub fn init(config: &Configuration) -> anyhow::Result<()> {
         })
     });
     tracing_subscriber::registry()
-        .with(env_filter)
-        .with(tracing_forest::ForestLayer::default())
+        .with(otel_layer(config)?)
+        .with(tracing_forest::ForestLayer::default().with_filter(env_filter))
         .init();
+    if config.otel.enabled && cfg!(not(feature = "otel")) {
+        warn!("OpenTelemetry export is enabled, but LLDAP was built without the \"otel\" feature: ignoring it");
+    }
     Ok(())
 }
 
+/// Flushes the traces that haven't been exported yet.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
 #[cfg(test)]
 pub fn init_for_tests() {
     if let Err(e) = tracing_subscriber::FmtSubscriber::builder()
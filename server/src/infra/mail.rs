@@ -1,114 +1,874 @@
-use crate::infra::{cli::SmtpEncryption, configuration::MailOptions};
-use anyhow::{anyhow, Ok, Result};
+use crate::{
+    domain::types::User,
+    infra::{
+        cli::SmtpEncryption,
+        configuration::MailOptions,
+        health::{SmtpCheck, SmtpStatus},
+        mail_queue::{EmailPriority, MailQueue, MailTransport, SendError},
+        mail_templates::{self, MailTemplates, RenderedEmail, LOGO_CID},
+    },
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use lettre::{
-    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
-    AsyncTransport, Message, Tokio1Executor,
+    message::{
+        header::{Header, HeaderName, HeaderValue},
+        Attachment, Mailbox, MultiPart, SinglePart,
+    },
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Tls, TlsParameters},
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
 };
-use tracing::debug;
+use tracing::{debug, info, instrument, warn};
 
-async fn send_email(
-    to: Mailbox,
-    subject: &str,
-    body: String,
-    options: &MailOptions,
-    server_url: &url::Url,
-) -> Result<()> {
-    let from = options
+fn build_transport(options: &MailOptions) -> Result<AsyncSmtpTransport<Tokio1Executor>> {
+    let tls_parameters = || {
+        TlsParameters::builder(options.server.clone())
+            .dangerous_accept_invalid_certs(options.accept_invalid_certs)
+            .build_rustls()
+    };
+    let tls = match options.smtp_encryption {
+        SmtpEncryption::None => Tls::None,
+        // Implicit TLS (SMTPS): the connection is encrypted from the start, usually on port 465.
+        SmtpEncryption::Tls => Tls::Wrapper(tls_parameters()?),
+        SmtpEncryption::StartTls => Tls::Required(tls_parameters()?),
+    };
+    let mut mailer = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&options.server)
+        .port(options.port())
+        .tls(tls);
+    // Without a user, no AUTH command is sent at all: some relays reject any attempt, and only
+    // accept mail based on the IP of the server.
+    if !options.user.is_empty() {
+        let creds = Credentials::new(
+            options.user.clone(),
+            options.password.unsecure().to_string(),
+        );
+        mailer = mailer.credentials(creds)
+    }
+    Ok(mailer.build())
+}
+
+/// `Auto-Submitted: auto-generated` (RFC 3834), for the auto-responders not to reply.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct AutoSubmitted;
+
+impl Header for AutoSubmitted {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("Auto-Submitted")
+    }
+
+    fn parse(s: &str) -> std::result::Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        if s.trim().eq_ignore_ascii_case("auto-generated") {
+            Ok(Self)
+        } else {
+            Err(format!("Unsupported Auto-Submitted value: {}", s).into())
+        }
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), "auto-generated".to_owned())
+    }
+}
+
+/// `Content-Language` (RFC 3282): the language of the templates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ContentLanguage(String);
+
+impl Header for ContentLanguage {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("Content-Language")
+    }
+
+    fn parse(s: &str) -> std::result::Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(s.trim().to_owned()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
+
+fn get_from(options: &MailOptions) -> Mailbox {
+    let mut from = options
         .from
         .clone()
         .unwrap_or_else(|| "LLDAP <nobody@lldap>".parse().unwrap());
+    if from.name.is_none() {
+        from.name = Some("LLDAP".to_owned());
+    }
+    from
+}
+
+/// A multipart/alternative message with the text and the HTML parts. The logo, if any, is
+/// attached to the HTML part (multipart/related).
+fn build_message(to: Mailbox, email: RenderedEmail, options: &MailOptions) -> Result<Message> {
+    let from = get_from(options);
     let reply_to = options.reply_to.clone().unwrap_or_else(|| from.clone());
-    debug!(
-        "Sending email to '{}' as '{}' via '{}'@'{}':'{}'",
-        &to, &from, &options.user, &options.server, options.port
+    let message_id = format!(
+        "<{}@{}>",
+        uuid::Uuid::new_v1(
+            uuid::Timestamp::now(uuid::NoContext),
+            "lldap!".as_bytes().try_into().unwrap()
+        ),
+        from.email.domain()
     );
-    let email = Message::builder()
-        .message_id(Some(format!(
-            "<{}@{}>",
-            uuid::Uuid::new_v1(
-                uuid::Timestamp::now(uuid::NoContext),
-                "lldap!".as_bytes().try_into().unwrap()
-            ),
-            server_url.domain().unwrap_or_default()
-        )))
+    let body = MultiPart::alternative().singlepart(SinglePart::plain(email.text));
+    let body = match email.logo {
+        None => body.singlepart(SinglePart::html(email.html)),
+        Some(logo) => body.multipart(
+            MultiPart::related()
+                .singlepart(SinglePart::html(email.html))
+                .singlepart(
+                    Attachment::new_inline(LOGO_CID.to_owned())
+                        .body(logo.content.clone(), logo.content_type.clone()),
+                ),
+        ),
+    };
+    Ok(Message::builder()
+        .message_id(Some(message_id))
+        .header(AutoSubmitted)
+        .header(ContentLanguage(email.language))
         .from(from)
         .reply_to(reply_to)
         .to(to)
-        .subject(subject)
-        .singlepart(
-            lettre::message::SinglePart::builder()
-                .header(lettre::message::header::ContentType::TEXT_PLAIN)
-                .body(body),
-        )?;
-    let mut mailer = match options.smtp_encryption {
-        SmtpEncryption::None => {
-            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&options.server)
+        .subject(email.subject)
+        .multipart(body)?)
+}
+
+/// Adds a hint to the common configuration errors.
+fn explain_error(e: lettre::transport::smtp::Error) -> anyhow::Error {
+    if e.to_string().contains("CorruptMessage") {
+        anyhow!("CorruptMessage returned by lettre, this usually means the SMTP encryption setting is wrong.").context(e)
+    } else if format!("{:?}", e).contains("certificate") {
+        anyhow!("The certificate of the SMTP server was rejected. Check the server name and the certificate chain, or set smtp_options.accept_invalid_certs = true (only for testing).").context(e)
+    } else {
+        e.into()
+    }
+}
+
+fn log_sending(message: &Message, options: &MailOptions) {
+    let to = message.headers().get_raw("To").unwrap_or_default();
+    if options.user.is_empty() {
+        debug!(
+            "Sending email to '{}' as '{}' via '{}':'{}' without authentication",
+            to,
+            get_from(options),
+            &options.server,
+            options.port()
+        );
+    } else {
+        debug!(
+            "Sending email to '{}' as '{}' via '{}'@'{}':'{}'",
+            to,
+            get_from(options),
+            &options.user,
+            &options.server,
+            options.port()
+        );
+    }
+}
+
+/// Sends the queued emails through the configured SMTP server. The connections are pooled and
+/// reused between the emails.
+pub struct SmtpMailTransport {
+    options: MailOptions,
+    /// The TLS configuration can be invalid: the error is reported when sending, the server starts
+    /// anyway.
+    transport: std::result::Result<AsyncSmtpTransport<Tokio1Executor>, String>,
+}
+
+impl SmtpMailTransport {
+    pub fn new(options: MailOptions) -> Self {
+        Self {
+            transport: build_transport(&options).map_err(|e| format!("{:#}", e)),
+            options,
         }
-        SmtpEncryption::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&options.server)?,
-        SmtpEncryption::StartTls => {
-            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&options.server)?
+    }
+
+    fn transport(&self) -> Result<&AsyncSmtpTransport<Tokio1Executor>> {
+        self.transport.as_ref().map_err(|e| anyhow!("{}", e))
+    }
+
+    /// Connects to the server, says EHLO, authenticates if there is a user and quits, without
+    /// sending anything.
+    pub async fn check_connection(&self) -> Result<()> {
+        match self.transport()?.test_connection().await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(anyhow!("The SMTP server closed the connection")),
+            Err(e) => Err(explain_error(e)),
         }
-    };
-    if options.user.as_str() != "" {
-        let creds = Credentials::new(
-            options.user.clone(),
-            options.password.unsecure().to_string(),
-        );
-        mailer = mailer.credentials(creds)
     }
+}
 
-    if let Err(e) = mailer.port(options.port).build().send(email).await {
-        if e.to_string().contains("CorruptMessage") {
-            Err(anyhow!("CorruptMessage returned by lettre, this usually means the SMTP encryption setting is wrong.").context(e))
-        } else {
-            Err(e.into())
+/// Checks the SMTP settings at startup, instead of waiting for the first email to fail. The
+/// result is logged and shown by `/ready`.
+pub async fn check_smtp_connection(transport: &SmtpMailTransport, status: &SmtpStatus) {
+    let options = &transport.options;
+    let result = transport.check_connection().await;
+    match &result {
+        Ok(()) => info!(
+            "Connected to the SMTP server {}:{}",
+            options.server,
+            options.port()
+        ),
+        Err(e) => warn!(
+            "Could not connect to the SMTP server {}:{}: {:#}. The password reset and invitation emails will fail until this is fixed. Set smtp_options.check_on_startup = false to skip this check.",
+            options.server,
+            options.port(),
+            e
+        ),
+    }
+    status.set(SmtpCheck {
+        reachable: result.is_ok(),
+        error: result.err().map(|e| format!("{:#}", e)),
+        checked_at: chrono::Utc::now(),
+    });
+}
+
+#[async_trait]
+impl MailTransport for SmtpMailTransport {
+    async fn send(&self, message: &Message) -> std::result::Result<(), SendError> {
+        log_sending(message, &self.options);
+        let transport = self.transport().map_err(SendError::Permanent)?;
+        match transport.send(message.clone()).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.is_permanent() => Err(SendError::Permanent(explain_error(e))),
+            Err(e) => Err(SendError::Transient(explain_error(e))),
         }
-    } else {
-        Ok(())
     }
 }
 
-pub async fn send_password_reset_email(
-    username: &str,
-    to: &str,
-    token: &str,
-    server_url: &url::Url,
+/// Sends the email right away, without the queue.
+#[instrument(skip_all, level = "info", fields(to = %to, subject = %email.subject), err)]
+async fn send_email(to: Mailbox, email: RenderedEmail, options: &MailOptions) -> Result<()> {
+    let message = build_message(to, email, options)?;
+    log_sending(&message, options);
+    build_transport(options)?
+        .send(message)
+        .await
+        .map_err(explain_error)?;
+    Ok(())
+}
+
+/// Queues the email, see `mail_queue`.
+#[instrument(skip_all, level = "info", fields(to = %to, subject = %email.subject), err)]
+fn queue_email(
+    to: Mailbox,
+    email: RenderedEmail,
     options: &MailOptions,
+    queue: &MailQueue,
+    priority: EmailPriority,
 ) -> Result<()> {
-    let to = to.parse()?;
+    queue.enqueue(build_message(to, email, options)?, priority)
+}
+
+fn password_reset_url(server_url: &url::Url, token: &str) -> url::Url {
     let mut reset_url = server_url.clone();
     reset_url
         .path_segments_mut()
         .unwrap()
+        .pop_if_empty()
         .extend(["reset-password", "step2", token]);
-    let body = format!(
-        "Hello {},
-This email has been sent to you in order to validate your identity.
-If you did not initiate the process your credentials might have been
-compromised. You should reset your password and contact an administrator.
+    reset_url
+}
+
+fn email_verification_url(server_url: &url::Url, token: &str) -> url::Url {
+    let mut verification_url = server_url.clone();
+    verification_url
+        .path_segments_mut()
+        .unwrap()
+        .pop_if_empty()
+        .extend(["verify-email", token]);
+    verification_url
+}
+
+/// The variables common to all the templates.
+fn template_context(server_url: &url::Url) -> tera::Context {
+    let mut context = tera::Context::new();
+    context.insert("server_name", server_url.host_str().unwrap_or("lldap"));
+    context.insert("server_url", server_url.as_str());
+    context
+}
+
+fn password_reset_context(username: &str, token: &str, server_url: &url::Url) -> tera::Context {
+    let mut context = template_context(server_url);
+    context.insert("username", username);
+    context.insert("reset_url", password_reset_url(server_url, token).as_str());
+    context.insert(
+        "expiry_minutes",
+        &crate::infra::sql_backend_handler::PASSWORD_RESET_TOKEN_VALIDITY_MINUTES,
+    );
+    context
+}
+
+fn invitation_context(
+    username: &str,
+    token: &str,
+    server_url: &url::Url,
+    options: &MailOptions,
+) -> tera::Context {
+    let mut context = template_context(server_url);
+    context.insert("username", username);
+    // The invitation leads to the same page as a password reset.
+    context.insert(
+        "invitation_url",
+        password_reset_url(server_url, token).as_str(),
+    );
+    context.insert("expiry_days", &options.invitation_validity_days);
+    context
+}
+
+fn email_change_context(
+    username: &str,
+    new_email: &str,
+    token: &str,
+    server_url: &url::Url,
+) -> tera::Context {
+    let mut context = template_context(server_url);
+    context.insert("username", username);
+    context.insert("new_email", new_email);
+    context.insert(
+        "verification_url",
+        email_verification_url(server_url, token).as_str(),
+    );
+    context.insert(
+        "expiry_hours",
+        &crate::infra::sql_backend_handler::EMAIL_CHANGE_TOKEN_VALIDITY_HOURS,
+    );
+    context
+}
 
-To reset your password please visit the following URL: {}
+fn email_changed_context(username: &str, new_email: &str, server_url: &url::Url) -> tera::Context {
+    let mut context = template_context(server_url);
+    context.insert("username", username);
+    context.insert("new_email", new_email);
+    context
+}
 
-Please contact an administrator if you did not initiate the process.",
-        username, reset_url
+fn account_expiring_context(
+    username: &str,
+    user_id: &str,
+    expires_at: chrono::DateTime<chrono::Utc>,
+    now: chrono::DateTime<chrono::Utc>,
+    server_url: &url::Url,
+) -> tera::Context {
+    let mut context = template_context(server_url);
+    context.insert("username", username);
+    context.insert("user_id", user_id);
+    context.insert(
+        "expiry_date",
+        &expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
     );
-    send_email(
-        to,
-        "[LLDAP] Password reset requested",
-        body,
-        options,
+    // Rounded up: "in 0 days" would read as already expired.
+    let days_left = ((expires_at - now).num_hours() + 23) / 24;
+    context.insert("days_left", &days_left);
+    context
+}
+
+/// The name in the greetings: the display name, or else the user id.
+fn greeting_name(user: &User) -> &str {
+    user.display_name
+        .as_deref()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| user.user_id.as_entered())
+}
+
+pub fn send_password_reset_email(
+    user: &User,
+    token: &str,
+    server_url: &url::Url,
+    options: &MailOptions,
+    templates: &MailTemplates,
+    queue: &MailQueue,
+) -> Result<()> {
+    let to = user.email.as_str().parse()?;
+    let context = password_reset_context(greeting_name(user), token, server_url);
+    let email = templates.render(
+        mail_templates::PASSWORD_RESET,
+        user.preferred_language.as_deref(),
+        &context,
+    )?;
+    queue_email(to, email, options, queue, EmailPriority::Critical)
+}
+
+pub fn send_invitation_email(
+    user: &User,
+    token: &str,
+    server_url: &url::Url,
+    options: &MailOptions,
+    templates: &MailTemplates,
+    queue: &MailQueue,
+) -> Result<()> {
+    let to = user.email.as_str().parse()?;
+    let context = invitation_context(greeting_name(user), token, server_url, options);
+    let email = templates.render(
+        mail_templates::INVITATION,
+        user.preferred_language.as_deref(),
+        &context,
+    )?;
+    queue_email(to, email, options, queue, EmailPriority::Deferrable)
+}
+
+/// Sent to the new address, which has to be verified before the change applies.
+pub fn send_email_change_email(
+    user: &User,
+    new_email: &str,
+    token: &str,
+    server_url: &url::Url,
+    options: &MailOptions,
+    templates: &MailTemplates,
+    queue: &MailQueue,
+) -> Result<()> {
+    let to = new_email.parse()?;
+    let context = email_change_context(greeting_name(user), new_email, token, server_url);
+    let email = templates.render(
+        mail_templates::EMAIL_CHANGE,
+        user.preferred_language.as_deref(),
+        &context,
+    )?;
+    queue_email(to, email, options, queue, EmailPriority::Critical)
+}
+
+/// Sent to the old address of `user` once the change is verified.
+pub fn send_email_changed_email(
+    user: &User,
+    new_email: &str,
+    server_url: &url::Url,
+    options: &MailOptions,
+    templates: &MailTemplates,
+    queue: &MailQueue,
+) -> Result<()> {
+    let to = user.email.as_str().parse()?;
+    let context = email_changed_context(greeting_name(user), new_email, server_url);
+    let email = templates.render(
+        mail_templates::EMAIL_CHANGED,
+        user.preferred_language.as_deref(),
+        &context,
+    )?;
+    queue_email(to, email, options, queue, EmailPriority::Deferrable)
+}
+
+/// Warns the user that their account expires soon, and `admin_email` too if set. The user is
+/// skipped without an email address.
+pub fn send_account_expiring_emails(
+    user: &User,
+    now: chrono::DateTime<chrono::Utc>,
+    admin_email: Option<&Mailbox>,
+    server_url: &url::Url,
+    options: &MailOptions,
+    templates: &MailTemplates,
+    queue: &MailQueue,
+) -> Result<()> {
+    let expires_at = user
+        .expires_at
+        .ok_or_else(|| anyhow!("The account of {} doesn't expire", user.user_id))?;
+    let context = account_expiring_context(
+        greeting_name(user),
+        user.user_id.as_entered(),
+        expires_at,
+        now,
         server_url,
-    )
-    .await
-}
-
-pub async fn send_test_email(to: Mailbox, options: &MailOptions) -> Result<()> {
-    send_email(
-        to,
-        "LLDAP test email",
-        "The test is successful! You can send emails from LLDAP".to_string(),
-        options,
-        &url::Url::parse("http://localhost").unwrap(),
-    )
-    .await
+    );
+    if !user.email.as_str().is_empty() {
+        let email = templates.render(
+            mail_templates::ACCOUNT_EXPIRING,
+            user.preferred_language.as_deref(),
+            &context,
+        )?;
+        let to = user.email.as_str().parse()?;
+        queue_email(to, email, options, queue, EmailPriority::Deferrable)?;
+    }
+    if let Some(admin_email) = admin_email {
+        // In the default language.
+        let email = templates.render(mail_templates::ACCOUNT_EXPIRING_ADMIN, None, &context)?;
+        queue_email(
+            admin_email.clone(),
+            email,
+            options,
+            queue,
+            EmailPriority::Deferrable,
+        )?;
+    }
+    Ok(())
+}
+
+/// The token in the example emails: the links don't work.
+pub const EXAMPLE_TOKEN: &str = "EXAMPLE-TOKEN-this-link-does-not-work";
+
+/// Renders an email with example values, to check the templates.
+pub fn render_example_email(
+    email: &str,
+    language: Option<&str>,
+    server_url: &url::Url,
+    options: &MailOptions,
+    templates: &MailTemplates,
+) -> Result<RenderedEmail> {
+    let context = match email {
+        mail_templates::PASSWORD_RESET => {
+            password_reset_context("John Doe", EXAMPLE_TOKEN, server_url)
+        }
+        mail_templates::INVITATION => {
+            invitation_context("John Doe", EXAMPLE_TOKEN, server_url, options)
+        }
+        mail_templates::EMAIL_CHANGE => email_change_context(
+            "John Doe",
+            "john.doe@example.org",
+            EXAMPLE_TOKEN,
+            server_url,
+        ),
+        mail_templates::EMAIL_CHANGED => {
+            email_changed_context("John Doe", "john.doe@example.org", server_url)
+        }
+        mail_templates::ACCOUNT_EXPIRING | mail_templates::ACCOUNT_EXPIRING_ADMIN => {
+            let now = chrono::Utc::now();
+            account_expiring_context(
+                "John Doe",
+                "john",
+                now + chrono::Duration::days(7),
+                now,
+                server_url,
+            )
+        }
+        _ => template_context(server_url),
+    };
+    templates.render(email, language, &context)
+}
+
+/// Bypasses the queue, to report the errors.
+pub async fn send_test_email(
+    to: Mailbox,
+    email: RenderedEmail,
+    options: &MailOptions,
+) -> Result<()> {
+    send_email(to, email, options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    /// A minimal SMTPS server for a single connection, returning the data of the email.
+    async fn start_smtps_server() -> (u16, tokio::task::JoinHandle<String>) {
+        let config = crate::infra::tls::get_server_config(
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/infra/testdata/localhost_cert.pem"
+            ),
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/infra/testdata/localhost_key.pem"
+            ),
+        )
+        .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(std::sync::Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut data = String::new();
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(_) => return data,
+            };
+            let (reader, mut writer) = tokio::io::split(stream);
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            writer.flush().await.unwrap();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let command = line.to_ascii_uppercase();
+                let reply: &[u8] = if command.starts_with("DATA") {
+                    writer
+                        .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                        .await
+                        .unwrap();
+                    writer.flush().await.unwrap();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        if line == "." {
+                            break;
+                        }
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    b"250 Queued\r\n"
+                } else if command.starts_with("QUIT") {
+                    b"221 Bye\r\n"
+                } else {
+                    b"250 OK\r\n"
+                };
+                writer.write_all(reply).await.unwrap();
+                writer.flush().await.unwrap();
+                if command.starts_with("QUIT") {
+                    break;
+                }
+            }
+            data
+        });
+        (port, server)
+    }
+
+    fn smtps_options(port: u16, accept_invalid_certs: bool) -> MailOptions {
+        MailOptions {
+            server: "localhost".to_owned(),
+            port: Some(port),
+            smtp_encryption: SmtpEncryption::Tls,
+            accept_invalid_certs,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_email_implicit_tls() {
+        let (port, server) = start_smtps_server().await;
+        send_test_email(
+            "user@example.com".parse().unwrap(),
+            render_test_email(None),
+            &smtps_options(port, true),
+        )
+        .await
+        .unwrap();
+        let data = server.await.unwrap();
+        assert!(data.contains("Subject: LLDAP test email"), "{}", data);
+        assert!(data.contains("The test is successful!"), "{}", data);
+    }
+
+    #[tokio::test]
+    async fn test_send_email_rejects_invalid_certificate() {
+        let (port, _server) = start_smtps_server().await;
+        let error = send_test_email(
+            "user@example.com".parse().unwrap(),
+            render_test_email(None),
+            &smtps_options(port, false),
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("certificate"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reuses_the_connection() {
+        // The server only accepts one connection.
+        let (port, _server) = start_smtps_server().await;
+        let transport = SmtpMailTransport::new(smtps_options(port, true));
+        for _ in 0..2 {
+            let message = build_message(
+                "user@example.com".parse().unwrap(),
+                render_test_email(None),
+                &transport.options,
+            )
+            .unwrap();
+            tokio::time::timeout(Duration::from_secs(10), transport.send(&message))
+                .await
+                .expect("a second connection was opened")
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_smtp_connection() {
+        let (port, server) = start_smtps_server().await;
+        let status = SmtpStatus::default();
+        check_smtp_connection(&SmtpMailTransport::new(smtps_options(port, true)), &status).await;
+        let check = status.get().unwrap();
+        assert!(check.reachable, "{:?}", check);
+        // Nothing was sent.
+        assert_eq!(server.await.unwrap(), "");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        check_smtp_connection(&SmtpMailTransport::new(smtps_options(port, true)), &status).await;
+        let check = status.get().unwrap();
+        assert!(!check.reachable);
+        assert!(check.error.is_some());
+    }
+
+    fn render_test_email(logo_file: Option<&std::path::Path>) -> RenderedEmail {
+        render_example_email(
+            mail_templates::TEST_EMAIL,
+            None,
+            &url::Url::parse("https://example.com").unwrap(),
+            &MailOptions::default(),
+            &MailTemplates::new(None, logo_file, "en").unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_example_emails() {
+        let templates = MailTemplates::new(None, None, "en").unwrap();
+        let server_url = url::Url::parse("https://example.com/lldap").unwrap();
+        let email = render_example_email(
+            mail_templates::PASSWORD_RESET,
+            None,
+            &server_url,
+            &MailOptions::default(),
+            &templates,
+        )
+        .unwrap();
+        assert!(
+            email.text.contains(
+                "https://example.com/lldap/reset-password/step2/EXAMPLE-TOKEN-this-link-does-not-work"
+            ),
+            "{}",
+            email.text
+        );
+        let email = render_example_email(
+            mail_templates::INVITATION,
+            None,
+            &server_url,
+            &MailOptions::default(),
+            &templates,
+        )
+        .unwrap();
+        assert!(email.text.contains("valid for 7 days"), "{}", email.text);
+        let email = render_example_email(
+            mail_templates::INVITATION,
+            Some("de"),
+            &server_url,
+            &MailOptions::default(),
+            &templates,
+        )
+        .unwrap();
+        assert!(email.text.contains("7 Tage lang"), "{}", email.text);
+        let email = render_example_email(
+            mail_templates::EMAIL_CHANGE,
+            None,
+            &server_url,
+            &MailOptions::default(),
+            &templates,
+        )
+        .unwrap();
+        assert!(
+            email.text.contains(
+                "https://example.com/lldap/verify-email/EXAMPLE-TOKEN-this-link-does-not-work"
+            ),
+            "{}",
+            email.text
+        );
+        assert!(email.text.contains("valid for 24 hours"), "{}", email.text);
+    }
+
+    /// The headers of the message, and the content types of the parts (without the parameters).
+    fn mime_structure(message: &Message) -> (Vec<String>, Vec<String>) {
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        let lines = formatted.split("\r\n").collect::<Vec<_>>();
+        let headers = lines
+            .iter()
+            .take_while(|line| !line.is_empty())
+            .filter(|line| !line.starts_with(' ') && !line.starts_with("Date:"))
+            .map(|line| match line.split_once(':') {
+                Some(("Message-ID", _)) => "Message-ID: <...>".to_owned(),
+                Some(("Content-Type", value)) => {
+                    format!("Content-Type:{}", value.split(';').next().unwrap())
+                }
+                _ => line.to_string(),
+            })
+            .collect();
+        let parts = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("Content-Type: "))
+            .map(|value| value.split(';').next().unwrap().to_owned())
+            .collect();
+        (headers, parts)
+    }
+
+    #[test]
+    fn test_message_structure() {
+        let options = MailOptions {
+            from: Some("admin@example.com".parse().unwrap()),
+            ..Default::default()
+        };
+        let message = build_message(
+            "user@example.com".parse().unwrap(),
+            render_test_email(None),
+            &options,
+        )
+        .unwrap();
+        let (headers, parts) = mime_structure(&message);
+        assert_eq!(
+            headers,
+            vec![
+                "Message-ID: <...>",
+                "Auto-Submitted: auto-generated",
+                "Content-Language: en",
+                "From: LLDAP <admin@example.com>",
+                "Reply-To: LLDAP <admin@example.com>",
+                "To: user@example.com",
+                "Subject: LLDAP test email",
+                "MIME-Version: 1.0",
+                "Content-Type: multipart/alternative",
+            ]
+        );
+        assert_eq!(
+            parts,
+            vec!["multipart/alternative", "text/plain", "text/html"]
+        );
+        let message_id = message.headers().get_raw("Message-ID").unwrap().to_owned();
+        assert!(message_id.ends_with("@example.com>"), "{}", message_id);
+    }
+
+    #[test]
+    fn test_message_structure_with_logo() {
+        let dir = std::env::temp_dir().join(format!("lldap-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("logo.png"), [0x89, b'P', b'N', b'G']).unwrap();
+        let email = render_test_email(Some(&dir.join("logo.png")));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let options = MailOptions {
+            from: Some("Acme IT <it@acme.example>".parse().unwrap()),
+            ..Default::default()
+        };
+        let message = build_message("user@example.com".parse().unwrap(), email, &options).unwrap();
+        let (headers, parts) = mime_structure(&message);
+        assert!(headers.contains(&"From: Acme IT <it@acme.example>".to_owned()));
+        assert!(!headers.iter().any(|h| h.starts_with("List-Unsubscribe")));
+        assert_eq!(
+            parts,
+            vec![
+                "multipart/alternative",
+                "text/plain",
+                "multipart/related",
+                "text/html",
+                "image/png"
+            ]
+        );
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        assert!(formatted.contains("cid:logo@lldap"), "{}", formatted);
+        assert!(
+            formatted
+                .to_ascii_lowercase()
+                .contains("content-id: <logo@lldap>"),
+            "{}",
+            formatted
+        );
+    }
+
+    #[test]
+    fn test_password_reset_url() {
+        let url = |s: &str| url::Url::parse(s).unwrap();
+        assert_eq!(
+            password_reset_url(&url("https://example.com"), "abc").as_str(),
+            "https://example.com/reset-password/step2/abc"
+        );
+        assert_eq!(
+            password_reset_url(&url("https://example.com/lldap"), "abc").as_str(),
+            "https://example.com/lldap/reset-password/step2/abc"
+        );
+        assert_eq!(
+            password_reset_url(&url("https://example.com/lldap/"), "abc").as_str(),
+            "https://example.com/lldap/reset-password/step2/abc"
+        );
+    }
 }
@@ -0,0 +1,751 @@
+//! In-process queue of the outgoing emails: the HTTP requests return without waiting for the SMTP
+//! server, and a background task sends the emails, retrying the transient failures with an
+//! exponential backoff.
+//!
+//! The queue is not persisted: on shutdown, the remaining emails get one last attempt, within the
+//! graceful shutdown timeout.
+//!
+//! The sending rate is limited, globally and per recipient, not to get the SMTP account suspended
+//! when something goes wrong: the emails over the limits are delayed. When too many emails are
+//! waiting, the oldest deferrable ones are dropped, the password resets are kept.
+
+use crate::infra::configuration::MailOptions;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use lettre::Message;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinHandle, time::Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+#[derive(Debug)]
+pub enum SendError {
+    /// Worth retrying: connection errors, 4xx replies.
+    Transient(anyhow::Error),
+    /// Rejected by the server (5xx replies).
+    Permanent(anyhow::Error),
+}
+
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    async fn send(&self, message: &Message) -> std::result::Result<(), SendError>;
+}
+
+/// Which emails are kept when the queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmailPriority {
+    /// Password resets: sent first, and dropped last.
+    Critical,
+    /// Notifications that can be sent again, like the invitations.
+    Deferrable,
+}
+
+struct QueuedEmail {
+    message: Message,
+    priority: EmailPriority,
+    /// The addresses of the envelope, for the per-recipient limit.
+    recipient: String,
+    to: String,
+    subject: String,
+    message_id: String,
+    delayed: bool,
+}
+
+impl QueuedEmail {
+    fn new(message: Message, priority: EmailPriority) -> Self {
+        let header = |name: &str| {
+            message
+                .headers()
+                .get_raw(name)
+                .unwrap_or_default()
+                .to_owned()
+        };
+        Self {
+            recipient: message
+                .envelope()
+                .to()
+                .iter()
+                .map(|address| address.to_string().to_ascii_lowercase())
+                .collect::<Vec<_>>()
+                .join(","),
+            to: header("To"),
+            subject: header("Subject"),
+            message_id: header("Message-ID"),
+            priority,
+            delayed: false,
+            message,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct RetryPolicy {
+    max_attempts: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            initial_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(300),
+        }
+    }
+}
+
+/// The limits from the configuration, 0 meaning unlimited.
+#[derive(Clone, Copy, Debug)]
+struct RateLimits {
+    per_minute: u32,
+    burst: u32,
+    per_recipient_per_day: u32,
+    max_queued: usize,
+}
+
+impl RateLimits {
+    fn new(options: &MailOptions) -> Self {
+        Self {
+            per_minute: options.max_emails_per_minute,
+            burst: options.email_burst,
+            per_recipient_per_day: options.max_emails_per_recipient_per_day,
+            max_queued: options.max_queued_emails,
+        }
+    }
+}
+
+/// The global limit: `per_second` emails on average, up to `capacity` at once.
+struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limits: &RateLimits, now: Instant) -> Option<Self> {
+        if limits.per_minute == 0 {
+            return None;
+        }
+        let capacity = limits.burst.max(1).into();
+        Some(Self {
+            capacity,
+            per_second: f64::from(limits.per_minute) / 60.0,
+            tokens: capacity,
+            updated: now,
+        })
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// When the next email can be sent, if not now.
+    fn wait_until(&mut self, now: Instant) -> Option<Instant> {
+        self.refill(now);
+        (self.tokens < 1.0)
+            .then(|| now + Duration::from_secs_f64((1.0 - self.tokens) / self.per_second))
+    }
+
+    fn take(&mut self, now: Instant) {
+        self.refill(now);
+        self.tokens -= 1.0;
+    }
+}
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The per-recipient limit, over a sliding window of 24 hours.
+struct RecipientLimiter {
+    max_per_day: usize,
+    sent: HashMap<String, VecDeque<Instant>>,
+}
+
+impl RecipientLimiter {
+    fn new(limits: &RateLimits) -> Self {
+        Self {
+            max_per_day: limits.per_recipient_per_day as usize,
+            sent: HashMap::new(),
+        }
+    }
+
+    /// When the next email can be sent to the recipient, if not now.
+    fn blocked_until(&mut self, recipient: &str, now: Instant) -> Option<Instant> {
+        if self.max_per_day == 0 {
+            return None;
+        }
+        let sent = self.sent.get_mut(recipient)?;
+        while sent.front().is_some_and(|time| *time + DAY <= now) {
+            sent.pop_front();
+        }
+        (sent.len() >= self.max_per_day).then(|| sent[0] + DAY)
+    }
+
+    fn record(&mut self, recipient: &str, now: Instant) {
+        if self.max_per_day == 0 {
+            return;
+        }
+        self.sent
+            .retain(|_, sent| sent.back().is_some_and(|time| *time + DAY > now));
+        self.sent
+            .entry(recipient.to_owned())
+            .or_default()
+            .push_back(now);
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    sent: AtomicU64,
+    failed: AtomicU64,
+    dropped: AtomicU64,
+    delayed: AtomicU64,
+}
+
+/// The activity of the queue since the startup, for the monitoring.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MailQueueStats {
+    /// Waiting to be sent, or being sent.
+    pub queued: usize,
+    pub sent: u64,
+    /// Given up after the retries, or rejected.
+    pub failed: u64,
+    /// Dropped because the queue was full.
+    pub dropped: u64,
+    /// Delayed by the rate limits.
+    pub delayed: u64,
+}
+
+/// The sending side of the queue, cheap to clone.
+#[derive(Clone)]
+pub struct MailQueue {
+    sender: mpsc::UnboundedSender<QueuedEmail>,
+    pending: Arc<AtomicUsize>,
+    counters: Arc<Counters>,
+}
+
+/// The background task sending the emails.
+pub struct MailQueueWorker {
+    handle: JoinHandle<()>,
+    stop: CancellationToken,
+    pending: Arc<AtomicUsize>,
+}
+
+impl MailQueue {
+    /// Starts the background task: it needs a running Tokio runtime.
+    pub fn start(
+        transport: Arc<dyn MailTransport>,
+        options: &MailOptions,
+    ) -> (Self, MailQueueWorker) {
+        Self::start_with_policy(
+            transport,
+            RetryPolicy::new(options.max_send_attempts),
+            RateLimits::new(options),
+        )
+    }
+
+    fn start_with_policy(
+        transport: Arc<dyn MailTransport>,
+        policy: RetryPolicy,
+        limits: RateLimits,
+    ) -> (Self, MailQueueWorker) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let stop = CancellationToken::new();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let counters = Arc::new(Counters::default());
+        let worker = Worker {
+            transport,
+            policy,
+            max_queued: limits.max_queued,
+            bucket: TokenBucket::new(&limits, Instant::now()),
+            recipients: RecipientLimiter::new(&limits),
+            critical: VecDeque::new(),
+            deferrable: VecDeque::new(),
+            pending: pending.clone(),
+            counters: counters.clone(),
+        };
+        let handle = tokio::spawn(run(receiver, worker, stop.clone()));
+        (
+            Self {
+                sender,
+                pending: pending.clone(),
+                counters,
+            },
+            MailQueueWorker {
+                handle,
+                stop,
+                pending,
+            },
+        )
+    }
+
+    /// Returns as soon as the email is queued.
+    pub fn enqueue(&self, message: Message, priority: EmailPriority) -> Result<()> {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.sender
+            .send(QueuedEmail::new(message, priority))
+            .map_err(|_| {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+                anyhow!("The mail queue is stopped")
+            })
+    }
+
+    pub fn stats(&self) -> MailQueueStats {
+        MailQueueStats {
+            queued: self.pending.load(Ordering::SeqCst),
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            delayed: self.counters.delayed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl MailQueueWorker {
+    /// Stops the retries and sends the remaining emails, waiting at most `timeout`.
+    pub async fn drain(self, timeout: Duration) {
+        self.stop.cancel();
+        let pending = self.pending.load(Ordering::SeqCst);
+        if pending > 0 {
+            info!("Sending the {} queued emails before stopping", pending);
+        }
+        if tokio::time::timeout(timeout, self.handle).await.is_err() {
+            error!(
+                "Shutdown timeout: {} queued emails were not sent",
+                self.pending.load(Ordering::SeqCst)
+            );
+        }
+    }
+}
+
+/// The state of the background task.
+struct Worker {
+    transport: Arc<dyn MailTransport>,
+    policy: RetryPolicy,
+    max_queued: usize,
+    bucket: Option<TokenBucket>,
+    recipients: RecipientLimiter,
+    critical: VecDeque<QueuedEmail>,
+    deferrable: VecDeque<QueuedEmail>,
+    pending: Arc<AtomicUsize>,
+    counters: Arc<Counters>,
+}
+
+impl Worker {
+    fn push(&mut self, email: QueuedEmail) {
+        match email.priority {
+            EmailPriority::Critical => self.critical.push_back(email),
+            EmailPriority::Deferrable => self.deferrable.push_back(email),
+        }
+        if self.max_queued == 0 {
+            return;
+        }
+        while self.critical.len() + self.deferrable.len() > self.max_queued {
+            let email = match self.deferrable.pop_front() {
+                Some(email) => email,
+                None => self.critical.pop_front().unwrap(),
+            };
+            error!(
+                to = %email.to,
+                subject = %email.subject,
+                message_id = %email.message_id,
+                "Too many queued emails (more than {}), dropping the email. It was not sent.",
+                self.max_queued
+            );
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// The next email that can be sent within the limits, the critical ones first. Otherwise,
+    /// when to try again, if anything is queued.
+    fn next(&mut self, now: Instant) -> std::result::Result<QueuedEmail, Option<Instant>> {
+        if self.critical.is_empty() && self.deferrable.is_empty() {
+            return Err(None);
+        }
+        let mut retry_at = self
+            .bucket
+            .as_mut()
+            .and_then(|bucket| bucket.wait_until(now));
+        if retry_at.is_none() {
+            for queue in [&mut self.critical, &mut self.deferrable] {
+                let mut position = None;
+                for (index, email) in queue.iter().enumerate() {
+                    match self.recipients.blocked_until(&email.recipient, now) {
+                        None => {
+                            position = Some(index);
+                            break;
+                        }
+                        Some(time) => retry_at = Some(retry_at.map_or(time, |t| t.min(time))),
+                    }
+                }
+                if let Some(email) = position.and_then(|index| queue.remove(index)) {
+                    if let Some(bucket) = &mut self.bucket {
+                        bucket.take(now);
+                    }
+                    self.recipients.record(&email.recipient, now);
+                    return Ok(email);
+                }
+            }
+        }
+        for email in self.critical.iter_mut().chain(self.deferrable.iter_mut()) {
+            if !email.delayed {
+                email.delayed = true;
+                self.counters.delayed.fetch_add(1, Ordering::Relaxed);
+                debug!(to = %email.to, subject = %email.subject, "Email delayed by the rate limits");
+            }
+        }
+        Err(retry_at)
+    }
+
+    /// The rate limits only count the first attempt: the retries are already spaced out.
+    async fn send(&self, email: QueuedEmail, stop: &CancellationToken) {
+        let counter = if send_with_retries(self.transport.as_ref(), &email, self.policy, stop).await
+        {
+            &self.counters.sent
+        } else {
+            &self.counters.failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn run(
+    mut receiver: mpsc::UnboundedReceiver<QueuedEmail>,
+    mut worker: Worker,
+    stop: CancellationToken,
+) {
+    // Once stopping, no new email is accepted, and the queued ones are sent within the limits.
+    let mut stopping = false;
+    loop {
+        while let Ok(email) = receiver.try_recv() {
+            worker.push(email);
+        }
+        let retry_at = match worker.next(Instant::now()) {
+            Ok(email) => {
+                worker.send(email, &stop).await;
+                continue;
+            }
+            Err(retry_at) => retry_at,
+        };
+        if stopping {
+            match retry_at {
+                Some(time) => tokio::time::sleep_until(time).await,
+                None => return,
+            }
+            continue;
+        }
+        let wait = async {
+            match retry_at {
+                Some(time) => tokio::time::sleep_until(time).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            email = receiver.recv() => match email {
+                Some(email) => worker.push(email),
+                None => stopping = true,
+            },
+            _ = wait => {}
+            _ = stop.cancelled() => {
+                receiver.close();
+                stopping = true;
+            }
+        }
+    }
+}
+
+/// Once `stop` is cancelled, the email gets one last attempt. Returns whether it was sent.
+async fn send_with_retries(
+    transport: &dyn MailTransport,
+    email: &QueuedEmail,
+    policy: RetryPolicy,
+    stop: &CancellationToken,
+) -> bool {
+    let mut delay = policy.initial_delay;
+    let mut attempt = 1;
+    loop {
+        let error = match transport.send(&email.message).await {
+            Ok(()) => {
+                debug!(to = %email.to, subject = %email.subject, attempt, "Email sent");
+                return true;
+            }
+            Err(SendError::Transient(e))
+                if attempt < policy.max_attempts && !stop.is_cancelled() =>
+            {
+                warn!(
+                    to = %email.to,
+                    subject = %email.subject,
+                    attempt,
+                    "Could not send the email, retrying in {}s: {:#}",
+                    delay.as_secs_f32(),
+                    e
+                );
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = stop.cancelled() => {}
+                }
+                delay = (delay * 2).min(policy.max_delay);
+                attempt += 1;
+                continue;
+            }
+            Err(SendError::Transient(e)) | Err(SendError::Permanent(e)) => e,
+        };
+        error!(
+            to = %email.to,
+            subject = %email.subject,
+            message_id = %email.message_id,
+            attempts = attempt,
+            "Giving up on the email: {:#}. It was not sent: the user has to request a new password reset, or the invitation has to be sent again.",
+            error
+        );
+        return false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::sync::Mutex;
+
+    /// Fails with the given errors first, then succeeds.
+    struct FakeTransport {
+        failures: Mutex<Vec<SendError>>,
+        attempts: AtomicUsize,
+        sent: Mutex<Vec<String>>,
+    }
+
+    impl FakeTransport {
+        fn new(failures: Vec<SendError>) -> Arc<Self> {
+            Arc::new(Self {
+                failures: Mutex::new(failures),
+                attempts: AtomicUsize::new(0),
+                sent: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl MailTransport for FakeTransport {
+        async fn send(&self, message: &Message) -> std::result::Result<(), SendError> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            let mut failures = self.failures.lock().unwrap();
+            if failures.is_empty() {
+                let subject = message.headers().get_raw("Subject").unwrap().to_owned();
+                self.sent.lock().unwrap().push(subject);
+                Ok(())
+            } else {
+                Err(failures.remove(0))
+            }
+        }
+    }
+
+    fn message(subject: &str) -> Message {
+        message_to("user@example.com", subject)
+    }
+
+    fn message_to(to: &str, subject: &str) -> Message {
+        Message::builder()
+            .from("LLDAP <nobody@lldap>".parse().unwrap())
+            .to(to.parse().unwrap())
+            .subject(subject)
+            .body(String::from("Hello"))
+            .unwrap()
+    }
+
+    fn unlimited() -> RateLimits {
+        RateLimits {
+            per_minute: 0,
+            burst: 0,
+            per_recipient_per_day: 0,
+            max_queued: 0,
+        }
+    }
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(4),
+        }
+    }
+
+    async fn wait_until_sent(queue: &MailQueue) {
+        while queue.pending.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    fn transient() -> SendError {
+        SendError::Transient(anyhow!("451 try again later"))
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_failures() {
+        let transport = FakeTransport::new(vec![transient(), transient()]);
+        let (queue, worker) =
+            MailQueue::start_with_policy(transport.clone(), policy(3), unlimited());
+        queue
+            .enqueue(message("reset"), EmailPriority::Critical)
+            .unwrap();
+        wait_until_sent(&queue).await;
+        worker.drain(Duration::from_secs(5)).await;
+        assert_eq!(transport.attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(*transport.sent.lock().unwrap(), vec!["reset".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up() {
+        // A permanent failure is not retried.
+        let transport = FakeTransport::new(vec![
+            SendError::Permanent(anyhow!("550 no such user")),
+            transient(),
+            transient(),
+        ]);
+        let (queue, worker) =
+            MailQueue::start_with_policy(transport.clone(), policy(2), unlimited());
+        queue
+            .enqueue(message("first"), EmailPriority::Deferrable)
+            .unwrap();
+        // Too many transient failures.
+        queue
+            .enqueue(message("second"), EmailPriority::Deferrable)
+            .unwrap();
+        queue
+            .enqueue(message("third"), EmailPriority::Deferrable)
+            .unwrap();
+        wait_until_sent(&queue).await;
+        worker.drain(Duration::from_secs(5)).await;
+        assert_eq!(transport.attempts.load(Ordering::SeqCst), 4);
+        assert_eq!(*transport.sent.lock().unwrap(), vec!["third".to_owned()]);
+        assert_eq!(
+            queue.stats(),
+            MailQueueStats {
+                sent: 1,
+                failed: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drain_on_shutdown() {
+        let transport = FakeTransport::new(vec![]);
+        let (queue, worker) =
+            MailQueue::start_with_policy(transport.clone(), policy(3), unlimited());
+        for subject in ["a", "b", "c"] {
+            queue
+                .enqueue(message(subject), EmailPriority::Critical)
+                .unwrap();
+        }
+        worker.drain(Duration::from_secs(5)).await;
+        assert_eq!(
+            *transport.sent.lock().unwrap(),
+            vec!["a".to_owned(), "b".to_owned(), "c".to_owned()]
+        );
+        assert!(queue
+            .enqueue(message("late"), EmailPriority::Critical)
+            .is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_global_rate_limit() {
+        let transport = FakeTransport::new(vec![]);
+        let limits = RateLimits {
+            per_minute: 60,
+            burst: 2,
+            ..unlimited()
+        };
+        let (queue, worker) = MailQueue::start_with_policy(transport.clone(), policy(3), limits);
+        let start = Instant::now();
+        for subject in ["a", "b", "c", "d"] {
+            queue
+                .enqueue(
+                    message_to(&format!("{}@example.com", subject), subject),
+                    EmailPriority::Critical,
+                )
+                .unwrap();
+        }
+        worker.drain(Duration::from_secs(10)).await;
+        // Two at once, then one per second.
+        assert_eq!(start.elapsed().as_secs(), 2);
+        assert_eq!(transport.sent.lock().unwrap().len(), 4);
+        assert_eq!(queue.stats().delayed, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_recipient_daily_cap() {
+        let transport = FakeTransport::new(vec![]);
+        let limits = RateLimits {
+            per_recipient_per_day: 2,
+            ..unlimited()
+        };
+        let (queue, worker) = MailQueue::start_with_policy(transport.clone(), policy(3), limits);
+        let start = Instant::now();
+        for (to, subject) in [
+            ("alice@example.com", "a1"),
+            ("Alice@Example.com", "a2"),
+            ("alice@example.com", "a3"),
+            ("bob@example.com", "b1"),
+        ] {
+            queue
+                .enqueue(message_to(to, subject), EmailPriority::Critical)
+                .unwrap();
+        }
+        worker.drain(DAY * 2).await;
+        // The third email to Alice waits for a day, without blocking Bob's.
+        assert_eq!(
+            *transport.sent.lock().unwrap(),
+            vec!["a1", "a2", "b1", "a3"]
+        );
+        assert_eq!(start.elapsed(), DAY);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_full_queue_drops_deferrable_emails() {
+        let transport = FakeTransport::new(vec![]);
+        let limits = RateLimits {
+            per_minute: 1,
+            burst: 1,
+            max_queued: 2,
+            ..unlimited()
+        };
+        let (queue, worker) = MailQueue::start_with_policy(transport.clone(), policy(3), limits);
+        queue
+            .enqueue(message("invitation 1"), EmailPriority::Deferrable)
+            .unwrap();
+        queue
+            .enqueue(message("reset 1"), EmailPriority::Critical)
+            .unwrap();
+        queue
+            .enqueue(message("invitation 2"), EmailPriority::Deferrable)
+            .unwrap();
+        queue
+            .enqueue(message("reset 2"), EmailPriority::Critical)
+            .unwrap();
+        worker.drain(Duration::from_secs(120)).await;
+        assert_eq!(*transport.sent.lock().unwrap(), vec!["reset 1", "reset 2"]);
+        assert_eq!(
+            queue.stats(),
+            MailQueueStats {
+                queued: 0,
+                sent: 2,
+                failed: 0,
+                dropped: 2,
+                delayed: 1,
+            }
+        );
+    }
+}
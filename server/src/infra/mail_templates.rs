@@ -0,0 +1,1153 @@
+//! Templates of the emails, with Tera. Each email has a subject, a plain-text body and an HTML
+//! body, that can be overridden by files in `smtp_options.templates_dir`:
+//! `<email>.subject.txt`, `<email>.txt` and `<email>.html`. The built-in HTML templates extend
+//! `layout.html`, which can be overridden as well to change the branding.
+//!
+//! The emails are sent in the preferred language of the user, or else in
+//! `smtp_options.default_language`, or else in English: "de-CH" falls back to "de". The built-in
+//! templates are in English, French and German. The files at the root of `templates_dir` are the
+//! English ones, and the other languages are in subdirectories named after their language tag,
+//! e.g. `fr/password_reset.txt`. A language is used for an email if it has its text template, in
+//! which case it needs the subject and the HTML as well. `layout.html` is shared by all the
+//! languages, and gets the `language` of the email.
+//!
+//! Variables available in all the templates: `server_name` (the host of `http_url`) and
+//! `server_url`. The password reset templates also get `username` (the display name, or the user
+//! id), `reset_url` and `expiry_minutes`. The invitation templates get `username`,
+//! `invitation_url` and `expiry_days`. The email change templates, sent to the new address, get
+//! `username`, `new_email`, `verification_url` and `expiry_hours`, and the notification sent to
+//! the old address once the change is verified (`email_changed`) gets `username` and
+//! `new_email`. The account expiration warnings, sent to the user (`account_expiring`) and to
+//! `account_expiration.admin_email` (`account_expiring_admin`), get `username`, `user_id`,
+//! `expiry_date` and `days_left`. With `smtp_options.logo_file`, `logo` is true and the image is
+//! attached to the HTML part: `<img src="cid:{{ logo_cid }}">`.
+
+use crate::domain::validation::check_language_tag;
+use anyhow::{anyhow, bail, Context as _, Result};
+use lettre::message::header::ContentType;
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tera::{Context, Tera};
+use tracing::warn;
+
+pub const PASSWORD_RESET: &str = "password_reset";
+pub const INVITATION: &str = "invitation";
+pub const TEST_EMAIL: &str = "test_email";
+pub const EMAIL_CHANGE: &str = "email_change";
+pub const EMAIL_CHANGED: &str = "email_changed";
+pub const ACCOUNT_EXPIRING: &str = "account_expiring";
+pub const ACCOUNT_EXPIRING_ADMIN: &str = "account_expiring_admin";
+
+const EMAILS: [&str; 7] = [
+    PASSWORD_RESET,
+    INVITATION,
+    TEST_EMAIL,
+    EMAIL_CHANGE,
+    EMAIL_CHANGED,
+    ACCOUNT_EXPIRING,
+    ACCOUNT_EXPIRING_ADMIN,
+];
+
+/// The Content-ID of the inline logo.
+pub const LOGO_CID: &str = "logo@lldap";
+
+const LAYOUT: &str = "layout.html";
+
+const PARTS: [&str; 3] = ["subject.txt", "txt", "html"];
+
+/// The language of the templates at the root, used when there is no better match.
+pub const FALLBACK_LANGUAGE: &str = "en";
+
+/// The name of a template: `fr/password_reset.txt`, or `password_reset.txt` for English.
+fn template_name(language: &str, email: &str, part: &str) -> String {
+    if language == FALLBACK_LANGUAGE {
+        format!("{}.{}", email, part)
+    } else {
+        format!("{}/{}.{}", language, email, part)
+    }
+}
+
+/// The languages to try, in order: the user's, the server default, then English, each followed by
+/// its more general tags ("de-CH" then "de"). Lowercase, like the template names.
+fn language_candidates(user_language: Option<&str>, default_language: &str) -> Vec<String> {
+    let mut candidates: Vec<String> = Vec::new();
+    for tag in user_language
+        .into_iter()
+        .chain([default_language, FALLBACK_LANGUAGE])
+    {
+        let tag = tag.to_ascii_lowercase();
+        let mut prefix = tag.as_str();
+        loop {
+            if !prefix.is_empty() && !candidates.iter().any(|c| c == prefix) {
+                candidates.push(prefix.to_owned());
+            }
+            match prefix.rsplit_once('-') {
+                Some((parent, _)) => prefix = parent,
+                None => break,
+            }
+        }
+    }
+    candidates
+}
+
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    (
+        LAYOUT,
+        r#"<!DOCTYPE html>
+<html lang="{{ language }}">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{% block title %}{% endblock title %}</title>
+</head>
+<body style="margin: 0; padding: 24px; background-color: #f4f5f7; font-family: Helvetica, Arial, sans-serif; color: #212529;">
+<div style="max-width: 560px; margin: 0 auto; padding: 32px; background-color: #ffffff; border-radius: 6px;">
+{% if logo %}<p style="margin: 0 0 24px; text-align: center;"><img src="cid:{{ logo_cid }}" alt="{{ server_name }}" style="max-height: 64px;"></p>{% endif %}
+{% block content %}{% endblock content %}
+</div>
+<p style="text-align: center; font-size: 12px; color: #6c757d;"><a href="{{ server_url }}" style="color: #6c757d;">{{ server_name }}</a></p>
+</body>
+</html>
+"#,
+    ),
+    (
+        "password_reset.subject.txt",
+        "[LLDAP] Password reset requested",
+    ),
+    (
+        "password_reset.txt",
+        "Hello {{ username }},
+This email has been sent to you in order to validate your identity.
+If you did not initiate the process your credentials might have been
+compromised. You should reset your password and contact an administrator.
+
+To reset your password please visit the following URL: {{ reset_url }}
+
+Please contact an administrator if you did not initiate the process.",
+    ),
+    (
+        "password_reset.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Password reset requested{% endblock title %}
+{% block content %}
+<p>Hello {{ username }},</p>
+<p>This email has been sent to you in order to validate your identity. If you did not initiate
+the process your credentials might have been compromised. You should reset your password and
+contact an administrator.</p>
+<p style="text-align: center;"><a href="{{ reset_url }}" style="display: inline-block; padding: 10px 20px; background-color: #0d6efd; color: #ffffff; border-radius: 4px; text-decoration: none;">Reset your password</a></p>
+<p>The link is valid for {{ expiry_minutes }} minutes. Please contact an administrator if you did
+not initiate the process.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "invitation.subject.txt",
+        "[LLDAP] Your account on {{ server_name }}",
+    ),
+    (
+        "invitation.txt",
+        "Hello {{ username }},
+An account has been created for you on {{ server_name }}.
+
+To choose your password, please visit the following URL: {{ invitation_url }}
+
+The link is valid for {{ expiry_days }} days, and can only be used once.",
+    ),
+    (
+        "invitation.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Your account on {{ server_name }}{% endblock title %}
+{% block content %}
+<p>Hello {{ username }},</p>
+<p>An account has been created for you on {{ server_name }}.</p>
+<p style="text-align: center;"><a href="{{ invitation_url }}" style="display: inline-block; padding: 10px 20px; background-color: #0d6efd; color: #ffffff; border-radius: 4px; text-decoration: none;">Choose your password</a></p>
+<p>The link is valid for {{ expiry_days }} days, and can only be used once.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "email_change.subject.txt",
+        "[LLDAP] Verify your new email address",
+    ),
+    (
+        "email_change.txt",
+        "Hello {{ username }},
+A change of the email address of your account on {{ server_name }} to
+{{ new_email }} has been requested.
+
+To confirm the change, please visit the following URL: {{ verification_url }}
+
+The link is valid for {{ expiry_hours }} hours. If you did not request this
+change, you can ignore this email.",
+    ),
+    (
+        "email_change.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Verify your new email address{% endblock title %}
+{% block content %}
+<p>Hello {{ username }},</p>
+<p>A change of the email address of your account on {{ server_name }} to {{ new_email }} has been
+requested.</p>
+<p style="text-align: center;"><a href="{{ verification_url }}" style="display: inline-block; padding: 10px 20px; background-color: #0d6efd; color: #ffffff; border-radius: 4px; text-decoration: none;">Confirm the new address</a></p>
+<p>The link is valid for {{ expiry_hours }} hours. If you did not request this change, you can
+ignore this email.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "email_changed.subject.txt",
+        "[LLDAP] Your email address has been changed",
+    ),
+    (
+        "email_changed.txt",
+        "Hello {{ username }},
+The email address of your account on {{ server_name }} has been changed to
+{{ new_email }}. This address will not receive the emails of the account anymore.
+
+Please contact an administrator if you did not request this change.",
+    ),
+    (
+        "email_changed.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Your email address has been changed{% endblock title %}
+{% block content %}
+<p>Hello {{ username }},</p>
+<p>The email address of your account on {{ server_name }} has been changed to {{ new_email }}.
+This address will not receive the emails of the account anymore.</p>
+<p>Please contact an administrator if you did not request this change.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "account_expiring.subject.txt",
+        "[LLDAP] Your account expires on {{ expiry_date }}",
+    ),
+    (
+        "account_expiring.txt",
+        "Hello {{ username }},
+Your account {{ user_id }} on {{ server_name }} expires on {{ expiry_date }}, in
+{{ days_left }} days. It will be disabled at that date.
+
+Please contact an administrator if you still need it.",
+    ),
+    (
+        "account_expiring.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Your account expires on {{ expiry_date }}{% endblock title %}
+{% block content %}
+<p>Hello {{ username }},</p>
+<p>Your account {{ user_id }} on {{ server_name }} expires on {{ expiry_date }}, in
+{{ days_left }} days. It will be disabled at that date.</p>
+<p>Please contact an administrator if you still need it.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "account_expiring_admin.subject.txt",
+        "[LLDAP] The account {{ user_id }} expires on {{ expiry_date }}",
+    ),
+    (
+        "account_expiring_admin.txt",
+        "Hello,
+The account {{ user_id }} ({{ username }}) on {{ server_name }} expires on
+{{ expiry_date }}, in {{ days_left }} days. It will be disabled at that date.
+
+To keep it, change or remove its expiration date: {{ server_url }}",
+    ),
+    (
+        "account_expiring_admin.html",
+        r#"{% extends "layout.html" %}
+{% block title %}The account {{ user_id }} expires on {{ expiry_date }}{% endblock title %}
+{% block content %}
+<p>Hello,</p>
+<p>The account {{ user_id }} ({{ username }}) on {{ server_name }} expires on {{ expiry_date }},
+in {{ days_left }} days. It will be disabled at that date.</p>
+<p>To keep it, change or remove its expiration date on
+<a href="{{ server_url }}">{{ server_name }}</a>.</p>
+{% endblock content %}
+"#,
+    ),
+    ("test_email.subject.txt", "LLDAP test email"),
+    (
+        "test_email.txt",
+        "The test is successful! You can send emails from LLDAP",
+    ),
+    (
+        "test_email.html",
+        r#"{% extends "layout.html" %}
+{% block title %}LLDAP test email{% endblock title %}
+{% block content %}
+<p>The test is successful! You can send emails from LLDAP.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "fr/password_reset.subject.txt",
+        "[LLDAP] Demande de réinitialisation du mot de passe",
+    ),
+    (
+        "fr/password_reset.txt",
+        "Bonjour {{ username }},
+Ce courriel vous a été envoyé afin de vérifier votre identité.
+Si vous n'êtes pas à l'origine de cette demande, vos identifiants ont peut-être
+été compromis. Vous devriez réinitialiser votre mot de passe et contacter un
+administrateur.
+
+Pour réinitialiser votre mot de passe, veuillez vous rendre à l'adresse suivante : {{ reset_url }}
+
+Veuillez contacter un administrateur si vous n'êtes pas à l'origine de cette demande.",
+    ),
+    (
+        "fr/password_reset.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Demande de réinitialisation du mot de passe{% endblock title %}
+{% block content %}
+<p>Bonjour {{ username }},</p>
+<p>Ce courriel vous a été envoyé afin de vérifier votre identité. Si vous n'êtes pas à l'origine
+de cette demande, vos identifiants ont peut-être été compromis. Vous devriez réinitialiser votre
+mot de passe et contacter un administrateur.</p>
+<p style="text-align: center;"><a href="{{ reset_url }}" style="display: inline-block; padding: 10px 20px; background-color: #0d6efd; color: #ffffff; border-radius: 4px; text-decoration: none;">Réinitialiser votre mot de passe</a></p>
+<p>Le lien est valable {{ expiry_minutes }} minutes. Veuillez contacter un administrateur si vous
+n'êtes pas à l'origine de cette demande.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "fr/invitation.subject.txt",
+        "[LLDAP] Votre compte sur {{ server_name }}",
+    ),
+    (
+        "fr/invitation.txt",
+        "Bonjour {{ username }},
+Un compte a été créé pour vous sur {{ server_name }}.
+
+Pour choisir votre mot de passe, veuillez vous rendre à l'adresse suivante : {{ invitation_url }}
+
+Le lien est valable {{ expiry_days }} jours et ne peut être utilisé qu'une seule fois.",
+    ),
+    (
+        "fr/invitation.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Votre compte sur {{ server_name }}{% endblock title %}
+{% block content %}
+<p>Bonjour {{ username }},</p>
+<p>Un compte a été créé pour vous sur {{ server_name }}.</p>
+<p style="text-align: center;"><a href="{{ invitation_url }}" style="display: inline-block; padding: 10px 20px; background-color: #0d6efd; color: #ffffff; border-radius: 4px; text-decoration: none;">Choisir votre mot de passe</a></p>
+<p>Le lien est valable {{ expiry_days }} jours et ne peut être utilisé qu'une seule fois.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "fr/email_change.subject.txt",
+        "[LLDAP] Vérifiez votre nouvelle adresse e-mail",
+    ),
+    (
+        "fr/email_change.txt",
+        "Bonjour {{ username }},
+Le changement de l'adresse e-mail de votre compte sur {{ server_name }} pour
+{{ new_email }} a été demandé.
+
+Pour confirmer le changement, veuillez vous rendre à l'adresse suivante : {{ verification_url }}
+
+Le lien est valable {{ expiry_hours }} heures. Si vous n'êtes pas à l'origine de
+cette demande, vous pouvez ignorer ce courriel.",
+    ),
+    (
+        "fr/email_change.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Vérifiez votre nouvelle adresse e-mail{% endblock title %}
+{% block content %}
+<p>Bonjour {{ username }},</p>
+<p>Le changement de l'adresse e-mail de votre compte sur {{ server_name }} pour {{ new_email }} a
+été demandé.</p>
+<p style="text-align: center;"><a href="{{ verification_url }}" style="display: inline-block; padding: 10px 20px; background-color: #0d6efd; color: #ffffff; border-radius: 4px; text-decoration: none;">Confirmer la nouvelle adresse</a></p>
+<p>Le lien est valable {{ expiry_hours }} heures. Si vous n'êtes pas à l'origine de cette demande,
+vous pouvez ignorer ce courriel.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "fr/email_changed.subject.txt",
+        "[LLDAP] Votre adresse e-mail a été modifiée",
+    ),
+    (
+        "fr/email_changed.txt",
+        "Bonjour {{ username }},
+L'adresse e-mail de votre compte sur {{ server_name }} a été remplacée par
+{{ new_email }}. Cette adresse ne recevra plus les courriels du compte.
+
+Veuillez contacter un administrateur si vous n'êtes pas à l'origine de ce changement.",
+    ),
+    (
+        "fr/email_changed.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Votre adresse e-mail a été modifiée{% endblock title %}
+{% block content %}
+<p>Bonjour {{ username }},</p>
+<p>L'adresse e-mail de votre compte sur {{ server_name }} a été remplacée par {{ new_email }}.
+Cette adresse ne recevra plus les courriels du compte.</p>
+<p>Veuillez contacter un administrateur si vous n'êtes pas à l'origine de ce changement.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "fr/account_expiring.subject.txt",
+        "[LLDAP] Votre compte expire le {{ expiry_date }}",
+    ),
+    (
+        "fr/account_expiring.txt",
+        "Bonjour {{ username }},
+Votre compte {{ user_id }} sur {{ server_name }} expire le {{ expiry_date }}, dans
+{{ days_left }} jours. Il sera désactivé à cette date.
+
+Veuillez contacter un administrateur si vous en avez encore besoin.",
+    ),
+    (
+        "fr/account_expiring.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Votre compte expire le {{ expiry_date }}{% endblock title %}
+{% block content %}
+<p>Bonjour {{ username }},</p>
+<p>Votre compte {{ user_id }} sur {{ server_name }} expire le {{ expiry_date }}, dans
+{{ days_left }} jours. Il sera désactivé à cette date.</p>
+<p>Veuillez contacter un administrateur si vous en avez encore besoin.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "fr/account_expiring_admin.subject.txt",
+        "[LLDAP] Le compte {{ user_id }} expire le {{ expiry_date }}",
+    ),
+    (
+        "fr/account_expiring_admin.txt",
+        "Bonjour,
+Le compte {{ user_id }} ({{ username }}) sur {{ server_name }} expire le
+{{ expiry_date }}, dans {{ days_left }} jours. Il sera désactivé à cette date.
+
+Pour le conserver, modifiez ou supprimez sa date d'expiration : {{ server_url }}",
+    ),
+    (
+        "fr/account_expiring_admin.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Le compte {{ user_id }} expire le {{ expiry_date }}{% endblock title %}
+{% block content %}
+<p>Bonjour,</p>
+<p>Le compte {{ user_id }} ({{ username }}) sur {{ server_name }} expire le {{ expiry_date }},
+dans {{ days_left }} jours. Il sera désactivé à cette date.</p>
+<p>Pour le conserver, modifiez ou supprimez sa date d'expiration sur
+<a href="{{ server_url }}">{{ server_name }}</a>.</p>
+{% endblock content %}
+"#,
+    ),
+    ("fr/test_email.subject.txt", "Courriel de test LLDAP"),
+    (
+        "fr/test_email.txt",
+        "Le test a réussi ! Vous pouvez envoyer des courriels depuis LLDAP",
+    ),
+    (
+        "fr/test_email.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Courriel de test LLDAP{% endblock title %}
+{% block content %}
+<p>Le test a réussi ! Vous pouvez envoyer des courriels depuis LLDAP.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "de/password_reset.subject.txt",
+        "[LLDAP] Zurücksetzen des Passworts angefordert",
+    ),
+    (
+        "de/password_reset.txt",
+        "Hallo {{ username }},
+diese E-Mail wurde Ihnen gesendet, um Ihre Identität zu bestätigen.
+Falls Sie den Vorgang nicht selbst ausgelöst haben, wurden Ihre Zugangsdaten
+möglicherweise kompromittiert. Sie sollten Ihr Passwort zurücksetzen und sich an
+einen Administrator wenden.
+
+Um Ihr Passwort zurückzusetzen, rufen Sie bitte die folgende URL auf: {{ reset_url }}
+
+Bitte wenden Sie sich an einen Administrator, falls Sie den Vorgang nicht selbst ausgelöst haben.",
+    ),
+    (
+        "de/password_reset.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Zurücksetzen des Passworts angefordert{% endblock title %}
+{% block content %}
+<p>Hallo {{ username }},</p>
+<p>diese E-Mail wurde Ihnen gesendet, um Ihre Identität zu bestätigen. Falls Sie den Vorgang nicht
+selbst ausgelöst haben, wurden Ihre Zugangsdaten möglicherweise kompromittiert. Sie sollten Ihr
+Passwort zurücksetzen und sich an einen Administrator wenden.</p>
+<p style="text-align: center;"><a href="{{ reset_url }}" style="display: inline-block; padding: 10px 20px; background-color: #0d6efd; color: #ffffff; border-radius: 4px; text-decoration: none;">Passwort zurücksetzen</a></p>
+<p>Der Link ist {{ expiry_minutes }} Minuten lang gültig. Bitte wenden Sie sich an einen
+Administrator, falls Sie den Vorgang nicht selbst ausgelöst haben.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "de/invitation.subject.txt",
+        "[LLDAP] Ihr Konto auf {{ server_name }}",
+    ),
+    (
+        "de/invitation.txt",
+        "Hallo {{ username }},
+für Sie wurde ein Konto auf {{ server_name }} erstellt.
+
+Um Ihr Passwort festzulegen, rufen Sie bitte die folgende URL auf: {{ invitation_url }}
+
+Der Link ist {{ expiry_days }} Tage lang gültig und kann nur einmal verwendet werden.",
+    ),
+    (
+        "de/invitation.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Ihr Konto auf {{ server_name }}{% endblock title %}
+{% block content %}
+<p>Hallo {{ username }},</p>
+<p>für Sie wurde ein Konto auf {{ server_name }} erstellt.</p>
+<p style="text-align: center;"><a href="{{ invitation_url }}" style="display: inline-block; padding: 10px 20px; background-color: #0d6efd; color: #ffffff; border-radius: 4px; text-decoration: none;">Passwort festlegen</a></p>
+<p>Der Link ist {{ expiry_days }} Tage lang gültig und kann nur einmal verwendet werden.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "de/email_change.subject.txt",
+        "[LLDAP] Bestätigen Sie Ihre neue E-Mail-Adresse",
+    ),
+    (
+        "de/email_change.txt",
+        "Hallo {{ username }},
+für Ihr Konto auf {{ server_name }} wurde die Änderung der E-Mail-Adresse zu
+{{ new_email }} angefordert.
+
+Um die Änderung zu bestätigen, rufen Sie bitte die folgende URL auf: {{ verification_url }}
+
+Der Link ist {{ expiry_hours }} Stunden lang gültig. Falls Sie diese Änderung
+nicht angefordert haben, können Sie diese E-Mail ignorieren.",
+    ),
+    (
+        "de/email_change.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Bestätigen Sie Ihre neue E-Mail-Adresse{% endblock title %}
+{% block content %}
+<p>Hallo {{ username }},</p>
+<p>für Ihr Konto auf {{ server_name }} wurde die Änderung der E-Mail-Adresse zu {{ new_email }}
+angefordert.</p>
+<p style="text-align: center;"><a href="{{ verification_url }}" style="display: inline-block; padding: 10px 20px; background-color: #0d6efd; color: #ffffff; border-radius: 4px; text-decoration: none;">Neue Adresse bestätigen</a></p>
+<p>Der Link ist {{ expiry_hours }} Stunden lang gültig. Falls Sie diese Änderung nicht angefordert
+haben, können Sie diese E-Mail ignorieren.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "de/email_changed.subject.txt",
+        "[LLDAP] Ihre E-Mail-Adresse wurde geändert",
+    ),
+    (
+        "de/email_changed.txt",
+        "Hallo {{ username }},
+die E-Mail-Adresse Ihres Kontos auf {{ server_name }} wurde zu {{ new_email }}
+geändert. Diese Adresse erhält keine E-Mails des Kontos mehr.
+
+Bitte wenden Sie sich an einen Administrator, falls Sie diese Änderung nicht angefordert haben.",
+    ),
+    (
+        "de/email_changed.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Ihre E-Mail-Adresse wurde geändert{% endblock title %}
+{% block content %}
+<p>Hallo {{ username }},</p>
+<p>die E-Mail-Adresse Ihres Kontos auf {{ server_name }} wurde zu {{ new_email }} geändert. Diese
+Adresse erhält keine E-Mails des Kontos mehr.</p>
+<p>Bitte wenden Sie sich an einen Administrator, falls Sie diese Änderung nicht angefordert
+haben.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "de/account_expiring.subject.txt",
+        "[LLDAP] Ihr Konto läuft am {{ expiry_date }} ab",
+    ),
+    (
+        "de/account_expiring.txt",
+        "Hallo {{ username }},
+Ihr Konto {{ user_id }} auf {{ server_name }} läuft am {{ expiry_date }} ab, in
+{{ days_left }} Tagen. Es wird an diesem Datum deaktiviert.
+
+Bitte wenden Sie sich an einen Administrator, falls Sie es weiterhin benötigen.",
+    ),
+    (
+        "de/account_expiring.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Ihr Konto läuft am {{ expiry_date }} ab{% endblock title %}
+{% block content %}
+<p>Hallo {{ username }},</p>
+<p>Ihr Konto {{ user_id }} auf {{ server_name }} läuft am {{ expiry_date }} ab, in
+{{ days_left }} Tagen. Es wird an diesem Datum deaktiviert.</p>
+<p>Bitte wenden Sie sich an einen Administrator, falls Sie es weiterhin benötigen.</p>
+{% endblock content %}
+"#,
+    ),
+    (
+        "de/account_expiring_admin.subject.txt",
+        "[LLDAP] Das Konto {{ user_id }} läuft am {{ expiry_date }} ab",
+    ),
+    (
+        "de/account_expiring_admin.txt",
+        "Hallo,
+das Konto {{ user_id }} ({{ username }}) auf {{ server_name }} läuft am
+{{ expiry_date }} ab, in {{ days_left }} Tagen. Es wird an diesem Datum deaktiviert.
+
+Um es zu behalten, ändern oder entfernen Sie sein Ablaufdatum: {{ server_url }}",
+    ),
+    (
+        "de/account_expiring_admin.html",
+        r#"{% extends "layout.html" %}
+{% block title %}Das Konto {{ user_id }} läuft am {{ expiry_date }} ab{% endblock title %}
+{% block content %}
+<p>Hallo,</p>
+<p>das Konto {{ user_id }} ({{ username }}) auf {{ server_name }} läuft am {{ expiry_date }} ab,
+in {{ days_left }} Tagen. Es wird an diesem Datum deaktiviert.</p>
+<p>Um es zu behalten, ändern oder entfernen Sie sein Ablaufdatum auf
+<a href="{{ server_url }}">{{ server_name }}</a>.</p>
+{% endblock content %}
+"#,
+    ),
+    ("de/test_email.subject.txt", "LLDAP-Test-E-Mail"),
+    (
+        "de/test_email.txt",
+        "Der Test war erfolgreich! Sie können E-Mails von LLDAP aus senden",
+    ),
+    (
+        "de/test_email.html",
+        r#"{% extends "layout.html" %}
+{% block title %}LLDAP-Test-E-Mail{% endblock title %}
+{% block content %}
+<p>Der Test war erfolgreich! Sie können E-Mails von LLDAP aus senden.</p>
+{% endblock content %}
+"#,
+    ),
+];
+
+/// An image attached to the HTML part of the emails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Logo {
+    pub content: Vec<u8>,
+    pub content_type: ContentType,
+}
+
+impl Logo {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content_type = match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("png") => "image/png",
+            Some("jpg") | Some("jpeg") => "image/jpeg",
+            Some("gif") => "image/gif",
+            _ => bail!(
+                "Unsupported logo format for {}, expected a PNG, JPEG or GIF image",
+                path.display()
+            ),
+        };
+        Ok(Self {
+            content: std::fs::read(path)
+                .with_context(|| format!("while reading the logo {}", path.display()))?,
+            content_type: ContentType::parse(content_type).unwrap(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+    /// Referenced by the HTML part.
+    pub logo: Option<Arc<Logo>>,
+    /// The language of the templates, e.g. "fr".
+    pub language: String,
+}
+
+#[derive(Debug)]
+pub struct MailTemplates {
+    tera: Tera,
+    logo: Option<Arc<Logo>>,
+    /// The templates loaded from `templates_dir`, to locate the errors.
+    files: HashMap<String, PathBuf>,
+    /// Lowercase.
+    default_language: String,
+}
+
+/// The variable that was missing when rendering, if that's the error.
+fn missing_variable(error: &tera::Error) -> Option<String> {
+    let mut source: Option<&dyn std::error::Error> = Some(error);
+    while let Some(e) = source {
+        if let Some(rest) = e.to_string().strip_prefix("Variable `") {
+            return rest.split('`').next().map(str::to_owned);
+        }
+        source = e.source();
+    }
+    None
+}
+
+fn example_context(email: &str) -> Context {
+    let mut context = Context::new();
+    context.insert("server_name", "example.com");
+    context.insert("server_url", "https://example.com/");
+    if email == PASSWORD_RESET {
+        context.insert("username", "John Doe");
+        context.insert(
+            "reset_url",
+            "https://example.com/reset-password/step2/token",
+        );
+        context.insert("expiry_minutes", &10);
+    } else if email == INVITATION {
+        context.insert("username", "John Doe");
+        context.insert(
+            "invitation_url",
+            "https://example.com/reset-password/step2/token",
+        );
+        context.insert("expiry_days", &7);
+    } else if email == EMAIL_CHANGE {
+        context.insert("username", "John Doe");
+        context.insert("new_email", "john.doe@example.org");
+        context.insert("verification_url", "https://example.com/verify-email/token");
+        context.insert("expiry_hours", &24);
+    } else if email == EMAIL_CHANGED {
+        context.insert("username", "John Doe");
+        context.insert("new_email", "john.doe@example.org");
+    } else if email == ACCOUNT_EXPIRING || email == ACCOUNT_EXPIRING_ADMIN {
+        context.insert("username", "John Doe");
+        context.insert("user_id", "john");
+        context.insert("expiry_date", "2024-06-30");
+        context.insert("days_left", &7);
+    }
+    context
+}
+
+impl MailTemplates {
+    /// Loads the built-in templates, overridden by the files in `templates_dir` if any. Every
+    /// template is rendered once with example values, to report the errors at startup.
+    pub fn new(
+        templates_dir: Option<&Path>,
+        logo_file: Option<&Path>,
+        default_language: &str,
+    ) -> Result<Self> {
+        check_language_tag(default_language).map_err(|e| {
+            anyhow!(
+                "Invalid smtp_options.default_language \"{}\": {}",
+                default_language,
+                e
+            )
+        })?;
+        let mut tera = Tera::default();
+        tera.add_raw_templates(BUILTIN_TEMPLATES.iter().copied())
+            .expect("invalid built-in email template");
+        let mut files = HashMap::new();
+        if let Some(templates_dir) = templates_dir {
+            let mut load = |dir: &Path, language: &str| -> Result<()> {
+                let emails = EMAILS.iter().flat_map(|email| {
+                    PARTS.map(|part| {
+                        (
+                            format!("{}.{}", email, part),
+                            template_name(language, email, part),
+                        )
+                    })
+                });
+                let layout =
+                    (language == FALLBACK_LANGUAGE).then(|| (LAYOUT.to_owned(), LAYOUT.to_owned()));
+                for (file, name) in emails.chain(layout) {
+                    let path = dir.join(&file);
+                    if path.exists() {
+                        tera.add_template_file(&path, Some(&name))
+                            .with_context(|| {
+                                format!("while loading the email template {}", path.display())
+                            })?;
+                        files.insert(name, path);
+                    }
+                }
+                Ok(())
+            };
+            load(templates_dir, FALLBACK_LANGUAGE)?;
+            let entries = std::fs::read_dir(templates_dir).with_context(|| {
+                format!(
+                    "while reading the email templates directory {}",
+                    templates_dir.display()
+                )
+            })?;
+            for entry in entries {
+                let path = entry?.path();
+                if !path.is_dir() {
+                    continue;
+                }
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                match check_language_tag(&name) {
+                    Ok(()) => load(&path, &name.to_ascii_lowercase())?,
+                    Err(e) => warn!("Ignoring the email templates in {}: {}", path.display(), e),
+                }
+            }
+        }
+        let logo = logo_file.map(Logo::load).transpose()?.map(Arc::new);
+        let templates = Self {
+            tera,
+            logo,
+            files,
+            default_language: default_language.to_ascii_lowercase(),
+        };
+        for language in &templates.languages() {
+            for email in EMAILS {
+                if !templates.has_template(&template_name(language, email, "txt")) {
+                    continue;
+                }
+                for part in PARTS {
+                    let name = template_name(language, email, part);
+                    if !templates.has_template(&name) {
+                        bail!(
+                            "The email template {} is missing: a language needs the subject, the text and the HTML templates of the emails it translates",
+                            templates_dir.unwrap_or(Path::new(".")).join(name).display()
+                        );
+                    }
+                }
+                templates
+                    .render_language(email, language, &example_context(email))
+                    .with_context(|| {
+                        format!(
+                            "while checking the {} email templates in \"{}\"",
+                            email, language
+                        )
+                    })?;
+            }
+        }
+        let primary_language = default_language.split('-').next().unwrap_or_default();
+        if !primary_language.eq_ignore_ascii_case(FALLBACK_LANGUAGE)
+            && templates.select_language(PASSWORD_RESET, None) == FALLBACK_LANGUAGE
+        {
+            warn!(
+                "There are no email templates for the default language \"{}\", the emails will be in English",
+                default_language
+            );
+        }
+        Ok(templates)
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        self.files.contains_key(name) || BUILTIN_TEMPLATES.iter().any(|(n, _)| *n == name)
+    }
+
+    /// The languages with templates, built-in or in `templates_dir`.
+    fn languages(&self) -> BTreeSet<String> {
+        BUILTIN_TEMPLATES
+            .iter()
+            .map(|(name, _)| *name)
+            .chain(self.files.keys().map(String::as_str))
+            .map(|name| match name.split_once('/') {
+                Some((language, _)) => language,
+                None => FALLBACK_LANGUAGE,
+            })
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// The language of the email for a user, see the module documentation.
+    pub fn select_language(&self, email: &str, user_language: Option<&str>) -> String {
+        language_candidates(user_language, &self.default_language)
+            .into_iter()
+            .find(|language| self.has_template(&template_name(language, email, "txt")))
+            .unwrap_or_else(|| FALLBACK_LANGUAGE.to_owned())
+    }
+
+    /// The file of the template, and the line of the missing variable if that's the error. Tera
+    /// already reports the line of the syntax errors.
+    fn locate_error(&self, name: &str, error: &tera::Error) -> String {
+        let path = match self.files.get(name) {
+            Some(path) => path,
+            None => return format!("{} (built-in)", name),
+        };
+        let line = missing_variable(error).and_then(|variable| {
+            std::fs::read_to_string(path)
+                .ok()?
+                .lines()
+                .position(|line| line.contains(&variable))
+        });
+        match line {
+            Some(line) => format!("{}, line {}", path.display(), line + 1),
+            None => path.display().to_string(),
+        }
+    }
+
+    /// Renders the email in the preferred language of the user, if there are templates for it.
+    pub fn render(
+        &self,
+        email: &str,
+        user_language: Option<&str>,
+        context: &Context,
+    ) -> Result<RenderedEmail> {
+        self.render_language(email, &self.select_language(email, user_language), context)
+    }
+
+    fn render_language(
+        &self,
+        email: &str,
+        language: &str,
+        context: &Context,
+    ) -> Result<RenderedEmail> {
+        let mut context = context.clone();
+        context.insert("logo", &self.logo.is_some());
+        context.insert("logo_cid", LOGO_CID);
+        context.insert("language", language);
+        let render = |part: &str| {
+            let name = template_name(language, email, part);
+            self.tera.render(&name, &context).map_err(|e| {
+                let location = self.locate_error(&name, &e);
+                anyhow::Error::new(e)
+                    .context(format!("while rendering the email template {}", location))
+            })
+        };
+        Ok(RenderedEmail {
+            // A subject is a single line.
+            subject: render("subject.txt")?.trim().replace(['\r', '\n'], " "),
+            text: render("txt")?,
+            html: render("html")?,
+            logo: self.logo.clone(),
+            language: language.to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("lldap-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_builtin_templates() {
+        let templates = MailTemplates::new(None, None, "en").unwrap();
+        let email = templates
+            .render(PASSWORD_RESET, None, &example_context(PASSWORD_RESET))
+            .unwrap();
+        assert_eq!(email.subject, "[LLDAP] Password reset requested");
+        assert!(
+            email.text.starts_with("Hello John Doe,\n"),
+            "{}",
+            email.text
+        );
+        assert!(email
+            .text
+            .contains("https://example.com/reset-password/step2/token"));
+        assert!(email.html.contains("<a href=\"https:&#x2F;&#x2F;example.com&#x2F;reset-password&#x2F;step2&#x2F;token\""), "{}", email.html);
+        assert!(!email.html.contains("cid:"), "{}", email.html);
+
+        let email = templates
+            .render(INVITATION, None, &example_context(INVITATION))
+            .unwrap();
+        assert_eq!(email.subject, "[LLDAP] Your account on example.com");
+        assert!(email.text.contains("valid for 7 days"), "{}", email.text);
+    }
+
+    #[test]
+    fn test_templates_dir() {
+        let dir = temp_dir();
+        std::fs::write(
+            dir.join("password_reset.subject.txt"),
+            "Passwort zurücksetzen für {{ server_name }}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("password_reset.html"),
+            "<p>Hallo {{ username }}, <a href=\"{{ reset_url }}\">hier</a></p>",
+        )
+        .unwrap();
+        let templates = MailTemplates::new(Some(&dir), None, "en").unwrap();
+        let mut context = example_context(PASSWORD_RESET);
+        context.insert("username", "<b>Jörg</b>");
+        let email = templates.render(PASSWORD_RESET, None, &context).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(email.subject, "Passwort zurücksetzen für example.com");
+        // The missing text template falls back to the built-in one.
+        assert!(email.text.starts_with("Hello <b>Jörg</b>,"));
+        // The HTML is escaped.
+        assert_eq!(
+            email.html,
+            "<p>Hallo &lt;b&gt;Jörg&lt;&#x2F;b&gt;, <a href=\"https:&#x2F;&#x2F;example.com&#x2F;reset-password&#x2F;step2&#x2F;token\">hier</a></p>"
+        );
+    }
+
+    #[test]
+    fn test_layout_and_logo() {
+        let dir = temp_dir();
+        std::fs::write(
+            dir.join("layout.html"),
+            "<div class=\"acme\">{% if logo %}<img src=\"cid:{{ logo_cid }}\">{% endif %}{% block content %}{% endblock content %}</div>",
+        )
+        .unwrap();
+        std::fs::write(dir.join("logo.png"), [0x89, b'P', b'N', b'G']).unwrap();
+        let templates = MailTemplates::new(Some(&dir), Some(&dir.join("logo.png")), "en").unwrap();
+        let email = templates
+            .render(TEST_EMAIL, None, &example_context(TEST_EMAIL))
+            .unwrap();
+        assert_eq!(
+            email.html.trim(),
+            "<div class=\"acme\"><img src=\"cid:logo@lldap\">\n<p>The test is successful! You can send emails from LLDAP.</p>\n</div>"
+        );
+        let logo = email.logo.unwrap();
+        assert_eq!(logo.content, vec![0x89, b'P', b'N', b'G']);
+        assert_eq!(logo.content_type, ContentType::parse("image/png").unwrap());
+
+        std::fs::write(dir.join("logo.bmp"), [0]).unwrap();
+        let error = format!(
+            "{:#}",
+            MailTemplates::new(None, Some(&dir.join("logo.bmp")), "en").unwrap_err()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(error.contains("Unsupported logo format"), "{}", error);
+    }
+
+    #[test]
+    fn test_errors_at_startup() {
+        let dir = temp_dir();
+        std::fs::write(dir.join("test_email.txt"), "Hello {{ name").unwrap();
+        let error = format!(
+            "{:#}",
+            MailTemplates::new(Some(&dir), None, "en").unwrap_err()
+        );
+        assert!(error.contains("test_email.txt"), "{}", error);
+
+        std::fs::write(dir.join("test_email.txt"), "Hello,\n{{ unknown_variable }}").unwrap();
+        let error = format!(
+            "{:#}",
+            MailTemplates::new(Some(&dir), None, "en").unwrap_err()
+        );
+        let path = dir.join("test_email.txt");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            error.contains(&format!("{}, line 2", path.display())),
+            "{}",
+            error
+        );
+        assert!(error.contains("unknown_variable"), "{}", error);
+    }
+
+    #[test]
+    fn test_language_candidates() {
+        assert_eq!(
+            language_candidates(Some("de-CH"), "fr"),
+            vec!["de-ch", "de", "fr", "en"]
+        );
+        assert_eq!(
+            language_candidates(Some("EN-gb"), "en"),
+            vec!["en-gb", "en"]
+        );
+        assert_eq!(
+            language_candidates(None, "zh-Hant-TW"),
+            vec!["zh-hant-tw", "zh-hant", "zh", "en"]
+        );
+    }
+
+    #[test]
+    fn test_select_language() {
+        let templates = MailTemplates::new(None, None, "de").unwrap();
+        assert_eq!(
+            templates.select_language(PASSWORD_RESET, Some("fr-CA")),
+            "fr"
+        );
+        assert_eq!(templates.select_language(PASSWORD_RESET, Some("en")), "en");
+        // Unsupported: the server default.
+        assert_eq!(templates.select_language(PASSWORD_RESET, Some("es")), "de");
+        assert_eq!(templates.select_language(PASSWORD_RESET, None), "de");
+        let templates = MailTemplates::new(None, None, "pt-BR").unwrap();
+        assert_eq!(templates.select_language(INVITATION, Some("es")), "en");
+
+        // The subject is localized as well.
+        let email = templates
+            .render(PASSWORD_RESET, Some("fr"), &example_context(PASSWORD_RESET))
+            .unwrap();
+        assert_eq!(email.language, "fr");
+        assert_eq!(
+            email.subject,
+            "[LLDAP] Demande de réinitialisation du mot de passe"
+        );
+        assert!(
+            email.text.starts_with("Bonjour John Doe,\n"),
+            "{}",
+            email.text
+        );
+        assert!(email.html.contains("<html lang=\"fr\">"), "{}", email.html);
+        let email = templates
+            .render(INVITATION, Some("de-AT"), &example_context(INVITATION))
+            .unwrap();
+        assert_eq!(email.subject, "[LLDAP] Ihr Konto auf example.com");
+        assert!(email.text.contains("7 Tage"), "{}", email.text);
+
+        let error = MailTemplates::new(None, None, "french").unwrap_err();
+        assert!(error.to_string().contains("default_language"), "{}", error);
+    }
+
+    #[test]
+    fn test_language_directories() {
+        let dir = temp_dir();
+        std::fs::create_dir(dir.join("es")).unwrap();
+        std::fs::write(
+            dir.join("es/password_reset.subject.txt"),
+            "Restablecer la contraseña",
+        )
+        .unwrap();
+        std::fs::write(dir.join("es/password_reset.txt"), "Hola {{ username }}").unwrap();
+        std::fs::write(
+            dir.join("es/password_reset.html"),
+            "<p lang=\"{{ language }}\">Hola {{ username }}</p>",
+        )
+        .unwrap();
+        // Overrides a built-in language, the other templates are kept.
+        std::fs::create_dir(dir.join("FR")).unwrap();
+        std::fs::write(dir.join("FR/password_reset.txt"), "Salut {{ username }}").unwrap();
+        // Not a language.
+        std::fs::create_dir(dir.join("images")).unwrap();
+        std::fs::write(dir.join("images/password_reset.txt"), "{{ oops").unwrap();
+        let templates = MailTemplates::new(Some(&dir), None, "en").unwrap();
+        let email = templates
+            .render(
+                PASSWORD_RESET,
+                Some("es-MX"),
+                &example_context(PASSWORD_RESET),
+            )
+            .unwrap();
+        assert_eq!(email.subject, "Restablecer la contraseña");
+        assert_eq!(email.text, "Hola John Doe");
+        assert_eq!(email.html, "<p lang=\"es\">Hola John Doe</p>");
+        // No Spanish invitation.
+        assert_eq!(templates.select_language(INVITATION, Some("es")), "en");
+        let email = templates
+            .render(PASSWORD_RESET, Some("fr"), &example_context(PASSWORD_RESET))
+            .unwrap();
+        assert_eq!(email.text, "Salut John Doe");
+        assert_eq!(
+            email.subject,
+            "[LLDAP] Demande de réinitialisation du mot de passe"
+        );
+
+        // A language without a built-in version needs all the templates of the email.
+        std::fs::create_dir(dir.join("it")).unwrap();
+        std::fs::write(dir.join("it/test_email.txt"), "Ciao").unwrap();
+        let error = format!(
+            "{:#}",
+            MailTemplates::new(Some(&dir), None, "en").unwrap_err()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            error.contains("test_email.subject.txt is missing"),
+            "{}",
+            error
+        );
+    }
+}
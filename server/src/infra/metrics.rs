@@ -0,0 +1,497 @@
+//! The `/metrics` endpoint, in the Prometheus text format. Like the health endpoints, it is not
+//! behind the path prefix: it is meant for the monitoring, not for the users.
+
+use crate::{
+    domain::{
+        query_metrics::{Histogram, QueryMetrics},
+        read_cache::ReadCache,
+        sql_backend_handler::SqlBackendHandler,
+        sql_tables::DbConnection,
+    },
+    infra::{
+        db_cleaner::PruneStats,
+        db_connection::{pool_status, time_acquire},
+        replication::ReplicationStatus,
+    },
+};
+use actix_web::{web, HttpResponse};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+/// How often the connection pools and the runtime are sampled, see `Metrics::sample`.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Default)]
+pub struct Metrics {
+    read_cache: Option<Arc<ReadCache>>,
+    query_metrics: Option<Arc<QueryMetrics>>,
+    /// By role, see `SqlBackendHandler::database_pools`.
+    pools: Vec<(&'static str, DbConnection)>,
+    replication: Option<ReplicationStatus>,
+    prune_stats: Option<PruneStats>,
+    samples: Arc<Mutex<Samples>>,
+}
+
+#[derive(Default)]
+struct Samples {
+    /// By pool role.
+    acquire_wait: BTreeMap<&'static str, Histogram>,
+    acquire_failures: BTreeMap<&'static str, u64>,
+    /// Of the runtime running the sampling, see `Metrics::start_sampling`.
+    scheduler_delay: Histogram,
+}
+
+impl Metrics {
+    pub fn new(read_cache: Option<Arc<ReadCache>>) -> Self {
+        Self {
+            read_cache,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the query latencies of the handler, and the state of its connection pools.
+    pub fn with_backend_handler(mut self, handler: &SqlBackendHandler) -> Self {
+        self.query_metrics = Some(handler.query_metrics());
+        self.pools = handler.database_pools();
+        self
+    }
+
+    /// Adds the state of the replication, for a replica.
+    pub fn with_replication(mut self, replication: Option<ReplicationStatus>) -> Self {
+        self.replication = replication;
+        self
+    }
+
+    /// Adds the rows deleted by the retention scheduler.
+    pub fn with_prune_stats(mut self, prune_stats: PruneStats) -> Self {
+        self.prune_stats = Some(prune_stats);
+        self
+    }
+
+    /// Times the acquisition of a connection from each pool, and how long a task waits to be
+    /// scheduled. Both are sampled rather than measured for every query: sea-orm doesn't tell how
+    /// long a query waited for its connection.
+    pub async fn sample(&self) {
+        let mut waits = Vec::with_capacity(self.pools.len());
+        for (role, pool) in &self.pools {
+            if let Some(wait) = time_acquire(pool).await {
+                waits.push((*role, wait));
+            }
+        }
+        let start = Instant::now();
+        tokio::task::yield_now().await;
+        let scheduler_delay = start.elapsed();
+
+        {
+            let mut samples = self.samples.lock().unwrap();
+            for (role, wait) in &waits {
+                let wait = match wait {
+                    Ok(wait) => wait,
+                    Err(wait) => {
+                        *samples.acquire_failures.entry(*role).or_default() += 1;
+                        wait
+                    }
+                };
+                samples
+                    .acquire_wait
+                    .entry(*role)
+                    .or_default()
+                    .observe(*wait);
+            }
+            samples.scheduler_delay.observe(scheduler_delay);
+        }
+
+        for (role, pool) in &self.pools {
+            if let Some(status) = pool_status(pool) {
+                debug!(
+                    "Database pool {}: {} connections, {} idle",
+                    role, status.size, status.idle
+                );
+            }
+        }
+        debug!(
+            "Connection acquisition: {:?}, scheduler delay: {:?}, queries in flight: {}",
+            waits,
+            scheduler_delay,
+            self.query_metrics.as_ref().map_or(0, |q| q.in_flight())
+        );
+    }
+
+    /// Samples every `interval`, on the current runtime.
+    pub fn start_sampling(self, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                self.sample().await;
+            }
+        });
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        if let Some(cache) = &self.read_cache {
+            let stats = cache.stats();
+            write_metric(
+                &mut out,
+                "lldap_cache_hits_total",
+                "counter",
+                "Reads answered by the read cache.",
+                stats.hits,
+            );
+            write_metric(
+                &mut out,
+                "lldap_cache_misses_total",
+                "counter",
+                "Reads sent to the database while the read cache is enabled.",
+                stats.misses,
+            );
+            write_metric(
+                &mut out,
+                "lldap_cache_entries",
+                "gauge",
+                "Entries in the read cache.",
+                stats.entries,
+            );
+        }
+        let statuses = self
+            .pools
+            .iter()
+            .filter_map(|(role, pool)| Some((*role, pool_status(pool)?)))
+            .collect::<Vec<_>>();
+        if !statuses.is_empty() {
+            write_header(
+                &mut out,
+                "lldap_db_pool_connections",
+                "gauge",
+                "Open database connections, idle or in use.",
+            );
+            for (role, status) in &statuses {
+                writeln!(
+                    out,
+                    "lldap_db_pool_connections{{pool=\"{}\"}} {}",
+                    role, status.size
+                )
+                .unwrap();
+            }
+            write_header(
+                &mut out,
+                "lldap_db_pool_idle_connections",
+                "gauge",
+                "Idle database connections.",
+            );
+            for (role, status) in &statuses {
+                writeln!(
+                    out,
+                    "lldap_db_pool_idle_connections{{pool=\"{}\"}} {}",
+                    role, status.idle
+                )
+                .unwrap();
+            }
+        }
+        let samples = self.samples.lock().unwrap();
+        if !samples.acquire_wait.is_empty() {
+            write_header(
+                &mut out,
+                "lldap_db_acquire_wait_seconds",
+                "histogram",
+                "Time to acquire a database connection, sampled periodically.",
+            );
+            for (role, histogram) in &samples.acquire_wait {
+                write_histogram(
+                    &mut out,
+                    "lldap_db_acquire_wait_seconds",
+                    "pool",
+                    role,
+                    histogram,
+                );
+            }
+            write_header(
+                &mut out,
+                "lldap_db_acquire_failures_total",
+                "counter",
+                "Sampled database connection acquisitions that failed, e.g. timed out.",
+            );
+            for role in samples.acquire_wait.keys() {
+                writeln!(
+                    out,
+                    "lldap_db_acquire_failures_total{{pool=\"{}\"}} {}",
+                    role,
+                    samples.acquire_failures.get(role).unwrap_or(&0)
+                )
+                .unwrap();
+            }
+        }
+        if samples.scheduler_delay.count > 0 {
+            write_header(
+                &mut out,
+                "lldap_scheduler_delay_seconds",
+                "histogram",
+                "Time for a task to be scheduled again after yielding, sampled periodically.",
+            );
+            write_histogram_lines(
+                &mut out,
+                "lldap_scheduler_delay_seconds",
+                "",
+                &samples.scheduler_delay,
+            );
+        }
+        drop(samples);
+        if let Some(replication) = &self.replication {
+            let state = replication.get();
+            write_metric(
+                &mut out,
+                "lldap_replication_last_sync_timestamp_seconds",
+                "gauge",
+                "When the replica was last known to be up to date with the primary, 0 if never.",
+                state.last_sync_at.map_or(0, |time| time.timestamp()),
+            );
+            if let Some(last_sync_at) = state.last_sync_at {
+                write_metric(
+                    &mut out,
+                    "lldap_replication_lag_seconds",
+                    "gauge",
+                    "Time since the replica was last known to be up to date with the primary.",
+                    (chrono::Utc::now() - last_sync_at).num_seconds().max(0),
+                );
+            }
+            write_metric(
+                &mut out,
+                "lldap_replication_failures_total",
+                "counter",
+                "Failed synchronizations with the primary.",
+                state.failures,
+            );
+        }
+        if let Some(prune_stats) = &self.prune_stats {
+            write_header(
+                &mut out,
+                "lldap_pruned_rows_total",
+                "counter",
+                "Expired or deleted rows removed by the retention scheduler, by table.",
+            );
+            for (target, rows) in prune_stats.get() {
+                writeln!(
+                    out,
+                    "lldap_pruned_rows_total{{table=\"{}\"}} {}",
+                    target.table(),
+                    rows
+                )
+                .unwrap();
+            }
+        }
+        if let Some(queries) = &self.query_metrics {
+            write_metric(
+                &mut out,
+                "lldap_queries_in_flight",
+                "gauge",
+                "Backend queries in progress.",
+                queries.in_flight(),
+            );
+            let durations = queries.durations();
+            if !durations.is_empty() {
+                write_header(
+                    &mut out,
+                    "lldap_query_duration_seconds",
+                    "histogram",
+                    "Duration of the backend queries, cache misses only, by handler method.",
+                );
+                for (query_class, histogram) in &durations {
+                    write_histogram(
+                        &mut out,
+                        "lldap_query_duration_seconds",
+                        "query",
+                        query_class,
+                        histogram,
+                    );
+                }
+            }
+        }
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl std::fmt::Display,
+) {
+    write_header(out, name, kind, help);
+    writeln!(out, "{} {}", name, value).unwrap();
+}
+
+fn write_histogram(
+    out: &mut String,
+    name: &str,
+    label: &str,
+    label_value: &str,
+    histogram: &Histogram,
+) {
+    write_histogram_lines(
+        out,
+        name,
+        &format!("{}=\"{}\",", label, label_value),
+        histogram,
+    );
+}
+
+/// `labels` is empty, or the labels other than `le`, each followed by a comma.
+fn write_histogram_lines(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    for (bound, count) in histogram.cumulative_buckets() {
+        let bound = bound.map_or_else(|| "+Inf".to_owned(), |b| b.to_string());
+        writeln!(
+            out,
+            "{}_bucket{{{}le=\"{}\"}} {}",
+            name, labels, bound, count
+        )
+        .unwrap();
+    }
+    let labels = labels.trim_end_matches(',');
+    let labels = if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    };
+    writeln!(out, "{}_sum{} {}", name, labels, histogram.sum_secs).unwrap();
+    writeln!(out, "{}_count{} {}", name, labels, histogram.count).unwrap();
+}
+
+async fn metrics(state: web::Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(state.render())
+}
+
+pub(crate) fn configure(cfg: &mut web::ServiceConfig, state: web::Data<Metrics>) {
+    cfg.app_data(state)
+        .route("/metrics", web::get().to(metrics));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::{
+            handler::UserListerBackendHandler,
+            read_cache::CacheKey,
+            sql_backend_handler::tests::{get_default_config, get_initialized_db},
+            types::UserId,
+        },
+        infra::{configuration::CacheOptions, db_cleaner::PruneTarget},
+    };
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_render_without_cache() {
+        assert_eq!(Metrics::default().render(), "");
+    }
+
+    #[test]
+    fn test_render_cache() {
+        let cache = Arc::new(ReadCache::new(&CacheOptions {
+            enabled: true,
+            ttl_secs: 60,
+            max_entries: 10,
+        }));
+        let key = CacheKey::UserDetails(UserId::new("bob"));
+        assert_eq!(cache.get::<u32>(&key), None);
+        cache.insert(key.clone(), 1u32, cache.generation());
+        assert_eq!(cache.get::<u32>(&key), Some(1));
+        assert_eq!(
+            Metrics::new(Some(cache)).render(),
+            "# HELP lldap_cache_hits_total Reads answered by the read cache.
+# TYPE lldap_cache_hits_total counter
+lldap_cache_hits_total 1
+# HELP lldap_cache_misses_total Reads sent to the database while the read cache is enabled.
+# TYPE lldap_cache_misses_total counter
+lldap_cache_misses_total 1
+# HELP lldap_cache_entries Entries in the read cache.
+# TYPE lldap_cache_entries gauge
+lldap_cache_entries 1
+"
+        );
+    }
+
+    #[test]
+    fn test_render_replication() {
+        let status = ReplicationStatus::new(&"https://primary".parse().unwrap());
+        status.record_failure("Connection refused".to_owned());
+        let metrics = Metrics::default().with_replication(Some(status.clone()));
+        assert_eq!(
+            metrics.render(),
+            "# HELP lldap_replication_last_sync_timestamp_seconds When the replica was last known to be up to date with the primary, 0 if never.
+# TYPE lldap_replication_last_sync_timestamp_seconds gauge
+lldap_replication_last_sync_timestamp_seconds 0
+# HELP lldap_replication_failures_total Failed synchronizations with the primary.
+# TYPE lldap_replication_failures_total counter
+lldap_replication_failures_total 1
+"
+        );
+        let last_sync_at = chrono::Utc::now() - chrono::Duration::seconds(30);
+        status.record_sync(last_sync_at);
+        let rendered = metrics.render();
+        assert!(rendered.contains(&format!(
+            "\nlldap_replication_last_sync_timestamp_seconds {}\n",
+            last_sync_at.timestamp()
+        )));
+        assert!(
+            rendered.contains("\nlldap_replication_lag_seconds 3"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn test_render_prune_stats() {
+        let stats = PruneStats::default();
+        stats.record(PruneTarget::Sessions, 12);
+        assert_eq!(
+            Metrics::default().with_prune_stats(stats).render(),
+            "# HELP lldap_pruned_rows_total Expired or deleted rows removed by the retention scheduler, by table.
+# TYPE lldap_pruned_rows_total counter
+lldap_pruned_rows_total{table=\"jwt_refresh_storage\"} 12
+lldap_pruned_rows_total{table=\"jwt_storage\"} 0
+lldap_pruned_rows_total{table=\"password_reset_tokens\"} 0
+lldap_pruned_rows_total{table=\"users\"} 0
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_render_database() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        handler.list_users(None, false).await.unwrap();
+        let metrics = Metrics::default().with_backend_handler(&handler);
+        metrics.sample().await;
+        let rendered = metrics.render();
+        for expected in [
+            "lldap_db_pool_connections{pool=\"primary\"} 1",
+            "lldap_db_pool_idle_connections{pool=\"primary\"} ",
+            "lldap_db_acquire_wait_seconds_count{pool=\"primary\"} 1",
+            "lldap_db_acquire_failures_total{pool=\"primary\"} 0",
+            "lldap_scheduler_delay_seconds_bucket{le=\"+Inf\"} 1",
+            "lldap_queries_in_flight 0",
+            "lldap_query_duration_seconds_bucket{query=\"list_users\",le=\"+Inf\"} 1",
+            "lldap_query_duration_seconds_count{query=\"list_users\"} 1",
+        ] {
+            assert!(
+                rendered.lines().any(|line| line.starts_with(expected)),
+                "{} not found in:\n{}",
+                expected,
+                rendered
+            );
+        }
+    }
+}
@@ -1,19 +1,51 @@
 pub mod access_control;
+pub mod account_expiration;
 pub mod auth_service;
+pub mod avatar_source;
+pub mod avatars;
+pub mod backup;
+pub mod bootstrap;
+pub mod build_info;
+pub mod change_plan;
 pub mod cli;
+pub mod client_ip;
+pub mod config_template;
 pub mod configuration;
+pub mod cors;
+pub mod csv_import;
 pub mod database_string;
+pub mod db_check;
 pub mod db_cleaner;
+pub mod db_connection;
+pub mod email_change;
 pub mod graphql;
+pub mod group_members;
+pub mod health;
 pub mod healthcheck;
+pub mod invitation;
+pub mod jwt_rotation;
 pub mod jwt_sql_tables;
 pub mod ldap_handler;
 pub mod ldap_server;
+pub mod ldif;
+pub mod ldif_import;
 pub mod logging;
 pub mod mail;
+pub mod mail_queue;
+pub mod mail_templates;
+pub mod metrics;
+pub mod provisioning;
+pub mod replication;
+pub mod request_id;
+pub mod reset_admin_password;
+pub mod secret_input;
+pub mod security_status;
+pub mod shutdown;
 pub mod sql_backend_handler;
+pub mod systemd;
 pub mod tcp_backend_handler;
 pub mod tcp_server;
+pub mod tls;
 
 #[cfg(test)]
 pub mod test_utils;
@@ -0,0 +1,405 @@
+//! Creating the users and the groups from the command line (`lldap create_user`,
+//! `lldap create_group`), e.g. from Ansible: directly in the database while the server is stopped,
+//! or through the HTTP API of a running server.
+//!
+//! When the user or the group already exists, nothing is changed and the command exits with
+//! `ALREADY_EXISTS_EXIT_CODE`, to make the scripts idempotent.
+
+use crate::domain::{
+    handler::{
+        CreateGroupRequest, CreateUserRequest, GroupListerBackendHandler, GroupRequestFilter,
+        UserBackendHandler,
+    },
+    sql_backend_handler::SqlBackendHandler,
+    sql_opaque_handler::register_password,
+    types::UserId,
+};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use async_trait::async_trait;
+use lldap_auth::{login, opaque, registration};
+use secstr::SecUtf8;
+use serde_json::json;
+
+/// The exit code when the user or the group already exists: 1 is any other error, 2 a usage error.
+pub const ALREADY_EXISTS_EXIT_CODE: i32 = 3;
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0} already exists")]
+pub struct AlreadyExists(pub String);
+
+pub struct NewUser {
+    pub user_id: UserId,
+    pub email: String,
+    pub display_name: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub password: Option<SecUtf8>,
+    /// The names of the groups, they must exist.
+    pub groups: Vec<String>,
+}
+
+#[async_trait]
+pub trait Provisioner: Send + Sync {
+    async fn create_user(&self, user: NewUser) -> Result<()>;
+    async fn create_group(&self, name: &str) -> Result<()>;
+}
+
+pub fn check_new_password(password: &SecUtf8) -> Result<()> {
+    let length = password.unsecure().chars().count();
+    ensure!(
+        length >= 8,
+        "Minimum password length is 8 characters, got {} characters",
+        length
+    );
+    Ok(())
+}
+
+/// Modifies the database directly: the server should be stopped.
+pub struct DatabaseProvisioner {
+    handler: SqlBackendHandler,
+}
+
+impl DatabaseProvisioner {
+    pub fn new(handler: SqlBackendHandler) -> Self {
+        Self { handler }
+    }
+}
+
+#[async_trait]
+impl Provisioner for DatabaseProvisioner {
+    async fn create_user(&self, user: NewUser) -> Result<()> {
+        if self.handler.get_user_details(&user.user_id).await.is_ok() {
+            return Err(AlreadyExists(format!("The user {}", user.user_id)).into());
+        }
+        let mut groups = Vec::new();
+        for name in &user.groups {
+            let group = self
+                .handler
+                .list_groups(Some(GroupRequestFilter::DisplayName(name.as_str().into())))
+                .await?;
+            groups.push(
+                group
+                    .first()
+                    .ok_or_else(|| anyhow!("The group {} doesn't exist", name))?
+                    .id,
+            );
+        }
+        self.handler
+            .create_user(CreateUserRequest {
+                user_id: user.user_id.clone(),
+                email: user.email.into(),
+                display_name: user.display_name,
+                first_name: user.first_name,
+                last_name: user.last_name,
+                groups,
+                ..Default::default()
+            })
+            .await?;
+        if let Some(password) = &user.password {
+            register_password(&self.handler, user.user_id.clone(), password)
+                .await
+                .context(format!(
+                    "The user {} was created, but not their password",
+                    user.user_id
+                ))?;
+        }
+        Ok(())
+    }
+
+    async fn create_group(&self, name: &str) -> Result<()> {
+        if !self
+            .handler
+            .list_groups(Some(GroupRequestFilter::DisplayName(name.into())))
+            .await?
+            .is_empty()
+        {
+            return Err(AlreadyExists(format!("The group {}", name)).into());
+        }
+        self.handler
+            .create_group(CreateGroupRequest {
+                display_name: name.into(),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+/// Goes through the GraphQL API of a running server, logged in as an admin.
+pub struct HttpProvisioner {
+    client: reqwest::Client,
+    base_url: url::Url,
+    token: String,
+}
+
+fn append_to_url(base_url: &url::Url, path: &str) -> url::Url {
+    let mut url = base_url.clone();
+    url.path_segments_mut()
+        .unwrap()
+        .pop_if_empty()
+        .extend(path.split('/'));
+    url
+}
+
+impl HttpProvisioner {
+    pub async fn login(base_url: &url::Url, username: &str, password: &SecUtf8) -> Result<Self> {
+        ensure!(
+            base_url.scheme() == "http" || base_url.scheme() == "https",
+            "The URL should start with `http://` or `https://`"
+        );
+        let client = reqwest::Client::new();
+        let response = client
+            .post(append_to_url(base_url, "auth/simple/login"))
+            .json(&login::ClientSimpleLoginRequest {
+                username: username.into(),
+                password: password.unsecure().to_owned(),
+                remember_me: None,
+            })
+            .send()
+            .await
+            .context(format!("Could not connect to {}", base_url))?;
+        ensure!(
+            response.status().is_success(),
+            "Could not log in as {}: {}",
+            username,
+            response.status()
+        );
+        let token = response
+            .json::<login::ServerLoginResponse>()
+            .await
+            .context("Invalid login response")?
+            .token;
+        Ok(Self {
+            client,
+            base_url: base_url.clone(),
+            token,
+        })
+    }
+
+    async fn post(&self, path: &str, body: &impl serde::Serialize) -> Result<reqwest::Response> {
+        let response = self
+            .client
+            .post(append_to_url(&self.base_url, path))
+            .bearer_auth(&self.token)
+            .json(body)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            bail!("{}: {}", status, response.text().await.unwrap_or_default());
+        }
+        Ok(response)
+    }
+
+    async fn graphql(
+        &self,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let mut response: serde_json::Value = self
+            .post(
+                "api/graphql",
+                &json!({"query": query, "variables": variables}),
+            )
+            .await?
+            .json()
+            .await?;
+        if let Some(errors) = response.get("errors").and_then(|e| e.as_array()) {
+            bail!(
+                "{}",
+                errors
+                    .iter()
+                    .filter_map(|e| e.get("message").and_then(|m| m.as_str()))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
+        }
+        Ok(response["data"].take())
+    }
+
+    async fn list_groups(&self) -> Result<Vec<(i64, String)>> {
+        let data = self
+            .graphql("{ groups { id displayName } }", json!({}))
+            .await?;
+        Ok(data["groups"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|g| Some((g["id"].as_i64()?, g["displayName"].as_str()?.to_owned())))
+            .collect())
+    }
+
+    async fn set_password(&self, user_id: &UserId, password: &SecUtf8) -> Result<()> {
+        let mut rng = rand::rngs::OsRng;
+        let registration_start = opaque::client::registration::start_registration(
+            password.unsecure().as_bytes(),
+            &mut rng,
+        )?;
+        let start_response: registration::ServerRegistrationStartResponse = self
+            .post(
+                "auth/opaque/register/start",
+                &registration::ClientRegistrationStartRequest {
+                    username: user_id.clone(),
+                    registration_start_request: registration_start.message,
+                },
+            )
+            .await?
+            .json()
+            .await?;
+        let registration_finish = opaque::client::registration::finish_registration(
+            registration_start.state,
+            start_response.registration_response,
+            &mut rng,
+        )?;
+        self.post(
+            "auth/opaque/register/finish",
+            &registration::ClientRegistrationFinishRequest {
+                server_data: start_response.server_data,
+                registration_upload: registration_finish.message,
+            },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Provisioner for HttpProvisioner {
+    async fn create_user(&self, user: NewUser) -> Result<()> {
+        let existing = self
+            .graphql(
+                "query($id: String!) { users(filters: {eq: {field: \"user_id\", value: $id}}) { id } }",
+                json!({"id": user.user_id.as_str()}),
+            )
+            .await?;
+        if existing["users"].as_array().is_some_and(|u| !u.is_empty()) {
+            return Err(AlreadyExists(format!("The user {}", user.user_id)).into());
+        }
+        let all_groups = self.list_groups().await?;
+        let groups = user
+            .groups
+            .iter()
+            .map(|name| {
+                all_groups
+                    .iter()
+                    .find(|(_, n)| n.eq_ignore_ascii_case(name))
+                    .map(|(id, _)| *id)
+                    .ok_or_else(|| anyhow!("The group {} doesn't exist", name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.graphql(
+            "mutation($user: CreateUserInput!) { createUser(user: $user) { id } }",
+            json!({"user": {
+                "id": user.user_id.as_str(),
+                "email": user.email,
+                "displayName": user.display_name,
+                "firstName": user.first_name,
+                "lastName": user.last_name,
+                "groups": groups,
+            }}),
+        )
+        .await?;
+        if let Some(password) = &user.password {
+            self.set_password(&user.user_id, password)
+                .await
+                .context(format!(
+                    "The user {} was created, but not their password",
+                    user.user_id
+                ))?;
+        }
+        Ok(())
+    }
+
+    async fn create_group(&self, name: &str) -> Result<()> {
+        if self
+            .list_groups()
+            .await?
+            .iter()
+            .any(|(_, n)| n.eq_ignore_ascii_case(name))
+        {
+            return Err(AlreadyExists(format!("The group {}", name)).into());
+        }
+        self.graphql(
+            "mutation($name: String!) { createGroup(name: $name) { id } }",
+            json!({ "name": name }),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        handler::{BindRequest, LoginHandler, UserRequestFilter},
+        sql_backend_handler::tests::*,
+    };
+    use pretty_assertions::assert_eq;
+
+    fn new_user(user_id: &str, groups: &[&str]) -> NewUser {
+        NewUser {
+            user_id: UserId::new(user_id),
+            email: format!("{}@example.com", user_id),
+            display_name: Some("Bob".to_owned()),
+            first_name: None,
+            last_name: None,
+            password: Some(SecUtf8::from("correct horse")),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_group_and_user() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        let provisioner = DatabaseProvisioner::new(handler.clone());
+        provisioner.create_group("staff").await.unwrap();
+        let error = provisioner.create_group("Staff").await.unwrap_err();
+        assert!(error.downcast_ref::<AlreadyExists>().is_some(), "{}", error);
+
+        provisioner
+            .create_user(new_user("bob", &["staff"]))
+            .await
+            .unwrap();
+        handler
+            .bind(BindRequest {
+                name: UserId::new("bob"),
+                password: "correct horse".to_owned(),
+            })
+            .await
+            .unwrap();
+        let members = handler
+            .list_users(Some(UserRequestFilter::MemberOf("staff".into())), false)
+            .await
+            .unwrap();
+        assert_eq!(
+            members
+                .iter()
+                .map(|u| u.user.user_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["bob"]
+        );
+
+        let error = provisioner
+            .create_user(new_user("bob", &[]))
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<AlreadyExists>().is_some(), "{}", error);
+        // A missing group is a real error, and the user is not created.
+        let error = provisioner
+            .create_user(new_user("carol", &["vpn"]))
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<AlreadyExists>().is_none(), "{}", error);
+        handler
+            .get_user_details(&UserId::new("carol"))
+            .await
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_check_new_password() {
+        check_new_password(&SecUtf8::from("short")).unwrap_err();
+        check_new_password(&SecUtf8::from("long enough")).unwrap();
+    }
+}
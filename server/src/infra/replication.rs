@@ -0,0 +1,550 @@
+//! Read-only replicas, see `ReplicationOptions`: a replica regularly copies the directory of its
+//! primary, and refuses every other change.
+//!
+//! The primary serves a snapshot of the directory (the data of `lldap export`, without the server
+//! key) to the holders of the replication token. Its ETag is a hash of the contents: the replica
+//! only downloads and applies the snapshot when it changed, in a single transaction (see
+//! `backup::mirror`). The password records are copied as they are, so the replica checks the
+//! passwords on its own, with the same server key, even when the primary is unreachable.
+
+use crate::{
+    domain::{
+        read_model::Change, sql_backend_handler::SqlBackendHandler, sql_tables::DbConnection,
+    },
+    infra::{
+        backup::{self, BackupData},
+        configuration::Configuration,
+    },
+};
+use actix_web::{
+    http::header::{AUTHORIZATION, ETAG, IF_NONE_MATCH},
+    web, HttpRequest, HttpResponse,
+};
+use anyhow::{bail, ensure, Context, Result};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use secstr::SecUtf8;
+use serde::Serialize;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tracing::{error, info, instrument, warn};
+use url::Url;
+
+/// Under `/api`.
+pub const SNAPSHOT_PATH: &str = "/replication/snapshot";
+
+/// The primary side: serves the snapshots.
+pub struct SnapshotSource {
+    sql_pool: DbConnection,
+    token: SecUtf8,
+}
+
+impl SnapshotSource {
+    pub fn new(sql_pool: DbConnection, token: SecUtf8) -> Self {
+        Self { sql_pool, token }
+    }
+}
+
+fn is_authorized(request: &HttpRequest, token: &SecUtf8) -> bool {
+    request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| {
+            orion::util::secure_cmp(given.as_bytes(), token.unsecure().as_bytes()).is_ok()
+        })
+}
+
+fn etag(body: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    format!(
+        "\"{}\"",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(body))
+    )
+}
+
+#[instrument(skip_all, level = "debug")]
+async fn snapshot_handler(request: HttpRequest, source: web::Data<SnapshotSource>) -> HttpResponse {
+    if !is_authorized(&request, &source.token) {
+        warn!("Replication request with an invalid token");
+        return HttpResponse::Unauthorized().body("Invalid replication token");
+    }
+    let body = match backup::dump(&source.sql_pool, false)
+        .await
+        .and_then(|data| Ok(serde_json::to_vec(&data)?))
+    {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Could not create the replication snapshot: {:#}", e);
+            return HttpResponse::InternalServerError().body("Could not create the snapshot");
+        }
+    };
+    let etag = etag(&body);
+    if request
+        .headers()
+        .get(IF_NONE_MATCH)
+        .is_some_and(|value| value.as_bytes() == etag.as_bytes())
+    {
+        return HttpResponse::NotModified()
+            .insert_header((ETAG, etag))
+            .finish();
+    }
+    HttpResponse::Ok()
+        .insert_header((ETAG, etag))
+        .content_type("application/json")
+        .body(body)
+}
+
+pub(crate) fn configure(cfg: &mut web::ServiceConfig, source: web::Data<SnapshotSource>) {
+    cfg.app_data(source)
+        .route(SNAPSHOT_PATH, web::get().to(snapshot_handler));
+}
+
+/// Where a replica stands, for `/ready` and `/metrics`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplicationState {
+    pub primary_url: String,
+    /// The last time the replica was known to be up to date: a snapshot was applied, or the
+    /// primary answered that nothing changed.
+    pub last_sync_at: Option<DateTime<Utc>>,
+    /// The error of the last attempt, if it failed.
+    pub last_error: Option<String>,
+    pub failures: u64,
+}
+
+/// Shared between the replication task and the endpoints. Starts without any synchronization.
+#[derive(Clone, Debug)]
+pub struct ReplicationStatus(Arc<RwLock<ReplicationState>>);
+
+impl ReplicationStatus {
+    pub fn new(primary_url: &Url) -> Self {
+        Self(Arc::new(RwLock::new(ReplicationState {
+            primary_url: primary_url.to_string(),
+            last_sync_at: None,
+            last_error: None,
+            failures: 0,
+        })))
+    }
+
+    pub fn record_sync(&self, time: DateTime<Utc>) {
+        let mut state = self.0.write().unwrap();
+        state.last_sync_at = Some(time);
+        state.last_error = None;
+    }
+
+    pub fn record_failure(&self, error: String) {
+        let mut state = self.0.write().unwrap();
+        state.last_error = Some(error);
+        state.failures += 1;
+    }
+
+    pub fn get(&self) -> ReplicationState {
+        self.0.read().unwrap().clone()
+    }
+
+    /// For the readiness probe, as of `now`.
+    pub fn check(&self, now: DateTime<Utc>) -> ReplicationCheck {
+        let state = self.get();
+        ReplicationCheck {
+            lag_secs: state
+                .last_sync_at
+                .map(|time| (now - time).num_seconds().max(0)),
+            primary_url: state.primary_url,
+            last_sync_at: state.last_sync_at,
+            error: state.last_error,
+        }
+    }
+}
+
+/// The replication part of `/ready`.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReplicationCheck {
+    pub primary_url: String,
+    pub last_sync_at: Option<DateTime<Utc>>,
+    /// Seconds since `last_sync_at`: the changes made on the primary since then may be missing.
+    pub lag_secs: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The replica side: copies the snapshots of the primary into the database.
+pub struct Replicator {
+    handler: SqlBackendHandler,
+    client: reqwest::Client,
+    snapshot_url: Url,
+    token: SecUtf8,
+    /// Of the last applied snapshot.
+    etag: Option<String>,
+    status: ReplicationStatus,
+}
+
+impl Replicator {
+    pub fn new(
+        config: &Configuration,
+        handler: SqlBackendHandler,
+        status: ReplicationStatus,
+    ) -> Result<Self> {
+        let options = &config.replication;
+        let primary_url = options
+            .primary_url
+            .as_ref()
+            .context("Not a replica: replication.primary_url is not set")?;
+        let mut snapshot_url = primary_url.clone();
+        snapshot_url
+            .path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid primary_url \"{}\"", primary_url))?
+            .pop_if_empty()
+            .push("api")
+            .extend(SNAPSHOT_PATH.trim_start_matches('/').split('/'));
+        Ok(Self {
+            handler,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(60))
+                .build()?,
+            snapshot_url,
+            token: options
+                .token
+                .clone()
+                .context("A replica needs the token of the primary")?,
+            etag: None,
+            status,
+        })
+    }
+
+    /// Fetches the snapshot of the primary, and applies it if it changed. Returns whether it did.
+    #[instrument(skip_all, level = "debug", err)]
+    pub async fn sync(&mut self) -> Result<bool> {
+        let mut request = self
+            .client
+            .get(self.snapshot_url.clone())
+            .bearer_auth(self.token.unsecure());
+        if let Some(etag) = &self.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Could not reach the primary at {}", self.snapshot_url))?;
+        match response.status() {
+            StatusCode::NOT_MODIFIED => return Ok(false),
+            status if status.is_success() => {}
+            status => bail!(
+                "The primary answered {}: {}",
+                status,
+                response.text().await.unwrap_or_default()
+            ),
+        }
+        let etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let data = response
+            .json::<BackupData>()
+            .await
+            .context("Invalid snapshot from the primary")?;
+        self.apply(data).await?;
+        self.etag = etag;
+        Ok(true)
+    }
+
+    async fn apply(&self, data: BackupData) -> Result<()> {
+        if let Some(private_key_hash) = &data.private_key_hash {
+            ensure!(
+                private_key_hash == &self.handler.config.get_private_key_info().private_key_hash,
+                "The primary has another server key: the passwords could not be checked. Set the same key_seed or key_file as on the primary"
+            );
+        }
+        let (users, groups) = (data.users.len(), data.groups.len());
+        backup::mirror(&self.handler.sql_pool, data).await?;
+        self.handler.after_write(Change::All).await;
+        info!(
+            "Replicated {} users and {} groups from the primary",
+            users, groups
+        );
+        Ok(())
+    }
+
+    /// Synchronizes now, then every `interval`, on the current runtime. The failures are only
+    /// recorded: the replica keeps serving its last copy.
+    pub fn start(mut self, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                match self.sync().await {
+                    Ok(_) => self.status.record_sync(Utc::now()),
+                    Err(e) => {
+                        warn!("Could not replicate the primary: {:#}", e);
+                        self.status.record_failure(format!("{:#}", e));
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        error::DomainError,
+        handler::{
+            BindRequest, CreateGroupRequest, CreateUserRequest, GroupBackendHandler,
+            GroupListerBackendHandler, LoginHandler, UserBackendHandler,
+        },
+        sql_backend_handler::tests::*,
+        types::{GroupName, UserId},
+    };
+    use actix_web::{http::StatusCode as ActixStatusCode, test, App};
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+
+    async fn replica(fixture: &TestFixture) -> Replicator {
+        let mut config = fixture.handler.config.clone();
+        config.replication.primary_url = Some(Url::parse("http://primary:17170/lldap").unwrap());
+        config.replication.token = Some(SecUtf8::from("token"));
+        let handler =
+            SqlBackendHandler::new(config.clone(), get_initialized_db().await).with_read_only();
+        Replicator::new(
+            &config,
+            handler,
+            ReplicationStatus::new(config.replication.primary_url.as_ref().unwrap()),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_url() {
+        let fixture = TestFixture::new().await;
+        assert_eq!(
+            replica(&fixture).await.snapshot_url.as_str(),
+            "http://primary:17170/lldap/api/replication/snapshot"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_snapshot() {
+        let fixture = TestFixture::new().await;
+        insert_user(&fixture.handler, "alice", "alice_pass").await;
+        let replicator = replica(&fixture).await;
+        let replica = &replicator.handler;
+        let data = || async {
+            backup::dump(&fixture.handler.sql_pool, false)
+                .await
+                .unwrap()
+        };
+        replicator.apply(data().await).await.unwrap();
+        // Applying the same snapshot again changes nothing.
+        replicator.apply(data().await).await.unwrap();
+        assert_eq!(
+            get_user_names(replica, None).await,
+            vec!["alice", "bob", "john", "nogroup", "patrick"]
+        );
+        replica
+            .bind(BindRequest {
+                name: UserId::new("alice"),
+                password: "alice_pass".to_owned(),
+            })
+            .await
+            .unwrap();
+
+        fixture
+            .handler
+            .delete_group(fixture.groups[0])
+            .await
+            .unwrap();
+        fixture
+            .handler
+            .permanently_delete_user(&UserId::new("bob"))
+            .await
+            .unwrap();
+        replicator.apply(data().await).await.unwrap();
+        assert_eq!(
+            get_user_names(replica, None).await,
+            vec!["alice", "john", "nogroup", "patrick"]
+        );
+        assert_eq!(
+            replica
+                .list_groups(None)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|g| g.display_name)
+                .collect::<Vec<_>>(),
+            fixture
+                .handler
+                .list_groups(None)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|g| g.display_name)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_snapshot_with_another_key() {
+        let fixture = TestFixture::new().await;
+        let mut data = backup::dump(&fixture.handler.sql_pool, false)
+            .await
+            .unwrap();
+        data.private_key_hash = Some(get_default_config().get_private_key_info().private_key_hash);
+        let error = replica(&fixture).await.apply(data).await.unwrap_err();
+        assert!(
+            error.to_string().contains("another server key"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replica_refuses_writes() {
+        let fixture = TestFixture::new().await;
+        insert_user(&fixture.handler, "alice", "alice_pass").await;
+        let replicator = replica(&fixture).await;
+        replicator
+            .apply(
+                backup::dump(&fixture.handler.sql_pool, false)
+                    .await
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let replica = &replicator.handler;
+        assert!(matches!(
+            replica
+                .create_user(CreateUserRequest {
+                    user_id: UserId::new("mallory"),
+                    ..Default::default()
+                })
+                .await,
+            Err(DomainError::ReadOnlyReplica)
+        ));
+        assert!(matches!(
+            replica
+                .add_user_to_group(&UserId::new("bob"), fixture.groups[1])
+                .await,
+            Err(DomainError::ReadOnlyReplica)
+        ));
+        assert!(matches!(
+            replica
+                .create_group(CreateGroupRequest {
+                    display_name: GroupName::from("Local"),
+                    ..Default::default()
+                })
+                .await,
+            Err(DomainError::ReadOnlyReplica)
+        ));
+        assert_eq!(
+            get_user_names(replica, None).await,
+            vec!["alice", "bob", "john", "nogroup", "patrick"]
+        );
+        // The binds still work, without recording the login.
+        replica
+            .bind(BindRequest {
+                name: UserId::new("alice"),
+                password: "alice_pass".to_owned(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            replica
+                .get_user_details(&UserId::new("alice"))
+                .await
+                .unwrap()
+                .last_login,
+            None
+        );
+    }
+
+    async fn get_snapshot(
+        source: SnapshotSource,
+        token: Option<&str>,
+        if_none_match: Option<&str>,
+    ) -> (ActixStatusCode, Option<String>) {
+        let app =
+            test::init_service(App::new().service(
+                web::scope("/api").configure(|cfg| configure(cfg, web::Data::new(source))),
+            ))
+            .await;
+        let mut request = test::TestRequest::get().uri("/api/replication/snapshot");
+        if let Some(token) = token {
+            request = request.insert_header((AUTHORIZATION, format!("Bearer {}", token)));
+        }
+        if let Some(etag) = if_none_match {
+            request = request.insert_header((IF_NONE_MATCH, etag));
+        }
+        let response = test::call_service(&app, request.to_request()).await;
+        (
+            response.status(),
+            response
+                .headers()
+                .get(ETAG)
+                .map(|value| value.to_str().unwrap().to_owned()),
+        )
+    }
+
+    #[actix_web::test]
+    async fn test_snapshot_endpoint() {
+        let fixture = TestFixture::new().await;
+        let source = || {
+            SnapshotSource::new(
+                fixture.handler.sql_pool.clone(),
+                SecUtf8::from("replication token"),
+            )
+        };
+        assert_eq!(
+            get_snapshot(source(), None, None).await,
+            (ActixStatusCode::UNAUTHORIZED, None)
+        );
+        assert_eq!(
+            get_snapshot(source(), Some("wrong token"), None).await,
+            (ActixStatusCode::UNAUTHORIZED, None)
+        );
+        let (status, etag) = get_snapshot(source(), Some("replication token"), None).await;
+        assert_eq!(status, ActixStatusCode::OK);
+        let etag = etag.unwrap();
+        assert_eq!(
+            get_snapshot(source(), Some("replication token"), Some(&etag)).await,
+            (ActixStatusCode::NOT_MODIFIED, Some(etag.clone()))
+        );
+        insert_user(&fixture.handler, "alice", "alice_pass").await;
+        let (status, new_etag) =
+            get_snapshot(source(), Some("replication token"), Some(&etag)).await;
+        assert_eq!(status, ActixStatusCode::OK);
+        assert_ne!(new_etag, Some(etag));
+    }
+
+    #[test]
+    fn test_status() {
+        let status = ReplicationStatus::new(&Url::parse("https://primary").unwrap());
+        let now = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        assert_eq!(
+            status.check(now),
+            ReplicationCheck {
+                primary_url: "https://primary/".to_owned(),
+                last_sync_at: None,
+                lag_secs: None,
+                error: None,
+            }
+        );
+        status.record_sync(now - chrono::Duration::seconds(90));
+        status.record_failure("Connection refused".to_owned());
+        assert_eq!(
+            status.check(now),
+            ReplicationCheck {
+                primary_url: "https://primary/".to_owned(),
+                last_sync_at: Some(now - chrono::Duration::seconds(90)),
+                lag_secs: Some(90),
+                error: Some("Connection refused".to_owned()),
+            }
+        );
+        assert_eq!(status.get().failures, 1);
+    }
+}
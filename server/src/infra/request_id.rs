@@ -0,0 +1,218 @@
+//! Request IDs for the HTTP requests, to correlate the logs, the responses (`X-Request-Id`) and the
+//! errors reported by the users, the client IP behind the trusted proxies, and the optional access
+//! log.
+
+use crate::infra::client_ip::{resolve_client_ip, ClientIp};
+use actix_http::header::{HeaderName, HeaderValue};
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    HttpMessage,
+};
+use futures::future::{ok, Ready};
+use futures_util::FutureExt;
+use ipnet::IpNet;
+use rand::Rng;
+use std::{
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tracing::info;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The ID of the current HTTP request, in the request extensions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+/// The GraphQL operation names of the current request, for the access log.
+#[derive(Clone, Debug)]
+pub struct GraphQLOperation(pub String);
+
+/// Generates a new ID, for an HTTP request or an LDAP connection.
+pub fn new_request_id() -> String {
+    format!(
+        "{:012x}",
+        rand::thread_rng().gen::<u64>() & 0xffff_ffff_ffff
+    )
+}
+
+/// Whether an incoming ID can be reused as is: it ends up in the logs and the headers.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Reuses the ID set by a trusted proxy, if any.
+fn get_or_create_request_id(request: &ServiceRequest, from_trusted_proxy: bool) -> String {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .filter(|_| from_trusted_proxy)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_owned)
+        .unwrap_or_else(new_request_id)
+}
+
+pub struct RequestIdentifier {
+    access_log: bool,
+    trusted_proxies: Rc<Vec<IpNet>>,
+}
+
+impl RequestIdentifier {
+    pub fn new(access_log: bool, trusted_proxies: Vec<IpNet>) -> Self {
+        Self {
+            access_log,
+            trusted_proxies: Rc::new(trusted_proxies),
+        }
+    }
+}
+
+impl<S> Transform<S, ServiceRequest> for RequestIdentifier
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = RequestIdentifierMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdentifierMiddleware {
+            service,
+            access_log: self.access_log,
+            trusted_proxies: self.trusted_proxies.clone(),
+        })
+    }
+}
+
+pub struct RequestIdentifierMiddleware<S> {
+    service: S,
+    access_log: bool,
+    trusted_proxies: Rc<Vec<IpNet>>,
+}
+
+impl<S> Service<ServiceRequest> for RequestIdentifierMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn core::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let peer = req.peer_addr().map(|a| a.ip());
+        let from_trusted_proxy = peer
+            .map(|ip| self.trusted_proxies.iter().any(|net| net.contains(&ip)))
+            .unwrap_or(false);
+        let client_ip = peer.map(|ip| resolve_client_ip(ip, req.headers(), &self.trusted_proxies));
+        let request_id = get_or_create_request_id(&req, from_trusted_proxy);
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+        if let Some(client_ip) = client_ip {
+            req.extensions_mut().insert(ClientIp(client_ip));
+        }
+        let access_log = self.access_log;
+        let start = Instant::now();
+        let method = req.method().to_string();
+        let path = req.path().to_owned();
+        let response = self.service.call(req);
+        async move {
+            let result = response.await;
+            if access_log {
+                let (status, operation) = match &result {
+                    Ok(response) => (
+                        response.status(),
+                        response
+                            .request()
+                            .extensions()
+                            .get::<GraphQLOperation>()
+                            .map(|o| o.0.clone()),
+                    ),
+                    Err(error) => (error.as_response_error().status_code(), None),
+                };
+                info!(
+                    target: "access_log",
+                    request_id = %request_id,
+                    client_ip = %client_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+                    method = %method,
+                    path = %path,
+                    operation = operation.as_deref().unwrap_or_default(),
+                    status = status.as_u16(),
+                    duration_ms = start.elapsed().as_millis() as u64,
+                    "HTTP request"
+                );
+            }
+            let mut response = result?;
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                response
+                    .headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+            Ok(response)
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use pretty_assertions::assert_eq;
+
+    async fn echo_request_id(request: HttpRequest) -> HttpResponse {
+        let id = request.extensions().get::<RequestId>().unwrap().0.clone();
+        HttpResponse::Ok().body(id)
+    }
+
+    #[actix_web::test]
+    async fn test_request_id() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIdentifier::new(
+                    true,
+                    vec!["10.0.0.0/8".parse().unwrap()],
+                ))
+                .route("/", web::get().to(echo_request_id)),
+        )
+        .await;
+        let response = test::call_service(&app, test::TestRequest::get().to_request()).await;
+        let header = response.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+        let body = test::read_body(response).await;
+        assert_eq!(header.to_str().unwrap().len(), 12);
+        assert_eq!(header.as_bytes(), &body[..]);
+
+        // A valid ID from a trusted proxy is kept, an invalid or untrusted one is replaced.
+        let request = |peer: &str, id: &str| {
+            test::TestRequest::get()
+                .peer_addr(peer.parse().unwrap())
+                .insert_header((REQUEST_ID_HEADER, id))
+                .to_request()
+        };
+        let response = test::call_service(&app, request("10.0.0.1:4711", "proxy-1234")).await;
+        assert_eq!(
+            response.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "proxy-1234"
+        );
+        let response = test::call_service(&app, request("192.0.2.1:4711", "proxy-1234")).await;
+        assert_ne!(
+            response.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "proxy-1234"
+        );
+        let response = test::call_service(&app, request("10.0.0.1:4711", "a b\"c")).await;
+        assert_ne!(response.headers().get(REQUEST_ID_HEADER).unwrap(), "a b\"c");
+    }
+}
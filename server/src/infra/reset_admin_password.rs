@@ -0,0 +1,184 @@
+//! `lldap reset_admin_password`: regains access to the admin account `ldap_user_dn` of the
+//! configuration when its password is lost, with the server stopped.
+
+use crate::{
+    domain::{
+        handler::{
+            CreateGroupRequest, CreateUserRequest, GroupBackendHandler, GroupListerBackendHandler,
+            GroupRequestFilter, UserBackendHandler, UserListerBackendHandler,
+        },
+        sql_backend_handler::SqlBackendHandler,
+        sql_opaque_handler::register_password,
+    },
+    infra::configuration::Configuration,
+};
+use anyhow::{bail, Context, Result};
+use secstr::SecUtf8;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+const ADMIN_GROUP: &str = "lldap_admin";
+
+/// The ports of the server, on the local host.
+fn server_addresses(config: &Configuration) -> Vec<(String, u16)> {
+    let local = |host: &str| match host {
+        "0.0.0.0" | "::" => "localhost".to_owned(),
+        host => host.to_owned(),
+    };
+    let mut addresses = vec![
+        (local(&config.ldap_host), config.ldap_port),
+        (local(&config.http_host), config.http_port),
+    ];
+    if config.ldaps_options.enabled {
+        addresses.push((local(&config.ldap_host), config.ldaps_options.port));
+    }
+    if let Some(port) = config.http_tls.https_port {
+        addresses.push((local(&config.http_host), port));
+    }
+    addresses
+}
+
+/// Refuses to modify the database under a running server, which would keep using its own state.
+/// `action` completes the error message, e.g. "resetting the admin password".
+pub async fn check_server_stopped(config: &Configuration, action: &str) -> Result<()> {
+    for (host, port) in server_addresses(config) {
+        let connection = tokio::time::timeout(
+            Duration::from_secs(1),
+            TcpStream::connect((host.as_str(), port)),
+        )
+        .await;
+        if let Ok(Ok(_)) = connection {
+            bail!(
+                "Something is listening on {}:{}: stop the LLDAP server before {}.",
+                host,
+                port,
+                action
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Makes sure that the admin user exists, is not deleted and is in the admin group, then sets its
+/// password. Returns what was changed.
+pub async fn reset_admin_password(
+    handler: &SqlBackendHandler,
+    config: &Configuration,
+    password: &SecUtf8,
+) -> Result<Vec<String>> {
+    let user_id = &config.ldap_user_dn;
+    let mut changes = Vec::new();
+    let admin_group = match handler
+        .list_groups(Some(GroupRequestFilter::DisplayName(ADMIN_GROUP.into())))
+        .await?
+        .first()
+    {
+        Some(group) => group.id,
+        None => {
+            changes.push(format!("Created the group {}", ADMIN_GROUP));
+            handler
+                .create_group(CreateGroupRequest {
+                    display_name: ADMIN_GROUP.into(),
+                    ..Default::default()
+                })
+                .await?
+        }
+    };
+    if handler.get_user_details(user_id).await.is_err() {
+        if handler
+            .list_deleted_users()
+            .await?
+            .iter()
+            .any(|deleted| &deleted.user.user_id == user_id)
+        {
+            handler.restore_user(user_id).await?;
+            changes.push(format!("Restored the deleted user {}", user_id));
+        } else {
+            handler
+                .create_user(CreateUserRequest {
+                    user_id: user_id.clone(),
+                    email: config.ldap_user_email.clone().into(),
+                    display_name: Some("Administrator".to_owned()),
+                    ..Default::default()
+                })
+                .await
+                .context(format!("while creating the user {}", user_id))?;
+            changes.push(format!("Created the user {}", user_id));
+        }
+    }
+    if handler.add_user_to_group(user_id, admin_group).await? {
+        changes.push(format!("Added {} to the group {}", user_id, ADMIN_GROUP));
+    }
+    register_password(handler, user_id.clone(), password)
+        .await
+        .context(format!("while setting the password of {}", user_id))?;
+    changes.push(format!("Changed the password of {}", user_id));
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        handler::{BindRequest, LoginHandler},
+        sql_backend_handler::tests::*,
+        types::UserId,
+    };
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_reset_admin_password() {
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config.clone(), get_initialized_db().await);
+        let password = SecUtf8::from("new admin password");
+        assert_eq!(
+            reset_admin_password(&handler, &config, &password)
+                .await
+                .unwrap(),
+            vec![
+                "Created the group lldap_admin",
+                "Created the user admin",
+                "Added admin to the group lldap_admin",
+                "Changed the password of admin",
+            ]
+        );
+        handler
+            .bind(BindRequest {
+                name: UserId::new("admin"),
+                password: "new admin password".to_owned(),
+            })
+            .await
+            .unwrap();
+
+        handler.delete_user(&UserId::new("admin")).await.unwrap();
+        assert_eq!(
+            reset_admin_password(&handler, &config, &SecUtf8::from("another password"))
+                .await
+                .unwrap(),
+            vec![
+                "Restored the deleted user admin",
+                "Changed the password of admin",
+            ]
+        );
+        handler
+            .bind(BindRequest {
+                name: UserId::new("admin"),
+                password: "new admin password".to_owned(),
+            })
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_check_server_stopped() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = get_default_config();
+        config.ldap_host = "127.0.0.1".to_owned();
+        config.ldap_port = listener.local_addr().unwrap().port();
+        config.http_host = "127.0.0.1".to_owned();
+        config.http_port = 0;
+        check_server_stopped(&config).await.unwrap_err();
+        drop(listener);
+        check_server_stopped(&config).await.unwrap();
+    }
+}
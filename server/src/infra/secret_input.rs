@@ -0,0 +1,201 @@
+//! Reading the passwords and other secrets given to the CLI commands, from a file, the standard
+//! input or a hidden prompt. The secrets never go through the arguments, which are visible in the
+//! process list and the shell history.
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::{Parser, ValueHint};
+use secstr::SecUtf8;
+use std::{
+    io::{IsTerminal, Read},
+    path::{Path, PathBuf},
+};
+use zeroize::Zeroizing;
+
+/// `--password-file` and `--password-stdin`, flattened in the commands that take a password.
+#[derive(Debug, Default, Parser, Clone)]
+pub struct PasswordInputOpts {
+    /// File containing the password. Only the final line break is ignored.
+    #[clap(long, value_hint = ValueHint::FilePath, conflicts_with = "password_stdin")]
+    pub password_file: Option<PathBuf>,
+
+    /// Read the password from the standard input, e.g. `echo "$PASSWORD" | lldap ...`.
+    #[clap(long)]
+    pub password_stdin: bool,
+}
+
+impl PasswordInputOpts {
+    pub fn source(&self) -> SecretSource<'_> {
+        SecretSource {
+            file: self.password_file.as_deref(),
+            stdin: self.password_stdin,
+        }
+    }
+}
+
+/// Where to read a secret from, out of the command line options.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SecretSource<'a> {
+    pub file: Option<&'a Path>,
+    pub stdin: bool,
+}
+
+impl SecretSource<'_> {
+    /// The secret from the file or the standard input, if any of them was given.
+    pub fn read(&self) -> Result<Option<SecUtf8>> {
+        match (self.file, self.stdin) {
+            (Some(_), true) => {
+                bail!("A secret can't be read from both a file and the standard input")
+            }
+            (Some(path), false) => read_secret_file(path).map(Some),
+            (None, true) => read_secret_stdin().map(Some),
+            (None, false) => Ok(None),
+        }
+    }
+
+    /// Same as `read`, but prompts for the secret when neither was given. With `confirm`, it is
+    /// asked twice.
+    pub fn read_or_prompt(&self, prompt: &str, confirm: bool) -> Result<SecUtf8> {
+        match self.read()? {
+            Some(secret) => Ok(secret),
+            None => prompt_secret(prompt, confirm),
+        }
+    }
+
+    /// Whether the secret comes from the standard input: only one of them can.
+    pub fn uses_stdin(&self) -> bool {
+        self.stdin || self.file.is_some_and(|f| f == Path::new("-"))
+    }
+}
+
+/// Removes the line break at the end, which most editors and `echo` add. Only one: the other
+/// whitespace is part of the secret.
+fn strip_line_break(secret: &str) -> &str {
+    secret
+        .strip_suffix("\r\n")
+        .or_else(|| secret.strip_suffix('\n'))
+        .unwrap_or(secret)
+}
+
+fn parse_secret(buffer: &[u8], origin: &str) -> Result<SecUtf8> {
+    let secret = std::str::from_utf8(buffer).context(format!("{} is not valid UTF-8", origin))?;
+    let secret = strip_line_break(secret);
+    ensure!(!secret.is_empty(), "{} is empty", origin);
+    Ok(SecUtf8::from(secret))
+}
+
+/// Reads a secret from a file. "-" is still accepted for the standard input.
+pub fn read_secret_file(path: &Path) -> Result<SecUtf8> {
+    if path == Path::new("-") {
+        return read_secret_stdin();
+    }
+    let buffer = Zeroizing::new(
+        std::fs::read(path).context(format!("Could not read the file `{}`", path.display()))?,
+    );
+    parse_secret(&buffer, &format!("The file `{}`", path.display()))
+}
+
+pub fn read_secret_stdin() -> Result<SecUtf8> {
+    let mut buffer = Zeroizing::new(Vec::new());
+    std::io::stdin()
+        .read_to_end(&mut buffer)
+        .context("Could not read the standard input")?;
+    parse_secret(&buffer, "The standard input")
+}
+
+/// Asks for the secret on the terminal, without echoing it.
+pub fn prompt_secret(prompt: &str, confirm: bool) -> Result<SecUtf8> {
+    ensure!(
+        std::io::stdin().is_terminal(),
+        "No terminal to ask for the password: give it in a file or on the standard input"
+    );
+    let secret = Zeroizing::new(rpassword::prompt_password(prompt)?);
+    ensure!(!secret.is_empty(), "The password is empty");
+    if confirm {
+        let confirmation = Zeroizing::new(rpassword::prompt_password("Confirm the password: ")?);
+        ensure!(secret == confirmation, "The passwords don't match");
+    }
+    Ok(SecUtf8::from(secret.as_str()))
+}
+
+/// The options that still take a secret as a plain argument, and where to put it instead.
+const DEPRECATED_SECRET_ARGS: [(&str, &str); 2] = [
+    (
+        "--smtp-password",
+        "the LLDAP_SMTP_OPTIONS__PASSWORD environment variable or the configuration file",
+    ),
+    (
+        "--server-key-seed",
+        "the LLDAP_SERVER_KEY_SEED environment variable or the configuration file",
+    ),
+];
+
+/// The deprecation warnings for the secrets given as arguments. The same options given through
+/// their environment variable are fine.
+pub fn deprecated_secret_args<I, S>(args: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    let mut warnings = Vec::new();
+    for arg in args {
+        let arg = arg.as_ref().to_string_lossy();
+        for (flag, replacement) in DEPRECATED_SECRET_ARGS {
+            if arg == flag || arg.starts_with(&format!("{}=", flag)) {
+                warnings.push(format!(
+                    "DEPRECATED: {} exposes the secret in the process list and the shell history, use {} instead.",
+                    flag, replacement
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_line_break() {
+        assert_eq!(strip_line_break("secret\n"), "secret");
+        assert_eq!(strip_line_break("secret\r\n"), "secret");
+        assert_eq!(strip_line_break("secret\n\n"), "secret\n");
+        assert_eq!(strip_line_break(" secret "), " secret ");
+        assert_eq!(strip_line_break("secret"), "secret");
+    }
+
+    #[test]
+    fn test_read_secret_file() {
+        let dir = std::env::temp_dir().join(format!("lldap-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("password");
+        std::fs::write(&path, "  secret password \r\n").unwrap();
+        let password = SecretSource {
+            file: Some(&path),
+            stdin: false,
+        }
+        .read()
+        .unwrap();
+        std::fs::write(&path, "\n").unwrap();
+        let empty = read_secret_file(&path);
+        let missing = read_secret_file(&dir.join("missing"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(password.unwrap().unsecure(), "  secret password ");
+        empty.unwrap_err();
+        missing.unwrap_err();
+        assert!(SecretSource::default().read().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_deprecated_secret_args() {
+        assert_eq!(
+            deprecated_secret_args(["lldap", "run", "--smtp-password", "secret"]).len(),
+            1
+        );
+        assert_eq!(
+            deprecated_secret_args(["lldap", "run", "--server-key-seed=seed"]).len(),
+            1
+        );
+        assert!(deprecated_secret_args(["lldap", "run", "--smtp-password-file", "f"]).is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+//! Misconfigurations weakening the security of the server, reported to the admins in the web UI
+//! since the startup warnings end up in logs that nobody reads. Anonymous binds are always
+//! refused, so there is nothing to report about them.
+
+use crate::{
+    domain::{
+        handler::{BindRequest, LoginHandler},
+        types::UserId,
+    },
+    infra::configuration::{Configuration, DEFAULT_ADMIN_PASSWORD, DEFAULT_JWT_SECRET},
+};
+use async_trait::async_trait;
+use secstr::SecUtf8;
+use tracing::warn;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, juniper::GraphQLEnum)]
+pub enum SecurityIssue {
+    DefaultJwtSecret,
+    DefaultAdminPassword,
+    LdapsDisabled,
+    PasswordResetWithoutHttpUrl,
+}
+
+impl SecurityIssue {
+    pub fn description(&self) -> &'static str {
+        match self {
+            SecurityIssue::DefaultJwtSecret => {
+                "The default JWT secret is used: anyone can forge a session token and log in as an admin."
+            }
+            SecurityIssue::DefaultAdminPassword => {
+                "The admin user can still log in with the default password."
+            }
+            SecurityIssue::LdapsDisabled => {
+                "LDAPS is disabled: the LDAP clients send the passwords in clear text."
+            }
+            SecurityIssue::PasswordResetWithoutHttpUrl => {
+                "The password reset is enabled, but http_url is not set: the links in the emails point to localhost."
+            }
+        }
+    }
+
+    pub fn remediation(&self) -> &'static str {
+        match self {
+            SecurityIssue::DefaultJwtSecret => {
+                "Set jwt_secret (or LLDAP_JWT_SECRET) to a long random string, e.g. from `openssl rand -base64 32`, and restart."
+            }
+            SecurityIssue::DefaultAdminPassword => {
+                "Change the password of the admin user, and set ldap_user_pass (or LLDAP_LDAP_USER_PASS) to something else."
+            }
+            SecurityIssue::LdapsDisabled => {
+                "Enable ldaps_options with a certificate, unless the LDAP traffic stays on a trusted network."
+            }
+            SecurityIssue::PasswordResetWithoutHttpUrl => {
+                "Set http_url (or LLDAP_HTTP_URL) to the public URL of the web UI."
+            }
+        }
+    }
+}
+
+#[async_trait]
+pub trait SecurityChecker: Send + Sync {
+    async fn security_issues(&self) -> Vec<SecurityIssue>;
+}
+
+/// A fixed list of issues.
+#[async_trait]
+impl SecurityChecker for Vec<SecurityIssue> {
+    async fn security_issues(&self) -> Vec<SecurityIssue> {
+        self.clone()
+    }
+}
+
+/// The issues that only depend on the configuration.
+pub fn configuration_issues(config: &Configuration) -> Vec<SecurityIssue> {
+    let mut issues = Vec::new();
+    if config.jwt_secret == SecUtf8::from(DEFAULT_JWT_SECRET) {
+        issues.push(SecurityIssue::DefaultJwtSecret);
+    }
+    if !config.ldaps_options.enabled {
+        issues.push(SecurityIssue::LdapsDisabled);
+    }
+    if config.smtp_options.enable_password_reset && config.http_url.host_str() == Some("localhost")
+    {
+        issues.push(SecurityIssue::PasswordResetWithoutHttpUrl);
+    }
+    issues
+}
+
+/// Checks the configuration, and whether the admin user still has the default password. The
+/// password can change at any time, so it is checked on each call.
+pub struct ConfigurationSecurityChecker<Backend> {
+    backend_handler: Backend,
+    admin: UserId,
+    configuration_issues: Vec<SecurityIssue>,
+}
+
+impl<Backend> ConfigurationSecurityChecker<Backend> {
+    pub fn new(config: &Configuration, backend_handler: Backend) -> Self {
+        Self {
+            backend_handler,
+            admin: config.ldap_user_dn.clone(),
+            configuration_issues: configuration_issues(config),
+        }
+    }
+}
+
+#[async_trait]
+impl<Backend: LoginHandler> SecurityChecker for ConfigurationSecurityChecker<Backend> {
+    async fn security_issues(&self) -> Vec<SecurityIssue> {
+        let mut issues = self.configuration_issues.clone();
+        match self
+            .backend_handler
+            .check_password(BindRequest {
+                name: self.admin.clone(),
+                password: DEFAULT_ADMIN_PASSWORD.to_owned(),
+            })
+            .await
+        {
+            Ok(true) => issues.push(SecurityIssue::DefaultAdminPassword),
+            Ok(false) => {}
+            Err(e) => warn!("Could not check the password of the admin user: {:#}", e),
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::test_utils::MockTestBackendHandler;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_configuration_issues() {
+        assert_eq!(
+            configuration_issues(&Configuration::defaults()),
+            vec![
+                SecurityIssue::DefaultJwtSecret,
+                SecurityIssue::LdapsDisabled
+            ]
+        );
+        let mut config = Configuration::defaults();
+        config.jwt_secret = SecUtf8::from("a very secret secret");
+        config.ldaps_options.enabled = true;
+        assert_eq!(configuration_issues(&config), vec![]);
+        config.smtp_options.enable_password_reset = true;
+        assert_eq!(
+            configuration_issues(&config),
+            vec![SecurityIssue::PasswordResetWithoutHttpUrl]
+        );
+        config.http_url = url::Url::parse("https://ldap.example.com").unwrap();
+        assert_eq!(configuration_issues(&config), vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_default_admin_password() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_check_password()
+            .withf(|request| {
+                request.name == UserId::new("admin") && request.password == DEFAULT_ADMIN_PASSWORD
+            })
+            .times(1)
+            .return_once(|_| Ok(true));
+        let mut config = Configuration::defaults();
+        config.ldaps_options.enabled = true;
+        let checker = ConfigurationSecurityChecker::new(&config, mock);
+        assert_eq!(
+            checker.security_issues().await,
+            vec![
+                SecurityIssue::DefaultJwtSecret,
+                SecurityIssue::DefaultAdminPassword
+            ]
+        );
+    }
+}
@@ -0,0 +1,42 @@
+//! Graceful shutdown: on SIGTERM or SIGINT, the server stops accepting connections and lets the
+//! requests in progress complete, up to `shutdown_timeout_secs`.
+
+use actix_server::ServerHandle;
+use anyhow::Result;
+pub use tokio_util::sync::CancellationToken as ShutdownToken;
+use tracing::{error, info};
+
+async fn wait_for_signal() -> Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sigterm = signal(SignalKind::terminate())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res.map(|_| "SIGINT").map_err(Into::into),
+            _ = sigterm.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await?;
+        Ok("Ctrl-C")
+    }
+}
+
+/// Stops the server on the first signal: the token is cancelled, so that the idle LDAP
+/// connections get closed, and the server stops accepting connections and waits for the
+/// requests in progress.
+pub fn handle_signals(server: ServerHandle, token: ShutdownToken) {
+    tokio::spawn(async move {
+        match wait_for_signal().await {
+            Ok(signal) => info!("Received {}, shutting down", signal),
+            Err(e) => {
+                error!("Could not listen for the shutdown signals: {:#}", e);
+                return;
+            }
+        }
+        super::systemd::notify("STOPPING=1");
+        token.cancel();
+        server.stop(true).await;
+    });
+}
@@ -1,12 +1,14 @@
-use super::tcp_backend_handler::TcpBackendHandler;
+use super::tcp_backend_handler::{PendingEmailChange, TcpBackendHandler};
 use crate::domain::{
     error::*,
     model::{self, JwtRefreshStorageColumn, JwtStorageColumn, PasswordResetTokensColumn},
     sql_backend_handler::SqlBackendHandler,
+    sql_migrations::try_get_schema_version,
+    sql_tables::SchemaVersion,
     types::UserId,
 };
 use async_trait::async_trait;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
 use sea_orm::{
     sea_query::{Cond, Expr},
     ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, QuerySelect,
@@ -14,6 +16,12 @@ use sea_orm::{
 use std::collections::HashSet;
 use tracing::{debug, instrument};
 
+/// How long a password reset link is valid.
+pub const PASSWORD_RESET_TOKEN_VALIDITY_MINUTES: i64 = 10;
+
+/// How long the link to verify a new email address is valid.
+pub const EMAIL_CHANGE_TOKEN_VALIDITY_HOURS: i64 = 24;
+
 fn gen_random_string(len: usize) -> String {
     use rand::{distributions::Alphanumeric, rngs::SmallRng, Rng, SeedableRng};
     let mut rng = SmallRng::from_entropy();
@@ -24,6 +32,31 @@ fn gen_random_string(len: usize) -> String {
         .collect()
 }
 
+impl SqlBackendHandler {
+    /// The password reset and invitation links share the tokens: both are single-use, and lead
+    /// to setting a new password. The email verification links use the same table, with the new
+    /// address set.
+    async fn create_password_reset_token(
+        &self,
+        user: &UserId,
+        validity: chrono::Duration,
+        new_email: Option<&str>,
+    ) -> Result<String> {
+        // They lead to changes of the users, that a replica can't make.
+        self.check_writable()?;
+        let token = gen_random_string(100);
+        let new_token = model::password_reset_tokens::Model {
+            token: token.clone(),
+            user_id: user.clone(),
+            expiry_date: chrono::Utc::now() + validity,
+            new_email: new_email.map(str::to_owned),
+        }
+        .into_active_model();
+        new_token.insert(&self.sql_pool).await?;
+        Ok(token)
+    }
+}
+
 #[async_trait]
 impl TcpBackendHandler for SqlBackendHandler {
     #[instrument(skip_all, level = "debug")]
@@ -41,7 +74,11 @@ impl TcpBackendHandler for SqlBackendHandler {
     }
 
     #[instrument(skip_all, level = "debug")]
-    async fn create_refresh_token(&self, user: &UserId) -> Result<(String, chrono::Duration)> {
+    async fn create_refresh_token(
+        &self,
+        user: &UserId,
+        validity: chrono::Duration,
+    ) -> Result<String> {
         debug!(?user);
         // TODO: Initialize the rng only once. Maybe Arc<Cell>?
         let refresh_token = gen_random_string(100);
@@ -52,15 +89,14 @@ impl TcpBackendHandler for SqlBackendHandler {
             refresh_token.hash(&mut s);
             s.finish()
         };
-        let duration = chrono::Duration::days(30);
         let new_token = model::jwt_refresh_storage::Model {
             refresh_token_hash: refresh_token_hash as i64,
             user_id: user.clone(),
-            expiry_date: chrono::Utc::now().naive_utc() + duration,
+            expiry_date: chrono::Utc::now() + validity,
         }
         .into_active_model();
         new_token.insert(&self.sql_pool).await?;
-        Ok((refresh_token, duration))
+        Ok(refresh_token)
     }
 
     #[instrument(skip_all, level = "debug")]
@@ -68,7 +104,7 @@ impl TcpBackendHandler for SqlBackendHandler {
         &self,
         user: &UserId,
         jwt_hash: u64,
-        expiry_date: NaiveDateTime,
+        expiry_date: DateTime<Utc>,
     ) -> Result<()> {
         debug!(?user, ?jwt_hash);
         let new_token = model::jwt_storage::Model {
@@ -83,14 +119,19 @@ impl TcpBackendHandler for SqlBackendHandler {
     }
 
     #[instrument(skip_all, level = "debug")]
-    async fn check_token(&self, refresh_token_hash: u64, user: &UserId) -> Result<bool> {
+    async fn check_token(
+        &self,
+        refresh_token_hash: u64,
+        user: &UserId,
+    ) -> Result<Option<DateTime<Utc>>> {
         debug!(?user);
         Ok(
             model::JwtRefreshStorage::find_by_id(refresh_token_hash as i64)
                 .filter(JwtRefreshStorageColumn::UserId.eq(user))
+                .filter(JwtRefreshStorageColumn::ExpiryDate.gt(chrono::Utc::now()))
                 .one(&self.sql_pool)
                 .await?
-                .is_some(),
+                .map(|token| token.expiry_date),
         )
     }
 
@@ -131,6 +172,7 @@ impl TcpBackendHandler for SqlBackendHandler {
     async fn start_password_reset(&self, user: &UserId) -> Result<Option<String>> {
         debug!(?user);
         if model::User::find_by_id(user.clone())
+            .filter(model::UserColumn::DeletedAt.is_null())
             .one(&self.sql_pool)
             .await?
             .is_none()
@@ -139,23 +181,31 @@ impl TcpBackendHandler for SqlBackendHandler {
             return Ok(None);
         }
 
-        let token = gen_random_string(100);
-        let duration = chrono::Duration::minutes(10);
+        let duration = chrono::Duration::minutes(PASSWORD_RESET_TOKEN_VALIDITY_MINUTES);
+        Ok(Some(
+            self.create_password_reset_token(user, duration, None)
+                .await?,
+        ))
+    }
 
-        let new_token = model::password_reset_tokens::Model {
-            token: token.clone(),
-            user_id: user.clone(),
-            expiry_date: chrono::Utc::now().naive_utc() + duration,
-        }
-        .into_active_model();
-        new_token.insert(&self.sql_pool).await?;
-        Ok(Some(token))
+    #[instrument(skip_all, level = "debug")]
+    async fn start_invitation(&self, user: &UserId, validity: chrono::Duration) -> Result<String> {
+        debug!(?user);
+        self.check_writable()?;
+        // Only the last invitation is valid.
+        model::PasswordResetTokens::delete_many()
+            .filter(PasswordResetTokensColumn::UserId.eq(user))
+            .filter(PasswordResetTokensColumn::NewEmail.is_null())
+            .exec(&self.sql_pool)
+            .await?;
+        self.create_password_reset_token(user, validity, None).await
     }
 
     #[instrument(skip_all, level = "debug", ret)]
     async fn get_user_id_for_password_reset_token(&self, token: &str) -> Result<UserId> {
         Ok(model::PasswordResetTokens::find_by_id(token.to_owned())
-            .filter(PasswordResetTokensColumn::ExpiryDate.gt(chrono::Utc::now().naive_utc()))
+            .filter(PasswordResetTokensColumn::ExpiryDate.gt(chrono::Utc::now()))
+            .filter(PasswordResetTokensColumn::NewEmail.is_null())
             .one(&self.sql_pool)
             .await?
             .ok_or_else(|| DomainError::EntityNotFound("Invalid reset token".to_owned()))?
@@ -175,4 +225,254 @@ impl TcpBackendHandler for SqlBackendHandler {
         }
         Ok(())
     }
+
+    #[instrument(skip_all, level = "debug")]
+    async fn start_email_change(
+        &self,
+        user: &UserId,
+        new_email: &str,
+        validity: chrono::Duration,
+    ) -> Result<String> {
+        debug!(?user, new_email);
+        self.check_writable()?;
+        self.cancel_email_change(user).await?;
+        self.create_password_reset_token(user, validity, Some(new_email))
+            .await
+    }
+
+    #[instrument(skip_all, level = "debug", ret)]
+    async fn get_pending_email_change(&self, user: &UserId) -> Result<Option<PendingEmailChange>> {
+        Ok(model::PasswordResetTokens::find()
+            .filter(PasswordResetTokensColumn::UserId.eq(user))
+            .filter(PasswordResetTokensColumn::ExpiryDate.gt(chrono::Utc::now()))
+            .filter(PasswordResetTokensColumn::NewEmail.is_not_null())
+            .one(&self.sql_pool)
+            .await?
+            .and_then(|token| {
+                Some(PendingEmailChange {
+                    new_email: token.new_email?,
+                    expiry_date: token.expiry_date,
+                })
+            }))
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    async fn cancel_email_change(&self, user: &UserId) -> Result<()> {
+        debug!(?user);
+        model::PasswordResetTokens::delete_many()
+            .filter(PasswordResetTokensColumn::UserId.eq(user))
+            .filter(PasswordResetTokensColumn::NewEmail.is_not_null())
+            .exec(&self.sql_pool)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    async fn finish_email_change(&self, token: &str) -> Result<(UserId, String)> {
+        let token = model::PasswordResetTokens::find_by_id(token.to_owned())
+            .filter(PasswordResetTokensColumn::ExpiryDate.gt(chrono::Utc::now()))
+            .filter(PasswordResetTokensColumn::NewEmail.is_not_null())
+            .one(&self.sql_pool)
+            .await?
+            .ok_or_else(|| {
+                DomainError::EntityNotFound("Invalid email verification token".to_owned())
+            })?;
+        model::PasswordResetTokens::delete_by_id(token.token)
+            .exec(&self.sql_pool)
+            .await?;
+        debug!(user = ?token.user_id);
+        Ok((token.user_id, token.new_email.unwrap_or_default()))
+    }
+
+    async fn get_schema_version(&self) -> Result<Option<SchemaVersion>> {
+        Ok(try_get_schema_version(&self.sql_pool).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sql_backend_handler::tests::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_invitation_tokens() {
+        let fixture = TestFixture::new().await;
+        let user = UserId::new("bob");
+        let first = fixture
+            .handler
+            .start_invitation(&user, chrono::Duration::days(7))
+            .await
+            .unwrap();
+        let second = fixture
+            .handler
+            .start_invitation(&user, chrono::Duration::days(7))
+            .await
+            .unwrap();
+        // Sending the invitation again invalidates the previous link.
+        assert!(fixture
+            .handler
+            .get_user_id_for_password_reset_token(&first)
+            .await
+            .is_err());
+        assert_eq!(
+            fixture
+                .handler
+                .get_user_id_for_password_reset_token(&second)
+                .await
+                .unwrap(),
+            user
+        );
+        // The tokens are single-use.
+        fixture
+            .handler
+            .delete_password_reset_token(&second)
+            .await
+            .unwrap();
+        assert!(fixture
+            .handler
+            .get_user_id_for_password_reset_token(&second)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expired_invitation() {
+        let fixture = TestFixture::new().await;
+        let token = fixture
+            .handler
+            .start_invitation(&UserId::new("bob"), chrono::Duration::days(-1))
+            .await
+            .unwrap();
+        assert!(fixture
+            .handler
+            .get_user_id_for_password_reset_token(&token)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_email_change() {
+        let fixture = TestFixture::new().await;
+        let user = UserId::new("bob");
+        let reset = fixture.handler.start_password_reset(&user).await.unwrap();
+        let first = fixture
+            .handler
+            .start_email_change(&user, "first@example.com", chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        let second = fixture
+            .handler
+            .start_email_change(&user, "bob@example.org", chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            fixture
+                .handler
+                .get_pending_email_change(&user)
+                .await
+                .unwrap()
+                .map(|change| change.new_email),
+            Some("bob@example.org".to_owned())
+        );
+        // Only the last change can be verified.
+        assert!(fixture.handler.finish_email_change(&first).await.is_err());
+        // The verification token can't be used to reset the password, and vice versa.
+        assert!(fixture
+            .handler
+            .get_user_id_for_password_reset_token(&second)
+            .await
+            .is_err());
+        assert!(fixture
+            .handler
+            .finish_email_change(reset.as_ref().unwrap())
+            .await
+            .is_err());
+        assert_eq!(
+            fixture.handler.finish_email_change(&second).await.unwrap(),
+            (user.clone(), "bob@example.org".to_owned())
+        );
+        assert!(fixture.handler.finish_email_change(&second).await.is_err());
+        assert_eq!(
+            fixture
+                .handler
+                .get_pending_email_change(&user)
+                .await
+                .unwrap(),
+            None
+        );
+        // The password reset is still pending.
+        assert_eq!(
+            fixture
+                .handler
+                .get_user_id_for_password_reset_token(reset.as_ref().unwrap())
+                .await
+                .unwrap(),
+            user
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_and_expired_email_change() {
+        let fixture = TestFixture::new().await;
+        let user = UserId::new("bob");
+        let token = fixture
+            .handler
+            .start_email_change(&user, "bob@example.org", chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        fixture.handler.cancel_email_change(&user).await.unwrap();
+        assert!(fixture.handler.finish_email_change(&token).await.is_err());
+        let expired = fixture
+            .handler
+            .start_email_change(&user, "bob@example.org", chrono::Duration::hours(-1))
+            .await
+            .unwrap();
+        assert_eq!(
+            fixture
+                .handler
+                .get_pending_email_change(&user)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(fixture.handler.finish_email_change(&expired).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_expiry() {
+        fn hash(token: &str) -> u64 {
+            use std::collections::hash_map::DefaultHasher;
+            use std::hash::{Hash, Hasher};
+            let mut s = DefaultHasher::new();
+            token.hash(&mut s);
+            s.finish()
+        }
+        let fixture = TestFixture::new().await;
+        let user = UserId::new("bob");
+        let valid = fixture
+            .handler
+            .create_refresh_token(&user, chrono::Duration::hours(12))
+            .await
+            .unwrap();
+        let expired = fixture
+            .handler
+            .create_refresh_token(&user, chrono::Duration::hours(-1))
+            .await
+            .unwrap();
+        assert!(fixture
+            .handler
+            .check_token(hash(&valid), &user)
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            fixture
+                .handler
+                .check_token(hash(&expired), &user)
+                .await
+                .unwrap(),
+            None
+        );
+    }
 }
@@ -0,0 +1,157 @@
+//! systemd integration: readiness and watchdog notifications for `Type=notify` services, and
+//! socket activation (with the `systemd` feature).
+//!
+//! Everything is driven by the environment variables set by systemd (NOTIFY_SOCKET,
+//! WATCHDOG_USEC, LISTEN_FDS), so nothing changes when running outside of systemd.
+
+use actix_rt::net::TcpStream;
+use actix_server::{ServerBuilder, ServerServiceFactory};
+use std::{collections::HashMap, net::TcpListener, time::Duration};
+use tracing::{debug, info, warn};
+
+/// Sends a state change, e.g. "READY=1", to systemd if it's listening.
+pub fn notify(state: &str) {
+    let socket = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => socket,
+        None => return,
+    };
+    debug!("Notifying systemd: {}", state);
+    if let Err(e) = send_notification(&socket, state) {
+        warn!("Could not notify systemd of {}: {:#}", state, e);
+    }
+}
+
+#[cfg(unix)]
+fn send_notification(socket: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+    let sender = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(abstract_name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            let address = SocketAddr::from_abstract_name(abstract_name)?;
+            sender.send_to_addr(state.as_bytes(), &address)?;
+        }
+        _ => {
+            sender.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send_notification(_socket: &std::ffi::OsStr, _state: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// The interval at which systemd expects "WATCHDOG=1", if `WatchdogSec` is set for this process.
+fn watchdog_interval() -> Option<Duration> {
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec))
+}
+
+/// Tells systemd that the server is ready, and starts pinging the watchdog if enabled.
+pub fn notify_ready() {
+    notify("READY=1");
+    if let Some(interval) = watchdog_interval() {
+        info!("Pinging the systemd watchdog every {:?}", interval / 2);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval / 2);
+            loop {
+                ticks.tick().await;
+                notify("WATCHDOG=1");
+            }
+        });
+    }
+}
+
+/// The listening sockets passed by systemd, by name (`FileDescriptorName=` in the socket unit):
+/// "ldap", "ldaps", "http" or "https".
+#[derive(Default)]
+pub struct ActivatedSockets {
+    listeners: HashMap<String, TcpListener>,
+}
+
+impl ActivatedSockets {
+    #[cfg(feature = "systemd")]
+    pub fn from_env() -> Self {
+        let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+        let names = names.split(':').collect::<Vec<_>>();
+        let mut fds = listenfd::ListenFd::from_env();
+        let mut listeners = HashMap::new();
+        for index in 0..fds.len() {
+            let name = names.get(index).copied().unwrap_or("unknown");
+            match fds.take_tcp_listener(index) {
+                Ok(Some(listener)) => {
+                    listeners.insert(name.to_owned(), listener);
+                }
+                Ok(None) => {}
+                Err(e) => warn!(
+                    "Ignoring the socket \"{}\" passed by systemd: {:#}",
+                    name, e
+                ),
+            }
+        }
+        Self { listeners }
+    }
+
+    #[cfg(not(feature = "systemd"))]
+    pub fn from_env() -> Self {
+        if std::env::var_os("LISTEN_FDS").is_some() {
+            warn!("Sockets were passed by systemd, but LLDAP was built without the \"systemd\" feature: ignoring them");
+        }
+        Self::default()
+    }
+
+    /// Listens on the socket `name` passed by systemd, or binds to `address` if there is none.
+    pub fn bind<F>(
+        &mut self,
+        server_builder: ServerBuilder,
+        name: &str,
+        address: (String, u16),
+        factory: F,
+    ) -> std::io::Result<ServerBuilder>
+    where
+        F: ServerServiceFactory<TcpStream>,
+    {
+        match self.listeners.remove(name) {
+            Some(listener) => {
+                info!("Using the \"{}\" socket passed by systemd", name);
+                listener.set_nonblocking(true)?;
+                server_builder.listen(name, listener, factory)
+            }
+            None => server_builder.bind(name, address, factory),
+        }
+    }
+
+    /// Warns about the sockets that didn't match any listener.
+    pub fn warn_unused(&self) {
+        for name in self.listeners.keys() {
+            warn!("Unused socket \"{}\" passed by systemd: expected one of ldap, ldaps, http or https", name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_notify() {
+        let dir = std::env::temp_dir().join(format!("lldap-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let receiver = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        send_notification(path.as_os_str(), "READY=1").unwrap();
+        let mut buffer = [0u8; 64];
+        let length = receiver.recv(&mut buffer).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(&buffer[..length], b"READY=1");
+    }
+}
@@ -1,20 +1,37 @@
 use async_trait::async_trait;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
 use std::collections::HashSet;
 
-use crate::domain::{error::Result, types::UserId};
+use crate::domain::{error::Result, sql_tables::SchemaVersion, types::UserId};
+
+/// A change of email address waiting for the new address to be verified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingEmailChange {
+    pub new_email: String,
+    pub expiry_date: DateTime<Utc>,
+}
 
 #[async_trait]
 pub trait TcpBackendHandler: Sync {
     async fn get_jwt_blacklist(&self) -> anyhow::Result<HashSet<u64>>;
-    async fn create_refresh_token(&self, user: &UserId) -> Result<(String, chrono::Duration)>;
+    /// Creates a refresh token for the user, valid for `validity`.
+    async fn create_refresh_token(
+        &self,
+        user: &UserId,
+        validity: chrono::Duration,
+    ) -> Result<String>;
     async fn register_jwt(
         &self,
         user: &UserId,
         jwt_hash: u64,
-        expiry_date: NaiveDateTime,
+        expiry_date: DateTime<Utc>,
     ) -> Result<()>;
-    async fn check_token(&self, refresh_token_hash: u64, user: &UserId) -> Result<bool>;
+    /// The expiry date of the refresh token, if it is valid.
+    async fn check_token(
+        &self,
+        refresh_token_hash: u64,
+        user: &UserId,
+    ) -> Result<Option<DateTime<Utc>>>;
     async fn blacklist_jwts(&self, user: &UserId) -> Result<HashSet<u64>>;
     async fn delete_refresh_token(&self, refresh_token_hash: u64) -> Result<()>;
 
@@ -22,8 +39,32 @@ pub trait TcpBackendHandler: Sync {
     /// If the user doesn't exist, returns `Ok(None)`, otherwise `Ok(Some(token))`.
     async fn start_password_reset(&self, user: &UserId) -> Result<Option<String>>;
 
+    /// Request a token for an invitation link, valid for `validity`. The previous tokens of the
+    /// user are invalidated.
+    async fn start_invitation(&self, user: &UserId, validity: chrono::Duration) -> Result<String>;
+
     /// Get the user ID associated with a password reset token.
     async fn get_user_id_for_password_reset_token(&self, token: &str) -> Result<UserId>;
 
     async fn delete_password_reset_token(&self, token: &str) -> Result<()>;
+
+    /// Request a token to verify the new email address of the user, valid for `validity`. It
+    /// replaces the previous pending change, if any.
+    async fn start_email_change(
+        &self,
+        user: &UserId,
+        new_email: &str,
+        validity: chrono::Duration,
+    ) -> Result<String>;
+
+    /// The change of email address waiting for verification, if it hasn't expired.
+    async fn get_pending_email_change(&self, user: &UserId) -> Result<Option<PendingEmailChange>>;
+
+    async fn cancel_email_change(&self, user: &UserId) -> Result<()>;
+
+    /// Consumes an email verification token, returning the user and their new email address.
+    async fn finish_email_change(&self, token: &str) -> Result<(UserId, String)>;
+
+    /// Reads the schema version, which also checks that the database is reachable.
+    async fn get_schema_version(&self) -> Result<Option<SchemaVersion>>;
 }
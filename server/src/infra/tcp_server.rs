@@ -2,26 +2,52 @@ use crate::{
     domain::{
         error::DomainError,
         handler::{BackendHandler, LoginHandler},
+        ldap::utils::{parse_distinguished_name, serialize_distinguished_name},
         opaque_handler::OpaqueHandler,
     },
     infra::{
-        access_control::{AccessControlledBackendHandler, ReadonlyBackendHandler},
+        access_control::{
+            AccessControlledBackendHandler, ReadonlyBackendHandler, UserWriteableBackendHandler,
+        },
         auth_service,
-        configuration::{Configuration, MailOptions},
+        avatar_source::{AvatarImporter, AvatarSource, SourceAvatarImporter},
+        avatars, build_info,
+        configuration::{Configuration, CorsOptions, LoginIdentifier, MailOptions, SessionOptions},
+        cors::Cors,
+        email_change::{EmailChangeVerifier, MailEmailChangeVerifier},
+        graphql::api::{AvatarLimits, GraphQLIntrospection},
+        group_members,
+        health::{self, HealthState, SmtpStatus},
+        invitation::{InvitationSender, MailInvitationSender},
         logging::CustomRootSpanBuilder,
+        mail_queue::MailQueue,
+        mail_templates::MailTemplates,
+        metrics::{self, Metrics},
+        replication::{self, ReplicationStatus, SnapshotSource},
+        request_id::RequestIdentifier,
+        security_status::{ConfigurationSecurityChecker, SecurityChecker},
+        systemd::ActivatedSockets,
         tcp_backend_handler::*,
+        tls,
     },
 };
 use actix_files::Files;
 use actix_http::{header, HttpServiceBuilder};
 use actix_server::ServerBuilder;
 use actix_service::map_config;
-use actix_web::{dev::AppConfig, guard, web, App, HttpResponse, Responder};
+use actix_web::{
+    dev::AppConfig,
+    guard,
+    middleware::{Compress, DefaultHeaders},
+    web, App, HttpRequest, HttpResponse, Responder,
+};
 use anyhow::{Context, Result};
 use hmac::Hmac;
+use lldap_auth::password_policy::PasswordPolicy;
 use sha2::Sha512;
 use std::collections::HashSet;
-use std::sync::RwLock;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use tracing::info;
 
 async fn index<Backend>(data: web::Data<AppState<Backend>>) -> actix_web::Result<impl Responder> {
@@ -36,7 +62,8 @@ async fn index<Backend>(data: web::Data<AppState<Backend>>) -> actix_web::Result
 
     Ok(file
         .customize()
-        .insert_header((header::CONTENT_TYPE, "text/html; charset=utf-8")))
+        .insert_header((header::CONTENT_TYPE, "text/html; charset=utf-8"))
+        .insert_header((header::CACHE_CONTROL, "no-cache")))
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -67,7 +94,17 @@ pub(crate) fn error_to_http_response(error: TcpError) -> HttpResponse {
             | DomainError::UnknownCryptoError(_) => HttpResponse::InternalServerError(),
             DomainError::Base64DecodeError(_)
             | DomainError::BinarySerializationError(_)
-            | DomainError::EntityNotFound(_) => HttpResponse::BadRequest(),
+            | DomainError::ValidationError(_)
+            | DomainError::EmailAlreadyInUse(_)
+            | DomainError::EntityNotFound(_)
+            | DomainError::LastAdmin(_)
+            | DomainError::LastGroupMember(..) => HttpResponse::BadRequest(),
+            DomainError::ReadOnlyReplica => HttpResponse::Forbidden(),
+            DomainError::ServerBusy(_) => {
+                let mut response = HttpResponse::ServiceUnavailable();
+                response.insert_header((header::RETRY_AFTER, "1"));
+                response
+            }
         },
         TcpError::BadRequest(_) => HttpResponse::BadRequest(),
         TcpError::NotFoundError(_) => HttpResponse::NotFound(),
@@ -77,6 +114,11 @@ pub(crate) fn error_to_http_response(error: TcpError) -> HttpResponse {
     .body(error.to_string())
 }
 
+/// `/api/password_policy`, without authentication: the password reset form needs it too.
+async fn password_policy_handler<Backend>(data: web::Data<AppState<Backend>>) -> HttpResponse {
+    HttpResponse::Ok().json(&data.password_policy)
+}
+
 async fn main_js_handler<Backend>(
     data: web::Data<AppState<Backend>>,
 ) -> actix_web::Result<impl Responder> {
@@ -88,82 +130,209 @@ async fn main_js_handler<Backend>(
 
     Ok(file
         .customize()
-        .insert_header((header::CONTENT_TYPE, "text/javascript")))
+        .insert_header((header::CONTENT_TYPE, "text/javascript"))
+        .insert_header((header::CACHE_CONTROL, "no-cache")))
 }
 
-async fn wasm_handler() -> actix_web::Result<impl Responder> {
-    Ok(actix_files::NamedFile::open_async("./app/pkg/lldap_app_bg.wasm").await?)
+/// Where to redirect a plain HTTP request when HTTPS has its own port: same host and path.
+fn https_location(host: &str, https_port: u16, path_and_query: &str) -> String {
+    // Strip the port, if any, but not the end of an IPv6 address.
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.ends_with(':') && port.bytes().all(|b| b.is_ascii_digit()) => {
+            name
+        }
+        _ => host,
+    };
+    format!("https://{}:{}{}", host, https_port, path_and_query)
 }
 
-async fn wasm_handler_compressed() -> actix_web::Result<impl Responder> {
-    Ok(
-        actix_files::NamedFile::open_async("./app/pkg/lldap_app_bg.wasm.gz")
-            .await?
-            .customize()
-            .insert_header(header::ContentEncoding::Gzip)
-            .insert_header((header::CONTENT_TYPE, "application/wasm")),
+async fn redirect_to_https(request: HttpRequest, https_port: web::Data<u16>) -> HttpResponse {
+    let location = https_location(
+        request.connection_info().host(),
+        **https_port,
+        request
+            .uri()
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/"),
+    );
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+async fn wasm_handler_compressed(path: PathBuf) -> actix_web::Result<impl Responder> {
+    Ok(actix_files::NamedFile::open_async(path)
+        .await?
+        .customize()
+        .insert_header(header::ContentEncoding::Gzip)
+        .insert_header((header::CONTENT_TYPE, "application/wasm")))
+}
+
+/// The compiled app and the static files. Their names don't change between versions, so they
+/// are revalidated with their ETag on every load, and they are compressed if the client supports
+/// it (except for the pre-compressed WASM file).
+fn static_files_config(cfg: &mut web::ServiceConfig, path_prefix: &str, app_dir: &Path) {
+    let cache_control = || DefaultHeaders::new().add((header::CACHE_CONTROL, "no-cache"));
+    let compressed_wasm = app_dir.join("pkg/lldap_app_bg.wasm.gz");
+    cfg.service(
+        web::scope(&format!("{}/pkg", path_prefix))
+            .wrap(Compress::default())
+            .wrap(cache_control())
+            .service(
+                web::resource("/lldap_app_bg.wasm.gz").route(
+                    web::route().to(move || wasm_handler_compressed(compressed_wasm.clone())),
+                ),
+            )
+            .service(Files::new("", app_dir.join("pkg"))),
     )
+    .service(
+        web::scope(&format!("{}/static", path_prefix))
+            .wrap(Compress::default())
+            .wrap(cache_control())
+            .service(Files::new("", app_dir.join("static"))),
+    );
 }
 
 fn http_config<Backend>(
     cfg: &mut web::ServiceConfig,
-    backend_handler: Backend,
-    jwt_secret: secstr::SecUtf8,
-    jwt_blacklist: HashSet<u64>,
-    server_url: url::Url,
-    mail_options: MailOptions,
+    app_state: AppState<Backend>,
+    path_prefix: &str,
+    cors: &CorsOptions,
+    health_state: web::Data<HealthState<Backend>>,
+    metrics: web::Data<Metrics>,
+    replication_source: Option<web::Data<SnapshotSource>>,
 ) where
     Backend: TcpBackendHandler + BackendHandler + LoginHandler + OpaqueHandler + Clone + 'static,
 {
-    let enable_password_reset = mail_options.enable_password_reset;
-    cfg.app_data(web::Data::new(AppState::<Backend> {
-        backend_handler: AccessControlledBackendHandler::new(backend_handler),
-        jwt_key: hmac::Mac::new_from_slice(jwt_secret.unsecure().as_bytes()).unwrap(),
-        jwt_blacklist: RwLock::new(jwt_blacklist),
-        server_url,
-        mail_options,
-    }))
-    .route(
-        "/health",
-        web::get().to(|| async { HttpResponse::Ok().finish() }),
-    )
-    .service(
-        web::scope("/auth")
-            .configure(|cfg| auth_service::configure_server::<Backend>(cfg, enable_password_reset)),
-    )
-    // API endpoint.
-    .service(
-        web::scope("/api")
-            .wrap(auth_service::CookieToHeaderTranslatorFactory)
-            .configure(super::graphql::api::configure_endpoint::<Backend>),
-    )
-    .service(
-        web::resource("/pkg/lldap_app_bg.wasm.gz").route(web::route().to(wasm_handler_compressed)),
-    )
-    .service(web::resource("/pkg/lldap_app_bg.wasm").route(web::route().to(wasm_handler)))
-    .service(web::resource("/static/main.js").route(web::route().to(main_js_handler::<Backend>)))
-    // Serve the /pkg path with the compiled WASM app.
-    .service(Files::new("/pkg", "./app/pkg"))
-    // Serve static files
-    .service(Files::new("/static", "./app/static"))
-    // Serve static fonts
-    .service(Files::new("/static/fonts", "./app/static/fonts"))
-    // Default to serve index.html for unknown routes, to support routing.
-    .default_service(web::route().guard(guard::Get()).to(index::<Backend>));
+    let enable_password_reset = app_state.mail_options.enable_password_reset;
+    let max_request_size = app_state.avatar_limits.max_request_size();
+    let enable_playground = app_state.graphql_playground_enabled;
+    let path = |path: &str| format!("{}{}", path_prefix, path);
+    cfg.app_data(web::Data::new(app_state))
+        // The health endpoints are for the orchestrators, not behind the reverse proxy.
+        .configure(|cfg| health::configure(cfg, health_state))
+        .configure(|cfg| metrics::configure(cfg, metrics))
+        .service(
+            web::scope(&path("/auth"))
+                .wrap(Cors::new(cors.clone()))
+                .configure(|cfg| {
+                    auth_service::configure_server::<Backend>(cfg, enable_password_reset)
+                }),
+        )
+        // API endpoint.
+        .service(
+            web::scope(&path("/api"))
+                .wrap(auth_service::CookieToHeaderTranslatorFactory)
+                .wrap(Cors::new(cors.clone()))
+                .route("/version", web::get().to(build_info::version_handler))
+                .route(
+                    "/password_policy",
+                    web::get().to(password_policy_handler::<Backend>),
+                )
+                .configure(group_members::configure::<Backend>)
+                .configure(|cfg| {
+                    super::graphql::api::configure_endpoint::<Backend>(
+                        cfg,
+                        max_request_size,
+                        enable_playground,
+                    )
+                })
+                .configure(|cfg| {
+                    if let Some(source) = replication_source {
+                        replication::configure(cfg, source)
+                    }
+                }),
+        )
+        .service(
+            web::scope(&path("/avatars"))
+                .wrap(auth_service::CookieToHeaderTranslatorFactory)
+                .configure(avatars::configure::<Backend>),
+        )
+        .service(
+            web::resource(path("/static/main.js"))
+                .route(web::route().to(main_js_handler::<Backend>)),
+        )
+        // Serve the compiled WASM app and the static files.
+        .configure(|cfg| static_files_config(cfg, path_prefix, Path::new("./app")))
+        // Default to serve index.html for unknown routes, to support routing.
+        .default_service(web::route().guard(guard::Get()).to(index::<Backend>));
 }
 
 pub(crate) struct AppState<Backend> {
     pub backend_handler: AccessControlledBackendHandler<Backend>,
     pub jwt_key: Hmac<Sha512>,
+    /// From `jwt_secret_previous`, only to verify the tokens.
+    pub previous_jwt_key: Option<Hmac<Sha512>>,
     pub jwt_blacklist: RwLock<HashSet<u64>>,
     pub server_url: url::Url,
     pub mail_options: MailOptions,
+    pub mail_templates: Arc<MailTemplates>,
+    pub mail_queue: MailQueue,
+    /// Only available if the emails are enabled.
+    pub invitation_sender: Option<Arc<dyn InvitationSender>>,
+    /// Only available if the password reset is enabled: otherwise the email changes apply
+    /// directly.
+    pub email_change_verifier: Option<Arc<dyn EmailChangeVerifier>>,
+    /// Whether the cookies should only be sent over HTTPS.
+    pub secure_cookies: bool,
+    pub password_policy: PasswordPolicy,
+    pub session_options: SessionOptions,
+    pub avatar_limits: AvatarLimits,
+    /// None without `avatar_source_url_template`.
+    pub avatar_importer: Option<Arc<dyn AvatarImporter>>,
+    /// Lowercase, like the DNs served over LDAP.
+    pub ldap_base_dn: String,
+    pub security_checker: Arc<dyn SecurityChecker>,
+    /// Serve the GraphQL playground and GraphiQL pages.
+    pub graphql_playground_enabled: bool,
+    pub graphql_introspection: GraphQLIntrospection,
+    pub login_identifier: LoginIdentifier,
 }
 
 impl<Backend: BackendHandler> AppState<Backend> {
+    #[cfg(test)]
+    pub fn new_for_tests(handler: Backend) -> Self {
+        Self {
+            backend_handler: AccessControlledBackendHandler::new(handler),
+            jwt_key: hmac::Mac::new_from_slice(b"secret").unwrap(),
+            previous_jwt_key: None,
+            jwt_blacklist: RwLock::new(HashSet::new()),
+            server_url: url::Url::parse("http://localhost").unwrap(),
+            mail_options: MailOptions::default(),
+            mail_templates: Arc::new(MailTemplates::new(None, None, "en").unwrap()),
+            mail_queue: MailQueue::start(
+                Arc::new(crate::infra::mail::SmtpMailTransport::new(
+                    MailOptions::default(),
+                )),
+                &MailOptions::default(),
+            )
+            .0,
+            invitation_sender: None,
+            email_change_verifier: None,
+            secure_cookies: false,
+            password_policy: PasswordPolicy::default(),
+            session_options: SessionOptions::default(),
+            avatar_limits: AvatarLimits {
+                max_size_kb: 1024,
+                max_dimension: 512,
+            },
+            avatar_importer: None,
+            ldap_base_dn: "dc=example,dc=com".to_owned(),
+            security_checker: Arc::new(Vec::new()),
+            graphql_playground_enabled: true,
+            graphql_introspection: GraphQLIntrospection::Enabled,
+            login_identifier: LoginIdentifier::UserId,
+        }
+    }
+
     pub fn get_readonly_handler(&self) -> &impl ReadonlyBackendHandler {
         self.backend_handler.unsafe_get_handler()
     }
+    /// Only for the changes authorized by a link sent by email, without a session.
+    pub fn get_user_writeable_handler(&self) -> &impl UserWriteableBackendHandler {
+        self.backend_handler.unsafe_get_handler()
+    }
 }
 impl<Backend: TcpBackendHandler> AppState<Backend> {
     pub fn get_tcp_handler(&self) -> &impl TcpBackendHandler {
@@ -185,55 +354,346 @@ pub async fn build_tcp_server<Backend>(
     config: &Configuration,
     backend_handler: Backend,
     server_builder: ServerBuilder,
+    sockets: &mut ActivatedSockets,
+    mail_queue: MailQueue,
+    smtp_status: SmtpStatus,
+    metrics: Metrics,
+    replication_source: Option<SnapshotSource>,
+    replication_status: Option<ReplicationStatus>,
 ) -> Result<ServerBuilder>
 where
     Backend: TcpBackendHandler + BackendHandler + LoginHandler + OpaqueHandler + Clone + 'static,
 {
     let jwt_secret = config.jwt_secret.clone();
+    let jwt_secret_previous = config.jwt_secret_previous.clone();
     let jwt_blacklist = backend_handler
         .get_jwt_blacklist()
         .await
         .context("while getting the jwt blacklist")?;
-    let server_url = config.http_url.clone();
+    // The LDAP server listens on all the interfaces by default, including the loopback one.
+    let ldap_host = match config.ldap_host.as_str() {
+        "0.0.0.0" | "::" => "localhost".to_owned(),
+        host => host.to_owned(),
+    };
+    let health_state = web::Data::new(
+        HealthState::new(
+            backend_handler.clone(),
+            (ldap_host, config.ldap_port),
+            smtp_status,
+        )
+        .with_replication(replication_status),
+    );
+    let metrics = web::Data::new(metrics);
+    let replication_source = replication_source.map(web::Data::new);
+    let server_url = config.public_url();
+    let path_prefix = config.path_prefix();
+    let cors = config.cors.clone();
     let mail_options = config.smtp_options.clone();
+    let mail_templates = Arc::new(
+        MailTemplates::new(
+            mail_options.templates_dir.as_deref(),
+            mail_options.logo_file.as_deref(),
+            &mail_options.default_language,
+        )
+        .context("while loading the email templates")?,
+    );
+    // Invitations go through the password reset page, so they need it.
+    let invitation_sender = mail_options.enable_password_reset.then(|| {
+        Arc::new(MailInvitationSender {
+            backend_handler: backend_handler.clone(),
+            server_url: server_url.clone(),
+            mail_options: mail_options.clone(),
+            mail_templates: mail_templates.clone(),
+            mail_queue: mail_queue.clone(),
+        }) as Arc<dyn InvitationSender>
+    });
+    let email_change_verifier = mail_options.enable_password_reset.then(|| {
+        Arc::new(MailEmailChangeVerifier {
+            backend_handler: backend_handler.clone(),
+            server_url: server_url.clone(),
+            mail_options: mail_options.clone(),
+            mail_templates: mail_templates.clone(),
+            mail_queue: mail_queue.clone(),
+        }) as Arc<dyn EmailChangeVerifier>
+    });
+    let avatar_importer = AvatarSource::from_config(config)?.map(|source| {
+        Arc::new(SourceAvatarImporter {
+            backend_handler: backend_handler.clone(),
+            source,
+        }) as Arc<dyn AvatarImporter>
+    });
+    let security_checker: Arc<dyn SecurityChecker> = Arc::new(ConfigurationSecurityChecker::new(
+        config,
+        backend_handler.clone(),
+    ));
+    let backend_handler = AccessControlledBackendHandler::new(backend_handler)
+        .with_attribute_visibility(config.attribute_visibility.clone());
     let verbose = config.verbose;
-    info!("Starting the API/web server on port {}", config.http_port);
-    server_builder
+    let access_log = config.http_access_log;
+    let trusted_proxies = config.trusted_proxies.clone();
+    let tls_options = &config.http_tls;
+    let secure_cookies = tls_options.enabled;
+    let password_policy = PasswordPolicy::from(&config.password_policy);
+    let session_options = config.session.clone();
+    let avatar_limits = AvatarLimits::from_config(config);
+    let graphql_playground_enabled = config.graphql_playground_enabled;
+    let graphql_introspection = config.graphql_introspection_enabled;
+    let login_identifier = config.login_identifier;
+    // The same form as the DNs served over LDAP.
+    let ldap_base_dn = parse_distinguished_name(&config.ldap_base_dn)
+        .map(|base_dn| serialize_distinguished_name(&base_dn))
+        .unwrap_or_else(|_| config.ldap_base_dn.to_ascii_lowercase());
+    let make_app = move || {
+        let app_state = AppState::<Backend> {
+            backend_handler: backend_handler.clone(),
+            jwt_key: hmac::Mac::new_from_slice(jwt_secret.unsecure().as_bytes()).unwrap(),
+            previous_jwt_key: jwt_secret_previous
+                .as_ref()
+                .map(|secret| hmac::Mac::new_from_slice(secret.unsecure().as_bytes()).unwrap()),
+            jwt_blacklist: RwLock::new(jwt_blacklist.clone()),
+            server_url: server_url.clone(),
+            mail_options: mail_options.clone(),
+            mail_templates: mail_templates.clone(),
+            mail_queue: mail_queue.clone(),
+            invitation_sender: invitation_sender.clone(),
+            email_change_verifier: email_change_verifier.clone(),
+            secure_cookies,
+            password_policy: password_policy.clone(),
+            session_options: session_options.clone(),
+            avatar_limits,
+            avatar_importer: avatar_importer.clone(),
+            ldap_base_dn: ldap_base_dn.clone(),
+            security_checker: security_checker.clone(),
+            graphql_playground_enabled,
+            graphql_introspection,
+            login_identifier,
+        };
+        let path_prefix = path_prefix.clone();
+        let cors = cors.clone();
+        let health_state = health_state.clone();
+        let metrics = metrics.clone();
+        let replication_source = replication_source.clone();
+        map_config(
+            App::new()
+                .wrap(actix_web::middleware::Condition::new(
+                    verbose,
+                    tracing_actix_web::TracingLogger::<CustomRootSpanBuilder>::new(),
+                ))
+                // Outermost, so that the request ID is available to the tracing span.
+                .wrap(RequestIdentifier::new(access_log, trusted_proxies.clone()))
+                .configure(move |cfg| {
+                    http_config(
+                        cfg,
+                        app_state,
+                        &path_prefix,
+                        &cors,
+                        health_state,
+                        metrics,
+                        replication_source,
+                    )
+                }),
+            |_| AppConfig::default(),
+        )
+    };
+    let bind_error = |port: u16| format!("While bringing up the TCP server with port {}", port);
+    if !tls_options.enabled {
+        info!("Starting the API/web server on port {}", config.http_port);
+        return sockets
+            .bind(
+                server_builder,
+                "http",
+                (config.http_host.clone(), config.http_port),
+                move || HttpServiceBuilder::default().finish(make_app()).tcp(),
+            )
+            .with_context(|| bind_error(config.http_port));
+    }
+    let tls_config = tls::get_server_config(&tls_options.cert_file, &tls_options.key_file)
+        .context("while setting up the HTTPS certificate")?;
+    let https_port = tls_options.https_port.unwrap_or(config.http_port);
+    let https_service = {
+        let make_app = make_app.clone();
+        move || {
+            HttpServiceBuilder::default()
+                .finish(make_app())
+                .rustls(tls_config.clone())
+        }
+    };
+    info!("Starting the HTTPS API/web server on port {}", https_port);
+    let server_builder = sockets
         .bind(
-            "http",
-            (config.http_host.clone(), config.http_port),
-            move || {
-                let backend_handler = backend_handler.clone();
-                let jwt_secret = jwt_secret.clone();
-                let jwt_blacklist = jwt_blacklist.clone();
-                let server_url = server_url.clone();
-                let mail_options = mail_options.clone();
-                HttpServiceBuilder::default()
-                    .finish(map_config(
-                        App::new()
-                            .wrap(actix_web::middleware::Condition::new(
-                                verbose,
-                                tracing_actix_web::TracingLogger::<CustomRootSpanBuilder>::new(),
-                            ))
-                            .configure(move |cfg| {
-                                http_config(
-                                    cfg,
-                                    backend_handler,
-                                    jwt_secret,
-                                    jwt_blacklist,
-                                    server_url,
-                                    mail_options,
-                                )
-                            }),
-                        |_| AppConfig::default(),
-                    ))
-                    .tcp()
-            },
+            server_builder,
+            "https",
+            (config.http_host.clone(), https_port),
+            https_service,
         )
-        .with_context(|| {
-            format!(
-                "While bringing up the TCP server with port {}",
-                config.http_port
+        .with_context(|| bind_error(https_port))?;
+    if tls_options.https_port.is_none() {
+        Ok(server_builder)
+    } else if tls_options.redirect_http {
+        info!("Redirecting the HTTP port {} to HTTPS", config.http_port);
+        sockets
+            .bind(
+                server_builder,
+                "http",
+                (config.http_host.clone(), config.http_port),
+                move || {
+                    HttpServiceBuilder::default()
+                        .finish(map_config(
+                            App::new()
+                                .app_data(web::Data::new(https_port))
+                                .default_service(web::to(redirect_to_https)),
+                            |_| AppConfig::default(),
+                        ))
+                        .tcp()
+                },
+            )
+            .with_context(|| bind_error(config.http_port))
+    } else {
+        info!("Starting the API/web server on port {}", config.http_port);
+        sockets
+            .bind(
+                server_builder,
+                "http",
+                (config.http_host.clone(), config.http_port),
+                move || HttpServiceBuilder::default().finish(make_app()).tcp(),
             )
-        })
+            .with_context(|| bind_error(config.http_port))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sql_backend_handler::{tests::*, SqlBackendHandler};
+    use actix_web::{http::StatusCode, test};
+    use pretty_assertions::assert_eq;
+
+    async fn post_status(path_prefix: &str, path: &str) -> StatusCode {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        let health_state = web::Data::new(HealthState::new(
+            handler.clone(),
+            ("localhost".to_owned(), 3890),
+            SmtpStatus::default(),
+        ));
+        let app_state = AppState::new_for_tests(handler);
+        let path_prefix = path_prefix.to_owned();
+        let app = test::init_service(App::new().configure(move |cfg| {
+            http_config(
+                cfg,
+                app_state,
+                &path_prefix,
+                &CorsOptions::default(),
+                health_state,
+                web::Data::new(Metrics::default()),
+                None,
+            )
+        }))
+        .await;
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(path)
+                .set_json(serde_json::json!({}))
+                .to_request(),
+        )
+        .await
+        .status()
+    }
+
+    #[actix_web::test]
+    async fn test_static_files_caching_and_compression() {
+        let app_dir = std::env::temp_dir().join(format!("lldap-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(app_dir.join("pkg")).unwrap();
+        std::fs::create_dir_all(app_dir.join("static")).unwrap();
+        std::fs::write(app_dir.join("pkg/lldap_app_bg.wasm"), vec![0u8; 4096]).unwrap();
+        std::fs::write(app_dir.join("static/style.css"), "body { margin: 0; }").unwrap();
+        let app = {
+            let app_dir = app_dir.clone();
+            test::init_service(
+                App::new().configure(move |cfg| static_files_config(cfg, "/lldap", &app_dir)),
+            )
+            .await
+        };
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/lldap/pkg/lldap_app_bg.wasm")
+                .insert_header((header::ACCEPT_ENCODING, "br"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers.get(header::CONTENT_ENCODING).unwrap(), "br");
+        assert_eq!(
+            headers.get(header::CONTENT_TYPE).unwrap(),
+            "application/wasm"
+        );
+        assert_eq!(headers.get(header::CACHE_CONTROL).unwrap(), "no-cache");
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/lldap/static/style.css")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers().get(header::ETAG).unwrap().clone();
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/lldap/static/style.css")
+                .insert_header((header::IF_NONE_MATCH, etag))
+                .to_request(),
+        )
+        .await;
+        std::fs::remove_dir_all(&app_dir).unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[actix_web::test]
+    async fn test_routes_without_path_prefix() {
+        // The request is invalid, but it reached the handler.
+        assert_eq!(
+            post_status("", "/auth/simple/login").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            post_status("", "/api/graphql").await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_routes_with_path_prefix() {
+        assert_eq!(
+            post_status("/lldap", "/lldap/auth/simple/login").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            post_status("/lldap", "/lldap/api/graphql").await,
+            StatusCode::UNAUTHORIZED
+        );
+        // Falls back to the index page, which doesn't exist in the tests.
+        assert_eq!(
+            post_status("/lldap", "/auth/simple/login").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_https_location() {
+        assert_eq!(
+            https_location("lldap.example.com:17170", 17171, "/login?next=/users"),
+            "https://lldap.example.com:17171/login?next=/users"
+        );
+        assert_eq!(https_location("lldap", 443, "/"), "https://lldap:443/");
+        assert_eq!(
+            https_location("[::1]:17170", 17171, "/"),
+            "https://[::1]:17171/"
+        );
+        assert_eq!(https_location("[::1]", 17171, "/"), "https://[::1]:17171/");
+    }
 }
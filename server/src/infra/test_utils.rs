@@ -1,7 +1,7 @@
 use crate::domain::{error::Result, handler::*, opaque_handler::*, types::*};
 
 use async_trait::async_trait;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 mockall::mock! {
     pub TestBackendHandler{}
@@ -10,7 +10,8 @@ mockall::mock! {
     }
     #[async_trait]
     impl LoginHandler for TestBackendHandler {
-        async fn bind(&self, request: BindRequest) -> Result<()>;
+        async fn bind(&self, request: BindRequest) -> Result<UserId>;
+        async fn check_password(&self, request: BindRequest) -> Result<bool>;
     }
     #[async_trait]
     impl GroupListerBackendHandler for TestBackendHandler {
@@ -26,16 +27,23 @@ mockall::mock! {
     #[async_trait]
     impl UserListerBackendHandler for TestBackendHandler {
         async fn list_users(&self, filters: Option<UserRequestFilter>, get_groups: bool) -> Result<Vec<UserAndGroups>>;
+        async fn list_deleted_users(&self) -> Result<Vec<DeletedUser>>;
+        async fn get_user_photos(&self, user_ids: &[UserId]) -> Result<HashMap<UserId, Vec<AttributeValue>>>;
     }
     #[async_trait]
     impl UserBackendHandler for TestBackendHandler {
         async fn get_user_details(&self, user_id: &UserId) -> Result<User>;
-        async fn create_user(&self, request: CreateUserRequest) -> Result<()>;
+        async fn create_user(&self, request: CreateUserRequest) -> Result<UserId>;
         async fn update_user(&self, request: UpdateUserRequest) -> Result<()>;
         async fn delete_user(&self, user_id: &UserId) -> Result<()>;
+        async fn restore_user(&self, user_id: &UserId) -> Result<()>;
+        async fn permanently_delete_user(&self, user_id: &UserId) -> Result<()>;
+        async fn delete_user_with_policy(&self, request: DeleteUserRequest) -> Result<Vec<EmptiedGroup>>;
+        async fn get_groups_emptied_by_deletion(&self, user_id: &UserId) -> Result<Vec<GroupDetails>>;
         async fn get_user_groups(&self, user_id: &UserId) -> Result<HashSet<GroupDetails>>;
-        async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
-        async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
+        async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<bool>;
+        async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<bool>;
+        async fn get_user_avatar_hash(&self, user_id: &UserId) -> Result<Option<String>>;
     }
     #[async_trait]
     impl ReadSchemaBackendHandler for TestBackendHandler {
@@ -0,0 +1,95 @@
+//! Certificates and keys for the TLS listeners (LDAPS and HTTPS).
+
+use anyhow::{anyhow, Context, Result};
+use rustls::{Certificate, PrivateKey};
+use std::{fs::File, io::BufReader};
+
+fn read_private_key(key_file: &str) -> Result<PrivateKey> {
+    use rustls_pemfile::{ec_private_keys, pkcs8_private_keys, rsa_private_keys};
+    pkcs8_private_keys(&mut BufReader::new(File::open(key_file)?))
+        .map_err(anyhow::Error::from)
+        .and_then(|keys| {
+            keys.into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("No PKCS8 key"))
+        })
+        .or_else(|_| {
+            rsa_private_keys(&mut BufReader::new(File::open(key_file)?))
+                .map_err(anyhow::Error::from)
+                .and_then(|keys| {
+                    keys.into_iter()
+                        .next()
+                        .ok_or_else(|| anyhow!("No PKCS1 key"))
+                })
+        })
+        .or_else(|_| {
+            ec_private_keys(&mut BufReader::new(File::open(key_file)?))
+                .map_err(anyhow::Error::from)
+                .and_then(|keys| keys.into_iter().next().ok_or_else(|| anyhow!("No EC key")))
+        })
+        .with_context(|| {
+            format!(
+                "Cannot read either PKCS1, PKCS8 or EC private key from {}",
+                key_file
+            )
+        })
+        .map(PrivateKey)
+}
+
+fn read_certificate_chain(cert_file: &str) -> Result<Vec<Certificate>> {
+    let certs = File::open(cert_file)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| Ok(rustls_pemfile::certs(&mut BufReader::new(file))?))
+        .with_context(|| format!("Cannot read the certificates from {}", cert_file))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No PEM certificate found in {}", cert_file));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+pub fn read_certificates(
+    cert_file: &str,
+    key_file: &str,
+) -> Result<(Vec<Certificate>, PrivateKey)> {
+    Ok((
+        read_certificate_chain(cert_file)?,
+        read_private_key(key_file)?,
+    ))
+}
+
+pub fn get_server_config(cert_file: &str, key_file: &str) -> Result<rustls::ServerConfig> {
+    let (certs, private_key) = read_certificates(cert_file, key_file)?;
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, private_key)
+        .with_context(|| {
+            format!(
+                "The private key in {} doesn't match the certificate in {}",
+                key_file, cert_file
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_name_the_file() {
+        let file = std::env::temp_dir().join(format!("lldap-test-{}.pem", uuid::Uuid::new_v4()));
+        let cert_file = file.to_str().unwrap();
+        let missing = read_certificates(cert_file, "key.pem").unwrap_err();
+        assert!(format!("{:#}", missing).starts_with(&format!(
+            "Cannot read the certificates from {}: ",
+            cert_file
+        )));
+        std::fs::write(&file, "not a certificate").unwrap();
+        let invalid = read_certificates(cert_file, "key.pem").unwrap_err();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(
+            invalid.to_string(),
+            format!("No PEM certificate found in {}", cert_file)
+        );
+    }
+}
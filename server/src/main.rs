@@ -3,7 +3,7 @@
 // TODO: Remove next line after upgrade to 1.77
 #![allow(clippy::blocks_in_conditions)]
 
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use crate::{
     domain::{
@@ -12,22 +12,39 @@ use crate::{
             GroupRequestFilter, UserBackendHandler, UserListerBackendHandler, UserRequestFilter,
         },
         sql_backend_handler::SqlBackendHandler,
+        sql_migrations::MigrationOptions,
         sql_opaque_handler::register_password,
         sql_tables::{get_private_key_info, set_private_key_info},
+        types::UserId,
     },
     infra::{
+        account_expiration::AccountExpiration,
+        avatar_source::{AvatarImporter, AvatarSource, SourceAvatarImporter},
+        change_plan::{Plan, Summary},
         cli::*,
         configuration::{compare_private_key_hashes, Configuration},
-        database_string::DatabaseUrl,
-        db_cleaner::Scheduler,
-        healthcheck, mail,
+        db_cleaner::{self, PruneStats, PruneTarget, Scheduler},
+        health::SmtpStatus,
+        healthcheck,
+        invitation::{InvitationSender, MailInvitationSender},
+        mail::{self, SmtpMailTransport},
+        mail_queue::MailQueue,
+        mail_templates::MailTemplates,
+        provisioning::{
+            check_new_password, AlreadyExists, DatabaseProvisioner, HttpProvisioner, NewUser,
+            Provisioner, ALREADY_EXISTS_EXIT_CODE,
+        },
+        replication::{ReplicationStatus, Replicator, SnapshotSource},
+        shutdown::ShutdownToken,
+        systemd::ActivatedSockets,
     },
 };
 use actix::Actor;
 use actix_server::ServerBuilder;
 use anyhow::{anyhow, bail, Context, Result};
 use futures_util::TryFutureExt;
-use sea_orm::{Database, DatabaseConnection};
+use sea_orm::DatabaseConnection;
+use strum::IntoEnumIterator;
 use tracing::*;
 
 mod domain;
@@ -40,26 +57,23 @@ async fn create_admin_user(handler: &SqlBackendHandler, config: &Configuration)
         "Minimum password length is 8 characters, got {} characters",
         pass_length
     );
+    let groups = handler
+        .list_groups(Some(GroupRequestFilter::DisplayName("lldap_admin".into())))
+        .await?;
+    assert_eq!(groups.len(), 1);
     handler
         .create_user(CreateUserRequest {
             user_id: config.ldap_user_dn.clone(),
             email: config.ldap_user_email.clone().into(),
             display_name: Some("Administrator".to_string()),
+            groups: vec![groups[0].id],
             ..Default::default()
         })
         .and_then(|_| {
             register_password(handler, config.ldap_user_dn.clone(), &config.ldap_user_pass)
         })
         .await
-        .context("Error creating admin user")?;
-    let groups = handler
-        .list_groups(Some(GroupRequestFilter::DisplayName("lldap_admin".into())))
-        .await?;
-    assert_eq!(groups.len(), 1);
-    handler
-        .add_user_to_group(&config.ldap_user_dn, groups[0].id)
-        .await
-        .context("Error adding admin user to group")
+        .context("Error creating admin user")
 }
 
 async fn ensure_group_exists(handler: &SqlBackendHandler, group_name: &str) -> Result<()> {
@@ -80,53 +94,77 @@ async fn ensure_group_exists(handler: &SqlBackendHandler, group_name: &str) -> R
     Ok(())
 }
 
-async fn setup_sql_tables(database_url: &DatabaseUrl) -> Result<DatabaseConnection> {
-    let sql_pool = {
-        let mut sql_opt = sea_orm::ConnectOptions::new(database_url.to_string());
-        sql_opt
-            .max_connections(5)
-            .sqlx_logging(true)
-            .sqlx_logging_level(log::LevelFilter::Debug);
-        Database::connect(sql_opt).await?
-    };
-    domain::sql_tables::init_table(&sql_pool)
+async fn connect_to_database(config: &Configuration) -> Result<DatabaseConnection> {
+    infra::db_connection::connect(
+        &config.database_url,
+        &config.database_options,
+        Duration::from_secs(config.database_startup_timeout_secs),
+    )
+    .await
+}
+
+async fn init_sql_tables(
+    sql_pool: &DatabaseConnection,
+    auto_migrate: Option<MigrationOptions>,
+    skip_db_checks: bool,
+) -> Result<()> {
+    domain::sql_tables::check_or_init_table(sql_pool, auto_migrate, skip_db_checks)
         .await
-        .context("while creating base tables")?;
-    infra::jwt_sql_tables::init_table(&sql_pool)
+        .context("while checking the database schema")?;
+    infra::jwt_sql_tables::init_table(sql_pool)
         .await
-        .context("while creating jwt tables")?;
-    Ok(sql_pool)
+        .context("while creating jwt tables")
 }
 
-#[instrument(skip_all)]
-async fn set_up_server(config: Configuration) -> Result<ServerBuilder> {
-    info!("Starting LLDAP version {}", env!("CARGO_PKG_VERSION"));
+async fn setup_sql_tables(
+    config: &Configuration,
+    auto_migrate: bool,
+) -> Result<DatabaseConnection> {
+    let sql_pool = connect_to_database(config).await?;
+    let auto_migrate = if auto_migrate {
+        Some(config.database_options.migration_options()?)
+    } else {
+        None
+    };
+    init_sql_tables(&sql_pool, auto_migrate, false).await?;
+    Ok(sql_pool)
+}
 
-    let sql_pool = setup_sql_tables(&config.database_url).await?;
-    let private_key_info = config.get_private_key_info();
-    let force_update_private_key = config.force_update_private_key;
-    match (
-        compare_private_key_hashes(
-            get_private_key_info(&sql_pool).await?.as_ref(),
-            &private_key_info,
-        ),
-        force_update_private_key,
-    ) {
-        (Ok(false), true) => {
-            bail!("The private key has not changed, but force_update_private_key/LLDAP_FORCE_UPDATE_PRIVATE_KEY is set to true. Please set force_update_private_key to false and restart the server.");
-        }
-        (Ok(true), _) | (Err(_), true) => {
-            set_private_key_info(&sql_pool, private_key_info).await?;
-        }
-        (Ok(false), false) => {}
-        (Err(e), false) => {
-            return Err(anyhow!("The private key encoding the passwords has changed since last successful startup. Changing the private key will invalidate all existing passwords. If you want to proceed, restart the server with the CLI arg --force-update-private-key=true or the env variable LLDAP_FORCE_UPDATE_PRIVATE_KEY=true. You probably also want --force-ldap-user-pass-reset / LLDAP_FORCE_LDAP_USER_PASS_RESET=true to reset the admin password to the value in the configuration.").context(e));
-        }
+/// Refuses to start if the schema has to be created or upgraded, with the command to do it.
+async fn check_no_pending_migration(
+    sql_pool: &DatabaseConnection,
+    config_file: &str,
+) -> Result<()> {
+    let migrate_command = format!(
+        "lldap migrate --config-file {} --database-url <URL of a user allowed to modify the schema>",
+        config_file
+    );
+    if domain::sql_tables::is_empty_database(sql_pool).await {
+        bail!(
+            "The database is empty, and run_migrations_on_startup is disabled. Create the schema with:\n  {}",
+            migrate_command
+        );
     }
-    let backend_handler = SqlBackendHandler::new(config.clone(), sql_pool.clone());
-    ensure_group_exists(&backend_handler, "lldap_admin").await?;
-    ensure_group_exists(&backend_handler, "lldap_password_manager").await?;
-    ensure_group_exists(&backend_handler, "lldap_strict_readonly").await?;
+    if let Some(version) = domain::sql_tables::get_pending_migration(sql_pool).await {
+        bail!(
+            "The database schema (version {}) needs to be upgraded to version {}, and run_migrations_on_startup is disabled. Back up the database, then upgrade it with:\n  {}",
+            version.0,
+            domain::sql_tables::LAST_SCHEMA_VERSION.0,
+            migrate_command
+        );
+    }
+    Ok(())
+}
+
+/// Creates the built-in groups and the admin user, and applies the bootstrap file.
+async fn set_up_directory(
+    backend_handler: &SqlBackendHandler,
+    config: &Configuration,
+    mail_queue: MailQueue,
+) -> Result<()> {
+    ensure_group_exists(backend_handler, "lldap_admin").await?;
+    ensure_group_exists(backend_handler, "lldap_password_manager").await?;
+    ensure_group_exists(backend_handler, "lldap_strict_readonly").await?;
     let admin_present = if let Ok(admins) = backend_handler
         .list_users(
             Some(UserRequestFilter::MemberOf("lldap_admin".into())),
@@ -140,14 +178,14 @@ async fn set_up_server(config: Configuration) -> Result<ServerBuilder> {
     };
     if !admin_present {
         warn!("Could not find an admin user, trying to create the user \"admin\" with the config-provided password");
-        create_admin_user(&backend_handler, &config)
+        create_admin_user(backend_handler, config)
             .await
             .map_err(|e| anyhow!("Error setting up admin login/account: {:#}", e))
             .context("while creating the admin user")?;
     } else if config.force_ldap_user_pass_reset {
         warn!("Forcing admin password reset to the config-provided password");
         register_password(
-            &backend_handler,
+            backend_handler,
             config.ldap_user_dn.clone(),
             &config.ldap_user_pass,
         )
@@ -157,74 +195,340 @@ async fn set_up_server(config: Configuration) -> Result<ServerBuilder> {
             &config.ldap_user_dn
         ))?;
     }
+    if let Some(file) = &config.bootstrap_file {
+        let bootstrap = infra::bootstrap::read_file(file)?;
+        let plan = infra::bootstrap::plan(
+            backend_handler,
+            &bootstrap,
+            config.bootstrap_prune,
+            &config.ldap_user_dn,
+        )
+        .await?;
+        let summary = apply_bootstrap_file(backend_handler, config, plan, mail_queue)
+            .await
+            .context("while applying the bootstrap_file")?;
+        info!("Bootstrap: {}", summary);
+    }
+    Ok(())
+}
+
+#[instrument(skip_all)]
+async fn set_up_server(
+    config: Configuration,
+    config_file: &str,
+    skip_db_checks: bool,
+    shutdown: ShutdownToken,
+    mail_queue: MailQueue,
+    smtp_status: SmtpStatus,
+) -> Result<ServerBuilder> {
+    info!("Starting LLDAP version {}", env!("CARGO_PKG_VERSION"));
+
+    let sql_pool = connect_to_database(&config).await?;
+    if config.is_server_key_from_existing_file()
+        && domain::sql_tables::is_empty_database(&sql_pool).await
+    {
+        warn!("The database is empty, but the server key file `{}` already exists: it may belong to another installation of LLDAP. The passwords stored in a database can only be checked with the key they were created with. If you meant to use the database of that installation, stop now and check the database_url setting: a new database, with a new admin user, is being created. If the previous start failed before creating the database, you can ignore this.", config.key_file);
+    }
+    if config.run_migrations_on_startup {
+        let migration_options = config.database_options.migration_options()?;
+        init_sql_tables(&sql_pool, Some(migration_options), skip_db_checks).await?;
+    } else {
+        // The schema is managed by `lldap migrate`, possibly with other credentials: only check it.
+        check_no_pending_migration(&sql_pool, config_file).await?;
+        domain::sql_tables::check_or_init_table(&sql_pool, None, skip_db_checks)
+            .await
+            .context("while checking the database schema")?;
+    }
+    let private_key_info = config.get_private_key_info();
+    let force_update_private_key = config.force_update_private_key;
+    match (
+        compare_private_key_hashes(
+            get_private_key_info(&sql_pool).await?.as_ref(),
+            &private_key_info,
+        ),
+        force_update_private_key,
+    ) {
+        (Ok(false), true) => {
+            bail!("The private key has not changed, but force_update_private_key/LLDAP_FORCE_UPDATE_PRIVATE_KEY is set to true. Please set force_update_private_key to false and restart the server.");
+        }
+        (Ok(true), _) | (Err(_), true) => {
+            set_private_key_info(&sql_pool, private_key_info).await?;
+        }
+        (Ok(false), false) => {}
+        (Err(e), false) => {
+            return Err(anyhow!("The private key encoding the passwords has changed since last successful startup. Changing the private key will invalidate all existing passwords. If you want to proceed, restart the server with the CLI arg --force-update-private-key=true or the env variable LLDAP_FORCE_UPDATE_PRIVATE_KEY=true. You probably also want --force-ldap-user-pass-reset / LLDAP_FORCE_LDAP_USER_PASS_RESET=true to reset the admin password to the value in the configuration.").context(e));
+        }
+    }
+    let mut backend_handler = SqlBackendHandler::new(config.clone(), sql_pool.clone());
+    if config.cache.enabled {
+        backend_handler = backend_handler.with_cache(&config.cache);
+    }
+    if config.replication.is_replica() {
+        if config.force_ldap_user_pass_reset {
+            bail!("The admin password of a replica can't be reset: reset it on the primary.");
+        }
+        // Everything, including the admin user, comes from the primary.
+        backend_handler = backend_handler.with_read_only();
+    } else {
+        set_up_directory(&backend_handler, &config, mail_queue.clone()).await?;
+    }
     if config.force_update_private_key || config.force_ldap_user_pass_reset {
         bail!("Restart the server without --force-update-private-key or --force-ldap-user-pass-reset to continue.");
     }
+    // Only after the setup above, which needs to read its own writes.
+    if let Some(database_read_url) = &config.database_read_url {
+        if let Some(read_pool) =
+            infra::db_connection::connect_read_replica(database_read_url, &config.database_options)
+                .await
+        {
+            backend_handler = backend_handler.with_read_replica(read_pool);
+        }
+    }
+    if config.in_memory_read_model {
+        backend_handler = backend_handler
+            .with_read_model()
+            .await
+            .context("while loading the in-memory read model")?;
+        let stats = backend_handler.read_model().unwrap().stats();
+        info!(
+            "In-memory read model loaded: {} users, {} groups, {} memberships, about {} KiB",
+            stats.users,
+            stats.groups,
+            stats.memberships,
+            stats.approximate_bytes / 1024
+        );
+    }
+    let mut sockets = ActivatedSockets::from_env();
     let server_builder = infra::ldap_server::build_ldap_server(
         &config,
         backend_handler.clone(),
         actix_server::Server::build(),
+        shutdown,
+        &mut sockets,
     )
     .context("while binding the LDAP server")?;
-    let server_builder =
-        infra::tcp_server::build_tcp_server(&config, backend_handler, server_builder)
-            .await
-            .context("while binding the TCP server")?;
-    // Run every hour.
-    let scheduler = Scheduler::new("0 0 * * * * *", sql_pool);
+    let replication_status = config
+        .replication
+        .primary_url
+        .as_ref()
+        .map(ReplicationStatus::new);
+    if let Some(status) = &replication_status {
+        Replicator::new(&config, backend_handler.clone(), status.clone())?
+            .start(Duration::from_secs(config.replication.sync_interval_secs));
+    }
+    // A primary serves its snapshot once a replication token is set.
+    let replication_source = match (&replication_status, &config.replication.token) {
+        (None, Some(token)) => Some(SnapshotSource::new(sql_pool.clone(), token.clone())),
+        _ => None,
+    };
+    let prune_stats = PruneStats::default();
+    let metrics = infra::metrics::Metrics::new(backend_handler.read_cache())
+        .with_backend_handler(&backend_handler)
+        .with_replication(replication_status.clone())
+        .with_prune_stats(prune_stats.clone());
+    metrics
+        .clone()
+        .start_sampling(infra::metrics::SAMPLE_INTERVAL);
+    // The replicas get the deletions from the primary.
+    let account_expiration = (!config.replication.is_replica())
+        .then(|| AccountExpiration::new(&config, backend_handler.clone(), mail_queue.clone()))
+        .transpose()?;
+    let server_builder = infra::tcp_server::build_tcp_server(
+        &config,
+        backend_handler,
+        server_builder,
+        &mut sockets,
+        mail_queue,
+        smtp_status,
+        metrics,
+        replication_source,
+        replication_status,
+    )
+    .await
+    .context("while binding the TCP server")?;
+    sockets.warn_unused();
+    let scheduler = Scheduler::new(&config, sql_pool, prune_stats);
     scheduler.start();
+    if let Some(account_expiration) = account_expiration {
+        account_expiration.start(infra::account_expiration::CHECK_INTERVAL);
+    }
     Ok(server_builder)
 }
 
 async fn run_server_command(opts: RunOpts) -> Result<()> {
     debug!("CLI: {:#?}", &opts);
 
+    let config_file = opts.general_config.config_file.clone();
+    let skip_db_checks = opts.skip_db_checks;
     let config = infra::configuration::init(opts)?;
     infra::logging::init(&config)?;
+    if config.smtp_options.enable_password_reset {
+        for warning in config.smtp_options.warnings() {
+            warn!("{}", warning);
+        }
+    }
 
-    let server = set_up_server(config).await?.workers(1);
+    let shutdown_timeout = config.shutdown_timeout_secs;
+    let shutdown = ShutdownToken::new();
+    // Built once: the SMTP connections are reused between the emails.
+    let smtp_transport = Arc::new(SmtpMailTransport::new(config.smtp_options.clone()));
+    let smtp_status = SmtpStatus::default();
+    if config.smtp_options.enable_password_reset && config.smtp_options.check_on_startup {
+        // In the background: a slow SMTP server doesn't delay the startup.
+        let (transport, status) = (smtp_transport.clone(), smtp_status.clone());
+        tokio::spawn(async move { mail::check_smtp_connection(&transport, &status).await });
+    }
+    let (mail_queue, mail_queue_worker) =
+        MailQueue::start(smtp_transport.clone(), &config.smtp_options);
+    let server = set_up_server(
+        config,
+        &config_file,
+        skip_db_checks,
+        shutdown.clone(),
+        mail_queue,
+        smtp_status,
+    )
+    .await?
+    .workers(1)
+    .shutdown_timeout(shutdown_timeout)
+    // The signals are handled below, to close the idle LDAP connections first.
+    .disable_signals()
+    .run();
+    infra::shutdown::handle_signals(server.handle(), shutdown);
+    // The listeners are bound and the database is up to date.
+    infra::systemd::notify_ready();
 
-    server.run().await.context("while starting the server")
+    server.await.context("while starting the server")?;
+    // The requests in progress may have queued emails until the end.
+    mail_queue_worker
+        .drain(Duration::from_secs(shutdown_timeout))
+        .await;
+    info!("Server stopped");
+    Ok(())
 }
 
 async fn send_test_email_command(opts: TestEmailOpts) -> Result<()> {
     let to = opts.to.parse()?;
-    let config = infra::configuration::init(opts)?;
+    let template = match opts.template {
+        TestEmailTemplate::Test => infra::mail_templates::TEST_EMAIL,
+        TestEmailTemplate::PasswordReset => infra::mail_templates::PASSWORD_RESET,
+        TestEmailTemplate::Invite => infra::mail_templates::INVITATION,
+        TestEmailTemplate::EmailChange => infra::mail_templates::EMAIL_CHANGE,
+        TestEmailTemplate::EmailChanged => infra::mail_templates::EMAIL_CHANGED,
+        TestEmailTemplate::AccountExpiring => infra::mail_templates::ACCOUNT_EXPIRING,
+        TestEmailTemplate::AccountExpiringAdmin => infra::mail_templates::ACCOUNT_EXPIRING_ADMIN,
+    };
+    let dry_run = opts.dry_run;
+    let language = opts.language.clone();
+    let smtp_password = opts.smtp_password_source().read()?;
+    let mut config = infra::configuration::init(opts)?;
     infra::logging::init(&config)?;
+    if let Some(password) = smtp_password {
+        config.smtp_options.password = password;
+    }
 
-    mail::send_test_email(to, &config.smtp_options)
+    let templates = infra::mail_templates::MailTemplates::new(
+        config.smtp_options.templates_dir.as_deref(),
+        config.smtp_options.logo_file.as_deref(),
+        &config.smtp_options.default_language,
+    )?;
+    let email = mail::render_example_email(
+        template,
+        language.as_deref(),
+        &config.public_url(),
+        &config.smtp_options,
+        &templates,
+    )?;
+    if dry_run {
+        println!("Subject: {}", email.subject);
+        println!("--- text ---\n{}", email.text);
+        println!("--- html ---\n{}", email.html);
+        if email.logo.is_some() {
+            println!("--- (with the logo inline) ---");
+        }
+        return Ok(());
+    }
+    for warning in config.smtp_options.warnings() {
+        warn!("{}", warning);
+    }
+    mail::send_test_email(to, email, &config.smtp_options)
         .await
-        .context("Could not send email: {:#}")
+        .context("Could not send the test email")
 }
 
-async fn run_healthcheck(opts: RunOpts) -> Result<()> {
+async fn run_healthcheck(opts: HealthCheckOpts) -> Result<()> {
     debug!("CLI: {:#?}", &opts);
-    let config = infra::configuration::init(opts)?;
+    let config = infra::configuration::init(opts.clone())?;
     infra::logging::init(&config)?;
 
     info!("Starting healthchecks");
 
     use tokio::time::timeout;
-    let delay = Duration::from_millis(3000);
+    // Each check has its own timeout, so that a hung server doesn't hang the probe.
+    let delay = Duration::from_millis(opts.timeout_ms);
+    let credentials = opts.ldap_bind.then(|| healthcheck::LdapCredentials {
+        dn: domain::ldap::utils::user_dn(&config.ldap_user_dn, &config.ldap_base_dn),
+        password: config.ldap_user_pass.unsecure().to_owned(),
+    });
+    let skip_ldap = opts.skip_ldap;
     let (ldap, ldaps, api) = tokio::join!(
-        timeout(delay, healthcheck::check_ldap(config.ldap_port)),
-        timeout(delay, healthcheck::check_ldaps(&config.ldaps_options)),
-        timeout(delay, healthcheck::check_api(config.http_port)),
+        timeout(delay, async {
+            if skip_ldap {
+                return Ok(());
+            }
+            healthcheck::check_ldap(config.ldap_port, credentials.as_ref()).await
+        }),
+        timeout(delay, async {
+            if skip_ldap {
+                return Ok(());
+            }
+            healthcheck::check_ldaps(&config.ldaps_options, credentials.as_ref()).await
+        }),
+        timeout(
+            delay,
+            healthcheck::check_api(config.http_port, &config.http_tls)
+        ),
     );
 
-    let failure = [ldap, ldaps, api]
+    let mut failures = [("LDAP", ldap), ("LDAPS", ldaps), ("API", api)]
         .into_iter()
-        .flat_map(|res| {
-            if let Err(e) = &res {
-                error!("Error running the health check: {:#}", e);
-            }
-            res
+        .filter_map(|(name, result)| {
+            result
+                .unwrap_or_else(|_| Err(anyhow!("no answer within {:?}", delay)))
+                .err()
+                .map(|e| format!("{}: {:#}", name, e))
         })
-        .any(|r| r.is_err());
-    if failure {
-        bail!("Healthcheck failed")
-    } else {
+        .collect::<Vec<_>>();
+    let certificates = [
+        (
+            "LDAPS certificate",
+            config.ldaps_options.enabled && !skip_ldap,
+            &config.ldaps_options.cert_file,
+            &config.ldaps_options.key_file,
+        ),
+        (
+            "HTTPS certificate",
+            config.http_tls.enabled,
+            &config.http_tls.cert_file,
+            &config.http_tls.key_file,
+        ),
+    ];
+    for (name, enabled, cert_file, key_file) in certificates {
+        if !enabled {
+            continue;
+        }
+        if let Err(e) = healthcheck::check_certificate_expiry(
+            cert_file,
+            key_file,
+            opts.cert_expiry_warning_days,
+        ) {
+            failures.push(format!("{}: {:#}", name, e));
+        }
+    }
+    if failures.is_empty() {
         Ok(())
+    } else {
+        bail!("Healthcheck failed: {}", failures.join("; "))
     }
 }
 
@@ -232,19 +536,541 @@ async fn create_schema_command(opts: RunOpts) -> Result<()> {
     debug!("CLI: {:#?}", &opts);
     let config = infra::configuration::init(opts)?;
     infra::logging::init(&config)?;
-    setup_sql_tables(&config.database_url).await?;
+    setup_sql_tables(&config, true).await?;
     info!("Schema created successfully.");
     Ok(())
 }
 
+async fn migrate_command(opts: MigrateOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let dry_run = opts.dry_run;
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    let sql_pool = connect_to_database(&config).await?;
+    domain::sql_tables::migrate(
+        &sql_pool,
+        config.database_options.migration_options()?,
+        dry_run,
+    )
+    .await
+    .context("while migrating the database schema")?;
+    if !dry_run {
+        infra::jwt_sql_tables::init_table(&sql_pool)
+            .await
+            .context("while creating jwt tables")?;
+        info!("Database schema is up to date.");
+    }
+    Ok(())
+}
+
+async fn export_command(opts: ExportOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let output = std::path::PathBuf::from(&opts.output);
+    let exclude_avatars = opts.exclude_avatars;
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    let sql_pool = connect_to_database(&config).await?;
+    domain::sql_tables::check_or_init_table(&sql_pool, None, false)
+        .await
+        .context("while checking the database schema")?;
+    if let Some(private_key_info) = get_private_key_info(&sql_pool).await? {
+        if private_key_info.private_key_hash != config.get_private_key_info().private_key_hash {
+            bail!("The configured server key is not the one that was used for the passwords in the database, the backup would be unusable. Check the `key_file`/`key_seed` configuration.");
+        }
+    }
+    let data = infra::backup::dump(&sql_pool, exclude_avatars)
+        .await
+        .context("while reading the database")?;
+    infra::backup::write_archive(&output, &data, &config.get_server_setup().serialize())?;
+    info!(
+        "Exported {} users and {} groups to {}",
+        data.users.len(),
+        data.groups.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+async fn import_command(opts: ImportOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let input = std::path::PathBuf::from(&opts.input);
+    let (data, server_key) = infra::backup::read_archive(&input)?;
+    let config = infra::configuration::init_with_server_key(opts, Some(&server_key))?;
+    infra::logging::init(&config)?;
+    infra::backup::check_server_key(&data, &server_key, &config)?;
+    let sql_pool = setup_sql_tables(&config, true).await?;
+    infra::backup::restore(&sql_pool, data)
+        .await
+        .context("while importing the archive")?;
+    set_private_key_info(&sql_pool, config.get_private_key_info()).await?;
+    info!("Imported {} successfully.", input.display());
+    Ok(())
+}
+
+async fn import_ldif_command(opts: ImportLdifOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let (file, dry_run, output) = (opts.file.clone(), opts.dry_run, opts.output);
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    let contents =
+        std::fs::read_to_string(&file).context(format!("Could not read the file `{}`", file))?;
+    let entries = infra::ldif::parse(&contents).context("while parsing the LDIF file")?;
+    let sql_pool = if dry_run {
+        connect_for_dry_run(&config).await?
+    } else {
+        setup_sql_tables(&config, false).await?
+    };
+    let backend_handler = SqlBackendHandler::new(config, sql_pool);
+    let plan =
+        infra::ldif_import::changes(&backend_handler, infra::ldif_import::plan(&entries)).await?;
+    if dry_run {
+        return plan.print(output);
+    }
+    for warning in &plan.warnings {
+        warn!("{}", warning);
+    }
+    let avatar_importer = avatar_importer(&backend_handler)?;
+    infra::change_plan::apply(
+        &backend_handler,
+        plan,
+        None,
+        avatar_importer
+            .as_ref()
+            .map(|importer| importer as &dyn AvatarImporter),
+    )
+    .await
+    .context("while importing the LDIF file")?
+    .print(output)
+}
+
+async fn import_csv_command(opts: ImportCsvOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let options = infra::csv_import::ImportOptions {
+        update_existing: opts.update_existing,
+        strict: opts.strict,
+    };
+    let (file, mapping) = (opts.file.clone(), opts.mapping.clone());
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    let mapping = infra::csv_import::read_mapping(&mapping)?;
+    let csv = std::fs::File::open(&file)
+        .context(format!("Could not read the file `{}`", file.display()))?;
+    let sql_pool = setup_sql_tables(&config, false).await?;
+    let backend_handler = SqlBackendHandler::new(config, sql_pool);
+    let avatar_importer = avatar_importer(&backend_handler)?;
+    let summary = infra::csv_import::import(
+        &backend_handler,
+        &mapping,
+        csv,
+        options,
+        avatar_importer
+            .as_ref()
+            .map(|importer| importer as &dyn AvatarImporter),
+    )
+    .await
+    .context("while importing the CSV file")?;
+    for error in &summary.failed {
+        warn!("{}", error);
+    }
+    info!("{}", summary);
+    if !summary.failed.is_empty() {
+        bail!("{} rows could not be imported", summary.failed.len());
+    }
+    Ok(())
+}
+
+async fn check_db_command(opts: CheckDbOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    let sql_pool = connect_to_database(&config).await?;
+    domain::sql_tables::check_or_init_table(&sql_pool, None, false)
+        .await
+        .context("while checking the database schema")?;
+    let problems = infra::db_check::find_problems(&sql_pool)
+        .await
+        .context("while reading the database")?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if !problems.is_empty() {
+        bail!(
+            "Found {} problems in the database. Fix the users and groups listed above: rename them or correct their email (or recreate them).",
+            problems.len()
+        );
+    }
+    info!("No problem found in the database");
+    Ok(())
+}
+
+async fn prune_command(opts: PruneOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let targets = if opts.what.is_empty() {
+        PruneTarget::iter().collect()
+    } else {
+        opts.what.clone()
+    };
+    let (older_than, dry_run) = (opts.older_than, opts.dry_run);
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    let sql_pool = if dry_run {
+        connect_for_dry_run(&config).await?
+    } else {
+        setup_sql_tables(&config, false).await?
+    };
+    let now = chrono::Utc::now();
+    for target in targets {
+        let older_than = match older_than.or_else(|| target.retention(&config)) {
+            Some(older_than) => older_than,
+            None => {
+                println!("{}: skipped, a replica gets them from the primary", target);
+                continue;
+            }
+        };
+        let rows = db_cleaner::prune(
+            &sql_pool,
+            target,
+            now - older_than,
+            config.retention.batch_size,
+            dry_run,
+        )
+        .await
+        .with_context(|| format!("while pruning the {}", target))?;
+        if dry_run {
+            println!("{}: {} rows would be deleted", target, rows);
+        } else {
+            println!("{}: {} rows deleted", target, rows);
+        }
+    }
+    Ok(())
+}
+
+async fn refresh_avatars_command(opts: RefreshAvatarsOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let only_missing = opts.only_missing;
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    let sql_pool = setup_sql_tables(&config, false).await?;
+    let backend_handler = SqlBackendHandler::new(config, sql_pool);
+    let Some(importer) = avatar_importer(&backend_handler)? else {
+        bail!("Set avatar_source_url_template to refresh the avatars");
+    };
+    let (mut refreshed, mut skipped, mut failed) = (0, 0, 0);
+    for user in backend_handler.list_users(None, false).await? {
+        let user_id = user.user.user_id;
+        if only_missing
+            && backend_handler
+                .get_user_avatar_hash(&user_id)
+                .await?
+                .is_some()
+        {
+            skipped += 1;
+            continue;
+        }
+        match importer.import_avatar(&user_id).await {
+            Ok(()) => refreshed += 1,
+            Err(e) => {
+                warn!("Could not import the avatar of {}: {:#}", user_id, e);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "Refreshed {} avatars, skipped {}, failed {}",
+        refreshed, skipped, failed
+    );
+    if failed > 0 {
+        bail!("{} avatars could not be refreshed", failed);
+    }
+    Ok(())
+}
+
+/// The importer of the avatars from `avatar_source_url_template`, if set.
+fn avatar_importer(
+    handler: &SqlBackendHandler,
+) -> Result<Option<SourceAvatarImporter<SqlBackendHandler>>> {
+    Ok(
+        AvatarSource::from_config(&handler.config)?.map(|source| SourceAvatarImporter {
+            backend_handler: handler.clone(),
+            source,
+        }),
+    )
+}
+
+/// Logs in to the server with `--url`, or else opens the database of the configuration.
+async fn get_provisioner<C>(
+    opts: C,
+    provisioning: &ProvisioningOpts,
+) -> Result<Box<dyn Provisioner>>
+where
+    C: infra::configuration::TopLevelCommandOpts + infra::configuration::ConfigOverrider,
+{
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    match &provisioning.url {
+        Some(url) => {
+            let password = provisioning.admin_password_source().read_or_prompt(
+                &format!("Password of {}: ", provisioning.admin_username),
+                false,
+            )?;
+            Ok(Box::new(
+                HttpProvisioner::login(url, &provisioning.admin_username, &password).await?,
+            ))
+        }
+        None => {
+            let sql_pool = setup_sql_tables(&config, false).await?;
+            Ok(Box::new(DatabaseProvisioner::new(SqlBackendHandler::new(
+                config, sql_pool,
+            ))))
+        }
+    }
+}
+
+async fn create_user_command(opts: CreateUserOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    if opts.password.source().uses_stdin() && opts.provisioning.admin_password_source().uses_stdin()
+    {
+        bail!("Only one of the passwords can be read from the standard input");
+    }
+    let password = opts.password.source().read()?;
+    if let Some(password) = &password {
+        check_new_password(password)?;
+    }
+    let user = NewUser {
+        user_id: UserId::new(&opts.user_id),
+        email: opts.email.clone(),
+        display_name: opts.display_name.clone(),
+        first_name: opts.first_name.clone(),
+        last_name: opts.last_name.clone(),
+        password,
+        groups: opts.groups.clone(),
+    };
+    let provisioning = opts.provisioning.clone();
+    let provisioner = get_provisioner(opts, &provisioning).await?;
+    let user_id = user.user_id.clone();
+    provisioner.create_user(user).await?;
+    info!("Created the user {}", user_id);
+    Ok(())
+}
+
+async fn create_group_command(opts: CreateGroupOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let name = opts.name.clone();
+    let provisioning = opts.provisioning.clone();
+    let provisioner = get_provisioner(opts, &provisioning).await?;
+    provisioner.create_group(&name).await?;
+    info!("Created the group {}", name);
+    Ok(())
+}
+
+async fn reset_admin_password_command(opts: ResetAdminPasswordOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let password_input = opts.password.clone();
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    infra::reset_admin_password::check_server_stopped(&config, "resetting the admin password")
+        .await?;
+    let password = password_input
+        .source()
+        .read_or_prompt(&format!("New password for {}: ", config.ldap_user_dn), true)?;
+    check_new_password(&password)?;
+    let sql_pool = setup_sql_tables(&config, false).await?;
+    let handler = SqlBackendHandler::new(config.clone(), sql_pool);
+    for change in
+        infra::reset_admin_password::reset_admin_password(&handler, &config, &password).await?
+    {
+        println!("{}", change);
+    }
+    Ok(())
+}
+
+/// For `--dry-run`: connects to the database without creating or upgrading the schema.
+async fn connect_for_dry_run(config: &Configuration) -> Result<DatabaseConnection> {
+    let sql_pool = connect_to_database(config).await?;
+    if domain::sql_tables::is_empty_database(&sql_pool).await {
+        bail!("The database is empty: create it with `lldap create_schema` before comparing it with a dry run");
+    }
+    domain::sql_tables::check_or_init_table(&sql_pool, None, false)
+        .await
+        .context("while checking the database schema")?;
+    Ok(sql_pool)
+}
+
+/// Applies a bootstrap file, with the invitations sent through `mail_queue`.
+async fn apply_bootstrap_file(
+    handler: &SqlBackendHandler,
+    config: &Configuration,
+    plan: Plan,
+    mail_queue: MailQueue,
+) -> Result<Summary> {
+    let invitation_sender = if config.smtp_options.enable_password_reset {
+        Some(MailInvitationSender {
+            backend_handler: handler.clone(),
+            server_url: config.public_url(),
+            mail_options: config.smtp_options.clone(),
+            mail_templates: Arc::new(
+                MailTemplates::new(
+                    config.smtp_options.templates_dir.as_deref(),
+                    config.smtp_options.logo_file.as_deref(),
+                    &config.smtp_options.default_language,
+                )
+                .context("while loading the email templates")?,
+            ),
+            mail_queue,
+        })
+    } else {
+        None
+    };
+    let avatar_importer = avatar_importer(handler)?;
+    infra::change_plan::apply(
+        handler,
+        plan,
+        invitation_sender
+            .as_ref()
+            .map(|sender| sender as &dyn InvitationSender),
+        avatar_importer
+            .as_ref()
+            .map(|importer| importer as &dyn AvatarImporter),
+    )
+    .await
+}
+
+async fn bootstrap_command(opts: BootstrapOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let (file, prune, dry_run, output) = (opts.file.clone(), opts.prune, opts.dry_run, opts.output);
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    let bootstrap = infra::bootstrap::read_file(&file)?;
+    let sql_pool = if dry_run {
+        connect_for_dry_run(&config).await?
+    } else {
+        setup_sql_tables(&config, false).await?
+    };
+    let handler = SqlBackendHandler::new(config.clone(), sql_pool);
+    let plan = infra::bootstrap::plan(&handler, &bootstrap, prune, &config.ldap_user_dn).await?;
+    if dry_run {
+        return plan.print(output);
+    }
+    let (mail_queue, mail_queue_worker) = MailQueue::start(
+        Arc::new(SmtpMailTransport::new(config.smtp_options.clone())),
+        &config.smtp_options,
+    );
+    let result = apply_bootstrap_file(&handler, &config, plan, mail_queue).await;
+    // Send the invitations before exiting.
+    mail_queue_worker
+        .drain(Duration::from_secs(config.shutdown_timeout_secs))
+        .await;
+    result?.print(output)
+}
+
+async fn rotate_jwt_secret_command(opts: RotateJwtSecretOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let config_file = std::path::PathBuf::from(&opts.general_config.config_file);
+    let (keep_previous, revoke_all_sessions, force) =
+        (opts.keep_previous, opts.revoke_all_sessions, opts.force);
+    let config = infra::configuration::load_without_server_key(opts)?;
+    infra::logging::init(&config)?;
+    if force {
+        warn!("The running server keeps using the old JWT secret until it is restarted");
+    } else {
+        infra::reset_admin_password::check_server_stopped(&config, "rotating the JWT secret")
+            .await?;
+    }
+    let secret = infra::jwt_rotation::generate_secret();
+    if infra::jwt_rotation::is_set_by_env() || !config_file.is_file() {
+        // The secret is only printed here, never logged.
+        println!("The JWT secret comes from the environment, set it there:");
+        println!("LLDAP_JWT_SECRET={}", secret.unsecure());
+        if keep_previous {
+            println!("LLDAP_JWT_SECRET_PREVIOUS=<the current LLDAP_JWT_SECRET>");
+        }
+    } else {
+        let previous = keep_previous.then(|| config.jwt_secret.clone());
+        infra::jwt_rotation::write_config_file(&config_file, &secret, previous.as_ref())?;
+        info!("Wrote the new JWT secret to {}", config_file.display());
+    }
+    if revoke_all_sessions {
+        let sql_pool = setup_sql_tables(&config, false).await?;
+        let (refresh_tokens, tokens) = infra::jwt_rotation::revoke_all_sessions(&sql_pool).await?;
+        info!(
+            "Revoked {} refresh tokens and {} session tokens",
+            refresh_tokens, tokens
+        );
+    }
+    if keep_previous {
+        info!("Restart the server, and remove jwt_secret_previous in a day, once the old tokens have expired");
+    } else {
+        info!("Restart the server to use the new secret");
+    }
+    Ok(())
+}
+
+fn generate_config_command(opts: GenerateConfigOpts) -> Result<()> {
+    let (from_current, output_file) = (opts.from_current, opts.output_file.clone());
+    let config = if from_current {
+        infra::configuration::load_without_server_key(opts)?
+    } else {
+        Configuration::defaults()
+    };
+    let contents = infra::config_template::generate(&config, from_current)?;
+    match output_file {
+        Some(path) => std::fs::write(&path, contents)
+            .context(format!("Could not write the file `{}`", path.display())),
+        None => {
+            print!("{}", contents);
+            Ok(())
+        }
+    }
+}
+
 #[actix::main]
 async fn main() -> Result<()> {
     let cli_opts = infra::cli::init();
-    match cli_opts.command {
+    let result = match cli_opts.command {
         Command::ExportGraphQLSchema(opts) => infra::graphql::api::export_schema(opts),
         Command::Run(opts) => run_server_command(opts).await,
         Command::HealthCheck(opts) => run_healthcheck(opts).await,
         Command::SendTestEmail(opts) => send_test_email_command(opts).await,
         Command::CreateSchema(opts) => create_schema_command(opts).await,
+        Command::Migrate(opts) => migrate_command(opts).await,
+        Command::Export(opts) => export_command(opts).await,
+        Command::Import(opts) => import_command(opts).await,
+        Command::ImportLdif(opts) => import_ldif_command(opts).await,
+        Command::ImportCsv(opts) => import_csv_command(opts).await,
+        Command::CheckDb(opts) => check_db_command(opts).await,
+        Command::Prune(opts) => prune_command(opts).await,
+        Command::RefreshAvatars(opts) => refresh_avatars_command(opts).await,
+        Command::CreateUser(opts) => create_user_command(opts).await,
+        Command::CreateGroup(opts) => create_group_command(opts).await,
+        Command::ResetAdminPassword(opts) => reset_admin_password_command(opts).await,
+        Command::Bootstrap(opts) => bootstrap_command(opts).await,
+        Command::RotateJwtSecret(opts) => rotate_jwt_secret_command(opts).await,
+        Command::Version(opts) => {
+            let info = infra::build_info::BuildInfo::get();
+            if opts.json {
+                println!("{}", serde_json::to_string_pretty(info)?);
+            } else {
+                print!("{}", info);
+            }
+            Ok(())
+        }
+        Command::GenerateConfig(opts) => generate_config_command(opts),
+        Command::Completions(opts) => {
+            infra::cli::print_completions(opts.shell, &mut std::io::stdout());
+            Ok(())
+        }
+        Command::Manpages(opts) => infra::cli::write_manpages(&opts.out_dir).map(|pages| {
+            for page in pages {
+                println!("{}", page.display());
+            }
+        }),
+    };
+    infra::logging::shutdown();
+    if let Err(e) = &result {
+        if e.downcast_ref::<AlreadyExists>().is_some() {
+            eprintln!("{:#}", e);
+            std::process::exit(ALREADY_EXISTS_EXIT_CODE);
+        }
     }
+    result
 }
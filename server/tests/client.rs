@@ -0,0 +1,152 @@
+use crate::common::{
+    env,
+    fixture::{create_lldap_command, new_id, LLDAPFixture},
+};
+use lldap_client::{CreateGroupInput, CreateUserInput, Error, LldapClient, UpdateUserInput};
+use serial_test::file_serial;
+mod common;
+
+#[test]
+fn schema_is_up_to_date() {
+    // The client types are generated from this file: it has to match the server.
+    let output = create_lldap_command()
+        .arg("export_graphql_schema")
+        .output()
+        .expect("failed to export the schema");
+    assert!(output.status.success(), "{:?}", output);
+    let exported = String::from_utf8(output.stdout).unwrap();
+    let committed = include_str!("../../schema.graphql");
+    assert!(
+        exported.trim() == committed.trim(),
+        "schema.graphql is out of date, run ./export_schema.sh"
+    );
+}
+
+fn new_user(id: &str) -> CreateUserInput {
+    CreateUserInput {
+        id: id.to_owned(),
+        email: format!("{}@lldap.test", id),
+        display_name: Some("Client User".to_owned()),
+        first_name: None,
+        last_name: None,
+        avatar: None,
+        preferred_language: None,
+        hosts: None,
+        phone: None,
+        mobile: None,
+        postal_address: None,
+        locality: None,
+        state_or_province: None,
+        attributes: None,
+        groups: None,
+    }
+}
+
+#[test]
+#[file_serial]
+fn client_manages_users_and_groups() {
+    let _fixture = LLDAPFixture::new();
+    let user_id = new_id(Some("client-user-"));
+    let group_name = new_id(Some("client-group-"));
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let mut admin =
+            LldapClient::login(&env::http_url(), &env::admin_dn(), &env::admin_password())
+                .await
+                .expect("failed to log in");
+        admin.refresh().await.expect("failed to refresh the token");
+
+        let created = admin
+            .create_user(new_user(&user_id), false)
+            .await
+            .expect("failed to create the user");
+        assert_eq!(created.id, user_id);
+        admin
+            .update_user(UpdateUserInput {
+                id: user_id.clone(),
+                email: None,
+                display_name: Some("Renamed".to_owned()),
+                first_name: None,
+                last_name: None,
+                avatar: None,
+                preferred_language: None,
+                hosts: None,
+                phone: None,
+                mobile: None,
+                postal_address: None,
+                locality: None,
+                state_or_province: None,
+                remove_attributes: None,
+                insert_attributes: None,
+                skip_email_verification: None,
+            })
+            .await
+            .expect("failed to update the user");
+        assert_eq!(
+            admin.get_user(&user_id).await.unwrap().display_name,
+            "Renamed"
+        );
+        assert!(admin
+            .list_users(None)
+            .await
+            .unwrap()
+            .iter()
+            .any(|u| u.id == user_id));
+
+        let group = admin
+            .create_group(CreateGroupInput {
+                display_name: group_name.clone(),
+                attributes: None,
+            })
+            .await
+            .expect("failed to create the group");
+        assert!(admin.add_user_to_group(&user_id, group.id).await.unwrap());
+        assert!(!admin.add_user_to_group(&user_id, group.id).await.unwrap());
+        let members = admin.get_group(group.id).await.unwrap().users;
+        assert_eq!(
+            members.iter().map(|u| u.id.as_str()).collect::<Vec<_>>(),
+            vec![user_id.as_str()]
+        );
+
+        admin
+            .set_password(&user_id, "client password")
+            .await
+            .expect("failed to set the password");
+        let user = LldapClient::login(&env::http_url(), &user_id, "client password")
+            .await
+            .expect("failed to log in as the user");
+        let mut token_client = LldapClient::with_token(&env::http_url(), user.token()).unwrap();
+        assert_eq!(token_client.get_user(&user_id).await.unwrap().id, user_id);
+        assert!(matches!(
+            token_client.list_groups().await,
+            Err(Error::GraphQL(_))
+        ));
+        assert!(matches!(
+            token_client.refresh().await,
+            Err(Error::MissingRefreshToken)
+        ));
+
+        assert!(admin
+            .remove_user_from_group(&user_id, group.id)
+            .await
+            .unwrap());
+        admin.delete_group(group.id).await.unwrap();
+        admin.delete_user(&user_id, true).await.unwrap();
+        assert!(matches!(
+            admin.get_user(&user_id).await,
+            Err(Error::GraphQL(_))
+        ));
+    });
+}
+
+#[test]
+#[file_serial]
+fn client_rejects_wrong_password() {
+    let _fixture = LLDAPFixture::new();
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        // With OPAQUE, the client is the one finding out that the password is wrong.
+        assert!(matches!(
+            LldapClient::login(&env::http_url(), &env::admin_dn(), "wrong password").await,
+            Err(Error::Opaque(_))
+        ));
+    });
+}
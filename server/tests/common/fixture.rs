@@ -43,23 +43,7 @@ const MAX_HEALTHCHECK_ATTEMPS: u8 = 10;
 
 impl LLDAPFixture {
     pub fn new() -> Self {
-        let mut cmd = create_lldap_command();
-        cmd.arg("run");
-        cmd.arg("--verbose");
-        let child = cmd.spawn().expect("Unable to start server");
-        let mut started = false;
-        for _ in 0..MAX_HEALTHCHECK_ATTEMPS {
-            let status = create_lldap_command()
-                .arg("healthcheck")
-                .status()
-                .expect("healthcheck fail");
-            if status.success() {
-                started = true;
-                break;
-            }
-            thread::sleep(Duration::from_millis(1000));
-        }
-        assert!(started);
+        let child = start_server();
         let client = ClientBuilder::new()
             .connect_timeout(std::time::Duration::from_secs(2))
             .timeout(std::time::Duration::from_secs(5))
@@ -108,7 +92,15 @@ impl LLDAPFixture {
                     display_name: None,
                     first_name: None,
                     last_name: None,
+                    preferred_language: None,
+                    hosts: None,
+                    phone: None,
+                    mobile: None,
+                    postal_address: None,
+                    locality: None,
+                    state_or_province: None,
                     attributes: None,
+                    groups: None,
                 },
             },
         )
@@ -177,47 +169,71 @@ impl Drop for LLDAPFixture {
         for group in groups.keys() {
             self.delete_group(group);
         }
-        let result = signal::kill(
-            Pid::from_raw(self.child.id().try_into().unwrap()),
-            Signal::SIGTERM,
-        );
-        if let Err(err) = result {
-            println!("Failed to send kill signal: {:?}", err);
-            let _ = self
-                .child
-                .kill()
-                .map_err(|err| println!("Failed to kill LLDAP: {:?}", err));
-            return;
+        stop_server(&mut self.child);
+    }
+}
+
+/// Starts the server and waits until it is healthy.
+pub fn start_server() -> ChildProcess {
+    let mut cmd = create_lldap_command();
+    cmd.arg("run");
+    cmd.arg("--verbose");
+    let child = cmd.spawn().expect("Unable to start server");
+    let mut started = false;
+    for _ in 0..MAX_HEALTHCHECK_ATTEMPS {
+        let status = create_lldap_command()
+            .arg("healthcheck")
+            .status()
+            .expect("healthcheck fail");
+        if status.success() {
+            started = true;
+            break;
         }
+        thread::sleep(Duration::from_millis(1000));
+    }
+    assert!(started);
+    child
+}
 
-        for _ in 0..10 {
-            let status = self.child.try_wait();
-            match status {
-                Err(e) => {
-                    println!(
-                        "Failed to get status while waiting for graceful exit: {}",
-                        e
-                    );
-                    break;
-                }
-                Ok(None) => {
-                    println!("LLDAP still running, sleeping for 1 second.");
-                }
-                Ok(Some(status)) => {
-                    if !status.success() {
-                        println!("LLDAP exited with status {}", status)
-                    }
-                    return;
+pub fn stop_server(child: &mut ChildProcess) {
+    let result = signal::kill(
+        Pid::from_raw(child.id().try_into().unwrap()),
+        Signal::SIGTERM,
+    );
+    if let Err(err) = result {
+        println!("Failed to send kill signal: {:?}", err);
+        let _ = child
+            .kill()
+            .map_err(|err| println!("Failed to kill LLDAP: {:?}", err));
+        return;
+    }
+
+    for _ in 0..10 {
+        let status = child.try_wait();
+        match status {
+            Err(e) => {
+                println!(
+                    "Failed to get status while waiting for graceful exit: {}",
+                    e
+                );
+                break;
+            }
+            Ok(None) => {
+                println!("LLDAP still running, sleeping for 1 second.");
+            }
+            Ok(Some(status)) => {
+                if !status.success() {
+                    println!("LLDAP exited with status {}", status)
                 }
+                return;
             }
-            thread::sleep(Duration::from_millis(1000));
         }
-        println!("LLDAP alive after 10 seconds, forcing exit.");
-        let _ = self
-            .child
-            .kill()
-            .map_err(|err| println!("Failed to kill LLDAP: {:?}", err));
+        thread::sleep(Duration::from_millis(1000));
     }
+    println!("LLDAP alive after 10 seconds, forcing exit.");
+    let _ = child
+        .kill()
+        .map_err(|err| println!("Failed to kill LLDAP: {:?}", err));
 }
 
 pub fn new_id(prefix: Option<&str>) -> String {
@@ -229,7 +245,7 @@ pub fn new_id(prefix: Option<&str>) -> String {
     }
 }
 
-fn create_lldap_command() -> Command {
+pub fn create_lldap_command() -> Command {
     let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).expect("cargo bin not found");
     // This gives us the absolute path of the repo base instead of running it in server/
     let path = canonicalize("..").expect("canonical path");
@@ -44,6 +44,16 @@ pub struct CreateGroup;
 )]
 pub struct ListUsers;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "tests/queries/list_users_last_login_before.graphql",
+    response_derives = "Debug",
+    variables_derives = "Debug,Clone",
+    custom_scalars_module = "crate::common::graphql"
+)]
+pub struct ListUsersLastLoginBefore;
+
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "../schema.graphql",
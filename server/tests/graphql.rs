@@ -2,7 +2,10 @@ use crate::common::{
     auth::get_token,
     env,
     fixture::{new_id, LLDAPFixture, User},
-    graphql::{get_user_details, list_users, post, GetUserDetails, ListUsers},
+    graphql::{
+        get_user_details, list_users, list_users_last_login_before, post, GetUserDetails,
+        ListUsers, ListUsersLastLoginBefore,
+    },
 };
 use reqwest::blocking::ClientBuilder;
 use serial_test::file_serial;
@@ -63,8 +66,64 @@ fn get_admin() {
     let admin_groups: HashSet<String> = result
         .user
         .groups
+        .expect("admin should see their groups")
         .iter()
         .map(|group| group.display_name.clone())
         .collect();
     assert!(admin_groups.contains(admin_group_name));
 }
+
+#[test]
+#[file_serial]
+fn timestamps_round_trip() {
+    // The DATETIME columns of MySQL don't store the fractions of seconds.
+    let before = chrono::Utc::now() - chrono::Duration::seconds(1);
+    let mut fixture = LLDAPFixture::new();
+    let user_name = new_id(Some("graphql-timestamps_round_trip-"));
+    fixture.load_state(&vec![User::new(&user_name, vec![])]);
+    let after = chrono::Utc::now() + chrono::Duration::seconds(1);
+    let client = ClientBuilder::new()
+        .connect_timeout(std::time::Duration::from_secs(2))
+        .timeout(std::time::Duration::from_secs(5))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("failed to make http client");
+    let token = get_token(&client);
+    let creation_date = post::<GetUserDetails>(
+        &client,
+        &token,
+        get_user_details::Variables {
+            id: user_name.clone(),
+        },
+    )
+    .expect("failed to get the user")
+    .user
+    .creation_date;
+    assert!(
+        before <= creation_date && creation_date <= after,
+        "{} is not between {} and {}",
+        creation_date,
+        before,
+        after
+    );
+
+    // The admin just logged in, and the new user never did.
+    let last_login_before = |date| {
+        post::<ListUsersLastLoginBefore>(
+            &client,
+            &token,
+            list_users_last_login_before::Variables { date },
+        )
+        .expect("failed to list users")
+        .users
+        .into_iter()
+        .map(|user| user.id)
+        .collect::<HashSet<_>>()
+    };
+    let users = last_login_before(chrono::Utc::now() - chrono::Duration::days(365));
+    assert!(users.contains(&user_name));
+    assert!(!users.contains(&env::admin_dn()));
+    let users = last_login_before(chrono::Utc::now() + chrono::Duration::days(1));
+    assert!(users.contains(&user_name));
+    assert!(users.contains(&env::admin_dn()));
+}
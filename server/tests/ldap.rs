@@ -94,6 +94,58 @@ fn admin_search() {
     ldap.unbind().expect("failed to unbind ldap connection");
 }
 
+#[test]
+#[file_serial]
+fn presence_filters() {
+    let mut fixture = LLDAPFixture::new();
+    let prefix = "ldap-presence_filters-";
+    let user1_name = new_id(Some(prefix));
+    let user2_name = new_id(Some(prefix));
+    let group_name = new_id(Some(prefix));
+    fixture.load_state(&vec![
+        User::new(&user1_name, vec![&group_name]),
+        User::new(&user2_name, vec![]),
+    ]);
+
+    let mut ldap =
+        LdapConn::new(env::ldap_url().as_str()).expect("failed to create ldap connection");
+    let base_dn = env::base_dn();
+    let bind_dn = format!("uid={},ou=people,{}", env::admin_dn(), base_dn);
+    ldap.simple_bind(bind_dn.as_str(), env::admin_password().as_str())
+        .expect("failed to bind to ldap");
+    let mut search = |filter: String| -> HashSet<String> {
+        ldap.search(base_dn.as_str(), Scope::Subtree, &filter, vec!["1.1"])
+            .expect("failed to search")
+            .success()
+            .expect("failed to get successful result")
+            .0
+            .into_iter()
+            .map(|result| SearchEntry::construct(result).dn)
+            .collect()
+    };
+    let user_dn = |name: &str| format!("uid={},ou=people,{}", name, base_dn);
+
+    let all = search("(objectClass=*)".to_owned());
+    assert!(all.contains(&format!("ou=people,{}", base_dn)));
+    assert!(all.contains(&format!("ou=groups,{}", base_dn)));
+    assert!(all.contains(&user_dn(&user1_name)));
+    assert!(all.contains(&format!("cn={},ou=groups,{}", group_name, base_dn)));
+    assert_eq!(
+        search(format!("(&(uid={}*)(mail=*))", prefix)),
+        HashSet::from([user_dn(&user1_name), user_dn(&user2_name)])
+    );
+    assert_eq!(
+        search(format!("(&(uid={}*)(!(displayName=*)))", prefix)),
+        HashSet::from([user_dn(&user1_name), user_dn(&user2_name)])
+    );
+    assert_eq!(
+        search(format!("(&(uid={}*)(!(memberOf=*)))", prefix)),
+        HashSet::from([user_dn(&user2_name)])
+    );
+    assert!(search(format!("(&(uid={}*)(unknownAttribute=*))", prefix)).is_empty());
+    ldap.unbind().expect("failed to unbind ldap connection");
+}
+
 fn get_users_and_groups(results: SearchResult) -> HashMap<String, HashSet<String>> {
     let results = results
         .success()
@@ -0,0 +1,23 @@
+use crate::common::{env, fixture::LLDAPFixture};
+use lldap_load_test::{Profile, Target};
+use serial_test::file_serial;
+mod common;
+
+#[test]
+#[file_serial]
+fn load_test_smoke() {
+    let _fixture = LLDAPFixture::new();
+    let target = Target {
+        ldap_url: env::ldap_url(),
+        http_url: env::http_url(),
+        base_dn: env::base_dn(),
+        admin_user: env::admin_dn(),
+        admin_password: env::admin_password(),
+    };
+    let report = lldap_load_test::run(&target, &Profile::smoke()).expect("load test failed");
+    assert_eq!(report.scenarios.len(), 3);
+    for (name, scenario) in &report.scenarios {
+        assert!(scenario.requests > 0, "{}: no requests", name);
+        assert_eq!(scenario.errors, 0, "{}: {:?}", name, scenario);
+    }
+}
@@ -0,0 +1,59 @@
+use crate::common::{
+    env,
+    fixture::{create_lldap_command, start_server, stop_server},
+};
+use ldap3::LdapConn;
+use serial_test::file_serial;
+use std::io::Write;
+use std::process::Stdio;
+mod common;
+
+fn reset_admin_password(password: &str) -> std::process::Output {
+    let mut child = create_lldap_command()
+        .args(["reset_admin_password", "--password-stdin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run reset_admin_password");
+    writeln!(child.stdin.take().unwrap(), "{}", password).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn bind_as_admin(password: &str) -> ldap3::result::Result<ldap3::LdapResult> {
+    let mut ldap =
+        LdapConn::new(env::ldap_url().as_str()).expect("failed to create ldap connection");
+    let bind_dn = format!("uid={},ou=people,{}", env::admin_dn(), env::base_dn());
+    ldap.simple_bind(bind_dn.as_str(), password)?.success()
+}
+
+#[test]
+#[file_serial]
+fn reset_then_bind() {
+    let new_password = "a brand new password";
+    let output = reset_admin_password(new_password);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains(&format!("Changed the password of {}", env::admin_dn())),
+        "{}",
+        stdout
+    );
+
+    let mut server = start_server();
+    // Refuses to run under a live server.
+    let refused = reset_admin_password("yet another password");
+    bind_as_admin(new_password).expect("failed to bind with the new password");
+    bind_as_admin(&env::admin_password()).unwrap_err();
+    stop_server(&mut server);
+    assert!(!refused.status.success());
+    assert!(
+        String::from_utf8_lossy(&refused.stderr).contains("stop the LLDAP server"),
+        "{:?}",
+        refused
+    );
+
+    // Put the password back for the other tests.
+    let output = reset_admin_password(&env::admin_password());
+    assert!(output.status.success(), "{:?}", output);
+}